@@ -0,0 +1,1444 @@
+//! Intel syntax text assembler
+//!
+//! Assembles NASM flavoured source on top of `Assembler`:
+//!
+//! ```text
+//!         bits 64
+//!         org 0x1000
+//! start:  lea rsi, [msg]
+//!         mov ecx, len
+//! .next:  lodsb
+//!         loop .next
+//!         hlt
+//!
+//!         section .data
+//! msg:    db "hello", 10
+//! len     equ $ - msg
+//!         times 16 - len db 0
+//! ```
+//!
+//! Labels starting with a `.` are local to the ordinary label before them. The directives
+//! are `db` / `dw` / `dd` / `dq` (which take strings as well as expressions), `resb` ..
+//! `resq`, `times`, `equ`, `align`, `section`, `org`, `bits` and `default rel` / `default abs`.
+//! Operands are written as NASM or MASM would: `qword ptr [rax + rcx*8 + 0x10]`, `fs:[0x28]`,
+//! `[rel label]`, with EVEX decorations as `{k1}{z}`, `{1to16}` and `{rn-sae}`. Numbers are
+//! decimal, `0x` / `h` hex, `0b` / `b` binary, `0o` / `q` octal or a character constant.
+//!
+//! Unlike NASM, a memory operand naming a label is rip relative by default in 64-bit code;
+//! plain numbers are only rip relative when asked for, `[rel 0x1234]`.
+//! Sections are placed one after the other from the `org` address (0 by default), in the order
+//! they first appear, each aligned to its `align=` attribute. Expressions may refer
+//! to labels defined further down: the program is assembled again with the symbol values of
+//! the previous pass until they stop changing.
+
+use crate::encode::{Arg, Assembler, EncodeError, Instruction, Label, Mem};
+use crate::opcodes::{Bitness, Mnemonic, RepPrefix, RoundingControl};
+use crate::registers::Register;
+use crate::tables;
+use std::collections::HashMap;
+use std::fmt;
+
+/// passes made before giving up on the symbol values settling
+const MAX_PASSES: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    /// a line that does not parse
+    Syntax(String),
+    UnknownMnemonic(String),
+    UndefinedSymbol(String),
+    DuplicateSymbol(String),
+    /// a `times` or `res*` count below zero
+    NegativeCount,
+    /// the instruction parsed but cannot be encoded
+    Encode(EncodeError),
+    /// symbol values kept changing from one pass to the next
+    NoConvergence,
+}
+
+/// An assembly error and the (1 based) line it was found on. Errors that are not about any
+/// one line have line 0
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub kind: AsmErrorKind,
+}
+
+impl fmt::Display for AsmErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmErrorKind::Syntax(message) => f.write_str(message),
+            AsmErrorKind::UnknownMnemonic(name) => write!(f, "unknown mnemonic {name}"),
+            AsmErrorKind::UndefinedSymbol(name) => write!(f, "undefined symbol {name}"),
+            AsmErrorKind::DuplicateSymbol(name) => write!(f, "symbol {name} is already defined"),
+            AsmErrorKind::NegativeCount => write!(f, "negative repeat count"),
+            AsmErrorKind::Encode(error) => write!(f, "{error}"),
+            AsmErrorKind::NoConvergence => write!(f, "symbol values do not settle"),
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{}", self.kind),
+            line => write!(f, "line {line}: {}", self.kind),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+fn syntax(message: impl Into<String>) -> AsmErrorKind {
+    AsmErrorKind::Syntax(message.into())
+}
+
+/// A named, contiguous part of an assembled program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub address: u64,
+    pub bytes: Vec<u8>,
+}
+
+/// A label, or an `equ` constant when `section` is `None`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u64,
+    /// index into `Object::sections`
+    pub section: Option<usize>,
+}
+
+/// An assembled program with its sections and symbol table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Object {
+    pub bitness: Bitness,
+    /// load address, set with `org`
+    pub base: u64,
+    /// in address order
+    pub sections: Vec<Section>,
+    /// in the order they are defined. Local labels are named after their scope, `main.loop`
+    pub symbols: Vec<Symbol>,
+}
+
+impl Object {
+    /// The program as a flat binary image, to be loaded at `base`. Gaps left by section
+    /// alignment are zero filled
+    pub fn flat(&self) -> Vec<u8> {
+        let mut image = Vec::new();
+        for section in &self.sections {
+            image.resize((section.address - self.base) as usize, 0);
+            image.extend(&section.bytes);
+        }
+        image
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<u64> {
+        self.symbols.iter().find(|s| s.name == name).map(|s| s.value)
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+}
+
+/// Assembles `source` into a flat binary image
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    assemble_object(source).map(|object| object.flat())
+}
+
+/// Assembles `source`, keeping the sections and symbol table
+pub fn assemble_object(source: &str) -> Result<Object, AsmError> {
+    let mut program = Program::default();
+    for (i, line) in source.lines().enumerate() {
+        program.line(line).map_err(|kind| AsmError { line: i + 1, kind })?;
+    }
+    program.assemble()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Str(Vec<u8>),
+    /// `{k1}`, `{z}`, `{1to8}`, `{rn-sae}`, lowercased without the braces
+    Decorator(String),
+    Punct(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{n}"),
+            Token::Ident(name) => f.write_str(name),
+            Token::Str(bytes) => write!(f, "\"{}\"", String::from_utf8_lossy(bytes)),
+            Token::Decorator(d) => write!(f, "{{{d}}}"),
+            Token::Punct(p) => f.write_str(p),
+        }
+    }
+}
+
+const PUNCTUATION: [&str; 18] = [
+    "<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "(", ")", "[", "]", ",", ":", "=",
+];
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || matches!(c, '_' | '.' | '$' | '@' | '?')
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$' | '@' | '?' | '#')
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, AsmErrorKind> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if matches!(c, '\'' | '"' | '`') {
+            let (bytes, next) = string(&chars, i)?;
+            tokens.push(Token::Str(bytes));
+            i = next;
+        } else if c == '{' {
+            let end = chars[i..]
+                .iter()
+                .position(|&c| c == '}')
+                .ok_or_else(|| syntax("unterminated {"))?;
+            let decorator: String = chars[i + 1..i + end].iter().collect();
+            tokens.push(Token::Decorator(decorator.trim().to_ascii_lowercase()));
+            i += end + 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            tokens.push(Token::Number(number(&text)?));
+        } else if is_ident_start(c) {
+            let start = i;
+            while i < chars.len() && is_ident_char(chars[i]) {
+                i += 1;
+            }
+            let mut name: String = chars[start..i].iter().collect();
+            // x87 registers are written st(3)
+            if name.eq_ignore_ascii_case("st")
+                && chars.get(i) == Some(&'(')
+                && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit())
+                && chars.get(i + 2) == Some(&')')
+            {
+                name = format!("st({})", chars[i + 1]);
+                i += 3;
+            }
+            tokens.push(Token::Ident(name));
+        } else {
+            let rest: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            let punct = PUNCTUATION
+                .iter()
+                .find(|p| rest.starts_with(**p))
+                .ok_or_else(|| syntax(format!("unexpected character '{c}'")))?;
+            tokens.push(Token::Punct(punct));
+            i += punct.len();
+        }
+    }
+    Ok(tokens)
+}
+
+/// A quoted string starting at `chars[start]`, and the index just after it. Backquoted
+/// strings take C style escapes
+fn string(chars: &[char], start: usize) -> Result<(Vec<u8>, usize), AsmErrorKind> {
+    let quote = chars[start];
+    let mut text = String::new();
+    let mut i = start + 1;
+    loop {
+        let Some(&c) = chars.get(i) else {
+            return Err(syntax("unterminated string"));
+        };
+        i += 1;
+        if c == quote {
+            return Ok((text.into_bytes(), i));
+        }
+        if c != '\\' || quote != '`' {
+            text.push(c);
+            continue;
+        }
+        let Some(&escape) = chars.get(i) else {
+            return Err(syntax("unterminated string"));
+        };
+        i += 1;
+        match escape {
+            'n' => text.push('\n'),
+            'r' => text.push('\r'),
+            't' => text.push('\t'),
+            '0' => text.push('\0'),
+            'x' => {
+                let digits: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+                let value = u8::from_str_radix(&digits, 16).map_err(|_| syntax("bad \\x escape"))?;
+                text.push(value as char);
+                i += 2;
+            }
+            other => text.push(other),
+        }
+    }
+}
+
+fn number(text: &str) -> Result<i64, AsmErrorKind> {
+    let lower = text.to_ascii_lowercase().replace('_', "");
+    let (digits, radix) = if let Some(digits) = lower.strip_suffix('h') {
+        (digits, 16)
+    } else if let Some(digits) = lower.strip_prefix("0x") {
+        (digits, 16)
+    } else if let Some(digits) = lower.strip_prefix("0b") {
+        (digits, 2)
+    } else if let Some(digits) = lower.strip_prefix("0o").or_else(|| lower.strip_prefix("0q")) {
+        (digits, 8)
+    } else if let Some(digits) = lower.strip_suffix('b').or_else(|| lower.strip_suffix('y')) {
+        (digits, 2)
+    } else if let Some(digits) = lower.strip_suffix('q').or_else(|| lower.strip_suffix('o')) {
+        (digits, 8)
+    } else {
+        (lower.strip_suffix('d').unwrap_or(&lower), 10)
+    };
+    u64::from_str_radix(digits, radix)
+        .map(|n| n as i64)
+        .map_err(|_| syntax(format!("bad number {text}")))
+}
+
+/// Splits on the commas outside brackets and parentheses
+fn split_commas(tokens: &[Token]) -> Vec<&[Token]> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Punct("[" | "(") => depth += 1,
+            Token::Punct("]" | ")") => depth -= 1,
+            Token::Punct(",") if depth == 0 => {
+                parts.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&tokens[start..]);
+    parts
+}
+
+/// Splits the inside of a memory operand into the terms that are added or subtracted,
+/// each with whether it is subtracted
+fn split_terms(tokens: &[Token]) -> Vec<(bool, &[Token])> {
+    let mut terms = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    let mut negative = false;
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Punct("(") => depth += 1,
+            Token::Punct(")") => depth -= 1,
+            // a sign is only a separator after something it can be added to, `4*-1` is a term
+            Token::Punct(sign @ ("+" | "-"))
+                if depth == 0
+                    && i > 0
+                    && matches!(
+                        tokens[i - 1],
+                        Token::Number(_) | Token::Ident(_) | Token::Str(_) | Token::Punct(")")
+                    ) =>
+            {
+                terms.push((negative, &tokens[start..i]));
+                negative = *sign == "-";
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    terms.push((negative, &tokens[start..]));
+    terms
+}
+
+fn size_keyword(word: &str) -> Option<u16> {
+    Some(match word.to_ascii_lowercase().as_str() {
+        "byte" => 1,
+        "word" => 2,
+        "dword" => 4,
+        "qword" => 8,
+        "tword" => 10,
+        "oword" | "xmmword" => 16,
+        "yword" | "ymmword" => 32,
+        "zword" | "zmmword" => 64,
+        _ => return None,
+    })
+}
+
+fn data_size(word: &str) -> Option<u8> {
+    Some(match word.to_ascii_lowercase().as_str() {
+        "db" => 1,
+        "dw" => 2,
+        "dd" => 4,
+        "dq" => 8,
+        _ => return None,
+    })
+}
+
+fn reserve_size(word: &str) -> Option<u8> {
+    Some(match word.to_ascii_lowercase().as_str() {
+        "resb" => 1,
+        "resw" => 2,
+        "resd" => 4,
+        "resq" => 8,
+        _ => return None,
+    })
+}
+
+/// Condition code spellings that share an encoding with the one the tables use
+const CONDITION_ALIASES: [(&str, &str); 14] = [
+    ("z", "e"),
+    ("nz", "ne"),
+    ("c", "b"),
+    ("nc", "ae"),
+    ("nae", "b"),
+    ("nb", "ae"),
+    ("na", "be"),
+    ("nbe", "a"),
+    ("pe", "p"),
+    ("po", "np"),
+    ("nge", "l"),
+    ("nl", "ge"),
+    ("ng", "le"),
+    ("nle", "g"),
+];
+
+/// String instructions that are written with their operand size in the name
+const SIZED_STRINGS: [(&str, Mnemonic); 7] = [
+    ("movs", Mnemonic::Movs),
+    ("cmps", Mnemonic::Cmps),
+    ("stos", Mnemonic::Stos),
+    ("lods", Mnemonic::Lods),
+    ("scas", Mnemonic::Scas),
+    ("ins", Mnemonic::Ins),
+    ("outs", Mnemonic::Outs),
+];
+
+/// The mnemonic an assembler name stands for, along with the operand size the name implies
+fn mnemonic(name: &str, has_operands: bool) -> Option<(Mnemonic, Option<u8>)> {
+    let name = name.to_ascii_lowercase();
+    // movsd and cmpsd are also SSE instructions, which always have operands
+    if !has_operands {
+        for (stem, mnemonic) in SIZED_STRINGS {
+            let size = match name.strip_prefix(stem) {
+                Some("w") => 2,
+                Some("d") => 4,
+                Some("q") => 8,
+                _ => continue,
+            };
+            return Some((mnemonic, Some(size)));
+        }
+    }
+    if let Some(mnemonic) = Mnemonic::from_name(&name) {
+        return Some((mnemonic, None));
+    }
+    for prefix in ["j", "cmov", "set"] {
+        if let Some(condition) = name.strip_prefix(prefix)
+            && let Some((_, canonical)) = CONDITION_ALIASES.iter().find(|(alias, _)| *alias == condition)
+        {
+            return Mnemonic::from_name(&format!("{prefix}{canonical}")).map(|m| (m, None));
+        }
+    }
+    let alias = match name.as_str() {
+        "loopz" => Mnemonic::Loope,
+        "loopnz" => Mnemonic::Loopne,
+        "xlat" => Mnemonic::Xlatb,
+        _ => return None,
+    };
+    Some((alias, None))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(i64),
+    Symbol(String),
+    /// `$`, the address of the current line
+    Here,
+    /// `$$`, the address of the current section
+    SectionStart,
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// `leaf` gives the value of symbols, `$` and `$$`
+    fn evaluate(&self, leaf: &dyn Fn(&Expr) -> Result<i64, AsmErrorKind>) -> Result<i64, AsmErrorKind> {
+        match self {
+            Expr::Number(n) => Ok(*n),
+            Expr::Unary(op, e) => {
+                let value = e.evaluate(leaf)?;
+                Ok(match *op {
+                    "-" => value.wrapping_neg(),
+                    "~" => !value,
+                    _ => value,
+                })
+            }
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.evaluate(leaf)?, b.evaluate(leaf)?);
+                if matches!(*op, "/" | "%") && b == 0 {
+                    return Err(syntax("division by zero"));
+                }
+                Ok(match *op {
+                    "+" => a.wrapping_add(b),
+                    "-" => a.wrapping_sub(b),
+                    "*" => a.wrapping_mul(b),
+                    "/" => a.wrapping_div(b),
+                    "%" => a.wrapping_rem(b),
+                    "&" => a & b,
+                    "|" => a | b,
+                    "^" => a ^ b,
+                    "<<" => a.wrapping_shl(b as u32),
+                    _ => (a as u64).wrapping_shr(b as u32) as i64,
+                })
+            }
+            leaf_expr => leaf(leaf_expr),
+        }
+    }
+
+    /// The value of an expression that may not refer to symbols
+    fn constant(&self) -> Result<i64, AsmErrorKind> {
+        self.evaluate(&|_| Err(syntax("expected a constant")))
+    }
+}
+
+fn precedence(op: &str) -> Option<u8> {
+    Some(match op {
+        "|" => 1,
+        "^" => 2,
+        "&" => 3,
+        "<<" | ">>" => 4,
+        "+" | "-" => 5,
+        "*" | "/" | "%" => 6,
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Addressing {
+    Default,
+    /// `[rel label]`
+    Rel,
+    /// `[abs label]`
+    Abs,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct MemorySource {
+    size: u16,
+    segment: Option<Register>,
+    base: Option<Register>,
+    index: Option<Register>,
+    scale: u8,
+    disp: Option<Expr>,
+    addressing: Addressing,
+    broadcast: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Register(Register),
+    Memory(MemorySource),
+    /// an immediate, or a branch target
+    Value { expr: Expr, size: Option<u8> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Data {
+    Bytes(Vec<u8>),
+    Value(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Statement {
+    Label(String),
+    Constant(String, Expr),
+    /// the prefixes and decorations are set on `template`, its operands come from `operands`
+    Instruction { template: Instruction, operands: Vec<Operand> },
+    Data { size: u8, values: Vec<Data> },
+    Reserve { size: u8, count: Expr },
+    Align(Expr),
+    Times(Expr, Box<Statement>),
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    number: usize,
+    statement: Statement,
+}
+
+#[derive(Debug, Clone)]
+struct SectionSource {
+    name: String,
+    align: u64,
+    lines: Vec<Line>,
+}
+
+#[derive(Debug, Clone)]
+struct SymbolSource {
+    name: String,
+    section: Option<usize>,
+}
+
+/// The parsed source
+#[derive(Debug)]
+struct Program {
+    bitness: Bitness,
+    base: u64,
+    default_rel: bool,
+    sections: Vec<SectionSource>,
+    current: Option<usize>,
+    symbols: Vec<SymbolSource>,
+    index: HashMap<String, usize>,
+    /// the last non local label
+    scope: String,
+    /// number of the line being parsed
+    number: usize,
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Program {
+            bitness: Bitness::Bits64,
+            base: 0,
+            default_rel: true,
+            sections: Vec::new(),
+            current: None,
+            symbols: Vec::new(),
+            index: HashMap::new(),
+            scope: String::new(),
+            number: 0,
+        }
+    }
+}
+
+impl Program {
+    fn line(&mut self, text: &str) -> Result<(), AsmErrorKind> {
+        self.number += 1;
+        let text = strip_comment(text).trim();
+        // [bits 64] and friends
+        let text = match text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            Some(inner) => inner.trim(),
+            None => text,
+        };
+        let tokens = tokenize(text)?;
+        let mut rest = tokens.as_slice();
+
+        if let [Token::Ident(name), Token::Punct(":"), tail @ ..] = rest
+            && Register::from_name(name).is_none()
+        {
+            let name = self.define(name, true)?;
+            self.push(Statement::Label(name));
+            rest = tail;
+        } else if let [Token::Ident(name), Token::Ident(next), tail @ ..] = rest {
+            // labels can leave out the colon in front of data
+            if next.eq_ignore_ascii_case("equ") {
+                let expr = self.expr_all(tail)?;
+                let name = self.define(name, false)?;
+                self.push(Statement::Constant(name, expr));
+                return Ok(());
+            }
+            if data_size(next).is_some() || reserve_size(next).is_some() || next.eq_ignore_ascii_case("times") {
+                let name = self.define(name, true)?;
+                self.push(Statement::Label(name));
+                rest = &rest[1..];
+            }
+        }
+
+        if rest.is_empty() {
+            return Ok(());
+        }
+        self.directive(rest)
+    }
+
+    fn directive(&mut self, tokens: &[Token]) -> Result<(), AsmErrorKind> {
+        let Token::Ident(word) = &tokens[0] else {
+            return Err(syntax(format!("unexpected {}", tokens[0])));
+        };
+        let rest = &tokens[1..];
+        match word.to_ascii_lowercase().as_str() {
+            "bits" | "use16" | "use32" | "use64" => {
+                let bits = match word.to_ascii_lowercase().as_str() {
+                    "bits" => self.expr_all(rest)?.constant()?,
+                    other => other[3..].parse().unwrap_or_default(),
+                };
+                self.bitness = match bits {
+                    16 => Bitness::Bits16,
+                    32 => Bitness::Bits32,
+                    64 => Bitness::Bits64,
+                    _ => return Err(syntax(format!("bits must be 16, 32 or 64, not {bits}"))),
+                };
+                self.before_code("bits")
+            }
+            "org" => {
+                self.base = self.expr_all(rest)?.constant()? as u64;
+                self.before_code("org")
+            }
+            "section" | "segment" => {
+                let [Token::Ident(name), attributes @ ..] = rest else {
+                    return Err(syntax("expected a section name"));
+                };
+                let index = match self.sections.iter().position(|s| s.name == *name) {
+                    Some(index) => index,
+                    None => {
+                        self.sections.push(SectionSource {
+                            name: name.clone(),
+                            align: 1,
+                            lines: Vec::new(),
+                        });
+                        self.sections.len() - 1
+                    }
+                };
+                self.current = Some(index);
+                self.section_attributes(index, attributes)
+            }
+            "default" => {
+                self.default_rel = match rest {
+                    [Token::Ident(mode)] if mode.eq_ignore_ascii_case("rel") => true,
+                    [Token::Ident(mode)] if mode.eq_ignore_ascii_case("abs") => false,
+                    _ => return Err(syntax("expected default rel or default abs")),
+                };
+                Ok(())
+            }
+            // there is a single flat image, so nothing to export
+            "global" => Ok(()),
+            _ => {
+                let statement = self.statement(tokens)?;
+                self.push(statement);
+                Ok(())
+            }
+        }
+    }
+
+    fn section_attributes(&mut self, index: usize, mut attributes: &[Token]) -> Result<(), AsmErrorKind> {
+        while let [first, tail @ ..] = attributes {
+            match (first, tail) {
+                (Token::Ident(key), [Token::Punct("="), Token::Number(align), tail @ ..])
+                    if key.eq_ignore_ascii_case("align") =>
+                {
+                    let align = *align as u64;
+                    if !align.is_power_of_two() {
+                        return Err(syntax("section alignment must be a power of two"));
+                    }
+                    self.sections[index].align = align;
+                    attributes = tail;
+                }
+                // progbits, nobits, exec, write and the like do not matter to a flat image
+                (Token::Ident(_), tail) => attributes = tail,
+                _ => return Err(syntax(format!("unexpected {first}"))),
+            }
+        }
+        Ok(())
+    }
+
+    fn before_code(&self, directive: &str) -> Result<(), AsmErrorKind> {
+        if self.sections.iter().any(|s| !s.lines.is_empty()) {
+            return Err(syntax(format!("{directive} must come before any code or data")));
+        }
+        Ok(())
+    }
+
+    /// Everything that can follow `times`
+    fn statement(&self, tokens: &[Token]) -> Result<Statement, AsmErrorKind> {
+        let Token::Ident(word) = &tokens[0] else {
+            return Err(syntax(format!("unexpected {}", tokens[0])));
+        };
+        let rest = &tokens[1..];
+        if let Some(size) = data_size(word) {
+            let values = split_commas(rest)
+                .into_iter()
+                .map(|part| match part {
+                    [Token::Str(bytes)] => Ok(Data::Bytes(bytes.clone())),
+                    _ => self.expr_all(part).map(Data::Value),
+                })
+                .collect::<Result<_, _>>()?;
+            return Ok(Statement::Data { size, values });
+        }
+        if let Some(size) = reserve_size(word) {
+            let count = self.expr_all(rest)?;
+            return Ok(Statement::Reserve { size, count });
+        }
+        match word.to_ascii_lowercase().as_str() {
+            "align" => Ok(Statement::Align(self.expr_all(rest)?)),
+            "times" => {
+                let mut pos = 0;
+                let count = self.expr(rest, &mut pos, 0)?;
+                if pos == rest.len() {
+                    return Err(syntax("times needs something to repeat"));
+                }
+                Ok(Statement::Times(count, Box::new(self.statement(&rest[pos..])?)))
+            }
+            _ => self.instruction(tokens),
+        }
+    }
+
+    fn instruction(&self, tokens: &[Token]) -> Result<Statement, AsmErrorKind> {
+        let mut lock = false;
+        let mut rep = None;
+        let mut rest = tokens;
+        let name = loop {
+            let [Token::Ident(word), tail @ ..] = rest else {
+                return Err(syntax("expected an instruction"));
+            };
+            match word.to_ascii_lowercase().as_str() {
+                "lock" => lock = true,
+                "rep" | "repe" | "repz" => rep = Some(RepPrefix::Rep),
+                "repne" | "repnz" | "bnd" => rep = Some(RepPrefix::Repne),
+                _ => break word,
+            }
+            rest = tail;
+        };
+
+        let mut template = Instruction::new(Mnemonic::Nop, &[]);
+        template.lock = lock;
+        template.rep = rep;
+        let mut operands = Vec::new();
+        let parts = &rest[1..];
+        if !parts.is_empty() {
+            for part in split_commas(parts) {
+                let mut broadcast = false;
+                let mut plain = Vec::new();
+                for token in part {
+                    match token {
+                        Token::Decorator(decorator) => decorate(&mut template, decorator, &mut broadcast)?,
+                        other => plain.push(other.clone()),
+                    }
+                }
+                if plain.is_empty() {
+                    // {rn-sae} is written as an operand of its own
+                    if part.is_empty() {
+                        return Err(syntax("missing operand"));
+                    }
+                    continue;
+                }
+                operands.push(self.operand(&plain, broadcast)?);
+            }
+        }
+
+        let (mnemonic, operand_size) =
+            mnemonic(name, !operands.is_empty()).ok_or_else(|| AsmErrorKind::UnknownMnemonic(name.clone()))?;
+        template.mnemonic = mnemonic;
+        template.operand_size = operand_size;
+        Ok(Statement::Instruction { template, operands })
+    }
+
+    fn operand(&self, tokens: &[Token], broadcast: bool) -> Result<Operand, AsmErrorKind> {
+        let mut size = None;
+        let mut rest = tokens;
+        if let [Token::Ident(word), tail @ ..] = rest
+            && let Some(bytes) = size_keyword(word)
+        {
+            size = Some(bytes);
+            rest = match tail {
+                [Token::Ident(ptr), after @ ..] if ptr.eq_ignore_ascii_case("ptr") => after,
+                _ => tail,
+            };
+        }
+        if let [Token::Ident(word), tail @ ..] = rest {
+            match word.to_ascii_lowercase().as_str() {
+                "short" | "near" => rest = tail,
+                "far" => return Err(syntax("far operands are not supported")),
+                _ => {}
+            }
+        }
+
+        let memory = match rest {
+            [Token::Punct("["), ..] => Some((None, rest)),
+            [Token::Ident(segment), Token::Punct(":"), tail @ ..]
+                if matches!(Register::from_name(segment), Some(Register::Segment(_)))
+                    && matches!(tail.first(), Some(Token::Punct("["))) =>
+            {
+                Some((Register::from_name(segment), tail))
+            }
+            _ => None,
+        };
+        if let Some((segment, tokens)) = memory {
+            return self
+                .memory(tokens, size.unwrap_or(0), segment, broadcast)
+                .map(Operand::Memory);
+        }
+        if broadcast {
+            return Err(syntax("broadcast needs a memory operand"));
+        }
+        if let [Token::Ident(name)] = rest
+            && let Some(register) = Register::from_name(name)
+        {
+            return Ok(Operand::Register(register));
+        }
+        let size = match size {
+            Some(size @ (1 | 2 | 4 | 8)) => Some(size as u8),
+            Some(_) => return Err(syntax("immediates are at most a qword")),
+            None => None,
+        };
+        Ok(Operand::Value {
+            expr: self.expr_all(rest)?,
+            size,
+        })
+    }
+
+    /// `tokens` runs from `[` to `]`
+    fn memory(
+        &self,
+        tokens: &[Token],
+        size: u16,
+        segment: Option<Register>,
+        broadcast: bool,
+    ) -> Result<MemorySource, AsmErrorKind> {
+        let Some((Token::Punct("]"), inner)) = tokens[1..].split_last() else {
+            return Err(syntax("expected ]"));
+        };
+        let mut memory = MemorySource {
+            size,
+            segment,
+            base: None,
+            index: None,
+            scale: 1,
+            disp: None,
+            addressing: Addressing::Default,
+            broadcast,
+        };
+
+        let mut inner = inner;
+        if let [Token::Ident(word), tail @ ..] = inner {
+            match word.to_ascii_lowercase().as_str() {
+                "rel" => (memory.addressing, inner) = (Addressing::Rel, tail),
+                "abs" => (memory.addressing, inner) = (Addressing::Abs, tail),
+                _ => {}
+            }
+        }
+        if let [Token::Ident(segment), Token::Punct(":"), tail @ ..] = inner
+            && let Some(register @ Register::Segment(_)) = Register::from_name(segment)
+        {
+            memory.segment = Some(register);
+            inner = tail;
+        }
+        if inner.is_empty() {
+            return Err(syntax("empty memory operand"));
+        }
+
+        for (negative, term) in split_terms(inner) {
+            let register = |name: &str| Register::from_name(name).filter(|_| !negative);
+            match term {
+                [Token::Ident(name)] if let Some(register) = register(name) => {
+                    if memory.base.is_none() {
+                        memory.base = Some(register);
+                    } else if memory.index.is_none() {
+                        memory.index = Some(register);
+                    } else {
+                        return Err(syntax("too many registers in memory operand"));
+                    }
+                }
+                [Token::Ident(name), Token::Punct("*"), Token::Number(scale)]
+                | [Token::Number(scale), Token::Punct("*"), Token::Ident(name)]
+                    if let Some(register) = register(name) =>
+                {
+                    if memory.index.is_some() {
+                        return Err(syntax("too many index registers in memory operand"));
+                    }
+                    if !matches!(scale, 1 | 2 | 4 | 8) {
+                        return Err(syntax("scale must be 1, 2, 4 or 8"));
+                    }
+                    memory.index = Some(register);
+                    memory.scale = *scale as u8;
+                }
+                _ => {
+                    if let [Token::Ident(name)] = term
+                        && Register::from_name(name).is_some()
+                    {
+                        return Err(syntax("registers cannot be subtracted"));
+                    }
+                    let mut expr = self.expr_all(term)?;
+                    if negative {
+                        expr = Expr::Unary("-", Box::new(expr));
+                    }
+                    memory.disp = Some(match memory.disp.take() {
+                        Some(disp) => Expr::Binary("+", Box::new(disp), Box::new(expr)),
+                        None => expr,
+                    });
+                }
+            }
+        }
+        Ok(memory)
+    }
+
+    /// An expression that has to use up all of `tokens`
+    fn expr_all(&self, tokens: &[Token]) -> Result<Expr, AsmErrorKind> {
+        let mut pos = 0;
+        let expr = self.expr(tokens, &mut pos, 0)?;
+        match tokens.get(pos) {
+            None => Ok(expr),
+            Some(token) => Err(syntax(format!("unexpected {token}"))),
+        }
+    }
+
+    /// Precedence climbing over the binary operators that bind at least as tightly as `min`
+    fn expr(&self, tokens: &[Token], pos: &mut usize, min: u8) -> Result<Expr, AsmErrorKind> {
+        let mut lhs = self.unary(tokens, pos)?;
+        while let Some(Token::Punct(op)) = tokens.get(*pos)
+            && let Some(level) = precedence(op)
+            && level >= min
+        {
+            *pos += 1;
+            let rhs = self.expr(tokens, pos, level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&self, tokens: &[Token], pos: &mut usize) -> Result<Expr, AsmErrorKind> {
+        let Some(token) = tokens.get(*pos) else {
+            return Err(syntax("expected an expression"));
+        };
+        *pos += 1;
+        match token {
+            Token::Punct(op @ ("-" | "+" | "~")) => Ok(Expr::Unary(op, Box::new(self.unary(tokens, pos)?))),
+            Token::Punct("(") => {
+                let expr = self.expr(tokens, pos, 0)?;
+                match tokens.get(*pos) {
+                    Some(Token::Punct(")")) => {
+                        *pos += 1;
+                        Ok(expr)
+                    }
+                    _ => Err(syntax("expected )")),
+                }
+            }
+            Token::Number(n) => Ok(Expr::Number(*n)),
+            // character constants are little endian, 'ab' is 0x6261
+            Token::Str(bytes) if bytes.len() <= 8 => {
+                Ok(Expr::Number(bytes.iter().rev().fold(0, |value, b| value << 8 | *b as i64)))
+            }
+            Token::Ident(name) => match name.as_str() {
+                "$" => Ok(Expr::Here),
+                "$$" => Ok(Expr::SectionStart),
+                _ if Register::from_name(name).is_some() => {
+                    Err(syntax(format!("register {name} used in an expression")))
+                }
+                // a leading $ marks a symbol that would otherwise read as a keyword
+                _ => Ok(Expr::Symbol(self.qualify(name.strip_prefix('$').unwrap_or(name)))),
+            },
+            other => Err(syntax(format!("unexpected {other}"))),
+        }
+    }
+
+    /// The full name of a symbol, local labels are prefixed with their scope
+    fn qualify(&self, name: &str) -> String {
+        if name.starts_with('.') {
+            format!("{}{}", self.scope, name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    fn define(&mut self, name: &str, label: bool) -> Result<String, AsmErrorKind> {
+        let local = name.starts_with('.');
+        let name = self.qualify(name);
+        if self.index.contains_key(&name) {
+            return Err(AsmErrorKind::DuplicateSymbol(name));
+        }
+        if label && !local {
+            self.scope = name.clone();
+        }
+        let section = label.then(|| self.section());
+        self.index.insert(name.clone(), self.symbols.len());
+        self.symbols.push(SymbolSource {
+            name: name.clone(),
+            section,
+        });
+        Ok(name)
+    }
+
+    /// The current section, starting `.text` when there is none
+    fn section(&mut self) -> usize {
+        *self.current.get_or_insert_with(|| {
+            self.sections.push(SectionSource {
+                name: ".text".to_string(),
+                align: 1,
+                lines: Vec::new(),
+            });
+            self.sections.len() - 1
+        })
+    }
+
+    fn push(&mut self, statement: Statement) {
+        let section = self.section();
+        let number = self.number;
+        self.sections[section].lines.push(Line { number, statement });
+    }
+
+    fn assemble(&self) -> Result<Object, AsmError> {
+        let mut layout = Layout::default();
+        for _ in 0..MAX_PASSES {
+            let (next, output, deferred) = self.pass(&layout)?;
+            if next == layout {
+                if let Some(error) = deferred {
+                    return Err(error);
+                }
+                return Ok(self.object(&layout, output));
+            }
+            layout = next;
+        }
+        Err(AsmError {
+            line: 0,
+            kind: AsmErrorKind::NoConvergence,
+        })
+    }
+
+    /// Assembles the program with the symbol values and addresses of the previous pass
+    fn pass(&self, layout: &Layout) -> Result<(Layout, Vec<u8>, Option<AsmError>), AsmError> {
+        let mut asm = Assembler::new(self.bitness);
+        let labels = self
+            .symbols
+            .iter()
+            .filter(|s| s.section.is_some())
+            .map(|s| (s.name.as_str(), asm.new_label()))
+            .collect();
+        let mut pass = Pass {
+            program: self,
+            asm,
+            labels,
+            values: layout.values.clone(),
+            line: 0,
+            deferred: None,
+        };
+
+        let mut lines = Vec::new();
+        let mut sections = Vec::new();
+        for (index, section) in self.sections.iter().enumerate() {
+            let end = match index {
+                0 => self.base,
+                _ => layout.section(index - 1, self.base).1,
+            };
+            pass.asm.db(&vec![0; padding(end, section.align) as usize]);
+            let start = pass.asm.here();
+            let here = Here {
+                line: 0,
+                next: 0,
+                section: layout.section(index, self.base).0,
+            };
+
+            let mut line_labels = Vec::new();
+            for (i, line) in section.lines.iter().enumerate() {
+                line_labels.push(pass.asm.here());
+                pass.line = line.number;
+                let here = Here {
+                    line: layout.line(index, i, self.base),
+                    next: match i + 1 == section.lines.len() {
+                        true => layout.section(index, self.base).1,
+                        false => layout.line(index, i + 1, self.base),
+                    },
+                    ..here
+                };
+                pass.statement(&line.statement, section, here).map_err(|kind| AsmError {
+                    line: line.number,
+                    kind,
+                })?;
+            }
+            lines.push(line_labels);
+            sections.push((start, pass.asm.here()));
+        }
+
+        let output = pass.asm.assemble(self.base).map_err(|error| AsmError {
+            line: 0,
+            kind: AsmErrorKind::Encode(error),
+        })?;
+        let address = |label: Label| pass.asm.label_address(label).unwrap_or(self.base);
+        let mut values = pass.values.clone();
+        for (name, label) in &pass.labels {
+            values.insert(name.to_string(), address(*label) as i64);
+        }
+        let next = Layout {
+            values,
+            lines: lines.iter().map(|l| l.iter().map(|label| address(*label)).collect()).collect(),
+            sections: sections.iter().map(|(start, end)| (address(*start), address(*end))).collect(),
+        };
+        Ok((next, output, pass.deferred))
+    }
+
+    fn object(&self, layout: &Layout, output: Vec<u8>) -> Object {
+        let sections = self
+            .sections
+            .iter()
+            .enumerate()
+            .map(|(index, section)| {
+                let (start, end) = layout.section(index, self.base);
+                Section {
+                    name: section.name.clone(),
+                    address: start,
+                    bytes: output[(start - self.base) as usize..(end - self.base) as usize].to_vec(),
+                }
+            })
+            .collect();
+        let symbols = self
+            .symbols
+            .iter()
+            .map(|symbol| Symbol {
+                name: symbol.name.clone(),
+                value: layout.values.get(&symbol.name).copied().unwrap_or_default() as u64,
+                section: symbol.section,
+            })
+            .collect();
+        Object {
+            bitness: self.bitness,
+            base: self.base,
+            sections,
+            symbols,
+        }
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if matches!(c, '\'' | '"' | '`') => quote = Some(c),
+            None if c == ';' => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+fn decorate(template: &mut Instruction, decorator: &str, broadcast: &mut bool) -> Result<(), AsmErrorKind> {
+    match decorator {
+        "z" => template.zeroing = true,
+        "sae" => template.sae = true,
+        "rn-sae" => template.rounding = Some(RoundingControl::Nearest),
+        "rd-sae" => template.rounding = Some(RoundingControl::Down),
+        "ru-sae" => template.rounding = Some(RoundingControl::Up),
+        "rz-sae" => template.rounding = Some(RoundingControl::Zero),
+        _ if decorator.starts_with("1to") => *broadcast = true,
+        _ => match Register::from_name(decorator) {
+            Some(mask @ Register::Opmask(_)) => template.opmask = Some(mask),
+            _ => return Err(syntax(format!("unknown decorator {{{decorator}}}"))),
+        },
+    }
+    if template.rounding.is_some() {
+        template.sae = true;
+    }
+    Ok(())
+}
+
+/// bytes needed to take `address` up to a multiple of `align`
+fn padding(address: u64, align: u64) -> u64 {
+    address.wrapping_neg() & (align - 1)
+}
+
+/// Symbol values and addresses found by a pass
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Layout {
+    values: HashMap<String, i64>,
+    /// address of every line, by section
+    lines: Vec<Vec<u64>>,
+    /// start and end address of every section
+    sections: Vec<(u64, u64)>,
+}
+
+impl Layout {
+    // before the first pass nothing is known, and everything is taken to be at the base
+
+    fn line(&self, section: usize, line: usize, base: u64) -> u64 {
+        self.lines.get(section).and_then(|l| l.get(line)).copied().unwrap_or(base)
+    }
+
+    fn section(&self, section: usize, base: u64) -> (u64, u64) {
+        self.sections.get(section).copied().unwrap_or((base, base))
+    }
+}
+
+/// The values of `$` and `$$` on a line
+#[derive(Debug, Clone, Copy)]
+struct Here {
+    line: u64,
+    /// where the next line starts, the end of an instruction for rip relative addresses
+    next: u64,
+    section: u64,
+}
+
+struct Pass<'a> {
+    program: &'a Program,
+    asm: Assembler,
+    labels: HashMap<&'a str, Label>,
+    values: HashMap<String, i64>,
+    line: usize,
+    /// an error that only stands if the pass turns out to be the last
+    deferred: Option<AsmError>,
+}
+
+impl Pass<'_> {
+    fn statement(&mut self, statement: &Statement, section: &SectionSource, here: Here) -> Result<(), AsmErrorKind> {
+        match statement {
+            Statement::Label(name) => self.asm.bind(self.labels[name.as_str()]).map_err(AsmErrorKind::Encode),
+            Statement::Constant(name, expr) => {
+                let value = self.eval(expr, here)?;
+                self.values.insert(name.clone(), value);
+                Ok(())
+            }
+            Statement::Instruction { template, operands } => {
+                let branch = tables::tables().is_relative_branch(template.mnemonic);
+                let mut instruction = template.clone();
+                instruction.operands = operands
+                    .iter()
+                    .map(|operand| self.arg(operand, branch, here))
+                    .collect::<Result<_, _>>()?;
+                self.asm.emit(instruction).map_err(AsmErrorKind::Encode)
+            }
+            Statement::Data { size, values } => {
+                let size = *size as usize;
+                let mut bytes = Vec::new();
+                for value in values {
+                    match value {
+                        Data::Bytes(string) => {
+                            bytes.extend(string);
+                            bytes.resize(bytes.len().next_multiple_of(size), 0);
+                        }
+                        Data::Value(expr) => bytes.extend(&self.eval(expr, here)?.to_le_bytes()[..size]),
+                    }
+                }
+                self.asm.db(&bytes);
+                Ok(())
+            }
+            Statement::Reserve { size, count } => {
+                let count = self.count(count, here)?;
+                self.asm.db(&vec![0; count * *size as usize]);
+                Ok(())
+            }
+            Statement::Align(expr) => {
+                let align = self.eval(expr, here)? as u64;
+                if !align.is_power_of_two() {
+                    return Err(syntax("alignment must be a power of two"));
+                }
+                let fill = if section.name.starts_with(".text") { 0x90 } else { 0 };
+                self.asm.db(&vec![fill; padding(here.line, align) as usize]);
+                Ok(())
+            }
+            Statement::Times(count, statement) => {
+                for _ in 0..self.count(count, here)? {
+                    self.statement(statement, section, here)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// A repeat count. Negative counts are an error only once the addresses have settled,
+    /// `times 16 - ($ - start)` can be negative on the way there
+    fn count(&mut self, expr: &Expr, here: Here) -> Result<usize, AsmErrorKind> {
+        let count = self.eval(expr, here)?;
+        if count < 0 {
+            self.deferred.get_or_insert(AsmError {
+                line: self.line,
+                kind: AsmErrorKind::NegativeCount,
+            });
+            return Ok(0);
+        }
+        Ok(count as usize)
+    }
+
+    fn eval(&self, expr: &Expr, here: Here) -> Result<i64, AsmErrorKind> {
+        expr.evaluate(&|leaf| match leaf {
+            Expr::Here => Ok(here.line as i64),
+            Expr::SectionStart => Ok(here.section as i64),
+            Expr::Symbol(name) if self.program.index.contains_key(name) => {
+                // not yet known on the first pass
+                Ok(self.values.get(name).copied().unwrap_or_default())
+            }
+            Expr::Symbol(name) => Err(AsmErrorKind::UndefinedSymbol(name.clone())),
+            _ => unreachable!("evaluate only passes leaves"),
+        })
+    }
+
+    fn arg(&self, operand: &Operand, branch: bool, here: Here) -> Result<Arg, AsmErrorKind> {
+        Ok(match operand {
+            Operand::Register(register) => Arg::Register(*register),
+            Operand::Value { expr, .. } if branch => match expr {
+                Expr::Symbol(name) if self.labels.contains_key(name.as_str()) => Arg::Label(self.labels[name.as_str()]),
+                _ => Arg::Address(self.eval(expr, here)? as u64),
+            },
+            Operand::Value { expr, size } => Arg::Immediate {
+                value: self.eval(expr, here)?,
+                size: *size,
+            },
+            Operand::Memory(memory) => Arg::Memory(self.memory(memory, here)?),
+        })
+    }
+
+    fn memory(&self, memory: &MemorySource, here: Here) -> Result<Mem, AsmErrorKind> {
+        let mut mem = Mem {
+            segment: memory.segment,
+            base: memory.base,
+            index: memory.index,
+            scale: memory.scale,
+            size: memory.size,
+            broadcast: memory.broadcast,
+            ..Mem::default()
+        };
+        let Some(disp) = &memory.disp else {
+            return Ok(mem);
+        };
+        mem.disp = self.eval(disp, here)?;
+
+        // a lone label is left to the assembler to fix up, rip relative in 64-bit code
+        let relative = match memory.addressing {
+            Addressing::Rel => true,
+            Addressing::Abs => self.program.bitness != Bitness::Bits64,
+            Addressing::Default => self.program.default_rel || self.program.bitness != Bitness::Bits64,
+        };
+        if relative
+            && mem.base.is_none()
+            && mem.index.is_none()
+            && let Some(name) = self.sole_label(disp)
+        {
+            mem.label = Some(self.labels[name]);
+            mem.disp = mem.disp.wrapping_sub(self.values.get(name).copied().unwrap_or_default());
+        } else if memory.addressing == Addressing::Rel
+            && self.program.bitness == Bitness::Bits64
+            && mem.base.is_none()
+            && mem.index.is_none()
+        {
+            // `[rel 0x1234]` is relative to the end of the instruction, which is where the
+            // previous pass found the next line
+            mem.base = Some(Register::Rip);
+            mem.disp = mem.disp.wrapping_sub(here.next as i64);
+        }
+        Ok(mem)
+    }
+
+    /// The label an address is relative to: the one label left over once any that are
+    /// subtracted from each other cancel out, as in `table + 8` or `table + (b - a)`
+    fn sole_label<'e>(&self, expr: &'e Expr) -> Option<&'e str> {
+        let mut terms = HashMap::new();
+        self.label_terms(expr, 1, &mut terms);
+        let mut remaining = terms.into_iter().filter(|(_, count)| *count != 0);
+        match (remaining.next(), remaining.next()) {
+            (Some((name, 1)), None) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn label_terms<'e>(&self, expr: &'e Expr, sign: i64, terms: &mut HashMap<&'e str, i64>) {
+        match expr {
+            Expr::Symbol(name) if self.labels.contains_key(name.as_str()) => {
+                *terms.entry(name.as_str()).or_default() += sign;
+            }
+            Expr::Unary("-", e) => self.label_terms(e, -sign, terms),
+            Expr::Unary("+", e) => self.label_terms(e, sign, terms),
+            Expr::Binary("+", a, b) => {
+                self.label_terms(a, sign, terms);
+                self.label_terms(b, sign, terms);
+            }
+            Expr::Binary("-", a, b) => {
+                self.label_terms(a, sign, terms);
+                self.label_terms(b, -sign, terms);
+            }
+            _ => {}
+        }
+    }
+}
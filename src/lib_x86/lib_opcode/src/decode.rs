@@ -0,0 +1,930 @@
+use crate::opcodes::*;
+use crate::registers::Register;
+use crate::tables::{self, Entry, Mod, Op, Pfx, SizeMatch, Sz};
+use std::fmt;
+
+/// Architectural limit on the length of a single instruction
+pub const MAX_INSTRUCTION_LENGTH: usize = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// the input ended part way through an instruction
+    UnexpectedEnd,
+    /// the instruction would be longer than 15 bytes
+    TooLong,
+    /// no instruction is encoded by these bytes in the current mode
+    InvalidOpcode { map: OpcodeMap, opcode: u8 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of instruction bytes"),
+            DecodeError::TooLong => write!(f, "instruction exceeds {MAX_INSTRUCTION_LENGTH} bytes"),
+            DecodeError::InvalidOpcode { map, opcode } => {
+                write!(f, "invalid opcode {opcode:02X} in map {map:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decodes a single 64-bit mode instruction from the start of `bytes`, at address 0
+pub fn decode(bytes: &[u8]) -> Result<X86Opcode, DecodeError> {
+    decode_at(Bitness::Bits64, bytes, 0)
+}
+
+/// Decodes a single instruction from the start of `bytes`, as if it was located at `ip`
+/// (which only matters for relative branch targets)
+pub fn decode_at(bitness: Bitness, bytes: &[u8], ip: u64) -> Result<X86Opcode, DecodeError> {
+    DecodeState::new(bitness, bytes, ip).decode()
+}
+
+/// Decodes a stream of instructions
+///
+/// Iterating yields one result per instruction. Undecodable bytes yield an error and are
+/// skipped one byte at a time, the way a disassembler would resynchronise.
+pub struct Decoder<'a> {
+    bitness: Bitness,
+    bytes: &'a [u8],
+    position: usize,
+    ip: u64,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bitness: Bitness, bytes: &'a [u8], ip: u64) -> Decoder<'a> {
+        Decoder {
+            bitness,
+            bytes,
+            position: 0,
+            ip,
+        }
+    }
+
+    /// offset into the input of the next instruction
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// address of the next instruction
+    pub fn ip(&self) -> u64 {
+        self.ip
+    }
+
+    /// Decodes the next instruction. On error the position is left unchanged
+    pub fn decode(&mut self) -> Result<X86Opcode, DecodeError> {
+        let instruction = decode_at(self.bitness, &self.bytes[self.position..], self.ip)?;
+        self.position += instruction.length as usize;
+        self.ip = instruction.next_ip();
+        Ok(instruction)
+    }
+}
+
+impl Iterator for Decoder<'_> {
+    type Item = Result<X86Opcode, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.bytes.len() {
+            return None;
+        }
+
+        let result = self.decode();
+        if result.is_err() {
+            self.position += 1;
+            self.ip = self.ip.wrapping_add(1);
+        }
+        Some(result)
+    }
+}
+
+/// Working state for decoding one instruction
+struct DecodeState<'a> {
+    bitness: Bitness,
+    bytes: &'a [u8],
+    pos: usize,
+    ip: u64,
+
+    prefixes: Prefixes,
+    rex: Option<Rex>,
+    encoding: Encoding,
+    vex: Option<VexPrefix>,
+    mandatory: MandatoryPrefix,
+    map: OpcodeMap,
+    opcode: u8,
+    modrm: Option<ModRm>,
+    sib: Option<Sib>,
+    displacement: Option<Displacement>,
+    immediates: [Option<Immediate>; 2],
+    memory: Option<MemoryOperand>,
+
+    operand_size: u8,
+    address_size: u8,
+    vector_length: u16,
+    broadcast: bool,
+}
+
+impl<'a> DecodeState<'a> {
+    fn new(bitness: Bitness, bytes: &'a [u8], ip: u64) -> Self {
+        DecodeState {
+            bitness,
+            bytes,
+            pos: 0,
+            ip,
+            prefixes: Prefixes::default(),
+            rex: None,
+            encoding: Encoding::Legacy,
+            vex: None,
+            mandatory: MandatoryPrefix::None,
+            map: OpcodeMap::Primary,
+            opcode: 0,
+            modrm: None,
+            sib: None,
+            displacement: None,
+            immediates: [None; 2],
+            memory: None,
+            operand_size: 4,
+            address_size: 8,
+            vector_length: 16,
+            broadcast: false,
+        }
+    }
+
+    fn peek(&self) -> Result<u8, DecodeError> {
+        if self.pos >= MAX_INSTRUCTION_LENGTH {
+            return Err(DecodeError::TooLong);
+        }
+        self.bytes.get(self.pos).copied().ok_or(DecodeError::UnexpectedEnd)
+    }
+
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        let b = self.peek()?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    /// reads a little endian value of `size` bytes, zero extended
+    fn read(&mut self, size: u8) -> Result<u64, DecodeError> {
+        let mut value = 0u64;
+        for i in 0..size {
+            value |= (self.byte()? as u64) << (8 * i);
+        }
+        Ok(value)
+    }
+
+    fn is_64(&self) -> bool {
+        self.bitness == Bitness::Bits64
+    }
+
+    fn is_evex(&self) -> bool {
+        self.encoding == Encoding::Evex
+    }
+
+    /// REX.W, or its VEX / EVEX equivalent
+    fn rex_w(&self) -> bool {
+        match self.vex {
+            Some(vex) => vex.w,
+            None => self.rex.is_some_and(|r| r.w),
+        }
+    }
+    fn rex_r(&self) -> u8 {
+        match self.vex {
+            Some(vex) => (vex.r as u8) << 3,
+            None => self.rex.map_or(0, |r| (r.r as u8) << 3),
+        }
+    }
+    fn rex_x(&self) -> u8 {
+        match self.vex {
+            Some(vex) => (vex.x as u8) << 3,
+            None => self.rex.map_or(0, |r| (r.x as u8) << 3),
+        }
+    }
+    fn rex_b(&self) -> u8 {
+        match self.vex {
+            Some(vex) => (vex.b as u8) << 3,
+            None => self.rex.map_or(0, |r| (r.b as u8) << 3),
+        }
+    }
+
+    /// ModR/M.reg extension for vector registers, EVEX reaches registers 16-31 through R'
+    fn vector_reg_ext(&self) -> u8 {
+        let r2 = self.vex.is_some_and(|v| self.is_evex() && v.r2);
+        self.rex_r() | ((r2 as u8) << 4)
+    }
+
+    /// ModR/M.rm extension for vector registers, EVEX reaches registers 16-31 through X
+    fn vector_rm_ext(&self) -> u8 {
+        let x = self.vex.is_some_and(|v| self.is_evex() && v.x);
+        self.rex_b() | ((x as u8) << 4)
+    }
+
+    fn vvvv(&self) -> u8 {
+        self.vex.map_or(0, |v| v.vvvv)
+    }
+
+    /// size in bytes of a broadcast element
+    fn element_size(&self) -> u16 {
+        if self.rex_w() { 8 } else { 4 }
+    }
+
+    fn decode(mut self) -> Result<X86Opcode, DecodeError> {
+        self.read_prefixes()?;
+        if self.at_vex_prefix() {
+            self.read_vex()?;
+        } else {
+            self.read_opcode()?;
+        }
+
+        let candidates = tables::tables()
+            .map(self.encoding, self.map)
+            .candidates(self.opcode);
+        let invalid = DecodeError::InvalidOpcode {
+            map: self.map,
+            opcode: self.opcode,
+        };
+        let first = candidates.first().ok_or(invalid)?;
+
+        if first.uses_modrm() {
+            self.modrm = Some(ModRm::from_byte(self.byte()?));
+        }
+
+        self.address_size = match (self.bitness, self.prefixes.address_size) {
+            (Bitness::Bits64, false) => 8,
+            (Bitness::Bits64, true) => 4,
+            (Bitness::Bits32, false) => 4,
+            (Bitness::Bits32, true) => 2,
+            (Bitness::Bits16, false) => 2,
+            (Bitness::Bits16, true) => 4,
+        };
+
+        let entry = *candidates
+            .iter()
+            .find(|e| self.matches(e))
+            .ok_or(invalid)?;
+
+        self.operand_size = self.operand_size_for(entry);
+
+        let mut rounding = None;
+        let mut sae = false;
+        if let Some(vex) = self.vex
+            && self.is_evex()
+            && vex.bcst
+        {
+            if self.modrm.is_some_and(|m| !m.is_register()) {
+                self.broadcast = true;
+            } else if entry.has(tables::SAE) {
+                sae = true;
+            } else {
+                // L'L holds the rounding mode, and the vector length is implicitly 512 bits
+                rounding = Some(RoundingControl::from_bits(vex.l));
+                sae = true;
+            }
+        }
+
+        if let Some(modrm) = self.modrm
+            && !modrm.is_register()
+            && !entry.has(tables::MOD_REG)
+        {
+            let vsib = entry.ops.iter().find_map(|op| match op {
+                Op::Vsib(sz) => Some(self.vector_register_size(*sz)),
+                _ => None,
+            });
+            self.read_memory(modrm, vsib)?;
+        }
+
+        let mut operands = Operands::new();
+        for op in entry.ops {
+            if let Some(operand) = self.operand(entry, *op)? {
+                operands.push(operand);
+            }
+        }
+
+        let length = self.pos as u8;
+        let next_ip = self.ip.wrapping_add(length as u64);
+        let mut operands_fixed = Operands::new();
+        for operand in operands.iter() {
+            // relative branches were recorded as offsets, now that the length is known they can
+            // be turned into absolute targets
+            operands_fixed.push(match *operand {
+                Operand::Branch(rel) => {
+                    let target = next_ip.wrapping_add(rel);
+                    Operand::Branch(match self.operand_size {
+                        2 => target & 0xFFFF,
+                        4 => target & 0xFFFF_FFFF,
+                        _ => target,
+                    })
+                }
+                other => other,
+            });
+        }
+
+        // shared SSE entries take their AVX name when VEX / EVEX encoded
+        let mnemonic = if self.encoding != Encoding::Legacy && entry.encodable(Encoding::Legacy) {
+            entry.mnemonic.vex_form()
+        } else {
+            entry.mnemonic
+        };
+
+        Ok(X86Opcode {
+            mnemonic,
+            operands: operands_fixed,
+            ip: self.ip,
+            length,
+            bitness: self.bitness,
+            operand_size: self.operand_size,
+            address_size: self.address_size,
+            prefixes: self.prefixes,
+            mandatory_prefix: self.mandatory,
+            rex: self.rex,
+            encoding: self.encoding,
+            vex: self.vex,
+            map: self.map,
+            opcode: self.opcode,
+            modrm: self.modrm,
+            sib: self.sib,
+            displacement: self.displacement,
+            immediate: self.immediates[0],
+            immediate2: self.immediates[1],
+            vector_length: self.vector_length,
+            broadcast: self.broadcast,
+            rounding,
+            sae,
+            flags: entry.flags,
+        })
+    }
+
+    /// Legacy prefixes and REX. A REX prefix only counts when it is the last prefix before
+    /// the opcode; a legacy prefix after it cancels it
+    fn read_prefixes(&mut self) -> Result<(), DecodeError> {
+        loop {
+            let b = self.peek()?;
+            match b {
+                0xF0 => self.prefixes.lock = true,
+                0xF2 => self.prefixes.rep = Some(RepPrefix::Repne),
+                0xF3 => self.prefixes.rep = Some(RepPrefix::Rep),
+                0x26 => self.prefixes.segment = Some(Register::ES),
+                0x2E => self.prefixes.segment = Some(Register::CS),
+                0x36 => self.prefixes.segment = Some(Register::SS),
+                0x3E => self.prefixes.segment = Some(Register::DS),
+                0x64 => self.prefixes.segment = Some(Register::FS),
+                0x65 => self.prefixes.segment = Some(Register::GS),
+                0x66 => self.prefixes.operand_size = true,
+                0x67 => self.prefixes.address_size = true,
+                0x40..=0x4F if self.is_64() => {
+                    self.rex = Some(Rex::from_byte(b));
+                    self.pos += 1;
+                    continue;
+                }
+                _ => break,
+            }
+            self.rex = None;
+            self.prefixes.count += 1;
+            self.pos += 1;
+        }
+
+        // F2 / F3 take priority over 66 when selecting an instruction
+        self.mandatory = match (self.prefixes.rep, self.prefixes.operand_size) {
+            (Some(RepPrefix::Rep), _) => MandatoryPrefix::PF3,
+            (Some(RepPrefix::Repne), _) => MandatoryPrefix::PF2,
+            (None, true) => MandatoryPrefix::P66,
+            (None, false) => MandatoryPrefix::None,
+        };
+
+        Ok(())
+    }
+
+    fn read_opcode(&mut self) -> Result<(), DecodeError> {
+        let b = self.byte()?;
+        if b != 0x0F {
+            self.map = OpcodeMap::Primary;
+            self.opcode = b;
+            return Ok(());
+        }
+
+        let b = self.byte()?;
+        match b {
+            0x38 => {
+                self.map = OpcodeMap::Map0F38;
+                self.opcode = self.byte()?;
+            }
+            0x3A => {
+                self.map = OpcodeMap::Map0F3A;
+                self.opcode = self.byte()?;
+            }
+            _ => {
+                self.map = OpcodeMap::Map0F;
+                self.opcode = b;
+            }
+        }
+        Ok(())
+    }
+
+    /// C4 / C5 / 62 are always VEX / EVEX in 64-bit mode. Elsewhere they are LES / LDS /
+    /// BOUND unless the following byte would be a register form ModR/M, which those
+    /// instructions cannot take
+    fn at_vex_prefix(&self) -> bool {
+        match self.bytes.get(self.pos) {
+            Some(0xC4 | 0xC5 | 0x62) => {
+                self.is_64() || self.bytes.get(self.pos + 1).is_some_and(|b| b >> 6 == 0b11)
+            }
+            _ => false,
+        }
+    }
+
+    fn read_vex(&mut self) -> Result<(), DecodeError> {
+        let lead = self.byte()?;
+        let invalid = DecodeError::InvalidOpcode {
+            map: OpcodeMap::Primary,
+            opcode: lead,
+        };
+        // VEX / EVEX replace REX and the mandatory prefixes, and cannot be locked
+        if self.rex.is_some()
+            || self.prefixes.lock
+            || self.prefixes.operand_size
+            || self.prefixes.rep.is_some()
+        {
+            return Err(invalid);
+        }
+
+        let mut vex = VexPrefix::default();
+        match lead {
+            0xC5 => {
+                let b1 = self.byte()?;
+                vex.size = 2;
+                vex.r = b1 & 0x80 == 0;
+                vex.vvvv = (!b1 >> 3) & 0xF;
+                vex.l = (b1 >> 2) & 1;
+                vex.pp = b1 & 0b11;
+                vex.map_select = 1;
+                self.encoding = Encoding::Vex;
+            }
+            0xC4 => {
+                let b1 = self.byte()?;
+                let b2 = self.byte()?;
+                vex.size = 3;
+                vex.r = b1 & 0x80 == 0;
+                vex.x = b1 & 0x40 == 0;
+                vex.b = b1 & 0x20 == 0;
+                vex.map_select = b1 & 0x1F;
+                vex.w = b2 & 0x80 != 0;
+                vex.vvvv = (!b2 >> 3) & 0xF;
+                vex.l = (b2 >> 2) & 1;
+                vex.pp = b2 & 0b11;
+                self.encoding = Encoding::Vex;
+            }
+            _ => {
+                let p0 = self.byte()?;
+                let p1 = self.byte()?;
+                let p2 = self.byte()?;
+                // fixed bits: P0[3:2] = 00, P1[2] = 1
+                if p0 & 0b1100 != 0 || p1 & 0b100 == 0 {
+                    return Err(invalid);
+                }
+                vex.size = 4;
+                vex.r = p0 & 0x80 == 0;
+                vex.x = p0 & 0x40 == 0;
+                vex.b = p0 & 0x20 == 0;
+                vex.r2 = p0 & 0x10 == 0;
+                vex.map_select = p0 & 0b11;
+                vex.w = p1 & 0x80 != 0;
+                vex.vvvv = ((!p1 >> 3) & 0xF) | (((p2 & 0x08 == 0) as u8) << 4);
+                vex.pp = p1 & 0b11;
+                vex.z = p2 & 0x80 != 0;
+                vex.l = (p2 >> 5) & 0b11;
+                vex.bcst = p2 & 0x10 != 0;
+                vex.aaa = p2 & 0b111;
+                self.encoding = Encoding::Evex;
+            }
+        }
+
+        if !self.is_64() {
+            // only 8 (vector) registers outside of 64-bit mode
+            vex.b = false;
+            vex.r2 = false;
+            vex.vvvv &= 0b111;
+        }
+
+        self.map = match vex.map_select {
+            1 => OpcodeMap::Map0F,
+            2 => OpcodeMap::Map0F38,
+            3 => OpcodeMap::Map0F3A,
+            _ => return Err(invalid),
+        };
+        self.mandatory = match vex.pp {
+            0 => MandatoryPrefix::None,
+            1 => MandatoryPrefix::P66,
+            2 => MandatoryPrefix::PF3,
+            _ => MandatoryPrefix::PF2,
+        };
+        self.vector_length = 16 << vex.l.min(2);
+        self.vex = Some(vex);
+        self.opcode = self.byte()?;
+
+        // register form EVEX.b reuses L'L as the rounding mode, at 512 bits
+        if self.is_evex() && vex.bcst && self.peek().is_ok_and(|m| m >> 6 == 0b11) {
+            self.vector_length = 64;
+        }
+        Ok(())
+    }
+
+    fn matches(&self, entry: &Entry) -> bool {
+        if entry.has(tables::I64) && self.is_64() {
+            return false;
+        }
+        if entry.has(tables::O64) && !self.is_64() {
+            return false;
+        }
+        if entry.has(tables::NO_REXB) && self.rex_b() != 0 {
+            return false;
+        }
+
+        let prefix_ok = match entry.prefix {
+            Pfx::Any => true,
+            Pfx::None => self.mandatory == MandatoryPrefix::None,
+            Pfx::P66 => self.mandatory == MandatoryPrefix::P66,
+            Pfx::PF3 => self.mandatory == MandatoryPrefix::PF3,
+            Pfx::PF2 => self.mandatory == MandatoryPrefix::PF2,
+        };
+        if !prefix_ok {
+            return false;
+        }
+
+        if entry.w.is_some_and(|w| w != self.rex_w()) {
+            return false;
+        }
+        if self.is_evex() && entry.has(tables::EW1) && !self.rex_w() {
+            return false;
+        }
+        if let (Some(l), Some(vex)) = (entry.l, self.vex)
+            && l != vex.l
+        {
+            return false;
+        }
+
+        if let Some(modrm) = self.modrm {
+            if entry.digit.is_some_and(|d| d != modrm.reg) {
+                return false;
+            }
+            if entry.modrm.is_some_and(|m| m != modrm.byte()) {
+                return false;
+            }
+            match entry.required_mod() {
+                Mod::Mem if modrm.is_register() => return false,
+                Mod::Reg if !modrm.is_register() && !entry.has(tables::MOD_REG) => return false,
+                _ => {}
+            }
+        }
+
+        match entry.size {
+            SizeMatch::Any => true,
+            SizeMatch::Operand(size) => self.operand_size_for(entry) == size,
+            SizeMatch::Address(size) => self.address_size == size,
+        }
+    }
+
+    /// Effective operand size if `entry` is the instruction being decoded
+    fn operand_size_for(&self, entry: &Entry) -> u8 {
+        entry.operand_size(self.bitness, self.rex_w(), self.prefixes.operand_size)
+    }
+
+    /// SIB and displacement following a memory form ModR/M. `vsib` is the size of the index
+    /// vector register for gathers and scatters
+    fn read_memory(&mut self, modrm: ModRm, vsib: Option<u16>) -> Result<(), DecodeError> {
+        if vsib.is_some() && (self.address_size == 2 || modrm.rm != 0b100) {
+            return Err(DecodeError::InvalidOpcode {
+                map: self.map,
+                opcode: self.opcode,
+            });
+        }
+        if self.address_size == 2 {
+            return self.read_memory_16(modrm);
+        }
+
+        let address_size = self.address_size as u16;
+        let gpr = |n: u8| Register::gpr(address_size, n, true);
+        let mut base = None;
+        let mut index = None;
+        let mut scale = 1;
+        let mut disp_size = match modrm.mode {
+            0b01 => 1,
+            0b10 => 4,
+            _ => 0,
+        };
+
+        if modrm.rm == 0b100 {
+            let sib = Sib::from_byte(self.byte()?);
+            self.sib = Some(sib);
+            let index_reg = sib.index | self.rex_x();
+            if let Some(size) = vsib {
+                // the index is a vector register, EVEX.V' extends it to registers 16-31
+                let v2 = self.is_evex() && self.vvvv() & 0x10 != 0;
+                index = Some(Register::vector(size, index_reg | ((v2 as u8) << 4)));
+                scale = 1 << sib.scale;
+            } else if index_reg != 0b100 {
+                // index 100 without REX.X means "no index"
+                index = Some(gpr(index_reg));
+                scale = 1 << sib.scale;
+            }
+            if sib.base == 0b101 && modrm.mode == 0b00 {
+                disp_size = 4;
+            } else {
+                base = Some(gpr(sib.base | self.rex_b()));
+            }
+        } else if modrm.rm == 0b101 && modrm.mode == 0b00 {
+            disp_size = 4;
+            if self.is_64() {
+                base = Some(if self.address_size == 8 {
+                    Register::Rip
+                } else {
+                    Register::Eip
+                });
+            }
+        } else {
+            base = Some(gpr(modrm.rm | self.rex_b()));
+        }
+
+        let displacement = self.read_displacement(disp_size)?;
+        self.memory = Some(MemoryOperand {
+            segment: self.prefixes.segment,
+            base,
+            index,
+            scale,
+            displacement,
+            size: 0,
+        });
+        Ok(())
+    }
+
+    fn read_memory_16(&mut self, modrm: ModRm) -> Result<(), DecodeError> {
+        const PAIRS: [(Option<Register>, Option<Register>); 8] = [
+            (Some(Register::BX), Some(Register::SI)),
+            (Some(Register::BX), Some(Register::DI)),
+            (Some(Register::BP), Some(Register::SI)),
+            (Some(Register::BP), Some(Register::DI)),
+            (Some(Register::SI), None),
+            (Some(Register::DI), None),
+            (Some(Register::BP), None),
+            (Some(Register::BX), None),
+        ];
+
+        let (mut base, index) = PAIRS[modrm.rm as usize];
+        let disp_size = match modrm.mode {
+            0b00 if modrm.rm == 0b110 => {
+                base = None;
+                2
+            }
+            0b01 => 1,
+            0b10 => 2,
+            _ => 0,
+        };
+
+        let displacement = self.read_displacement(disp_size)?;
+        self.memory = Some(MemoryOperand {
+            segment: self.prefixes.segment,
+            base,
+            index,
+            scale: 1,
+            displacement,
+            size: 0,
+        });
+        Ok(())
+    }
+
+    fn read_displacement(&mut self, size: u8) -> Result<i64, DecodeError> {
+        if size == 0 {
+            return Ok(0);
+        }
+        let value = sign_extend(self.read(size)?, size);
+        self.displacement = Some(Displacement { value, size });
+        Ok(value)
+    }
+
+    fn read_immediate(&mut self, size: u8) -> Result<u64, DecodeError> {
+        let value = self.read(size)?;
+        let slot = if self.immediates[0].is_none() { 0 } else { 1 };
+        self.immediates[slot] = Some(Immediate { value, size });
+        Ok(value)
+    }
+
+    /// width in bytes of a general purpose operand
+    fn gpr_size(&self, sz: Sz) -> u16 {
+        sz.gpr_size(self.operand_size)
+    }
+
+    /// width in bytes of the vector register for a V/W/U/H operand
+    fn vector_register_size(&self, sz: Sz) -> u16 {
+        sz.vector_register_size(self.vector_length)
+    }
+
+    /// width in bytes of the memory location for a W operand
+    fn vector_memory_size(&self, sz: Sz) -> u16 {
+        sz.vector_memory_size(self.vector_length, self.operand_size)
+    }
+
+    fn memory_operand(&self, size: u16) -> Operand {
+        let mut memory = self.memory.expect("memory form ModR/M already decoded");
+        memory.size = if self.broadcast {
+            self.element_size()
+        } else {
+            size
+        };
+        // EVEX compresses disp8 by scaling it with the size of the memory access
+        if self.is_evex() && self.displacement.is_some_and(|d| d.size == 1) {
+            memory.displacement *= memory.size.max(1) as i64;
+        }
+        Operand::Memory(memory)
+    }
+
+    fn modrm(&self) -> ModRm {
+        self.modrm.expect("entry uses ModR/M")
+    }
+
+    /// register or memory operand from the ModR/M r/m field
+    fn rm_operand(&self, register: impl Fn(u8) -> Register, memory_size: u16) -> Operand {
+        let modrm = self.modrm();
+        if modrm.is_register() {
+            Operand::Register(register(modrm.rm | self.rex_b()))
+        } else {
+            self.memory_operand(memory_size)
+        }
+    }
+
+    /// BND0 to BND3, the encodings of BND4-7 are invalid
+    fn bound_register(&self, n: u8) -> Result<Operand, DecodeError> {
+        match n {
+            0..=3 => Ok(Operand::Register(Register::Bound(n))),
+            _ => Err(DecodeError::InvalidOpcode {
+                map: self.map,
+                opcode: self.opcode,
+            }),
+        }
+    }
+
+    fn operand(&mut self, entry: &Entry, op: Op) -> Result<Option<Operand>, DecodeError> {
+        let rex = self.rex.is_some();
+        let osz = self.operand_size;
+
+        let operand = match op {
+            Op::E(sz) | Op::M(sz) | Op::R(sz) => {
+                let size = self.gpr_size(sz);
+                if entry.has(tables::MOD_REG) {
+                    Operand::Register(Register::gpr(size, self.modrm().rm | self.rex_b(), rex))
+                } else {
+                    // memory only forms can be vector sized (movntps Mx)
+                    self.rm_operand(|n| Register::gpr(size, n, rex), self.vector_memory_size(sz))
+                }
+            }
+            Op::Er(register_sz, memory_sz) => {
+                let size = self.gpr_size(register_sz);
+                self.rm_operand(|n| Register::gpr(size, n, rex), self.gpr_size(memory_sz))
+            }
+            Op::G(sz) => {
+                let n = self.modrm().reg | self.rex_r();
+                Operand::Register(Register::gpr(self.gpr_size(sz), n, rex))
+            }
+            Op::Z(sz) => {
+                let n = (self.opcode & 0b111) | self.rex_b();
+                Operand::Register(Register::gpr(self.gpr_size(sz), n, rex))
+            }
+            Op::Acc(sz) => Operand::Register(Register::gpr(self.gpr_size(sz), 0, rex)),
+            Op::I(sz) => {
+                let (encoded, size) = match sz {
+                    Sz::B => (1, 1),
+                    Sz::W => (2, 2),
+                    Sz::Z => (if osz == 2 { 2 } else { 4 }, osz),
+                    _ => (osz, osz),
+                };
+                let raw = self.read_immediate(encoded)?;
+                let value = truncate(sign_extend(raw, encoded) as u64, size);
+                Operand::Immediate(Immediate { value, size })
+            }
+            Op::Ibs => {
+                let raw = self.read_immediate(1)?;
+                let value = truncate(sign_extend(raw, 1) as u64, osz);
+                Operand::Immediate(Immediate { value, size: osz })
+            }
+            Op::J(sz) => {
+                let size = match sz {
+                    Sz::B => 1,
+                    _ if osz == 2 => 2,
+                    _ => 4,
+                };
+                let rel = sign_extend(self.read_immediate(size)?, size);
+                // turned into an absolute target once the instruction length is known
+                Operand::Branch(rel as u64)
+            }
+            Op::O(sz) => {
+                let size = self.gpr_size(sz);
+                let address = self.read(self.address_size)?;
+                self.displacement = Some(Displacement {
+                    value: address as i64,
+                    size: self.address_size,
+                });
+                Operand::Memory(MemoryOperand {
+                    segment: self.prefixes.segment,
+                    base: None,
+                    index: None,
+                    scale: 1,
+                    displacement: address as i64,
+                    size,
+                })
+            }
+            Op::A => {
+                let offset_size = if osz == 2 { 2 } else { 4 };
+                let offset = self.read_immediate(offset_size)? as u32;
+                let selector = self.read_immediate(2)? as u16;
+                Operand::FarPointer { selector, offset }
+            }
+            Op::Cl => Operand::Register(Register::CL),
+            Op::Dx => Operand::Register(Register::DX),
+            Op::One => Operand::Immediate(Immediate { value: 1, size: 1 }),
+            Op::Seg(n) => Operand::Register(Register::Segment(n)),
+            Op::S => {
+                let n = self.modrm().reg;
+                if n > 5 {
+                    return Err(DecodeError::InvalidOpcode {
+                        map: self.map,
+                        opcode: self.opcode,
+                    });
+                }
+                Operand::Register(Register::Segment(n))
+            }
+            Op::C => Operand::Register(Register::Control(self.modrm().reg | self.rex_r())),
+            Op::D => Operand::Register(Register::Debug(self.modrm().reg | self.rex_r())),
+            Op::V(sz) => {
+                let n = self.modrm().reg | self.vector_reg_ext();
+                Operand::Register(Register::vector(self.vector_register_size(sz), n))
+            }
+            Op::W(sz) | Op::U(sz) => {
+                let modrm = self.modrm();
+                if modrm.is_register() {
+                    let n = modrm.rm | self.vector_rm_ext();
+                    Operand::Register(Register::vector(self.vector_register_size(sz), n))
+                } else {
+                    self.memory_operand(self.vector_memory_size(sz))
+                }
+            }
+            // only present in VEX / EVEX encodings
+            Op::H(_) if self.encoding == Encoding::Legacy => return Ok(None),
+            Op::H(sz) => {
+                Operand::Register(Register::vector(self.vector_register_size(sz), self.vvvv()))
+            }
+            Op::L(sz) => {
+                // register number in the upper four bits of an imm8
+                let mut n = (self.read_immediate(1)? as u8) >> 4;
+                if !self.is_64() {
+                    n &= 0b111;
+                }
+                Operand::Register(Register::vector(self.vector_register_size(sz), n))
+            }
+            Op::B(sz) => Operand::Register(Register::gpr(self.gpr_size(sz), self.vvvv() & 0xF, rex)),
+            Op::KReg => Operand::Register(Register::Opmask(self.modrm().reg)),
+            Op::KVvvv => Operand::Register(Register::Opmask(self.vvvv() & 0b111)),
+            Op::KRm(sz) => {
+                let size = self.gpr_size(sz);
+                self.rm_operand(|n| Register::Opmask(n & 0b111), size)
+            }
+            Op::BndReg => self.bound_register(self.modrm().reg | self.rex_r())?,
+            Op::BndRm(sz) => {
+                let modrm = self.modrm();
+                if modrm.is_register() {
+                    self.bound_register(modrm.rm | self.rex_b())?
+                } else {
+                    self.memory_operand(self.gpr_size(sz))
+                }
+            }
+            Op::Vsib(_) => self.memory_operand(self.element_size()),
+            Op::P => Operand::Register(Register::Mmx(self.modrm().reg)),
+            Op::Q(sz) => {
+                let modrm = self.modrm();
+                if modrm.is_register() {
+                    // REX.B does not extend mmx registers
+                    Operand::Register(Register::Mmx(modrm.rm))
+                } else {
+                    self.memory_operand(self.gpr_size(sz))
+                }
+            }
+            Op::N => Operand::Register(Register::Mmx(self.modrm().rm)),
+            Op::St0 => Operand::Register(Register::St(0)),
+            Op::Sti => Operand::Register(Register::St(self.modrm().rm)),
+            Op::Xmm0 => Operand::Register(Register::XMM0),
+        };
+
+        Ok(Some(operand))
+    }
+}
+
+/// sign extends the low `size` bytes of `value`
+fn sign_extend(value: u64, size: u8) -> i64 {
+    let shift = 64 - (size as u32 * 8);
+    ((value << shift) as i64) >> shift
+}
+
+/// keeps the low `size` bytes of `value`
+fn truncate(value: u64, size: u8) -> u64 {
+    if size >= 8 {
+        value
+    } else {
+        value & ((1u64 << (size * 8)) - 1)
+    }
+}
@@ -0,0 +1,1663 @@
+//! Instruction encoder and programmatic assembler
+//!
+//! Encoding works off the same opcode tables as the decoder, searched by mnemonic: every
+//! entry that can produce the mnemonic is tried against the operands, with each operand size,
+//! vector length and W setting it could be encoded with, and the shortest encoding wins. VEX
+//! is preferred over EVEX when both can express the instruction. Each candidate is decoded
+//! again before being accepted, so an encoding that the decoder would read as a different
+//! instruction (`xchg eax, eax` as `90` is `nop`) is never produced.
+//!
+//! `Assembler` builds on `encode` with labels: jumps, calls and rip relative memory operands
+//! can refer to a label before it is bound, and are fixed up when the program is assembled.
+//! Relative branches start out in their short form and are widened until every target is in
+//! range.
+
+use crate::decode::decode_at;
+use crate::opcodes::{Bitness, Encoding, Mnemonic, OpcodeMap, RepPrefix, RoundingControl};
+use crate::registers::Register;
+use crate::tables::{self, Form, Mod, Op, Pfx, SizeMatch, Sz};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeError {
+    /// no form of the instruction takes these operands
+    InvalidOperands(Mnemonic),
+    /// a memory or immediate operand without an explicit size matches forms of different sizes
+    AmbiguousOperandSize(Mnemonic),
+    /// base / index / scale / displacement combination that cannot be encoded
+    InvalidMemoryOperand,
+    /// a label was referenced but never bound
+    UnboundLabel(Label),
+    LabelAlreadyBound(Label),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::InvalidOperands(m) => write!(f, "invalid operands for {}", m.as_str()),
+            EncodeError::AmbiguousOperandSize(m) => write!(f, "operand size not specified for {}", m.as_str()),
+            EncodeError::InvalidMemoryOperand => write!(f, "memory operand cannot be encoded"),
+            EncodeError::UnboundLabel(label) => write!(f, "label {} is never bound", label.0),
+            EncodeError::LabelAlreadyBound(label) => write!(f, "label {} is already bound", label.0),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+/// A position in an `Assembler` program, usable before it is bound
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Label(u32);
+
+/// A memory operand: `segment:[base + index * scale + disp]`
+///
+/// `size` is the number of bytes accessed, and can be left at 0 when another operand
+/// implies it (`mov rax, [rbx]`). A `label` makes the operand refer to the label's address
+/// plus `disp`, rip relative in 64-bit mode and absolute otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mem {
+    pub segment: Option<Register>,
+    pub base: Option<Register>,
+    pub index: Option<Register>,
+    pub scale: u8,
+    pub disp: i64,
+    pub size: u16,
+    pub label: Option<Label>,
+    /// EVEX embedded broadcast, `[rax]{1to16}`. The size is that of one element
+    pub broadcast: bool,
+}
+
+impl Default for Mem {
+    fn default() -> Self {
+        Mem {
+            segment: None,
+            base: None,
+            index: None,
+            scale: 1,
+            disp: 0,
+            size: 0,
+            label: None,
+            broadcast: false,
+        }
+    }
+}
+
+impl Mem {
+    /// `[base]`
+    pub fn base(base: Register) -> Mem {
+        Mem {
+            base: Some(base),
+            ..Mem::default()
+        }
+    }
+
+    /// `[address]`, without a base or index register
+    pub fn absolute(address: i64) -> Mem {
+        Mem {
+            disp: address,
+            ..Mem::default()
+        }
+    }
+
+    /// the address of `label`
+    pub fn label(label: Label) -> Mem {
+        Mem {
+            label: Some(label),
+            ..Mem::default()
+        }
+    }
+
+    pub fn index(mut self, index: Register, scale: u8) -> Mem {
+        self.index = Some(index);
+        self.scale = scale;
+        self
+    }
+
+    pub fn disp(mut self, disp: i64) -> Mem {
+        self.disp = disp;
+        self
+    }
+
+    pub fn segment(mut self, segment: Register) -> Mem {
+        self.segment = Some(segment);
+        self
+    }
+
+    pub fn size(mut self, size: u16) -> Mem {
+        self.size = size;
+        self
+    }
+
+    pub fn byte(self) -> Mem {
+        self.size(1)
+    }
+    pub fn word(self) -> Mem {
+        self.size(2)
+    }
+    pub fn dword(self) -> Mem {
+        self.size(4)
+    }
+    pub fn qword(self) -> Mem {
+        self.size(8)
+    }
+    pub fn xmmword(self) -> Mem {
+        self.size(16)
+    }
+    pub fn ymmword(self) -> Mem {
+        self.size(32)
+    }
+    pub fn zmmword(self) -> Mem {
+        self.size(64)
+    }
+
+    /// broadcast a single `element_size` element across the vector
+    pub fn broadcast(mut self, element_size: u16) -> Mem {
+        self.broadcast = true;
+        self.size = element_size;
+        self
+    }
+}
+
+/// An operand passed to the encoder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arg {
+    Register(Register),
+    Memory(Mem),
+    /// `size` forces the encoded width of the immediate, otherwise the shortest encoding that
+    /// holds `value` is used
+    Immediate { value: i64, size: Option<u8> },
+    /// relative branch target
+    Label(Label),
+    /// relative branch to an absolute address
+    Address(u64),
+    /// ptr16:16 / ptr16:32 operand of a far jump / call
+    FarPointer { selector: u16, offset: u32 },
+}
+
+/// Immediates with an explicit encoded width
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Imm8(pub u8);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Imm16(pub u16);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Imm32(pub u32);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Imm64(pub u64);
+
+impl From<Register> for Arg {
+    fn from(register: Register) -> Self {
+        Arg::Register(register)
+    }
+}
+
+impl From<Mem> for Arg {
+    fn from(mem: Mem) -> Self {
+        Arg::Memory(mem)
+    }
+}
+
+impl From<Label> for Arg {
+    fn from(label: Label) -> Self {
+        Arg::Label(label)
+    }
+}
+
+macro_rules! immediate_from {
+    ($($ty:ty),*) => {
+        $(impl From<$ty> for Arg {
+            fn from(value: $ty) -> Self {
+                Arg::Immediate { value: value as i64, size: None }
+            }
+        })*
+    };
+}
+
+immediate_from!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+macro_rules! sized_immediate_from {
+    ($($ty:ident => $size:literal),*) => {
+        $(impl From<$ty> for Arg {
+            fn from(imm: $ty) -> Self {
+                Arg::Immediate { value: imm.0 as i64, size: Some($size) }
+            }
+        })*
+    };
+}
+
+sized_immediate_from!(Imm8 => 1, Imm16 => 2, Imm32 => 4, Imm64 => 8);
+
+/// An instruction to encode: the mnemonic and operands, in Intel order, plus the prefixes and
+/// EVEX decorations that are not operands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub mnemonic: Mnemonic,
+    pub operands: Vec<Arg>,
+    pub lock: bool,
+    pub rep: Option<RepPrefix>,
+    /// operand size for instructions whose operands do not imply one (`movs`, `stos`...)
+    pub operand_size: Option<u8>,
+    /// EVEX opmask register applied to the destination
+    pub opmask: Option<Register>,
+    /// EVEX zeroing masking
+    pub zeroing: bool,
+    /// EVEX embedded rounding, register forms only
+    pub rounding: Option<RoundingControl>,
+    /// EVEX suppress all exceptions
+    pub sae: bool,
+}
+
+impl Instruction {
+    pub fn new(mnemonic: Mnemonic, operands: &[Arg]) -> Instruction {
+        Instruction {
+            mnemonic,
+            operands: operands.to_vec(),
+            lock: false,
+            rep: None,
+            operand_size: None,
+            opmask: None,
+            zeroing: false,
+            rounding: None,
+            sae: false,
+        }
+    }
+
+    pub fn lock(mut self) -> Instruction {
+        self.lock = true;
+        self
+    }
+
+    /// rep / repe
+    pub fn rep(mut self) -> Instruction {
+        self.rep = Some(RepPrefix::Rep);
+        self
+    }
+
+    pub fn repne(mut self) -> Instruction {
+        self.rep = Some(RepPrefix::Repne);
+        self
+    }
+
+    pub fn operand_size(mut self, size: u8) -> Instruction {
+        self.operand_size = Some(size);
+        self
+    }
+
+    pub fn mask(mut self, opmask: Register) -> Instruction {
+        self.opmask = Some(opmask);
+        self
+    }
+
+    pub fn zeroing(mut self) -> Instruction {
+        self.zeroing = true;
+        self
+    }
+
+    pub fn rounding(mut self, rounding: RoundingControl) -> Instruction {
+        self.rounding = Some(rounding);
+        self.sae = true;
+        self
+    }
+
+    pub fn sae(mut self) -> Instruction {
+        self.sae = true;
+        self
+    }
+
+    /// needs an EVEX prefix whatever the operands are
+    fn evex_only(&self) -> bool {
+        self.opmask.is_some()
+            || self.zeroing
+            || self.rounding.is_some()
+            || self.sae
+            || self.operands.iter().any(|a| matches!(a, Arg::Memory(m) if m.broadcast))
+    }
+}
+
+/// Encodes a single instruction located at `ip`. Branch targets must be given as
+/// `Arg::Address`, labels only exist within an `Assembler`
+pub fn encode(bitness: Bitness, instruction: &Instruction, ip: u64) -> Result<Vec<u8>, EncodeError> {
+    let context = Context {
+        bitness,
+        ip,
+        short_branches: true,
+        labels: &|_| None,
+    };
+    context.encode(instruction)
+}
+
+/// What an instruction is being encoded against
+struct Context<'a> {
+    bitness: Bitness,
+    ip: u64,
+    /// whether rel8 branch forms may be used
+    short_branches: bool,
+    labels: &'a dyn Fn(Label) -> Option<u64>,
+}
+
+/// Settings an entry is tried with
+#[derive(Debug, Clone, Copy)]
+struct Config {
+    w: bool,
+    prefix_66: bool,
+    operand_size: u8,
+    vector_length: u16,
+}
+
+/// ModR/M r/m operand
+#[derive(Debug, Clone, Copy)]
+enum Rm {
+    Register(u8),
+    Memory(Mem),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Imm {
+    Value { value: u64, size: u8 },
+    Branch { target: u64, size: u8 },
+}
+
+/// How a form encodes the operands
+#[derive(Debug, Default)]
+struct Plan {
+    /// ModR/M.reg, with the REX.R and EVEX.R' extensions as bits 3 and 4
+    reg: u8,
+    rm: Option<Rm>,
+    vvvv: u8,
+    /// register number added to the opcode byte
+    opcode_reg: u8,
+    immediates: Vec<Imm>,
+    /// uses spl / bpl / sil / dil
+    rex_required: bool,
+    /// uses ah / ch / dh / bh
+    rex_forbidden: bool,
+    /// size the form gives a memory operand, for disp8*N and ambiguity checks
+    memory_size: u16,
+    /// size of the index register of a vsib memory operand
+    vsib: bool,
+}
+
+/// A successfully encoded candidate
+struct Candidate {
+    bytes: Vec<u8>,
+    evex: bool,
+    /// uses a 66 prefix to change the operand size
+    size_override: bool,
+    memory_size: u16,
+}
+
+impl Context<'_> {
+    fn is_64(&self) -> bool {
+        self.bitness == Bitness::Bits64
+    }
+
+    fn encode(&self, instruction: &Instruction) -> Result<Vec<u8>, EncodeError> {
+        let mnemonic = instruction.mnemonic;
+        let invalid = EncodeError::InvalidOperands(mnemonic);
+        // labels have to be resolvable up front, rather than failing every form
+        for arg in &instruction.operands {
+            let label = match arg {
+                Arg::Label(label) => Some(*label),
+                Arg::Memory(mem) => mem.label,
+                _ => None,
+            };
+            if let Some(label) = label
+                && (self.labels)(label).is_none()
+            {
+                return Err(EncodeError::UnboundLabel(label));
+            }
+        }
+
+        let mut best: Option<Candidate> = None;
+        let mut memory_sizes = (u16::MAX, false);
+        for form in tables::tables().forms(mnemonic) {
+            for config in self.configs(form, instruction) {
+                let Some(candidate) = self.try_form(form, config, instruction) else {
+                    continue;
+                };
+                if candidate.memory_size != 0 {
+                    match memory_sizes.0 {
+                        u16::MAX => memory_sizes.0 = candidate.memory_size,
+                        size if size != candidate.memory_size => memory_sizes.1 = true,
+                        _ => {}
+                    }
+                }
+                // when nothing pins the operand size (`push 0x190`) the default one is used,
+                // even where an override would be shorter
+                let better = best.as_ref().is_none_or(|b| {
+                    (candidate.evex, candidate.size_override, candidate.bytes.len())
+                        < (b.evex, b.size_override, b.bytes.len())
+                });
+                if better {
+                    best = Some(candidate);
+                }
+            }
+        }
+
+        let unsized_memory = instruction
+            .operands
+            .iter()
+            .any(|a| matches!(a, Arg::Memory(m) if m.size == 0));
+        if unsized_memory && memory_sizes.1 {
+            return Err(EncodeError::AmbiguousOperandSize(mnemonic));
+        }
+        best.map(|b| b.bytes).ok_or(invalid)
+    }
+
+    /// Operand size / W / vector length combinations worth trying for a form
+    fn configs(&self, form: &Form, instruction: &Instruction) -> Vec<Config> {
+        let entry = form.entry;
+        let mut configs = Vec::new();
+        if entry.has(tables::I64) && self.is_64() || entry.has(tables::O64) && !self.is_64() {
+            return configs;
+        }
+        if instruction.evex_only() && form.encoding != Encoding::Evex {
+            return configs;
+        }
+
+        // near branches are only encoded with the default operand size, though the decoder
+        // takes a 66 prefix on CALL and RET
+        let branch = entry.ops.iter().any(|op| matches!(op, Op::J(_))) || entry.has(tables::BND);
+        let w_options: &[bool] = match entry.w {
+            Some(w) => if w { &[true] } else { &[false] },
+            None if form.encoding == Encoding::Evex && entry.has(tables::EW1) => &[true],
+            // REX.W only exists in 64-bit mode, VEX.W everywhere
+            None if form.encoding == Encoding::Legacy && !self.is_64() => &[false],
+            None => &[false, true],
+        };
+        // a 66 override is only an option where it changes the operand size, which includes
+        // the 16-bit forms of F3 / F2 entries such as popcnt and crc32
+        let sized_mandatory = matches!(entry.prefix, Pfx::PF3 | Pfx::PF2)
+            && entry.ops.iter().any(|op| matches!(op, Op::G(Sz::V) | Op::E(Sz::V)));
+        let prefix_66_options: &[bool] =
+            if form.encoding == Encoding::Legacy && (entry.prefix == Pfx::Any || sized_mandatory) && !branch {
+                &[false, true]
+            } else {
+                &[false]
+            };
+        let vector_lengths: &[u16] = match form.encoding {
+            Encoding::Legacy => &[16],
+            Encoding::Vex => &[16, 32],
+            Encoding::Evex if instruction.rounding.is_some() || instruction.sae => &[64],
+            Encoding::Evex => &[16, 32, 64],
+        };
+
+        for &w in w_options {
+            for &prefix_66 in prefix_66_options {
+                let operand_size = entry.operand_size(self.bitness, w, prefix_66);
+                if instruction.operand_size.is_some_and(|size| size != operand_size) {
+                    continue;
+                }
+                if let SizeMatch::Operand(size) = entry.size
+                    && size != operand_size
+                {
+                    continue;
+                }
+                for &vector_length in vector_lengths {
+                    if let Some(l) = entry.l
+                        && form.encoding != Encoding::Legacy
+                        && vector_length != 16 << l
+                    {
+                        continue;
+                    }
+                    configs.push(Config {
+                        w,
+                        prefix_66,
+                        operand_size,
+                        vector_length,
+                    });
+                }
+            }
+        }
+        configs
+    }
+
+    fn try_form(&self, form: &Form, config: Config, instruction: &Instruction) -> Option<Candidate> {
+        let entry = form.entry;
+        if instruction.lock && !entry.has(tables::LOCK) {
+            return None;
+        }
+        match instruction.rep {
+            Some(RepPrefix::Rep) if !entry.has(tables::REP | tables::REPE) => return None,
+            Some(RepPrefix::Repne) if !entry.has(tables::REPE | tables::BND) => return None,
+            _ => {}
+        }
+        if instruction.lock && !matches!(instruction.operands.first(), Some(Arg::Memory(_))) {
+            return None;
+        }
+
+        let plan = self.plan(form, config, instruction)?;
+        let bytes = self.emit(form, config, instruction, &plan)?;
+
+        // the decoder has to read the bytes back as the same instruction
+        let decoded = decode_at(self.bitness, &bytes, self.ip).ok()?;
+        if decoded.mnemonic != instruction.mnemonic || decoded.length as usize != bytes.len() {
+            return None;
+        }
+
+        Some(Candidate {
+            bytes,
+            evex: form.encoding == Encoding::Evex,
+            size_override: config.prefix_66,
+            memory_size: plan.memory_size,
+        })
+    }
+
+    /// Matches the operands against the entry's operand list
+    fn plan(&self, form: &Form, config: Config, instruction: &Instruction) -> Option<Plan> {
+        let entry = form.entry;
+        let encoding = form.encoding;
+        let osz = config.operand_size;
+        let vl = config.vector_length;
+        let mut plan = Plan::default();
+        let mut args = instruction.operands.iter();
+
+        for op in entry.ops {
+            // H only takes part in VEX / EVEX encodings
+            if matches!(op, Op::H(_)) && encoding == Encoding::Legacy {
+                continue;
+            }
+            let arg = *args.next()?;
+            match *op {
+                Op::E(sz) | Op::M(sz) | Op::R(sz) => {
+                    let register_only = matches!(op, Op::R(_)) || entry.has(tables::MOD_REG);
+                    let memory_size = sz.vector_memory_size(vl, osz);
+                    plan.rm = Some(match arg {
+                        Arg::Register(r) if !matches!(op, Op::M(_)) => {
+                            Rm::Register(self.gpr(&mut plan, r, sz.gpr_size(osz))?)
+                        }
+                        Arg::Memory(m) if !register_only => {
+                            self.memory(&mut plan, m, memory_size)?
+                        }
+                        _ => return None,
+                    });
+                }
+                Op::Er(register_sz, memory_sz) => {
+                    plan.rm = Some(match arg {
+                        Arg::Register(r) => Rm::Register(self.gpr(&mut plan, r, register_sz.gpr_size(osz))?),
+                        Arg::Memory(m) => self.memory(&mut plan, m, memory_sz.gpr_size(osz))?,
+                        _ => return None,
+                    });
+                }
+                Op::G(sz) => plan.reg = self.gpr(&mut plan, register(arg)?, sz.gpr_size(osz))?,
+                Op::Z(sz) => plan.opcode_reg = self.gpr(&mut plan, register(arg)?, sz.gpr_size(osz))?,
+                Op::B(sz) => plan.vvvv = self.gpr(&mut plan, register(arg)?, sz.gpr_size(osz))?,
+                Op::Acc(sz) => {
+                    if self.gpr(&mut plan, register(arg)?, sz.gpr_size(osz))? != 0 {
+                        return None;
+                    }
+                }
+                Op::I(sz) => {
+                    let (encoded, extended) = match sz {
+                        Sz::B => (1, 1),
+                        Sz::W => (2, 2),
+                        Sz::Z if osz == 2 => (2, 2),
+                        Sz::Z => (4, osz),
+                        _ => (osz, osz),
+                    };
+                    plan.immediates.push(immediate(arg, encoded, extended)?);
+                }
+                Op::Ibs => plan.immediates.push(immediate(arg, 1, osz)?),
+                Op::One => {
+                    if arg != (Arg::Immediate { value: 1, size: None }) {
+                        return None;
+                    }
+                }
+                Op::J(sz) => {
+                    let size = match sz {
+                        Sz::B if !self.short_branches => return None,
+                        Sz::B => 1,
+                        _ if osz == 2 => 2,
+                        _ => 4,
+                    };
+                    let target = match arg {
+                        Arg::Address(address) => address,
+                        Arg::Label(label) => (self.labels)(label)?,
+                        _ => return None,
+                    };
+                    plan.immediates.push(Imm::Branch { target, size });
+                }
+                Op::O(sz) => {
+                    let Arg::Memory(m) = arg else { return None };
+                    let size = sz.gpr_size(osz);
+                    if m.base.is_some() || m.index.is_some() || m.label.is_some() || m.broadcast {
+                        return None;
+                    }
+                    if m.size != 0 && m.size != size {
+                        return None;
+                    }
+                    let address_size = self.default_address_size();
+                    if !fits(m.disp, address_size, address_size) {
+                        return None;
+                    }
+                    if let Some(segment) = m.segment {
+                        // carried through as a memory operand so the prefix is emitted
+                        plan.rm = Some(Rm::Memory(Mem::default().segment(segment)));
+                    }
+                    plan.memory_size = size;
+                    plan.immediates.push(Imm::Value {
+                        value: m.disp as u64,
+                        size: address_size,
+                    });
+                }
+                Op::A => {
+                    let Arg::FarPointer { selector, offset } = arg else { return None };
+                    let offset_size = if osz == 2 { 2 } else { 4 };
+                    if offset_size == 2 && offset > 0xFFFF {
+                        return None;
+                    }
+                    plan.immediates.push(Imm::Value {
+                        value: offset as u64,
+                        size: offset_size,
+                    });
+                    plan.immediates.push(Imm::Value {
+                        value: selector as u64,
+                        size: 2,
+                    });
+                }
+                Op::Cl => expect(arg, Register::CL)?,
+                Op::Dx => expect(arg, Register::DX)?,
+                Op::Seg(n) => expect(arg, Register::Segment(n))?,
+                Op::St0 => expect(arg, Register::St(0))?,
+                Op::Xmm0 => expect(arg, Register::Xmm(0))?,
+                Op::S => match register(arg)? {
+                    Register::Segment(n) => plan.reg = n,
+                    _ => return None,
+                },
+                Op::C => match register(arg)? {
+                    Register::Control(n) => plan.reg = self.numbered(n, 16)?,
+                    _ => return None,
+                },
+                Op::D => match register(arg)? {
+                    Register::Debug(n) => plan.reg = self.numbered(n, 16)?,
+                    _ => return None,
+                },
+                Op::V(sz) => plan.reg = self.vector(register(arg)?, sz.vector_register_size(vl), encoding)?,
+                Op::H(sz) => plan.vvvv = self.vector(register(arg)?, sz.vector_register_size(vl), encoding)?,
+                Op::W(sz) | Op::U(sz) => {
+                    plan.rm = Some(match arg {
+                        Arg::Register(r) => {
+                            Rm::Register(self.vector(r, sz.vector_register_size(vl), encoding)?)
+                        }
+                        Arg::Memory(m) if matches!(op, Op::W(_)) => {
+                            let size = if m.broadcast {
+                                element_size(config.w)
+                            } else {
+                                sz.vector_memory_size(vl, osz)
+                            };
+                            self.memory(&mut plan, m, size)?
+                        }
+                        _ => return None,
+                    });
+                }
+                Op::L(sz) => {
+                    let n = self.vector(register(arg)?, sz.vector_register_size(vl), encoding)?;
+                    plan.immediates.push(Imm::Value {
+                        value: (n as u64) << 4,
+                        size: 1,
+                    });
+                }
+                Op::P => match register(arg)? {
+                    Register::Mmx(n) => plan.reg = n,
+                    _ => return None,
+                },
+                Op::Q(sz) => {
+                    plan.rm = Some(match arg {
+                        Arg::Register(Register::Mmx(n)) => Rm::Register(n),
+                        Arg::Memory(m) => self.memory(&mut plan, m, sz.gpr_size(osz))?,
+                        _ => return None,
+                    });
+                }
+                Op::N => match register(arg)? {
+                    Register::Mmx(n) => plan.rm = Some(Rm::Register(n)),
+                    _ => return None,
+                },
+                Op::Sti => match register(arg)? {
+                    Register::St(n) => plan.rm = Some(Rm::Register(n)),
+                    _ => return None,
+                },
+                Op::KReg => plan.reg = opmask(arg)?,
+                Op::KVvvv => plan.vvvv = opmask(arg)?,
+                Op::KRm(sz) => {
+                    plan.rm = Some(match arg {
+                        Arg::Register(_) => Rm::Register(opmask(arg)?),
+                        Arg::Memory(m) => self.memory(&mut plan, m, sz.gpr_size(osz))?,
+                        _ => return None,
+                    });
+                }
+                Op::BndReg => plan.reg = bound(arg)?,
+                Op::BndRm(sz) => {
+                    plan.rm = Some(match arg {
+                        Arg::Register(_) => Rm::Register(bound(arg)?),
+                        Arg::Memory(m) => self.memory(&mut plan, m, sz.gpr_size(osz))?,
+                        _ => return None,
+                    });
+                }
+                Op::Vsib(sz) => {
+                    let Arg::Memory(m) = arg else { return None };
+                    let index = m.index?;
+                    if !index.is_vector() || index.size() != sz.vector_register_size(vl) {
+                        return None;
+                    }
+                    plan.vsib = true;
+                    plan.rm = Some(self.memory(&mut plan, m, element_size(config.w))?);
+                }
+            }
+        }
+
+        if args.next().is_some() {
+            return None;
+        }
+        Some(plan)
+    }
+
+    /// general purpose register of the given size, returning its number
+    fn gpr(&self, plan: &mut Plan, register: Register, size: u16) -> Option<u8> {
+        if !register.is_gpr() || register.size() != size {
+            return None;
+        }
+        match register {
+            Register::Gpr8High(n) => {
+                plan.rex_forbidden = true;
+                Some(n + 4)
+            }
+            Register::Gpr8(n) if (4..8).contains(&n) => {
+                plan.rex_required = true;
+                Some(n)
+            }
+            other => self.numbered(other.number(), 16),
+        }
+    }
+
+    /// vector register of the given size, returning its number
+    fn vector(&self, register: Register, size: u16, encoding: Encoding) -> Option<u8> {
+        if !register.is_vector() || register.size() != size {
+            return None;
+        }
+        let limit = match encoding {
+            Encoding::Evex => 32,
+            _ => 16,
+        };
+        self.numbered(register.number(), limit)
+    }
+
+    /// registers above 7 need a REX / VEX / EVEX extension bit, which only exists in 64-bit mode
+    fn numbered(&self, n: u8, limit: u8) -> Option<u8> {
+        let limit = if self.is_64() { limit } else { 8 };
+        (n < limit).then_some(n)
+    }
+
+    fn default_address_size(&self) -> u8 {
+        match self.bitness {
+            Bitness::Bits64 => 8,
+            Bitness::Bits32 => 4,
+            Bitness::Bits16 => 2,
+        }
+    }
+
+    /// checks a memory operand against the size the form accesses, and resolves its label
+    fn memory(&self, plan: &mut Plan, mut mem: Mem, size: u16) -> Option<Rm> {
+        if mem.size != 0 && size != 0 && mem.size != size {
+            return None;
+        }
+        // a vector index only makes sense for gathers and scatters
+        if mem.index.is_some_and(|i| i.is_vector()) && !plan.vsib {
+            return None;
+        }
+        if let Some(label) = mem.label {
+            if mem.base.is_some() || mem.index.is_some() {
+                return None;
+            }
+            let address = (self.labels)(label)?;
+            mem.disp = mem.disp.wrapping_add(address as i64);
+            if self.is_64() {
+                mem.base = Some(Register::Rip);
+            }
+        }
+        plan.memory_size = size;
+        Some(Rm::Memory(mem))
+    }
+
+    /// address size a memory operand is encoded with, from its registers
+    fn address_size(&self, mem: &Mem) -> Option<u8> {
+        let mut size = None;
+        let registers = mem
+            .base
+            .iter()
+            .chain(mem.index.iter().filter(|i| !i.is_vector()));
+        for register in registers {
+            let register_size = match register {
+                Register::Rip | Register::Gpr64(_) => 8,
+                Register::Eip | Register::Gpr32(_) => 4,
+                Register::Gpr16(_) => 2,
+                _ => return None,
+            };
+            if size.is_some_and(|s| s != register_size) {
+                return None;
+            }
+            size = Some(register_size);
+        }
+        let size = size.unwrap_or(self.default_address_size());
+        let valid = match self.bitness {
+            Bitness::Bits64 => size != 2,
+            Bitness::Bits32 | Bitness::Bits16 => size != 8,
+        };
+        valid.then_some(size)
+    }
+
+    /// Lays out the bytes of one candidate
+    fn emit(&self, form: &Form, config: Config, instruction: &Instruction, plan: &Plan) -> Option<Vec<u8>> {
+        let entry = form.entry;
+        let encoding = form.encoding;
+        let memory = match plan.rm {
+            Some(Rm::Memory(mem)) => Some(mem),
+            _ => None,
+        };
+
+        let address_size = match (memory, entry.size) {
+            (Some(mem), _) if mem.base.is_some() || mem.index.is_some() || mem.label.is_some() => {
+                self.address_size(&mem)?
+            }
+            (_, SizeMatch::Address(size)) => size,
+            _ => self.default_address_size(),
+        };
+        if let SizeMatch::Address(size) = entry.size
+            && size != address_size
+        {
+            return None;
+        }
+        if plan.rex_required && plan.rex_forbidden {
+            return None;
+        }
+
+        // ModR/M, SIB and displacement
+        let mut modrm_bytes = Vec::new();
+        let mut rip_relative = None;
+        let (mut rex_x, mut rex_b, mut evex_v2) = (false, false, false);
+        let reg_field = entry.digit.unwrap_or(plan.reg & 0b111);
+        if let Some(byte) = entry.modrm {
+            modrm_bytes.push(byte);
+        } else if entry.uses_modrm() {
+            match (plan.rm, entry.required_mod()) {
+                (Some(Rm::Register(n)), Mod::Any | Mod::Reg) => {
+                    modrm_bytes.push(0b1100_0000 | (reg_field << 3) | (n & 0b111));
+                    rex_b = n & 0b1000 != 0;
+                    // EVEX.X extends the r/m vector register to 16-31
+                    rex_x = n & 0b1_0000 != 0;
+                }
+                (Some(Rm::Memory(mem)), Mod::Any | Mod::Mem) => {
+                    let n = if encoding == Encoding::Evex {
+                        plan.memory_size.max(1) as i64
+                    } else {
+                        1
+                    };
+                    let encoded = self.modrm_memory(&mem, reg_field, address_size, n)?;
+                    modrm_bytes = encoded.bytes;
+                    rip_relative = encoded.rip_relative;
+                    rex_x = encoded.index & 0b1000 != 0;
+                    rex_b = encoded.base & 0b1000 != 0;
+                    evex_v2 = encoded.index & 0b1_0000 != 0;
+                }
+                // lfence and friends: a register form ModR/M with only the digit in use
+                (None, Mod::Reg) => modrm_bytes.push(0b1100_0000 | (reg_field << 3)),
+                // register / memory mismatch with the mod the entry requires
+                _ => return None,
+            }
+        }
+        if plan.opcode_reg & 0b1000 != 0 {
+            rex_b = true;
+        }
+        if entry.has(tables::NO_REXB) && rex_b {
+            return None;
+        }
+        let rex_r = plan.reg & 0b1000 != 0;
+        let evex_r2 = plan.reg & 0b1_0000 != 0;
+
+        let mut bytes = Vec::with_capacity(15);
+        if instruction.lock {
+            bytes.push(0xF0);
+        }
+        match instruction.rep {
+            Some(RepPrefix::Rep) => bytes.push(0xF3),
+            Some(RepPrefix::Repne) => bytes.push(0xF2),
+            None => {}
+        }
+        if let Some(segment) = memory.and_then(|m| m.segment) {
+            bytes.push(match segment {
+                Register::Segment(0) => 0x26,
+                Register::Segment(1) => 0x2E,
+                Register::Segment(2) => 0x36,
+                Register::Segment(3) => 0x3E,
+                Register::Segment(4) => 0x64,
+                Register::Segment(5) => 0x65,
+                _ => return None,
+            });
+        }
+        if address_size != self.default_address_size() {
+            bytes.push(0x67);
+        }
+
+        let map_select = match form.map {
+            OpcodeMap::Primary => 0,
+            OpcodeMap::Map0F => 1,
+            OpcodeMap::Map0F38 => 2,
+            OpcodeMap::Map0F3A => 3,
+        };
+        let pp = match entry.prefix {
+            Pfx::P66 => 1,
+            Pfx::PF3 => 2,
+            Pfx::PF2 => 3,
+            Pfx::Any | Pfx::None => 0,
+        };
+
+        match encoding {
+            Encoding::Legacy => {
+                if config.prefix_66 || entry.prefix == Pfx::P66 {
+                    bytes.push(0x66);
+                }
+                match entry.prefix {
+                    Pfx::PF3 => bytes.push(0xF3),
+                    Pfx::PF2 => bytes.push(0xF2),
+                    _ => {}
+                }
+                // the rep prefix would turn a Pfx::None entry into a different instruction
+                if entry.prefix == Pfx::None && instruction.rep.is_some() {
+                    return None;
+                }
+                // vector registers 16-31 need EVEX
+                if rex_x && matches!(plan.rm, Some(Rm::Register(_))) || evex_r2 || evex_v2 {
+                    return None;
+                }
+                let rex = (config.w as u8) << 3 | (rex_r as u8) << 2 | (rex_x as u8) << 1 | rex_b as u8;
+                if rex != 0 || plan.rex_required {
+                    if plan.rex_forbidden || !self.is_64() {
+                        return None;
+                    }
+                    bytes.push(0x40 | rex);
+                }
+                match form.map {
+                    OpcodeMap::Primary => {}
+                    OpcodeMap::Map0F => bytes.push(0x0F),
+                    OpcodeMap::Map0F38 => bytes.extend([0x0F, 0x38]),
+                    OpcodeMap::Map0F3A => bytes.extend([0x0F, 0x3A]),
+                }
+            }
+            Encoding::Vex => {
+                if plan.rex_forbidden && plan.rex_required || evex_r2 || evex_v2 || plan.vvvv > 15 {
+                    return None;
+                }
+                if rex_x && matches!(plan.rm, Some(Rm::Register(_))) {
+                    return None;
+                }
+                let l = (config.vector_length == 32) as u8;
+                let vvvv = (!plan.vvvv & 0xF) << 3;
+                if !rex_x && !rex_b && !config.w && form.map == OpcodeMap::Map0F {
+                    bytes.push(0xC5);
+                    bytes.push(((!rex_r as u8) << 7) | vvvv | (l << 2) | pp);
+                } else {
+                    bytes.push(0xC4);
+                    bytes.push(((!rex_r as u8) << 7) | ((!rex_x as u8) << 6) | ((!rex_b as u8) << 5) | map_select);
+                    bytes.push(((config.w as u8) << 7) | vvvv | (l << 2) | pp);
+                }
+            }
+            Encoding::Evex => {
+                let register_form = matches!(plan.rm, Some(Rm::Register(_)));
+                let broadcast = memory.is_some_and(|m| m.broadcast);
+                if (instruction.rounding.is_some() || instruction.sae) && !register_form {
+                    return None;
+                }
+                if instruction.sae && instruction.rounding.is_none() && !entry.has(tables::SAE) {
+                    return None;
+                }
+                if instruction.rounding.is_some() && entry.has(tables::SAE) {
+                    return None;
+                }
+                let aaa = match instruction.opmask {
+                    Some(Register::Opmask(n)) if n != 0 => n,
+                    Some(_) => return None,
+                    None if instruction.zeroing => return None,
+                    None => 0,
+                };
+                // V' extends vvvv, or the vsib index when there is one
+                let v2 = evex_v2 || plan.vvvv & 0b1_0000 != 0;
+                let ll = match instruction.rounding {
+                    Some(rounding) => rounding as u8,
+                    None => match config.vector_length {
+                        64 => 2,
+                        32 => 1,
+                        _ => 0,
+                    },
+                };
+                let b = broadcast || instruction.sae;
+                bytes.push(0x62);
+                bytes.push(
+                    ((!rex_r as u8) << 7)
+                        | ((!rex_x as u8) << 6)
+                        | ((!rex_b as u8) << 5)
+                        | ((!evex_r2 as u8) << 4)
+                        | map_select,
+                );
+                bytes.push(((config.w as u8) << 7) | ((!plan.vvvv & 0xF) << 3) | 0b100 | pp);
+                bytes.push(
+                    ((instruction.zeroing as u8) << 7)
+                        | (ll << 5)
+                        | ((b as u8) << 4)
+                        | ((!v2 as u8) << 3)
+                        | aaa,
+                );
+            }
+        }
+
+        bytes.push(entry.opcode + (plan.opcode_reg & 0b111));
+        let modrm_start = bytes.len();
+        bytes.extend(&modrm_bytes);
+
+        let mut branch = None;
+        for imm in &plan.immediates {
+            match *imm {
+                Imm::Value { value, size } => bytes.extend(&value.to_le_bytes()[..size as usize]),
+                Imm::Branch { target, size } => {
+                    branch = Some((bytes.len(), target, size));
+                    bytes.extend(&[0u8; 4][..size as usize]);
+                }
+            }
+        }
+        if bytes.len() > crate::decode::MAX_INSTRUCTION_LENGTH {
+            return None;
+        }
+
+        // now that the length is known, relative displacements can be filled in
+        let next_ip = self.ip.wrapping_add(bytes.len() as u64);
+        if let Some((pos, target)) = rip_relative {
+            let disp = (target as u64).wrapping_sub(next_ip) as i64;
+            if !fits(disp, 4, 8) || sign_extend(truncate(disp as u64, 4), 4) != disp {
+                return None;
+            }
+            let pos = modrm_start + pos;
+            bytes[pos..pos + 4].copy_from_slice(&(disp as i32).to_le_bytes());
+        }
+        if let Some((pos, target, size)) = branch {
+            let rel = target.wrapping_sub(next_ip) as i64;
+            let in_range = match size {
+                1 => i8::try_from(rel).is_ok(),
+                // 16-bit branches wrap ip, any target in the segment is reachable
+                2 => true,
+                _ => !self.is_64() || i32::try_from(rel).is_ok(),
+            };
+            if !in_range {
+                return None;
+            }
+            bytes[pos..pos + size as usize].copy_from_slice(&rel.to_le_bytes()[..size as usize]);
+        }
+        Some(bytes)
+    }
+
+    /// ModR/M, SIB and displacement bytes for a memory operand. `n` is the EVEX disp8 scale
+    fn modrm_memory(&self, mem: &Mem, reg: u8, address_size: u8, n: i64) -> Option<EncodedMemory> {
+        if address_size == 2 {
+            return modrm_memory_16(mem, reg);
+        }
+        let number = |r: Register| match r {
+            Register::Rip | Register::Eip => None,
+            other => Some(other.number()),
+        };
+        let scale_bits = match mem.scale {
+            1 => 0,
+            2 => 1,
+            4 => 2,
+            8 => 3,
+            _ => return None,
+        };
+        let index = mem.index.map(|i| i.number());
+        let base = mem.base;
+        let mut out = EncodedMemory {
+            bytes: Vec::new(),
+            rip_relative: None,
+            index: index.unwrap_or(0),
+            base: 0,
+        };
+
+        // rip relative, the displacement is relative to the end of the instruction
+        if let Some(Register::Rip | Register::Eip) = base {
+            if index.is_some() {
+                return None;
+            }
+            out.bytes.push((reg << 3) | 0b101);
+            if mem.label.is_some() {
+                out.rip_relative = Some((1, mem.disp));
+                out.bytes.extend([0; 4]);
+            } else {
+                if !fits_signed(mem.disp, 4) {
+                    return None;
+                }
+                out.bytes.extend((mem.disp as i32).to_le_bytes());
+            }
+            return Some(out);
+        }
+
+        if index == Some(4) && !mem.index.is_some_and(|i| i.is_vector()) {
+            // rsp cannot be an index
+            return None;
+        }
+        let disp32 = |disp: i64| -> Option<[u8; 4]> {
+            let ok = if address_size == 8 { fits_signed(disp, 4) } else { fits(disp, 4, 4) };
+            ok.then(|| (disp as u32).to_le_bytes())
+        };
+
+        let Some(base) = base else {
+            let disp = disp32(mem.disp)?;
+            if index.is_none() && !self.is_64() {
+                // mod 00 r/m 101 is a plain disp32 outside of 64-bit mode
+                out.bytes.push((reg << 3) | 0b101);
+            } else {
+                // 64-bit mode needs a SIB with no base, as mod 00 r/m 101 is rip relative
+                out.bytes.push((reg << 3) | 0b100);
+                out.bytes.push((scale_bits << 6) | ((index.unwrap_or(4) & 0b111) << 3) | 0b101);
+            }
+            out.bytes.extend(disp);
+            return Some(out);
+        };
+
+        let base = number(base)?;
+        out.base = base;
+        let disp8 = if mem.disp % n == 0 { i8::try_from(mem.disp / n).ok() } else { None };
+        let (mode, disp_bytes) = if mem.disp == 0 && base & 0b111 != 0b101 {
+            (0b00, vec![])
+        } else if let Some(d) = disp8 {
+            (0b01, vec![d as u8])
+        } else {
+            (0b10, disp32(mem.disp)?.to_vec())
+        };
+
+        if index.is_some() || base & 0b111 == 0b100 {
+            out.bytes.push((mode << 6) | (reg << 3) | 0b100);
+            out.bytes.push((scale_bits << 6) | ((index.unwrap_or(4) & 0b111) << 3) | (base & 0b111));
+        } else {
+            out.bytes.push((mode << 6) | (reg << 3) | (base & 0b111));
+        }
+        out.bytes.extend(disp_bytes);
+        Some(out)
+    }
+}
+
+struct EncodedMemory {
+    bytes: Vec<u8>,
+    /// offset of a rip relative disp32 within `bytes`, and its absolute target
+    rip_relative: Option<(usize, i64)>,
+    /// index and base register numbers, for the REX / VEX / EVEX extension bits
+    index: u8,
+    base: u8,
+}
+
+/// 16-bit addressing only has fixed base / index combinations
+fn modrm_memory_16(mem: &Mem, reg: u8) -> Option<EncodedMemory> {
+    if mem.scale != 1 && mem.index.is_some() {
+        return None;
+    }
+    let mut registers = [mem.base, mem.index];
+    registers.sort_by_key(|r| r.map(|r| r.number()));
+    let rm = match registers {
+        [None, None] => None,
+        [Some(Register::BX), Some(Register::SI)] | [Some(Register::SI), Some(Register::BX)] => Some(0),
+        [Some(Register::BX), Some(Register::DI)] | [Some(Register::DI), Some(Register::BX)] => Some(1),
+        [Some(Register::BP), Some(Register::SI)] | [Some(Register::SI), Some(Register::BP)] => Some(2),
+        [Some(Register::BP), Some(Register::DI)] | [Some(Register::DI), Some(Register::BP)] => Some(3),
+        [None, Some(Register::SI)] => Some(4),
+        [None, Some(Register::DI)] => Some(5),
+        [None, Some(Register::BP)] => Some(6),
+        [None, Some(Register::BX)] => Some(7),
+        _ => return None,
+    };
+    if !fits(mem.disp, 2, 2) {
+        return None;
+    }
+    let mut bytes = Vec::new();
+    match rm {
+        None => {
+            bytes.push((reg << 3) | 0b110);
+            bytes.extend((mem.disp as u16).to_le_bytes());
+        }
+        Some(rm) if mem.disp == 0 && rm != 6 => bytes.push((reg << 3) | rm),
+        Some(rm) if i8::try_from(mem.disp).is_ok() => {
+            bytes.push(0b0100_0000 | (reg << 3) | rm);
+            bytes.push(mem.disp as u8);
+        }
+        Some(rm) => {
+            bytes.push(0b1000_0000 | (reg << 3) | rm);
+            bytes.extend((mem.disp as u16).to_le_bytes());
+        }
+    }
+    Some(EncodedMemory {
+        bytes,
+        rip_relative: None,
+        index: 0,
+        base: 0,
+    })
+}
+
+fn register(arg: Arg) -> Option<Register> {
+    match arg {
+        Arg::Register(r) => Some(r),
+        _ => None,
+    }
+}
+
+fn expect(arg: Arg, register: Register) -> Option<()> {
+    (arg == Arg::Register(register)).then_some(())
+}
+
+fn opmask(arg: Arg) -> Option<u8> {
+    match arg {
+        Arg::Register(Register::Opmask(n)) => Some(n),
+        _ => None,
+    }
+}
+
+fn bound(arg: Arg) -> Option<u8> {
+    match arg {
+        Arg::Register(Register::Bound(n)) if n < 4 => Some(n),
+        _ => None,
+    }
+}
+
+/// size in bytes of a broadcast element
+fn element_size(w: bool) -> u16 {
+    if w { 8 } else { 4 }
+}
+
+/// An immediate encoded in `encoded` bytes and extended to `extended` bytes by the processor
+fn immediate(arg: Arg, encoded: u8, extended: u8) -> Option<Imm> {
+    let Arg::Immediate { value, size } = arg else {
+        return None;
+    };
+    let representable = match size {
+        Some(size) => size == encoded,
+        None => {
+            let truncated = truncate(value as u64, extended);
+            fits(value, extended, extended)
+                && truncate(sign_extend(truncated, encoded) as u64, extended) == truncated
+        }
+    };
+    representable.then_some(Imm::Value {
+        value: truncate(value as u64, encoded),
+        size: encoded,
+    })
+}
+
+/// whether `value` can be held in `size` bytes, as either a signed or an unsigned number, or
+/// as a signed number extended from `encoded` bytes
+fn fits(value: i64, encoded: u8, size: u8) -> bool {
+    if size >= 8 {
+        return encoded >= 8 || fits_signed(value, encoded);
+    }
+    let bits = size as u32 * 8;
+    value >= -(1i64 << (bits - 1)) && value < (1i64 << bits)
+}
+
+fn fits_signed(value: i64, size: u8) -> bool {
+    size >= 8 || sign_extend(truncate(value as u64, size), size) == value
+}
+
+/// sign extends the low `size` bytes of `value`
+fn sign_extend(value: u64, size: u8) -> i64 {
+    let shift = 64 - (size as u32 * 8);
+    ((value << shift) as i64) >> shift
+}
+
+/// keeps the low `size` bytes of `value`
+fn truncate(value: u64, size: u8) -> u64 {
+    if size >= 8 {
+        value
+    } else {
+        value & ((1u64 << (size * 8)) - 1)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Instruction(Instruction),
+    Data(Vec<u8>),
+}
+
+/// Builds a program an instruction at a time
+///
+/// ```ignore
+/// let mut asm = Assembler::new(Bitness::Bits64);
+/// let done = asm.new_label();
+/// asm.mov(Register::RAX, 10)?;
+/// let top = asm.here();
+/// asm.dec(Register::RAX)?;
+/// asm.jz(done)?;
+/// asm.jmp(top)?;
+/// asm.bind(done)?;
+/// asm.hlt()?;
+/// let code = asm.assemble(0x1000)?;
+/// ```
+///
+/// Instructions are checked when they are added, so an error points at the call that caused
+/// it; branch displacements are only worked out by `assemble`, once every label is bound.
+#[derive(Debug, Clone)]
+pub struct Assembler {
+    bitness: Bitness,
+    items: Vec<Item>,
+    /// item index each label is bound before
+    labels: Vec<Option<usize>>,
+    /// label addresses from the last `assemble`
+    addresses: Vec<u64>,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Assembler::new(Bitness::Bits64)
+    }
+}
+
+impl Assembler {
+    pub fn new(bitness: Bitness) -> Assembler {
+        Assembler {
+            bitness,
+            items: Vec::new(),
+            labels: Vec::new(),
+            addresses: Vec::new(),
+        }
+    }
+
+    pub fn bitness(&self) -> Bitness {
+        self.bitness
+    }
+
+    /// A label that is not yet bound to a position
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() as u32 - 1)
+    }
+
+    /// Binds `label` to the current position
+    pub fn bind(&mut self, label: Label) -> Result<(), EncodeError> {
+        let slot = &mut self.labels[label.0 as usize];
+        if slot.is_some() {
+            return Err(EncodeError::LabelAlreadyBound(label));
+        }
+        *slot = Some(self.items.len());
+        Ok(())
+    }
+
+    /// A new label bound to the current position
+    pub fn here(&mut self) -> Label {
+        let label = self.new_label();
+        self.labels[label.0 as usize] = Some(self.items.len());
+        label
+    }
+
+    /// Address `label` was given by the last `assemble`
+    pub fn label_address(&self, label: Label) -> Option<u64> {
+        self.addresses.get(label.0 as usize).copied()
+    }
+
+    /// Adds an instruction, checking that it can be encoded
+    pub fn emit(&mut self, instruction: Instruction) -> Result<(), EncodeError> {
+        // every label and branch target is taken to be at the instruction itself, which is
+        // enough to find out whether any form accepts the operands
+        let mut check = instruction.clone();
+        for arg in &mut check.operands {
+            if let Arg::Address(_) = arg {
+                *arg = Arg::Label(Label(0));
+            }
+        }
+        let context = Context {
+            bitness: self.bitness,
+            ip: 0,
+            short_branches: true,
+            labels: &|_| Some(0),
+        };
+        context.encode(&check)?;
+        self.items.push(Item::Instruction(instruction));
+        Ok(())
+    }
+
+    /// Adds an instruction from its mnemonic and operands
+    pub fn instruction(&mut self, mnemonic: Mnemonic, operands: &[Arg]) -> Result<(), EncodeError> {
+        self.emit(Instruction::new(mnemonic, operands))
+    }
+
+    /// raw bytes
+    pub fn db(&mut self, bytes: &[u8]) {
+        self.items.push(Item::Data(bytes.to_vec()));
+    }
+
+    pub fn dw(&mut self, words: &[u16]) {
+        self.items.push(Item::Data(words.iter().flat_map(|w| w.to_le_bytes()).collect()));
+    }
+
+    pub fn dd(&mut self, dwords: &[u32]) {
+        self.items.push(Item::Data(dwords.iter().flat_map(|d| d.to_le_bytes()).collect()));
+    }
+
+    pub fn dq(&mut self, qwords: &[u64]) {
+        self.items.push(Item::Data(qwords.iter().flat_map(|q| q.to_le_bytes()).collect()));
+    }
+
+    /// Encodes the program as if loaded at `base`
+    pub fn assemble(&mut self, base: u64) -> Result<Vec<u8>, EncodeError> {
+        for (i, slot) in self.labels.iter().enumerate() {
+            if slot.is_none() {
+                return Err(EncodeError::UnboundLabel(Label(i as u32)));
+            }
+        }
+
+        // item addresses, starting from every branch being short. A branch that does not
+        // reach is widened for good, so lengths only ever grow and this settles
+        let mut long = vec![false; self.items.len()];
+        let mut addresses = vec![base; self.items.len() + 1];
+        loop {
+            let mut changed = false;
+            let mut output = Vec::new();
+            let mut next = vec![base; self.items.len() + 1];
+            for (i, item) in self.items.iter().enumerate() {
+                let ip = base.wrapping_add(output.len() as u64);
+                next[i] = ip;
+                match item {
+                    Item::Data(bytes) => output.extend(bytes),
+                    Item::Instruction(instruction) => {
+                        let bytes = self.encode_item(instruction, ip, &addresses, !long[i])?;
+                        let previous = addresses[i + 1].wrapping_sub(addresses[i]);
+                        if !long[i] && (bytes.len() as u64) > previous && previous != 0 {
+                            long[i] = true;
+                        }
+                        output.extend(bytes);
+                    }
+                }
+            }
+            next[self.items.len()] = base.wrapping_add(output.len() as u64);
+            if next != addresses {
+                changed = true;
+            }
+            addresses = next;
+            if !changed {
+                self.addresses = self
+                    .labels
+                    .iter()
+                    .map(|slot| addresses[slot.expect("labels checked above")])
+                    .collect();
+                return Ok(output);
+            }
+        }
+    }
+
+    fn encode_item(
+        &self,
+        instruction: &Instruction,
+        ip: u64,
+        addresses: &[u64],
+        short_branches: bool,
+    ) -> Result<Vec<u8>, EncodeError> {
+        let labels = |label: Label| {
+            self.labels
+                .get(label.0 as usize)
+                .copied()
+                .flatten()
+                .map(|item| addresses[item])
+        };
+        let context = Context {
+            bitness: self.bitness,
+            ip,
+            short_branches,
+            labels: &labels,
+        };
+        context.encode(instruction)
+    }
+}
+
+/// Typed helpers for common instructions, anything else goes through `Assembler::instruction`
+macro_rules! instructions {
+    ($($name:ident => $mnemonic:ident($($arg:ident),*);)*) => {
+        impl Assembler {
+            $(
+                pub fn $name(&mut self, $($arg: impl Into<Arg>),*) -> Result<(), EncodeError> {
+                    self.instruction(Mnemonic::$mnemonic, &[$($arg.into()),*])
+                }
+            )*
+        }
+    };
+}
+
+instructions! {
+    nop => Nop();
+    hlt => Hlt();
+    ret => Ret();
+    leave => Leave();
+    int3 => Int3();
+    int => Int(vector);
+    syscall => Syscall();
+    cpuid => Cpuid();
+    ud2 => Ud2();
+    cbw => Cbw();
+    cwde => Cwde();
+    cdqe => Cdqe();
+    cwd => Cwd();
+    cdq => Cdq();
+    cqo => Cqo();
+    clc => Clc();
+    stc => Stc();
+    cmc => Cmc();
+    cld => Cld();
+    std => Std();
+    pushfq => Pushfq();
+    popfq => Popfq();
+
+    push => Push(src);
+    pop => Pop(dst);
+    inc => Inc(dst);
+    dec => Dec(dst);
+    neg => Neg(dst);
+    not => Not(dst);
+    mul => Mul(src);
+    div => Div(src);
+    idiv => Idiv(src);
+    bswap => Bswap(dst);
+    call => Call(target);
+    jmp => Jmp(target);
+    jo => Jo(target);
+    jno => Jno(target);
+    jb => Jb(target);
+    jae => Jae(target);
+    je => Je(target);
+    jz => Je(target);
+    jne => Jne(target);
+    jnz => Jne(target);
+    jbe => Jbe(target);
+    ja => Ja(target);
+    js => Js(target);
+    jns => Jns(target);
+    jp => Jp(target);
+    jnp => Jnp(target);
+    jl => Jl(target);
+    jge => Jge(target);
+    jle => Jle(target);
+    jg => Jg(target);
+    sete => Sete(dst);
+    setne => Setne(dst);
+    setb => Setb(dst);
+    setae => Setae(dst);
+    setl => Setl(dst);
+    setg => Setg(dst);
+
+    mov => Mov(dst, src);
+    movzx => Movzx(dst, src);
+    movsx => Movsx(dst, src);
+    movsxd => Movsxd(dst, src);
+    lea => Lea(dst, src);
+    add => Add(dst, src);
+    or => Or(dst, src);
+    adc => Adc(dst, src);
+    sbb => Sbb(dst, src);
+    and => And(dst, src);
+    sub => Sub(dst, src);
+    xor => Xor(dst, src);
+    cmp => Cmp(dst, src);
+    test => Test(dst, src);
+    xchg => Xchg(dst, src);
+    imul => Imul(dst, src);
+    shl => Shl(dst, count);
+    shr => Shr(dst, count);
+    sar => Sar(dst, count);
+    rol => Rol(dst, count);
+    ror => Ror(dst, count);
+    rcl => Rcl(dst, count);
+    rcr => Rcr(dst, count);
+    bt => Bt(dst, bit);
+    bts => Bts(dst, bit);
+    btr => Btr(dst, bit);
+    btc => Btc(dst, bit);
+    bsf => Bsf(dst, src);
+    bsr => Bsr(dst, src);
+    popcnt => Popcnt(dst, src);
+    lzcnt => Lzcnt(dst, src);
+    tzcnt => Tzcnt(dst, src);
+    xadd => Xadd(dst, src);
+    cmpxchg => Cmpxchg(dst, src);
+    cmove => Cmove(dst, src);
+    cmovne => Cmovne(dst, src);
+    cmovl => Cmovl(dst, src);
+    cmovg => Cmovg(dst, src);
+    cmovb => Cmovb(dst, src);
+    cmova => Cmova(dst, src);
+
+    imul3 => Imul(dst, src, imm);
+    shld => Shld(dst, src, count);
+    shrd => Shrd(dst, src, count);
+    andn => Andn(dst, src1, src2);
+    shlx => Shlx(dst, src, count);
+    shrx => Shrx(dst, src, count);
+    sarx => Sarx(dst, src, count);
+    rorx => Rorx(dst, src, count);
+    mulx => Mulx(high, low, src);
+
+    movd => Movd(dst, src);
+    movq => Movq(dst, src);
+    movaps => Movaps(dst, src);
+    movups => Movups(dst, src);
+    movdqa => Movdqa(dst, src);
+    movdqu => Movdqu(dst, src);
+    addps => Addps(dst, src);
+    addpd => Addpd(dst, src);
+    addss => Addss(dst, src);
+    addsd => Addsd(dst, src);
+    mulps => Mulps(dst, src);
+    mulsd => Mulsd(dst, src);
+    xorps => Xorps(dst, src);
+    pxor => Pxor(dst, src);
+    paddd => Paddd(dst, src);
+    paddq => Paddq(dst, src);
+
+    vmovaps => Vmovaps(dst, src);
+    vmovups => Vmovups(dst, src);
+    vmovdqu => Vmovdqu(dst, src);
+    vaddps => Vaddps(dst, src1, src2);
+    vaddpd => Vaddpd(dst, src1, src2);
+    vmulps => Vmulps(dst, src1, src2);
+    vxorps => Vxorps(dst, src1, src2);
+    vpxor => Vpxor(dst, src1, src2);
+    vpaddd => Vpaddd(dst, src1, src2);
+    vfmadd231ps => Vfmadd231ps(dst, src1, src2);
+    vzeroupper => Vzeroupper();
+}
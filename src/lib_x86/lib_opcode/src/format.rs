@@ -0,0 +1,727 @@
+//! Instruction formatters
+//!
+//! `Formatter` renders decoded instructions as text, in one of four syntaxes:
+//!
+//! ```text
+//! Nasm    mov qword [rax+rcx*8+0x10], rbx
+//! Masm    mov qword ptr [rax+rcx*8+10h], rbx
+//! Att     mov %rbx, 0x10(%rax,%rcx,8)
+//! Go      MOVQ BX, 0x10(AX)(CX*8)
+//! ```
+//!
+//! NASM output is the dialect `assemble` reads, so a formatted instruction assembles back to
+//! an equivalent one. Branch targets and rip relative addresses are printed as absolute
+//! addresses, or as symbols when a resolver is given.
+
+use crate::opcodes::{MemoryOperand, Mnemonic, Operand, RepPrefix, RoundingControl, X86Opcode};
+use crate::registers::Register;
+use crate::tables;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Syntax {
+    /// Intel syntax as NASM writes it
+    #[default]
+    Nasm,
+    /// Intel syntax as MASM writes it, with `ptr` sizes and segment overrides outside the brackets
+    Masm,
+    /// AT&T syntax as GNU as writes it: sources first, `%` registers and `$` immediates
+    Att,
+    /// Go (Plan 9) assembler syntax: sources first, sized mnemonics, `off(base)(index*scale)`
+    Go,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HexStyle {
+    /// `0x1f`
+    Prefix,
+    /// `1fh`, with a leading zero when the first digit is a letter: `0ffh`
+    Suffix,
+}
+
+/// Renders decoded instructions as text
+///
+/// ```ignore
+/// let symbols = |address| (address == 0x1000).then(|| "main".to_string());
+/// let formatter = Formatter::new(Syntax::Att).symbols(&symbols);
+/// println!("{}", formatter.format(&instruction)); // call main
+/// ```
+#[derive(Clone, Copy)]
+pub struct Formatter<'a> {
+    syntax: Syntax,
+    uppercase: bool,
+    hex: HexStyle,
+    symbols: Option<&'a dyn Fn(u64) -> Option<String>>,
+}
+
+impl Default for Formatter<'_> {
+    fn default() -> Self {
+        Formatter::new(Syntax::Nasm)
+    }
+}
+
+impl fmt::Debug for Formatter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Formatter")
+            .field("syntax", &self.syntax)
+            .field("uppercase", &self.uppercase)
+            .field("hex", &self.hex)
+            .field("symbols", &self.symbols.is_some())
+            .finish()
+    }
+}
+
+impl<'a> Formatter<'a> {
+    /// A formatter with the usual conventions of `syntax`: MASM numbers are written `10h`,
+    /// everything else `0x10`, and Go is uppercase
+    pub fn new(syntax: Syntax) -> Formatter<'a> {
+        Formatter {
+            syntax,
+            uppercase: syntax == Syntax::Go,
+            hex: match syntax {
+                Syntax::Masm => HexStyle::Suffix,
+                _ => HexStyle::Prefix,
+            },
+            symbols: None,
+        }
+    }
+
+    /// Uppercase mnemonics, registers, keywords and hex digits. Symbols are left as they are
+    pub fn uppercase(mut self, uppercase: bool) -> Formatter<'a> {
+        self.uppercase = uppercase;
+        self
+    }
+
+    pub fn hex_style(mut self, hex: HexStyle) -> Formatter<'a> {
+        self.hex = hex;
+        self
+    }
+
+    /// Names addresses for branch targets and rip relative operands. Addresses the resolver
+    /// returns `None` for are printed as numbers
+    pub fn symbols(mut self, resolver: &'a dyn Fn(u64) -> Option<String>) -> Formatter<'a> {
+        self.symbols = Some(resolver);
+        self
+    }
+
+    pub fn syntax(&self) -> Syntax {
+        self.syntax
+    }
+
+    pub fn format(&self, instruction: &X86Opcode) -> String {
+        let mut text = self.prefixes(instruction);
+        text.push_str(&self.mnemonic(instruction));
+        let operands = match self.syntax {
+            Syntax::Nasm | Syntax::Masm => self.intel_operands(instruction),
+            Syntax::Att => self.att_operands(instruction),
+            Syntax::Go => self.go_operands(instruction),
+        };
+        if !operands.is_empty() {
+            text.push(' ');
+            text.push_str(&operands.join(", "));
+        }
+        text
+    }
+
+    fn case(&self, text: &str) -> String {
+        if self.uppercase {
+            text.to_ascii_uppercase()
+        } else {
+            text.to_ascii_lowercase()
+        }
+    }
+
+    fn hex(&self, value: u64) -> String {
+        let digits = if self.uppercase {
+            format!("{value:X}")
+        } else {
+            format!("{value:x}")
+        };
+        match self.hex {
+            HexStyle::Prefix => format!("0x{digits}"),
+            HexStyle::Suffix if digits.starts_with(|c: char| c.is_ascii_alphabetic()) => format!("0{digits}h"),
+            HexStyle::Suffix => format!("{digits}h"),
+        }
+    }
+
+    fn signed_hex(&self, value: i64) -> String {
+        match value < 0 {
+            true => format!("-{}", self.hex(value.unsigned_abs())),
+            false => self.hex(value as u64),
+        }
+    }
+
+    /// An address, by name if the resolver knows it
+    fn address(&self, address: u64) -> String {
+        self.symbols
+            .and_then(|resolve| resolve(address))
+            .unwrap_or_else(|| self.hex(address))
+    }
+
+    fn prefixes(&self, instruction: &X86Opcode) -> String {
+        let mut prefixes = Vec::new();
+        if instruction.prefixes.lock {
+            prefixes.push("lock");
+        }
+        if instruction.is_string() {
+            let compares = matches!(
+                instruction.mnemonic,
+                Mnemonic::Cmps | Mnemonic::Cmpsb | Mnemonic::Scas | Mnemonic::Scasb
+            );
+            match instruction.prefixes.rep {
+                Some(RepPrefix::Rep) if compares => prefixes.push("repe"),
+                Some(RepPrefix::Rep) => prefixes.push("rep"),
+                Some(RepPrefix::Repne) => prefixes.push("repne"),
+                None => {}
+            }
+        }
+        if instruction.has_bnd_prefix() {
+            prefixes.push("bnd");
+        }
+        // go puts prefixes on the line as pseudo instructions
+        let separator = match self.syntax {
+            Syntax::Go => "; ",
+            _ => " ",
+        };
+        prefixes.iter().map(|p| format!("{}{separator}", self.case(p))).collect()
+    }
+
+    fn mnemonic(&self, instruction: &X86Opcode) -> String {
+        let name = match self.syntax {
+            Syntax::Nasm | Syntax::Masm => string_name(instruction, 'd').unwrap_or_else(|| instruction.mnemonic.as_str().to_string()),
+            Syntax::Att => att_mnemonic(instruction),
+            Syntax::Go => go_mnemonic(instruction),
+        };
+        self.case(&name)
+    }
+
+    fn register(&self, register: Register) -> String {
+        match (self.syntax, register) {
+            (Syntax::Masm, Register::St(i)) => self.case(&format!("st({i})")),
+            (Syntax::Att, Register::St(0)) => self.case("%st"),
+            (Syntax::Att, Register::St(i)) => self.case(&format!("%st({i})")),
+            (Syntax::Att, register) => self.case(&format!("%{}", register.name())),
+            (Syntax::Go, register) => self.case(&go_register(register)),
+            (_, register) => self.case(register.name()),
+        }
+    }
+
+    fn intel_operands(&self, instruction: &X86Opcode) -> Vec<String> {
+        let mut operands: Vec<String> = instruction
+            .operands()
+            .iter()
+            .map(|operand| match operand {
+                Operand::Register(register) => self.register(*register),
+                Operand::Immediate(immediate) => self.hex(immediate.value & mask(immediate.size)),
+                Operand::Memory(memory) => self.intel_memory(instruction, memory),
+                Operand::Branch(target) => self.address(*target),
+                Operand::FarPointer { selector, offset } => {
+                    format!("{}:{}", self.hex(*selector as u64), self.hex(*offset as u64))
+                }
+            })
+            .collect();
+        if let Some(destination) = operands.first_mut() {
+            destination.push_str(&self.masking(instruction));
+        }
+        if let Some(rounding) = self.rounding(instruction) {
+            operands.push(rounding);
+        }
+        operands
+    }
+
+    fn intel_memory(&self, instruction: &X86Opcode, memory: &MemoryOperand) -> String {
+        let mut text = String::new();
+        if let Some(size) = intel_size(memory.size, self.syntax) {
+            text.push_str(&self.case(size));
+            text.push(' ');
+        }
+        let segment = memory.segment.map(|s| format!("{}:", self.register(s)));
+        if self.syntax == Syntax::Masm
+            && let Some(segment) = &segment
+        {
+            text.push_str(segment);
+        }
+        text.push('[');
+        if self.syntax == Syntax::Nasm
+            && let Some(segment) = &segment
+        {
+            text.push_str(segment);
+        }
+
+        if let Some(target) = rip_target(instruction, memory) {
+            if self.syntax == Syntax::Nasm {
+                text.push_str(&self.case("rel "));
+            }
+            text.push_str(&self.address(target));
+        } else if memory.base.is_none() && memory.index.is_none() {
+            text.push_str(&self.hex(memory.displacement as u64 & mask(instruction.address_size)));
+        } else {
+            let mut terms = Vec::new();
+            if let Some(base) = memory.base {
+                terms.push(self.register(base));
+            }
+            if let Some(index) = memory.index {
+                terms.push(format!("{}*{}", self.register(index), memory.scale));
+            }
+            text.push_str(&terms.join("+"));
+            if memory.displacement != 0 {
+                let sign = if memory.displacement < 0 { '-' } else { '+' };
+                text.push(sign);
+                text.push_str(&self.hex(memory.displacement.unsigned_abs()));
+            }
+        }
+        text.push(']');
+        text.push_str(&self.broadcast(instruction, memory.size));
+        text
+    }
+
+    fn att_operands(&self, instruction: &X86Opcode) -> Vec<String> {
+        let indirect = is_indirect_branch(instruction);
+        let mut operands: Vec<String> = instruction
+            .operands()
+            .iter()
+            .map(|operand| match operand {
+                Operand::Register(register) if indirect => format!("*{}", self.register(*register)),
+                Operand::Register(register) => self.register(*register),
+                Operand::Immediate(immediate) => format!("${}", self.hex(immediate.value & mask(immediate.size))),
+                Operand::Memory(memory) if indirect => format!("*{}", self.att_memory(instruction, memory)),
+                Operand::Memory(memory) => self.att_memory(instruction, memory),
+                Operand::Branch(target) => self.address(*target),
+                Operand::FarPointer { selector, offset } => {
+                    format!("${}, ${}", self.hex(*selector as u64), self.hex(*offset as u64))
+                }
+            })
+            .collect();
+        // ENTER keeps the Intel order of its two immediates, as the far pointers do
+        if instruction.mnemonic != Mnemonic::Enter {
+            operands.reverse();
+        }
+        if let Some(destination) = operands.last_mut() {
+            destination.push_str(&self.masking(instruction));
+        }
+        if let Some(rounding) = self.rounding(instruction) {
+            operands.insert(0, rounding);
+        }
+        operands
+    }
+
+    fn att_memory(&self, instruction: &X86Opcode, memory: &MemoryOperand) -> String {
+        let mut text = String::new();
+        if let Some(segment) = memory.segment {
+            text.push_str(&self.register(segment));
+            text.push(':');
+        }
+        if let Some(target) = rip_target(instruction, memory) {
+            match self.symbols.and_then(|resolve| resolve(target)) {
+                Some(symbol) => text.push_str(&symbol),
+                None => text.push_str(&self.signed_hex(memory.displacement)),
+            }
+            text.push_str(&format!("({})", self.register(memory.base.expect("rip relative has a base"))));
+        } else if memory.base.is_none() && memory.index.is_none() {
+            text.push_str(&self.hex(memory.displacement as u64 & mask(instruction.address_size)));
+        } else {
+            if memory.displacement != 0 {
+                text.push_str(&self.signed_hex(memory.displacement));
+            }
+            text.push('(');
+            if let Some(base) = memory.base {
+                text.push_str(&self.register(base));
+            }
+            if let Some(index) = memory.index {
+                text.push_str(&format!(",{},{}", self.register(index), memory.scale));
+            }
+            text.push(')');
+        }
+        text.push_str(&self.broadcast(instruction, memory.size));
+        text
+    }
+
+    fn go_operands(&self, instruction: &X86Opcode) -> Vec<String> {
+        let mut operands: Vec<String> = instruction
+            .operands()
+            .iter()
+            .rev()
+            .map(|operand| match operand {
+                Operand::Register(register) => self.register(*register),
+                Operand::Immediate(immediate) => {
+                    format!("${}", self.signed_hex(sign_extend(immediate.value, immediate.size)))
+                }
+                Operand::Memory(memory) => self.go_memory(instruction, memory),
+                Operand::Branch(target) => match self.symbols.and_then(|resolve| resolve(*target)) {
+                    Some(symbol) => format!("{symbol}(SB)"),
+                    None => self.hex(*target),
+                },
+                Operand::FarPointer { selector, offset } => {
+                    format!("${}, ${}", self.hex(*selector as u64), self.hex(*offset as u64))
+                }
+            })
+            .collect();
+        // the opmask is an operand of its own, just before the destination
+        if let Some(opmask) = instruction.opmask() {
+            let position = operands.len().saturating_sub(1);
+            operands.insert(position, self.register(opmask));
+        }
+        operands
+    }
+
+    fn go_memory(&self, instruction: &X86Opcode, memory: &MemoryOperand) -> String {
+        let mut text = String::new();
+        if let Some(segment) = memory.segment {
+            text.push_str(&self.register(segment));
+            text.push(':');
+        }
+        if let Some(target) = rip_target(instruction, memory) {
+            match self.symbols.and_then(|resolve| resolve(target)) {
+                Some(symbol) => text.push_str(&format!("{symbol}(SB)")),
+                None => text.push_str(&format!("{}(IP)", self.signed_hex(memory.displacement))),
+            }
+        } else if memory.base.is_none() && memory.index.is_none() {
+            text.push_str(&self.hex(memory.displacement as u64 & mask(instruction.address_size)));
+        } else {
+            text.push_str(&self.signed_hex(memory.displacement));
+            if let Some(base) = memory.base {
+                text.push_str(&format!("({})", self.register(base)));
+            }
+            if let Some(index) = memory.index {
+                text.push_str(&format!("({}*{})", self.register(index), memory.scale));
+            }
+        }
+        text
+    }
+
+    /// `{k1}{z}` on the destination
+    fn masking(&self, instruction: &X86Opcode) -> String {
+        let mut text = String::new();
+        if let Some(opmask) = instruction.opmask() {
+            text.push_str(&format!("{{{}}}", self.register(opmask)));
+        }
+        if instruction.zeroing() {
+            text.push_str(&self.case("{z}"));
+        }
+        text
+    }
+
+    /// `{rn-sae}` / `{sae}`, written as an operand of its own
+    fn rounding(&self, instruction: &X86Opcode) -> Option<String> {
+        let text = match instruction.rounding {
+            Some(RoundingControl::Nearest) => "{rn-sae}",
+            Some(RoundingControl::Down) => "{rd-sae}",
+            Some(RoundingControl::Up) => "{ru-sae}",
+            Some(RoundingControl::Zero) => "{rz-sae}",
+            None if instruction.sae => "{sae}",
+            None => return None,
+        };
+        Some(self.case(text))
+    }
+
+    /// `{1to16}` after a broadcast memory operand
+    fn broadcast(&self, instruction: &X86Opcode, element_size: u16) -> String {
+        if !instruction.broadcast || element_size == 0 {
+            return String::new();
+        }
+        self.case(&format!("{{1to{}}}", instruction.vector_length / element_size))
+    }
+}
+
+/// Formats with NASM syntax
+impl fmt::Display for X86Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&Formatter::default().format(self))
+    }
+}
+
+/// all ones in the low `size` bytes
+fn mask(size: u8) -> u64 {
+    match size {
+        0 | 8.. => u64::MAX,
+        size => (1u64 << (size * 8)) - 1,
+    }
+}
+
+fn sign_extend(value: u64, size: u8) -> i64 {
+    match size {
+        1 => value as i8 as i64,
+        2 => value as i16 as i64,
+        4 => value as i32 as i64,
+        _ => value as i64,
+    }
+}
+
+/// The address a rip relative operand refers to
+fn rip_target(instruction: &X86Opcode, memory: &MemoryOperand) -> Option<u64> {
+    let target = instruction.next_ip().wrapping_add(memory.displacement as u64);
+    match memory.base {
+        Some(Register::Rip) => Some(target),
+        Some(Register::Eip) => Some(target & 0xFFFF_FFFF),
+        _ => None,
+    }
+}
+
+fn intel_size(size: u16, syntax: Syntax) -> Option<&'static str> {
+    let masm = syntax == Syntax::Masm;
+    Some(match size {
+        1 if masm => "byte ptr",
+        2 if masm => "word ptr",
+        4 if masm => "dword ptr",
+        6 if masm => "fword ptr",
+        8 if masm => "qword ptr",
+        10 if masm => "tbyte ptr",
+        16 if masm => "xmmword ptr",
+        32 if masm => "ymmword ptr",
+        64 if masm => "zmmword ptr",
+        1 => "byte",
+        2 => "word",
+        4 => "dword",
+        8 => "qword",
+        10 => "tword",
+        16 => "oword",
+        32 => "yword",
+        64 => "zword",
+        _ => return None,
+    })
+}
+
+fn is_indirect_branch(instruction: &X86Opcode) -> bool {
+    matches!(
+        instruction.mnemonic,
+        Mnemonic::Jmp | Mnemonic::Call | Mnemonic::Jmpf | Mnemonic::Callf
+    ) && matches!(
+        instruction.operands().first(),
+        Some(Operand::Register(_) | Operand::Memory(_))
+    )
+}
+
+/// The sized name of a string instruction whose size comes from the operand size, `movsq`.
+/// `dword` is the letter for 4 bytes: `d` in Intel syntax, `l` in AT&T and Go
+fn string_name(instruction: &X86Opcode, dword: char) -> Option<String> {
+    let stem = match instruction.mnemonic {
+        Mnemonic::Movs => "movs",
+        Mnemonic::Cmps => "cmps",
+        Mnemonic::Stos => "stos",
+        Mnemonic::Lods => "lods",
+        Mnemonic::Scas => "scas",
+        Mnemonic::Ins => "ins",
+        Mnemonic::Outs => "outs",
+        _ => return None,
+    };
+    let size = match instruction.operand_size {
+        2 => 'w',
+        4 => dword,
+        _ => 'q',
+    };
+    Some(format!("{stem}{size}"))
+}
+
+fn size_letter(size: u16) -> &'static str {
+    match size {
+        1 => "b",
+        2 => "w",
+        4 => "l",
+        _ => "q",
+    }
+}
+
+/// Whether a register holds integer data, which is what decides between `addq` and `addps`
+fn is_integer_register(register: &Register) -> bool {
+    register.is_gpr() || matches!(register, Register::Segment(_) | Register::Control(_) | Register::Debug(_))
+}
+
+/// Instructions with a memory operand that is not sized by the operation, and which take
+/// no size suffix even when nothing else gives the size away
+const UNSIZED: [&str; 20] = [
+    "ret", "retf", "clwb", "cldemote", "invlpg", "ldmxcsr", "stmxcsr", "vldmxcsr", "vstmxcsr",
+    "cmpxchg8b", "cmpxchg16b", "sldt", "str", "lldt", "ltr", "verr", "verw", "lmsw", "smsw",
+    "vmclear",
+];
+
+/// Families of unsized instructions, by name prefix. x87 instructions get their own suffixes
+const UNSIZED_FAMILIES: [&str; 11] = [
+    "set", "prefetch", "clflush", "xsave", "xrstor", "vmptr", "sgdt", "sidt", "lgdt", "lidt", "f",
+];
+
+/// Size of the integer operation an instruction performs, for the AT&T and Go size suffix.
+/// `None` for instructions that take no suffix, and the flag says whether a register
+/// operand already implies the size
+fn integer_size(instruction: &X86Opcode) -> Option<(u16, bool)> {
+    let mnemonic = instruction.mnemonic;
+    let name = mnemonic.as_str();
+    let no_suffix = tables::tables().is_relative_branch(mnemonic)
+        || is_indirect_branch(instruction)
+        || string_name(instruction, 'l').is_some()
+        || UNSIZED.contains(&name)
+        || UNSIZED_FAMILIES.iter().any(|family| name.starts_with(family));
+    let operands = instruction.operands();
+    if no_suffix || operands.is_empty() {
+        return None;
+    }
+    let mut registers = operands.iter().filter_map(|operand| match operand {
+        Operand::Register(register) => Some(register),
+        _ => None,
+    });
+    if registers.clone().any(|r| !is_integer_register(r)) {
+        return None;
+    }
+    // the port is not what decides the size of in / out
+    let register = match mnemonic {
+        Mnemonic::Out => registers.next_back(),
+        _ => registers.next(),
+    };
+    if let Some(register) = register {
+        return Some((register.size(), true));
+    }
+    match instruction.memory_operand() {
+        Some(memory) if matches!(memory.size, 1 | 2 | 4 | 8) => Some((memory.size, false)),
+        Some(_) => None,
+        None if mnemonic == Mnemonic::Push => Some((instruction.operand_size as u16, false)),
+        None => None,
+    }
+}
+
+/// Size suffix of an x87 memory operand in AT&T syntax, `flds` / `fildll`
+fn x87_suffix(instruction: &X86Opcode) -> Option<&'static str> {
+    let name = instruction.mnemonic.as_str();
+    if !name.starts_with('f') {
+        return None;
+    }
+    let size = instruction.memory_operand()?.size;
+    match (name.starts_with("fi"), size) {
+        (true, 2) => Some("s"),
+        (true, 4) => Some("l"),
+        (true, 8) => Some("ll"),
+        (false, 4) => Some("s"),
+        (false, 8) => Some("l"),
+        (false, 10) => Some("t"),
+        _ => None,
+    }
+}
+
+/// Source and destination sizes of `movzx` / `movsx` / `movsxd`
+fn extension_sizes(instruction: &X86Opcode) -> Option<(u16, u16)> {
+    let operand_size = |operand: &Operand| match operand {
+        Operand::Register(register) => register.size(),
+        Operand::Memory(memory) => memory.size,
+        _ => 0,
+    };
+    match instruction.operands() {
+        [destination, source] => Some((operand_size(source), operand_size(destination))),
+        _ => None,
+    }
+}
+
+fn att_mnemonic(instruction: &X86Opcode) -> String {
+    if let Some(name) = string_name(instruction, 'l') {
+        return name;
+    }
+    let name = match instruction.mnemonic {
+        Mnemonic::Cbw => "cbtw",
+        Mnemonic::Cwde => "cwtl",
+        Mnemonic::Cdqe => "cltq",
+        Mnemonic::Cwd => "cwtd",
+        Mnemonic::Cdq => "cltd",
+        Mnemonic::Cqo => "cqto",
+        Mnemonic::Jmpf => "ljmp",
+        Mnemonic::Callf => "lcall",
+        Mnemonic::Retf => "lret",
+        Mnemonic::Movzx | Mnemonic::Movsx | Mnemonic::Movsxd => {
+            if let Some((source, destination)) = extension_sizes(instruction) {
+                let stem = if instruction.mnemonic == Mnemonic::Movzx { "movz" } else { "movs" };
+                return format!("{stem}{}{}", size_letter(source), size_letter(destination));
+            }
+            instruction.mnemonic.as_str()
+        }
+        mnemonic => mnemonic.as_str(),
+    };
+    // only needed where no register gives the size away, `movl $1, (%rax)`
+    let suffix = match integer_size(instruction) {
+        Some((size, false)) => size_letter(size),
+        _ => x87_suffix(instruction).unwrap_or(""),
+    };
+    format!("{name}{suffix}")
+}
+
+/// Plan 9 spelling of a condition code suffix
+fn go_condition(condition: &str) -> Option<&'static str> {
+    Some(match condition {
+        "o" => "OS",
+        "no" => "OC",
+        "b" => "CS",
+        "ae" => "CC",
+        "e" => "EQ",
+        "ne" => "NE",
+        "be" => "LS",
+        "a" => "HI",
+        "s" => "MI",
+        "ns" => "PL",
+        "p" => "PS",
+        "np" => "PC",
+        "l" => "LT",
+        "ge" => "GE",
+        "le" => "LE",
+        "g" => "GT",
+        _ => return None,
+    })
+}
+
+fn go_mnemonic(instruction: &X86Opcode) -> String {
+    let mnemonic = instruction.mnemonic;
+    let name = mnemonic.as_str();
+    let size = integer_size(instruction).map(|(size, _)| size_letter(size).to_ascii_uppercase());
+
+    let mut text = if let Some(name) = string_name(instruction, 'l') {
+        name.to_ascii_uppercase()
+    } else if let Some(condition) = name.strip_prefix('j').and_then(go_condition) {
+        format!("J{condition}")
+    } else if let Some(condition) = name.strip_prefix("set").and_then(go_condition) {
+        format!("SET{condition}")
+    } else if let Some(condition) = name.strip_prefix("cmov").and_then(go_condition) {
+        format!("CMOV{}{condition}", size.unwrap_or_default())
+    } else if matches!(mnemonic, Mnemonic::Movzx | Mnemonic::Movsx | Mnemonic::Movsxd)
+        && let Some((source, destination)) = extension_sizes(instruction)
+    {
+        let kind = if mnemonic == Mnemonic::Movzx { "ZX" } else { "SX" };
+        format!(
+            "MOV{}{}{kind}",
+            size_letter(source).to_ascii_uppercase(),
+            size_letter(destination).to_ascii_uppercase()
+        )
+    } else {
+        format!("{}{}", name.to_ascii_uppercase(), size.unwrap_or_default())
+    };
+
+    // AVX-512 decorations are mnemonic suffixes
+    match instruction.rounding {
+        Some(RoundingControl::Nearest) => text.push_str(".RN_SAE"),
+        Some(RoundingControl::Down) => text.push_str(".RD_SAE"),
+        Some(RoundingControl::Up) => text.push_str(".RU_SAE"),
+        Some(RoundingControl::Zero) => text.push_str(".RZ_SAE"),
+        None if instruction.sae => text.push_str(".SAE"),
+        None => {}
+    }
+    if instruction.broadcast {
+        text.push_str(".BCST");
+    }
+    if instruction.zeroing() {
+        text.push_str(".Z");
+    }
+    text
+}
+
+fn go_register(register: Register) -> String {
+    const GPR: [&str; 8] = ["AX", "CX", "DX", "BX", "SP", "BP", "SI", "DI"];
+    const GPR8: [&str; 8] = ["AL", "CL", "DL", "BL", "SPB", "BPB", "SIB", "DIB"];
+    match register {
+        // go names general purpose registers the same whatever the width
+        Register::Gpr16(n) | Register::Gpr32(n) | Register::Gpr64(n) if n < 8 => GPR[n as usize].to_string(),
+        Register::Gpr16(n) | Register::Gpr32(n) | Register::Gpr64(n) => format!("R{n}"),
+        Register::Gpr8(n) if n < 8 => GPR8[n as usize].to_string(),
+        Register::Gpr8(n) => format!("R{n}B"),
+        Register::Xmm(n) => format!("X{n}"),
+        Register::Ymm(n) => format!("Y{n}"),
+        Register::Zmm(n) => format!("Z{n}"),
+        Register::Mmx(n) => format!("M{n}"),
+        Register::St(n) => format!("F{n}"),
+        Register::Opmask(n) => format!("K{n}"),
+        Register::Rip | Register::Eip => "IP".to_string(),
+        other => other.name().to_ascii_uppercase(),
+    }
+}
@@ -1,9 +1,16 @@
 pub mod opcodes;
+pub mod registers;
 pub mod encode;
 pub mod decode;
+pub mod assemble;
+pub mod format;
+mod tables;
 
-mod prelude {
+pub mod prelude {
+    pub use crate::assemble::*;
     pub use crate::decode::*;
     pub use crate::encode::*;
+    pub use crate::format::*;
     pub use crate::opcodes::*;
-}
\ No newline at end of file
+    pub use crate::registers::*;
+}
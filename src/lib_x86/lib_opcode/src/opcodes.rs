@@ -1,10 +1,1579 @@
-impl UnsignedInteger for u8 {}
+use crate::registers::Register;
+use std::ops::Deref;
 
-trait UnsignedInteger {}
-impl UnsignedInteger for u16 {}
-impl UnsignedInteger for u32 {}
-impl UnsignedInteger for u64 {}
+/// Declares the `Mnemonic` enum along with its textual names
+macro_rules! mnemonics {
+    ($($variant:ident => $name:literal),* $(,)?) => {
+        /// Instruction mnemonics
+        ///
+        /// Instructions that differ only in operand size share a mnemonic (`add al, 1` and
+        /// `add rax, 1` are both `Add`), the operands carry the size. The exceptions are the
+        /// cases where assemblers spell the sizes differently: string instructions (`movsb`
+        /// vs `movs`, the latter sized by the operand size), and the sign extension family
+        /// (`cbw`, `cwde`, `cdqe`).
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Mnemonic {
+            $($variant),*
+        }
 
+        impl Mnemonic {
+            /// lowercase assembler name of the mnemonic
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(Mnemonic::$variant => $name),*
+                }
+            }
+
+            /// Looks a mnemonic up by its (case insensitive) assembler name
+            pub fn from_name(name: &str) -> Option<Mnemonic> {
+                match name.to_ascii_lowercase().as_str() {
+                    $($name => Some(Mnemonic::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+mnemonics! {
+    Aaa => "aaa",
+    Aad => "aad",
+    Aam => "aam",
+    Aas => "aas",
+    Adc => "adc",
+    Adcx => "adcx",
+    Add => "add",
+    Addpd => "addpd",
+    Addps => "addps",
+    Addsd => "addsd",
+    Addss => "addss",
+    Addsubpd => "addsubpd",
+    Addsubps => "addsubps",
+    Adox => "adox",
+    Aesdec => "aesdec",
+    Aesdeclast => "aesdeclast",
+    Aesenc => "aesenc",
+    Aesenclast => "aesenclast",
+    Aesimc => "aesimc",
+    Aeskeygenassist => "aeskeygenassist",
+    And => "and",
+    Andn => "andn",
+    Andnpd => "andnpd",
+    Andnps => "andnps",
+    Andpd => "andpd",
+    Andps => "andps",
+    Arpl => "arpl",
+    Bextr => "bextr",
+    Blendpd => "blendpd",
+    Blendps => "blendps",
+    Blendvpd => "blendvpd",
+    Blendvps => "blendvps",
+    Blsi => "blsi",
+    Blsmsk => "blsmsk",
+    Blsr => "blsr",
+    Bndcl => "bndcl",
+    Bndcn => "bndcn",
+    Bndcu => "bndcu",
+    Bndldx => "bndldx",
+    Bndmk => "bndmk",
+    Bndmov => "bndmov",
+    Bndstx => "bndstx",
+    Bound => "bound",
+    Bsf => "bsf",
+    Bsr => "bsr",
+    Bswap => "bswap",
+    Bt => "bt",
+    Btc => "btc",
+    Btr => "btr",
+    Bts => "bts",
+    Bzhi => "bzhi",
+    Call => "call",
+    Callf => "callf",
+    Cbw => "cbw",
+    Cdq => "cdq",
+    Cdqe => "cdqe",
+    Clac => "clac",
+    Clc => "clc",
+    Cld => "cld",
+    Clflush => "clflush",
+    Clflushopt => "clflushopt",
+    Cli => "cli",
+    Clts => "clts",
+    Clwb => "clwb",
+    Cmc => "cmc",
+    Cmova => "cmova",
+    Cmovae => "cmovae",
+    Cmovb => "cmovb",
+    Cmovbe => "cmovbe",
+    Cmove => "cmove",
+    Cmovg => "cmovg",
+    Cmovge => "cmovge",
+    Cmovl => "cmovl",
+    Cmovle => "cmovle",
+    Cmovne => "cmovne",
+    Cmovno => "cmovno",
+    Cmovnp => "cmovnp",
+    Cmovns => "cmovns",
+    Cmovo => "cmovo",
+    Cmovp => "cmovp",
+    Cmovs => "cmovs",
+    Cmp => "cmp",
+    Cmppd => "cmppd",
+    Cmpps => "cmpps",
+    Cmps => "cmps",
+    Cmpsb => "cmpsb",
+    Cmpsd => "cmpsd",
+    Cmpss => "cmpss",
+    Cmpxchg => "cmpxchg",
+    Cmpxchg16b => "cmpxchg16b",
+    Cmpxchg8b => "cmpxchg8b",
+    Comisd => "comisd",
+    Comiss => "comiss",
+    Cpuid => "cpuid",
+    Cqo => "cqo",
+    Crc32 => "crc32",
+    Cvtdq2pd => "cvtdq2pd",
+    Cvtdq2ps => "cvtdq2ps",
+    Cvtpd2dq => "cvtpd2dq",
+    Cvtpd2pi => "cvtpd2pi",
+    Cvtpd2ps => "cvtpd2ps",
+    Cvtpi2pd => "cvtpi2pd",
+    Cvtpi2ps => "cvtpi2ps",
+    Cvtps2dq => "cvtps2dq",
+    Cvtps2pd => "cvtps2pd",
+    Cvtps2pi => "cvtps2pi",
+    Cvtsd2si => "cvtsd2si",
+    Cvtsd2ss => "cvtsd2ss",
+    Cvtsi2sd => "cvtsi2sd",
+    Cvtsi2ss => "cvtsi2ss",
+    Cvtss2sd => "cvtss2sd",
+    Cvtss2si => "cvtss2si",
+    Cvttpd2dq => "cvttpd2dq",
+    Cvttpd2pi => "cvttpd2pi",
+    Cvttps2dq => "cvttps2dq",
+    Cvttps2pi => "cvttps2pi",
+    Cvttsd2si => "cvttsd2si",
+    Cvttss2si => "cvttss2si",
+    Cwd => "cwd",
+    Cwde => "cwde",
+    Daa => "daa",
+    Das => "das",
+    Dec => "dec",
+    Div => "div",
+    Divpd => "divpd",
+    Divps => "divps",
+    Divsd => "divsd",
+    Divss => "divss",
+    Dppd => "dppd",
+    Dpps => "dpps",
+    Emms => "emms",
+    Endbr32 => "endbr32",
+    Endbr64 => "endbr64",
+    Enter => "enter",
+    Extractps => "extractps",
+    F2xm1 => "f2xm1",
+    Fabs => "fabs",
+    Fadd => "fadd",
+    Faddp => "faddp",
+    Fbld => "fbld",
+    Fbstp => "fbstp",
+    Fchs => "fchs",
+    Fcmovb => "fcmovb",
+    Fcmovbe => "fcmovbe",
+    Fcmove => "fcmove",
+    Fcmovnb => "fcmovnb",
+    Fcmovnbe => "fcmovnbe",
+    Fcmovne => "fcmovne",
+    Fcmovnu => "fcmovnu",
+    Fcmovu => "fcmovu",
+    Fcom => "fcom",
+    Fcomi => "fcomi",
+    Fcomip => "fcomip",
+    Fcomp => "fcomp",
+    Fcompp => "fcompp",
+    Fcos => "fcos",
+    Fdecstp => "fdecstp",
+    Fdiv => "fdiv",
+    Fdivp => "fdivp",
+    Fdivr => "fdivr",
+    Fdivrp => "fdivrp",
+    Ffree => "ffree",
+    Fiadd => "fiadd",
+    Ficom => "ficom",
+    Ficomp => "ficomp",
+    Fidiv => "fidiv",
+    Fidivr => "fidivr",
+    Fild => "fild",
+    Fimul => "fimul",
+    Fincstp => "fincstp",
+    Fist => "fist",
+    Fistp => "fistp",
+    Fisttp => "fisttp",
+    Fisub => "fisub",
+    Fisubr => "fisubr",
+    Fld => "fld",
+    Fld1 => "fld1",
+    Fldcw => "fldcw",
+    Fldenv => "fldenv",
+    Fldl2e => "fldl2e",
+    Fldl2t => "fldl2t",
+    Fldlg2 => "fldlg2",
+    Fldln2 => "fldln2",
+    Fldpi => "fldpi",
+    Fldz => "fldz",
+    Fmul => "fmul",
+    Fmulp => "fmulp",
+    Fnclex => "fnclex",
+    Fninit => "fninit",
+    Fnop => "fnop",
+    Fnsave => "fnsave",
+    Fnstcw => "fnstcw",
+    Fnstenv => "fnstenv",
+    Fnstsw => "fnstsw",
+    Fpatan => "fpatan",
+    Fprem => "fprem",
+    Fprem1 => "fprem1",
+    Fptan => "fptan",
+    Frndint => "frndint",
+    Frstor => "frstor",
+    Fscale => "fscale",
+    Fsin => "fsin",
+    Fsincos => "fsincos",
+    Fsqrt => "fsqrt",
+    Fst => "fst",
+    Fstp => "fstp",
+    Fsub => "fsub",
+    Fsubp => "fsubp",
+    Fsubr => "fsubr",
+    Fsubrp => "fsubrp",
+    Ftst => "ftst",
+    Fucom => "fucom",
+    Fucomi => "fucomi",
+    Fucomip => "fucomip",
+    Fucomp => "fucomp",
+    Fucompp => "fucompp",
+    Fxam => "fxam",
+    Fxch => "fxch",
+    Fxrstor => "fxrstor",
+    Fxrstor64 => "fxrstor64",
+    Fxsave => "fxsave",
+    Fxsave64 => "fxsave64",
+    Fxtract => "fxtract",
+    Fyl2x => "fyl2x",
+    Fyl2xp1 => "fyl2xp1",
+    Getsec => "getsec",
+    Haddpd => "haddpd",
+    Haddps => "haddps",
+    Hlt => "hlt",
+    Hsubpd => "hsubpd",
+    Hsubps => "hsubps",
+    Idiv => "idiv",
+    Imul => "imul",
+    In => "in",
+    Inc => "inc",
+    Ins => "ins",
+    Insb => "insb",
+    Insertps => "insertps",
+    Int => "int",
+    Int1 => "int1",
+    Int3 => "int3",
+    Into => "into",
+    Invd => "invd",
+    Invept => "invept",
+    Invlpg => "invlpg",
+    Invpcid => "invpcid",
+    Invvpid => "invvpid",
+    Iret => "iret",
+    Iretd => "iretd",
+    Iretq => "iretq",
+    Ja => "ja",
+    Jae => "jae",
+    Jb => "jb",
+    Jbe => "jbe",
+    Jcxz => "jcxz",
+    Je => "je",
+    Jecxz => "jecxz",
+    Jg => "jg",
+    Jge => "jge",
+    Jl => "jl",
+    Jle => "jle",
+    Jmp => "jmp",
+    Jmpf => "jmpf",
+    Jne => "jne",
+    Jno => "jno",
+    Jnp => "jnp",
+    Jns => "jns",
+    Jo => "jo",
+    Jp => "jp",
+    Jrcxz => "jrcxz",
+    Js => "js",
+    Kaddb => "kaddb",
+    Kaddd => "kaddd",
+    Kaddq => "kaddq",
+    Kaddw => "kaddw",
+    Kandb => "kandb",
+    Kandd => "kandd",
+    Kandnb => "kandnb",
+    Kandnd => "kandnd",
+    Kandnq => "kandnq",
+    Kandnw => "kandnw",
+    Kandq => "kandq",
+    Kandw => "kandw",
+    Kmovb => "kmovb",
+    Kmovd => "kmovd",
+    Kmovq => "kmovq",
+    Kmovw => "kmovw",
+    Knotb => "knotb",
+    Knotd => "knotd",
+    Knotq => "knotq",
+    Knotw => "knotw",
+    Korb => "korb",
+    Kord => "kord",
+    Korq => "korq",
+    Kortestb => "kortestb",
+    Kortestd => "kortestd",
+    Kortestq => "kortestq",
+    Kortestw => "kortestw",
+    Korw => "korw",
+    Kshiftlb => "kshiftlb",
+    Kshiftld => "kshiftld",
+    Kshiftlq => "kshiftlq",
+    Kshiftlw => "kshiftlw",
+    Kshiftrb => "kshiftrb",
+    Kshiftrd => "kshiftrd",
+    Kshiftrq => "kshiftrq",
+    Kshiftrw => "kshiftrw",
+    Ktestb => "ktestb",
+    Ktestd => "ktestd",
+    Ktestq => "ktestq",
+    Ktestw => "ktestw",
+    Kunpckbw => "kunpckbw",
+    Kunpckdq => "kunpckdq",
+    Kunpckwd => "kunpckwd",
+    Kxnorb => "kxnorb",
+    Kxnord => "kxnord",
+    Kxnorq => "kxnorq",
+    Kxnorw => "kxnorw",
+    Kxorb => "kxorb",
+    Kxord => "kxord",
+    Kxorq => "kxorq",
+    Kxorw => "kxorw",
+    Lahf => "lahf",
+    Lar => "lar",
+    Lddqu => "lddqu",
+    Ldmxcsr => "ldmxcsr",
+    Lds => "lds",
+    Lea => "lea",
+    Leave => "leave",
+    Les => "les",
+    Lfence => "lfence",
+    Lfs => "lfs",
+    Lgdt => "lgdt",
+    Lgs => "lgs",
+    Lidt => "lidt",
+    Lldt => "lldt",
+    Lmsw => "lmsw",
+    Lods => "lods",
+    Lodsb => "lodsb",
+    Loop => "loop",
+    Loope => "loope",
+    Loopne => "loopne",
+    Lsl => "lsl",
+    Lss => "lss",
+    Ltr => "ltr",
+    Lzcnt => "lzcnt",
+    Maskmovdqu => "maskmovdqu",
+    Maskmovq => "maskmovq",
+    Maxpd => "maxpd",
+    Maxps => "maxps",
+    Maxsd => "maxsd",
+    Maxss => "maxss",
+    Mfence => "mfence",
+    Minpd => "minpd",
+    Minps => "minps",
+    Minsd => "minsd",
+    Minss => "minss",
+    Monitor => "monitor",
+    Mov => "mov",
+    Movapd => "movapd",
+    Movaps => "movaps",
+    Movbe => "movbe",
+    Movd => "movd",
+    Movddup => "movddup",
+    Movdq2q => "movdq2q",
+    Movdqa => "movdqa",
+    Movdqu => "movdqu",
+    Movhlps => "movhlps",
+    Movhpd => "movhpd",
+    Movhps => "movhps",
+    Movlhps => "movlhps",
+    Movlpd => "movlpd",
+    Movlps => "movlps",
+    Movmskpd => "movmskpd",
+    Movmskps => "movmskps",
+    Movntdq => "movntdq",
+    Movntdqa => "movntdqa",
+    Movnti => "movnti",
+    Movntpd => "movntpd",
+    Movntps => "movntps",
+    Movntq => "movntq",
+    Movq => "movq",
+    Movq2dq => "movq2dq",
+    Movs => "movs",
+    Movsb => "movsb",
+    Movsd => "movsd",
+    Movshdup => "movshdup",
+    Movsldup => "movsldup",
+    Movss => "movss",
+    Movsx => "movsx",
+    Movsxd => "movsxd",
+    Movupd => "movupd",
+    Movups => "movups",
+    Movzx => "movzx",
+    Mpsadbw => "mpsadbw",
+    Mul => "mul",
+    Mulpd => "mulpd",
+    Mulps => "mulps",
+    Mulsd => "mulsd",
+    Mulss => "mulss",
+    Mulx => "mulx",
+    Mwait => "mwait",
+    Neg => "neg",
+    Nop => "nop",
+    Not => "not",
+    Or => "or",
+    Orpd => "orpd",
+    Orps => "orps",
+    Out => "out",
+    Outs => "outs",
+    Outsb => "outsb",
+    Pabsb => "pabsb",
+    Pabsd => "pabsd",
+    Pabsw => "pabsw",
+    Packssdw => "packssdw",
+    Packsswb => "packsswb",
+    Packusdw => "packusdw",
+    Packuswb => "packuswb",
+    Paddb => "paddb",
+    Paddd => "paddd",
+    Paddq => "paddq",
+    Paddsb => "paddsb",
+    Paddsw => "paddsw",
+    Paddusb => "paddusb",
+    Paddusw => "paddusw",
+    Paddw => "paddw",
+    Palignr => "palignr",
+    Pand => "pand",
+    Pandn => "pandn",
+    Pause => "pause",
+    Pavgb => "pavgb",
+    Pavgw => "pavgw",
+    Pblendvb => "pblendvb",
+    Pblendw => "pblendw",
+    Pclmulqdq => "pclmulqdq",
+    Pcmpeqb => "pcmpeqb",
+    Pcmpeqd => "pcmpeqd",
+    Pcmpeqq => "pcmpeqq",
+    Pcmpeqw => "pcmpeqw",
+    Pcmpestri => "pcmpestri",
+    Pcmpestrm => "pcmpestrm",
+    Pcmpgtb => "pcmpgtb",
+    Pcmpgtd => "pcmpgtd",
+    Pcmpgtq => "pcmpgtq",
+    Pcmpgtw => "pcmpgtw",
+    Pcmpistri => "pcmpistri",
+    Pcmpistrm => "pcmpistrm",
+    Pdep => "pdep",
+    Pext => "pext",
+    Pextrb => "pextrb",
+    Pextrd => "pextrd",
+    Pextrq => "pextrq",
+    Pextrw => "pextrw",
+    Phaddd => "phaddd",
+    Phaddsw => "phaddsw",
+    Phaddw => "phaddw",
+    Phminposuw => "phminposuw",
+    Phsubd => "phsubd",
+    Phsubsw => "phsubsw",
+    Phsubw => "phsubw",
+    Pinsrb => "pinsrb",
+    Pinsrd => "pinsrd",
+    Pinsrq => "pinsrq",
+    Pinsrw => "pinsrw",
+    Pmaddubsw => "pmaddubsw",
+    Pmaddwd => "pmaddwd",
+    Pmaxsb => "pmaxsb",
+    Pmaxsd => "pmaxsd",
+    Pmaxsw => "pmaxsw",
+    Pmaxub => "pmaxub",
+    Pmaxud => "pmaxud",
+    Pmaxuw => "pmaxuw",
+    Pminsb => "pminsb",
+    Pminsd => "pminsd",
+    Pminsw => "pminsw",
+    Pminub => "pminub",
+    Pminud => "pminud",
+    Pminuw => "pminuw",
+    Pmovmskb => "pmovmskb",
+    Pmovsxbd => "pmovsxbd",
+    Pmovsxbq => "pmovsxbq",
+    Pmovsxbw => "pmovsxbw",
+    Pmovsxdq => "pmovsxdq",
+    Pmovsxwd => "pmovsxwd",
+    Pmovsxwq => "pmovsxwq",
+    Pmovzxbd => "pmovzxbd",
+    Pmovzxbq => "pmovzxbq",
+    Pmovzxbw => "pmovzxbw",
+    Pmovzxdq => "pmovzxdq",
+    Pmovzxwd => "pmovzxwd",
+    Pmovzxwq => "pmovzxwq",
+    Pmuldq => "pmuldq",
+    Pmulhrsw => "pmulhrsw",
+    Pmulhuw => "pmulhuw",
+    Pmulhw => "pmulhw",
+    Pmulld => "pmulld",
+    Pmullw => "pmullw",
+    Pmuludq => "pmuludq",
+    Pop => "pop",
+    Popa => "popa",
+    Popad => "popad",
+    Popcnt => "popcnt",
+    Popf => "popf",
+    Popfd => "popfd",
+    Popfq => "popfq",
+    Por => "por",
+    Prefetch => "prefetch",
+    Prefetchnta => "prefetchnta",
+    Prefetcht0 => "prefetcht0",
+    Prefetcht1 => "prefetcht1",
+    Prefetcht2 => "prefetcht2",
+    Prefetchw => "prefetchw",
+    Psadbw => "psadbw",
+    Pshufb => "pshufb",
+    Pshufd => "pshufd",
+    Pshufhw => "pshufhw",
+    Pshuflw => "pshuflw",
+    Pshufw => "pshufw",
+    Psignb => "psignb",
+    Psignd => "psignd",
+    Psignw => "psignw",
+    Pslld => "pslld",
+    Pslldq => "pslldq",
+    Psllq => "psllq",
+    Psllw => "psllw",
+    Psrad => "psrad",
+    Psraw => "psraw",
+    Psrld => "psrld",
+    Psrldq => "psrldq",
+    Psrlq => "psrlq",
+    Psrlw => "psrlw",
+    Psubb => "psubb",
+    Psubd => "psubd",
+    Psubq => "psubq",
+    Psubsb => "psubsb",
+    Psubsw => "psubsw",
+    Psubusb => "psubusb",
+    Psubusw => "psubusw",
+    Psubw => "psubw",
+    Ptest => "ptest",
+    Punpckhbw => "punpckhbw",
+    Punpckhdq => "punpckhdq",
+    Punpckhqdq => "punpckhqdq",
+    Punpckhwd => "punpckhwd",
+    Punpcklbw => "punpcklbw",
+    Punpckldq => "punpckldq",
+    Punpcklqdq => "punpcklqdq",
+    Punpcklwd => "punpcklwd",
+    Push => "push",
+    Pusha => "pusha",
+    Pushad => "pushad",
+    Pushf => "pushf",
+    Pushfd => "pushfd",
+    Pushfq => "pushfq",
+    Pxor => "pxor",
+    Rcl => "rcl",
+    Rcpps => "rcpps",
+    Rcpss => "rcpss",
+    Rcr => "rcr",
+    Rdfsbase => "rdfsbase",
+    Rdgsbase => "rdgsbase",
+    Rdmsr => "rdmsr",
+    Rdpid => "rdpid",
+    Rdpkru => "rdpkru",
+    Rdpmc => "rdpmc",
+    Rdrand => "rdrand",
+    Rdseed => "rdseed",
+    Rdtsc => "rdtsc",
+    Rdtscp => "rdtscp",
+    Ret => "ret",
+    Retf => "retf",
+    Rol => "rol",
+    Ror => "ror",
+    Rorx => "rorx",
+    Roundpd => "roundpd",
+    Roundps => "roundps",
+    Roundsd => "roundsd",
+    Roundss => "roundss",
+    Rsm => "rsm",
+    Rsqrtps => "rsqrtps",
+    Rsqrtss => "rsqrtss",
+    Sahf => "sahf",
+    Sal => "sal",
+    Salc => "salc",
+    Sar => "sar",
+    Sarx => "sarx",
+    Sbb => "sbb",
+    Scas => "scas",
+    Scasb => "scasb",
+    Seta => "seta",
+    Setae => "setae",
+    Setb => "setb",
+    Setbe => "setbe",
+    Sete => "sete",
+    Setg => "setg",
+    Setge => "setge",
+    Setl => "setl",
+    Setle => "setle",
+    Setne => "setne",
+    Setno => "setno",
+    Setnp => "setnp",
+    Setns => "setns",
+    Seto => "seto",
+    Setp => "setp",
+    Sets => "sets",
+    Sfence => "sfence",
+    Sgdt => "sgdt",
+    Sha1msg1 => "sha1msg1",
+    Sha1msg2 => "sha1msg2",
+    Sha1nexte => "sha1nexte",
+    Sha1rnds4 => "sha1rnds4",
+    Sha256msg1 => "sha256msg1",
+    Sha256msg2 => "sha256msg2",
+    Sha256rnds2 => "sha256rnds2",
+    Shl => "shl",
+    Shld => "shld",
+    Shlx => "shlx",
+    Shr => "shr",
+    Shrd => "shrd",
+    Shrx => "shrx",
+    Shufpd => "shufpd",
+    Shufps => "shufps",
+    Sidt => "sidt",
+    Sldt => "sldt",
+    Smsw => "smsw",
+    Sqrtpd => "sqrtpd",
+    Sqrtps => "sqrtps",
+    Sqrtsd => "sqrtsd",
+    Sqrtss => "sqrtss",
+    Stac => "stac",
+    Stc => "stc",
+    Std => "std",
+    Sti => "sti",
+    Stmxcsr => "stmxcsr",
+    Stos => "stos",
+    Stosb => "stosb",
+    Str => "str",
+    Sub => "sub",
+    Subpd => "subpd",
+    Subps => "subps",
+    Subsd => "subsd",
+    Subss => "subss",
+    Swapgs => "swapgs",
+    Syscall => "syscall",
+    Sysenter => "sysenter",
+    Sysexit => "sysexit",
+    Sysret => "sysret",
+    Test => "test",
+    Tzcnt => "tzcnt",
+    Ucomisd => "ucomisd",
+    Ucomiss => "ucomiss",
+    Ud0 => "ud0",
+    Ud1 => "ud1",
+    Ud2 => "ud2",
+    Unpckhpd => "unpckhpd",
+    Unpckhps => "unpckhps",
+    Unpcklpd => "unpcklpd",
+    Unpcklps => "unpcklps",
+    Vaddpd => "vaddpd",
+    Vaddps => "vaddps",
+    Vaddsd => "vaddsd",
+    Vaddss => "vaddss",
+    Vaddsubpd => "vaddsubpd",
+    Vaddsubps => "vaddsubps",
+    Vaesdec => "vaesdec",
+    Vaesdeclast => "vaesdeclast",
+    Vaesenc => "vaesenc",
+    Vaesenclast => "vaesenclast",
+    Vaesimc => "vaesimc",
+    Vaeskeygenassist => "vaeskeygenassist",
+    Valignd => "valignd",
+    Valignq => "valignq",
+    Vandnpd => "vandnpd",
+    Vandnps => "vandnps",
+    Vandpd => "vandpd",
+    Vandps => "vandps",
+    Vblendmpd => "vblendmpd",
+    Vblendmps => "vblendmps",
+    Vblendpd => "vblendpd",
+    Vblendps => "vblendps",
+    Vblendvpd => "vblendvpd",
+    Vblendvps => "vblendvps",
+    Vbroadcastf128 => "vbroadcastf128",
+    Vbroadcastf32x2 => "vbroadcastf32x2",
+    Vbroadcastf32x4 => "vbroadcastf32x4",
+    Vbroadcastf32x8 => "vbroadcastf32x8",
+    Vbroadcastf64x2 => "vbroadcastf64x2",
+    Vbroadcastf64x4 => "vbroadcastf64x4",
+    Vbroadcasti128 => "vbroadcasti128",
+    Vbroadcasti32x2 => "vbroadcasti32x2",
+    Vbroadcasti32x4 => "vbroadcasti32x4",
+    Vbroadcasti32x8 => "vbroadcasti32x8",
+    Vbroadcasti64x2 => "vbroadcasti64x2",
+    Vbroadcasti64x4 => "vbroadcasti64x4",
+    Vbroadcastsd => "vbroadcastsd",
+    Vbroadcastss => "vbroadcastss",
+    Vcmppd => "vcmppd",
+    Vcmpps => "vcmpps",
+    Vcmpsd => "vcmpsd",
+    Vcmpss => "vcmpss",
+    Vcomisd => "vcomisd",
+    Vcomiss => "vcomiss",
+    Vcompresspd => "vcompresspd",
+    Vcompressps => "vcompressps",
+    Vcvtdq2pd => "vcvtdq2pd",
+    Vcvtdq2ps => "vcvtdq2ps",
+    Vcvtpd2dq => "vcvtpd2dq",
+    Vcvtpd2ps => "vcvtpd2ps",
+    Vcvtpd2qq => "vcvtpd2qq",
+    Vcvtpd2udq => "vcvtpd2udq",
+    Vcvtpd2uqq => "vcvtpd2uqq",
+    Vcvtph2ps => "vcvtph2ps",
+    Vcvtps2dq => "vcvtps2dq",
+    Vcvtps2pd => "vcvtps2pd",
+    Vcvtps2ph => "vcvtps2ph",
+    Vcvtps2qq => "vcvtps2qq",
+    Vcvtps2udq => "vcvtps2udq",
+    Vcvtps2uqq => "vcvtps2uqq",
+    Vcvtqq2pd => "vcvtqq2pd",
+    Vcvtqq2ps => "vcvtqq2ps",
+    Vcvtsd2si => "vcvtsd2si",
+    Vcvtsd2ss => "vcvtsd2ss",
+    Vcvtsd2usi => "vcvtsd2usi",
+    Vcvtsi2sd => "vcvtsi2sd",
+    Vcvtsi2ss => "vcvtsi2ss",
+    Vcvtss2sd => "vcvtss2sd",
+    Vcvtss2si => "vcvtss2si",
+    Vcvtss2usi => "vcvtss2usi",
+    Vcvttpd2dq => "vcvttpd2dq",
+    Vcvttpd2qq => "vcvttpd2qq",
+    Vcvttpd2udq => "vcvttpd2udq",
+    Vcvttpd2uqq => "vcvttpd2uqq",
+    Vcvttps2dq => "vcvttps2dq",
+    Vcvttps2qq => "vcvttps2qq",
+    Vcvttps2udq => "vcvttps2udq",
+    Vcvttps2uqq => "vcvttps2uqq",
+    Vcvttsd2si => "vcvttsd2si",
+    Vcvttsd2usi => "vcvttsd2usi",
+    Vcvttss2si => "vcvttss2si",
+    Vcvttss2usi => "vcvttss2usi",
+    Vcvtudq2pd => "vcvtudq2pd",
+    Vcvtudq2ps => "vcvtudq2ps",
+    Vcvtuqq2pd => "vcvtuqq2pd",
+    Vcvtuqq2ps => "vcvtuqq2ps",
+    Vcvtusi2sd => "vcvtusi2sd",
+    Vcvtusi2ss => "vcvtusi2ss",
+    Vdbpsadbw => "vdbpsadbw",
+    Vdivpd => "vdivpd",
+    Vdivps => "vdivps",
+    Vdivsd => "vdivsd",
+    Vdivss => "vdivss",
+    Vdppd => "vdppd",
+    Vdpps => "vdpps",
+    Verr => "verr",
+    Verw => "verw",
+    Vexpandpd => "vexpandpd",
+    Vexpandps => "vexpandps",
+    Vextractf128 => "vextractf128",
+    Vextractf32x4 => "vextractf32x4",
+    Vextractf32x8 => "vextractf32x8",
+    Vextractf64x2 => "vextractf64x2",
+    Vextractf64x4 => "vextractf64x4",
+    Vextracti128 => "vextracti128",
+    Vextracti32x4 => "vextracti32x4",
+    Vextracti32x8 => "vextracti32x8",
+    Vextracti64x2 => "vextracti64x2",
+    Vextracti64x4 => "vextracti64x4",
+    Vextractps => "vextractps",
+    Vfixupimmpd => "vfixupimmpd",
+    Vfixupimmps => "vfixupimmps",
+    Vfixupimmsd => "vfixupimmsd",
+    Vfixupimmss => "vfixupimmss",
+    Vfmadd132pd => "vfmadd132pd",
+    Vfmadd132ps => "vfmadd132ps",
+    Vfmadd132sd => "vfmadd132sd",
+    Vfmadd132ss => "vfmadd132ss",
+    Vfmadd213pd => "vfmadd213pd",
+    Vfmadd213ps => "vfmadd213ps",
+    Vfmadd213sd => "vfmadd213sd",
+    Vfmadd213ss => "vfmadd213ss",
+    Vfmadd231pd => "vfmadd231pd",
+    Vfmadd231ps => "vfmadd231ps",
+    Vfmadd231sd => "vfmadd231sd",
+    Vfmadd231ss => "vfmadd231ss",
+    Vfmaddsub132pd => "vfmaddsub132pd",
+    Vfmaddsub132ps => "vfmaddsub132ps",
+    Vfmaddsub213pd => "vfmaddsub213pd",
+    Vfmaddsub213ps => "vfmaddsub213ps",
+    Vfmaddsub231pd => "vfmaddsub231pd",
+    Vfmaddsub231ps => "vfmaddsub231ps",
+    Vfmsub132pd => "vfmsub132pd",
+    Vfmsub132ps => "vfmsub132ps",
+    Vfmsub132sd => "vfmsub132sd",
+    Vfmsub132ss => "vfmsub132ss",
+    Vfmsub213pd => "vfmsub213pd",
+    Vfmsub213ps => "vfmsub213ps",
+    Vfmsub213sd => "vfmsub213sd",
+    Vfmsub213ss => "vfmsub213ss",
+    Vfmsub231pd => "vfmsub231pd",
+    Vfmsub231ps => "vfmsub231ps",
+    Vfmsub231sd => "vfmsub231sd",
+    Vfmsub231ss => "vfmsub231ss",
+    Vfmsubadd132pd => "vfmsubadd132pd",
+    Vfmsubadd132ps => "vfmsubadd132ps",
+    Vfmsubadd213pd => "vfmsubadd213pd",
+    Vfmsubadd213ps => "vfmsubadd213ps",
+    Vfmsubadd231pd => "vfmsubadd231pd",
+    Vfmsubadd231ps => "vfmsubadd231ps",
+    Vfnmadd132pd => "vfnmadd132pd",
+    Vfnmadd132ps => "vfnmadd132ps",
+    Vfnmadd132sd => "vfnmadd132sd",
+    Vfnmadd132ss => "vfnmadd132ss",
+    Vfnmadd213pd => "vfnmadd213pd",
+    Vfnmadd213ps => "vfnmadd213ps",
+    Vfnmadd213sd => "vfnmadd213sd",
+    Vfnmadd213ss => "vfnmadd213ss",
+    Vfnmadd231pd => "vfnmadd231pd",
+    Vfnmadd231ps => "vfnmadd231ps",
+    Vfnmadd231sd => "vfnmadd231sd",
+    Vfnmadd231ss => "vfnmadd231ss",
+    Vfnmsub132pd => "vfnmsub132pd",
+    Vfnmsub132ps => "vfnmsub132ps",
+    Vfnmsub132sd => "vfnmsub132sd",
+    Vfnmsub132ss => "vfnmsub132ss",
+    Vfnmsub213pd => "vfnmsub213pd",
+    Vfnmsub213ps => "vfnmsub213ps",
+    Vfnmsub213sd => "vfnmsub213sd",
+    Vfnmsub213ss => "vfnmsub213ss",
+    Vfnmsub231pd => "vfnmsub231pd",
+    Vfnmsub231ps => "vfnmsub231ps",
+    Vfnmsub231sd => "vfnmsub231sd",
+    Vfnmsub231ss => "vfnmsub231ss",
+    Vfpclasspd => "vfpclasspd",
+    Vfpclassps => "vfpclassps",
+    Vfpclasssd => "vfpclasssd",
+    Vfpclassss => "vfpclassss",
+    Vgatherdpd => "vgatherdpd",
+    Vgatherdps => "vgatherdps",
+    Vgatherqpd => "vgatherqpd",
+    Vgatherqps => "vgatherqps",
+    Vgetexppd => "vgetexppd",
+    Vgetexpps => "vgetexpps",
+    Vgetexpsd => "vgetexpsd",
+    Vgetexpss => "vgetexpss",
+    Vgetmantpd => "vgetmantpd",
+    Vgetmantps => "vgetmantps",
+    Vgetmantsd => "vgetmantsd",
+    Vgetmantss => "vgetmantss",
+    Vhaddpd => "vhaddpd",
+    Vhaddps => "vhaddps",
+    Vhsubpd => "vhsubpd",
+    Vhsubps => "vhsubps",
+    Vinsertf128 => "vinsertf128",
+    Vinsertf32x4 => "vinsertf32x4",
+    Vinsertf32x8 => "vinsertf32x8",
+    Vinsertf64x2 => "vinsertf64x2",
+    Vinsertf64x4 => "vinsertf64x4",
+    Vinserti128 => "vinserti128",
+    Vinserti32x4 => "vinserti32x4",
+    Vinserti32x8 => "vinserti32x8",
+    Vinserti64x2 => "vinserti64x2",
+    Vinserti64x4 => "vinserti64x4",
+    Vinsertps => "vinsertps",
+    Vlddqu => "vlddqu",
+    Vldmxcsr => "vldmxcsr",
+    Vmaskmovdqu => "vmaskmovdqu",
+    Vmaskmovpd => "vmaskmovpd",
+    Vmaskmovps => "vmaskmovps",
+    Vmaxpd => "vmaxpd",
+    Vmaxps => "vmaxps",
+    Vmaxsd => "vmaxsd",
+    Vmaxss => "vmaxss",
+    Vmcall => "vmcall",
+    Vmclear => "vmclear",
+    Vmfunc => "vmfunc",
+    Vminpd => "vminpd",
+    Vminps => "vminps",
+    Vminsd => "vminsd",
+    Vminss => "vminss",
+    Vmlaunch => "vmlaunch",
+    Vmovapd => "vmovapd",
+    Vmovaps => "vmovaps",
+    Vmovd => "vmovd",
+    Vmovddup => "vmovddup",
+    Vmovdqa => "vmovdqa",
+    Vmovdqa32 => "vmovdqa32",
+    Vmovdqa64 => "vmovdqa64",
+    Vmovdqu => "vmovdqu",
+    Vmovdqu16 => "vmovdqu16",
+    Vmovdqu32 => "vmovdqu32",
+    Vmovdqu64 => "vmovdqu64",
+    Vmovdqu8 => "vmovdqu8",
+    Vmovhlps => "vmovhlps",
+    Vmovhpd => "vmovhpd",
+    Vmovhps => "vmovhps",
+    Vmovlhps => "vmovlhps",
+    Vmovlpd => "vmovlpd",
+    Vmovlps => "vmovlps",
+    Vmovmskpd => "vmovmskpd",
+    Vmovmskps => "vmovmskps",
+    Vmovntdq => "vmovntdq",
+    Vmovntdqa => "vmovntdqa",
+    Vmovntpd => "vmovntpd",
+    Vmovntps => "vmovntps",
+    Vmovq => "vmovq",
+    Vmovsd => "vmovsd",
+    Vmovshdup => "vmovshdup",
+    Vmovsldup => "vmovsldup",
+    Vmovss => "vmovss",
+    Vmovupd => "vmovupd",
+    Vmovups => "vmovups",
+    Vmpsadbw => "vmpsadbw",
+    Vmptrld => "vmptrld",
+    Vmptrst => "vmptrst",
+    Vmread => "vmread",
+    Vmresume => "vmresume",
+    Vmulpd => "vmulpd",
+    Vmulps => "vmulps",
+    Vmulsd => "vmulsd",
+    Vmulss => "vmulss",
+    Vmwrite => "vmwrite",
+    Vmxoff => "vmxoff",
+    Vmxon => "vmxon",
+    Vorpd => "vorpd",
+    Vorps => "vorps",
+    Vpabsb => "vpabsb",
+    Vpabsd => "vpabsd",
+    Vpabsq => "vpabsq",
+    Vpabsw => "vpabsw",
+    Vpackssdw => "vpackssdw",
+    Vpacksswb => "vpacksswb",
+    Vpackusdw => "vpackusdw",
+    Vpackuswb => "vpackuswb",
+    Vpaddb => "vpaddb",
+    Vpaddd => "vpaddd",
+    Vpaddq => "vpaddq",
+    Vpaddsb => "vpaddsb",
+    Vpaddsw => "vpaddsw",
+    Vpaddusb => "vpaddusb",
+    Vpaddusw => "vpaddusw",
+    Vpaddw => "vpaddw",
+    Vpalignr => "vpalignr",
+    Vpand => "vpand",
+    Vpandd => "vpandd",
+    Vpandn => "vpandn",
+    Vpandnd => "vpandnd",
+    Vpandnq => "vpandnq",
+    Vpandq => "vpandq",
+    Vpavgb => "vpavgb",
+    Vpavgw => "vpavgw",
+    Vpblendd => "vpblendd",
+    Vpblendmb => "vpblendmb",
+    Vpblendmd => "vpblendmd",
+    Vpblendmq => "vpblendmq",
+    Vpblendmw => "vpblendmw",
+    Vpblendvb => "vpblendvb",
+    Vpblendw => "vpblendw",
+    Vpbroadcastb => "vpbroadcastb",
+    Vpbroadcastd => "vpbroadcastd",
+    Vpbroadcastmb2q => "vpbroadcastmb2q",
+    Vpbroadcastmw2d => "vpbroadcastmw2d",
+    Vpbroadcastq => "vpbroadcastq",
+    Vpbroadcastw => "vpbroadcastw",
+    Vpclmulqdq => "vpclmulqdq",
+    Vpcmpb => "vpcmpb",
+    Vpcmpd => "vpcmpd",
+    Vpcmpeqb => "vpcmpeqb",
+    Vpcmpeqd => "vpcmpeqd",
+    Vpcmpeqq => "vpcmpeqq",
+    Vpcmpeqw => "vpcmpeqw",
+    Vpcmpestri => "vpcmpestri",
+    Vpcmpestrm => "vpcmpestrm",
+    Vpcmpgtb => "vpcmpgtb",
+    Vpcmpgtd => "vpcmpgtd",
+    Vpcmpgtq => "vpcmpgtq",
+    Vpcmpgtw => "vpcmpgtw",
+    Vpcmpistri => "vpcmpistri",
+    Vpcmpistrm => "vpcmpistrm",
+    Vpcmpq => "vpcmpq",
+    Vpcmpub => "vpcmpub",
+    Vpcmpud => "vpcmpud",
+    Vpcmpuq => "vpcmpuq",
+    Vpcmpuw => "vpcmpuw",
+    Vpcmpw => "vpcmpw",
+    Vpcompressd => "vpcompressd",
+    Vpcompressq => "vpcompressq",
+    Vpconflictd => "vpconflictd",
+    Vpconflictq => "vpconflictq",
+    Vperm2f128 => "vperm2f128",
+    Vperm2i128 => "vperm2i128",
+    Vpermb => "vpermb",
+    Vpermd => "vpermd",
+    Vpermi2b => "vpermi2b",
+    Vpermi2d => "vpermi2d",
+    Vpermi2pd => "vpermi2pd",
+    Vpermi2ps => "vpermi2ps",
+    Vpermi2q => "vpermi2q",
+    Vpermi2w => "vpermi2w",
+    Vpermilpd => "vpermilpd",
+    Vpermilps => "vpermilps",
+    Vpermpd => "vpermpd",
+    Vpermps => "vpermps",
+    Vpermq => "vpermq",
+    Vpermt2b => "vpermt2b",
+    Vpermt2d => "vpermt2d",
+    Vpermt2pd => "vpermt2pd",
+    Vpermt2ps => "vpermt2ps",
+    Vpermt2q => "vpermt2q",
+    Vpermt2w => "vpermt2w",
+    Vpermw => "vpermw",
+    Vpexpandd => "vpexpandd",
+    Vpexpandq => "vpexpandq",
+    Vpextrb => "vpextrb",
+    Vpextrd => "vpextrd",
+    Vpextrq => "vpextrq",
+    Vpextrw => "vpextrw",
+    Vpgatherdd => "vpgatherdd",
+    Vpgatherdq => "vpgatherdq",
+    Vpgatherqd => "vpgatherqd",
+    Vpgatherqq => "vpgatherqq",
+    Vphaddd => "vphaddd",
+    Vphaddsw => "vphaddsw",
+    Vphaddw => "vphaddw",
+    Vphminposuw => "vphminposuw",
+    Vphsubd => "vphsubd",
+    Vphsubsw => "vphsubsw",
+    Vphsubw => "vphsubw",
+    Vpinsrb => "vpinsrb",
+    Vpinsrd => "vpinsrd",
+    Vpinsrq => "vpinsrq",
+    Vpinsrw => "vpinsrw",
+    Vplzcntd => "vplzcntd",
+    Vplzcntq => "vplzcntq",
+    Vpmaddubsw => "vpmaddubsw",
+    Vpmaddwd => "vpmaddwd",
+    Vpmaskmovd => "vpmaskmovd",
+    Vpmaskmovq => "vpmaskmovq",
+    Vpmaxsb => "vpmaxsb",
+    Vpmaxsd => "vpmaxsd",
+    Vpmaxsq => "vpmaxsq",
+    Vpmaxsw => "vpmaxsw",
+    Vpmaxub => "vpmaxub",
+    Vpmaxud => "vpmaxud",
+    Vpmaxuq => "vpmaxuq",
+    Vpmaxuw => "vpmaxuw",
+    Vpminsb => "vpminsb",
+    Vpminsd => "vpminsd",
+    Vpminsq => "vpminsq",
+    Vpminsw => "vpminsw",
+    Vpminub => "vpminub",
+    Vpminud => "vpminud",
+    Vpminuq => "vpminuq",
+    Vpminuw => "vpminuw",
+    Vpmovb2m => "vpmovb2m",
+    Vpmovd2m => "vpmovd2m",
+    Vpmovdb => "vpmovdb",
+    Vpmovdw => "vpmovdw",
+    Vpmovm2b => "vpmovm2b",
+    Vpmovm2d => "vpmovm2d",
+    Vpmovm2q => "vpmovm2q",
+    Vpmovm2w => "vpmovm2w",
+    Vpmovmskb => "vpmovmskb",
+    Vpmovq2m => "vpmovq2m",
+    Vpmovqb => "vpmovqb",
+    Vpmovqd => "vpmovqd",
+    Vpmovqw => "vpmovqw",
+    Vpmovsdb => "vpmovsdb",
+    Vpmovsdw => "vpmovsdw",
+    Vpmovsqb => "vpmovsqb",
+    Vpmovsqd => "vpmovsqd",
+    Vpmovsqw => "vpmovsqw",
+    Vpmovswb => "vpmovswb",
+    Vpmovsxbd => "vpmovsxbd",
+    Vpmovsxbq => "vpmovsxbq",
+    Vpmovsxbw => "vpmovsxbw",
+    Vpmovsxdq => "vpmovsxdq",
+    Vpmovsxwd => "vpmovsxwd",
+    Vpmovsxwq => "vpmovsxwq",
+    Vpmovusdb => "vpmovusdb",
+    Vpmovusdw => "vpmovusdw",
+    Vpmovusqb => "vpmovusqb",
+    Vpmovusqd => "vpmovusqd",
+    Vpmovusqw => "vpmovusqw",
+    Vpmovuswb => "vpmovuswb",
+    Vpmovw2m => "vpmovw2m",
+    Vpmovwb => "vpmovwb",
+    Vpmovzxbd => "vpmovzxbd",
+    Vpmovzxbq => "vpmovzxbq",
+    Vpmovzxbw => "vpmovzxbw",
+    Vpmovzxdq => "vpmovzxdq",
+    Vpmovzxwd => "vpmovzxwd",
+    Vpmovzxwq => "vpmovzxwq",
+    Vpmuldq => "vpmuldq",
+    Vpmulhrsw => "vpmulhrsw",
+    Vpmulhuw => "vpmulhuw",
+    Vpmulhw => "vpmulhw",
+    Vpmulld => "vpmulld",
+    Vpmullq => "vpmullq",
+    Vpmullw => "vpmullw",
+    Vpmultishiftqb => "vpmultishiftqb",
+    Vpmuludq => "vpmuludq",
+    Vpor => "vpor",
+    Vpord => "vpord",
+    Vporq => "vporq",
+    Vprold => "vprold",
+    Vprolq => "vprolq",
+    Vprolvd => "vprolvd",
+    Vprolvq => "vprolvq",
+    Vprord => "vprord",
+    Vprorq => "vprorq",
+    Vprorvd => "vprorvd",
+    Vprorvq => "vprorvq",
+    Vpsadbw => "vpsadbw",
+    Vpscatterdd => "vpscatterdd",
+    Vpscatterdq => "vpscatterdq",
+    Vpscatterqd => "vpscatterqd",
+    Vpscatterqq => "vpscatterqq",
+    Vpshufb => "vpshufb",
+    Vpshufd => "vpshufd",
+    Vpshufhw => "vpshufhw",
+    Vpshuflw => "vpshuflw",
+    Vpsignb => "vpsignb",
+    Vpsignd => "vpsignd",
+    Vpsignw => "vpsignw",
+    Vpslld => "vpslld",
+    Vpslldq => "vpslldq",
+    Vpsllq => "vpsllq",
+    Vpsllvd => "vpsllvd",
+    Vpsllvq => "vpsllvq",
+    Vpsllvw => "vpsllvw",
+    Vpsllw => "vpsllw",
+    Vpsrad => "vpsrad",
+    Vpsraq => "vpsraq",
+    Vpsravd => "vpsravd",
+    Vpsravq => "vpsravq",
+    Vpsravw => "vpsravw",
+    Vpsraw => "vpsraw",
+    Vpsrld => "vpsrld",
+    Vpsrldq => "vpsrldq",
+    Vpsrlq => "vpsrlq",
+    Vpsrlvd => "vpsrlvd",
+    Vpsrlvq => "vpsrlvq",
+    Vpsrlvw => "vpsrlvw",
+    Vpsrlw => "vpsrlw",
+    Vpsubb => "vpsubb",
+    Vpsubd => "vpsubd",
+    Vpsubq => "vpsubq",
+    Vpsubsb => "vpsubsb",
+    Vpsubsw => "vpsubsw",
+    Vpsubusb => "vpsubusb",
+    Vpsubusw => "vpsubusw",
+    Vpsubw => "vpsubw",
+    Vpternlogd => "vpternlogd",
+    Vpternlogq => "vpternlogq",
+    Vptest => "vptest",
+    Vptestmb => "vptestmb",
+    Vptestmd => "vptestmd",
+    Vptestmq => "vptestmq",
+    Vptestmw => "vptestmw",
+    Vptestnmb => "vptestnmb",
+    Vptestnmd => "vptestnmd",
+    Vptestnmq => "vptestnmq",
+    Vptestnmw => "vptestnmw",
+    Vpunpckhbw => "vpunpckhbw",
+    Vpunpckhdq => "vpunpckhdq",
+    Vpunpckhqdq => "vpunpckhqdq",
+    Vpunpckhwd => "vpunpckhwd",
+    Vpunpcklbw => "vpunpcklbw",
+    Vpunpckldq => "vpunpckldq",
+    Vpunpcklqdq => "vpunpcklqdq",
+    Vpunpcklwd => "vpunpcklwd",
+    Vpxor => "vpxor",
+    Vpxord => "vpxord",
+    Vpxorq => "vpxorq",
+    Vrangepd => "vrangepd",
+    Vrangeps => "vrangeps",
+    Vrangesd => "vrangesd",
+    Vrangess => "vrangess",
+    Vrcp14pd => "vrcp14pd",
+    Vrcp14ps => "vrcp14ps",
+    Vrcp14sd => "vrcp14sd",
+    Vrcp14ss => "vrcp14ss",
+    Vrcpps => "vrcpps",
+    Vrcpss => "vrcpss",
+    Vreducepd => "vreducepd",
+    Vreduceps => "vreduceps",
+    Vreducesd => "vreducesd",
+    Vreducess => "vreducess",
+    Vrndscalepd => "vrndscalepd",
+    Vrndscaleps => "vrndscaleps",
+    Vrndscalesd => "vrndscalesd",
+    Vrndscaless => "vrndscaless",
+    Vroundpd => "vroundpd",
+    Vroundps => "vroundps",
+    Vroundsd => "vroundsd",
+    Vroundss => "vroundss",
+    Vrsqrt14pd => "vrsqrt14pd",
+    Vrsqrt14ps => "vrsqrt14ps",
+    Vrsqrt14sd => "vrsqrt14sd",
+    Vrsqrt14ss => "vrsqrt14ss",
+    Vrsqrtps => "vrsqrtps",
+    Vrsqrtss => "vrsqrtss",
+    Vscalefpd => "vscalefpd",
+    Vscalefps => "vscalefps",
+    Vscalefsd => "vscalefsd",
+    Vscalefss => "vscalefss",
+    Vscatterdpd => "vscatterdpd",
+    Vscatterdps => "vscatterdps",
+    Vscatterqpd => "vscatterqpd",
+    Vscatterqps => "vscatterqps",
+    Vshuff32x4 => "vshuff32x4",
+    Vshuff64x2 => "vshuff64x2",
+    Vshufi32x4 => "vshufi32x4",
+    Vshufi64x2 => "vshufi64x2",
+    Vshufpd => "vshufpd",
+    Vshufps => "vshufps",
+    Vsqrtpd => "vsqrtpd",
+    Vsqrtps => "vsqrtps",
+    Vsqrtsd => "vsqrtsd",
+    Vsqrtss => "vsqrtss",
+    Vstmxcsr => "vstmxcsr",
+    Vsubpd => "vsubpd",
+    Vsubps => "vsubps",
+    Vsubsd => "vsubsd",
+    Vsubss => "vsubss",
+    Vtestpd => "vtestpd",
+    Vtestps => "vtestps",
+    Vucomisd => "vucomisd",
+    Vucomiss => "vucomiss",
+    Vunpckhpd => "vunpckhpd",
+    Vunpckhps => "vunpckhps",
+    Vunpcklpd => "vunpcklpd",
+    Vunpcklps => "vunpcklps",
+    Vxorpd => "vxorpd",
+    Vxorps => "vxorps",
+    Vzeroall => "vzeroall",
+    Vzeroupper => "vzeroupper",
+    Wait => "wait",
+    Wbinvd => "wbinvd",
+    Wrfsbase => "wrfsbase",
+    Wrgsbase => "wrgsbase",
+    Wrmsr => "wrmsr",
+    Wrpkru => "wrpkru",
+    Xabort => "xabort",
+    Xadd => "xadd",
+    Xbegin => "xbegin",
+    Xchg => "xchg",
+    Xend => "xend",
+    Xgetbv => "xgetbv",
+    Xlatb => "xlatb",
+    Xor => "xor",
+    Xorpd => "xorpd",
+    Xorps => "xorps",
+    Xrstor => "xrstor",
+    Xrstor64 => "xrstor64",
+    Xrstors => "xrstors",
+    Xrstors64 => "xrstors64",
+    Xsave => "xsave",
+    Xsave64 => "xsave64",
+    Xsavec => "xsavec",
+    Xsavec64 => "xsavec64",
+    Xsaveopt => "xsaveopt",
+    Xsaveopt64 => "xsaveopt64",
+    Xsaves => "xsaves",
+    Xsaves64 => "xsaves64",
+    Xsetbv => "xsetbv",
+    Xtest => "xtest",
+}
+
+
+/// Declares the AVX spelling of the legacy SSE mnemonics that are also VEX / EVEX encodable
+macro_rules! vex_forms {
+    ($($legacy:ident => $vex:ident),* $(,)?) => {
+        impl Mnemonic {
+            /// The mnemonic an SSE instruction takes when it is VEX or EVEX encoded, `addps`
+            /// becomes `vaddps`. Mnemonics without a legacy form are returned unchanged
+            pub fn vex_form(self) -> Mnemonic {
+                match self {
+                    $(Mnemonic::$legacy => Mnemonic::$vex,)*
+                    other => other,
+                }
+            }
+
+            /// The legacy SSE form of an AVX mnemonic, if it has one
+            pub fn legacy_form(self) -> Option<Mnemonic> {
+                match self {
+                    $(Mnemonic::$vex => Some(Mnemonic::$legacy),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+vex_forms! {
+    Addpd => Vaddpd,
+    Addps => Vaddps,
+    Addsd => Vaddsd,
+    Addss => Vaddss,
+    Addsubpd => Vaddsubpd,
+    Addsubps => Vaddsubps,
+    Aesdec => Vaesdec,
+    Aesdeclast => Vaesdeclast,
+    Aesenc => Vaesenc,
+    Aesenclast => Vaesenclast,
+    Aesimc => Vaesimc,
+    Aeskeygenassist => Vaeskeygenassist,
+    Andnpd => Vandnpd,
+    Andnps => Vandnps,
+    Andpd => Vandpd,
+    Andps => Vandps,
+    Blendpd => Vblendpd,
+    Blendps => Vblendps,
+    Cmppd => Vcmppd,
+    Cmpps => Vcmpps,
+    Cmpsd => Vcmpsd,
+    Cmpss => Vcmpss,
+    Comisd => Vcomisd,
+    Comiss => Vcomiss,
+    Cvtdq2pd => Vcvtdq2pd,
+    Cvtdq2ps => Vcvtdq2ps,
+    Cvtpd2dq => Vcvtpd2dq,
+    Cvtpd2ps => Vcvtpd2ps,
+    Cvtps2dq => Vcvtps2dq,
+    Cvtps2pd => Vcvtps2pd,
+    Cvtsd2si => Vcvtsd2si,
+    Cvtsd2ss => Vcvtsd2ss,
+    Cvtsi2sd => Vcvtsi2sd,
+    Cvtsi2ss => Vcvtsi2ss,
+    Cvtss2sd => Vcvtss2sd,
+    Cvtss2si => Vcvtss2si,
+    Cvttpd2dq => Vcvttpd2dq,
+    Cvttps2dq => Vcvttps2dq,
+    Cvttsd2si => Vcvttsd2si,
+    Cvttss2si => Vcvttss2si,
+    Divpd => Vdivpd,
+    Divps => Vdivps,
+    Divsd => Vdivsd,
+    Divss => Vdivss,
+    Dppd => Vdppd,
+    Dpps => Vdpps,
+    Extractps => Vextractps,
+    Haddpd => Vhaddpd,
+    Haddps => Vhaddps,
+    Hsubpd => Vhsubpd,
+    Hsubps => Vhsubps,
+    Insertps => Vinsertps,
+    Lddqu => Vlddqu,
+    Ldmxcsr => Vldmxcsr,
+    Maskmovdqu => Vmaskmovdqu,
+    Maxpd => Vmaxpd,
+    Maxps => Vmaxps,
+    Maxsd => Vmaxsd,
+    Maxss => Vmaxss,
+    Minpd => Vminpd,
+    Minps => Vminps,
+    Minsd => Vminsd,
+    Minss => Vminss,
+    Movapd => Vmovapd,
+    Movaps => Vmovaps,
+    Movd => Vmovd,
+    Movddup => Vmovddup,
+    Movdqa => Vmovdqa,
+    Movdqu => Vmovdqu,
+    Movhlps => Vmovhlps,
+    Movhpd => Vmovhpd,
+    Movhps => Vmovhps,
+    Movlhps => Vmovlhps,
+    Movlpd => Vmovlpd,
+    Movlps => Vmovlps,
+    Movmskpd => Vmovmskpd,
+    Movmskps => Vmovmskps,
+    Movntdq => Vmovntdq,
+    Movntdqa => Vmovntdqa,
+    Movntpd => Vmovntpd,
+    Movntps => Vmovntps,
+    Movq => Vmovq,
+    Movsd => Vmovsd,
+    Movshdup => Vmovshdup,
+    Movsldup => Vmovsldup,
+    Movss => Vmovss,
+    Movupd => Vmovupd,
+    Movups => Vmovups,
+    Mpsadbw => Vmpsadbw,
+    Mulpd => Vmulpd,
+    Mulps => Vmulps,
+    Mulsd => Vmulsd,
+    Mulss => Vmulss,
+    Orpd => Vorpd,
+    Orps => Vorps,
+    Pabsb => Vpabsb,
+    Pabsd => Vpabsd,
+    Pabsw => Vpabsw,
+    Packssdw => Vpackssdw,
+    Packsswb => Vpacksswb,
+    Packusdw => Vpackusdw,
+    Packuswb => Vpackuswb,
+    Paddb => Vpaddb,
+    Paddd => Vpaddd,
+    Paddq => Vpaddq,
+    Paddsb => Vpaddsb,
+    Paddsw => Vpaddsw,
+    Paddusb => Vpaddusb,
+    Paddusw => Vpaddusw,
+    Paddw => Vpaddw,
+    Palignr => Vpalignr,
+    Pand => Vpand,
+    Pandn => Vpandn,
+    Pavgb => Vpavgb,
+    Pavgw => Vpavgw,
+    Pblendw => Vpblendw,
+    Pclmulqdq => Vpclmulqdq,
+    Pcmpeqb => Vpcmpeqb,
+    Pcmpeqd => Vpcmpeqd,
+    Pcmpeqq => Vpcmpeqq,
+    Pcmpeqw => Vpcmpeqw,
+    Pcmpestri => Vpcmpestri,
+    Pcmpestrm => Vpcmpestrm,
+    Pcmpgtb => Vpcmpgtb,
+    Pcmpgtd => Vpcmpgtd,
+    Pcmpgtq => Vpcmpgtq,
+    Pcmpgtw => Vpcmpgtw,
+    Pcmpistri => Vpcmpistri,
+    Pcmpistrm => Vpcmpistrm,
+    Pextrb => Vpextrb,
+    Pextrd => Vpextrd,
+    Pextrq => Vpextrq,
+    Pextrw => Vpextrw,
+    Phaddd => Vphaddd,
+    Phaddsw => Vphaddsw,
+    Phaddw => Vphaddw,
+    Phminposuw => Vphminposuw,
+    Phsubd => Vphsubd,
+    Phsubsw => Vphsubsw,
+    Phsubw => Vphsubw,
+    Pinsrb => Vpinsrb,
+    Pinsrd => Vpinsrd,
+    Pinsrq => Vpinsrq,
+    Pinsrw => Vpinsrw,
+    Pmaddubsw => Vpmaddubsw,
+    Pmaddwd => Vpmaddwd,
+    Pmaxsb => Vpmaxsb,
+    Pmaxsd => Vpmaxsd,
+    Pmaxsw => Vpmaxsw,
+    Pmaxub => Vpmaxub,
+    Pmaxud => Vpmaxud,
+    Pmaxuw => Vpmaxuw,
+    Pminsb => Vpminsb,
+    Pminsd => Vpminsd,
+    Pminsw => Vpminsw,
+    Pminub => Vpminub,
+    Pminud => Vpminud,
+    Pminuw => Vpminuw,
+    Pmovmskb => Vpmovmskb,
+    Pmovsxbd => Vpmovsxbd,
+    Pmovsxbq => Vpmovsxbq,
+    Pmovsxbw => Vpmovsxbw,
+    Pmovsxdq => Vpmovsxdq,
+    Pmovsxwd => Vpmovsxwd,
+    Pmovsxwq => Vpmovsxwq,
+    Pmovzxbd => Vpmovzxbd,
+    Pmovzxbq => Vpmovzxbq,
+    Pmovzxbw => Vpmovzxbw,
+    Pmovzxdq => Vpmovzxdq,
+    Pmovzxwd => Vpmovzxwd,
+    Pmovzxwq => Vpmovzxwq,
+    Pmuldq => Vpmuldq,
+    Pmulhrsw => Vpmulhrsw,
+    Pmulhuw => Vpmulhuw,
+    Pmulhw => Vpmulhw,
+    Pmulld => Vpmulld,
+    Pmullw => Vpmullw,
+    Pmuludq => Vpmuludq,
+    Por => Vpor,
+    Psadbw => Vpsadbw,
+    Pshufb => Vpshufb,
+    Pshufd => Vpshufd,
+    Pshufhw => Vpshufhw,
+    Pshuflw => Vpshuflw,
+    Psignb => Vpsignb,
+    Psignd => Vpsignd,
+    Psignw => Vpsignw,
+    Pslld => Vpslld,
+    Pslldq => Vpslldq,
+    Psllq => Vpsllq,
+    Psllw => Vpsllw,
+    Psrad => Vpsrad,
+    Psraw => Vpsraw,
+    Psrld => Vpsrld,
+    Psrldq => Vpsrldq,
+    Psrlq => Vpsrlq,
+    Psrlw => Vpsrlw,
+    Psubb => Vpsubb,
+    Psubd => Vpsubd,
+    Psubq => Vpsubq,
+    Psubsb => Vpsubsb,
+    Psubsw => Vpsubsw,
+    Psubusb => Vpsubusb,
+    Psubusw => Vpsubusw,
+    Psubw => Vpsubw,
+    Ptest => Vptest,
+    Punpckhbw => Vpunpckhbw,
+    Punpckhdq => Vpunpckhdq,
+    Punpckhqdq => Vpunpckhqdq,
+    Punpckhwd => Vpunpckhwd,
+    Punpcklbw => Vpunpcklbw,
+    Punpckldq => Vpunpckldq,
+    Punpcklqdq => Vpunpcklqdq,
+    Punpcklwd => Vpunpcklwd,
+    Pxor => Vpxor,
+    Rcpps => Vrcpps,
+    Rcpss => Vrcpss,
+    Roundpd => Vroundpd,
+    Roundps => Vroundps,
+    Roundsd => Vroundsd,
+    Roundss => Vroundss,
+    Rsqrtps => Vrsqrtps,
+    Rsqrtss => Vrsqrtss,
+    Shufpd => Vshufpd,
+    Shufps => Vshufps,
+    Sqrtpd => Vsqrtpd,
+    Sqrtps => Vsqrtps,
+    Sqrtsd => Vsqrtsd,
+    Sqrtss => Vsqrtss,
+    Stmxcsr => Vstmxcsr,
+    Subpd => Vsubpd,
+    Subps => Vsubps,
+    Subsd => Vsubsd,
+    Subss => Vsubss,
+    Ucomisd => Vucomisd,
+    Ucomiss => Vucomiss,
+    Unpckhpd => Vunpckhpd,
+    Unpckhps => Vunpckhps,
+    Unpcklpd => Vunpcklpd,
+    Unpcklps => Vunpcklps,
+    Xorpd => Vxorpd,
+    Xorps => Vxorps,
+}
 
 /*
 Reference:
@@ -25,20 +1594,395 @@ scale-plus-index forms of 32-bit addressing require the SIB byte. The SIB byte i
 • The base field specifies the register number of the base register.
 See Section 2.1.5 for the encodings of the ModR/M and SIB bytes.
 */
-pub enum OperandType<T:UnsignedInteger> {
-        Register(T),
-        Memory(T)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModRm {
+    /// mod field, bits 7:6
+    pub mode: u8,
+    /// reg/opcode field, bits 5:3
+    pub reg: u8,
+    /// r/m field, bits 2:0
+    pub rm: u8,
+}
+
+impl ModRm {
+    pub fn from_byte(byte: u8) -> ModRm {
+        ModRm {
+            mode: byte >> 6,
+            reg: (byte >> 3) & 0b111,
+            rm: byte & 0b111,
+        }
+    }
+
+    pub fn byte(&self) -> u8 {
+        (self.mode << 6) | (self.reg << 3) | self.rm
+    }
+
+    /// mod == 0b11, r/m names a register rather than a memory location
+    pub fn is_register(&self) -> bool {
+        self.mode == 0b11
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sib {
+    /// scale field as encoded (0..3), the scale factor is 1 << scale
+    pub scale: u8,
+    pub index: u8,
+    pub base: u8,
+}
+
+impl Sib {
+    pub fn from_byte(byte: u8) -> Sib {
+        Sib {
+            scale: byte >> 6,
+            index: (byte >> 3) & 0b111,
+            base: byte & 0b111,
+        }
+    }
+
+    pub fn byte(&self) -> u8 {
+        (self.scale << 6) | (self.index << 3) | self.base
+    }
+}
+
+/// REX prefix (40..4F), 64-bit mode only
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rex {
+    /// 64-bit operand size
+    pub w: bool,
+    /// extension of ModR/M.reg
+    pub r: bool,
+    /// extension of SIB.index
+    pub x: bool,
+    /// extension of ModR/M.rm, SIB.base or the opcode register field
+    pub b: bool,
+}
+
+impl Rex {
+    pub fn from_byte(byte: u8) -> Rex {
+        Rex {
+            w: byte & 0b1000 != 0,
+            r: byte & 0b0100 != 0,
+            x: byte & 0b0010 != 0,
+            b: byte & 0b0001 != 0,
+        }
+    }
+
+    pub fn byte(&self) -> u8 {
+        0x40 | ((self.w as u8) << 3) | ((self.r as u8) << 2) | ((self.x as u8) << 1) | self.b as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepPrefix {
+    /// F3: rep / repe / repz
+    Rep,
+    /// F2: repne / repnz, or bnd on a near branch
+    Repne,
+}
+
+/// Legacy prefixes, as they appeared in front of the instruction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Prefixes {
+    /// F0
+    pub lock: bool,
+    /// last F2 / F3 seen. This is still set when the prefix was consumed as a mandatory prefix,
+    /// see [`X86Opcode::mandatory_prefix`]
+    pub rep: Option<RepPrefix>,
+    /// segment override (2E, 36, 3E, 26, 64, 65)
+    pub segment: Option<Register>,
+    /// 66
+    pub operand_size: bool,
+    /// 67
+    pub address_size: bool,
+    /// number of legacy prefix bytes, including redundant ones
+    pub count: u8,
+}
+
+/// 66 / F3 / F2 when used to select an instruction rather than modify it (`66 0F 58` is addpd)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MandatoryPrefix {
+    #[default]
+    None,
+    P66,
+    PF3,
+    PF2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OpcodeMap {
+    /// one byte opcodes
+    Primary,
+    /// 0F xx
+    Map0F,
+    /// 0F 38 xx
+    Map0F38,
+    /// 0F 3A xx
+    Map0F3A,
+}
+
+/// How the opcode was encoded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// legacy prefixes, optional REX and a 0F / 0F 38 / 0F 3A escape
+    #[default]
+    Legacy,
+    /// two byte (C5) or three byte (C4) VEX prefix
+    Vex,
+    /// four byte EVEX prefix (62)
+    Evex,
+}
+
+/// Fields of a VEX or EVEX prefix
+///
+/// The encoding stores R, X, B, R', V' and vvvv inverted, they are stored the right way up here
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VexPrefix {
+    /// prefix length in bytes, including the C4 / C5 / 62 byte
+    pub size: u8,
+    /// extension of ModR/M.reg
+    pub r: bool,
+    /// extension of SIB.index, and of ModR/M.rm for EVEX vector registers 16-31
+    pub x: bool,
+    /// extension of ModR/M.rm or SIB.base
+    pub b: bool,
+    /// EVEX.R', second extension bit of ModR/M.reg
+    pub r2: bool,
+    /// VEX.W / EVEX.W
+    pub w: bool,
+    /// extra register operand. EVEX.V' is folded in as bit 4
+    pub vvvv: u8,
+    /// VEX.L or EVEX.L'L: 0 for 128 bit, 1 for 256 bit, 2 for 512 bit
+    pub l: u8,
+    /// VEX.mmmmm / EVEX.mm: 1 = 0F, 2 = 0F 38, 3 = 0F 3A
+    pub map_select: u8,
+    /// implied mandatory prefix: 0 = none, 1 = 66, 2 = F3, 3 = F2
+    pub pp: u8,
+    /// EVEX.aaa, opmask register. 0 means no masking
+    pub aaa: u8,
+    /// EVEX.z, zeroing rather than merging masking
+    pub z: bool,
+    /// EVEX.b: broadcast for memory operands, rounding control / exception suppression for
+    /// register operands
+    pub bcst: bool,
+}
+
+/// Rounding mode, in MXCSR.RC / EVEX.L'L bit order
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoundingControl {
+    #[default]
+    Nearest,
+    Down,
+    Up,
+    Zero,
+}
+
+impl RoundingControl {
+    pub fn from_bits(bits: u8) -> RoundingControl {
+        match bits & 0b11 {
+            0 => RoundingControl::Nearest,
+            1 => RoundingControl::Down,
+            2 => RoundingControl::Up,
+            _ => RoundingControl::Zero,
+        }
+    }
+}
+
+/// Processor mode the bytes are decoded for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Bitness {
+    Bits16,
+    Bits32,
+    #[default]
+    Bits64,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Displacement {
+    /// sign extended displacement
+    pub value: i64,
+    /// encoded size in bytes
+    pub size: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Immediate {
+    /// Value of the immediate at the width it is used at, eg the imm8 in `add rax, -1` is
+    /// sign extended to 0xFFFF_FFFF_FFFF_FFFF. Raw immediates (`X86Opcode::immediate`) are
+    /// zero extended as encoded
+    pub value: u64,
+    /// width in bytes
+    pub size: u8,
+}
+
+/// A memory reference: `segment:[base + index * scale + displacement]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryOperand {
+    /// explicit segment override, if any
+    pub segment: Option<Register>,
+    /// base register. `Register::Rip` for rip relative addressing, in which case the
+    /// displacement is relative to the end of the instruction
+    pub base: Option<Register>,
+    pub index: Option<Register>,
+    /// 1, 2, 4 or 8
+    pub scale: u8,
+    pub displacement: i64,
+    /// number of bytes accessed, 0 where it does not apply (lea, prefetch...)
+    pub size: u16,
+}
 
-pub enum X86Opcode {
-    Add00(OperandType<u8>,OperandType<u8>),
-    Add01(OperandType<u8>,OperandType<u8>),
-    AddR8M8(OperandType<u8>,OperandType<u8>),
-    Add03(u8,u8),
-    AddAlImm8(u8,u8),
-    AddEaxImm(u8,u8),
-    Push(u8) /*u8 = register?*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register),
+    Memory(MemoryOperand),
+    Immediate(Immediate),
+    /// Absolute target of a relative jump / call
+    Branch(u64),
+    /// ptr16:16 / ptr16:32 immediate of a far jump / call
+    FarPointer { selector: u16, offset: u32 },
+}
+
+pub const MAX_OPERANDS: usize = 4;
+
+/// Fixed capacity operand list, so decoding does not need to allocate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Operands {
+    items: [Operand; MAX_OPERANDS],
+    len: u8,
+}
+
+impl Operands {
+    pub fn new() -> Operands {
+        Operands {
+            items: [Operand::Immediate(Immediate { value: 0, size: 0 }); MAX_OPERANDS],
+            len: 0,
+        }
+    }
+
+    /// Panics if the list is full; no instruction has more than `MAX_OPERANDS` operands
+    pub fn push(&mut self, operand: Operand) {
+        self.items[self.len as usize] = operand;
+        self.len += 1;
+    }
+}
+
+impl Default for Operands {
+    fn default() -> Self {
+        Operands::new()
+    }
+}
 
+impl Deref for Operands {
+    type Target = [Operand];
 
-}
\ No newline at end of file
+    fn deref(&self) -> &Self::Target {
+        &self.items[..self.len as usize]
+    }
+}
+
+/// A decoded instruction
+///
+/// Carries both the raw encoding (prefixes, opcode, ModR/M, SIB, displacement, immediates)
+/// for static analysis, and the interpreted form (mnemonic and operands) for execution and
+/// formatting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct X86Opcode {
+    pub mnemonic: Mnemonic,
+    pub operands: Operands,
+
+    /// address the instruction was decoded at
+    pub ip: u64,
+    /// total encoded length in bytes, 1..=15
+    pub length: u8,
+    pub bitness: Bitness,
+
+    /// effective operand size in bytes (2, 4 or 8)
+    pub operand_size: u8,
+    /// effective address size in bytes (2, 4 or 8)
+    pub address_size: u8,
+
+    pub prefixes: Prefixes,
+    pub mandatory_prefix: MandatoryPrefix,
+    pub rex: Option<Rex>,
+    pub encoding: Encoding,
+    /// VEX / EVEX prefix fields, when `encoding` is not `Legacy`
+    pub vex: Option<VexPrefix>,
+    pub map: OpcodeMap,
+    pub opcode: u8,
+    pub modrm: Option<ModRm>,
+    pub sib: Option<Sib>,
+    pub displacement: Option<Displacement>,
+    pub immediate: Option<Immediate>,
+    /// second immediate, only used by `enter`
+    pub immediate2: Option<Immediate>,
+
+    /// Vector length in bytes: 16, 32 or 64 as selected by VEX.L / EVEX.L'L. Legacy
+    /// encodings report 16, the width of SSE registers
+    pub vector_length: u16,
+    /// EVEX embedded broadcast: the memory operand is a single element repeated across the
+    /// vector, its size is the element size
+    pub broadcast: bool,
+    /// EVEX embedded rounding mode, overriding MXCSR.RC
+    pub rounding: Option<RoundingControl>,
+    /// EVEX suppress all exceptions, implied by embedded rounding
+    pub sae: bool,
+
+    /// table flags of the matched entry
+    pub(crate) flags: u16,
+}
+
+impl X86Opcode {
+    pub fn operands(&self) -> &[Operand] {
+        &self.operands
+    }
+
+    /// address of the following instruction
+    pub fn next_ip(&self) -> u64 {
+        self.ip.wrapping_add(self.length as u64)
+    }
+
+    /// the (single) memory operand of the instruction, if it has one
+    pub fn memory_operand(&self) -> Option<&MemoryOperand> {
+        self.operands.iter().find_map(|o| match o {
+            Operand::Memory(m) => Some(m),
+            _ => None,
+        })
+    }
+
+    /// target of a relative branch
+    pub fn branch_target(&self) -> Option<u64> {
+        self.operands.iter().find_map(|o| match o {
+            Operand::Branch(target) => Some(*target),
+            _ => None,
+        })
+    }
+
+    /// whether a LOCK prefix is architecturally allowed, ie the instruction is a read-modify-write
+    /// with a memory destination
+    pub fn accepts_lock(&self) -> bool {
+        self.flags & crate::tables::LOCK != 0
+            && matches!(self.operands.first(), Some(Operand::Memory(_)))
+    }
+
+    /// EVEX opmask register applied to the destination, if any
+    pub fn opmask(&self) -> Option<Register> {
+        self.vex
+            .filter(|v| self.encoding == Encoding::Evex && v.aaa != 0)
+            .map(|v| Register::Opmask(v.aaa))
+    }
+
+    /// EVEX zeroing masking, masked out elements are zeroed instead of left unchanged
+    pub fn zeroing(&self) -> bool {
+        self.encoding == Encoding::Evex && self.vex.is_some_and(|v| v.z)
+    }
+
+    /// string instruction that honours rep / repe / repne
+    pub fn is_string(&self) -> bool {
+        self.flags & (crate::tables::REP | crate::tables::REPE) != 0
+    }
+
+    /// near branch with a BND (F2) prefix, which leaves the MPX bounds registers alone
+    pub fn has_bnd_prefix(&self) -> bool {
+        self.flags & crate::tables::BND != 0 && self.prefixes.rep == Some(RepPrefix::Repne)
+    }
+}
@@ -0,0 +1,324 @@
+use std::fmt;
+
+/// Architectural registers as they appear in instruction operands
+///
+/// Registers are grouped by file and addressed by their encoding number, so that the number
+/// can go straight back into a ModR/M, SIB, REX or VEX field when encoding. The named
+/// constants (`Register::RAX`, `Register::XMM0` etc) exist for readability at call sites.
+///
+/// The legacy high byte registers (AH, CH, DH, BH) get their own variant, as they share
+/// encodings 4..7 with SPL/BPL/SIL/DIL and are only reachable when no REX prefix is present.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Register {
+    /// AL, CL, DL, BL, SPL, BPL, SIL, DIL, R8B..R15B
+    Gpr8(u8),
+    /// AH, CH, DH, BH (index 0..3)
+    Gpr8High(u8),
+    Gpr16(u8),
+    Gpr32(u8),
+    Gpr64(u8),
+    /// ES, CS, SS, DS, FS, GS
+    Segment(u8),
+    Control(u8),
+    Debug(u8),
+    /// x87 stack register, relative to the current top of stack
+    St(u8),
+    Mmx(u8),
+    Xmm(u8),
+    Ymm(u8),
+    Zmm(u8),
+    /// AVX-512 opmask registers k0..k7
+    Opmask(u8),
+    /// MPX bounds registers bnd0..bnd3
+    Bound(u8),
+    Rip,
+    Eip,
+}
+
+const GPR8_NAMES: [&str; 16] = [
+    "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b",
+    "r13b", "r14b", "r15b",
+];
+const GPR8_HIGH_NAMES: [&str; 4] = ["ah", "ch", "dh", "bh"];
+const GPR16_NAMES: [&str; 16] = [
+    "ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w", "r11w", "r12w", "r13w",
+    "r14w", "r15w",
+];
+const GPR32_NAMES: [&str; 16] = [
+    "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d", "r12d",
+    "r13d", "r14d", "r15d",
+];
+const GPR64_NAMES: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+const SEGMENT_NAMES: [&str; 6] = ["es", "cs", "ss", "ds", "fs", "gs"];
+
+/// Builds the name table for a numbered register file (xmm0..xmm31 etc) at compile time,
+/// so that names can be handed out as `&'static str` without allocating
+macro_rules! numbered_names {
+    ($prefix:literal, 8) => {
+        [
+            concat!($prefix, "0"), concat!($prefix, "1"), concat!($prefix, "2"), concat!($prefix, "3"),
+            concat!($prefix, "4"), concat!($prefix, "5"), concat!($prefix, "6"), concat!($prefix, "7"),
+        ]
+    };
+    ($prefix:literal, 16) => {
+        [
+            concat!($prefix, "0"), concat!($prefix, "1"), concat!($prefix, "2"), concat!($prefix, "3"),
+            concat!($prefix, "4"), concat!($prefix, "5"), concat!($prefix, "6"), concat!($prefix, "7"),
+            concat!($prefix, "8"), concat!($prefix, "9"), concat!($prefix, "10"), concat!($prefix, "11"),
+            concat!($prefix, "12"), concat!($prefix, "13"), concat!($prefix, "14"), concat!($prefix, "15"),
+        ]
+    };
+    ($prefix:literal, 32) => {
+        [
+            concat!($prefix, "0"), concat!($prefix, "1"), concat!($prefix, "2"), concat!($prefix, "3"),
+            concat!($prefix, "4"), concat!($prefix, "5"), concat!($prefix, "6"), concat!($prefix, "7"),
+            concat!($prefix, "8"), concat!($prefix, "9"), concat!($prefix, "10"), concat!($prefix, "11"),
+            concat!($prefix, "12"), concat!($prefix, "13"), concat!($prefix, "14"), concat!($prefix, "15"),
+            concat!($prefix, "16"), concat!($prefix, "17"), concat!($prefix, "18"), concat!($prefix, "19"),
+            concat!($prefix, "20"), concat!($prefix, "21"), concat!($prefix, "22"), concat!($prefix, "23"),
+            concat!($prefix, "24"), concat!($prefix, "25"), concat!($prefix, "26"), concat!($prefix, "27"),
+            concat!($prefix, "28"), concat!($prefix, "29"), concat!($prefix, "30"), concat!($prefix, "31"),
+        ]
+    };
+}
+
+const CONTROL_NAMES: [&str; 16] = numbered_names!("cr", 16);
+const DEBUG_NAMES: [&str; 16] = numbered_names!("dr", 16);
+const ST_NAMES: [&str; 8] = numbered_names!("st", 8);
+const MMX_NAMES: [&str; 8] = numbered_names!("mm", 8);
+const XMM_NAMES: [&str; 32] = numbered_names!("xmm", 32);
+const YMM_NAMES: [&str; 32] = numbered_names!("ymm", 32);
+const ZMM_NAMES: [&str; 32] = numbered_names!("zmm", 32);
+const OPMASK_NAMES: [&str; 8] = numbered_names!("k", 8);
+const BOUND_NAMES: [&str; 8] = numbered_names!("bnd", 8);
+
+impl Register {
+    pub const AL: Register = Register::Gpr8(0);
+    pub const CL: Register = Register::Gpr8(1);
+    pub const DL: Register = Register::Gpr8(2);
+    pub const BL: Register = Register::Gpr8(3);
+    pub const AH: Register = Register::Gpr8High(0);
+    pub const CH: Register = Register::Gpr8High(1);
+    pub const DH: Register = Register::Gpr8High(2);
+    pub const BH: Register = Register::Gpr8High(3);
+
+    pub const AX: Register = Register::Gpr16(0);
+    pub const CX: Register = Register::Gpr16(1);
+    pub const DX: Register = Register::Gpr16(2);
+    pub const BX: Register = Register::Gpr16(3);
+    pub const SP: Register = Register::Gpr16(4);
+    pub const BP: Register = Register::Gpr16(5);
+    pub const SI: Register = Register::Gpr16(6);
+    pub const DI: Register = Register::Gpr16(7);
+
+    pub const EAX: Register = Register::Gpr32(0);
+    pub const ECX: Register = Register::Gpr32(1);
+    pub const EDX: Register = Register::Gpr32(2);
+    pub const EBX: Register = Register::Gpr32(3);
+    pub const ESP: Register = Register::Gpr32(4);
+    pub const EBP: Register = Register::Gpr32(5);
+    pub const ESI: Register = Register::Gpr32(6);
+    pub const EDI: Register = Register::Gpr32(7);
+
+    pub const RAX: Register = Register::Gpr64(0);
+    pub const RCX: Register = Register::Gpr64(1);
+    pub const RDX: Register = Register::Gpr64(2);
+    pub const RBX: Register = Register::Gpr64(3);
+    pub const RSP: Register = Register::Gpr64(4);
+    pub const RBP: Register = Register::Gpr64(5);
+    pub const RSI: Register = Register::Gpr64(6);
+    pub const RDI: Register = Register::Gpr64(7);
+    pub const R8: Register = Register::Gpr64(8);
+    pub const R9: Register = Register::Gpr64(9);
+    pub const R10: Register = Register::Gpr64(10);
+    pub const R11: Register = Register::Gpr64(11);
+    pub const R12: Register = Register::Gpr64(12);
+    pub const R13: Register = Register::Gpr64(13);
+    pub const R14: Register = Register::Gpr64(14);
+    pub const R15: Register = Register::Gpr64(15);
+
+    pub const ES: Register = Register::Segment(0);
+    pub const CS: Register = Register::Segment(1);
+    pub const SS: Register = Register::Segment(2);
+    pub const DS: Register = Register::Segment(3);
+    pub const FS: Register = Register::Segment(4);
+    pub const GS: Register = Register::Segment(5);
+
+    pub const XMM0: Register = Register::Xmm(0);
+    pub const XMM1: Register = Register::Xmm(1);
+    pub const XMM2: Register = Register::Xmm(2);
+    pub const XMM3: Register = Register::Xmm(3);
+    pub const XMM4: Register = Register::Xmm(4);
+    pub const XMM5: Register = Register::Xmm(5);
+    pub const XMM6: Register = Register::Xmm(6);
+    pub const XMM7: Register = Register::Xmm(7);
+
+    /// Encoding number of the register within its file, as used by ModR/M, SIB, REX etc
+    pub fn number(&self) -> u8 {
+        match *self {
+            Register::Gpr8High(n) => n + 4,
+            Register::Gpr8(n)
+            | Register::Gpr16(n)
+            | Register::Gpr32(n)
+            | Register::Gpr64(n)
+            | Register::Segment(n)
+            | Register::Control(n)
+            | Register::Debug(n)
+            | Register::St(n)
+            | Register::Mmx(n)
+            | Register::Xmm(n)
+            | Register::Ymm(n)
+            | Register::Zmm(n)
+            | Register::Opmask(n)
+            | Register::Bound(n) => n,
+            Register::Rip | Register::Eip => 0,
+        }
+    }
+
+    /// Width of the register in bytes
+    pub fn size(&self) -> u16 {
+        match self {
+            Register::Gpr8(_) | Register::Gpr8High(_) => 1,
+            Register::Gpr16(_) | Register::Segment(_) => 2,
+            Register::Gpr32(_) | Register::Eip => 4,
+            Register::Gpr64(_)
+            | Register::Rip
+            | Register::Control(_)
+            | Register::Debug(_)
+            | Register::Mmx(_)
+            | Register::Opmask(_) => 8,
+            Register::St(_) => 10,
+            Register::Xmm(_) | Register::Bound(_) => 16,
+            Register::Ymm(_) => 32,
+            Register::Zmm(_) => 64,
+        }
+    }
+
+    pub fn is_gpr(&self) -> bool {
+        matches!(
+            self,
+            Register::Gpr8(_)
+                | Register::Gpr8High(_)
+                | Register::Gpr16(_)
+                | Register::Gpr32(_)
+                | Register::Gpr64(_)
+        )
+    }
+
+    pub fn is_vector(&self) -> bool {
+        matches!(self, Register::Xmm(_) | Register::Ymm(_) | Register::Zmm(_))
+    }
+
+    /// General purpose register of the given width (in bytes) with the given encoding number.
+    /// `rex` selects SPL..DIL over AH..BH for byte registers 4..7
+    pub fn gpr(size: u16, number: u8, rex: bool) -> Register {
+        match size {
+            1 if !rex && (4..8).contains(&number) => Register::Gpr8High(number - 4),
+            1 => Register::Gpr8(number),
+            2 => Register::Gpr16(number),
+            4 => Register::Gpr32(number),
+            _ => Register::Gpr64(number),
+        }
+    }
+
+    /// Vector register of the given width (in bytes) with the given encoding number
+    pub fn vector(size: u16, number: u8) -> Register {
+        match size {
+            64 => Register::Zmm(number),
+            32 => Register::Ymm(number),
+            _ => Register::Xmm(number),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        let n = self.number() as usize;
+        match self {
+            Register::Gpr8(_) => GPR8_NAMES[n],
+            Register::Gpr8High(i) => GPR8_HIGH_NAMES[*i as usize],
+            Register::Gpr16(_) => GPR16_NAMES[n],
+            Register::Gpr32(_) => GPR32_NAMES[n],
+            Register::Gpr64(_) => GPR64_NAMES[n],
+            Register::Segment(_) => SEGMENT_NAMES[n],
+            Register::Control(_) => CONTROL_NAMES[n],
+            Register::Debug(_) => DEBUG_NAMES[n],
+            Register::St(_) => ST_NAMES[n],
+            Register::Mmx(_) => MMX_NAMES[n],
+            Register::Xmm(_) => XMM_NAMES[n],
+            Register::Ymm(_) => YMM_NAMES[n],
+            Register::Zmm(_) => ZMM_NAMES[n],
+            Register::Opmask(_) => OPMASK_NAMES[n],
+            Register::Bound(_) => BOUND_NAMES[n],
+            Register::Rip => "rip",
+            Register::Eip => "eip",
+        }
+    }
+
+    /// Looks a register up by its (case insensitive) name, eg "r10d", "XMM17", "st3"
+    pub fn from_name(name: &str) -> Option<Register> {
+        let lower = name.to_ascii_lowercase();
+        let lower = lower.as_str();
+
+        let find = |names: &[&str]| names.iter().position(|n| *n == lower).map(|i| i as u8);
+
+        if let Some(i) = find(&GPR64_NAMES) {
+            return Some(Register::Gpr64(i));
+        }
+        if let Some(i) = find(&GPR32_NAMES) {
+            return Some(Register::Gpr32(i));
+        }
+        if let Some(i) = find(&GPR16_NAMES) {
+            return Some(Register::Gpr16(i));
+        }
+        if let Some(i) = find(&GPR8_NAMES) {
+            return Some(Register::Gpr8(i));
+        }
+        if let Some(i) = find(&GPR8_HIGH_NAMES) {
+            return Some(Register::Gpr8High(i));
+        }
+        if let Some(i) = find(&SEGMENT_NAMES) {
+            return Some(Register::Segment(i));
+        }
+        // the x87 stack is usually written st(3) in intel syntax and %st(3) in at&t
+        if lower == "st" {
+            return Some(Register::St(0));
+        }
+        if let Some(inner) = lower.strip_prefix("st(").and_then(|s| s.strip_suffix(')')) {
+            return inner.parse::<u8>().ok().filter(|i| *i < 8).map(Register::St);
+        }
+
+        let tables: [(&[&str], RegisterConstructor); 9] = [
+            (&XMM_NAMES, Register::Xmm),
+            (&YMM_NAMES, Register::Ymm),
+            (&ZMM_NAMES, Register::Zmm),
+            (&MMX_NAMES, Register::Mmx),
+            (&ST_NAMES, Register::St),
+            (&CONTROL_NAMES, Register::Control),
+            (&DEBUG_NAMES, Register::Debug),
+            (&OPMASK_NAMES, Register::Opmask),
+            (&BOUND_NAMES[..4], Register::Bound),
+        ];
+
+        for (names, ctor) in tables {
+            if let Some(i) = find(names) {
+                return Some(ctor(i));
+            }
+        }
+
+        match lower {
+            "rip" => Some(Register::Rip),
+            "eip" => Some(Register::Eip),
+            _ => None,
+        }
+    }
+}
+
+type RegisterConstructor = fn(u8) -> Register;
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
@@ -0,0 +1,2103 @@
+#![allow(non_upper_case_globals)]
+
+//! Opcode maps
+//!
+//! Each map is a flat list of entries written in the operand notation of the Intel SDM opcode
+//! map (Vol 2, Appendix A): `Eb, Gb` for `ADD r/m8, r8` and so on. An opcode byte can appear
+//! in several entries; the decoder picks the first one whose constraints (mandatory prefix,
+//! ModR/M.reg digit, mod, REX.W, operand size...) match the bytes being decoded, so more
+//! specific entries must come before more general ones.
+//!
+//! SSE entries double as the AVX form of the instruction when they are marked as VEX / EVEX
+//! encodable: `vaddps` is the `addps` entry decoded from a VEX prefix, with the `H` operand
+//! (VEX.vvvv) taking part. Instructions that only exist in AVX form have their own entries.
+//!
+//! The same tables drive the encoder, which searches them by mnemonic instead of by opcode.
+
+use crate::opcodes::Mnemonic as M;
+use crate::opcodes::{Bitness, Encoding, Mnemonic, OpcodeMap};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Operand size codes, as used by the SDM opcode map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Sz {
+    /// byte
+    B,
+    /// word
+    W,
+    /// doubleword
+    D,
+    /// quadword
+    Q,
+    /// 80 bit x87 extended precision / packed bcd
+    T,
+    /// word, doubleword or quadword depending on operand size
+    V,
+    /// word for 16-bit operand size, doubleword otherwise
+    Z,
+    /// doubleword, or quadword when REX.W / VEX.W is set
+    Y,
+    /// far pointer, 16 bit selector + operand size offset
+    P,
+    /// full vector length (xmm/ymm/zmm)
+    X,
+    /// always 128 bits
+    Dq,
+    /// half the vector length (cvtps2pd, pmovsxbw)
+    Half,
+    /// quarter the vector length (pmovsxbd)
+    Quarter,
+    /// eighth of the vector length (pmovsxbq)
+    Eighth,
+    /// movddup: 64 bits at 128 bit vector length, full vector otherwise
+    Dup,
+    /// size does not apply (lea, prefetch, fxsave...)
+    Any,
+}
+
+impl Sz {
+    /// width in bytes of a general purpose register or memory operand, at the given effective
+    /// operand size
+    pub(crate) fn gpr_size(self, operand_size: u8) -> u16 {
+        let osz = operand_size as u16;
+        match self {
+            Sz::B => 1,
+            Sz::W => 2,
+            Sz::D => 4,
+            Sz::Q => 8,
+            Sz::T => 10,
+            Sz::V => osz,
+            Sz::Z => {
+                if osz == 2 {
+                    2
+                } else {
+                    4
+                }
+            }
+            Sz::Y => {
+                if osz == 8 {
+                    8
+                } else {
+                    4
+                }
+            }
+            Sz::P => 2 + osz,
+            Sz::Dq => 16,
+            _ => 0,
+        }
+    }
+
+    /// width in bytes of the vector register for a V/W/U/H operand, at the given vector length
+    pub(crate) fn vector_register_size(self, vector_length: u16) -> u16 {
+        match self {
+            Sz::X | Sz::Dup => vector_length,
+            Sz::Half => (vector_length / 2).max(16),
+            _ => 16,
+        }
+    }
+
+    /// width in bytes of the memory location for a W operand
+    pub(crate) fn vector_memory_size(self, vector_length: u16, operand_size: u8) -> u16 {
+        match self {
+            Sz::X => vector_length,
+            Sz::Half => vector_length / 2,
+            Sz::Quarter => vector_length / 4,
+            Sz::Eighth => vector_length / 8,
+            Sz::Dup => {
+                if vector_length == 16 {
+                    8
+                } else {
+                    vector_length
+                }
+            }
+            other => other.gpr_size(operand_size),
+        }
+    }
+}
+
+/// Operand addressing methods, as used by the SDM opcode map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    /// ModR/M r/m: general purpose register or memory
+    E(Sz),
+    /// ModR/M r/m with different register and memory widths, eg `Rd/Mb`
+    Er(Sz, Sz),
+    /// ModR/M r/m, memory only
+    M(Sz),
+    /// ModR/M r/m, general purpose register only
+    R(Sz),
+    /// ModR/M reg, general purpose register
+    G(Sz),
+    /// general purpose register in the low 3 bits of the opcode
+    Z(Sz),
+    /// immediate
+    I(Sz),
+    /// imm8, sign extended to the operand size
+    Ibs,
+    /// relative branch offset
+    J(Sz),
+    /// absolute memory offset (moffs)
+    O(Sz),
+    /// far pointer immediate (ptr16:16 / ptr16:32)
+    A,
+    /// AL / AX / EAX / RAX
+    Acc(Sz),
+    Cl,
+    Dx,
+    /// the constant 1 (shift by one forms)
+    One,
+    /// fixed segment register
+    Seg(u8),
+    /// ModR/M reg, segment register
+    S,
+    /// ModR/M reg, control register
+    C,
+    /// ModR/M reg, debug register
+    D,
+    /// ModR/M reg, vector register
+    V(Sz),
+    /// ModR/M r/m, vector register or memory
+    W(Sz),
+    /// ModR/M r/m, vector register only
+    U(Sz),
+    /// VEX.vvvv vector register. Not present in legacy encodings
+    H(Sz),
+    /// vector register in imm8[7:4]
+    L(Sz),
+    /// ModR/M reg, mmx register
+    P,
+    /// ModR/M r/m, mmx register or memory
+    Q(Sz),
+    /// ModR/M r/m, mmx register only
+    N,
+    /// VEX.vvvv general purpose register
+    B(Sz),
+    /// ModR/M reg, opmask register
+    KReg,
+    /// ModR/M r/m, opmask register or memory
+    KRm(Sz),
+    /// VEX.vvvv opmask register
+    KVvvv,
+    /// ModR/M reg, MPX bounds register
+    BndReg,
+    /// ModR/M r/m, bounds register or memory
+    BndRm(Sz),
+    /// vector SIB memory operand (gathers / scatters), the index register is a vector of
+    /// the given size
+    Vsib(Sz),
+    St0,
+    /// ModR/M r/m, x87 stack register
+    Sti,
+    /// implicit xmm0 (blendv)
+    Xmm0,
+}
+
+impl Op {
+    /// operand is encoded through the ModR/M byte
+    pub(crate) fn uses_modrm(&self) -> bool {
+        matches!(
+            self,
+            Op::E(_)
+                | Op::Er(..)
+                | Op::M(_)
+                | Op::R(_)
+                | Op::G(_)
+                | Op::S
+                | Op::C
+                | Op::D
+                | Op::V(_)
+                | Op::W(_)
+                | Op::U(_)
+                | Op::P
+                | Op::Q(_)
+                | Op::N
+                | Op::Sti
+                | Op::KReg
+                | Op::KRm(_)
+                | Op::BndReg
+                | Op::BndRm(_)
+                | Op::Vsib(_)
+        )
+    }
+
+    pub(crate) fn memory_only(&self) -> bool {
+        matches!(self, Op::M(_) | Op::Vsib(_))
+    }
+
+    pub(crate) fn register_only(&self) -> bool {
+        matches!(self, Op::R(_) | Op::U(_) | Op::N | Op::Sti)
+    }
+}
+
+/// Mandatory prefix an entry requires
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Pfx {
+    /// legacy prefixes keep their usual meaning (operand size, rep)
+    Any,
+    /// no 66/F2/F3 prefix
+    None,
+    P66,
+    PF3,
+    PF2,
+}
+
+/// ModR/M.mod an entry requires
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mod {
+    Any,
+    Mem,
+    Reg,
+}
+
+/// Effective operand or address size an entry requires
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SizeMatch {
+    Any,
+    Operand(u8),
+    Address(u8),
+}
+
+/// invalid in 64-bit mode
+pub(crate) const I64: u16 = 1 << 0;
+/// only valid in 64-bit mode
+pub(crate) const O64: u16 = 1 << 1;
+/// operand size defaults to 64 bits in 64-bit mode
+pub(crate) const D64: u16 = 1 << 2;
+/// operand size is always 64 bits in 64-bit mode
+pub(crate) const F64: u16 = 1 << 3;
+/// accepts a LOCK prefix when the destination is memory
+pub(crate) const LOCK: u16 = 1 << 4;
+/// string instruction, accepts REP
+pub(crate) const REP: u16 = 1 << 5;
+/// string instruction, accepts REPE / REPNE
+pub(crate) const REPE: u16 = 1 << 6;
+/// only matches without REX.B (90 is nop, 41 90 is xchg r8, rax)
+pub(crate) const NO_REXB: u16 = 1 << 7;
+/// mod field is ignored and always treated as a register (mov to/from control registers)
+pub(crate) const MOD_REG: u16 = 1 << 8;
+/// EVEX.b on a register form only suppresses exceptions, rather than also selecting a rounding
+/// mode
+pub(crate) const SAE: u16 = 1 << 9;
+/// the EVEX form requires EVEX.W1 (double precision and quadword element forms of shared SSE
+/// entries, which are WIG in their VEX form)
+pub(crate) const EW1: u16 = 1 << 10;
+/// near branch that accepts a BND (F2) prefix, which keeps the MPX bounds registers
+pub(crate) const BND: u16 = 1 << 11;
+
+/// Encodings an entry can be decoded from
+pub(crate) const LEGACY: u8 = 1 << 0;
+pub(crate) const VEX: u8 = 1 << 1;
+pub(crate) const EVEX: u8 = 1 << 2;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Entry {
+    pub(crate) opcode: u8,
+    pub(crate) mnemonic: Mnemonic,
+    pub(crate) ops: &'static [Op],
+    pub(crate) prefix: Pfx,
+    /// ModR/M.reg, for group opcodes (/digit)
+    pub(crate) digit: Option<u8>,
+    /// exact ModR/M byte, for opcodes that use the whole byte (0F 01 D0 xgetbv)
+    pub(crate) modrm: Option<u8>,
+    pub(crate) mod_: Mod,
+    /// REX.W (or VEX.W/EVEX.W) the entry requires
+    pub(crate) w: Option<bool>,
+    pub(crate) size: SizeMatch,
+    /// VEX.L / EVEX.L'L the entry requires
+    pub(crate) l: Option<u8>,
+    /// set of `LEGACY`, `VEX` and `EVEX`
+    pub(crate) encodings: u8,
+    pub(crate) flags: u16,
+}
+
+pub(crate) const fn op(opcode: u8, mnemonic: Mnemonic, ops: &'static [Op]) -> Entry {
+    Entry {
+        opcode,
+        mnemonic,
+        ops,
+        prefix: Pfx::Any,
+        digit: None,
+        modrm: None,
+        mod_: Mod::Any,
+        w: None,
+        size: SizeMatch::Any,
+        l: None,
+        encodings: LEGACY,
+        flags: 0,
+    }
+}
+
+/// an instruction that only exists in VEX form
+pub(crate) const fn vop(opcode: u8, mnemonic: Mnemonic, ops: &'static [Op]) -> Entry {
+    op(opcode, mnemonic, ops).only(VEX)
+}
+
+/// an instruction that only exists in EVEX form
+pub(crate) const fn eop(opcode: u8, mnemonic: Mnemonic, ops: &'static [Op]) -> Entry {
+    op(opcode, mnemonic, ops).only(EVEX)
+}
+
+/// an instruction with VEX and EVEX forms, but no legacy one
+pub(crate) const fn veop(opcode: u8, mnemonic: Mnemonic, ops: &'static [Op]) -> Entry {
+    op(opcode, mnemonic, ops).only(VEX | EVEX)
+}
+
+impl Entry {
+    const fn np(mut self) -> Self {
+        self.prefix = Pfx::None;
+        self
+    }
+    const fn p66(mut self) -> Self {
+        self.prefix = Pfx::P66;
+        self
+    }
+    const fn pf3(mut self) -> Self {
+        self.prefix = Pfx::PF3;
+        self
+    }
+    const fn pf2(mut self) -> Self {
+        self.prefix = Pfx::PF2;
+        self
+    }
+    const fn digit(mut self, reg: u8) -> Self {
+        self.digit = Some(reg);
+        self
+    }
+    const fn modrm(mut self, byte: u8) -> Self {
+        self.modrm = Some(byte);
+        self
+    }
+    const fn mem(mut self) -> Self {
+        self.mod_ = Mod::Mem;
+        self
+    }
+    const fn reg(mut self) -> Self {
+        self.mod_ = Mod::Reg;
+        self
+    }
+    const fn w0(mut self) -> Self {
+        self.w = Some(false);
+        self
+    }
+    const fn w1(mut self) -> Self {
+        self.w = Some(true);
+        self
+    }
+    const fn o16(mut self) -> Self {
+        self.size = SizeMatch::Operand(2);
+        self
+    }
+    const fn o32(mut self) -> Self {
+        self.size = SizeMatch::Operand(4);
+        self
+    }
+    const fn o64(mut self) -> Self {
+        self.size = SizeMatch::Operand(8);
+        self
+    }
+    const fn a16(mut self) -> Self {
+        self.size = SizeMatch::Address(2);
+        self
+    }
+    const fn a32(mut self) -> Self {
+        self.size = SizeMatch::Address(4);
+        self
+    }
+    const fn a64(mut self) -> Self {
+        self.size = SizeMatch::Address(8);
+        self
+    }
+    const fn l0(mut self) -> Self {
+        self.l = Some(0);
+        self
+    }
+    const fn l1(mut self) -> Self {
+        self.l = Some(1);
+        self
+    }
+    /// also decodable from a VEX prefix
+    const fn vex(self) -> Self {
+        self.also(VEX)
+    }
+    /// also decodable from an EVEX prefix
+    const fn evex(self) -> Self {
+        self.also(EVEX)
+    }
+    const fn also(mut self, encodings: u8) -> Self {
+        self.encodings |= encodings;
+        self
+    }
+    const fn only(mut self, encodings: u8) -> Self {
+        self.encodings = encodings;
+        self
+    }
+    const fn f(mut self, flags: u16) -> Self {
+        self.flags |= flags;
+        self
+    }
+    const fn ew1(self) -> Self {
+        self.f(EW1)
+    }
+
+    pub(crate) fn has(&self, flag: u16) -> bool {
+        self.flags & flag != 0
+    }
+
+    pub(crate) fn encodable(&self, encoding: Encoding) -> bool {
+        let bit = match encoding {
+            Encoding::Legacy => LEGACY,
+            Encoding::Vex => VEX,
+            Encoding::Evex => EVEX,
+        };
+        self.encodings & bit != 0
+    }
+
+    /// Whether the encoding carries a ModR/M byte
+    pub(crate) fn uses_modrm(&self) -> bool {
+        self.digit.is_some()
+            || self.modrm.is_some()
+            || self.mod_ != Mod::Any
+            || self.ops.iter().any(Op::uses_modrm)
+    }
+
+    /// mod constraint, including the one implied by memory-only / register-only operands
+    pub(crate) fn required_mod(&self) -> Mod {
+        if self.modrm.is_some() || self.has(MOD_REG) {
+            return Mod::Reg;
+        }
+        if self.mod_ != Mod::Any {
+            return self.mod_;
+        }
+        if self.ops.iter().any(Op::memory_only) {
+            Mod::Mem
+        } else if self.ops.iter().any(Op::register_only) {
+            Mod::Reg
+        } else {
+            Mod::Any
+        }
+    }
+
+    /// Register operand encoded in the opcode byte, so the entry covers opcode..opcode+7
+    pub(crate) fn plus_r(&self) -> bool {
+        self.ops.iter().any(|o| matches!(o, Op::Z(_)))
+    }
+
+    /// Effective operand size when this entry is encoded with the given W bit and 66 prefix.
+    /// A 66 prefix that selects the entry is not also an operand size override
+    pub(crate) fn operand_size(&self, bitness: Bitness, w: bool, prefix_66: bool) -> u8 {
+        let override_66 = prefix_66 && self.prefix != Pfx::P66;
+        match bitness {
+            Bitness::Bits64 => {
+                if self.has(F64) || w {
+                    8
+                } else if override_66 {
+                    2
+                } else if self.has(D64) {
+                    8
+                } else {
+                    4
+                }
+            }
+            Bitness::Bits32 => {
+                if override_66 {
+                    2
+                } else {
+                    4
+                }
+            }
+            Bitness::Bits16 => {
+                if override_66 {
+                    4
+                } else {
+                    2
+                }
+            }
+        }
+    }
+}
+
+/*
+    Operand shorthands, named as in the SDM opcode map
+*/
+
+const Eb: Op = Op::E(Sz::B);
+const Ew: Op = Op::E(Sz::W);
+const Ed: Op = Op::E(Sz::D);
+const Eq: Op = Op::E(Sz::Q);
+const Ev: Op = Op::E(Sz::V);
+const Ey: Op = Op::E(Sz::Y);
+const Gb: Op = Op::G(Sz::B);
+const Gw: Op = Op::G(Sz::W);
+const Gd: Op = Op::G(Sz::D);
+const Gv: Op = Op::G(Sz::V);
+const Gy: Op = Op::G(Sz::Y);
+const Gz: Op = Op::G(Sz::Z);
+const Gq: Op = Op::G(Sz::Q);
+const Mb: Op = Op::M(Sz::B);
+const Mw: Op = Op::M(Sz::W);
+const Md: Op = Op::M(Sz::D);
+const Mq: Op = Op::M(Sz::Q);
+const Mt: Op = Op::M(Sz::T);
+const Mv: Op = Op::M(Sz::V);
+const My: Op = Op::M(Sz::Y);
+const Mp: Op = Op::M(Sz::P);
+const Mdq: Op = Op::M(Sz::Dq);
+const Mx: Op = Op::M(Sz::X);
+const M_: Op = Op::M(Sz::Any);
+const Rv: Op = Op::R(Sz::V);
+const Ry: Op = Op::R(Sz::Y);
+const Rd: Op = Op::R(Sz::D);
+const Rq: Op = Op::R(Sz::Q);
+const Zb: Op = Op::Z(Sz::B);
+const Zv: Op = Op::Z(Sz::V);
+const Zy: Op = Op::Z(Sz::Y);
+const Ib: Op = Op::I(Sz::B);
+const Iw: Op = Op::I(Sz::W);
+const Iz: Op = Op::I(Sz::Z);
+const Iv: Op = Op::I(Sz::V);
+const Ibs: Op = Op::Ibs;
+const Jb: Op = Op::J(Sz::B);
+const Jz: Op = Op::J(Sz::Z);
+const Ob: Op = Op::O(Sz::B);
+const Ov: Op = Op::O(Sz::V);
+const AL: Op = Op::Acc(Sz::B);
+const eAX: Op = Op::Acc(Sz::Z);
+const rAX: Op = Op::Acc(Sz::V);
+const CL: Op = Op::Cl;
+const DX: Op = Op::Dx;
+const ONE: Op = Op::One;
+const Sw: Op = Op::S;
+const Cd: Op = Op::C;
+const Dd: Op = Op::D;
+const Vx: Op = Op::V(Sz::X);
+const Vdq: Op = Op::V(Sz::Dq);
+const Vh: Op = Op::V(Sz::Half);
+const Wx: Op = Op::W(Sz::X);
+const Wdq: Op = Op::W(Sz::Dq);
+const Wq: Op = Op::W(Sz::Q);
+const Wd: Op = Op::W(Sz::D);
+const Wh: Op = Op::W(Sz::Half);
+const Wqr: Op = Op::W(Sz::Quarter);
+const Wer: Op = Op::W(Sz::Eighth);
+const Wdup: Op = Op::W(Sz::Dup);
+const Wb: Op = Op::W(Sz::B);
+const Ww: Op = Op::W(Sz::W);
+const Ux: Op = Op::U(Sz::X);
+const Udq: Op = Op::U(Sz::Dq);
+const Hx: Op = Op::H(Sz::X);
+const Hdq: Op = Op::H(Sz::Dq);
+const Hh: Op = Op::H(Sz::Half);
+const Lx: Op = Op::L(Sz::X);
+const By: Op = Op::B(Sz::Y);
+const KG: Op = Op::KReg;
+const KH: Op = Op::KVvvv;
+const KU: Op = Op::KRm(Sz::Any);
+const KEb: Op = Op::KRm(Sz::B);
+const KEw: Op = Op::KRm(Sz::W);
+const KEd: Op = Op::KRm(Sz::D);
+const KEq: Op = Op::KRm(Sz::Q);
+const BG: Op = Op::BndReg;
+const BE: Op = Op::BndRm(Sz::Dq);
+/// vsib with a full width index vector (vm32x / vm32y / vm32z, or the qword equivalents)
+const VSIBx: Op = Op::Vsib(Sz::X);
+/// vsib with a half width index vector, when dword indices address qword elements
+const VSIBh: Op = Op::Vsib(Sz::Half);
+const Pq: Op = Op::P;
+const Qq: Op = Op::Q(Sz::Q);
+const Qd: Op = Op::Q(Sz::D);
+const Nq: Op = Op::N;
+const ST0: Op = Op::St0;
+const STi: Op = Op::Sti;
+const XMM0: Op = Op::Xmm0;
+
+/// `Rd/Mb`, `Rv/Mw` etc: a full width register or a narrower memory location
+const Ry_Mb: Op = Op::Er(Sz::Y, Sz::B);
+const Ry_Mw: Op = Op::Er(Sz::Y, Sz::W);
+const Rv_Mw: Op = Op::Er(Sz::V, Sz::W);
+const Udq_Md: Op = Op::W(Sz::D);
+
+/// an SSE instruction on packed values, and its MMX counterpart without the 66 prefix.
+/// `encodings` are the AVX encodings the SSE form also has
+const fn mmx_sse(opcode: u8, mnemonic: Mnemonic, encodings: u8) -> [Entry; 2] {
+    [
+        op(opcode, mnemonic, &[Pq, Qq]).np(),
+        op(opcode, mnemonic, &[Vx, Hx, Wx]).p66().also(encodings),
+    ]
+}
+
+/// marks every entry of a helper generated group as EVEX.W1
+const fn evex_w1<const N: usize>(mut entries: [Entry; N]) -> [Entry; N] {
+    let mut i = 0;
+    while i < N {
+        entries[i] = entries[i].ew1();
+        i += 1;
+    }
+    entries
+}
+
+/// packed single / packed double / scalar single / scalar double forms of an SSE operation
+/// All four are also VEX and EVEX encodable
+const fn sse_ps_pd_ss_sd(opcode: u8, m: [Mnemonic; 4], flags: u16) -> [Entry; 4] {
+    [
+        op(opcode, m[0], &[Vx, Hx, Wx]).np().vex().evex().f(flags),
+        op(opcode, m[1], &[Vx, Hx, Wx]).p66().vex().evex().ew1().f(flags),
+        op(opcode, m[2], &[Vdq, Hdq, Wd]).pf3().vex().evex().f(flags),
+        op(opcode, m[3], &[Vdq, Hdq, Wq]).pf2().vex().evex().ew1().f(flags),
+    ]
+}
+
+/// the eight classic ALU operations share an opcode layout: op Eb,Gb / Ev,Gv / Gb,Eb / Gv,Ev / AL,Ib / rAX,Iz
+const fn alu(base: u8, mnemonic: Mnemonic, lock: u16) -> [Entry; 6] {
+    [
+        op(base, mnemonic, &[Eb, Gb]).f(lock),
+        op(base + 1, mnemonic, &[Ev, Gv]).f(lock),
+        op(base + 2, mnemonic, &[Gb, Eb]),
+        op(base + 3, mnemonic, &[Gv, Ev]),
+        op(base + 4, mnemonic, &[AL, Ib]),
+        op(base + 5, mnemonic, &[rAX, Iz]),
+    ]
+}
+
+/// group 1 (80, 81, 82, 83)
+const fn group1(digit: u8, mnemonic: Mnemonic, lock: u16) -> [Entry; 4] {
+    [
+        op(0x80, mnemonic, &[Eb, Ib]).digit(digit).f(lock),
+        op(0x81, mnemonic, &[Ev, Iz]).digit(digit).f(lock),
+        op(0x82, mnemonic, &[Eb, Ib]).digit(digit).f(lock | I64),
+        op(0x83, mnemonic, &[Ev, Ibs]).digit(digit).f(lock),
+    ]
+}
+
+/// group 2 shifts and rotates (C0, C1, D0, D1, D2, D3)
+const fn group2(digit: u8, mnemonic: Mnemonic) -> [Entry; 6] {
+    [
+        op(0xC0, mnemonic, &[Eb, Ib]).digit(digit),
+        op(0xC1, mnemonic, &[Ev, Ib]).digit(digit),
+        op(0xD0, mnemonic, &[Eb, ONE]).digit(digit),
+        op(0xD1, mnemonic, &[Ev, ONE]).digit(digit),
+        op(0xD2, mnemonic, &[Eb, CL]).digit(digit),
+        op(0xD3, mnemonic, &[Ev, CL]).digit(digit),
+    ]
+}
+
+/// condition code ordering shared by Jcc, SETcc and CMOVcc
+const JCC: [Mnemonic; 16] = [
+    M::Jo, M::Jno, M::Jb, M::Jae, M::Je, M::Jne, M::Jbe, M::Ja,
+    M::Js, M::Jns, M::Jp, M::Jnp, M::Jl, M::Jge, M::Jle, M::Jg,
+];
+const SETCC: [Mnemonic; 16] = [
+    M::Seto, M::Setno, M::Setb, M::Setae, M::Sete, M::Setne, M::Setbe, M::Seta,
+    M::Sets, M::Setns, M::Setp, M::Setnp, M::Setl, M::Setge, M::Setle, M::Setg,
+];
+const CMOVCC: [Mnemonic; 16] = [
+    M::Cmovo, M::Cmovno, M::Cmovb, M::Cmovae, M::Cmove, M::Cmovne, M::Cmovbe, M::Cmova,
+    M::Cmovs, M::Cmovns, M::Cmovp, M::Cmovnp, M::Cmovl, M::Cmovge, M::Cmovle, M::Cmovg,
+];
+
+const fn cc_group(base: u8, mnemonics: &[Mnemonic; 16], ops: &'static [Op], flags: u16) -> [Entry; 16] {
+    let mut out = [op(base, mnemonics[0], ops); 16];
+    let mut i = 0;
+    while i < 16 {
+        out[i] = op(base + i as u8, mnemonics[i], ops).f(flags);
+        i += 1;
+    }
+    out
+}
+
+/// x87 instructions with a memory operand, one per ModR/M.reg digit
+const fn x87_mem(opcode: u8, ops: [(Mnemonic, &'static [Op]); 8]) -> [Entry; 8] {
+    let mut out = [op(opcode, ops[0].0, ops[0].1).mem(); 8];
+    let mut i = 0;
+    while i < 8 {
+        out[i] = op(opcode, ops[i].0, ops[i].1).digit(i as u8).mem();
+        i += 1;
+    }
+    out
+}
+
+/// x87 arithmetic with a memory operand: add, mul, com, comp, sub, subr, div, divr
+const fn x87_arith_mem(opcode: u8, size: &'static [Op], int: bool) -> [Entry; 8] {
+    let m = if int {
+        [M::Fiadd, M::Fimul, M::Ficom, M::Ficomp, M::Fisub, M::Fisubr, M::Fidiv, M::Fidivr]
+    } else {
+        [M::Fadd, M::Fmul, M::Fcom, M::Fcomp, M::Fsub, M::Fsubr, M::Fdiv, M::Fdivr]
+    };
+    x87_mem(
+        opcode,
+        [
+            (m[0], size), (m[1], size), (m[2], size), (m[3], size),
+            (m[4], size), (m[5], size), (m[6], size), (m[7], size),
+        ],
+    )
+}
+
+/// EVEX instruction pair where W selects the element size, `vpandd` (W0) / `vpandq` (W1)
+const fn evex_w(opcode: u8, m: [Mnemonic; 2], ops: &'static [Op]) -> [Entry; 2] {
+    [eop(opcode, m[0], ops).p66().w0(), eop(opcode, m[1], ops).p66().w1()]
+}
+
+/// FMA3 packed single / double (W0 / W1) at `opcode`, scalar single / double at `opcode + 1`
+const fn fma(opcode: u8, m: [Mnemonic; 4]) -> [Entry; 4] {
+    [
+        veop(opcode, m[0], &[Vx, Hx, Wx]).p66().w0(),
+        veop(opcode, m[1], &[Vx, Hx, Wx]).p66().w1(),
+        veop(opcode + 1, m[2], &[Vdq, Hdq, Wd]).p66().w0(),
+        veop(opcode + 1, m[3], &[Vdq, Hdq, Wq]).p66().w1(),
+    ]
+}
+
+/// FMA3 fmaddsub / fmsubadd, which only have packed forms
+const fn fma_packed(opcode: u8, m: [Mnemonic; 2]) -> [Entry; 2] {
+    [
+        veop(opcode, m[0], &[Vx, Hx, Wx]).p66().w0(),
+        veop(opcode, m[1], &[Vx, Hx, Wx]).p66().w1(),
+    ]
+}
+
+/// AVX-512 opmask instruction in its byte / word / dword / qword forms, selected by the 66
+/// prefix and VEX.W
+const fn opmask(opcode: u8, m: [Mnemonic; 4], ops: &'static [Op]) -> [Entry; 4] {
+    [
+        vop(opcode, m[0], ops).p66().w0(),
+        vop(opcode, m[1], ops).np().w0(),
+        vop(opcode, m[2], ops).p66().w1(),
+        vop(opcode, m[3], ops).np().w1(),
+    ]
+}
+
+/// binary opmask operation, `kandw k1, k2, k3`
+const fn opmask_binary(opcode: u8, m: [Mnemonic; 4]) -> [Entry; 4] {
+    let e = opmask(opcode, m, &[KG, KH, KU]);
+    [e[0].reg().l1(), e[1].reg().l1(), e[2].reg().l1(), e[3].reg().l1()]
+}
+
+/// unary opmask operation, `knotw k1, k2`
+const fn opmask_unary(opcode: u8, m: [Mnemonic; 4]) -> [Entry; 4] {
+    let e = opmask(opcode, m, &[KG, KU]);
+    [e[0].reg().l0(), e[1].reg().l0(), e[2].reg().l0(), e[3].reg().l0()]
+}
+
+/*
+    One byte opcode map
+*/
+
+static ONE_BYTE: &[&[Entry]] = &[
+    &alu(0x00, M::Add, LOCK),
+    &alu(0x08, M::Or, LOCK),
+    &alu(0x10, M::Adc, LOCK),
+    &alu(0x18, M::Sbb, LOCK),
+    &alu(0x20, M::And, LOCK),
+    &alu(0x28, M::Sub, LOCK),
+    &alu(0x30, M::Xor, LOCK),
+    &alu(0x38, M::Cmp, 0),
+    &[
+        op(0x06, M::Push, &[Op::Seg(0)]).f(I64),
+        op(0x07, M::Pop, &[Op::Seg(0)]).f(I64),
+        op(0x0E, M::Push, &[Op::Seg(1)]).f(I64),
+        op(0x16, M::Push, &[Op::Seg(2)]).f(I64),
+        op(0x17, M::Pop, &[Op::Seg(2)]).f(I64),
+        op(0x1E, M::Push, &[Op::Seg(3)]).f(I64),
+        op(0x1F, M::Pop, &[Op::Seg(3)]).f(I64),
+        op(0x27, M::Daa, &[]).f(I64),
+        op(0x2F, M::Das, &[]).f(I64),
+        op(0x37, M::Aaa, &[]).f(I64),
+        op(0x3F, M::Aas, &[]).f(I64),
+        op(0x40, M::Inc, &[Zv]).f(I64),
+        op(0x48, M::Dec, &[Zv]).f(I64),
+        op(0x50, M::Push, &[Zv]).f(D64),
+        op(0x58, M::Pop, &[Zv]).f(D64),
+        op(0x60, M::Pusha, &[]).o16().f(I64),
+        op(0x60, M::Pushad, &[]).f(I64),
+        op(0x61, M::Popa, &[]).o16().f(I64),
+        op(0x61, M::Popad, &[]).f(I64),
+        op(0x62, M::Bound, &[Gv, Op::M(Sz::Any)]).f(I64),
+        op(0x63, M::Movsxd, &[Gv, Ed]).f(O64),
+        op(0x63, M::Arpl, &[Ew, Gw]).f(I64),
+        op(0x68, M::Push, &[Iz]).f(D64),
+        op(0x69, M::Imul, &[Gv, Ev, Iz]),
+        op(0x6A, M::Push, &[Ibs]).f(D64),
+        op(0x6B, M::Imul, &[Gv, Ev, Ibs]),
+        op(0x6C, M::Insb, &[]).f(REP),
+        op(0x6D, M::Ins, &[]).f(REP),
+        op(0x6E, M::Outsb, &[]).f(REP),
+        op(0x6F, M::Outs, &[]).f(REP),
+    ],
+    &cc_group(0x70, &JCC, &[Jb], F64 | BND),
+    &group1(0, M::Add, LOCK),
+    &group1(1, M::Or, LOCK),
+    &group1(2, M::Adc, LOCK),
+    &group1(3, M::Sbb, LOCK),
+    &group1(4, M::And, LOCK),
+    &group1(5, M::Sub, LOCK),
+    &group1(6, M::Xor, LOCK),
+    &group1(7, M::Cmp, 0),
+    &[
+        op(0x84, M::Test, &[Eb, Gb]),
+        op(0x85, M::Test, &[Ev, Gv]),
+        op(0x86, M::Xchg, &[Eb, Gb]).f(LOCK),
+        op(0x87, M::Xchg, &[Ev, Gv]).f(LOCK),
+        op(0x88, M::Mov, &[Eb, Gb]),
+        op(0x89, M::Mov, &[Ev, Gv]),
+        op(0x8A, M::Mov, &[Gb, Eb]),
+        op(0x8B, M::Mov, &[Gv, Ev]),
+        op(0x8C, M::Mov, &[Rv_Mw, Sw]),
+        op(0x8D, M::Lea, &[Gv, M_]),
+        op(0x8E, M::Mov, &[Sw, Ew]),
+        op(0x8F, M::Pop, &[Ev]).digit(0).f(D64),
+        op(0x90, M::Pause, &[]).pf3().f(NO_REXB),
+        op(0x90, M::Nop, &[]).f(NO_REXB),
+        op(0x90, M::Xchg, &[Zv, rAX]),
+        op(0x98, M::Cbw, &[]).o16(),
+        op(0x98, M::Cwde, &[]).o32(),
+        op(0x98, M::Cdqe, &[]).o64(),
+        op(0x99, M::Cwd, &[]).o16(),
+        op(0x99, M::Cdq, &[]).o32(),
+        op(0x99, M::Cqo, &[]).o64(),
+        op(0x9A, M::Callf, &[Op::A]).f(I64),
+        op(0x9B, M::Wait, &[]),
+        op(0x9C, M::Pushf, &[]).o16().f(D64),
+        op(0x9C, M::Pushfd, &[]).o32().f(D64),
+        op(0x9C, M::Pushfq, &[]).o64().f(D64),
+        op(0x9D, M::Popf, &[]).o16().f(D64),
+        op(0x9D, M::Popfd, &[]).o32().f(D64),
+        op(0x9D, M::Popfq, &[]).o64().f(D64),
+        op(0x9E, M::Sahf, &[]),
+        op(0x9F, M::Lahf, &[]),
+        op(0xA0, M::Mov, &[AL, Ob]),
+        op(0xA1, M::Mov, &[rAX, Ov]),
+        op(0xA2, M::Mov, &[Ob, AL]),
+        op(0xA3, M::Mov, &[Ov, rAX]),
+        op(0xA4, M::Movsb, &[]).f(REP),
+        op(0xA5, M::Movs, &[]).f(REP),
+        op(0xA6, M::Cmpsb, &[]).f(REPE),
+        op(0xA7, M::Cmps, &[]).f(REPE),
+        op(0xA8, M::Test, &[AL, Ib]),
+        op(0xA9, M::Test, &[rAX, Iz]),
+        op(0xAA, M::Stosb, &[]).f(REP),
+        op(0xAB, M::Stos, &[]).f(REP),
+        op(0xAC, M::Lodsb, &[]).f(REP),
+        op(0xAD, M::Lods, &[]).f(REP),
+        op(0xAE, M::Scasb, &[]).f(REPE),
+        op(0xAF, M::Scas, &[]).f(REPE),
+        op(0xB0, M::Mov, &[Zb, Ib]),
+        op(0xB8, M::Mov, &[Zv, Iv]),
+    ],
+    &group2(0, M::Rol),
+    &group2(1, M::Ror),
+    &group2(2, M::Rcl),
+    &group2(3, M::Rcr),
+    &group2(4, M::Shl),
+    &group2(5, M::Shr),
+    &group2(6, M::Sal),
+    &group2(7, M::Sar),
+    &[
+        op(0xC2, M::Ret, &[Iw]).f(D64 | BND),
+        op(0xC3, M::Ret, &[]).f(D64 | BND),
+        op(0xC4, M::Les, &[Gz, Mp]).f(I64),
+        op(0xC5, M::Lds, &[Gz, Mp]).f(I64),
+        op(0xC6, M::Xabort, &[Ib]).modrm(0xF8),
+        op(0xC6, M::Mov, &[Eb, Ib]).digit(0),
+        op(0xC7, M::Xbegin, &[Jz]).modrm(0xF8),
+        op(0xC7, M::Mov, &[Ev, Iz]).digit(0),
+        op(0xC8, M::Enter, &[Iw, Ib]).f(D64),
+        op(0xC9, M::Leave, &[]).f(D64),
+        op(0xCA, M::Retf, &[Iw]),
+        op(0xCB, M::Retf, &[]),
+        op(0xCC, M::Int3, &[]),
+        op(0xCD, M::Int, &[Ib]),
+        op(0xCE, M::Into, &[]).f(I64),
+        op(0xCF, M::Iret, &[]).o16(),
+        op(0xCF, M::Iretd, &[]).o32(),
+        op(0xCF, M::Iretq, &[]).o64(),
+        op(0xD4, M::Aam, &[Ib]).f(I64),
+        op(0xD5, M::Aad, &[Ib]).f(I64),
+        op(0xD6, M::Salc, &[]).f(I64),
+        op(0xD7, M::Xlatb, &[]),
+    ],
+    /* x87 escapes, D8..DF */
+    &x87_arith_mem(0xD8, &[Md], false),
+    &[
+        op(0xD8, M::Fadd, &[ST0, STi]).digit(0),
+        op(0xD8, M::Fmul, &[ST0, STi]).digit(1),
+        op(0xD8, M::Fcom, &[STi]).digit(2),
+        op(0xD8, M::Fcomp, &[STi]).digit(3),
+        op(0xD8, M::Fsub, &[ST0, STi]).digit(4),
+        op(0xD8, M::Fsubr, &[ST0, STi]).digit(5),
+        op(0xD8, M::Fdiv, &[ST0, STi]).digit(6),
+        op(0xD8, M::Fdivr, &[ST0, STi]).digit(7),
+        op(0xD9, M::Fld, &[Md]).digit(0),
+        op(0xD9, M::Fst, &[Md]).digit(2),
+        op(0xD9, M::Fstp, &[Md]).digit(3),
+        op(0xD9, M::Fldenv, &[M_]).digit(4),
+        op(0xD9, M::Fldcw, &[Mw]).digit(5),
+        op(0xD9, M::Fnstenv, &[M_]).digit(6),
+        op(0xD9, M::Fnstcw, &[Mw]).digit(7),
+        op(0xD9, M::Fld, &[STi]).digit(0),
+        op(0xD9, M::Fxch, &[STi]).digit(1),
+        op(0xD9, M::Fnop, &[]).modrm(0xD0),
+        op(0xD9, M::Fchs, &[]).modrm(0xE0),
+        op(0xD9, M::Fabs, &[]).modrm(0xE1),
+        op(0xD9, M::Ftst, &[]).modrm(0xE4),
+        op(0xD9, M::Fxam, &[]).modrm(0xE5),
+        op(0xD9, M::Fld1, &[]).modrm(0xE8),
+        op(0xD9, M::Fldl2t, &[]).modrm(0xE9),
+        op(0xD9, M::Fldl2e, &[]).modrm(0xEA),
+        op(0xD9, M::Fldpi, &[]).modrm(0xEB),
+        op(0xD9, M::Fldlg2, &[]).modrm(0xEC),
+        op(0xD9, M::Fldln2, &[]).modrm(0xED),
+        op(0xD9, M::Fldz, &[]).modrm(0xEE),
+        op(0xD9, M::F2xm1, &[]).modrm(0xF0),
+        op(0xD9, M::Fyl2x, &[]).modrm(0xF1),
+        op(0xD9, M::Fptan, &[]).modrm(0xF2),
+        op(0xD9, M::Fpatan, &[]).modrm(0xF3),
+        op(0xD9, M::Fxtract, &[]).modrm(0xF4),
+        op(0xD9, M::Fprem1, &[]).modrm(0xF5),
+        op(0xD9, M::Fdecstp, &[]).modrm(0xF6),
+        op(0xD9, M::Fincstp, &[]).modrm(0xF7),
+        op(0xD9, M::Fprem, &[]).modrm(0xF8),
+        op(0xD9, M::Fyl2xp1, &[]).modrm(0xF9),
+        op(0xD9, M::Fsqrt, &[]).modrm(0xFA),
+        op(0xD9, M::Fsincos, &[]).modrm(0xFB),
+        op(0xD9, M::Frndint, &[]).modrm(0xFC),
+        op(0xD9, M::Fscale, &[]).modrm(0xFD),
+        op(0xD9, M::Fsin, &[]).modrm(0xFE),
+        op(0xD9, M::Fcos, &[]).modrm(0xFF),
+    ],
+    &x87_arith_mem(0xDA, &[Md], true),
+    &[
+        op(0xDA, M::Fcmovb, &[ST0, STi]).digit(0),
+        op(0xDA, M::Fcmove, &[ST0, STi]).digit(1),
+        op(0xDA, M::Fcmovbe, &[ST0, STi]).digit(2),
+        op(0xDA, M::Fcmovu, &[ST0, STi]).digit(3),
+        op(0xDA, M::Fucompp, &[]).modrm(0xE9),
+        op(0xDB, M::Fild, &[Md]).digit(0),
+        op(0xDB, M::Fisttp, &[Md]).digit(1),
+        op(0xDB, M::Fist, &[Md]).digit(2),
+        op(0xDB, M::Fistp, &[Md]).digit(3),
+        op(0xDB, M::Fld, &[Mt]).digit(5),
+        op(0xDB, M::Fstp, &[Mt]).digit(7),
+        op(0xDB, M::Fcmovnb, &[ST0, STi]).digit(0),
+        op(0xDB, M::Fcmovne, &[ST0, STi]).digit(1),
+        op(0xDB, M::Fcmovnbe, &[ST0, STi]).digit(2),
+        op(0xDB, M::Fcmovnu, &[ST0, STi]).digit(3),
+        op(0xDB, M::Fnclex, &[]).modrm(0xE2),
+        op(0xDB, M::Fninit, &[]).modrm(0xE3),
+        op(0xDB, M::Fucomi, &[ST0, STi]).digit(5),
+        op(0xDB, M::Fcomi, &[ST0, STi]).digit(6),
+    ],
+    &x87_arith_mem(0xDC, &[Mq], false),
+    &[
+        op(0xDC, M::Fadd, &[STi, ST0]).digit(0),
+        op(0xDC, M::Fmul, &[STi, ST0]).digit(1),
+        op(0xDC, M::Fsubr, &[STi, ST0]).digit(4),
+        op(0xDC, M::Fsub, &[STi, ST0]).digit(5),
+        op(0xDC, M::Fdivr, &[STi, ST0]).digit(6),
+        op(0xDC, M::Fdiv, &[STi, ST0]).digit(7),
+        op(0xDD, M::Fld, &[Mq]).digit(0),
+        op(0xDD, M::Fisttp, &[Mq]).digit(1),
+        op(0xDD, M::Fst, &[Mq]).digit(2),
+        op(0xDD, M::Fstp, &[Mq]).digit(3),
+        op(0xDD, M::Frstor, &[M_]).digit(4),
+        op(0xDD, M::Fnsave, &[M_]).digit(6),
+        op(0xDD, M::Fnstsw, &[Mw]).digit(7),
+        op(0xDD, M::Ffree, &[STi]).digit(0),
+        op(0xDD, M::Fst, &[STi]).digit(2),
+        op(0xDD, M::Fstp, &[STi]).digit(3),
+        op(0xDD, M::Fucom, &[STi]).digit(4),
+        op(0xDD, M::Fucomp, &[STi]).digit(5),
+    ],
+    &x87_arith_mem(0xDE, &[Mw], true),
+    &[
+        op(0xDE, M::Faddp, &[STi, ST0]).digit(0),
+        op(0xDE, M::Fmulp, &[STi, ST0]).digit(1),
+        op(0xDE, M::Fcompp, &[]).modrm(0xD9),
+        op(0xDE, M::Fsubrp, &[STi, ST0]).digit(4),
+        op(0xDE, M::Fsubp, &[STi, ST0]).digit(5),
+        op(0xDE, M::Fdivrp, &[STi, ST0]).digit(6),
+        op(0xDE, M::Fdivp, &[STi, ST0]).digit(7),
+        op(0xDF, M::Fild, &[Mw]).digit(0),
+        op(0xDF, M::Fisttp, &[Mw]).digit(1),
+        op(0xDF, M::Fist, &[Mw]).digit(2),
+        op(0xDF, M::Fistp, &[Mw]).digit(3),
+        op(0xDF, M::Fbld, &[Mt]).digit(4),
+        op(0xDF, M::Fild, &[Mq]).digit(5),
+        op(0xDF, M::Fbstp, &[Mt]).digit(6),
+        op(0xDF, M::Fistp, &[Mq]).digit(7),
+        op(0xDF, M::Fnstsw, &[Op::Acc(Sz::W)]).modrm(0xE0),
+        op(0xDF, M::Fucomip, &[ST0, STi]).digit(5),
+        op(0xDF, M::Fcomip, &[ST0, STi]).digit(6),
+    ],
+    &[
+        op(0xE0, M::Loopne, &[Jb]).f(F64),
+        op(0xE1, M::Loope, &[Jb]).f(F64),
+        op(0xE2, M::Loop, &[Jb]).f(F64),
+        op(0xE3, M::Jcxz, &[Jb]).a16().f(F64),
+        op(0xE3, M::Jecxz, &[Jb]).a32().f(F64),
+        op(0xE3, M::Jrcxz, &[Jb]).a64().f(F64),
+        op(0xE4, M::In, &[AL, Ib]),
+        op(0xE5, M::In, &[eAX, Ib]),
+        op(0xE6, M::Out, &[Ib, AL]),
+        op(0xE7, M::Out, &[Ib, eAX]),
+        op(0xE8, M::Call, &[Jz]).f(D64 | BND),
+        op(0xE9, M::Jmp, &[Jz]).f(F64 | BND),
+        op(0xEA, M::Jmpf, &[Op::A]).f(I64),
+        op(0xEB, M::Jmp, &[Jb]).f(F64 | BND),
+        op(0xEC, M::In, &[AL, DX]),
+        op(0xED, M::In, &[eAX, DX]),
+        op(0xEE, M::Out, &[DX, AL]),
+        op(0xEF, M::Out, &[DX, eAX]),
+        op(0xF1, M::Int1, &[]),
+        op(0xF4, M::Hlt, &[]),
+        op(0xF5, M::Cmc, &[]),
+        /* group 3 */
+        op(0xF6, M::Test, &[Eb, Ib]).digit(0),
+        op(0xF6, M::Test, &[Eb, Ib]).digit(1),
+        op(0xF6, M::Not, &[Eb]).digit(2).f(LOCK),
+        op(0xF6, M::Neg, &[Eb]).digit(3).f(LOCK),
+        op(0xF6, M::Mul, &[Eb]).digit(4),
+        op(0xF6, M::Imul, &[Eb]).digit(5),
+        op(0xF6, M::Div, &[Eb]).digit(6),
+        op(0xF6, M::Idiv, &[Eb]).digit(7),
+        op(0xF7, M::Test, &[Ev, Iz]).digit(0),
+        op(0xF7, M::Test, &[Ev, Iz]).digit(1),
+        op(0xF7, M::Not, &[Ev]).digit(2).f(LOCK),
+        op(0xF7, M::Neg, &[Ev]).digit(3).f(LOCK),
+        op(0xF7, M::Mul, &[Ev]).digit(4),
+        op(0xF7, M::Imul, &[Ev]).digit(5),
+        op(0xF7, M::Div, &[Ev]).digit(6),
+        op(0xF7, M::Idiv, &[Ev]).digit(7),
+        op(0xF8, M::Clc, &[]),
+        op(0xF9, M::Stc, &[]),
+        op(0xFA, M::Cli, &[]),
+        op(0xFB, M::Sti, &[]),
+        op(0xFC, M::Cld, &[]),
+        op(0xFD, M::Std, &[]),
+        /* group 4 */
+        op(0xFE, M::Inc, &[Eb]).digit(0).f(LOCK),
+        op(0xFE, M::Dec, &[Eb]).digit(1).f(LOCK),
+        /* group 5 */
+        op(0xFF, M::Inc, &[Ev]).digit(0).f(LOCK),
+        op(0xFF, M::Dec, &[Ev]).digit(1).f(LOCK),
+        op(0xFF, M::Call, &[Ev]).digit(2).f(D64 | BND),
+        op(0xFF, M::Callf, &[Mp]).digit(3),
+        op(0xFF, M::Jmp, &[Ev]).digit(4).f(F64 | BND),
+        op(0xFF, M::Jmpf, &[Mp]).digit(5),
+        op(0xFF, M::Push, &[Ev]).digit(6).f(D64),
+    ],
+];
+
+/*
+    Two byte opcode map (0F xx)
+*/
+
+static TWO_BYTE: &[&[Entry]] = &[
+    &[
+        /* group 6 */
+        op(0x00, M::Sldt, &[Rv_Mw]).digit(0),
+        op(0x00, M::Str, &[Rv_Mw]).digit(1),
+        op(0x00, M::Lldt, &[Ew]).digit(2),
+        op(0x00, M::Ltr, &[Ew]).digit(3),
+        op(0x00, M::Verr, &[Ew]).digit(4),
+        op(0x00, M::Verw, &[Ew]).digit(5),
+        /* group 7, register forms first as they use the whole ModR/M byte */
+        op(0x01, M::Vmcall, &[]).modrm(0xC1),
+        op(0x01, M::Vmlaunch, &[]).modrm(0xC2),
+        op(0x01, M::Vmresume, &[]).modrm(0xC3),
+        op(0x01, M::Vmxoff, &[]).modrm(0xC4),
+        op(0x01, M::Monitor, &[]).modrm(0xC8),
+        op(0x01, M::Mwait, &[]).modrm(0xC9),
+        op(0x01, M::Clac, &[]).modrm(0xCA),
+        op(0x01, M::Stac, &[]).modrm(0xCB),
+        op(0x01, M::Xgetbv, &[]).modrm(0xD0),
+        op(0x01, M::Xsetbv, &[]).modrm(0xD1),
+        op(0x01, M::Vmfunc, &[]).modrm(0xD4),
+        op(0x01, M::Xend, &[]).modrm(0xD5),
+        op(0x01, M::Xtest, &[]).modrm(0xD6),
+        op(0x01, M::Rdpkru, &[]).modrm(0xEE),
+        op(0x01, M::Wrpkru, &[]).modrm(0xEF),
+        op(0x01, M::Swapgs, &[]).modrm(0xF8).f(O64),
+        op(0x01, M::Rdtscp, &[]).modrm(0xF9),
+        op(0x01, M::Sgdt, &[M_]).digit(0),
+        op(0x01, M::Sidt, &[M_]).digit(1),
+        op(0x01, M::Lgdt, &[M_]).digit(2),
+        op(0x01, M::Lidt, &[M_]).digit(3),
+        op(0x01, M::Smsw, &[Rv_Mw]).digit(4),
+        op(0x01, M::Lmsw, &[Ew]).digit(6),
+        op(0x01, M::Invlpg, &[Mb]).digit(7),
+        op(0x02, M::Lar, &[Gv, Ew]),
+        op(0x03, M::Lsl, &[Gv, Ew]),
+        op(0x05, M::Syscall, &[]),
+        op(0x06, M::Clts, &[]),
+        op(0x07, M::Sysret, &[]),
+        op(0x08, M::Invd, &[]),
+        op(0x09, M::Wbinvd, &[]),
+        op(0x0B, M::Ud2, &[]),
+        op(0x0D, M::Prefetch, &[Mb]).digit(0),
+        op(0x0D, M::Prefetchw, &[Mb]).digit(1),
+        op(0x0D, M::Nop, &[Ev]),
+        /* movups / movss / movlps ... */
+        op(0x10, M::Movups, &[Vx, Wx]).np().vex().evex(),
+        op(0x10, M::Movupd, &[Vx, Wx]).p66().vex().evex().ew1(),
+        op(0x10, M::Movss, &[Vdq, Hdq, Udq]).pf3().vex().evex(),
+        op(0x10, M::Movss, &[Vdq, Md]).pf3().vex().evex(),
+        op(0x10, M::Movsd, &[Vdq, Hdq, Udq]).pf2().vex().evex().ew1(),
+        op(0x10, M::Movsd, &[Vdq, Mq]).pf2().vex().evex().ew1(),
+        op(0x11, M::Movups, &[Wx, Vx]).np().vex().evex(),
+        op(0x11, M::Movupd, &[Wx, Vx]).p66().vex().evex().ew1(),
+        op(0x11, M::Movss, &[Udq, Hdq, Vdq]).pf3().vex().evex(),
+        op(0x11, M::Movss, &[Md, Vdq]).pf3().vex().evex(),
+        op(0x11, M::Movsd, &[Udq, Hdq, Vdq]).pf2().vex().evex().ew1(),
+        op(0x11, M::Movsd, &[Mq, Vdq]).pf2().vex().evex().ew1(),
+        op(0x12, M::Movhlps, &[Vdq, Hdq, Udq]).np().vex().evex(),
+        op(0x12, M::Movlps, &[Vdq, Hdq, Mq]).np().vex().evex(),
+        op(0x12, M::Movlpd, &[Vdq, Hdq, Mq]).p66().vex().evex().ew1(),
+        op(0x12, M::Movsldup, &[Vx, Wx]).pf3().vex().evex(),
+        op(0x12, M::Movddup, &[Vx, Wdup]).pf2().vex().evex().ew1(),
+        op(0x13, M::Movlps, &[Mq, Vdq]).np().vex().evex(),
+        op(0x13, M::Movlpd, &[Mq, Vdq]).p66().vex().evex().ew1(),
+        op(0x14, M::Unpcklps, &[Vx, Hx, Wx]).np().vex().evex(),
+        op(0x14, M::Unpcklpd, &[Vx, Hx, Wx]).p66().vex().evex().ew1(),
+        op(0x15, M::Unpckhps, &[Vx, Hx, Wx]).np().vex().evex(),
+        op(0x15, M::Unpckhpd, &[Vx, Hx, Wx]).p66().vex().evex().ew1(),
+        op(0x16, M::Movlhps, &[Vdq, Hdq, Udq]).np().vex().evex(),
+        op(0x16, M::Movhps, &[Vdq, Hdq, Mq]).np().vex().evex(),
+        op(0x16, M::Movhpd, &[Vdq, Hdq, Mq]).p66().vex().evex().ew1(),
+        op(0x16, M::Movshdup, &[Vx, Wx]).pf3().vex().evex(),
+        op(0x17, M::Movhps, &[Mq, Vdq]).np().vex().evex(),
+        op(0x17, M::Movhpd, &[Mq, Vdq]).p66().vex().evex().ew1(),
+        /* group 16 */
+        op(0x18, M::Prefetchnta, &[Mb]).digit(0),
+        op(0x18, M::Prefetcht0, &[Mb]).digit(1),
+        op(0x18, M::Prefetcht1, &[Mb]).digit(2),
+        op(0x18, M::Prefetcht2, &[Mb]).digit(3),
+        op(0x18, M::Nop, &[Ev]),
+        /* reserved hint nops, apart from mpx and cet's endbr */
+        op(0x19, M::Nop, &[Ev]),
+        op(0x1A, M::Bndldx, &[BG, M_]).np(),
+        op(0x1A, M::Bndmov, &[BG, BE]).p66(),
+        op(0x1A, M::Bndcl, &[BG, Ey]).pf3().f(F64),
+        op(0x1A, M::Bndcu, &[BG, Ey]).pf2().f(F64),
+        op(0x1A, M::Nop, &[Ev]),
+        op(0x1B, M::Bndstx, &[M_, BG]).np(),
+        op(0x1B, M::Bndmov, &[BE, BG]).p66(),
+        op(0x1B, M::Bndmk, &[BG, M_]).pf3().f(F64),
+        op(0x1B, M::Bndcn, &[BG, Ey]).pf2().f(F64),
+        op(0x1B, M::Nop, &[Ev]),
+        op(0x1C, M::Nop, &[Ev]),
+        op(0x1D, M::Nop, &[Ev]),
+        op(0x1E, M::Endbr64, &[]).pf3().modrm(0xFA),
+        op(0x1E, M::Endbr32, &[]).pf3().modrm(0xFB),
+        op(0x1E, M::Nop, &[Ev]),
+        op(0x1F, M::Nop, &[Ev]),
+        op(0x20, M::Mov, &[Rq, Cd]).f(MOD_REG | F64),
+        op(0x21, M::Mov, &[Rq, Dd]).f(MOD_REG | F64),
+        op(0x22, M::Mov, &[Cd, Rq]).f(MOD_REG | F64),
+        op(0x23, M::Mov, &[Dd, Rq]).f(MOD_REG | F64),
+        op(0x28, M::Movaps, &[Vx, Wx]).np().vex().evex(),
+        op(0x28, M::Movapd, &[Vx, Wx]).p66().vex().evex().ew1(),
+        op(0x29, M::Movaps, &[Wx, Vx]).np().vex().evex(),
+        op(0x29, M::Movapd, &[Wx, Vx]).p66().vex().evex().ew1(),
+        op(0x2A, M::Cvtpi2ps, &[Vdq, Qq]).np(),
+        op(0x2A, M::Cvtpi2pd, &[Vdq, Qq]).p66(),
+        op(0x2A, M::Cvtsi2ss, &[Vdq, Hdq, Ey]).pf3().vex().evex(),
+        op(0x2A, M::Cvtsi2sd, &[Vdq, Hdq, Ey]).pf2().vex().evex(),
+        op(0x2B, M::Movntps, &[Mx, Vx]).np().vex().evex(),
+        op(0x2B, M::Movntpd, &[Mx, Vx]).p66().vex().evex().ew1(),
+        op(0x2C, M::Cvttps2pi, &[Pq, Wq]).np(),
+        op(0x2C, M::Cvttpd2pi, &[Pq, Wdq]).p66(),
+        op(0x2C, M::Cvttss2si, &[Gy, Wd]).pf3().vex().evex().f(SAE),
+        op(0x2C, M::Cvttsd2si, &[Gy, Wq]).pf2().vex().evex().f(SAE),
+        op(0x2D, M::Cvtps2pi, &[Pq, Wq]).np(),
+        op(0x2D, M::Cvtpd2pi, &[Pq, Wdq]).p66(),
+        op(0x2D, M::Cvtss2si, &[Gy, Wd]).pf3().vex().evex(),
+        op(0x2D, M::Cvtsd2si, &[Gy, Wq]).pf2().vex().evex(),
+        op(0x2E, M::Ucomiss, &[Vdq, Wd]).np().vex().evex().f(SAE),
+        op(0x2E, M::Ucomisd, &[Vdq, Wq]).p66().vex().evex().ew1().f(SAE),
+        op(0x2F, M::Comiss, &[Vdq, Wd]).np().vex().evex().f(SAE),
+        op(0x2F, M::Comisd, &[Vdq, Wq]).p66().vex().evex().ew1().f(SAE),
+        op(0x30, M::Wrmsr, &[]),
+        op(0x31, M::Rdtsc, &[]),
+        op(0x32, M::Rdmsr, &[]),
+        op(0x33, M::Rdpmc, &[]),
+        op(0x34, M::Sysenter, &[]),
+        op(0x35, M::Sysexit, &[]),
+        op(0x37, M::Getsec, &[]),
+    ],
+    &cc_group(0x40, &CMOVCC, &[Gv, Ev], 0),
+    &[
+        op(0x50, M::Movmskps, &[Gy, Ux]).np().vex(),
+        op(0x50, M::Movmskpd, &[Gy, Ux]).p66().vex(),
+        op(0x51, M::Sqrtps, &[Vx, Wx]).np().vex().evex(),
+        op(0x51, M::Sqrtpd, &[Vx, Wx]).p66().vex().evex().ew1(),
+        op(0x51, M::Sqrtss, &[Vdq, Hdq, Wd]).pf3().vex().evex(),
+        op(0x51, M::Sqrtsd, &[Vdq, Hdq, Wq]).pf2().vex().evex().ew1(),
+        op(0x52, M::Rsqrtps, &[Vx, Wx]).np().vex(),
+        op(0x52, M::Rsqrtss, &[Vdq, Hdq, Wd]).pf3().vex(),
+        op(0x53, M::Rcpps, &[Vx, Wx]).np().vex(),
+        op(0x53, M::Rcpss, &[Vdq, Hdq, Wd]).pf3().vex(),
+        op(0x54, M::Andps, &[Vx, Hx, Wx]).np().vex().evex(),
+        op(0x54, M::Andpd, &[Vx, Hx, Wx]).p66().vex().evex().ew1(),
+        op(0x55, M::Andnps, &[Vx, Hx, Wx]).np().vex().evex(),
+        op(0x55, M::Andnpd, &[Vx, Hx, Wx]).p66().vex().evex().ew1(),
+        op(0x56, M::Orps, &[Vx, Hx, Wx]).np().vex().evex(),
+        op(0x56, M::Orpd, &[Vx, Hx, Wx]).p66().vex().evex().ew1(),
+        op(0x57, M::Xorps, &[Vx, Hx, Wx]).np().vex().evex(),
+        op(0x57, M::Xorpd, &[Vx, Hx, Wx]).p66().vex().evex().ew1(),
+    ],
+    &sse_ps_pd_ss_sd(0x58, [M::Addps, M::Addpd, M::Addss, M::Addsd], 0),
+    &sse_ps_pd_ss_sd(0x59, [M::Mulps, M::Mulpd, M::Mulss, M::Mulsd], 0),
+    &[
+        op(0x5A, M::Cvtps2pd, &[Vx, Wh]).np().vex().evex().f(SAE),
+        op(0x5A, M::Cvtpd2ps, &[Vh, Wx]).p66().vex().evex().ew1(),
+        op(0x5A, M::Cvtss2sd, &[Vdq, Hdq, Wd]).pf3().vex().evex().f(SAE),
+        op(0x5A, M::Cvtsd2ss, &[Vdq, Hdq, Wq]).pf2().vex().evex().ew1(),
+        op(0x5B, M::Cvtdq2ps, &[Vx, Wx]).np().vex(),
+        op(0x5B, M::Cvtps2dq, &[Vx, Wx]).p66().vex().evex(),
+        op(0x5B, M::Cvttps2dq, &[Vx, Wx]).pf3().vex().evex().f(SAE),
+    ],
+    &sse_ps_pd_ss_sd(0x5C, [M::Subps, M::Subpd, M::Subss, M::Subsd], 0),
+    &sse_ps_pd_ss_sd(0x5D, [M::Minps, M::Minpd, M::Minss, M::Minsd], SAE),
+    &sse_ps_pd_ss_sd(0x5E, [M::Divps, M::Divpd, M::Divss, M::Divsd], 0),
+    &sse_ps_pd_ss_sd(0x5F, [M::Maxps, M::Maxpd, M::Maxss, M::Maxsd], SAE),
+    &[
+        op(0x60, M::Punpcklbw, &[Pq, Qd]).np(),
+        op(0x60, M::Punpcklbw, &[Vx, Hx, Wx]).p66().vex().evex(),
+        op(0x61, M::Punpcklwd, &[Pq, Qd]).np(),
+        op(0x61, M::Punpcklwd, &[Vx, Hx, Wx]).p66().vex().evex(),
+        op(0x62, M::Punpckldq, &[Pq, Qd]).np(),
+        op(0x62, M::Punpckldq, &[Vx, Hx, Wx]).p66().vex().evex(),
+    ],
+    &mmx_sse(0x63, M::Packsswb, VEX | EVEX),
+    &mmx_sse(0x64, M::Pcmpgtb, VEX),
+    &mmx_sse(0x65, M::Pcmpgtw, VEX),
+    &mmx_sse(0x66, M::Pcmpgtd, VEX),
+    &mmx_sse(0x67, M::Packuswb, VEX | EVEX),
+    &mmx_sse(0x68, M::Punpckhbw, VEX | EVEX),
+    &mmx_sse(0x69, M::Punpckhwd, VEX | EVEX),
+    &mmx_sse(0x6A, M::Punpckhdq, VEX | EVEX),
+    &mmx_sse(0x6B, M::Packssdw, VEX | EVEX),
+    &[
+        op(0x6C, M::Punpcklqdq, &[Vx, Hx, Wx]).p66().vex().evex().ew1(),
+        op(0x6D, M::Punpckhqdq, &[Vx, Hx, Wx]).p66().vex().evex().ew1(),
+        op(0x6E, M::Movd, &[Pq, Ed]).np().w0(),
+        op(0x6E, M::Movq, &[Pq, Eq]).np().w1(),
+        op(0x6E, M::Movd, &[Vdq, Ed]).p66().w0().vex().evex(),
+        op(0x6E, M::Movq, &[Vdq, Eq]).p66().w1().vex().evex(),
+        op(0x6F, M::Movq, &[Pq, Qq]).np(),
+        op(0x6F, M::Movdqa, &[Vx, Wx]).p66().vex(),
+        op(0x6F, M::Movdqu, &[Vx, Wx]).pf3().vex(),
+        op(0x70, M::Pshufw, &[Pq, Qq, Ib]).np(),
+        op(0x70, M::Pshufd, &[Vx, Wx, Ib]).p66().vex().evex(),
+        op(0x70, M::Pshufhw, &[Vx, Wx, Ib]).pf3().vex().evex(),
+        op(0x70, M::Pshuflw, &[Vx, Wx, Ib]).pf2().vex().evex(),
+        /* groups 12, 13, 14: shift by immediate */
+        op(0x71, M::Psrlw, &[Nq, Ib]).np().digit(2),
+        op(0x71, M::Psrlw, &[Hx, Ux, Ib]).p66().digit(2).vex().evex(),
+        op(0x71, M::Psraw, &[Nq, Ib]).np().digit(4),
+        op(0x71, M::Psraw, &[Hx, Ux, Ib]).p66().digit(4).vex().evex(),
+        op(0x71, M::Psllw, &[Nq, Ib]).np().digit(6),
+        op(0x71, M::Psllw, &[Hx, Ux, Ib]).p66().digit(6).vex().evex(),
+        op(0x72, M::Psrld, &[Nq, Ib]).np().digit(2),
+        op(0x72, M::Psrld, &[Hx, Ux, Ib]).p66().digit(2).vex().evex(),
+        op(0x72, M::Psrad, &[Nq, Ib]).np().digit(4),
+        op(0x72, M::Psrad, &[Hx, Ux, Ib]).p66().digit(4).vex(),
+        op(0x72, M::Pslld, &[Nq, Ib]).np().digit(6),
+        op(0x72, M::Pslld, &[Hx, Ux, Ib]).p66().digit(6).vex().evex(),
+        op(0x73, M::Psrlq, &[Nq, Ib]).np().digit(2),
+        op(0x73, M::Psrlq, &[Hx, Ux, Ib]).p66().digit(2).vex().evex().ew1(),
+        op(0x73, M::Psrldq, &[Hx, Ux, Ib]).p66().digit(3).vex().evex(),
+        op(0x73, M::Psllq, &[Nq, Ib]).np().digit(6),
+        op(0x73, M::Psllq, &[Hx, Ux, Ib]).p66().digit(6).vex().evex().ew1(),
+        op(0x73, M::Pslldq, &[Hx, Ux, Ib]).p66().digit(7).vex().evex(),
+    ],
+    &mmx_sse(0x74, M::Pcmpeqb, VEX),
+    &mmx_sse(0x75, M::Pcmpeqw, VEX),
+    &mmx_sse(0x76, M::Pcmpeqd, VEX),
+    &[
+        op(0x77, M::Emms, &[]).np(),
+        op(0x78, M::Vmread, &[Ey, Gy]).np().f(F64),
+        op(0x79, M::Vmwrite, &[Gy, Ey]).np().f(F64),
+        op(0x7C, M::Haddpd, &[Vx, Hx, Wx]).p66().vex(),
+        op(0x7C, M::Haddps, &[Vx, Hx, Wx]).pf2().vex(),
+        op(0x7D, M::Hsubpd, &[Vx, Hx, Wx]).p66().vex(),
+        op(0x7D, M::Hsubps, &[Vx, Hx, Wx]).pf2().vex(),
+        op(0x7E, M::Movd, &[Ed, Pq]).np().w0(),
+        op(0x7E, M::Movq, &[Eq, Pq]).np().w1(),
+        op(0x7E, M::Movd, &[Ed, Vdq]).p66().w0().vex().evex(),
+        op(0x7E, M::Movq, &[Eq, Vdq]).p66().w1().vex().evex(),
+        op(0x7E, M::Movq, &[Vdq, Wq]).pf3().vex().evex().ew1(),
+        op(0x7F, M::Movq, &[Qq, Pq]).np(),
+        op(0x7F, M::Movdqa, &[Wx, Vx]).p66().vex(),
+        op(0x7F, M::Movdqu, &[Wx, Vx]).pf3().vex(),
+    ],
+    &cc_group(0x80, &JCC, &[Jz], F64 | BND),
+    &cc_group(0x90, &SETCC, &[Eb], 0),
+    &[
+        op(0xA0, M::Push, &[Op::Seg(4)]).f(D64),
+        op(0xA1, M::Pop, &[Op::Seg(4)]).f(D64),
+        op(0xA2, M::Cpuid, &[]),
+        op(0xA3, M::Bt, &[Ev, Gv]),
+        op(0xA4, M::Shld, &[Ev, Gv, Ib]),
+        op(0xA5, M::Shld, &[Ev, Gv, CL]),
+        op(0xA8, M::Push, &[Op::Seg(5)]).f(D64),
+        op(0xA9, M::Pop, &[Op::Seg(5)]).f(D64),
+        op(0xAA, M::Rsm, &[]),
+        op(0xAB, M::Bts, &[Ev, Gv]).f(LOCK),
+        op(0xAC, M::Shrd, &[Ev, Gv, Ib]),
+        op(0xAD, M::Shrd, &[Ev, Gv, CL]),
+        /* group 15 */
+        op(0xAE, M::Rdfsbase, &[Ry]).pf3().digit(0),
+        op(0xAE, M::Rdgsbase, &[Ry]).pf3().digit(1),
+        op(0xAE, M::Wrfsbase, &[Ry]).pf3().digit(2),
+        op(0xAE, M::Wrgsbase, &[Ry]).pf3().digit(3),
+        op(0xAE, M::Lfence, &[]).np().digit(5).reg(),
+        op(0xAE, M::Mfence, &[]).np().digit(6).reg(),
+        op(0xAE, M::Sfence, &[]).np().digit(7).reg(),
+        op(0xAE, M::Fxsave64, &[M_]).np().digit(0).w1(),
+        op(0xAE, M::Fxsave, &[M_]).np().digit(0),
+        op(0xAE, M::Fxrstor64, &[M_]).np().digit(1).w1(),
+        op(0xAE, M::Fxrstor, &[M_]).np().digit(1),
+        op(0xAE, M::Ldmxcsr, &[Md]).np().digit(2).vex(),
+        op(0xAE, M::Stmxcsr, &[Md]).np().digit(3).vex(),
+        op(0xAE, M::Xsave64, &[M_]).np().digit(4).w1(),
+        op(0xAE, M::Xsave, &[M_]).np().digit(4),
+        op(0xAE, M::Xrstor64, &[M_]).np().digit(5).w1(),
+        op(0xAE, M::Xrstor, &[M_]).np().digit(5),
+        op(0xAE, M::Xsaveopt64, &[M_]).np().digit(6).w1(),
+        op(0xAE, M::Xsaveopt, &[M_]).np().digit(6),
+        op(0xAE, M::Clwb, &[Mb]).p66().digit(6),
+        op(0xAE, M::Clflush, &[Mb]).np().digit(7),
+        op(0xAE, M::Clflushopt, &[Mb]).p66().digit(7),
+        op(0xAF, M::Imul, &[Gv, Ev]),
+        op(0xB0, M::Cmpxchg, &[Eb, Gb]).f(LOCK),
+        op(0xB1, M::Cmpxchg, &[Ev, Gv]).f(LOCK),
+        op(0xB2, M::Lss, &[Gv, Mp]),
+        op(0xB3, M::Btr, &[Ev, Gv]).f(LOCK),
+        op(0xB4, M::Lfs, &[Gv, Mp]),
+        op(0xB5, M::Lgs, &[Gv, Mp]),
+        op(0xB6, M::Movzx, &[Gv, Eb]),
+        op(0xB7, M::Movzx, &[Gv, Ew]),
+        op(0xB8, M::Popcnt, &[Gv, Ev]).pf3(),
+        op(0xB9, M::Ud1, &[Gv, Ev]),
+        /* group 8 */
+        op(0xBA, M::Bt, &[Ev, Ib]).digit(4),
+        op(0xBA, M::Bts, &[Ev, Ib]).digit(5).f(LOCK),
+        op(0xBA, M::Btr, &[Ev, Ib]).digit(6).f(LOCK),
+        op(0xBA, M::Btc, &[Ev, Ib]).digit(7).f(LOCK),
+        op(0xBB, M::Btc, &[Ev, Gv]).f(LOCK),
+        op(0xBC, M::Tzcnt, &[Gv, Ev]).pf3(),
+        op(0xBC, M::Bsf, &[Gv, Ev]),
+        op(0xBD, M::Lzcnt, &[Gv, Ev]).pf3(),
+        op(0xBD, M::Bsr, &[Gv, Ev]),
+        op(0xBE, M::Movsx, &[Gv, Eb]),
+        op(0xBF, M::Movsx, &[Gv, Ew]),
+        op(0xC0, M::Xadd, &[Eb, Gb]).f(LOCK),
+        op(0xC1, M::Xadd, &[Ev, Gv]).f(LOCK),
+        op(0xC2, M::Cmpps, &[Vx, Hx, Wx, Ib]).np().vex(),
+        op(0xC2, M::Cmppd, &[Vx, Hx, Wx, Ib]).p66().vex(),
+        op(0xC2, M::Cmpss, &[Vdq, Hdq, Wd, Ib]).pf3().vex(),
+        op(0xC2, M::Cmpsd, &[Vdq, Hdq, Wq, Ib]).pf2().vex(),
+        op(0xC3, M::Movnti, &[My, Gy]).np(),
+        op(0xC4, M::Pinsrw, &[Pq, Ry_Mw, Ib]).np(),
+        op(0xC4, M::Pinsrw, &[Vdq, Hdq, Ry_Mw, Ib]).p66().vex().evex(),
+        op(0xC5, M::Pextrw, &[Gd, Nq, Ib]).np(),
+        op(0xC5, M::Pextrw, &[Gd, Udq, Ib]).p66().vex().evex(),
+        op(0xC6, M::Shufps, &[Vx, Hx, Wx, Ib]).np().vex().evex(),
+        op(0xC6, M::Shufpd, &[Vx, Hx, Wx, Ib]).p66().vex().evex().ew1(),
+        /* group 9 */
+        op(0xC7, M::Cmpxchg16b, &[Mdq]).digit(1).w1().f(LOCK),
+        op(0xC7, M::Cmpxchg8b, &[Mq]).digit(1).f(LOCK),
+        op(0xC7, M::Xrstors64, &[M_]).np().digit(3).w1(),
+        op(0xC7, M::Xrstors, &[M_]).np().digit(3),
+        op(0xC7, M::Xsavec64, &[M_]).np().digit(4).w1(),
+        op(0xC7, M::Xsavec, &[M_]).np().digit(4),
+        op(0xC7, M::Xsaves64, &[M_]).np().digit(5).w1(),
+        op(0xC7, M::Xsaves, &[M_]).np().digit(5),
+        op(0xC7, M::Vmptrld, &[Mq]).np().digit(6),
+        op(0xC7, M::Vmclear, &[Mq]).p66().digit(6),
+        op(0xC7, M::Vmxon, &[Mq]).pf3().digit(6),
+        op(0xC7, M::Vmptrst, &[Mq]).np().digit(7),
+        op(0xC7, M::Rdrand, &[Rv]).digit(6),
+        op(0xC7, M::Rdpid, &[Rq]).pf3().digit(7),
+        op(0xC7, M::Rdseed, &[Rv]).digit(7),
+        op(0xC8, M::Bswap, &[Zy]),
+        op(0xD0, M::Addsubpd, &[Vx, Hx, Wx]).p66().vex(),
+        op(0xD0, M::Addsubps, &[Vx, Hx, Wx]).pf2().vex(),
+        op(0xD1, M::Psrlw, &[Pq, Qq]).np(),
+        op(0xD1, M::Psrlw, &[Vx, Hx, Wdq]).p66().vex().evex(),
+        op(0xD2, M::Psrld, &[Pq, Qq]).np(),
+        op(0xD2, M::Psrld, &[Vx, Hx, Wdq]).p66().vex().evex(),
+        op(0xD3, M::Psrlq, &[Pq, Qq]).np(),
+        op(0xD3, M::Psrlq, &[Vx, Hx, Wdq]).p66().vex().evex().ew1(),
+    ],
+    &evex_w1(mmx_sse(0xD4, M::Paddq, VEX | EVEX)),
+    &mmx_sse(0xD5, M::Pmullw, VEX | EVEX),
+    &[
+        op(0xD6, M::Movq, &[Wq, Vdq]).p66().vex().evex().ew1(),
+        op(0xD6, M::Movq2dq, &[Vdq, Nq]).pf3(),
+        op(0xD6, M::Movdq2q, &[Pq, Udq]).pf2(),
+        op(0xD7, M::Pmovmskb, &[Gd, Nq]).np(),
+        op(0xD7, M::Pmovmskb, &[Gd, Ux]).p66().vex(),
+    ],
+    &mmx_sse(0xD8, M::Psubusb, VEX | EVEX),
+    &mmx_sse(0xD9, M::Psubusw, VEX | EVEX),
+    &mmx_sse(0xDA, M::Pminub, VEX | EVEX),
+    &mmx_sse(0xDB, M::Pand, VEX),
+    &mmx_sse(0xDC, M::Paddusb, VEX | EVEX),
+    &mmx_sse(0xDD, M::Paddusw, VEX | EVEX),
+    &mmx_sse(0xDE, M::Pmaxub, VEX | EVEX),
+    &mmx_sse(0xDF, M::Pandn, VEX),
+    &mmx_sse(0xE0, M::Pavgb, VEX | EVEX),
+    &[
+        op(0xE1, M::Psraw, &[Pq, Qq]).np(),
+        op(0xE1, M::Psraw, &[Vx, Hx, Wdq]).p66().vex().evex(),
+        op(0xE2, M::Psrad, &[Pq, Qq]).np(),
+        op(0xE2, M::Psrad, &[Vx, Hx, Wdq]).p66().vex(),
+    ],
+    &mmx_sse(0xE3, M::Pavgw, VEX | EVEX),
+    &mmx_sse(0xE4, M::Pmulhuw, VEX | EVEX),
+    &mmx_sse(0xE5, M::Pmulhw, VEX | EVEX),
+    &[
+        op(0xE6, M::Cvttpd2dq, &[Vh, Wx]).p66().vex().evex().ew1().f(SAE),
+        op(0xE6, M::Cvtdq2pd, &[Vx, Wh]).pf3().vex(),
+        op(0xE6, M::Cvtpd2dq, &[Vh, Wx]).pf2().vex().evex().ew1(),
+        op(0xE7, M::Movntq, &[Mq, Pq]).np(),
+        op(0xE7, M::Movntdq, &[Mx, Vx]).p66().vex().evex(),
+    ],
+    &mmx_sse(0xE8, M::Psubsb, VEX | EVEX),
+    &mmx_sse(0xE9, M::Psubsw, VEX | EVEX),
+    &mmx_sse(0xEA, M::Pminsw, VEX | EVEX),
+    &mmx_sse(0xEB, M::Por, VEX),
+    &mmx_sse(0xEC, M::Paddsb, VEX | EVEX),
+    &mmx_sse(0xED, M::Paddsw, VEX | EVEX),
+    &mmx_sse(0xEE, M::Pmaxsw, VEX | EVEX),
+    &mmx_sse(0xEF, M::Pxor, VEX),
+    &[
+        op(0xF0, M::Lddqu, &[Vx, Mx]).pf2().vex(),
+        op(0xF1, M::Psllw, &[Pq, Qq]).np(),
+        op(0xF1, M::Psllw, &[Vx, Hx, Wdq]).p66().vex().evex(),
+        op(0xF2, M::Pslld, &[Pq, Qq]).np(),
+        op(0xF2, M::Pslld, &[Vx, Hx, Wdq]).p66().vex().evex(),
+        op(0xF3, M::Psllq, &[Pq, Qq]).np(),
+        op(0xF3, M::Psllq, &[Vx, Hx, Wdq]).p66().vex().evex().ew1(),
+    ],
+    &evex_w1(mmx_sse(0xF4, M::Pmuludq, VEX | EVEX)),
+    &mmx_sse(0xF5, M::Pmaddwd, VEX | EVEX),
+    &mmx_sse(0xF6, M::Psadbw, VEX | EVEX),
+    &[
+        op(0xF7, M::Maskmovq, &[Pq, Nq]).np(),
+        op(0xF7, M::Maskmovdqu, &[Vdq, Udq]).p66().vex(),
+    ],
+    &mmx_sse(0xF8, M::Psubb, VEX | EVEX),
+    &mmx_sse(0xF9, M::Psubw, VEX | EVEX),
+    &mmx_sse(0xFA, M::Psubd, VEX | EVEX),
+    &evex_w1(mmx_sse(0xFB, M::Psubq, VEX | EVEX)),
+    &mmx_sse(0xFC, M::Paddb, VEX | EVEX),
+    &mmx_sse(0xFD, M::Paddw, VEX | EVEX),
+    &mmx_sse(0xFE, M::Paddd, VEX | EVEX),
+    &[op(0xFF, M::Ud0, &[Gd, Ed])],
+    /* AVX */
+    &[
+        vop(0x77, M::Vzeroupper, &[]).np().l0(),
+        vop(0x77, M::Vzeroall, &[]).np().l1(),
+    ],
+    /* AVX-512 opmask instructions */
+    &opmask_binary(0x41, [M::Kandb, M::Kandw, M::Kandd, M::Kandq]),
+    &opmask_binary(0x42, [M::Kandnb, M::Kandnw, M::Kandnd, M::Kandnq]),
+    &opmask_unary(0x44, [M::Knotb, M::Knotw, M::Knotd, M::Knotq]),
+    &opmask_binary(0x45, [M::Korb, M::Korw, M::Kord, M::Korq]),
+    &opmask_binary(0x46, [M::Kxnorb, M::Kxnorw, M::Kxnord, M::Kxnorq]),
+    &opmask_binary(0x47, [M::Kxorb, M::Kxorw, M::Kxord, M::Kxorq]),
+    &opmask_binary(0x4A, [M::Kaddb, M::Kaddw, M::Kaddd, M::Kaddq]),
+    &opmask_unary(0x98, [M::Kortestb, M::Kortestw, M::Kortestd, M::Kortestq]),
+    &opmask_unary(0x99, [M::Ktestb, M::Ktestw, M::Ktestd, M::Ktestq]),
+    &[
+        vop(0x4B, M::Kunpckbw, &[KG, KH, KU]).p66().w0().l1().reg(),
+        vop(0x4B, M::Kunpckwd, &[KG, KH, KU]).np().w0().l1().reg(),
+        vop(0x4B, M::Kunpckdq, &[KG, KH, KU]).np().w1().l1().reg(),
+        vop(0x90, M::Kmovb, &[KG, KEb]).p66().w0().l0(),
+        vop(0x90, M::Kmovw, &[KG, KEw]).np().w0().l0(),
+        vop(0x90, M::Kmovd, &[KG, KEd]).p66().w1().l0(),
+        vop(0x90, M::Kmovq, &[KG, KEq]).np().w1().l0(),
+        vop(0x91, M::Kmovb, &[KEb, KG]).p66().w0().l0().mem(),
+        vop(0x91, M::Kmovw, &[KEw, KG]).np().w0().l0().mem(),
+        vop(0x91, M::Kmovd, &[KEd, KG]).p66().w1().l0().mem(),
+        vop(0x91, M::Kmovq, &[KEq, KG]).np().w1().l0().mem(),
+        vop(0x92, M::Kmovb, &[KG, Rd]).p66().w0().l0(),
+        vop(0x92, M::Kmovw, &[KG, Rd]).np().w0().l0(),
+        vop(0x92, M::Kmovd, &[KG, Rd]).pf2().w0().l0(),
+        vop(0x92, M::Kmovq, &[KG, Rq]).pf2().w1().l0(),
+        vop(0x93, M::Kmovb, &[Gd, KU]).p66().w0().l0().reg(),
+        vop(0x93, M::Kmovw, &[Gd, KU]).np().w0().l0().reg(),
+        vop(0x93, M::Kmovd, &[Gd, KU]).pf2().w0().l0().reg(),
+        vop(0x93, M::Kmovq, &[Gq, KU]).pf2().w1().l0().reg(),
+    ],
+    /* AVX-512 */
+    &[
+        eop(0x5B, M::Vcvtdq2ps, &[Vx, Wx]).np().w0(),
+        eop(0x5B, M::Vcvtqq2ps, &[Vh, Wx]).np().w1(),
+        eop(0x64, M::Vpcmpgtb, &[KG, Hx, Wx]).p66(),
+        eop(0x65, M::Vpcmpgtw, &[KG, Hx, Wx]).p66(),
+        eop(0x66, M::Vpcmpgtd, &[KG, Hx, Wx]).p66().w0(),
+        eop(0x6F, M::Vmovdqa32, &[Vx, Wx]).p66().w0(),
+        eop(0x6F, M::Vmovdqa64, &[Vx, Wx]).p66().w1(),
+        eop(0x6F, M::Vmovdqu32, &[Vx, Wx]).pf3().w0(),
+        eop(0x6F, M::Vmovdqu64, &[Vx, Wx]).pf3().w1(),
+        eop(0x6F, M::Vmovdqu8, &[Vx, Wx]).pf2().w0(),
+        eop(0x6F, M::Vmovdqu16, &[Vx, Wx]).pf2().w1(),
+        eop(0x72, M::Vprord, &[Hx, Wx, Ib]).p66().digit(0).w0(),
+        eop(0x72, M::Vprorq, &[Hx, Wx, Ib]).p66().digit(0).w1(),
+        eop(0x72, M::Vprold, &[Hx, Wx, Ib]).p66().digit(1).w0(),
+        eop(0x72, M::Vprolq, &[Hx, Wx, Ib]).p66().digit(1).w1(),
+        eop(0x72, M::Vpsrad, &[Hx, Wx, Ib]).p66().digit(4).w0(),
+        eop(0x72, M::Vpsraq, &[Hx, Wx, Ib]).p66().digit(4).w1(),
+        eop(0x74, M::Vpcmpeqb, &[KG, Hx, Wx]).p66(),
+        eop(0x75, M::Vpcmpeqw, &[KG, Hx, Wx]).p66(),
+        eop(0x76, M::Vpcmpeqd, &[KG, Hx, Wx]).p66().w0(),
+        eop(0x78, M::Vcvttps2udq, &[Vx, Wx]).np().w0().f(SAE),
+        eop(0x78, M::Vcvttpd2udq, &[Vh, Wx]).np().w1().f(SAE),
+        eop(0x78, M::Vcvttps2uqq, &[Vx, Wh]).p66().w0().f(SAE),
+        eop(0x78, M::Vcvttpd2uqq, &[Vx, Wx]).p66().w1().f(SAE),
+        eop(0x78, M::Vcvttss2usi, &[Gy, Wd]).pf3().f(SAE),
+        eop(0x78, M::Vcvttsd2usi, &[Gy, Wq]).pf2().f(SAE),
+        eop(0x79, M::Vcvtps2udq, &[Vx, Wx]).np().w0(),
+        eop(0x79, M::Vcvtpd2udq, &[Vh, Wx]).np().w1(),
+        eop(0x79, M::Vcvtps2uqq, &[Vx, Wh]).p66().w0(),
+        eop(0x79, M::Vcvtpd2uqq, &[Vx, Wx]).p66().w1(),
+        eop(0x79, M::Vcvtss2usi, &[Gy, Wd]).pf3(),
+        eop(0x79, M::Vcvtsd2usi, &[Gy, Wq]).pf2(),
+        eop(0x7A, M::Vcvttps2qq, &[Vx, Wh]).p66().w0().f(SAE),
+        eop(0x7A, M::Vcvttpd2qq, &[Vx, Wx]).p66().w1().f(SAE),
+        eop(0x7A, M::Vcvtudq2pd, &[Vx, Wh]).pf3().w0(),
+        eop(0x7A, M::Vcvtuqq2pd, &[Vx, Wx]).pf3().w1(),
+        eop(0x7A, M::Vcvtudq2ps, &[Vx, Wx]).pf2().w0(),
+        eop(0x7A, M::Vcvtuqq2ps, &[Vh, Wx]).pf2().w1(),
+        eop(0x7B, M::Vcvtps2qq, &[Vx, Wh]).p66().w0(),
+        eop(0x7B, M::Vcvtpd2qq, &[Vx, Wx]).p66().w1(),
+        eop(0x7B, M::Vcvtusi2ss, &[Vdq, Hdq, Ey]).pf3(),
+        eop(0x7B, M::Vcvtusi2sd, &[Vdq, Hdq, Ey]).pf2(),
+        eop(0x7F, M::Vmovdqa32, &[Wx, Vx]).p66().w0(),
+        eop(0x7F, M::Vmovdqa64, &[Wx, Vx]).p66().w1(),
+        eop(0x7F, M::Vmovdqu32, &[Wx, Vx]).pf3().w0(),
+        eop(0x7F, M::Vmovdqu64, &[Wx, Vx]).pf3().w1(),
+        eop(0x7F, M::Vmovdqu8, &[Wx, Vx]).pf2().w0(),
+        eop(0x7F, M::Vmovdqu16, &[Wx, Vx]).pf2().w1(),
+        eop(0xC2, M::Vcmpps, &[KG, Hx, Wx, Ib]).np().w0().f(SAE),
+        eop(0xC2, M::Vcmppd, &[KG, Hx, Wx, Ib]).p66().w1().f(SAE),
+        eop(0xC2, M::Vcmpss, &[KG, Hdq, Wd, Ib]).pf3().w0().f(SAE),
+        eop(0xC2, M::Vcmpsd, &[KG, Hdq, Wq, Ib]).pf2().w1().f(SAE),
+        eop(0xE6, M::Vcvtdq2pd, &[Vx, Wh]).pf3().w0(),
+        eop(0xE6, M::Vcvtqq2pd, &[Vx, Wx]).pf3().w1(),
+    ],
+    &evex_w(0xDB, [M::Vpandd, M::Vpandq], &[Vx, Hx, Wx]),
+    &evex_w(0xDF, [M::Vpandnd, M::Vpandnq], &[Vx, Hx, Wx]),
+    &evex_w(0xE2, [M::Vpsrad, M::Vpsraq], &[Vx, Hx, Wdq]),
+    &evex_w(0xEB, [M::Vpord, M::Vporq], &[Vx, Hx, Wx]),
+    &evex_w(0xEF, [M::Vpxord, M::Vpxorq], &[Vx, Hx, Wx]),
+];
+
+/*
+    Three byte opcode map (0F 38 xx)
+*/
+
+static THREE_BYTE_38: &[&[Entry]] = &[
+    &mmx_sse(0x00, M::Pshufb, VEX | EVEX),
+    &mmx_sse(0x01, M::Phaddw, VEX),
+    &mmx_sse(0x02, M::Phaddd, VEX),
+    &mmx_sse(0x03, M::Phaddsw, VEX),
+    &mmx_sse(0x04, M::Pmaddubsw, VEX | EVEX),
+    &mmx_sse(0x05, M::Phsubw, VEX),
+    &mmx_sse(0x06, M::Phsubd, VEX),
+    &mmx_sse(0x07, M::Phsubsw, VEX),
+    &mmx_sse(0x08, M::Psignb, VEX),
+    &mmx_sse(0x09, M::Psignw, VEX),
+    &mmx_sse(0x0A, M::Psignd, VEX),
+    &mmx_sse(0x0B, M::Pmulhrsw, VEX | EVEX),
+    &[
+        op(0x10, M::Pblendvb, &[Vdq, Wdq, XMM0]).p66(),
+        op(0x14, M::Blendvps, &[Vdq, Wdq, XMM0]).p66(),
+        op(0x15, M::Blendvpd, &[Vdq, Wdq, XMM0]).p66(),
+        op(0x17, M::Ptest, &[Vx, Wx]).p66().vex(),
+        op(0x1C, M::Pabsb, &[Pq, Qq]).np(),
+        op(0x1C, M::Pabsb, &[Vx, Wx]).p66().vex().evex(),
+        op(0x1D, M::Pabsw, &[Pq, Qq]).np(),
+        op(0x1D, M::Pabsw, &[Vx, Wx]).p66().vex().evex(),
+        op(0x1E, M::Pabsd, &[Pq, Qq]).np(),
+        op(0x1E, M::Pabsd, &[Vx, Wx]).p66().vex().evex(),
+        op(0x20, M::Pmovsxbw, &[Vx, Wh]).p66().vex().evex(),
+        op(0x21, M::Pmovsxbd, &[Vx, Wqr]).p66().vex().evex(),
+        op(0x22, M::Pmovsxbq, &[Vx, Wer]).p66().vex().evex(),
+        op(0x23, M::Pmovsxwd, &[Vx, Wh]).p66().vex().evex(),
+        op(0x24, M::Pmovsxwq, &[Vx, Wqr]).p66().vex().evex(),
+        op(0x25, M::Pmovsxdq, &[Vx, Wh]).p66().vex().evex(),
+        op(0x28, M::Pmuldq, &[Vx, Hx, Wx]).p66().vex().evex().ew1(),
+        op(0x29, M::Pcmpeqq, &[Vx, Hx, Wx]).p66().vex(),
+        op(0x2A, M::Movntdqa, &[Vx, Mx]).p66().vex().evex(),
+        op(0x2B, M::Packusdw, &[Vx, Hx, Wx]).p66().vex().evex(),
+        op(0x30, M::Pmovzxbw, &[Vx, Wh]).p66().vex().evex(),
+        op(0x31, M::Pmovzxbd, &[Vx, Wqr]).p66().vex().evex(),
+        op(0x32, M::Pmovzxbq, &[Vx, Wer]).p66().vex().evex(),
+        op(0x33, M::Pmovzxwd, &[Vx, Wh]).p66().vex().evex(),
+        op(0x34, M::Pmovzxwq, &[Vx, Wqr]).p66().vex().evex(),
+        op(0x35, M::Pmovzxdq, &[Vx, Wh]).p66().vex().evex(),
+        op(0x37, M::Pcmpgtq, &[Vx, Hx, Wx]).p66().vex(),
+        op(0x38, M::Pminsb, &[Vx, Hx, Wx]).p66().vex().evex(),
+        op(0x39, M::Pminsd, &[Vx, Hx, Wx]).p66().vex(),
+        op(0x3A, M::Pminuw, &[Vx, Hx, Wx]).p66().vex().evex(),
+        op(0x3B, M::Pminud, &[Vx, Hx, Wx]).p66().vex(),
+        op(0x3C, M::Pmaxsb, &[Vx, Hx, Wx]).p66().vex().evex(),
+        op(0x3D, M::Pmaxsd, &[Vx, Hx, Wx]).p66().vex(),
+        op(0x3E, M::Pmaxuw, &[Vx, Hx, Wx]).p66().vex().evex(),
+        op(0x3F, M::Pmaxud, &[Vx, Hx, Wx]).p66().vex(),
+        op(0x40, M::Pmulld, &[Vx, Hx, Wx]).p66().vex(),
+        op(0x41, M::Phminposuw, &[Vdq, Wdq]).p66().vex(),
+        op(0x80, M::Invept, &[Gy, Mdq]).p66().f(F64),
+        op(0x81, M::Invvpid, &[Gy, Mdq]).p66().f(F64),
+        op(0x82, M::Invpcid, &[Gy, Mdq]).p66().f(F64),
+        op(0xC8, M::Sha1nexte, &[Vdq, Wdq]).np(),
+        op(0xC9, M::Sha1msg1, &[Vdq, Wdq]).np(),
+        op(0xCA, M::Sha1msg2, &[Vdq, Wdq]).np(),
+        op(0xCB, M::Sha256rnds2, &[Vdq, Wdq, XMM0]).np(),
+        op(0xCC, M::Sha256msg1, &[Vdq, Wdq]).np(),
+        op(0xCD, M::Sha256msg2, &[Vdq, Wdq]).np(),
+        op(0xDB, M::Aesimc, &[Vdq, Wdq]).p66().vex(),
+        op(0xDC, M::Aesenc, &[Vx, Hx, Wx]).p66().vex().evex(),
+        op(0xDD, M::Aesenclast, &[Vx, Hx, Wx]).p66().vex().evex(),
+        op(0xDE, M::Aesdec, &[Vx, Hx, Wx]).p66().vex().evex(),
+        op(0xDF, M::Aesdeclast, &[Vx, Hx, Wx]).p66().vex().evex(),
+        op(0xF0, M::Crc32, &[Gy, Eb]).pf2(),
+        op(0xF0, M::Movbe, &[Gv, Mv]),
+        op(0xF1, M::Crc32, &[Gy, Ev]).pf2(),
+        op(0xF1, M::Movbe, &[Mv, Gv]),
+        op(0xF6, M::Adcx, &[Gy, Ey]).p66(),
+        op(0xF6, M::Adox, &[Gy, Ey]).pf3(),
+    ],
+    /* AVX / AVX2 / F16C */
+    &[
+        vop(0x0C, M::Vpermilps, &[Vx, Hx, Wx]).p66().w0(),
+        vop(0x0D, M::Vpermilpd, &[Vx, Hx, Wx]).p66().w0(),
+        vop(0x0E, M::Vtestps, &[Vx, Wx]).p66().w0(),
+        vop(0x0F, M::Vtestpd, &[Vx, Wx]).p66().w0(),
+        veop(0x13, M::Vcvtph2ps, &[Vx, Wh]).p66().w0().f(SAE),
+        vop(0x16, M::Vpermps, &[Vx, Hx, Wx]).p66().w0().l1(),
+        veop(0x18, M::Vbroadcastss, &[Vx, Wd]).p66().w0(),
+        vop(0x19, M::Vbroadcastsd, &[Vx, Wq]).p66().w0().l1(),
+        vop(0x1A, M::Vbroadcastf128, &[Vx, Wdq]).p66().w0().l1().mem(),
+        vop(0x2C, M::Vmaskmovps, &[Vx, Hx, Mx]).p66().w0(),
+        vop(0x2D, M::Vmaskmovpd, &[Vx, Hx, Mx]).p66().w0(),
+        vop(0x2E, M::Vmaskmovps, &[Mx, Hx, Vx]).p66().w0(),
+        vop(0x2F, M::Vmaskmovpd, &[Mx, Hx, Vx]).p66().w0(),
+        vop(0x36, M::Vpermd, &[Vx, Hx, Wx]).p66().w0().l1(),
+        veop(0x45, M::Vpsrlvd, &[Vx, Hx, Wx]).p66().w0(),
+        veop(0x45, M::Vpsrlvq, &[Vx, Hx, Wx]).p66().w1(),
+        veop(0x46, M::Vpsravd, &[Vx, Hx, Wx]).p66().w0(),
+        veop(0x47, M::Vpsllvd, &[Vx, Hx, Wx]).p66().w0(),
+        veop(0x47, M::Vpsllvq, &[Vx, Hx, Wx]).p66().w1(),
+        veop(0x58, M::Vpbroadcastd, &[Vx, Wd]).p66().w0(),
+        vop(0x59, M::Vpbroadcastq, &[Vx, Wq]).p66().w0(),
+        vop(0x5A, M::Vbroadcasti128, &[Vx, Wdq]).p66().w0().l1().mem(),
+        veop(0x78, M::Vpbroadcastb, &[Vx, Wb]).p66().w0(),
+        veop(0x79, M::Vpbroadcastw, &[Vx, Ww]).p66().w0(),
+        vop(0x8C, M::Vpmaskmovd, &[Vx, Hx, Mx]).p66().w0(),
+        vop(0x8C, M::Vpmaskmovq, &[Vx, Hx, Mx]).p66().w1(),
+        vop(0x8E, M::Vpmaskmovd, &[Mx, Hx, Vx]).p66().w0(),
+        vop(0x8E, M::Vpmaskmovq, &[Mx, Hx, Vx]).p66().w1(),
+        vop(0x90, M::Vpgatherdd, &[Vx, VSIBx, Hx]).p66().w0(),
+        vop(0x90, M::Vpgatherdq, &[Vx, VSIBh, Hx]).p66().w1(),
+        vop(0x91, M::Vpgatherqd, &[Vh, VSIBx, Hh]).p66().w0(),
+        vop(0x91, M::Vpgatherqq, &[Vx, VSIBx, Hx]).p66().w1(),
+        vop(0x92, M::Vgatherdps, &[Vx, VSIBx, Hx]).p66().w0(),
+        vop(0x92, M::Vgatherdpd, &[Vx, VSIBh, Hx]).p66().w1(),
+        vop(0x93, M::Vgatherqps, &[Vh, VSIBx, Hh]).p66().w0(),
+        vop(0x93, M::Vgatherqpd, &[Vx, VSIBx, Hx]).p66().w1(),
+    ],
+    /* FMA */
+    &fma_packed(0x96, [M::Vfmaddsub132ps, M::Vfmaddsub132pd]),
+    &fma_packed(0x97, [M::Vfmsubadd132ps, M::Vfmsubadd132pd]),
+    &fma(0x98, [M::Vfmadd132ps, M::Vfmadd132pd, M::Vfmadd132ss, M::Vfmadd132sd]),
+    &fma(0x9A, [M::Vfmsub132ps, M::Vfmsub132pd, M::Vfmsub132ss, M::Vfmsub132sd]),
+    &fma(0x9C, [M::Vfnmadd132ps, M::Vfnmadd132pd, M::Vfnmadd132ss, M::Vfnmadd132sd]),
+    &fma(0x9E, [M::Vfnmsub132ps, M::Vfnmsub132pd, M::Vfnmsub132ss, M::Vfnmsub132sd]),
+    &fma_packed(0xA6, [M::Vfmaddsub213ps, M::Vfmaddsub213pd]),
+    &fma_packed(0xA7, [M::Vfmsubadd213ps, M::Vfmsubadd213pd]),
+    &fma(0xA8, [M::Vfmadd213ps, M::Vfmadd213pd, M::Vfmadd213ss, M::Vfmadd213sd]),
+    &fma(0xAA, [M::Vfmsub213ps, M::Vfmsub213pd, M::Vfmsub213ss, M::Vfmsub213sd]),
+    &fma(0xAC, [M::Vfnmadd213ps, M::Vfnmadd213pd, M::Vfnmadd213ss, M::Vfnmadd213sd]),
+    &fma(0xAE, [M::Vfnmsub213ps, M::Vfnmsub213pd, M::Vfnmsub213ss, M::Vfnmsub213sd]),
+    &fma_packed(0xB6, [M::Vfmaddsub231ps, M::Vfmaddsub231pd]),
+    &fma_packed(0xB7, [M::Vfmsubadd231ps, M::Vfmsubadd231pd]),
+    &fma(0xB8, [M::Vfmadd231ps, M::Vfmadd231pd, M::Vfmadd231ss, M::Vfmadd231sd]),
+    &fma(0xBA, [M::Vfmsub231ps, M::Vfmsub231pd, M::Vfmsub231ss, M::Vfmsub231sd]),
+    &fma(0xBC, [M::Vfnmadd231ps, M::Vfnmadd231pd, M::Vfnmadd231ss, M::Vfnmadd231sd]),
+    &fma(0xBE, [M::Vfnmsub231ps, M::Vfnmsub231pd, M::Vfnmsub231ss, M::Vfnmsub231sd]),
+    /* BMI1 / BMI2 */
+    &[
+        vop(0xF2, M::Andn, &[Gy, By, Ey]).np().l0(),
+        vop(0xF3, M::Blsr, &[By, Ey]).np().digit(1).l0(),
+        vop(0xF3, M::Blsmsk, &[By, Ey]).np().digit(2).l0(),
+        vop(0xF3, M::Blsi, &[By, Ey]).np().digit(3).l0(),
+        vop(0xF5, M::Bzhi, &[Gy, Ey, By]).np().l0(),
+        vop(0xF5, M::Pext, &[Gy, By, Ey]).pf3().l0(),
+        vop(0xF5, M::Pdep, &[Gy, By, Ey]).pf2().l0(),
+        vop(0xF6, M::Mulx, &[Gy, By, Ey]).pf2().l0(),
+        vop(0xF7, M::Bextr, &[Gy, Ey, By]).np().l0(),
+        vop(0xF7, M::Shlx, &[Gy, Ey, By]).p66().l0(),
+        vop(0xF7, M::Sarx, &[Gy, Ey, By]).pf3().l0(),
+        vop(0xF7, M::Shrx, &[Gy, Ey, By]).pf2().l0(),
+    ],
+    /* AVX-512 */
+    &[
+        eop(0x0C, M::Vpermilps, &[Vx, Hx, Wx]).p66().w0(),
+        eop(0x0D, M::Vpermilpd, &[Vx, Hx, Wx]).p66().w1(),
+        eop(0x19, M::Vbroadcastf32x2, &[Vx, Wq]).p66().w0(),
+        eop(0x19, M::Vbroadcastsd, &[Vx, Wq]).p66().w1(),
+        eop(0x1A, M::Vbroadcastf32x4, &[Vx, Wdq]).p66().w0().mem(),
+        eop(0x1A, M::Vbroadcastf64x2, &[Vx, Wdq]).p66().w1().mem(),
+        eop(0x1B, M::Vbroadcastf32x8, &[Vx, Wh]).p66().w0().mem(),
+        eop(0x1B, M::Vbroadcastf64x4, &[Vx, Wh]).p66().w1().mem(),
+        eop(0x1F, M::Vpabsq, &[Vx, Wx]).p66().w1(),
+        eop(0x29, M::Vpcmpeqq, &[KG, Hx, Wx]).p66().w1(),
+        eop(0x37, M::Vpcmpgtq, &[KG, Hx, Wx]).p66().w1(),
+        eop(0x46, M::Vpsravq, &[Vx, Hx, Wx]).p66().w1(),
+        eop(0x59, M::Vbroadcasti32x2, &[Vx, Wq]).p66().w0(),
+        eop(0x59, M::Vpbroadcastq, &[Vx, Wq]).p66().w1(),
+        eop(0x5A, M::Vbroadcasti32x4, &[Vx, Wdq]).p66().w0().mem(),
+        eop(0x5A, M::Vbroadcasti64x2, &[Vx, Wdq]).p66().w1().mem(),
+        eop(0x5B, M::Vbroadcasti32x8, &[Vx, Wh]).p66().w0().mem(),
+        eop(0x5B, M::Vbroadcasti64x4, &[Vx, Wh]).p66().w1().mem(),
+        eop(0x7A, M::Vpbroadcastb, &[Vx, Rd]).p66().w0(),
+        eop(0x7B, M::Vpbroadcastw, &[Vx, Rd]).p66().w0(),
+        eop(0x7C, M::Vpbroadcastd, &[Vx, Rd]).p66().w0(),
+        eop(0x7C, M::Vpbroadcastq, &[Vx, Rq]).p66().w1(),
+        eop(0x8A, M::Vcompressps, &[Wx, Vx]).p66().w0(),
+        eop(0x8A, M::Vcompresspd, &[Wx, Vx]).p66().w1(),
+        eop(0x8B, M::Vpcompressd, &[Wx, Vx]).p66().w0(),
+        eop(0x8B, M::Vpcompressq, &[Wx, Vx]).p66().w1(),
+        eop(0x90, M::Vpgatherdd, &[Vx, VSIBx]).p66().w0(),
+        eop(0x90, M::Vpgatherdq, &[Vx, VSIBh]).p66().w1(),
+        eop(0x91, M::Vpgatherqd, &[Vh, VSIBx]).p66().w0(),
+        eop(0x91, M::Vpgatherqq, &[Vx, VSIBx]).p66().w1(),
+        eop(0x92, M::Vgatherdps, &[Vx, VSIBx]).p66().w0(),
+        eop(0x92, M::Vgatherdpd, &[Vx, VSIBh]).p66().w1(),
+        eop(0x93, M::Vgatherqps, &[Vh, VSIBx]).p66().w0(),
+        eop(0x93, M::Vgatherqpd, &[Vx, VSIBx]).p66().w1(),
+        eop(0xA0, M::Vpscatterdd, &[VSIBx, Vx]).p66().w0(),
+        eop(0xA0, M::Vpscatterdq, &[VSIBh, Vx]).p66().w1(),
+        eop(0xA1, M::Vpscatterqd, &[VSIBx, Vh]).p66().w0(),
+        eop(0xA1, M::Vpscatterqq, &[VSIBx, Vx]).p66().w1(),
+        eop(0xA2, M::Vscatterdps, &[VSIBx, Vx]).p66().w0(),
+        eop(0xA2, M::Vscatterdpd, &[VSIBh, Vx]).p66().w1(),
+        eop(0xA3, M::Vscatterqps, &[VSIBx, Vh]).p66().w0(),
+        eop(0xA3, M::Vscatterqpd, &[VSIBx, Vx]).p66().w1(),
+        /* F3 prefixed: down converting moves, and mask <-> vector conversions */
+        eop(0x10, M::Vpmovuswb, &[Wh, Vx]).pf3().w0(),
+        eop(0x11, M::Vpmovusdb, &[Wqr, Vx]).pf3().w0(),
+        eop(0x12, M::Vpmovusqb, &[Wer, Vx]).pf3().w0(),
+        eop(0x13, M::Vpmovusdw, &[Wh, Vx]).pf3().w0(),
+        eop(0x14, M::Vpmovusqw, &[Wqr, Vx]).pf3().w0(),
+        eop(0x15, M::Vpmovusqd, &[Wh, Vx]).pf3().w0(),
+        eop(0x20, M::Vpmovswb, &[Wh, Vx]).pf3().w0(),
+        eop(0x21, M::Vpmovsdb, &[Wqr, Vx]).pf3().w0(),
+        eop(0x22, M::Vpmovsqb, &[Wer, Vx]).pf3().w0(),
+        eop(0x23, M::Vpmovsdw, &[Wh, Vx]).pf3().w0(),
+        eop(0x24, M::Vpmovsqw, &[Wqr, Vx]).pf3().w0(),
+        eop(0x25, M::Vpmovsqd, &[Wh, Vx]).pf3().w0(),
+        eop(0x26, M::Vptestnmb, &[KG, Hx, Wx]).pf3().w0(),
+        eop(0x26, M::Vptestnmw, &[KG, Hx, Wx]).pf3().w1(),
+        eop(0x27, M::Vptestnmd, &[KG, Hx, Wx]).pf3().w0(),
+        eop(0x27, M::Vptestnmq, &[KG, Hx, Wx]).pf3().w1(),
+        eop(0x28, M::Vpmovm2b, &[Vx, KU]).pf3().w0().reg(),
+        eop(0x28, M::Vpmovm2w, &[Vx, KU]).pf3().w1().reg(),
+        eop(0x29, M::Vpmovb2m, &[KG, Ux]).pf3().w0(),
+        eop(0x29, M::Vpmovw2m, &[KG, Ux]).pf3().w1(),
+        eop(0x2A, M::Vpbroadcastmb2q, &[Vx, KU]).pf3().w1().reg(),
+        eop(0x30, M::Vpmovwb, &[Wh, Vx]).pf3().w0(),
+        eop(0x31, M::Vpmovdb, &[Wqr, Vx]).pf3().w0(),
+        eop(0x32, M::Vpmovqb, &[Wer, Vx]).pf3().w0(),
+        eop(0x33, M::Vpmovdw, &[Wh, Vx]).pf3().w0(),
+        eop(0x34, M::Vpmovqw, &[Wqr, Vx]).pf3().w0(),
+        eop(0x35, M::Vpmovqd, &[Wh, Vx]).pf3().w0(),
+        eop(0x38, M::Vpmovm2d, &[Vx, KU]).pf3().w0().reg(),
+        eop(0x38, M::Vpmovm2q, &[Vx, KU]).pf3().w1().reg(),
+        eop(0x39, M::Vpmovd2m, &[KG, Ux]).pf3().w0(),
+        eop(0x39, M::Vpmovq2m, &[KG, Ux]).pf3().w1(),
+        eop(0x3A, M::Vpbroadcastmw2d, &[Vx, KU]).pf3().w0().reg(),
+    ],
+    &[
+        eop(0x10, M::Vpsrlvw, &[Vx, Hx, Wx]).p66().w1(),
+        eop(0x11, M::Vpsravw, &[Vx, Hx, Wx]).p66().w1(),
+        eop(0x12, M::Vpsllvw, &[Vx, Hx, Wx]).p66().w1(),
+    ],
+    &evex_w(0x14, [M::Vprorvd, M::Vprorvq], &[Vx, Hx, Wx]),
+    &evex_w(0x15, [M::Vprolvd, M::Vprolvq], &[Vx, Hx, Wx]),
+    &evex_w(0x16, [M::Vpermps, M::Vpermpd], &[Vx, Hx, Wx]),
+    &evex_w(0x26, [M::Vptestmb, M::Vptestmw], &[KG, Hx, Wx]),
+    &evex_w(0x27, [M::Vptestmd, M::Vptestmq], &[KG, Hx, Wx]),
+    &evex_w(0x2C, [M::Vscalefps, M::Vscalefpd], &[Vx, Hx, Wx]),
+    &[
+        eop(0x2D, M::Vscalefss, &[Vdq, Hdq, Wd]).p66().w0(),
+        eop(0x2D, M::Vscalefsd, &[Vdq, Hdq, Wq]).p66().w1(),
+        eop(0x42, M::Vgetexpps, &[Vx, Wx]).p66().w0().f(SAE),
+        eop(0x42, M::Vgetexppd, &[Vx, Wx]).p66().w1().f(SAE),
+        eop(0x43, M::Vgetexpss, &[Vdq, Hdq, Wd]).p66().w0().f(SAE),
+        eop(0x43, M::Vgetexpsd, &[Vdq, Hdq, Wq]).p66().w1().f(SAE),
+        eop(0x4D, M::Vrcp14ss, &[Vdq, Hdq, Wd]).p66().w0(),
+        eop(0x4D, M::Vrcp14sd, &[Vdq, Hdq, Wq]).p66().w1(),
+        eop(0x4F, M::Vrsqrt14ss, &[Vdq, Hdq, Wd]).p66().w0(),
+        eop(0x4F, M::Vrsqrt14sd, &[Vdq, Hdq, Wq]).p66().w1(),
+    ],
+    &evex_w(0x36, [M::Vpermd, M::Vpermq], &[Vx, Hx, Wx]),
+    &evex_w(0x39, [M::Vpminsd, M::Vpminsq], &[Vx, Hx, Wx]),
+    &evex_w(0x3B, [M::Vpminud, M::Vpminuq], &[Vx, Hx, Wx]),
+    &evex_w(0x3D, [M::Vpmaxsd, M::Vpmaxsq], &[Vx, Hx, Wx]),
+    &evex_w(0x3F, [M::Vpmaxud, M::Vpmaxuq], &[Vx, Hx, Wx]),
+    &evex_w(0x40, [M::Vpmulld, M::Vpmullq], &[Vx, Hx, Wx]),
+    &evex_w(0x44, [M::Vplzcntd, M::Vplzcntq], &[Vx, Wx]),
+    &evex_w(0x4C, [M::Vrcp14ps, M::Vrcp14pd], &[Vx, Wx]),
+    &evex_w(0x4E, [M::Vrsqrt14ps, M::Vrsqrt14pd], &[Vx, Wx]),
+    &evex_w(0x64, [M::Vpblendmd, M::Vpblendmq], &[Vx, Hx, Wx]),
+    &evex_w(0x65, [M::Vblendmps, M::Vblendmpd], &[Vx, Hx, Wx]),
+    &evex_w(0x66, [M::Vpblendmb, M::Vpblendmw], &[Vx, Hx, Wx]),
+    &evex_w(0x75, [M::Vpermi2b, M::Vpermi2w], &[Vx, Hx, Wx]),
+    &evex_w(0x76, [M::Vpermi2d, M::Vpermi2q], &[Vx, Hx, Wx]),
+    &evex_w(0x77, [M::Vpermi2ps, M::Vpermi2pd], &[Vx, Hx, Wx]),
+    &evex_w(0x7D, [M::Vpermt2b, M::Vpermt2w], &[Vx, Hx, Wx]),
+    &evex_w(0x7E, [M::Vpermt2d, M::Vpermt2q], &[Vx, Hx, Wx]),
+    &evex_w(0x7F, [M::Vpermt2ps, M::Vpermt2pd], &[Vx, Hx, Wx]),
+    &[eop(0x83, M::Vpmultishiftqb, &[Vx, Hx, Wx]).p66().w1()],
+    &evex_w(0x88, [M::Vexpandps, M::Vexpandpd], &[Vx, Wx]),
+    &evex_w(0x89, [M::Vpexpandd, M::Vpexpandq], &[Vx, Wx]),
+    &evex_w(0x8D, [M::Vpermb, M::Vpermw], &[Vx, Hx, Wx]),
+    &evex_w(0xC4, [M::Vpconflictd, M::Vpconflictq], &[Vx, Wx]),
+];
+
+/*
+    Three byte opcode map (0F 3A xx)
+*/
+
+static THREE_BYTE_3A: &[&[Entry]] = &[
+    &[
+        op(0x08, M::Roundps, &[Vx, Wx, Ib]).p66().vex(),
+        op(0x09, M::Roundpd, &[Vx, Wx, Ib]).p66().vex(),
+        op(0x0A, M::Roundss, &[Vdq, Hdq, Wd, Ib]).p66().vex(),
+        op(0x0B, M::Roundsd, &[Vdq, Hdq, Wq, Ib]).p66().vex(),
+        op(0x0C, M::Blendps, &[Vx, Hx, Wx, Ib]).p66().vex(),
+        op(0x0D, M::Blendpd, &[Vx, Hx, Wx, Ib]).p66().vex(),
+        op(0x0E, M::Pblendw, &[Vx, Hx, Wx, Ib]).p66().vex(),
+        op(0x0F, M::Palignr, &[Pq, Qq, Ib]).np(),
+        op(0x0F, M::Palignr, &[Vx, Hx, Wx, Ib]).p66().vex().evex(),
+        op(0x14, M::Pextrb, &[Ry_Mb, Vdq, Ib]).p66().vex().evex(),
+        op(0x15, M::Pextrw, &[Ry_Mw, Vdq, Ib]).p66().vex().evex(),
+        op(0x16, M::Pextrd, &[Ed, Vdq, Ib]).p66().w0().vex().evex(),
+        op(0x16, M::Pextrq, &[Eq, Vdq, Ib]).p66().w1().vex().evex(),
+        op(0x17, M::Extractps, &[Ed, Vdq, Ib]).p66().vex().evex(),
+        op(0x20, M::Pinsrb, &[Vdq, Hdq, Ry_Mb, Ib]).p66().vex().evex(),
+        op(0x21, M::Insertps, &[Vdq, Hdq, Udq_Md, Ib]).p66().vex().evex(),
+        op(0x22, M::Pinsrd, &[Vdq, Hdq, Ed, Ib]).p66().w0().vex().evex(),
+        op(0x22, M::Pinsrq, &[Vdq, Hdq, Eq, Ib]).p66().w1().vex().evex(),
+        op(0x40, M::Dpps, &[Vx, Hx, Wx, Ib]).p66().vex(),
+        op(0x41, M::Dppd, &[Vdq, Hdq, Wdq, Ib]).p66().vex(),
+        op(0x42, M::Mpsadbw, &[Vx, Hx, Wx, Ib]).p66().vex(),
+        op(0x44, M::Pclmulqdq, &[Vx, Hx, Wx, Ib]).p66().vex().evex(),
+        op(0x60, M::Pcmpestrm, &[Vdq, Wdq, Ib]).p66().vex(),
+        op(0x61, M::Pcmpestri, &[Vdq, Wdq, Ib]).p66().vex(),
+        op(0x62, M::Pcmpistrm, &[Vdq, Wdq, Ib]).p66().vex(),
+        op(0x63, M::Pcmpistri, &[Vdq, Wdq, Ib]).p66().vex(),
+        op(0xCC, M::Sha1rnds4, &[Vdq, Wdq, Ib]).np(),
+        op(0xDF, M::Aeskeygenassist, &[Vdq, Wdq, Ib]).p66().vex(),
+    ],
+    /* AVX / AVX2 / F16C */
+    &[
+        veop(0x00, M::Vpermq, &[Vx, Wx, Ib]).p66().w1(),
+        veop(0x01, M::Vpermpd, &[Vx, Wx, Ib]).p66().w1(),
+        vop(0x02, M::Vpblendd, &[Vx, Hx, Wx, Ib]).p66().w0(),
+        veop(0x04, M::Vpermilps, &[Vx, Wx, Ib]).p66().w0(),
+        vop(0x05, M::Vpermilpd, &[Vx, Wx, Ib]).p66().w0(),
+        vop(0x06, M::Vperm2f128, &[Vx, Hx, Wx, Ib]).p66().w0().l1(),
+        vop(0x18, M::Vinsertf128, &[Vx, Hx, Wdq, Ib]).p66().w0().l1(),
+        vop(0x19, M::Vextractf128, &[Wdq, Vx, Ib]).p66().w0().l1(),
+        veop(0x1D, M::Vcvtps2ph, &[Wh, Vx, Ib]).p66().w0().f(SAE),
+        vop(0x38, M::Vinserti128, &[Vx, Hx, Wdq, Ib]).p66().w0().l1(),
+        vop(0x39, M::Vextracti128, &[Wdq, Vx, Ib]).p66().w0().l1(),
+        vop(0x46, M::Vperm2i128, &[Vx, Hx, Wx, Ib]).p66().w0().l1(),
+        vop(0x4A, M::Vblendvps, &[Vx, Hx, Wx, Lx]).p66().w0(),
+        vop(0x4B, M::Vblendvpd, &[Vx, Hx, Wx, Lx]).p66().w0(),
+        vop(0x4C, M::Vpblendvb, &[Vx, Hx, Wx, Lx]).p66().w0(),
+    ],
+    /* AVX-512 opmask shifts */
+    &[
+        vop(0x30, M::Kshiftrb, &[KG, KU, Ib]).p66().w0().l0().reg(),
+        vop(0x30, M::Kshiftrw, &[KG, KU, Ib]).p66().w1().l0().reg(),
+        vop(0x31, M::Kshiftrd, &[KG, KU, Ib]).p66().w0().l0().reg(),
+        vop(0x31, M::Kshiftrq, &[KG, KU, Ib]).p66().w1().l0().reg(),
+        vop(0x32, M::Kshiftlb, &[KG, KU, Ib]).p66().w0().l0().reg(),
+        vop(0x32, M::Kshiftlw, &[KG, KU, Ib]).p66().w1().l0().reg(),
+        vop(0x33, M::Kshiftld, &[KG, KU, Ib]).p66().w0().l0().reg(),
+        vop(0x33, M::Kshiftlq, &[KG, KU, Ib]).p66().w1().l0().reg(),
+    ],
+    /* BMI2 */
+    &[vop(0xF0, M::Rorx, &[Gy, Ey, Ib]).pf2().l0()],
+    /* AVX-512 */
+    &[
+        eop(0x05, M::Vpermilpd, &[Vx, Wx, Ib]).p66().w1(),
+        eop(0x08, M::Vrndscaleps, &[Vx, Wx, Ib]).p66().w0().f(SAE),
+        eop(0x09, M::Vrndscalepd, &[Vx, Wx, Ib]).p66().w1().f(SAE),
+        eop(0x0A, M::Vrndscaless, &[Vdq, Hdq, Wd, Ib]).p66().w0().f(SAE),
+        eop(0x0B, M::Vrndscalesd, &[Vdq, Hdq, Wq, Ib]).p66().w1().f(SAE),
+        eop(0x26, M::Vgetmantps, &[Vx, Wx, Ib]).p66().w0().f(SAE),
+        eop(0x26, M::Vgetmantpd, &[Vx, Wx, Ib]).p66().w1().f(SAE),
+        eop(0x27, M::Vgetmantss, &[Vdq, Hdq, Wd, Ib]).p66().w0().f(SAE),
+        eop(0x27, M::Vgetmantsd, &[Vdq, Hdq, Wq, Ib]).p66().w1().f(SAE),
+        eop(0x42, M::Vdbpsadbw, &[Vx, Hx, Wx, Ib]).p66().w0(),
+        eop(0x50, M::Vrangeps, &[Vx, Hx, Wx, Ib]).p66().w0().f(SAE),
+        eop(0x50, M::Vrangepd, &[Vx, Hx, Wx, Ib]).p66().w1().f(SAE),
+        eop(0x51, M::Vrangess, &[Vdq, Hdq, Wd, Ib]).p66().w0().f(SAE),
+        eop(0x51, M::Vrangesd, &[Vdq, Hdq, Wq, Ib]).p66().w1().f(SAE),
+        eop(0x54, M::Vfixupimmps, &[Vx, Hx, Wx, Ib]).p66().w0().f(SAE),
+        eop(0x54, M::Vfixupimmpd, &[Vx, Hx, Wx, Ib]).p66().w1().f(SAE),
+        eop(0x55, M::Vfixupimmss, &[Vdq, Hdq, Wd, Ib]).p66().w0().f(SAE),
+        eop(0x55, M::Vfixupimmsd, &[Vdq, Hdq, Wq, Ib]).p66().w1().f(SAE),
+        eop(0x56, M::Vreduceps, &[Vx, Wx, Ib]).p66().w0().f(SAE),
+        eop(0x56, M::Vreducepd, &[Vx, Wx, Ib]).p66().w1().f(SAE),
+        eop(0x57, M::Vreducess, &[Vdq, Hdq, Wd, Ib]).p66().w0().f(SAE),
+        eop(0x57, M::Vreducesd, &[Vdq, Hdq, Wq, Ib]).p66().w1().f(SAE),
+        eop(0x66, M::Vfpclassps, &[KG, Wx, Ib]).p66().w0(),
+        eop(0x66, M::Vfpclasspd, &[KG, Wx, Ib]).p66().w1(),
+        eop(0x67, M::Vfpclassss, &[KG, Wd, Ib]).p66().w0(),
+        eop(0x67, M::Vfpclasssd, &[KG, Wq, Ib]).p66().w1(),
+    ],
+    &evex_w(0x03, [M::Valignd, M::Valignq], &[Vx, Hx, Wx, Ib]),
+    &evex_w(0x18, [M::Vinsertf32x4, M::Vinsertf64x2], &[Vx, Hx, Wdq, Ib]),
+    &evex_w(0x19, [M::Vextractf32x4, M::Vextractf64x2], &[Wdq, Vx, Ib]),
+    &evex_w(0x1A, [M::Vinsertf32x8, M::Vinsertf64x4], &[Vx, Hx, Wh, Ib]),
+    &evex_w(0x1B, [M::Vextractf32x8, M::Vextractf64x4], &[Wh, Vx, Ib]),
+    &evex_w(0x1E, [M::Vpcmpud, M::Vpcmpuq], &[KG, Hx, Wx, Ib]),
+    &evex_w(0x1F, [M::Vpcmpd, M::Vpcmpq], &[KG, Hx, Wx, Ib]),
+    &evex_w(0x23, [M::Vshuff32x4, M::Vshuff64x2], &[Vx, Hx, Wx, Ib]),
+    &evex_w(0x25, [M::Vpternlogd, M::Vpternlogq], &[Vx, Hx, Wx, Ib]),
+    &evex_w(0x38, [M::Vinserti32x4, M::Vinserti64x2], &[Vx, Hx, Wdq, Ib]),
+    &evex_w(0x39, [M::Vextracti32x4, M::Vextracti64x2], &[Wdq, Vx, Ib]),
+    &evex_w(0x3A, [M::Vinserti32x8, M::Vinserti64x4], &[Vx, Hx, Wh, Ib]),
+    &evex_w(0x3B, [M::Vextracti32x8, M::Vextracti64x4], &[Wh, Vx, Ib]),
+    &evex_w(0x3E, [M::Vpcmpub, M::Vpcmpuw], &[KG, Hx, Wx, Ib]),
+    &evex_w(0x3F, [M::Vpcmpb, M::Vpcmpw], &[KG, Hx, Wx, Ib]),
+    &evex_w(0x43, [M::Vshufi32x4, M::Vshufi64x2], &[Vx, Hx, Wx, Ib]),
+];
+
+/// Entries for one opcode map and encoding, indexed by opcode byte
+pub(crate) struct MapIndex(Vec<Vec<&'static Entry>>);
+
+impl MapIndex {
+    fn build(groups: &[&'static [Entry]], encoding: Encoding) -> MapIndex {
+        let mut slots: Vec<Vec<&'static Entry>> = vec![Vec::new(); 256];
+        for group in groups {
+            for entry in group.iter().filter(|e| e.encodable(encoding)) {
+                let span = if entry.plus_r() { 8 } else { 1 };
+                for i in 0..span {
+                    slots[entry.opcode as usize + i].push(entry);
+                }
+            }
+        }
+        MapIndex(slots)
+    }
+
+    pub(crate) fn candidates(&self, opcode: u8) -> &[&'static Entry] {
+        &self.0[opcode as usize]
+    }
+}
+
+/// An entry as seen by the encoder: which map it lives in, and which of its encodings is used
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Form {
+    pub(crate) map: OpcodeMap,
+    pub(crate) encoding: Encoding,
+    pub(crate) entry: &'static Entry,
+}
+
+/// One index per opcode map, in `OpcodeMap` order, plus the reverse index by mnemonic
+pub(crate) struct Tables {
+    pub(crate) legacy: [MapIndex; 4],
+    pub(crate) vex: [MapIndex; 4],
+    pub(crate) evex: [MapIndex; 4],
+    forms: HashMap<Mnemonic, Vec<Form>>,
+}
+
+impl Tables {
+    pub(crate) fn map(&self, encoding: Encoding, map: OpcodeMap) -> &MapIndex {
+        let maps = match encoding {
+            Encoding::Legacy => &self.legacy,
+            Encoding::Vex => &self.vex,
+            Encoding::Evex => &self.evex,
+        };
+        match map {
+            OpcodeMap::Primary => &maps[0],
+            OpcodeMap::Map0F => &maps[1],
+            OpcodeMap::Map0F38 => &maps[2],
+            OpcodeMap::Map0F3A => &maps[3],
+        }
+    }
+
+    /// every way an instruction can be encoded, in table order
+    pub(crate) fn forms(&self, mnemonic: Mnemonic) -> &[Form] {
+        self.forms.get(&mnemonic).map_or(&[], |forms| forms.as_slice())
+    }
+
+    /// whether some form of the mnemonic takes a relative branch target
+    pub(crate) fn is_relative_branch(&self, mnemonic: Mnemonic) -> bool {
+        self.forms(mnemonic)
+            .iter()
+            .any(|form| form.entry.ops.iter().any(|op| matches!(op, Op::J(_))))
+    }
+}
+
+/// Indexes every entry under the mnemonic it decodes to in each of its encodings
+fn build_forms() -> HashMap<Mnemonic, Vec<Form>> {
+    let maps = [
+        (OpcodeMap::Primary, ONE_BYTE),
+        (OpcodeMap::Map0F, TWO_BYTE),
+        (OpcodeMap::Map0F38, THREE_BYTE_38),
+        (OpcodeMap::Map0F3A, THREE_BYTE_3A),
+    ];
+    let mut forms: HashMap<Mnemonic, Vec<Form>> = HashMap::new();
+    for (map, groups) in maps {
+        for entry in groups.iter().flat_map(|group| group.iter()) {
+            for encoding in [Encoding::Legacy, Encoding::Vex, Encoding::Evex] {
+                if !entry.encodable(encoding) {
+                    continue;
+                }
+                let mnemonic = if encoding != Encoding::Legacy && entry.encodable(Encoding::Legacy) {
+                    entry.mnemonic.vex_form()
+                } else {
+                    entry.mnemonic
+                };
+                forms.entry(mnemonic).or_default().push(Form {
+                    map,
+                    encoding,
+                    entry,
+                });
+            }
+        }
+    }
+    forms
+}
+
+static TABLES: OnceLock<Tables> = OnceLock::new();
+
+/// Opcode lookup tables, built on first use
+pub(crate) fn tables() -> &'static Tables {
+    let build = |encoding| {
+        [
+            MapIndex::build(ONE_BYTE, encoding),
+            MapIndex::build(TWO_BYTE, encoding),
+            MapIndex::build(THREE_BYTE_38, encoding),
+            MapIndex::build(THREE_BYTE_3A, encoding),
+        ]
+    };
+    TABLES.get_or_init(|| Tables {
+        legacy: build(Encoding::Legacy),
+        vex: build(Encoding::Vex),
+        evex: build(Encoding::Evex),
+        forms: build_forms(),
+    })
+}
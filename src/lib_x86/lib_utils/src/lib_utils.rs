@@ -12,7 +12,7 @@ pub fn format_truncated_hex(f: &mut fmt::Formatter<'_>, data: &[u8]) -> fmt::Res
     } else {
         writeln!(f, "[")?;
         let mut padded_data = data.to_vec();
-        while padded_data.len() % 64 != 0 {
+        while !padded_data.len().is_multiple_of(64) {
             padded_data.push(0);
         }
         for chunk in padded_data.chunks(64) {
@@ -38,7 +38,7 @@ pub fn dump_truncated_hex(data: &[u8]) -> String {
     } else {
         writeln!(f, "[").expect("shouldnt fail writing");
         let mut padded_data = data.to_vec();
-        while padded_data.len() % 64 != 0 {
+        while !padded_data.len().is_multiple_of(64) {
             padded_data.push(0);
         }
         for chunk in padded_data.chunks(64) {
@@ -60,7 +60,7 @@ pub fn dump_truncated_hex(data: &[u8]) -> String {
 pub fn format_hex(f: &mut fmt::Formatter<'_>, data: &[u8]) -> fmt::Result {
     writeln!(f, "[")?;
     let mut padded_data = data.to_vec();
-    while padded_data.len() % 64 != 0 {
+    while !padded_data.len().is_multiple_of(64) {
         padded_data.push(0);
     }
     for chunk in padded_data.chunks(64) {
@@ -79,7 +79,7 @@ pub fn dump_hex(data: &[u8]) -> String {
     let mut f = String::new();
     writeln!(f, "[").expect("shouldnt fail writing");
     let mut padded_data = data.to_vec();
-    while padded_data.len() % 64 != 0 {
+    while !padded_data.len().is_multiple_of(64) {
         padded_data.push(0);
     }
     for chunk in padded_data.chunks(64) {
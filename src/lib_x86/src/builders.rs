@@ -1,8 +1,14 @@
 use lib_types::error::VmBuildError;
 use lib_types::memory::ByteUnits;
-use crate::functions::{InterruptVector, SyscallVector, SystemFunction};
+use crate::functions::{InterruptVector, SyscallVector};
 use crate::memory::ContiguousMemory;
+use crate::cpuid::CpuProfile;
+use crate::execute::MXCSR_DEFAULT;
+use crate::flags::Flags;
 use crate::prelude::X86Machine;
+use lib_opcode::prelude::Register;
+use std::cell::Cell;
+use std::collections::HashSet;
 
 /// An initialised set of X86Machine constructor options
 ///
@@ -10,6 +16,10 @@ pub struct MachineOptions {
     pub memory: ByteUnits,
     pub syscalls: SyscallVector,
     pub interrupts: InterruptVector,
+    pub instruction_budget: Option<u64>,
+    pub cpu_profile: CpuProfile,
+    /// Memory shared with other machines, in place of `memory` bytes of its own
+    pub shared_memory: Option<ContiguousMemory>,
 }
 
 impl MachineOptions {
@@ -18,6 +28,9 @@ impl MachineOptions {
             memory: None,
             syscalls: None,
             interrupts: None,
+            instruction_budget: None,
+            cpu_profile: None,
+            shared_memory: None,
         }
     }
 
@@ -35,29 +48,59 @@ impl MachineOptions {
         self
     }
 
+    pub fn instruction_budget(mut self, instruction_budget: Option<u64>) -> Self {
+        self.instruction_budget = instruction_budget;
+        self
+    }
+
+    pub fn cpu_profile(mut self, cpu_profile: CpuProfile) -> Self {
+        self.cpu_profile = cpu_profile;
+        self
+    }
+
+    pub fn shared_memory(mut self, memory: &ContiguousMemory) -> Self {
+        self.memory = ByteUnits::Bytes(memory.len() as u64);
+        self.shared_memory = Some(memory.share());
+        self
+    }
+
     pub fn build(self) -> X86Machine {
-        let mem = ContiguousMemory::with_size(&self.memory);
+        let mem = self.shared_memory.unwrap_or_else(|| ContiguousMemory::with_size(&self.memory));
 
         let sp = mem.len();
 
-        X86Machine {
-            mmx_registers: Default::default(),
+        let mut machine = X86Machine {
             segment_pointers: Default::default(),
             gp_registers: Default::default(),
-            xmm_registers: Default::default(),
-            ymm_registers: Default::default(),
+            vector_registers: Default::default(),
+            opmask_registers: Default::default(),
             bounds_registers: Default::default(),
+            bound_config_registers: Default::default(),
             mxcsr_register: Default::default(),
+            xcr0_register: Default::default(),
             fpu: Default::default(),
-            flags: 0,
+            flags: Flags::default(),
             instruction_counter: 0,
-            stack_pointer: sp as u64,
+            instruction_budget: self.instruction_budget,
+            instructions_retired: 0,
+            breakpoints: HashSet::new(),
+            pending_stop: None,
+            locked_access: Cell::new(None),
+            privilege_level: 3,
+            cpu_profile: self.cpu_profile,
             interrupts: self.interrupts,
             syscalls: self.syscalls,
             // stack: ContiguousMemory::with_size(&ByteUnits::GibiBytes(1)),
             memory: mem,
             assigned_memory: self.memory,
-        }
+        };
+        // the stack starts at the top of memory and grows down
+        machine.set_register(Register::RSP, sp as u64);
+        machine.set_mxcsr(MXCSR_DEFAULT);
+        // as an OS would, every state component the processor supports is enabled
+        machine.set_xcr0(machine.cpu_profile.supported_xcr0());
+        machine.fninit().expect("FNINIT cannot fault");
+        machine
     }
 
 }
@@ -67,6 +110,12 @@ pub struct MachineBuilder {
     pub memory: Option<ByteUnits>,
     pub syscalls: Option<SyscallVector>,
     pub interrupts: Option<InterruptVector>,
+    /// unlimited if not set
+    pub instruction_budget: Option<u64>,
+    /// `CpuProfile::emulator()` if not set
+    pub cpu_profile: Option<CpuProfile>,
+    /// the machine gets memory of its own if not set
+    pub shared_memory: Option<ContiguousMemory>,
 }
 
 impl MachineBuilder {
@@ -75,6 +124,9 @@ impl MachineBuilder {
             memory: None,
             syscalls: None,
             interrupts: None,
+            instruction_budget: None,
+            cpu_profile: None,
+            shared_memory: None,
         }
     }
 
@@ -87,6 +139,9 @@ impl MachineBuilder {
             memory,
             syscalls,
             interrupts,
+            instruction_budget: self.instruction_budget,
+            cpu_profile: self.cpu_profile.unwrap_or_default(),
+            shared_memory: self.shared_memory,
         }
             .build()
     }
@@ -98,6 +153,9 @@ impl MachineBuilder {
             memory: self.memory.unwrap(),
             syscalls: self.syscalls.unwrap(),
             interrupts: self.interrupts.unwrap(),
+            instruction_budget: self.instruction_budget,
+            cpu_profile: self.cpu_profile.unwrap_or_default(),
+            shared_memory: self.shared_memory,
         }
             .build()
     }
@@ -136,6 +194,9 @@ impl MachineBuilder {
                 memory,
                 syscalls,
                 interrupts,
+                instruction_budget: self.instruction_budget,
+                cpu_profile: self.cpu_profile.unwrap_or_default(),
+                shared_memory: self.shared_memory,
             }
                 .build())
         }
@@ -149,6 +210,9 @@ impl MachineBuilder {
             memory,
             syscalls,
             interrupts,
+            instruction_budget: self.instruction_budget,
+            cpu_profile: self.cpu_profile.unwrap_or_default(),
+            shared_memory: self.shared_memory,
         }
             .build()
     }
@@ -167,12 +231,33 @@ impl MachineBuilder {
         self
     }
 
+    /// How many instructions `run` and `run_until` execute before giving up
+    pub fn instruction_budget(mut self, instruction_budget: u64) -> Self {
+        self.instruction_budget = Some(instruction_budget);
+        self
+    }
+
+    /// The processor CPUID describes to the guest
+    pub fn cpu_profile(mut self, cpu_profile: CpuProfile) -> Self {
+        self.cpu_profile = Some(cpu_profile);
+        self
+    }
+
+    /// Runs the machine on the same memory as another, as a second virtual CPU of the guest
+    /// (see `ContiguousMemory::share`). RSP starts at the top of memory as it does for the
+    /// other, so at least one of them needs its stack moved
+    pub fn shared_memory(mut self, memory: &ContiguousMemory) -> Self {
+        self.memory = Some(ByteUnits::Bytes(memory.len() as u64));
+        self.shared_memory = Some(memory.share());
+        self
+    }
+
 }
 
 fn empty_syscalls() -> SyscallVector {
-    SyscallVector([SystemFunction::default(); 1024])
+    SyscallVector::empty()
 }
 
 fn empty_interrupts() -> InterruptVector {
-    InterruptVector([SystemFunction::default(); 255])
+    InterruptVector::empty()
 }
\ No newline at end of file
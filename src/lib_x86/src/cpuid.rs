@@ -0,0 +1,518 @@
+//! What the CPUID instruction reports
+//!
+//! A `CpuProfile` describes the processor the guest sees: vendor, family / model / stepping,
+//! brand string, cache hierarchy and the instruction set extensions it advertises. The
+//! CPUID leaves are worked out from it, so the profile given to `MachineBuilder` decides
+//! which code paths a guest picks (libc chooses its memcpy that way).
+//!
+//! `CpuProfile::emulator()`, the default, only advertises what the emulator implements. The
+//! presets for real processors advertise everything the processor has, implemented or not.
+use std::collections::HashSet;
+
+/// Register of a CPUID result
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CpuidRegister {
+    Eax,
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+/// Instruction set extensions and other capabilities advertised through feature bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    Fpu,
+    Tsc,
+    Cx8,
+    Cmov,
+    Clflush,
+    Mmx,
+    Fxsr,
+    Sse,
+    Sse2,
+    Sse3,
+    Pclmulqdq,
+    Ssse3,
+    Fma,
+    Cx16,
+    Sse41,
+    Sse42,
+    Movbe,
+    Popcnt,
+    Aes,
+    Xsave,
+    /// The OS has enabled XSAVE (CR4.OSXSAVE), needed before AVX may be used
+    Osxsave,
+    Xsaveopt,
+    /// The compacted form of the XSAVE area
+    Xsavec,
+    Avx,
+    F16c,
+    Rdrand,
+    Bmi1,
+    Avx2,
+    Bmi2,
+    /// Enhanced REP MOVSB / STOSB
+    Erms,
+    Mpx,
+    Avx512f,
+    Avx512dq,
+    Rdseed,
+    Adx,
+    Avx512ifma,
+    Avx512cd,
+    Sha,
+    Avx512bw,
+    Avx512vl,
+    Avx512vbmi,
+    /// VEX.256 and EVEX forms of the AES instructions
+    Vaes,
+    /// VEX.256 and EVEX forms of PCLMULQDQ
+    Vpclmulqdq,
+    /// Fast short REP MOVSB
+    Fsrm,
+    /// LAHF / SAHF in 64-bit mode
+    LahfLm,
+    /// LZCNT
+    Abm,
+    Prefetchw,
+    Syscall,
+    /// No-execute pages
+    Nx,
+    Rdtscp,
+    /// 64-bit mode
+    LongMode,
+}
+
+impl Feature {
+    /// Leaf, subleaf, register and bit that advertise the feature
+    pub fn location(&self) -> (u32, u32, CpuidRegister, u8) {
+        use CpuidRegister::*;
+        match self {
+            Feature::Fpu => (1, 0, Edx, 0),
+            Feature::Tsc => (1, 0, Edx, 4),
+            Feature::Cx8 => (1, 0, Edx, 8),
+            Feature::Cmov => (1, 0, Edx, 15),
+            Feature::Clflush => (1, 0, Edx, 19),
+            Feature::Mmx => (1, 0, Edx, 23),
+            Feature::Fxsr => (1, 0, Edx, 24),
+            Feature::Sse => (1, 0, Edx, 25),
+            Feature::Sse2 => (1, 0, Edx, 26),
+            Feature::Sse3 => (1, 0, Ecx, 0),
+            Feature::Pclmulqdq => (1, 0, Ecx, 1),
+            Feature::Ssse3 => (1, 0, Ecx, 9),
+            Feature::Fma => (1, 0, Ecx, 12),
+            Feature::Cx16 => (1, 0, Ecx, 13),
+            Feature::Sse41 => (1, 0, Ecx, 19),
+            Feature::Sse42 => (1, 0, Ecx, 20),
+            Feature::Movbe => (1, 0, Ecx, 22),
+            Feature::Popcnt => (1, 0, Ecx, 23),
+            Feature::Aes => (1, 0, Ecx, 25),
+            Feature::Xsave => (1, 0, Ecx, 26),
+            Feature::Osxsave => (1, 0, Ecx, 27),
+            Feature::Xsaveopt => (0xD, 1, Eax, 0),
+            Feature::Xsavec => (0xD, 1, Eax, 1),
+            Feature::Avx => (1, 0, Ecx, 28),
+            Feature::F16c => (1, 0, Ecx, 29),
+            Feature::Rdrand => (1, 0, Ecx, 30),
+            Feature::Bmi1 => (7, 0, Ebx, 3),
+            Feature::Avx2 => (7, 0, Ebx, 5),
+            Feature::Bmi2 => (7, 0, Ebx, 8),
+            Feature::Erms => (7, 0, Ebx, 9),
+            Feature::Mpx => (7, 0, Ebx, 14),
+            Feature::Avx512f => (7, 0, Ebx, 16),
+            Feature::Avx512dq => (7, 0, Ebx, 17),
+            Feature::Rdseed => (7, 0, Ebx, 18),
+            Feature::Adx => (7, 0, Ebx, 19),
+            Feature::Avx512ifma => (7, 0, Ebx, 21),
+            Feature::Avx512cd => (7, 0, Ebx, 28),
+            Feature::Sha => (7, 0, Ebx, 29),
+            Feature::Avx512bw => (7, 0, Ebx, 30),
+            Feature::Avx512vl => (7, 0, Ebx, 31),
+            Feature::Avx512vbmi => (7, 0, Ecx, 1),
+            Feature::Vaes => (7, 0, Ecx, 9),
+            Feature::Vpclmulqdq => (7, 0, Ecx, 10),
+            Feature::Fsrm => (7, 0, Edx, 4),
+            Feature::LahfLm => (0x8000_0001, 0, Ecx, 0),
+            Feature::Abm => (0x8000_0001, 0, Ecx, 5),
+            Feature::Prefetchw => (0x8000_0001, 0, Ecx, 8),
+            Feature::Syscall => (0x8000_0001, 0, Edx, 11),
+            Feature::Nx => (0x8000_0001, 0, Edx, 20),
+            Feature::Rdtscp => (0x8000_0001, 0, Edx, 27),
+            Feature::LongMode => (0x8000_0001, 0, Edx, 29),
+        }
+    }
+}
+
+/// Features the emulator implements, which `CpuProfile::emulator()` advertises
+pub const IMPLEMENTED_FEATURES: &[Feature] = &[
+    Feature::Fpu,
+    Feature::Cx8,
+    Feature::Cmov,
+    Feature::Mmx,
+    Feature::Fxsr,
+    Feature::Sse,
+    Feature::Sse2,
+    Feature::Sse3,
+    Feature::Pclmulqdq,
+    Feature::Ssse3,
+    Feature::Cx16,
+    Feature::Sse41,
+    Feature::Sse42,
+    Feature::Popcnt,
+    Feature::Aes,
+    Feature::Fma,
+    Feature::Xsave,
+    Feature::Osxsave,
+    Feature::Xsaveopt,
+    Feature::Xsavec,
+    Feature::Avx,
+    Feature::F16c,
+    Feature::Avx2,
+    Feature::Avx512f,
+    Feature::Avx512dq,
+    Feature::Avx512cd,
+    Feature::Avx512bw,
+    Feature::Avx512vl,
+    Feature::Avx512vbmi,
+    Feature::Vaes,
+    Feature::Vpclmulqdq,
+    Feature::Bmi1,
+    Feature::Bmi2,
+    Feature::Erms,
+    Feature::Mpx,
+    Feature::Adx,
+    Feature::Sha,
+    Feature::LahfLm,
+    Feature::Abm,
+    Feature::Syscall,
+    Feature::LongMode,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKind {
+    Data,
+    Instruction,
+    Unified,
+}
+
+/// One cache of the hierarchy, reported through leaf 4 (and 0x80000006 for level 2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cache {
+    pub level: u8,
+    pub kind: CacheKind,
+    pub line_size: u16,
+    pub ways: u16,
+    pub sets: u32,
+    /// Logical processors sharing the cache
+    pub shared_by: u16,
+}
+
+impl Cache {
+    pub fn new(level: u8, kind: CacheKind, line_size: u16, ways: u16, sets: u32, shared_by: u16) -> Cache {
+        Cache {
+            level,
+            kind,
+            line_size,
+            ways,
+            sets,
+            shared_by,
+        }
+    }
+
+    /// Size in bytes
+    pub fn size(&self) -> u64 {
+        self.line_size as u64 * self.ways as u64 * self.sets as u64
+    }
+}
+
+/// The processor CPUID describes. See the module documentation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuProfile {
+    /// 12 bytes, eg `GenuineIntel`
+    pub vendor: [u8; 12],
+    /// Up to 48 bytes, padded with NULs
+    pub brand: String,
+    pub family: u16,
+    pub model: u8,
+    pub stepping: u8,
+    pub features: HashSet<Feature>,
+    pub caches: Vec<Cache>,
+    /// Logical processors in the package
+    pub logical_processors: u8,
+    pub physical_address_bits: u8,
+    pub linear_address_bits: u8,
+}
+
+/// XCR0 bits of the state components XSAVE manages
+pub const XCR0_X87: u64 = 1 << 0;
+pub const XCR0_SSE: u64 = 1 << 1;
+/// the upper halves of YMM0-15
+pub const XCR0_AVX: u64 = 1 << 2;
+/// BND0-3
+pub const XCR0_BNDREGS: u64 = 1 << 3;
+/// BNDCFGU and BNDSTATUS
+pub const XCR0_BNDCSR: u64 = 1 << 4;
+pub const XCR0_OPMASK: u64 = 1 << 5;
+/// the upper halves of ZMM0-15
+pub const XCR0_ZMM_HI256: u64 = 1 << 6;
+/// ZMM16-31
+pub const XCR0_HI16_ZMM: u64 = 1 << 7;
+
+/// Bytes of the legacy region and header of an XSAVE area, where the extended components start
+/// in the compacted form
+pub(crate) const XSAVE_EXTENDED: u32 = 512 + 64;
+
+/// Size and offset in the standard form of the XSAVE area of an extended state component, as
+/// leaf 0xD reports them. x87 and SSE state is in the legacy region
+pub(crate) fn xsave_component(component: u32) -> Option<(u32, u32)> {
+    match component {
+        2 => Some((256, 576)),
+        3 => Some((64, 960)),
+        4 => Some((64, 1024)),
+        5 => Some((64, 1088)),
+        6 => Some((512, 1152)),
+        7 => Some((1024, 1664)),
+        _ => None,
+    }
+}
+
+/// The extended components of an XCR0 value, lowest first
+pub(crate) fn xsave_components(xcr0: u64) -> impl Iterator<Item = u32> {
+    (2..64).filter(move |&component| xcr0 >> component & 1 != 0)
+}
+
+/// Size of the standard form of the XSAVE area for the components of `xcr0`
+pub fn xsave_size(xcr0: u64) -> u32 {
+    xsave_components(xcr0)
+        .filter_map(xsave_component)
+        .map(|(size, offset)| offset + size)
+        .fold(XSAVE_EXTENDED, u32::max)
+}
+
+/// Size of the compacted form of the XSAVE area for the components of `xcr0`
+pub fn xsave_compacted_size(xcr0: u64) -> u32 {
+    XSAVE_EXTENDED + xsave_components(xcr0).filter_map(xsave_component).map(|(size, _)| size).sum::<u32>()
+}
+
+/// Highest basic and extended leaves reported
+const MAX_BASIC_LEAF: u32 = 0xD;
+const MAX_EXTENDED_LEAF: u32 = 0x8000_0008;
+
+impl Default for CpuProfile {
+    fn default() -> Self {
+        CpuProfile::emulator()
+    }
+}
+
+impl CpuProfile {
+    /// A generic Intel 64 processor advertising only the extensions the emulator implements
+    pub fn emulator() -> CpuProfile {
+        CpuProfile {
+            vendor: *b"GenuineIntel",
+            brand: "x86_rs virtual CPU".to_string(),
+            family: 6,
+            model: 0x5E,
+            stepping: 3,
+            features: IMPLEMENTED_FEATURES.iter().copied().collect(),
+            caches: vec![
+                Cache::new(1, CacheKind::Data, 64, 8, 64, 1),
+                Cache::new(1, CacheKind::Instruction, 64, 8, 64, 1),
+                Cache::new(2, CacheKind::Unified, 64, 4, 1024, 1),
+                Cache::new(3, CacheKind::Unified, 64, 16, 8192, 1),
+            ],
+            logical_processors: 1,
+            physical_address_bits: 39,
+            linear_address_bits: 48,
+        }
+    }
+
+    /// A Skylake client core (Core i7-6700K)
+    pub fn skylake() -> CpuProfile {
+        use Feature::*;
+        let features = [
+            Fpu, Tsc, Cx8, Cmov, Clflush, Mmx, Fxsr, Sse, Sse2, Sse3, Pclmulqdq, Ssse3, Fma, Cx16, Sse41, Sse42,
+            Movbe, Popcnt, Aes, Xsave, Osxsave, Xsaveopt, Xsavec, Avx, F16c, Rdrand, Bmi1, Avx2, Bmi2, Erms, Mpx,
+            Rdseed, Adx, LahfLm, Abm, Prefetchw, Syscall, Nx, Rdtscp, LongMode,
+        ];
+        CpuProfile {
+            brand: "Intel(R) Core(TM) i7-6700K CPU @ 4.00GHz".to_string(),
+            features: features.into_iter().collect(),
+            caches: vec![
+                Cache::new(1, CacheKind::Data, 64, 8, 64, 2),
+                Cache::new(1, CacheKind::Instruction, 64, 8, 64, 2),
+                Cache::new(2, CacheKind::Unified, 64, 4, 1024, 2),
+                Cache::new(3, CacheKind::Unified, 64, 16, 8192, 16),
+            ],
+            logical_processors: 8,
+            ..CpuProfile::emulator()
+        }
+    }
+
+    /// A Skylake server core (Xeon Gold 6148), which adds AVX-512
+    pub fn skylake_server() -> CpuProfile {
+        use Feature::*;
+        let mut profile = CpuProfile::skylake();
+        profile.brand = "Intel(R) Xeon(R) Gold 6148 CPU @ 2.40GHz".to_string();
+        profile.model = 0x55;
+        profile.stepping = 4;
+        profile.features.extend([Avx512f, Avx512dq, Avx512cd, Avx512bw, Avx512vl]);
+        profile.caches = vec![
+            Cache::new(1, CacheKind::Data, 64, 8, 64, 2),
+            Cache::new(1, CacheKind::Instruction, 64, 8, 64, 2),
+            Cache::new(2, CacheKind::Unified, 64, 16, 1024, 2),
+            Cache::new(3, CacheKind::Unified, 64, 11, 40960, 40),
+        ];
+        profile.logical_processors = 40;
+        profile.physical_address_bits = 46;
+        profile
+    }
+
+    pub fn with_feature(mut self, feature: Feature) -> Self {
+        self.features.insert(feature);
+        self
+    }
+
+    pub fn without_feature(mut self, feature: Feature) -> Self {
+        self.features.remove(&feature);
+        self
+    }
+
+    pub fn has(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+
+    /// The XCR0 bits of the state components XSAVE can manage, which XSETBV may enable
+    pub fn supported_xcr0(&self) -> u64 {
+        let mut xcr0 = XCR0_X87 | XCR0_SSE;
+        if self.has(Feature::Avx) {
+            xcr0 |= XCR0_AVX;
+        }
+        if self.has(Feature::Mpx) {
+            xcr0 |= XCR0_BNDREGS | XCR0_BNDCSR;
+        }
+        if self.has(Feature::Avx512f) {
+            xcr0 |= XCR0_OPMASK | XCR0_ZMM_HI256 | XCR0_HI16_ZMM;
+        }
+        xcr0
+    }
+
+    /// EAX, EBX, ECX and EDX for a leaf (EAX) and subleaf (ECX). As on Intel processors a leaf
+    /// past the highest basic or extended one gets the values of the highest basic leaf
+    pub fn leaf(&self, leaf: u32, subleaf: u32) -> [u32; 4] {
+        let leaf = match leaf {
+            0..=MAX_BASIC_LEAF | 0x8000_0000..=MAX_EXTENDED_LEAF => leaf,
+            _ => MAX_BASIC_LEAF,
+        };
+        // only some leaves look at ECX
+        let subleaf = match leaf {
+            4 | 7 | 0xD => subleaf,
+            _ => 0,
+        };
+        let mut registers = match leaf {
+            0 => {
+                let word = |i: usize| u32::from_le_bytes(self.vendor[i..i + 4].try_into().expect("4 bytes"));
+                [MAX_BASIC_LEAF, word(0), word(8), word(4)]
+            }
+            1 => [self.signature(), 8 << 8 | (self.logical_processors as u32) << 16, 0, 0],
+            // no descriptors, leaf 4 has the caches
+            2 => [0x00FF_FF01, 0, 0, 0],
+            4 => self.cache_leaf(subleaf as usize),
+            0xD => self.xsave_leaf(subleaf),
+            0x8000_0000 => [MAX_EXTENDED_LEAF, 0, 0, 0],
+            0x8000_0002..=0x8000_0004 => {
+                let mut brand = [0; 48];
+                let length = self.brand.len().min(47);
+                brand[..length].copy_from_slice(&self.brand.as_bytes()[..length]);
+                let start = (leaf - 0x8000_0002) as usize * 16;
+                let word = |i: usize| u32::from_le_bytes(brand[start + i * 4..start + i * 4 + 4].try_into().expect("4 bytes"));
+                [word(0), word(1), word(2), word(3)]
+            }
+            0x8000_0006 => [0, 0, self.level_2_leaf(), 0],
+            0x8000_0008 => [(self.linear_address_bits as u32) << 8 | self.physical_address_bits as u32, 0, 0, 0],
+            _ => [0; 4],
+        };
+        for feature in &self.features {
+            let (feature_leaf, feature_subleaf, register, bit) = feature.location();
+            if feature_leaf == leaf && feature_subleaf == subleaf {
+                registers[register as usize] |= 1 << bit;
+            }
+        }
+        registers
+    }
+
+    /// Family, model and stepping in the leaf 1 encoding, with the extended fields used for
+    /// families 6 and 15 up
+    fn signature(&self) -> u32 {
+        let (family, extended_family) = match self.family {
+            0..15 => (self.family as u32, 0),
+            _ => (15, self.family as u32 - 15),
+        };
+        let (model, extended_model) = match self.family {
+            6 | 15.. => (self.model as u32 & 0xF, self.model as u32 >> 4),
+            _ => (self.model as u32 & 0xF, 0),
+        };
+        extended_family << 20 | extended_model << 16 | family << 8 | model << 4 | self.stepping as u32
+    }
+
+    /// Leaf 4, one subleaf per cache, then a null cache
+    fn cache_leaf(&self, index: usize) -> [u32; 4] {
+        let Some(cache) = self.caches.get(index) else {
+            return [0; 4];
+        };
+        let kind = match cache.kind {
+            CacheKind::Data => 1,
+            CacheKind::Instruction => 2,
+            CacheKind::Unified => 3,
+        };
+        let cores = (self.logical_processors as u32).max(1) - 1;
+        let eax = cores.min(0x3F) << 26
+            | (cache.shared_by as u32).saturating_sub(1).min(0xFFF) << 14
+            | 1 << 8
+            | (cache.level as u32) << 5
+            | kind;
+        let ebx = (cache.ways as u32 - 1) << 22 | (cache.line_size as u32 - 1);
+        [eax, ebx, cache.sets - 1, 0]
+    }
+
+    /// Leaf 0xD: the supported components and the size of the XSAVE area for all of them in
+    /// subleaf 0, then the size and offset of each extended component. The sizes for what XCR0
+    /// enables are filled in by the machine
+    fn xsave_leaf(&self, subleaf: u32) -> [u32; 4] {
+        let supported = self.supported_xcr0();
+        match subleaf {
+            0 => {
+                let size = xsave_size(supported);
+                [supported as u32, size, size, (supported >> 32) as u32]
+            }
+            1 => [0, xsave_compacted_size(supported), 0, 0],
+            _ if supported >> subleaf & 1 == 0 => [0; 4],
+            _ => match xsave_component(subleaf) {
+                Some((size, offset)) => [size, offset, 0, 0],
+                None => [0; 4],
+            },
+        }
+    }
+
+    /// ECX of leaf 0x80000006: size in KiB, associativity and line size of the level 2 cache
+    fn level_2_leaf(&self) -> u32 {
+        let Some(cache) = self.caches.iter().find(|cache| cache.level == 2) else {
+            return 0;
+        };
+        let associativity = match cache.ways {
+            1 => 1,
+            2 => 2,
+            4 => 4,
+            8 => 6,
+            16 => 8,
+            32 => 0xA,
+            48 => 0xB,
+            64 => 0xC,
+            96 => 0xD,
+            128 => 0xE,
+            // no code of its own, see leaf 4
+            _ => 7,
+        };
+        ((cache.size() / 1024) as u32) << 16 | associativity << 12 | cache.line_size as u32
+    }
+}
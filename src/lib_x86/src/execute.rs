@@ -0,0 +1,1027 @@
+//! Fetch, decode and execute
+//!
+//! `step` runs a single instruction from `instruction_counter`; `run` and `run_until` repeat it
+//! until something stops the machine, and say why through `StopReason`.
+//!
+//! Guest addresses are offsets into `memory`, there is no paging. Exceptions are delivered
+//! through `interrupts` by vector number: if the guest (or an intrinsic) handles the vector
+//! execution carries on, otherwise the machine stops with a `Fault`.
+mod alu;
+mod atomic;
+mod avx;
+mod avx512;
+mod bits;
+mod branch;
+mod crypto;
+mod data;
+mod mmx;
+mod mpx;
+mod muldiv;
+mod operands;
+mod packed;
+mod shift;
+mod sse;
+mod stack;
+mod string;
+mod system;
+mod x87;
+mod xsave;
+
+use crate::execute::alu::Alu;
+use crate::execute::avx::Fused;
+use crate::execute::avx512::{mask_size, OpmaskLogic};
+use crate::execute::bits::{BitTest, LowestBit};
+use crate::execute::crypto::AesRound;
+use crate::execute::mmx::is_mmx;
+use crate::execute::mpx::BoundCheck;
+use crate::execute::packed::Lanewise;
+use crate::execute::shift::Shift;
+use crate::execute::sse::{Arithmetic, Element, Lanes, Logic};
+use crate::execute::string::StringOp;
+use crate::flags::{Condition, FlagOp, RFlags};
+use crate::functions::SystemFunction;
+use crate::softfloat::{Constant, DOUBLE, SINGLE};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{decode_at, Bitness, DecodeError, Mnemonic, Operand, X86Opcode, MAX_INSTRUCTION_LENGTH};
+
+pub(crate) use atomic::LockedAccess;
+pub(crate) use operands::{mask, sign_extend};
+pub(crate) use sse::MXCSR_DEFAULT;
+
+/// Why `step`, `run` or `run_until` handed control back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// `hlt`
+    Halted,
+    /// an `int3` with nothing handling vector 3, or an address in `breakpoints`. Holds the
+    /// address of the instruction
+    Breakpoint(u64),
+    Fault(Fault),
+    /// an intrinsic asked for the guest to exit (see `X86Machine::exit`), with its status
+    Exit(u64),
+    /// `instruction_budget` instructions ran without anything else stopping the machine
+    BudgetExhausted,
+    /// the `run_until` predicate held
+    Condition,
+}
+
+/// Something the guest could not recover from. `instruction_counter` is left on the
+/// instruction at `address`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// an exception with nothing handling its vector
+    Exception { exception: Exception, address: u64 },
+    /// the instruction decodes, but executing it is not implemented
+    Unsupported { mnemonic: Mnemonic, address: u64 },
+    /// `int n` with nothing handling vector n
+    InterruptNotFound { vector: u8, address: u64 },
+    /// `syscall` with a number that has no entry in `syscalls`
+    SyscallNotFound { number: u64, address: u64 },
+}
+
+/// Architectural exceptions, see `vector` for their interrupt numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    /// #DE
+    DivideError,
+    /// #DB
+    Debug,
+    /// #BP
+    Breakpoint,
+    /// #OF
+    Overflow,
+    /// #BR
+    BoundRange,
+    /// #UD
+    InvalidOpcode,
+    /// #NM
+    DeviceNotAvailable,
+    /// #SS
+    StackFault,
+    /// #GP
+    GeneralProtection,
+    /// #PF, with the address that could not be accessed
+    PageFault(u64),
+    /// #MF
+    FloatingPoint,
+    /// #AC
+    AlignmentCheck,
+    /// #XM
+    SimdFloatingPoint,
+}
+
+impl Exception {
+    pub fn vector(&self) -> u8 {
+        match self {
+            Exception::DivideError => 0,
+            Exception::Debug => 1,
+            Exception::Breakpoint => 3,
+            Exception::Overflow => 4,
+            Exception::BoundRange => 5,
+            Exception::InvalidOpcode => 6,
+            Exception::DeviceNotAvailable => 7,
+            Exception::StackFault => 12,
+            Exception::GeneralProtection => 13,
+            Exception::PageFault(_) => 14,
+            Exception::FloatingPoint => 16,
+            Exception::AlignmentCheck => 17,
+            Exception::SimdFloatingPoint => 19,
+        }
+    }
+
+    /// Traps are reported once the instruction has completed, faults leave the instruction
+    /// counter on the instruction so that it can be restarted
+    pub fn is_trap(&self) -> bool {
+        matches!(self, Exception::Debug | Exception::Breakpoint | Exception::Overflow)
+    }
+}
+
+/// What interrupted an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Trap {
+    /// delivered through `interrupts`
+    Exception(Exception),
+    /// `int n`, also delivered through `interrupts`
+    Interrupt(u8),
+    /// stops the machine without the guest getting involved
+    Stop(StopReason),
+}
+
+impl From<Exception> for Trap {
+    fn from(exception: Exception) -> Self {
+        Trap::Exception(exception)
+    }
+}
+
+pub(crate) type Execution = Result<(), Trap>;
+
+/// The instruction is not implemented
+pub(crate) fn unsupported(op: &X86Opcode) -> Trap {
+    Trap::Stop(StopReason::Fault(Fault::Unsupported {
+        mnemonic: op.mnemonic,
+        address: op.ip,
+    }))
+}
+
+impl X86Machine {
+    /// Executes the instruction at `instruction_counter`. Returns `None` if the machine can
+    /// carry on
+    ///
+    /// Address breakpoints are not checked, so stepping is also how to get past one
+    pub fn step(&mut self) -> Option<StopReason> {
+        let ip = self.instruction_counter;
+        let trap = match self.fetch(ip) {
+            Ok(op) => {
+                self.instruction_counter = op.next_ip();
+                self.execute(&op).err()
+            }
+            Err(trap) => Some(trap),
+        };
+        match trap {
+            None => {
+                self.instructions_retired += 1;
+                self.pending_stop.take()
+            }
+            Some(trap) => self.trap(trap, ip),
+        }
+    }
+
+    /// Executes until the machine stops, see `StopReason`
+    pub fn run(&mut self) -> StopReason {
+        self.run_until(|_| false)
+    }
+
+    /// Executes until `predicate` holds, checking it before every instruction, or the
+    /// machine stops for some other reason
+    ///
+    /// A breakpoint on the first instruction is ignored, so that a run resumes from the
+    /// breakpoint it last stopped at
+    pub fn run_until(&mut self, mut predicate: impl FnMut(&X86Machine) -> bool) -> StopReason {
+        let mut executed = 0u64;
+        loop {
+            if predicate(self) {
+                return StopReason::Condition;
+            }
+            if self.instruction_budget.is_some_and(|budget| executed >= budget) {
+                return StopReason::BudgetExhausted;
+            }
+            let ip = self.instruction_counter;
+            if executed > 0 && self.breakpoints.contains(&ip) {
+                return StopReason::Breakpoint(ip);
+            }
+            executed += 1;
+            if let Some(reason) = self.step() {
+                return reason;
+            }
+        }
+    }
+
+    /// Stops the machine with `StopReason::Exit` once the current instruction completes.
+    /// Meant for intrinsics implementing `exit` style syscalls
+    pub fn exit(&mut self, status: u64) {
+        self.pending_stop = Some(StopReason::Exit(status));
+    }
+
+    fn fetch(&self, ip: u64) -> Result<X86Opcode, Trap> {
+        let start = usize::try_from(ip)
+            .ok()
+            .filter(|&start| start < self.memory.len())
+            .ok_or(Exception::PageFault(ip))?;
+        let end = self.memory.len().min(start + MAX_INSTRUCTION_LENGTH);
+        let bytes = self.memory.read(start, end - start).expect("checked to be in bounds");
+        decode_at(Bitness::Bits64, &bytes, ip).map_err(|e| match e {
+            // the instruction runs off the end of memory
+            DecodeError::UnexpectedEnd => Exception::PageFault(end as u64).into(),
+            _ => Exception::InvalidOpcode.into(),
+        })
+    }
+
+    fn execute(&mut self, op: &X86Opcode) -> Execution {
+        self.check_lock(op)?;
+        self.check_xcr0(op)?;
+        if is_mmx(op) {
+            self.enter_mmx()?;
+        }
+        match self.needs_lock(op) {
+            true => self.locked(op),
+            false => self.dispatch(op),
+        }
+    }
+
+    fn dispatch(&mut self, op: &X86Opcode) -> Execution {
+        use Mnemonic as M;
+        // the VEX forms of SSE instructions share their handlers, which look at `op.vex`
+        match op.mnemonic.legacy_form().unwrap_or(op.mnemonic) {
+            M::Nop | M::Pause | M::Endbr32 | M::Endbr64 => Ok(()),
+            M::Prefetch | M::Prefetchw | M::Prefetchnta | M::Prefetcht0 | M::Prefetcht1 | M::Prefetcht2 => Ok(()),
+            M::Hlt => Err(Trap::Stop(StopReason::Halted)),
+            M::Int3 => Err(Exception::Breakpoint.into()),
+            M::Int1 => Err(Exception::Debug.into()),
+            M::Int => self.int(op),
+            M::Ud0 | M::Ud1 | M::Ud2 => Err(Exception::InvalidOpcode.into()),
+            M::Syscall => self.syscall(op),
+            M::Cpuid => self.cpuid(),
+
+            M::Mov | M::Movzx => self.mov(op),
+            M::Movsx | M::Movsxd => self.movsx(op),
+            M::Lea => self.lea(op),
+            M::Xlatb => self.xlatb(op),
+            M::Cbw | M::Cwde | M::Cdqe => self.extend_accumulator(op),
+            M::Cwd | M::Cdq | M::Cqo => self.extend_into_high(op),
+
+            M::Add => self.alu(op, Alu::Add, true),
+            M::Adc => self.alu(op, Alu::Adc, true),
+            M::Sub => self.alu(op, Alu::Sub, true),
+            M::Sbb => self.alu(op, Alu::Sbb, true),
+            M::Cmp => self.alu(op, Alu::Sub, false),
+            M::And => self.alu(op, Alu::And, true),
+            M::Or => self.alu(op, Alu::Or, true),
+            M::Xor => self.alu(op, Alu::Xor, true),
+            M::Test => self.alu(op, Alu::And, false),
+            M::Inc => self.inc_dec(op, FlagOp::Inc),
+            M::Dec => self.inc_dec(op, FlagOp::Dec),
+            M::Neg => self.neg(op),
+            M::Not => self.not(op),
+            M::Clc => self.carry(|_| false),
+            M::Stc => self.carry(|_| true),
+            M::Cmc => self.carry(|carry| !carry),
+            M::Mul => self.mul(op),
+            M::Imul => self.imul(op),
+            M::Div => self.div(op),
+            M::Idiv => self.idiv(op),
+            M::Mulx => self.mulx(op),
+
+            M::Shl | M::Sal => self.shift(op, Shift::Shl),
+            M::Shr => self.shift(op, Shift::Shr),
+            M::Sar => self.shift(op, Shift::Sar),
+            M::Rol => self.shift(op, Shift::Rol),
+            M::Ror => self.shift(op, Shift::Ror),
+            M::Rcl => self.shift(op, Shift::Rcl),
+            M::Rcr => self.shift(op, Shift::Rcr),
+            M::Shld => self.double_shift(op, true),
+            M::Shrd => self.double_shift(op, false),
+            M::Shlx => self.shift_flagless(op, Shift::Shl),
+            M::Shrx => self.shift_flagless(op, Shift::Shr),
+            M::Sarx => self.shift_flagless(op, Shift::Sar),
+            M::Rorx => self.rorx(op),
+
+            M::Xchg => self.xchg(op),
+            M::Xadd => self.xadd(op),
+            M::Cmpxchg => self.cmpxchg(op),
+            M::Cmpxchg8b | M::Cmpxchg16b => self.cmpxchg_wide(op),
+            M::Mfence | M::Lfence | M::Sfence => self.fence(op),
+
+            M::Bt => self.bit_test(op, BitTest::Test),
+            M::Bts => self.bit_test(op, BitTest::Set),
+            M::Btr => self.bit_test(op, BitTest::Reset),
+            M::Btc => self.bit_test(op, BitTest::Complement),
+            M::Bsf => self.bit_scan(op, true),
+            M::Bsr => self.bit_scan(op, false),
+            M::Popcnt => self.popcnt(op),
+            M::Lzcnt => self.count_zeros(op, true),
+            M::Tzcnt => self.count_zeros(op, false),
+            M::Andn => self.andn(op),
+            M::Bextr => self.bextr(op),
+            M::Blsi => self.lowest_bit(op, LowestBit::Isolate),
+            M::Blsmsk => self.lowest_bit(op, LowestBit::Mask),
+            M::Blsr => self.lowest_bit(op, LowestBit::Reset),
+            M::Bzhi => self.bzhi(op),
+            M::Pdep => self.pdep(op),
+            M::Pext => self.pext(op),
+            M::Adcx => self.add_carry(op, RFlags::Carry),
+            M::Adox => self.add_carry(op, RFlags::Overflow),
+            M::Bswap => self.bswap(op),
+
+            M::Push => self.push_operand(op),
+            M::Pop => self.pop_operand(op),
+            M::Pushf | M::Pushfq => self.pushf(op),
+            M::Popf | M::Popfq => self.popf(op),
+            M::Lahf => self.lahf(),
+            M::Sahf => self.sahf(),
+            M::Enter => self.enter(op),
+            M::Leave => self.leave(op),
+
+            M::Jmp => self.jmp(op),
+            M::Jmpf => self.jmp_far(op),
+            M::Call => self.call(op),
+            M::Callf => self.call_far(op),
+            M::Ret => self.ret(op),
+            M::Retf => self.ret_far(op),
+            M::Loop => self.loop_cc(op, None),
+            M::Loope => self.loop_cc(op, Some(true)),
+            M::Loopne => self.loop_cc(op, Some(false)),
+            M::Jcxz => self.jump_if_counter_zero(op, 2),
+            M::Jecxz => self.jump_if_counter_zero(op, 4),
+            M::Jrcxz => self.jump_if_counter_zero(op, 8),
+
+            M::Cld => self.direction(false),
+            M::Std => self.direction(true),
+            M::Movsb => self.string(op, StringOp::Movs, 1),
+            M::Movs => self.string(op, StringOp::Movs, op.operand_size as usize),
+            M::Stosb => self.string(op, StringOp::Stos, 1),
+            M::Stos => self.string(op, StringOp::Stos, op.operand_size as usize),
+            M::Lodsb => self.string(op, StringOp::Lods, 1),
+            M::Lods => self.string(op, StringOp::Lods, op.operand_size as usize),
+            M::Scasb => self.string(op, StringOp::Scas, 1),
+            M::Scas => self.string(op, StringOp::Scas, op.operand_size as usize),
+            M::Cmpsb => self.string(op, StringOp::Cmps, 1),
+            M::Cmps => self.string(op, StringOp::Cmps, op.operand_size as usize),
+
+            M::Movaps | M::Movapd => self.move_vector(op, true),
+            M::Movups | M::Movupd => self.move_vector(op, false),
+            M::Movss => self.move_scalar(op, 4),
+            M::Movsd => self.move_scalar(op, 8),
+            M::Movlps | M::Movlpd | M::Movhlps => self.move_half(op, false),
+            M::Movhps | M::Movhpd | M::Movlhps => self.move_half(op, true),
+            M::Addps => self.float_arithmetic(op, Arithmetic::Add, SINGLE, Lanes::Packed),
+            M::Addpd => self.float_arithmetic(op, Arithmetic::Add, DOUBLE, Lanes::Packed),
+            M::Addss => self.float_arithmetic(op, Arithmetic::Add, SINGLE, Lanes::Scalar),
+            M::Addsd => self.float_arithmetic(op, Arithmetic::Add, DOUBLE, Lanes::Scalar),
+            M::Subps => self.float_arithmetic(op, Arithmetic::Sub, SINGLE, Lanes::Packed),
+            M::Subpd => self.float_arithmetic(op, Arithmetic::Sub, DOUBLE, Lanes::Packed),
+            M::Subss => self.float_arithmetic(op, Arithmetic::Sub, SINGLE, Lanes::Scalar),
+            M::Subsd => self.float_arithmetic(op, Arithmetic::Sub, DOUBLE, Lanes::Scalar),
+            M::Mulps => self.float_arithmetic(op, Arithmetic::Mul, SINGLE, Lanes::Packed),
+            M::Mulpd => self.float_arithmetic(op, Arithmetic::Mul, DOUBLE, Lanes::Packed),
+            M::Mulss => self.float_arithmetic(op, Arithmetic::Mul, SINGLE, Lanes::Scalar),
+            M::Mulsd => self.float_arithmetic(op, Arithmetic::Mul, DOUBLE, Lanes::Scalar),
+            M::Divps => self.float_arithmetic(op, Arithmetic::Div, SINGLE, Lanes::Packed),
+            M::Divpd => self.float_arithmetic(op, Arithmetic::Div, DOUBLE, Lanes::Packed),
+            M::Divss => self.float_arithmetic(op, Arithmetic::Div, SINGLE, Lanes::Scalar),
+            M::Divsd => self.float_arithmetic(op, Arithmetic::Div, DOUBLE, Lanes::Scalar),
+            M::Minps => self.float_arithmetic(op, Arithmetic::Min, SINGLE, Lanes::Packed),
+            M::Minpd => self.float_arithmetic(op, Arithmetic::Min, DOUBLE, Lanes::Packed),
+            M::Minss => self.float_arithmetic(op, Arithmetic::Min, SINGLE, Lanes::Scalar),
+            M::Minsd => self.float_arithmetic(op, Arithmetic::Min, DOUBLE, Lanes::Scalar),
+            M::Maxps => self.float_arithmetic(op, Arithmetic::Max, SINGLE, Lanes::Packed),
+            M::Maxpd => self.float_arithmetic(op, Arithmetic::Max, DOUBLE, Lanes::Packed),
+            M::Maxss => self.float_arithmetic(op, Arithmetic::Max, SINGLE, Lanes::Scalar),
+            M::Maxsd => self.float_arithmetic(op, Arithmetic::Max, DOUBLE, Lanes::Scalar),
+            M::Sqrtps => self.float_arithmetic(op, Arithmetic::Sqrt, SINGLE, Lanes::Packed),
+            M::Sqrtpd => self.float_arithmetic(op, Arithmetic::Sqrt, DOUBLE, Lanes::Packed),
+            M::Sqrtss => self.float_arithmetic(op, Arithmetic::Sqrt, SINGLE, Lanes::Scalar),
+            M::Sqrtsd => self.float_arithmetic(op, Arithmetic::Sqrt, DOUBLE, Lanes::Scalar),
+            M::Rcpps => self.reciprocal(op, SINGLE, Lanes::Packed, false),
+            M::Rcpss => self.reciprocal(op, SINGLE, Lanes::Scalar, false),
+            M::Rsqrtps => self.reciprocal(op, SINGLE, Lanes::Packed, true),
+            M::Rsqrtss => self.reciprocal(op, SINGLE, Lanes::Scalar, true),
+            M::Haddps => self.float_horizontal(op, Arithmetic::Add, SINGLE),
+            M::Haddpd => self.float_horizontal(op, Arithmetic::Add, DOUBLE),
+            M::Hsubps => self.float_horizontal(op, Arithmetic::Sub, SINGLE),
+            M::Hsubpd => self.float_horizontal(op, Arithmetic::Sub, DOUBLE),
+            M::Addsubps => self.float_add_subtract(op, SINGLE),
+            M::Addsubpd => self.float_add_subtract(op, DOUBLE),
+            M::Roundps => self.round(op, SINGLE, Lanes::Packed, false),
+            M::Roundpd => self.round(op, DOUBLE, Lanes::Packed, false),
+            M::Roundss => self.round(op, SINGLE, Lanes::Scalar, false),
+            M::Roundsd => self.round(op, DOUBLE, Lanes::Scalar, false),
+            M::Dpps => self.dot_product(op, SINGLE),
+            M::Dppd => self.dot_product(op, DOUBLE),
+            M::Andps | M::Andpd => self.float_logic(op, Logic::And),
+            M::Andnps | M::Andnpd => self.float_logic(op, Logic::AndNot),
+            M::Orps | M::Orpd => self.float_logic(op, Logic::Or),
+            M::Xorps | M::Xorpd => self.float_logic(op, Logic::Xor),
+            M::Cmpps => self.float_compare(op, SINGLE, Lanes::Packed),
+            M::Cmppd => self.float_compare(op, DOUBLE, Lanes::Packed),
+            M::Cmpss => self.float_compare(op, SINGLE, Lanes::Scalar),
+            M::Cmpsd => self.float_compare(op, DOUBLE, Lanes::Scalar),
+            M::Comiss => self.float_compare_flags(op, SINGLE, true),
+            M::Ucomiss => self.float_compare_flags(op, SINGLE, false),
+            M::Comisd => self.float_compare_flags(op, DOUBLE, true),
+            M::Ucomisd => self.float_compare_flags(op, DOUBLE, false),
+            M::Cvtsi2ss => self.convert_from_integer(op, SINGLE, true),
+            M::Cvtsi2sd => self.convert_from_integer(op, DOUBLE, true),
+            M::Cvtss2si => self.convert_to_integer(op, SINGLE, false, true),
+            M::Cvttss2si => self.convert_to_integer(op, SINGLE, true, true),
+            M::Cvtsd2si => self.convert_to_integer(op, DOUBLE, false, true),
+            M::Cvttsd2si => self.convert_to_integer(op, DOUBLE, true, true),
+            M::Cvtss2sd => self.convert(op, Element::Float(SINGLE), Element::Float(DOUBLE), Lanes::Scalar, false),
+            M::Cvtsd2ss => self.convert(op, Element::Float(DOUBLE), Element::Float(SINGLE), Lanes::Scalar, false),
+            M::Cvtps2pd => self.convert(op, Element::Float(SINGLE), Element::Float(DOUBLE), Lanes::Packed, false),
+            M::Cvtpd2ps => self.convert(op, Element::Float(DOUBLE), Element::Float(SINGLE), Lanes::Packed, false),
+            M::Cvtdq2ps => self.convert(op, Element::Int32, Element::Float(SINGLE), Lanes::Packed, false),
+            M::Cvtps2dq => self.convert(op, Element::Float(SINGLE), Element::Int32, Lanes::Packed, false),
+            M::Cvttps2dq => self.convert(op, Element::Float(SINGLE), Element::Int32, Lanes::Packed, true),
+            M::Cvtdq2pd => self.convert(op, Element::Int32, Element::Float(DOUBLE), Lanes::Packed, false),
+            M::Cvtpd2dq => self.convert(op, Element::Float(DOUBLE), Element::Int32, Lanes::Packed, false),
+            M::Cvttpd2dq => self.convert(op, Element::Float(DOUBLE), Element::Int32, Lanes::Packed, true),
+            M::Cvtpi2ps => self.convert(op, Element::Int32, Element::Float(SINGLE), Lanes::Packed, false),
+            M::Cvtpi2pd => self.convert(op, Element::Int32, Element::Float(DOUBLE), Lanes::Packed, false),
+            M::Cvtps2pi => self.convert(op, Element::Float(SINGLE), Element::Int32, Lanes::Packed, false),
+            M::Cvttps2pi => self.convert(op, Element::Float(SINGLE), Element::Int32, Lanes::Packed, true),
+            M::Cvtpd2pi => self.convert(op, Element::Float(DOUBLE), Element::Int32, Lanes::Packed, false),
+            M::Cvttpd2pi => self.convert(op, Element::Float(DOUBLE), Element::Int32, Lanes::Packed, true),
+            M::Vcvtph2ps => self.convert_half(op, true),
+            M::Vcvtps2ph => self.convert_half(op, false),
+            M::Vcvtps2udq => self.convert(op, Element::Float(SINGLE), Element::Uint32, Lanes::Packed, false),
+            M::Vcvttps2udq => self.convert(op, Element::Float(SINGLE), Element::Uint32, Lanes::Packed, true),
+            M::Vcvtps2qq => self.convert(op, Element::Float(SINGLE), Element::Int64, Lanes::Packed, false),
+            M::Vcvttps2qq => self.convert(op, Element::Float(SINGLE), Element::Int64, Lanes::Packed, true),
+            M::Vcvtps2uqq => self.convert(op, Element::Float(SINGLE), Element::Uint64, Lanes::Packed, false),
+            M::Vcvttps2uqq => self.convert(op, Element::Float(SINGLE), Element::Uint64, Lanes::Packed, true),
+            M::Vcvtpd2udq => self.convert(op, Element::Float(DOUBLE), Element::Uint32, Lanes::Packed, false),
+            M::Vcvttpd2udq => self.convert(op, Element::Float(DOUBLE), Element::Uint32, Lanes::Packed, true),
+            M::Vcvtpd2qq => self.convert(op, Element::Float(DOUBLE), Element::Int64, Lanes::Packed, false),
+            M::Vcvttpd2qq => self.convert(op, Element::Float(DOUBLE), Element::Int64, Lanes::Packed, true),
+            M::Vcvtpd2uqq => self.convert(op, Element::Float(DOUBLE), Element::Uint64, Lanes::Packed, false),
+            M::Vcvttpd2uqq => self.convert(op, Element::Float(DOUBLE), Element::Uint64, Lanes::Packed, true),
+            M::Vcvtudq2ps => self.convert(op, Element::Uint32, Element::Float(SINGLE), Lanes::Packed, false),
+            M::Vcvtudq2pd => self.convert(op, Element::Uint32, Element::Float(DOUBLE), Lanes::Packed, false),
+            M::Vcvtqq2ps => self.convert(op, Element::Int64, Element::Float(SINGLE), Lanes::Packed, false),
+            M::Vcvtqq2pd => self.convert(op, Element::Int64, Element::Float(DOUBLE), Lanes::Packed, false),
+            M::Vcvtuqq2ps => self.convert(op, Element::Uint64, Element::Float(SINGLE), Lanes::Packed, false),
+            M::Vcvtuqq2pd => self.convert(op, Element::Uint64, Element::Float(DOUBLE), Lanes::Packed, false),
+            M::Vcvtusi2ss => self.convert_from_integer(op, SINGLE, false),
+            M::Vcvtusi2sd => self.convert_from_integer(op, DOUBLE, false),
+            M::Vcvtss2usi => self.convert_to_integer(op, SINGLE, false, false),
+            M::Vcvttss2usi => self.convert_to_integer(op, SINGLE, true, false),
+            M::Vcvtsd2usi => self.convert_to_integer(op, DOUBLE, false, false),
+            M::Vcvttsd2usi => self.convert_to_integer(op, DOUBLE, true, false),
+            M::Fxsave => self.fxsave(op, false),
+            M::Fxsave64 => self.fxsave(op, true),
+            M::Fxrstor => self.fxrstor(op, false),
+            M::Fxrstor64 => self.fxrstor(op, true),
+            M::Xsave | M::Xsaveopt => self.xsave(op, false, false),
+            M::Xsave64 | M::Xsaveopt64 => self.xsave(op, true, false),
+            M::Xsavec => self.xsave(op, false, true),
+            M::Xsavec64 => self.xsave(op, true, true),
+            M::Xrstor => self.xrstor(op, false),
+            M::Xrstor64 => self.xrstor(op, true),
+            M::Xgetbv => self.xgetbv(),
+            M::Xsetbv => self.xsetbv(),
+            M::Aesenc => self.aes(op, AesRound::Encrypt),
+            M::Aesenclast => self.aes(op, AesRound::EncryptLast),
+            M::Aesdec => self.aes(op, AesRound::Decrypt),
+            M::Aesdeclast => self.aes(op, AesRound::DecryptLast),
+            M::Aesimc => self.aesimc(op),
+            M::Aeskeygenassist => self.aeskeygenassist(op),
+            M::Pclmulqdq => self.pclmulqdq(op),
+            M::Sha1rnds4 => self.sha1rnds4(op),
+            M::Sha1nexte => self.sha1nexte(op),
+            M::Sha1msg1 => self.sha1msg1(op),
+            M::Sha1msg2 => self.sha1msg2(op),
+            M::Sha256rnds2 => self.sha256rnds2(op),
+            M::Sha256msg1 => self.sha256msg1(op),
+            M::Sha256msg2 => self.sha256msg2(op),
+            M::Bndmk => self.bndmk(op),
+            M::Bndcl => self.bound_check(op, BoundCheck::Lower),
+            M::Bndcu => self.bound_check(op, BoundCheck::Upper),
+            M::Bndcn => self.bound_check(op, BoundCheck::UpperRaw),
+            M::Bndmov => self.bndmov(op),
+            M::Bndldx => self.bndldx(op),
+            M::Bndstx => self.bndstx(op),
+            M::Ldmxcsr => self.ldmxcsr(op),
+            M::Stmxcsr => self.stmxcsr(op),
+            M::Movd | M::Movq | M::Movq2dq | M::Movdq2q => self.move_low(op),
+            M::Movntq => self.move_vector(op, false),
+            M::Maskmovq | M::Maskmovdqu => self.masked_store(op),
+            M::Emms => self.emms(),
+            M::Movdqa | M::Movntdq | M::Movntdqa | M::Movntps | M::Movntpd => self.move_vector(op, true),
+            M::Movdqu | M::Lddqu => self.move_vector(op, false),
+            M::Movnti => self.mov(op),
+            M::Pand => self.float_logic(op, Logic::And),
+            M::Pandn => self.float_logic(op, Logic::AndNot),
+            M::Por => self.float_logic(op, Logic::Or),
+            M::Pxor => self.float_logic(op, Logic::Xor),
+            M::Paddb => self.packed(op, Lanewise::Add, 1),
+            M::Paddw => self.packed(op, Lanewise::Add, 2),
+            M::Paddd => self.packed(op, Lanewise::Add, 4),
+            M::Paddq => self.packed(op, Lanewise::Add, 8),
+            M::Psubb => self.packed(op, Lanewise::Sub, 1),
+            M::Psubw => self.packed(op, Lanewise::Sub, 2),
+            M::Psubd => self.packed(op, Lanewise::Sub, 4),
+            M::Psubq => self.packed(op, Lanewise::Sub, 8),
+            M::Paddsb => self.packed(op, Lanewise::AddSaturate { signed: true }, 1),
+            M::Paddsw => self.packed(op, Lanewise::AddSaturate { signed: true }, 2),
+            M::Paddusb => self.packed(op, Lanewise::AddSaturate { signed: false }, 1),
+            M::Paddusw => self.packed(op, Lanewise::AddSaturate { signed: false }, 2),
+            M::Psubsb => self.packed(op, Lanewise::SubSaturate { signed: true }, 1),
+            M::Psubsw => self.packed(op, Lanewise::SubSaturate { signed: true }, 2),
+            M::Psubusb => self.packed(op, Lanewise::SubSaturate { signed: false }, 1),
+            M::Psubusw => self.packed(op, Lanewise::SubSaturate { signed: false }, 2),
+            M::Pmullw => self.packed(op, Lanewise::MulLow, 2),
+            M::Pmulld => self.packed(op, Lanewise::MulLow, 4),
+            M::Pmulhw => self.packed(op, Lanewise::MulHigh { signed: true }, 2),
+            M::Pmulhuw => self.packed(op, Lanewise::MulHigh { signed: false }, 2),
+            M::Pmulhrsw => self.packed(op, Lanewise::MulHighRound, 2),
+            M::Pmuludq => self.multiply_wide(op, false),
+            M::Pmuldq => self.multiply_wide(op, true),
+            M::Pmaddwd => self.multiply_add(op, false),
+            M::Pmaddubsw => self.multiply_add(op, true),
+            M::Psadbw => self.sum_absolute_differences(op),
+            M::Mpsadbw => self.multiple_sums_of_absolute_differences(op),
+            M::Pavgb => self.packed(op, Lanewise::Average, 1),
+            M::Pavgw => self.packed(op, Lanewise::Average, 2),
+            M::Pcmpeqb => self.packed(op, Lanewise::Equal, 1),
+            M::Pcmpeqw => self.packed(op, Lanewise::Equal, 2),
+            M::Pcmpeqd => self.packed(op, Lanewise::Equal, 4),
+            M::Pcmpeqq => self.packed(op, Lanewise::Equal, 8),
+            M::Pcmpgtb => self.packed(op, Lanewise::Greater, 1),
+            M::Pcmpgtw => self.packed(op, Lanewise::Greater, 2),
+            M::Pcmpgtd => self.packed(op, Lanewise::Greater, 4),
+            M::Pcmpgtq => self.packed(op, Lanewise::Greater, 8),
+            M::Pminsb => self.packed(op, Lanewise::Min { signed: true }, 1),
+            M::Pminsw => self.packed(op, Lanewise::Min { signed: true }, 2),
+            M::Pminsd => self.packed(op, Lanewise::Min { signed: true }, 4),
+            M::Pminub => self.packed(op, Lanewise::Min { signed: false }, 1),
+            M::Pminuw => self.packed(op, Lanewise::Min { signed: false }, 2),
+            M::Pminud => self.packed(op, Lanewise::Min { signed: false }, 4),
+            M::Pmaxsb => self.packed(op, Lanewise::Max { signed: true }, 1),
+            M::Pmaxsw => self.packed(op, Lanewise::Max { signed: true }, 2),
+            M::Pmaxsd => self.packed(op, Lanewise::Max { signed: true }, 4),
+            M::Pmaxub => self.packed(op, Lanewise::Max { signed: false }, 1),
+            M::Pmaxuw => self.packed(op, Lanewise::Max { signed: false }, 2),
+            M::Pmaxud => self.packed(op, Lanewise::Max { signed: false }, 4),
+            M::Psignb => self.packed(op, Lanewise::Sign, 1),
+            M::Psignw => self.packed(op, Lanewise::Sign, 2),
+            M::Psignd => self.packed(op, Lanewise::Sign, 4),
+            M::Pabsb => self.packed(op, Lanewise::Abs, 1),
+            M::Pabsw => self.packed(op, Lanewise::Abs, 2),
+            M::Pabsd => self.packed(op, Lanewise::Abs, 4),
+            M::Phaddw => self.horizontal(op, Lanewise::Add, 2),
+            M::Phaddd => self.horizontal(op, Lanewise::Add, 4),
+            M::Phaddsw => self.horizontal(op, Lanewise::AddSaturate { signed: true }, 2),
+            M::Phsubw => self.horizontal(op, Lanewise::Sub, 2),
+            M::Phsubd => self.horizontal(op, Lanewise::Sub, 4),
+            M::Phsubsw => self.horizontal(op, Lanewise::SubSaturate { signed: true }, 2),
+            M::Phminposuw => self.minimum_position(op),
+            M::Psllw => self.shift_lanes(op, Shift::Shl, 2),
+            M::Pslld => self.shift_lanes(op, Shift::Shl, 4),
+            M::Psllq => self.shift_lanes(op, Shift::Shl, 8),
+            M::Psrlw => self.shift_lanes(op, Shift::Shr, 2),
+            M::Psrld => self.shift_lanes(op, Shift::Shr, 4),
+            M::Psrlq => self.shift_lanes(op, Shift::Shr, 8),
+            M::Psraw => self.shift_lanes(op, Shift::Sar, 2),
+            M::Psrad => self.shift_lanes(op, Shift::Sar, 4),
+            M::Pslldq => self.shift_bytes(op, true),
+            M::Psrldq => self.shift_bytes(op, false),
+            M::Packsswb => self.pack(op, 2, true),
+            M::Packssdw => self.pack(op, 4, true),
+            M::Packuswb => self.pack(op, 2, false),
+            M::Packusdw => self.pack(op, 4, false),
+            M::Punpcklbw => self.unpack(op, 1, false),
+            M::Punpcklwd => self.unpack(op, 2, false),
+            M::Punpckldq | M::Unpcklps => self.unpack(op, 4, false),
+            M::Punpcklqdq | M::Unpcklpd => self.unpack(op, 8, false),
+            M::Punpckhbw => self.unpack(op, 1, true),
+            M::Punpckhwd => self.unpack(op, 2, true),
+            M::Punpckhdq | M::Unpckhps => self.unpack(op, 4, true),
+            M::Punpckhqdq | M::Unpckhpd => self.unpack(op, 8, true),
+            M::Pshufb => self.shuffle_bytes(op),
+            M::Pshufd => self.shuffle(op, 4, 0),
+            M::Pshuflw | M::Pshufw => self.shuffle(op, 2, 0),
+            M::Pshufhw => self.shuffle(op, 2, 4),
+            M::Movsldup => self.duplicate(op, 4, false),
+            M::Movshdup => self.duplicate(op, 4, true),
+            M::Movddup => self.duplicate(op, 8, false),
+            M::Shufps => self.shuffle_float(op, 4),
+            M::Shufpd => self.shuffle_float(op, 8),
+            M::Palignr => self.align(op),
+            M::Pmovzxbw => self.extend(op, 1, 2, false),
+            M::Pmovzxbd => self.extend(op, 1, 4, false),
+            M::Pmovzxbq => self.extend(op, 1, 8, false),
+            M::Pmovzxwd => self.extend(op, 2, 4, false),
+            M::Pmovzxwq => self.extend(op, 2, 8, false),
+            M::Pmovzxdq => self.extend(op, 4, 8, false),
+            M::Pmovsxbw => self.extend(op, 1, 2, true),
+            M::Pmovsxbd => self.extend(op, 1, 4, true),
+            M::Pmovsxbq => self.extend(op, 1, 8, true),
+            M::Pmovsxwd => self.extend(op, 2, 4, true),
+            M::Pmovsxwq => self.extend(op, 2, 8, true),
+            M::Pmovsxdq => self.extend(op, 4, 8, true),
+            M::Ptest => self.ptest(op, None),
+            M::Pblendw => self.blend(op, 2, false),
+            M::Blendps => self.blend(op, 4, false),
+            M::Blendpd => self.blend(op, 8, false),
+            M::Pblendvb | M::Vpblendvb => self.blend(op, 1, true),
+            M::Blendvps | M::Vblendvps => self.blend(op, 4, true),
+            M::Blendvpd | M::Vblendvpd => self.blend(op, 8, true),
+            M::Pextrb => self.extract(op, 1),
+            M::Pextrw => self.extract(op, 2),
+            M::Pextrd | M::Extractps => self.extract(op, 4),
+            M::Pextrq => self.extract(op, 8),
+            M::Pinsrb => self.insert(op, 1),
+            M::Pinsrw => self.insert(op, 2),
+            M::Pinsrd => self.insert(op, 4),
+            M::Pinsrq => self.insert(op, 8),
+            M::Insertps => self.insert_float(op),
+            M::Pmovmskb => self.move_mask(op, 1),
+            M::Movmskps => self.move_mask(op, 4),
+            M::Movmskpd => self.move_mask(op, 8),
+            M::Pcmpestri => self.compare_strings(op, true, true),
+            M::Pcmpestrm => self.compare_strings(op, true, false),
+            M::Pcmpistri => self.compare_strings(op, false, true),
+            M::Pcmpistrm => self.compare_strings(op, false, false),
+            M::Crc32 => self.crc32(op),
+            M::Vzeroupper => self.zero_upper(false),
+            M::Vzeroall => self.zero_upper(true),
+            M::Vbroadcastss | M::Vpbroadcastd => self.broadcast(op, 4),
+            M::Vbroadcastsd | M::Vpbroadcastq => self.broadcast(op, 8),
+            M::Vbroadcastf128 | M::Vbroadcasti128 => self.broadcast(op, 16),
+            M::Vpbroadcastb => self.broadcast(op, 1),
+            M::Vpbroadcastw => self.broadcast(op, 2),
+            M::Vinsertf128 | M::Vinserti128 => self.insert_block(op),
+            M::Vextractf128 | M::Vextracti128 => self.extract_block(op),
+            M::Vperm2f128 | M::Vperm2i128 => self.permute_blocks(op),
+            M::Vpermd | M::Vpermps => self.permute(op, 4),
+            M::Vpermq | M::Vpermpd => self.permute(op, 8),
+            M::Vpermilps => self.permute_in_blocks(op, 4),
+            M::Vpermilpd => self.permute_in_blocks(op, 8),
+            M::Vtestps => self.ptest(op, Some(4)),
+            M::Vtestpd => self.ptest(op, Some(8)),
+            M::Vpblendd => self.blend(op, 4, false),
+            M::Vmaskmovps | M::Vpmaskmovd => self.mask_move(op, 4),
+            M::Vmaskmovpd | M::Vpmaskmovq => self.mask_move(op, 8),
+            M::Vpsllvd => self.packed(op, Lanewise::Shift(Shift::Shl), 4),
+            M::Vpsllvq => self.packed(op, Lanewise::Shift(Shift::Shl), 8),
+            M::Vpsrlvd => self.packed(op, Lanewise::Shift(Shift::Shr), 4),
+            M::Vpsrlvq => self.packed(op, Lanewise::Shift(Shift::Shr), 8),
+            M::Vpsravd => self.packed(op, Lanewise::Shift(Shift::Sar), 4),
+            M::Vgatherdps | M::Vpgatherdd => self.gather(op, 4, 4),
+            M::Vgatherdpd | M::Vpgatherdq => self.gather(op, 4, 8),
+            M::Vgatherqps | M::Vpgatherqd => self.gather(op, 8, 4),
+            M::Vgatherqpd | M::Vpgatherqq => self.gather(op, 8, 8),
+            M::Vfmadd132ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [1, 3, 2], Fused::Add),
+            M::Vfmadd132pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [1, 3, 2], Fused::Add),
+            M::Vfmadd132ss => self.fused_multiply_add(op, SINGLE, Lanes::Scalar, [1, 3, 2], Fused::Add),
+            M::Vfmadd132sd => self.fused_multiply_add(op, DOUBLE, Lanes::Scalar, [1, 3, 2], Fused::Add),
+            M::Vfmadd213ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [2, 1, 3], Fused::Add),
+            M::Vfmadd213pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [2, 1, 3], Fused::Add),
+            M::Vfmadd213ss => self.fused_multiply_add(op, SINGLE, Lanes::Scalar, [2, 1, 3], Fused::Add),
+            M::Vfmadd213sd => self.fused_multiply_add(op, DOUBLE, Lanes::Scalar, [2, 1, 3], Fused::Add),
+            M::Vfmadd231ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [2, 3, 1], Fused::Add),
+            M::Vfmadd231pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [2, 3, 1], Fused::Add),
+            M::Vfmadd231ss => self.fused_multiply_add(op, SINGLE, Lanes::Scalar, [2, 3, 1], Fused::Add),
+            M::Vfmadd231sd => self.fused_multiply_add(op, DOUBLE, Lanes::Scalar, [2, 3, 1], Fused::Add),
+            M::Vfmsub132ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [1, 3, 2], Fused::Sub),
+            M::Vfmsub132pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [1, 3, 2], Fused::Sub),
+            M::Vfmsub132ss => self.fused_multiply_add(op, SINGLE, Lanes::Scalar, [1, 3, 2], Fused::Sub),
+            M::Vfmsub132sd => self.fused_multiply_add(op, DOUBLE, Lanes::Scalar, [1, 3, 2], Fused::Sub),
+            M::Vfmsub213ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [2, 1, 3], Fused::Sub),
+            M::Vfmsub213pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [2, 1, 3], Fused::Sub),
+            M::Vfmsub213ss => self.fused_multiply_add(op, SINGLE, Lanes::Scalar, [2, 1, 3], Fused::Sub),
+            M::Vfmsub213sd => self.fused_multiply_add(op, DOUBLE, Lanes::Scalar, [2, 1, 3], Fused::Sub),
+            M::Vfmsub231ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [2, 3, 1], Fused::Sub),
+            M::Vfmsub231pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [2, 3, 1], Fused::Sub),
+            M::Vfmsub231ss => self.fused_multiply_add(op, SINGLE, Lanes::Scalar, [2, 3, 1], Fused::Sub),
+            M::Vfmsub231sd => self.fused_multiply_add(op, DOUBLE, Lanes::Scalar, [2, 3, 1], Fused::Sub),
+            M::Vfnmadd132ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [1, 3, 2], Fused::NegatedAdd),
+            M::Vfnmadd132pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [1, 3, 2], Fused::NegatedAdd),
+            M::Vfnmadd132ss => self.fused_multiply_add(op, SINGLE, Lanes::Scalar, [1, 3, 2], Fused::NegatedAdd),
+            M::Vfnmadd132sd => self.fused_multiply_add(op, DOUBLE, Lanes::Scalar, [1, 3, 2], Fused::NegatedAdd),
+            M::Vfnmadd213ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [2, 1, 3], Fused::NegatedAdd),
+            M::Vfnmadd213pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [2, 1, 3], Fused::NegatedAdd),
+            M::Vfnmadd213ss => self.fused_multiply_add(op, SINGLE, Lanes::Scalar, [2, 1, 3], Fused::NegatedAdd),
+            M::Vfnmadd213sd => self.fused_multiply_add(op, DOUBLE, Lanes::Scalar, [2, 1, 3], Fused::NegatedAdd),
+            M::Vfnmadd231ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [2, 3, 1], Fused::NegatedAdd),
+            M::Vfnmadd231pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [2, 3, 1], Fused::NegatedAdd),
+            M::Vfnmadd231ss => self.fused_multiply_add(op, SINGLE, Lanes::Scalar, [2, 3, 1], Fused::NegatedAdd),
+            M::Vfnmadd231sd => self.fused_multiply_add(op, DOUBLE, Lanes::Scalar, [2, 3, 1], Fused::NegatedAdd),
+            M::Vfnmsub132ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [1, 3, 2], Fused::NegatedSub),
+            M::Vfnmsub132pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [1, 3, 2], Fused::NegatedSub),
+            M::Vfnmsub132ss => self.fused_multiply_add(op, SINGLE, Lanes::Scalar, [1, 3, 2], Fused::NegatedSub),
+            M::Vfnmsub132sd => self.fused_multiply_add(op, DOUBLE, Lanes::Scalar, [1, 3, 2], Fused::NegatedSub),
+            M::Vfnmsub213ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [2, 1, 3], Fused::NegatedSub),
+            M::Vfnmsub213pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [2, 1, 3], Fused::NegatedSub),
+            M::Vfnmsub213ss => self.fused_multiply_add(op, SINGLE, Lanes::Scalar, [2, 1, 3], Fused::NegatedSub),
+            M::Vfnmsub213sd => self.fused_multiply_add(op, DOUBLE, Lanes::Scalar, [2, 1, 3], Fused::NegatedSub),
+            M::Vfnmsub231ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [2, 3, 1], Fused::NegatedSub),
+            M::Vfnmsub231pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [2, 3, 1], Fused::NegatedSub),
+            M::Vfnmsub231ss => self.fused_multiply_add(op, SINGLE, Lanes::Scalar, [2, 3, 1], Fused::NegatedSub),
+            M::Vfnmsub231sd => self.fused_multiply_add(op, DOUBLE, Lanes::Scalar, [2, 3, 1], Fused::NegatedSub),
+            M::Vfmaddsub132ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [1, 3, 2], Fused::AddSub),
+            M::Vfmaddsub132pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [1, 3, 2], Fused::AddSub),
+            M::Vfmaddsub213ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [2, 1, 3], Fused::AddSub),
+            M::Vfmaddsub213pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [2, 1, 3], Fused::AddSub),
+            M::Vfmaddsub231ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [2, 3, 1], Fused::AddSub),
+            M::Vfmaddsub231pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [2, 3, 1], Fused::AddSub),
+            M::Vfmsubadd132ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [1, 3, 2], Fused::SubAdd),
+            M::Vfmsubadd132pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [1, 3, 2], Fused::SubAdd),
+            M::Vfmsubadd213ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [2, 1, 3], Fused::SubAdd),
+            M::Vfmsubadd213pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [2, 1, 3], Fused::SubAdd),
+            M::Vfmsubadd231ps => self.fused_multiply_add(op, SINGLE, Lanes::Packed, [2, 3, 1], Fused::SubAdd),
+            M::Vfmsubadd231pd => self.fused_multiply_add(op, DOUBLE, Lanes::Packed, [2, 3, 1], Fused::SubAdd),
+            M::Kmovb | M::Kmovw | M::Kmovd | M::Kmovq => self.kmov(op, mask_size(op)),
+            M::Kandb | M::Kandw | M::Kandd | M::Kandq => self.opmask_logic(op, OpmaskLogic::And, mask_size(op)),
+            M::Kandnb | M::Kandnw | M::Kandnd | M::Kandnq => self.opmask_logic(op, OpmaskLogic::AndNot, mask_size(op)),
+            M::Korb | M::Korw | M::Kord | M::Korq => self.opmask_logic(op, OpmaskLogic::Or, mask_size(op)),
+            M::Kxorb | M::Kxorw | M::Kxord | M::Kxorq => self.opmask_logic(op, OpmaskLogic::Xor, mask_size(op)),
+            M::Kxnorb | M::Kxnorw | M::Kxnord | M::Kxnorq => self.opmask_logic(op, OpmaskLogic::Xnor, mask_size(op)),
+            M::Kaddb | M::Kaddw | M::Kaddd | M::Kaddq => self.opmask_logic(op, OpmaskLogic::Add, mask_size(op)),
+            M::Knotb | M::Knotw | M::Knotd | M::Knotq => self.opmask_logic(op, OpmaskLogic::Not, mask_size(op)),
+            M::Kortestb | M::Kortestw | M::Kortestd | M::Kortestq => self.opmask_test(op, mask_size(op), true),
+            M::Ktestb | M::Ktestw | M::Ktestd | M::Ktestq => self.opmask_test(op, mask_size(op), false),
+            M::Kshiftlb | M::Kshiftlw | M::Kshiftld | M::Kshiftlq => self.opmask_shift(op, mask_size(op), true),
+            M::Kshiftrb | M::Kshiftrw | M::Kshiftrd | M::Kshiftrq => self.opmask_shift(op, mask_size(op), false),
+            M::Kunpckbw => self.opmask_unpack(op, 1),
+            M::Kunpckwd => self.opmask_unpack(op, 2),
+            M::Kunpckdq => self.opmask_unpack(op, 4),
+            M::Vmovdqa32 | M::Vmovdqa64 => self.move_vector(op, true),
+            M::Vmovdqu8 | M::Vmovdqu16 | M::Vmovdqu32 | M::Vmovdqu64 => self.move_vector(op, false),
+            M::Vpandd | M::Vpandq => self.float_logic(op, Logic::And),
+            M::Vpandnd | M::Vpandnq => self.float_logic(op, Logic::AndNot),
+            M::Vpord | M::Vporq => self.float_logic(op, Logic::Or),
+            M::Vpxord | M::Vpxorq => self.float_logic(op, Logic::Xor),
+            M::Vpternlogd | M::Vpternlogq => self.ternary_logic(op),
+            M::Vpabsq => self.packed(op, Lanewise::Abs, 8),
+            M::Vpminsq => self.packed(op, Lanewise::Min { signed: true }, 8),
+            M::Vpminuq => self.packed(op, Lanewise::Min { signed: false }, 8),
+            M::Vpmaxsq => self.packed(op, Lanewise::Max { signed: true }, 8),
+            M::Vpmaxuq => self.packed(op, Lanewise::Max { signed: false }, 8),
+            M::Vpmullq => self.packed(op, Lanewise::MulLow, 8),
+            M::Vplzcntd => self.packed(op, Lanewise::LeadingZeros, 4),
+            M::Vplzcntq => self.packed(op, Lanewise::LeadingZeros, 8),
+            M::Vpconflictd => self.conflicts(op, 4),
+            M::Vpconflictq => self.conflicts(op, 8),
+            M::Vpmultishiftqb => self.multishift(op),
+            M::Vdbpsadbw => self.double_block_sums(op),
+            M::Vpbroadcastmb2q => self.broadcast_opmask(op, 1, 8),
+            M::Vpbroadcastmw2d => self.broadcast_opmask(op, 2, 4),
+            M::Vscalefps => self.float_arithmetic(op, Arithmetic::Scale, SINGLE, Lanes::Packed),
+            M::Vscalefpd => self.float_arithmetic(op, Arithmetic::Scale, DOUBLE, Lanes::Packed),
+            M::Vscalefss => self.float_arithmetic(op, Arithmetic::Scale, SINGLE, Lanes::Scalar),
+            M::Vscalefsd => self.float_arithmetic(op, Arithmetic::Scale, DOUBLE, Lanes::Scalar),
+            M::Vgetexpps => self.float_arithmetic(op, Arithmetic::Exponent, SINGLE, Lanes::Packed),
+            M::Vgetexppd => self.float_arithmetic(op, Arithmetic::Exponent, DOUBLE, Lanes::Packed),
+            M::Vgetexpss => self.float_arithmetic(op, Arithmetic::Exponent, SINGLE, Lanes::Scalar),
+            M::Vgetexpsd => self.float_arithmetic(op, Arithmetic::Exponent, DOUBLE, Lanes::Scalar),
+            M::Vgetmantps => self.mantissa(op, SINGLE, Lanes::Packed),
+            M::Vgetmantpd => self.mantissa(op, DOUBLE, Lanes::Packed),
+            M::Vgetmantss => self.mantissa(op, SINGLE, Lanes::Scalar),
+            M::Vgetmantsd => self.mantissa(op, DOUBLE, Lanes::Scalar),
+            M::Vrndscaleps => self.round(op, SINGLE, Lanes::Packed, false),
+            M::Vrndscalepd => self.round(op, DOUBLE, Lanes::Packed, false),
+            M::Vrndscaless => self.round(op, SINGLE, Lanes::Scalar, false),
+            M::Vrndscalesd => self.round(op, DOUBLE, Lanes::Scalar, false),
+            M::Vrcp14ps => self.reciprocal(op, SINGLE, Lanes::Packed, false),
+            M::Vrcp14pd => self.reciprocal(op, DOUBLE, Lanes::Packed, false),
+            M::Vrcp14ss => self.reciprocal(op, SINGLE, Lanes::Scalar, false),
+            M::Vrcp14sd => self.reciprocal(op, DOUBLE, Lanes::Scalar, false),
+            M::Vrsqrt14ps => self.reciprocal(op, SINGLE, Lanes::Packed, true),
+            M::Vrsqrt14pd => self.reciprocal(op, DOUBLE, Lanes::Packed, true),
+            M::Vrsqrt14ss => self.reciprocal(op, SINGLE, Lanes::Scalar, true),
+            M::Vrsqrt14sd => self.reciprocal(op, DOUBLE, Lanes::Scalar, true),
+            M::Vfixupimmps => self.fixup(op, SINGLE, Lanes::Packed),
+            M::Vfixupimmpd => self.fixup(op, DOUBLE, Lanes::Packed),
+            M::Vfixupimmss => self.fixup(op, SINGLE, Lanes::Scalar),
+            M::Vfixupimmsd => self.fixup(op, DOUBLE, Lanes::Scalar),
+            M::Vreduceps => self.round(op, SINGLE, Lanes::Packed, true),
+            M::Vreducepd => self.round(op, DOUBLE, Lanes::Packed, true),
+            M::Vreducess => self.round(op, SINGLE, Lanes::Scalar, true),
+            M::Vreducesd => self.round(op, DOUBLE, Lanes::Scalar, true),
+            M::Vrangeps => self.range(op, SINGLE, Lanes::Packed),
+            M::Vrangepd => self.range(op, DOUBLE, Lanes::Packed),
+            M::Vrangess => self.range(op, SINGLE, Lanes::Scalar),
+            M::Vrangesd => self.range(op, DOUBLE, Lanes::Scalar),
+            M::Vfpclassps => self.float_class(op, SINGLE, Lanes::Packed),
+            M::Vfpclasspd => self.float_class(op, DOUBLE, Lanes::Packed),
+            M::Vfpclassss => self.float_class(op, SINGLE, Lanes::Scalar),
+            M::Vfpclasssd => self.float_class(op, DOUBLE, Lanes::Scalar),
+            M::Vpsraq => self.shift_lanes(op, Shift::Sar, 8),
+            M::Vprold => self.shift_lanes(op, Shift::Rol, 4),
+            M::Vprolq => self.shift_lanes(op, Shift::Rol, 8),
+            M::Vprord => self.shift_lanes(op, Shift::Ror, 4),
+            M::Vprorq => self.shift_lanes(op, Shift::Ror, 8),
+            M::Vpsllvw => self.packed(op, Lanewise::Shift(Shift::Shl), 2),
+            M::Vpsrlvw => self.packed(op, Lanewise::Shift(Shift::Shr), 2),
+            M::Vpsravw => self.packed(op, Lanewise::Shift(Shift::Sar), 2),
+            M::Vpsravq => self.packed(op, Lanewise::Shift(Shift::Sar), 8),
+            M::Vprolvd => self.packed(op, Lanewise::Shift(Shift::Rol), 4),
+            M::Vprolvq => self.packed(op, Lanewise::Shift(Shift::Rol), 8),
+            M::Vprorvd => self.packed(op, Lanewise::Shift(Shift::Ror), 4),
+            M::Vprorvq => self.packed(op, Lanewise::Shift(Shift::Ror), 8),
+            M::Vpcmpb => self.compare_integers(op, 1, true),
+            M::Vpcmpw => self.compare_integers(op, 2, true),
+            M::Vpcmpd => self.compare_integers(op, 4, true),
+            M::Vpcmpq => self.compare_integers(op, 8, true),
+            M::Vpcmpub => self.compare_integers(op, 1, false),
+            M::Vpcmpuw => self.compare_integers(op, 2, false),
+            M::Vpcmpud => self.compare_integers(op, 4, false),
+            M::Vpcmpuq => self.compare_integers(op, 8, false),
+            M::Vptestmb => self.test_elements(op, 1, false),
+            M::Vptestmw => self.test_elements(op, 2, false),
+            M::Vptestmd => self.test_elements(op, 4, false),
+            M::Vptestmq => self.test_elements(op, 8, false),
+            M::Vptestnmb => self.test_elements(op, 1, true),
+            M::Vptestnmw => self.test_elements(op, 2, true),
+            M::Vptestnmd => self.test_elements(op, 4, true),
+            M::Vptestnmq => self.test_elements(op, 8, true),
+            M::Vpmovm2b | M::Vpmovm2w | M::Vpmovm2d | M::Vpmovm2q => self.move_vector(op, false),
+            M::Vpmovb2m | M::Vpmovw2m | M::Vpmovd2m | M::Vpmovq2m => self.move_vector(op, false),
+            M::Vpblendmb => self.blend_by_opmask(op, 1),
+            M::Vpblendmw => self.blend_by_opmask(op, 2),
+            M::Vblendmps | M::Vpblendmd => self.blend_by_opmask(op, 4),
+            M::Vblendmpd | M::Vpblendmq => self.blend_by_opmask(op, 8),
+            M::Vcompressps | M::Vpcompressd => self.compress(op, 4),
+            M::Vcompresspd | M::Vpcompressq => self.compress(op, 8),
+            M::Vexpandps | M::Vpexpandd => self.expand(op, 4),
+            M::Vexpandpd | M::Vpexpandq => self.expand(op, 8),
+            M::Vpermb => self.permute(op, 1),
+            M::Vpermw => self.permute(op, 2),
+            M::Vpermt2b => self.permute_two_tables(op, 1, false),
+            M::Vpermt2w => self.permute_two_tables(op, 2, false),
+            M::Vpermt2d | M::Vpermt2ps => self.permute_two_tables(op, 4, false),
+            M::Vpermt2q | M::Vpermt2pd => self.permute_two_tables(op, 8, false),
+            M::Vpermi2b => self.permute_two_tables(op, 1, true),
+            M::Vpermi2w => self.permute_two_tables(op, 2, true),
+            M::Vpermi2d | M::Vpermi2ps => self.permute_two_tables(op, 4, true),
+            M::Vpermi2q | M::Vpermi2pd => self.permute_two_tables(op, 8, true),
+            M::Valignd => self.align_elements(op, 4),
+            M::Valignq => self.align_elements(op, 8),
+            M::Vshuff32x4 | M::Vshuff64x2 | M::Vshufi32x4 | M::Vshufi64x2 => self.shuffle_blocks(op),
+            M::Vbroadcastf32x2 | M::Vbroadcasti32x2 => self.broadcast(op, 8),
+            M::Vbroadcastf32x4 | M::Vbroadcastf64x2 | M::Vbroadcasti32x4 | M::Vbroadcasti64x2 => self.broadcast(op, 16),
+            M::Vbroadcastf32x8 | M::Vbroadcastf64x4 | M::Vbroadcasti32x8 | M::Vbroadcasti64x4 => self.broadcast(op, 32),
+            M::Vinsertf32x4 | M::Vinsertf64x2 | M::Vinsertf32x8 | M::Vinsertf64x4 => self.insert_block(op),
+            M::Vinserti32x4 | M::Vinserti64x2 | M::Vinserti32x8 | M::Vinserti64x4 => self.insert_block(op),
+            M::Vextractf32x4 | M::Vextractf64x2 | M::Vextractf32x8 | M::Vextractf64x4 => self.extract_block(op),
+            M::Vextracti32x4 | M::Vextracti64x2 | M::Vextracti32x8 | M::Vextracti64x4 => self.extract_block(op),
+            M::Vpmovwb => self.narrow(op, 2, 1, None),
+            M::Vpmovdb => self.narrow(op, 4, 1, None),
+            M::Vpmovqb => self.narrow(op, 8, 1, None),
+            M::Vpmovdw => self.narrow(op, 4, 2, None),
+            M::Vpmovqw => self.narrow(op, 8, 2, None),
+            M::Vpmovqd => self.narrow(op, 8, 4, None),
+            M::Vpmovswb => self.narrow(op, 2, 1, Some(true)),
+            M::Vpmovsdb => self.narrow(op, 4, 1, Some(true)),
+            M::Vpmovsqb => self.narrow(op, 8, 1, Some(true)),
+            M::Vpmovsdw => self.narrow(op, 4, 2, Some(true)),
+            M::Vpmovsqw => self.narrow(op, 8, 2, Some(true)),
+            M::Vpmovsqd => self.narrow(op, 8, 4, Some(true)),
+            M::Vpmovuswb => self.narrow(op, 2, 1, Some(false)),
+            M::Vpmovusdb => self.narrow(op, 4, 1, Some(false)),
+            M::Vpmovusqb => self.narrow(op, 8, 1, Some(false)),
+            M::Vpmovusdw => self.narrow(op, 4, 2, Some(false)),
+            M::Vpmovusqw => self.narrow(op, 8, 2, Some(false)),
+            M::Vpmovusqd => self.narrow(op, 8, 4, Some(false)),
+            M::Vscatterdps | M::Vpscatterdd => self.scatter(op, 4, 4),
+            M::Vscatterdpd | M::Vpscatterdq => self.scatter(op, 4, 8),
+            M::Vscatterqps | M::Vpscatterqd => self.scatter(op, 8, 4),
+            M::Vscatterqpd | M::Vpscatterqq => self.scatter(op, 8, 8),
+
+            M::Wait => self.fwait(),
+            M::Fninit => self.fninit(),
+            M::Fnclex => self.fnclex(),
+            M::Fldcw => self.fldcw(op),
+            M::Fnstcw => self.fnstcw(op),
+            M::Fnstsw => self.fnstsw(op),
+            M::Fnstenv => self.fnstenv(op),
+            M::Fldenv => self.fldenv(op),
+            M::Fnsave => self.fnsave(op),
+            M::Frstor => self.frstor(op),
+            M::Fnop => self.fnop(op),
+            M::Fld | M::Fild => self.fld(op),
+            M::Fbld => self.fbld(op),
+            M::Fld1 => self.fld_constant(op, Constant::One),
+            M::Fldz => self.fld_constant(op, Constant::Zero),
+            M::Fldpi => self.fld_constant(op, Constant::Pi),
+            M::Fldl2t => self.fld_constant(op, Constant::Log2Ten),
+            M::Fldl2e => self.fld_constant(op, Constant::Log2E),
+            M::Fldlg2 => self.fld_constant(op, Constant::Log10Two),
+            M::Fldln2 => self.fld_constant(op, Constant::LnTwo),
+            M::Fst => self.fst(op, false),
+            M::Fstp => self.fst(op, true),
+            M::Fist => self.fist(op, false, false),
+            M::Fistp => self.fist(op, true, false),
+            M::Fisttp => self.fist(op, true, true),
+            M::Fbstp => self.fbstp(op),
+            M::Fxch => self.fxch(op),
+            M::Ffree => self.ffree(op),
+            M::Fincstp => self.rotate_fpu_stack(op, true),
+            M::Fdecstp => self.rotate_fpu_stack(op, false),
+            M::Fadd | M::Fiadd => self.farith(op, Arithmetic::Add, false, false),
+            M::Faddp => self.farith(op, Arithmetic::Add, false, true),
+            M::Fsub | M::Fisub => self.farith(op, Arithmetic::Sub, false, false),
+            M::Fsubp => self.farith(op, Arithmetic::Sub, false, true),
+            M::Fsubr | M::Fisubr => self.farith(op, Arithmetic::Sub, true, false),
+            M::Fsubrp => self.farith(op, Arithmetic::Sub, true, true),
+            M::Fmul | M::Fimul => self.farith(op, Arithmetic::Mul, false, false),
+            M::Fmulp => self.farith(op, Arithmetic::Mul, false, true),
+            M::Fdiv | M::Fidiv => self.farith(op, Arithmetic::Div, false, false),
+            M::Fdivp => self.farith(op, Arithmetic::Div, false, true),
+            M::Fdivr | M::Fidivr => self.farith(op, Arithmetic::Div, true, false),
+            M::Fdivrp => self.farith(op, Arithmetic::Div, true, true),
+            M::Fcom | M::Ficom => self.fcom(op, true, 0),
+            M::Fcomp | M::Ficomp => self.fcom(op, true, 1),
+            M::Fcompp => self.fcom(op, true, 2),
+            M::Fucom => self.fcom(op, false, 0),
+            M::Fucomp => self.fcom(op, false, 1),
+            M::Fucompp => self.fcom(op, false, 2),
+            M::Fcomi => self.fcomi(op, true, false),
+            M::Fcomip => self.fcomi(op, true, true),
+            M::Fucomi => self.fcomi(op, false, false),
+            M::Fucomip => self.fcomi(op, false, true),
+            M::Ftst => self.ftst(op),
+            M::Fxam => self.fxam(op),
+            M::Fchs => self.fsign(op, false),
+            M::Fabs => self.fsign(op, true),
+            M::Fcmovb => self.fcmov(op, Condition::Below),
+            M::Fcmove => self.fcmov(op, Condition::Equal),
+            M::Fcmovbe => self.fcmov(op, Condition::BelowOrEqual),
+            M::Fcmovu => self.fcmov(op, Condition::Parity),
+            M::Fcmovnb => self.fcmov(op, Condition::AboveOrEqual),
+            M::Fcmovne => self.fcmov(op, Condition::NotEqual),
+            M::Fcmovnbe => self.fcmov(op, Condition::Above),
+            M::Fcmovnu => self.fcmov(op, Condition::NotParity),
+            M::Fsqrt => self.fsqrt(op),
+            M::Frndint => self.frndint(op),
+            M::Fscale => self.fscale(op),
+            M::Fxtract => self.fxtract(op),
+            M::Fprem => self.fprem(op, false),
+            M::Fprem1 => self.fprem(op, true),
+            M::F2xm1 => self.f2xm1(op),
+            M::Fyl2x => self.fyl2x(op, false),
+            M::Fyl2xp1 => self.fyl2x(op, true),
+            M::Fpatan => self.fpatan(op),
+            M::Fptan => self.fptan(op),
+            M::Fsin => self.fsincos(op, true, false),
+            M::Fcos => self.fsincos(op, false, true),
+            M::Fsincos => self.fsincos(op, true, true),
+
+            mnemonic => match Condition::from_mnemonic(mnemonic) {
+                Some(condition) => match op.operands[0] {
+                    Operand::Branch(_) => self.jcc(op, condition),
+                    _ if op.operands.len() == 1 => self.setcc(op, condition),
+                    _ => self.cmovcc(op, condition),
+                },
+                None => Err(unsupported(op)),
+            },
+        }
+    }
+
+    /// Hands a trap raised by the instruction at `ip` to the guest, returning why the machine
+    /// stops if nothing handles it
+    fn trap(&mut self, trap: Trap, ip: u64) -> Option<StopReason> {
+        let (vector, unhandled) = match trap {
+            Trap::Stop(reason) => (None, reason),
+            Trap::Exception(Exception::Breakpoint) => (Some(3), StopReason::Breakpoint(ip)),
+            Trap::Exception(exception) => (
+                Some(exception.vector()),
+                StopReason::Fault(Fault::Exception { exception, address: ip }),
+            ),
+            Trap::Interrupt(vector) => (
+                Some(vector),
+                StopReason::Fault(Fault::InterruptNotFound { vector, address: ip }),
+            ),
+        };
+        let handler = vector.map_or(SystemFunction::Unimplemented, |v| self.interrupts.get(v as usize));
+        let handled = !matches!(handler, SystemFunction::Unimplemented);
+
+        let completed = match trap {
+            Trap::Stop(reason) => !matches!(reason, StopReason::Fault(_)),
+            Trap::Exception(exception) => exception.is_trap(),
+            Trap::Interrupt(_) => handled,
+        };
+        if completed {
+            self.instructions_retired += 1;
+        } else {
+            self.instruction_counter = ip;
+        }
+
+        if !handled {
+            return Some(unhandled);
+        }
+        handler.call(self);
+        self.pending_stop.take()
+    }
+}
@@ -0,0 +1,90 @@
+//! Integer arithmetic and logic: ADD, ADC, SUB, SBB, CMP, AND, OR, XOR, TEST, INC, DEC, NEG, NOT,
+//! and CLC, STC and CMC for the carry ADC and SBB take in
+//!
+//! Every operation works on values zero extended to 64 bits plus an operand size in bytes. The
+//! flags are left for `Flags` to work out if anything reads them.
+use crate::execute::operands::{mask, operand_size};
+use crate::execute::Execution;
+use crate::flags::{AsRFlags, FlagOp, RFlags};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::X86Opcode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Alu {
+    Add,
+    Adc,
+    Sub,
+    Sbb,
+    And,
+    Or,
+    Xor,
+}
+
+impl Alu {
+    /// Result, along with what the flags are to be worked out from
+    pub(crate) fn apply(self, a: u64, b: u64, carry: bool, size: usize) -> (u64, FlagOp, bool) {
+        let (result, op, carry) = match self {
+            Alu::Add => (a.wrapping_add(b), FlagOp::Add, false),
+            Alu::Adc => (a.wrapping_add(b).wrapping_add(carry as u64), FlagOp::Add, carry),
+            Alu::Sub => (a.wrapping_sub(b), FlagOp::Sub, false),
+            Alu::Sbb => (a.wrapping_sub(b).wrapping_sub(carry as u64), FlagOp::Sub, carry),
+            Alu::And => (a & b, FlagOp::Logic, false),
+            Alu::Or => (a | b, FlagOp::Logic, false),
+            Alu::Xor => (a ^ b, FlagOp::Logic, false),
+        };
+        (result & mask(size), op, carry)
+    }
+}
+
+impl X86Machine {
+    /// Two operand arithmetic. `write` is false for CMP and TEST, which only set flags
+    pub(crate) fn alu(&mut self, op: &X86Opcode, alu: Alu, write: bool) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let a = self.read_operand(op, &op.operands[0])?;
+        let b = self.read_operand(op, &op.operands[1])? & mask(size);
+        let (result, flag_op, carry) = alu.apply(a, b, self.flags.is_set(RFlags::Carry), size);
+        if write {
+            self.write_operand(op, &op.operands[0], result)?;
+        }
+        self.flags.defer(flag_op, a, b, result, size, carry);
+        Ok(())
+    }
+
+    /// INC and DEC, which leave CF alone
+    pub(crate) fn inc_dec(&mut self, op: &X86Opcode, flag_op: FlagOp) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let a = self.read_operand(op, &op.operands[0])?;
+        let result = match flag_op {
+            FlagOp::Inc => a.wrapping_add(1),
+            _ => a.wrapping_sub(1),
+        } & mask(size);
+        self.write_operand(op, &op.operands[0], result)?;
+        self.flags.defer(flag_op, a, 1, result, size, false);
+        Ok(())
+    }
+
+    /// `0 - operand`, CF is set unless the operand was zero
+    pub(crate) fn neg(&mut self, op: &X86Opcode) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let a = self.read_operand(op, &op.operands[0])?;
+        let result = a.wrapping_neg() & mask(size);
+        self.write_operand(op, &op.operands[0], result)?;
+        self.flags.defer(FlagOp::Sub, 0, a, result, size, false);
+        Ok(())
+    }
+
+    /// No flags are affected
+    pub(crate) fn not(&mut self, op: &X86Opcode) -> Execution {
+        let a = self.read_operand(op, &op.operands[0])?;
+        self.write_operand(op, &op.operands[0], !a)
+    }
+
+    /// CLC, STC and CMC: CF becomes `carry` of what it was
+    pub(crate) fn carry(&mut self, carry: impl FnOnce(bool) -> bool) -> Execution {
+        match carry(self.flags.is_set(RFlags::Carry)) {
+            true => self.flags.set(RFlags::Carry),
+            false => self.flags.clear(RFlags::Carry),
+        }
+        Ok(())
+    }
+}
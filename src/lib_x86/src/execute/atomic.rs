@@ -0,0 +1,174 @@
+//! Exchanges: XCHG, XADD, CMPXCHG, CMPXCHG8B and CMPXCHG16B, and the fences
+//!
+//! While a machine has `memory` to itself, instructions run one at a time and each
+//! read-modify-write is one indivisible step whether or not it is locked. Once other machines
+//! share it (see `ContiguousMemory::share`) a locked instruction runs as a compare-and-swap on
+//! the host, see `locked`. CMPXCHG8B and CMPXCHG16B always do. All of the reads come before any
+//! of the writes, so a fault leaves everything as it was. What a LOCK prefix needs besides is
+//! checking, see `check_lock`.
+use crate::execute::operands::{mask, operand_size};
+use crate::execute::{unsupported, Exception, Execution};
+use crate::flags::{AsRFlags, FlagOp, RFlags};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{Mnemonic, Operand, Register, X86Opcode};
+use std::sync::atomic::{fence, Ordering};
+
+/// How far a locked instruction has got with its memory operand, on memory other machines
+/// share
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LockedAccess {
+    /// nothing has been read yet
+    Armed,
+    /// the destination held `value` when it was read
+    Read { address: u64, size: usize, value: u64 },
+    /// the store found the value read still there and replaced it
+    Done,
+    /// another machine changed the destination in between, so nothing was stored
+    Lost,
+}
+
+/// Whether the instruction is a locked read-modify-write. XCHG with memory is, with or without
+/// the prefix
+fn is_locked(op: &X86Opcode) -> bool {
+    op.prefixes.lock || (op.mnemonic == Mnemonic::Xchg && op.memory_operand().is_some())
+}
+
+/// Accumulator for an operand size
+fn accumulator(size: usize) -> Register {
+    match size {
+        1 => Register::AL,
+        2 => Register::AX,
+        4 => Register::EAX,
+        _ => Register::RAX,
+    }
+}
+
+impl X86Machine {
+    /// Whether the instruction has to run through `locked`
+    pub(crate) fn needs_lock(&self, op: &X86Opcode) -> bool {
+        is_locked(op) && self.memory.is_shared()
+    }
+
+    /// Runs a locked instruction on memory other machines share. The value it reads from its
+    /// destination is remembered, and its store is a compare-and-swap against it. If another
+    /// machine changed the destination in between the registers and flags are put back and
+    /// the instruction runs again on what is there now
+    pub(crate) fn locked(&mut self, op: &X86Opcode) -> Execution {
+        loop {
+            let (registers, flags) = (self.gp_registers.clone(), self.flags);
+            self.locked_access.set(Some(LockedAccess::Armed));
+            let result = self.dispatch(op);
+            match self.locked_access.take() {
+                Some(LockedAccess::Lost) => {
+                    self.gp_registers = registers;
+                    self.flags = flags;
+                }
+                _ => return result,
+            }
+        }
+    }
+
+    /// MFENCE, LFENCE and SFENCE, as host fences for the machines sharing memory
+    pub(crate) fn fence(&mut self, op: &X86Opcode) -> Execution {
+        match op.mnemonic {
+            Mnemonic::Lfence => fence(Ordering::Acquire),
+            Mnemonic::Sfence => fence(Ordering::Release),
+            _ => fence(Ordering::SeqCst),
+        }
+        Ok(())
+    }
+
+    /// #UD for a LOCK prefix on anything other than a read-modify-write with a memory destination
+    pub(crate) fn check_lock(&self, op: &X86Opcode) -> Result<(), Exception> {
+        match op.prefixes.lock && !op.accepts_lock() {
+            true => Err(Exception::InvalidOpcode),
+            false => Ok(()),
+        }
+    }
+
+    /// XCHG. With a memory operand it is locked whether or not the prefix is there
+    pub(crate) fn xchg(&mut self, op: &X86Opcode) -> Execution {
+        let a = self.read_operand(op, &op.operands[0])?;
+        let b = self.read_operand(op, &op.operands[1])?;
+        self.write_operand(op, &op.operands[0], b)?;
+        self.write_operand(op, &op.operands[1], a)
+    }
+
+    /// XADD: the destination gets the sum and the source the old destination. When both are the
+    /// same register it ends up with the sum
+    pub(crate) fn xadd(&mut self, op: &X86Opcode) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let a = self.read_operand(op, &op.operands[0])?;
+        let b = self.read_operand(op, &op.operands[1])? & mask(size);
+        let sum = a.wrapping_add(b) & mask(size);
+        // the destination has been read, so writing it back cannot fault
+        self.write_operand(op, &op.operands[1], a)?;
+        self.write_operand(op, &op.operands[0], sum)?;
+        self.flags.defer(FlagOp::Add, a, b, sum, size, false);
+        Ok(())
+    }
+
+    /// CMPXCHG: compares the accumulator with the destination, flags as for CMP. If they are
+    /// equal the destination gets the source, otherwise the accumulator gets the destination.
+    /// A memory destination is written either way, as on hardware
+    pub(crate) fn cmpxchg(&mut self, op: &X86Opcode) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let accumulator = accumulator(size);
+        let expected = self.register(accumulator);
+        let current = self.read_operand(op, &op.operands[0])?;
+        let source = self.read_operand(op, &op.operands[1])?;
+        let equal = current == expected;
+        match (equal, op.operands[0]) {
+            (true, _) => self.write_operand(op, &op.operands[0], source)?,
+            (false, Operand::Memory(_)) => self.write_operand(op, &op.operands[0], current)?,
+            (false, _) => {}
+        }
+        if !equal {
+            self.set_register(accumulator, current);
+        }
+        let result = expected.wrapping_sub(current) & mask(size);
+        self.flags.defer(FlagOp::Sub, expected, current, result, size, false);
+        Ok(())
+    }
+
+    /// CMPXCHG8B and CMPXCHG16B: EDX:EAX (RDX:RAX) against the memory operand, which gets
+    /// ECX:EBX (RCX:RBX) if they are equal and is loaded into EDX:EAX otherwise. Only ZF is
+    /// affected. The 16 byte form needs an aligned operand
+    pub(crate) fn cmpxchg_wide(&mut self, op: &X86Opcode) -> Execution {
+        let Operand::Memory(memory) = op.operands[0] else {
+            return Err(Exception::InvalidOpcode.into());
+        };
+        let half = memory.size as usize / 2;
+        let registers = match half {
+            4 => [Register::EAX, Register::EDX, Register::EBX, Register::ECX],
+            8 => [Register::RAX, Register::RDX, Register::RBX, Register::RCX],
+            _ => return Err(unsupported(op)),
+        };
+        let [low, high, new_low, new_high] = registers.map(|register| self.register(register) as u128);
+
+        let address = self.effective_address(op, &memory);
+        if half == 8 && !address.is_multiple_of(16) {
+            return Err(Exception::GeneralProtection.into());
+        }
+        let start = usize::try_from(address).map_err(|_| Exception::PageFault(address))?;
+        let bits = half * 8;
+        let (current, new) = (high << bits | low, new_high << bits | new_low);
+        let exchanged = match half {
+            4 => self
+                .memory
+                .compare_exchange(start, 8, current as u64, new as u64)
+                .map(|exchanged| exchanged.map(u128::from).map_err(u128::from)),
+            _ => self.memory.compare_exchange_16(start, current, new),
+        };
+        let exchanged = exchanged.map_err(|_| Exception::PageFault(address))?;
+        match exchanged {
+            Ok(_) => self.flags.set(RFlags::Zero),
+            Err(held) => {
+                self.set_register(registers[0], held as u64 & mask(half));
+                self.set_register(registers[1], (held >> bits) as u64);
+                self.flags.clear(RFlags::Zero);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,308 @@
+//! AVX and AVX2 instructions that have no SSE form: clearing the upper halves of the YMM
+//! registers, broadcasts, moving and permuting 128-bit blocks, permutes across the whole
+//! register, masked moves, gathers, and FMA3
+//!
+//! The VEX forms of the SSE instructions are in `sse` and `packed`, which run them 128 bits
+//! at a time.
+use crate::execute::operands::{blocks, element, immediate, lane, operand_size, set_element, sign_extend, sources, Vector, BLOCKS};
+use crate::execute::sse::Lanes;
+use crate::execute::{unsupported, Exception, Execution};
+use crate::softfloat::{self, Format};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{Encoding, MemoryOperand, Operand, Register, X86Opcode};
+
+/// Vector registers VEX can address
+const REGISTERS: u8 = 16;
+
+/// What an FMA instruction does with the product and the third operand, by the rest of its
+/// mnemonic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fused {
+    /// FMADD, `a * b + c`
+    Add,
+    /// FMSUB, `a * b - c`
+    Sub,
+    /// FNMADD, `-(a * b) + c`
+    NegatedAdd,
+    /// FNMSUB, `-(a * b) - c`
+    NegatedSub,
+    /// FMADDSUB, subtracting in the even lanes and adding in the odd ones
+    AddSub,
+    /// FMSUBADD, adding in the even lanes and subtracting in the odd ones
+    SubAdd,
+}
+
+impl Fused {
+    /// Whether the product and the addend of lane `index` are negated
+    fn negations(self, index: usize) -> (bool, bool) {
+        match self {
+            Fused::Add => (false, false),
+            Fused::Sub => (false, true),
+            Fused::NegatedAdd => (true, false),
+            Fused::NegatedSub => (true, true),
+            Fused::AddSub => (false, index.is_multiple_of(2)),
+            Fused::SubAdd => (false, !index.is_multiple_of(2)),
+        }
+    }
+}
+
+/// Number of a vector register operand
+fn vector_register(operand: &Operand) -> Option<u8> {
+    match *operand {
+        Operand::Register(Register::Xmm(n) | Register::Ymm(n) | Register::Zmm(n)) => Some(n),
+        _ => None,
+    }
+}
+
+impl X86Machine {
+    /// VZEROUPPER clears the upper half of every YMM register, VZEROALL all of them
+    pub(crate) fn zero_upper(&mut self, all: bool) -> Execution {
+        for n in 0..REGISTERS {
+            let mut vector = [0; BLOCKS];
+            if !all {
+                vector[0] = self.xmm(n);
+            }
+            self.set_vector(n, vector);
+        }
+        Ok(())
+    }
+
+    /// VBROADCASTSS, VBROADCASTSD, VBROADCASTF128 and VPBROADCAST: the low `size` bytes of the
+    /// source in every lane. The AVX-512 block broadcasts repeat 8, 16 or 32 bytes the same way
+    pub(crate) fn broadcast(&mut self, op: &X86Opcode, size: usize) -> Execution {
+        let source = self.read_blocks(op, &op.operands[1], false)?;
+        let mut result = [0; BLOCKS];
+        match size {
+            16 | 32 => {
+                let width = size / 16;
+                for i in 0..blocks(op) {
+                    result[i] = source[i % width];
+                }
+            }
+            _ => {
+                for i in 0..blocks(op) * 16 / size {
+                    set_element(&mut result, i, size, element(&source, 0, size));
+                }
+            }
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VINSERTF128 and VINSERTI128, and the AVX-512 inserts of 128 or 256 bits: the first
+    /// source with the second replacing the part of it the immediate picks
+    pub(crate) fn insert_block(&mut self, op: &X86Opcode) -> Execution {
+        let (a, b) = sources(op);
+        let mut result = self.read_blocks(op, a, false)?;
+        let width = operand_size(b) / 16;
+        let at = immediate(op) as usize % (blocks(op) / width) * width;
+        result[at..at + width].copy_from_slice(&self.read_blocks(op, b, false)?[..width]);
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VEXTRACTF128 and VEXTRACTI128, and the AVX-512 extracts of 128 or 256 bits: the part of
+    /// the source the immediate picks
+    pub(crate) fn extract_block(&mut self, op: &X86Opcode) -> Execution {
+        let source = self.read_blocks(op, &op.operands[1], false)?;
+        let width = operand_size(&op.operands[0]) / 16;
+        let at = immediate(op) as usize % (operand_size(&op.operands[1]) / 16 / width) * width;
+        let mut result = [0; BLOCKS];
+        result[..width].copy_from_slice(&source[at..at + width]);
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VPERM2F128 and VPERM2I128: each half of the result is one of the four halves of the
+    /// sources, picked by a nibble of the immediate, or zero when bit 3 of the nibble is set
+    pub(crate) fn permute_blocks(&mut self, op: &X86Opcode) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let halves = [a[0], a[1], b[0], b[1]];
+        let control = immediate(op) as usize;
+        let mut result = [0; BLOCKS];
+        for (i, block) in result.iter_mut().take(2).enumerate() {
+            let nibble = control >> (4 * i);
+            if nibble & 8 == 0 {
+                *block = halves[nibble & 3];
+            }
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VPERMD and VPERMPS pick each lane of the second source by the matching lane of the
+    /// first, VPERMQ and VPERMPD by two bit fields of the immediate (within each 256 bits).
+    /// Lanes come from anywhere in the register
+    pub(crate) fn permute(&mut self, op: &X86Opcode, size: usize) -> Execution {
+        let count = blocks(op) * 16 / size;
+        let (indices, source) = match op.operands.last() {
+            Some(Operand::Immediate(_)) => {
+                let control = immediate(op) as usize;
+                let indices = (0..count).map(|i| i & !3 | control >> (2 * (i & 3)) & 3).collect::<Vec<_>>();
+                (indices, self.read_blocks(op, &op.operands[1], false)?)
+            }
+            _ => {
+                let (a, b) = self.read_sources(op)?;
+                let indices = (0..count).map(|i| element(&a, i, size) as usize % count).collect();
+                (indices, b)
+            }
+        };
+        let mut result = [0; BLOCKS];
+        for (i, &index) in indices.iter().enumerate() {
+            set_element(&mut result, i, size, element(&source, index, size));
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VPERMILPS and VPERMILPD: lanes picked from within the same block, by bits 1:0 (PS) or
+    /// bit 1 (PD) of the lanes of the second source, or by fields of the immediate. The
+    /// immediate of VPERMILPS is used again for every block, VPERMILPD has a bit per lane
+    pub(crate) fn permute_in_blocks(&mut self, op: &X86Opcode, size: usize) -> Execution {
+        let count = 16 / size;
+        let (source, control) = match op.operands.last() {
+            Some(Operand::Immediate(_)) => (self.read_blocks(op, &op.operands[1], false)?, None),
+            _ => {
+                let (a, b) = self.read_sources(op)?;
+                (a, Some(b))
+            }
+        };
+        let bits = immediate(op) as usize;
+        let mut result = [0; BLOCKS];
+        for block in 0..blocks(op) {
+            for i in 0..count {
+                let index = match (control, size) {
+                    (Some(control), 4) => lane(control[block], i, 4) as usize & 3,
+                    (Some(control), _) => lane(control[block], i, 8) as usize >> 1 & 1,
+                    (None, 4) => bits >> (2 * i) & 3,
+                    (None, _) => bits >> (block * count + i) & 1,
+                };
+                set_element(&mut result, block * count + i, size, lane(source[block], index, size));
+            }
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VMASKMOVPS, VMASKMOVPD, VPMASKMOVD and VPMASKMOVQ: the lanes whose mask (the first
+    /// source) has its top bit set are loaded, the others cleared, or only they are stored.
+    /// Masked off lanes are not accessed, so they cannot fault
+    pub(crate) fn mask_move(&mut self, op: &X86Opcode, size: usize) -> Execution {
+        let mask = self.read_blocks(op, &op.operands[1], false)?;
+        let selected = |i: usize| element(&mask, i, size) >> (size * 8 - 1) != 0;
+        let count = blocks(op) * 16 / size;
+        match (op.operands[0], op.operands[2]) {
+            (Operand::Memory(memory), source) => {
+                let source = self.read_blocks(op, &source, false)?;
+                let address = self.effective_address(op, &memory);
+                for i in (0..count).filter(|&i| selected(i)) {
+                    let value = element(&source, i, size) as u64;
+                    self.store(address.wrapping_add((i * size) as u64), size, value)?;
+                }
+                Ok(())
+            }
+            (destination, Operand::Memory(memory)) => {
+                let address = self.effective_address(op, &memory);
+                let mut result = [0; BLOCKS];
+                for i in (0..count).filter(|&i| selected(i)) {
+                    let value = self.load(address.wrapping_add((i * size) as u64), size)?;
+                    set_element(&mut result, i, size, value as u128);
+                }
+                self.write_blocks(op, &destination, result, false)
+            }
+            _ => Err(unsupported(op)),
+        }
+    }
+
+    /// VGATHER and VPGATHER: `size` byte lanes loaded through a vector of `index_size` byte
+    /// signed indices, where the mask (the third operand) has its top bit set. Each lane
+    /// clears its mask lane once loaded, so that a fault part way through leaves the
+    /// instruction restartable, and when all are done the whole mask register is cleared.
+    /// Lanes the mask skips keep the destination. #UD if any two of the destination, index
+    /// and mask are the same register
+    pub(crate) fn gather(&mut self, op: &X86Opcode, index_size: usize, size: usize) -> Execution {
+        if op.encoding == Encoding::Evex {
+            return self.gather_by_opmask(op, index_size, size);
+        }
+        let Operand::Memory(memory) = op.operands[1] else {
+            return Err(unsupported(op));
+        };
+        let index_register = memory.index.and_then(|index| vector_register(&Operand::Register(index)));
+        let (Some(destination), Some(index_register), Some(mask_register)) =
+            (vector_register(&op.operands[0]), index_register, vector_register(&op.operands[2]))
+        else {
+            return Err(unsupported(op));
+        };
+        if destination == index_register || destination == mask_register || index_register == mask_register {
+            return Err(Exception::InvalidOpcode.into());
+        }
+
+        let count = blocks(op) * 16 / index_size.max(size);
+        let mut result = self.read_blocks(op, &op.operands[0], false)?;
+        let mut mask = self.read_blocks(op, &op.operands[2], false)?;
+        // lanes past the count are cleared in both
+        let keep = |vector: &mut Vector| {
+            let mut kept = [0; BLOCKS];
+            for i in 0..count {
+                set_element(&mut kept, i, size, element(vector, i, size));
+            }
+            *vector = kept;
+        };
+        keep(&mut result);
+        keep(&mut mask);
+
+        for i in 0..count {
+            if element(&mask, i, size) >> (size * 8 - 1) == 0 {
+                continue;
+            }
+            let address = self.element_address(op, &memory, index_size, i);
+            match self.load(address, size) {
+                Ok(value) => {
+                    set_element(&mut result, i, size, value as u128);
+                    set_element(&mut mask, i, size, 0);
+                }
+                Err(exception) => {
+                    self.write_blocks(op, &op.operands[0], result, false)?;
+                    self.write_blocks(op, &op.operands[2], mask, false)?;
+                    return Err(exception.into());
+                }
+            }
+        }
+        self.write_blocks(op, &op.operands[0], result, false)?;
+        self.write_blocks(op, &op.operands[2], [0; BLOCKS], false)
+    }
+
+    /// The address of element `index` of a VSIB memory operand: its base and displacement
+    /// plus that element of the index register, sign extended from `index_size` bytes and scaled
+    pub(crate) fn element_address(&self, op: &X86Opcode, memory: &MemoryOperand, index_size: usize, index: usize) -> u64 {
+        let indices = memory.index.map_or([0; BLOCKS], |register| self.vector(register.number()));
+        let offset = sign_extend(element(&indices, index, index_size) as u64, index_size).wrapping_mul(memory.scale as u64) as i64;
+        let element_memory = MemoryOperand { index: None, displacement: memory.displacement.wrapping_add(offset), ..*memory };
+        self.effective_address(op, &element_memory)
+    }
+
+    /// FMA3: `order` names the operands multiplied and added by the digits of the mnemonic,
+    /// so 132 is `operand 1 * operand 3 + operand 2`. The product is not rounded before it is
+    /// added. A scalar form keeps the rest of the low block of the destination
+    pub(crate) fn fused_multiply_add(&mut self, op: &X86Opcode, f: Format, lanes: Lanes, order: [usize; 3], fused: Fused) -> Execution {
+        let size = f.bits() as usize / 8;
+        let values = [
+            self.read_blocks(op, &op.operands[0], false)?,
+            self.read_blocks(op, &op.operands[1], false)?,
+            self.read_blocks(op, &op.operands[2], false)?,
+        ];
+        let [x, y, z] = order.map(|n| values[n - 1]);
+        let (count, mut result) = match lanes {
+            Lanes::Packed => (blocks(op) * 16 / size, [0; BLOCKS]),
+            Lanes::Scalar => {
+                let mut result = [0; BLOCKS];
+                result[0] = values[0][0];
+                (1, result)
+            }
+        };
+        let mut env = self.float_environment(op);
+        let mask = self.write_mask(op);
+        for i in (0..count).filter(|&i| mask.is_none_or(|mask| mask.selected(i))) {
+            let (negate_product, negate_addend) = fused.negations(i);
+            let operands = (element(&x, i, size), element(&y, i, size), element(&z, i, size));
+            let value = softfloat::fused_multiply_add(f, operands, negate_product, negate_addend, &mut env);
+            set_element(&mut result, i, size, value);
+        }
+        self.float_exceptions(op, env.flags)?;
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+}
@@ -0,0 +1,738 @@
+//! AVX-512: the opmask registers and the instructions on them, and the EVEX only vector
+//! instructions: compares into an opmask, ternary logic, blends by an opmask, compress and
+//! expand, two table permutes, element alignment, 128-bit block shuffles, down conversions,
+//! conflict detection and opmask broadcasts, byte multishifts, double block sums of absolute
+//! differences, masked gathers and scatters, and the floating point VGETMANT, VFIXUPIMM,
+//! VRANGE and VFPCLASS
+//!
+//! The EVEX forms of the SSE and AVX instructions run through the same code as those. What EVEX
+//! adds is dealt with on the way in and out: `read_blocks` repeats an embedded broadcast across
+//! the vector, `write_blocks` applies the opmask to the destination (merging or zeroing), and
+//! `float_environment` / `float_exceptions` take the embedded rounding mode and suppress
+//! exceptions. Which elements the opmask covers comes from the mnemonic, see `element_size`.
+use crate::execute::operands::{blocks, element, immediate, lane, lane_mask, mask, set_element, sign_extend, Vector, BLOCKS};
+use crate::execute::shift::flag;
+use crate::execute::sse::{each_lane, Lanes};
+use crate::execute::{unsupported, Exception, Execution, Trap};
+use crate::flags::{RFlags, ARITHMETIC_FLAGS};
+use crate::softfloat::{self, Class, Constant, Format, Rounding, DIVIDE_BY_ZERO, EXTENDED, INVALID};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{Encoding, Mnemonic, Operand, Register, X86Opcode};
+
+/// EVEX masking of a destination, by an opmask register and EVEX.z
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct WriteMask {
+    bits: u64,
+    zeroing: bool,
+    /// bytes in an element
+    pub(crate) size: usize,
+    /// elements the mask covers, only the lowest for a scalar instruction
+    count: usize,
+}
+
+impl WriteMask {
+    /// Whether element `index` is written. Elements past those the mask covers always are
+    pub(crate) fn selected(&self, index: usize) -> bool {
+        index >= self.count || self.bits >> index & 1 != 0
+    }
+
+    /// `value` with the elements the mask leaves out taken from `old`, or cleared
+    pub(crate) fn merge(&self, mut value: Vector, old: Vector) -> Vector {
+        for i in (0..self.count).filter(|&i| !self.selected(i)) {
+            let kept = if self.zeroing { 0 } else { element(&old, i, self.size) };
+            set_element(&mut value, i, self.size, kept);
+        }
+        value
+    }
+}
+
+/// An operation on opmask registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OpmaskLogic {
+    And,
+    /// `!first & second`
+    AndNot,
+    Or,
+    Xor,
+    Xnor,
+    Add,
+    /// of the only source
+    Not,
+}
+
+/// The element size an EVEX instruction is masked by, and whether it is scalar, from its
+/// mnemonic: `vaddps` and `vpaddd` mask doublewords, `vmovdqu16` words, `vinserti64x4` and
+/// `vcvtps2pd` quadwords (the elements written), `vaddss` only its lowest doubleword
+fn element_size(op: &X86Opcode) -> (usize, bool) {
+    let name = op.mnemonic.as_str();
+    let by_suffix = |name: &str| match name.as_bytes().last() {
+        Some(b'b') => Some(1),
+        Some(b'w') => Some(2),
+        Some(b'd') => Some(4),
+        Some(b'q') => Some(8),
+        _ => None,
+    };
+    let bits = |digits: &str| digits.parse::<usize>().ok().map(|bits| bits / 8);
+    let trailing_digits = name.trim_end_matches(|c: char| c.is_ascii_digit());
+
+    let size = match name {
+        "vpalignr" => Some(1),
+        // vpcmpd is not a PD instruction
+        "vmovshdup" | "vmovsldup" | "vbroadcastss" | "vpcmpd" => Some(4),
+        "vmovddup" | "vbroadcastsd" => Some(8),
+        // 32x4, 64x2 and the like: blocks of 32 or 64-bit elements
+        _ if name.contains('x') && trailing_digits.len() < name.len() => {
+            name.rsplit_once('x').and_then(|(head, _)| bits(&head[head.len() - 2..]))
+        }
+        _ if trailing_digits.len() < name.len() => bits(&name[trailing_digits.len()..]),
+        _ if name.starts_with("vcvt") && name.contains('2') => match name.rsplit('2').next() {
+            Some("ps" | "dq" | "udq") => Some(4),
+            Some("pd" | "qq" | "uqq") => Some(8),
+            Some("ss") => return (4, true),
+            Some("sd") => return (8, true),
+            Some("ph") => Some(2),
+            _ => None,
+        },
+        _ if name.ends_with("2m") => by_suffix(&name[..name.len() - 2]),
+        _ if name.starts_with("vpunpck") => by_suffix(&name[..9]),
+        _ if !name.starts_with("vp") && name.ends_with("ss") => return (4, true),
+        _ if !name.starts_with("vp") && name.ends_with("sd") => return (8, true),
+        _ if name.ends_with("ps") => Some(4),
+        _ if name.ends_with("pd") => Some(8),
+        _ => by_suffix(name),
+    };
+    let default = if op.vex.is_some_and(|vex| vex.w) { 8 } else { 4 };
+    (size.unwrap_or(default), false)
+}
+
+/// Bytes of the opmask registers an opmask instruction works on, by the last letter of its
+/// mnemonic
+pub(crate) fn mask_size(op: &X86Opcode) -> usize {
+    match op.mnemonic.as_str().as_bytes().last() {
+        Some(b'b') => 1,
+        Some(b'w') => 2,
+        Some(b'd') => 4,
+        _ => 8,
+    }
+}
+
+/// Instructions that use their opmask for something other than masking the destination
+fn masks_itself(mnemonic: Mnemonic) -> bool {
+    use Mnemonic as M;
+    matches!(
+        mnemonic,
+        M::Vblendmps
+            | M::Vblendmpd
+            | M::Vpblendmb
+            | M::Vpblendmw
+            | M::Vpblendmd
+            | M::Vpblendmq
+            | M::Vcompressps
+            | M::Vcompresspd
+            | M::Vpcompressd
+            | M::Vpcompressq
+            | M::Vexpandps
+            | M::Vexpandpd
+            | M::Vpexpandd
+            | M::Vpexpandq
+            | M::Vgatherdps
+            | M::Vgatherdpd
+            | M::Vgatherqps
+            | M::Vgatherqpd
+            | M::Vpgatherdd
+            | M::Vpgatherdq
+            | M::Vpgatherqd
+            | M::Vpgatherqq
+            | M::Vscatterdps
+            | M::Vscatterdpd
+            | M::Vscatterqps
+            | M::Vscatterqpd
+            | M::Vpscatterdd
+            | M::Vpscatterdq
+            | M::Vpscatterqd
+            | M::Vpscatterqq
+    )
+}
+
+/// Number of an opmask register operand
+fn opmask_register(op: &X86Opcode, operand: &Operand) -> Result<u8, Trap> {
+    match *operand {
+        Operand::Register(Register::Opmask(n)) => Ok(n),
+        _ => Err(unsupported(op)),
+    }
+}
+
+impl X86Machine {
+    /// The opmask of an EVEX instruction, all ones when it has none (k0)
+    pub(crate) fn opmask_bits(&self, op: &X86Opcode) -> u64 {
+        match op.vex {
+            Some(vex) if op.encoding == Encoding::Evex && vex.aaa != 0 => self.opmask(vex.aaa),
+            _ => u64::MAX,
+        }
+    }
+
+    /// How an EVEX instruction masks its destination, `None` if it does not
+    pub(crate) fn write_mask(&self, op: &X86Opcode) -> Option<WriteMask> {
+        let vex = op.vex.filter(|vex| op.encoding == Encoding::Evex && (vex.aaa != 0 || vex.z))?;
+        if masks_itself(op.mnemonic) {
+            return None;
+        }
+        let (size, scalar) = element_size(op);
+        Some(WriteMask {
+            bits: self.opmask_bits(op),
+            zeroing: vex.z,
+            size,
+            count: if scalar { 1 } else { blocks(op) * 16 / size },
+        })
+    }
+
+    /// Opmask register `n` as a vector, each element all ones where its bit is set
+    pub(crate) fn opmask_lanes(&self, op: &X86Opcode, n: u8) -> Vector {
+        let (size, _) = element_size(op);
+        let bits = self.opmask(n);
+        let mut lanes = [0; BLOCKS];
+        for i in (0..blocks(op) * 16 / size).filter(|&i| bits >> i & 1 != 0) {
+            set_element(&mut lanes, i, size, lane_mask(size));
+        }
+        lanes
+    }
+
+    /// Sets opmask register `n` to the top bits of the elements of `value`, those the
+    /// instruction's own opmask leaves out cleared
+    pub(crate) fn set_opmask_lanes(&mut self, op: &X86Opcode, n: u8, value: Vector) {
+        let (size, scalar) = element_size(op);
+        let count = if scalar { 1 } else { blocks(op) * 16 / size };
+        let bits = (0..count).fold(0, |bits, i| bits | ((element(&value, i, size) >> (size * 8 - 1)) as u64 & 1) << i);
+        self.set_opmask(n, bits & self.opmask_bits(op));
+    }
+
+    /// KMOV between opmask registers, memory and general purpose registers, `size` bytes of it
+    pub(crate) fn kmov(&mut self, op: &X86Opcode, size: usize) -> Execution {
+        let value = match op.operands[1] {
+            Operand::Register(Register::Opmask(n)) => self.opmask(n),
+            source => self.read_operand(op, &source)?,
+        } & mask(size);
+        match op.operands[0] {
+            Operand::Register(Register::Opmask(n)) => {
+                self.set_opmask(n, value);
+                Ok(())
+            }
+            destination => self.write_operand(op, &destination, value),
+        }
+    }
+
+    /// KAND, KANDN, KOR, KXOR, KXNOR, KADD and KNOT, on the low `size` bytes with the rest of
+    /// the destination cleared
+    pub(crate) fn opmask_logic(&mut self, op: &X86Opcode, logic: OpmaskLogic, size: usize) -> Execution {
+        let destination = opmask_register(op, &op.operands[0])?;
+        let a = self.opmask(opmask_register(op, &op.operands[1])?);
+        let b = match op.operands.get(2) {
+            Some(operand) => self.opmask(opmask_register(op, operand)?),
+            None => 0,
+        };
+        let value = match logic {
+            OpmaskLogic::And => a & b,
+            OpmaskLogic::AndNot => !a & b,
+            OpmaskLogic::Or => a | b,
+            OpmaskLogic::Xor => a ^ b,
+            OpmaskLogic::Xnor => !(a ^ b),
+            OpmaskLogic::Add => a.wrapping_add(b),
+            OpmaskLogic::Not => !a,
+        };
+        self.set_opmask(destination, value & mask(size));
+        Ok(())
+    }
+
+    /// KORTEST sets ZF if the OR of the two is all zeros and CF if it is all ones, KTEST sets
+    /// ZF if their AND is zero and CF if the second has no bits the first lacks. The other
+    /// arithmetic flags are cleared
+    pub(crate) fn opmask_test(&mut self, op: &X86Opcode, size: usize, or: bool) -> Execution {
+        let a = self.opmask(opmask_register(op, &op.operands[0])?) & mask(size);
+        let b = self.opmask(opmask_register(op, &op.operands[1])?) & mask(size);
+        let (zero, carry) = match or {
+            true => (a | b == 0, a | b == mask(size)),
+            false => (a & b == 0, !a & b == 0),
+        };
+        self.flags.update(ARITHMETIC_FLAGS, flag(zero, RFlags::Zero) | flag(carry, RFlags::Carry));
+        Ok(())
+    }
+
+    /// KSHIFTL and KSHIFTR by the immediate, clearing the register for a count of `size * 8` or
+    /// more
+    pub(crate) fn opmask_shift(&mut self, op: &X86Opcode, size: usize, left: bool) -> Execution {
+        let destination = opmask_register(op, &op.operands[0])?;
+        let value = self.opmask(opmask_register(op, &op.operands[1])?) & mask(size);
+        let count = immediate(op) as u32;
+        let value = match (count < size as u32 * 8, left) {
+            (false, _) => 0,
+            (true, true) => value << count,
+            (true, false) => value >> count,
+        };
+        self.set_opmask(destination, value & mask(size));
+        Ok(())
+    }
+
+    /// KUNPCKBW, KUNPCKWD and KUNPCKDQ: the low `size` bytes of the second source, then those
+    /// of the first
+    pub(crate) fn opmask_unpack(&mut self, op: &X86Opcode, size: usize) -> Execution {
+        let destination = opmask_register(op, &op.operands[0])?;
+        let a = self.opmask(opmask_register(op, &op.operands[1])?) & mask(size);
+        let b = self.opmask(opmask_register(op, &op.operands[2])?) & mask(size);
+        self.set_opmask(destination, a << (size * 8) | b);
+        Ok(())
+    }
+
+    /// VPCMP and VPCMPU: each element compared by the predicate in bits 2:0 of the immediate,
+    /// equal, less, less or equal, false, not equal, not less, greater or true
+    pub(crate) fn compare_integers(&mut self, op: &X86Opcode, size: usize, signed: bool) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let predicate = immediate(op) & 7;
+        let mut result = [0; BLOCKS];
+        for i in 0..blocks(op) * 16 / size {
+            let (x, y) = (element(&a, i, size) as u64, element(&b, i, size) as u64);
+            let order = match signed {
+                true => (sign_extend(x, size) as i64).cmp(&(sign_extend(y, size) as i64)),
+                false => x.cmp(&y),
+            };
+            let holds = match predicate {
+                0 => order.is_eq(),
+                1 => order.is_lt(),
+                2 => order.is_le(),
+                3 => false,
+                4 => order.is_ne(),
+                5 => order.is_ge(),
+                6 => order.is_gt(),
+                _ => true,
+            };
+            if holds {
+                set_element(&mut result, i, size, lane_mask(size));
+            }
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VPTESTM sets the bits of the elements whose AND is not zero, VPTESTNM of those where it is
+    pub(crate) fn test_elements(&mut self, op: &X86Opcode, size: usize, negated: bool) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let mut result = [0; BLOCKS];
+        for i in 0..blocks(op) * 16 / size {
+            if (element(&a, i, size) & element(&b, i, size) == 0) == negated {
+                set_element(&mut result, i, size, lane_mask(size));
+            }
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VPTERNLOGD and VPTERNLOGQ: each bit of the result is the bit of the immediate indexed by
+    /// the bits of the destination, first source and second source, in that order from the top
+    pub(crate) fn ternary_logic(&mut self, op: &X86Opcode) -> Execution {
+        let destination = self.read_blocks(op, &op.operands[0], false)?;
+        let (a, b) = self.read_sources(op)?;
+        let table = immediate(op);
+        let mut result = [0; BLOCKS];
+        for (i, block) in result.iter_mut().take(blocks(op)).enumerate() {
+            let pick = |bit: u64, value: u128| if bit != 0 { value } else { !value };
+            *block = (0..8)
+                .filter(|&index| table >> index & 1 != 0)
+                .map(|index| pick(index & 4, destination[i]) & pick(index & 2, a[i]) & pick(index & 1, b[i]))
+                .fold(0, |result, term| result | term);
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VBLENDM and VPBLENDM: the second source where the opmask is set, the first (or zero,
+    /// with EVEX.z) where it is not
+    pub(crate) fn blend_by_opmask(&mut self, op: &X86Opcode, size: usize) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let bits = self.opmask_bits(op);
+        let zeroing = op.vex.is_some_and(|vex| vex.z);
+        let mut result = [0; BLOCKS];
+        for i in 0..blocks(op) * 16 / size {
+            let value = match (bits >> i & 1 != 0, zeroing) {
+                (true, _) => element(&b, i, size),
+                (false, false) => element(&a, i, size),
+                (false, true) => 0,
+            };
+            set_element(&mut result, i, size, value);
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VCOMPRESS and VPCOMPRESS: the elements the opmask selects, packed together at the bottom
+    /// of the destination. A register keeps (or with EVEX.z clears) the rest, memory is only
+    /// written for the elements stored
+    pub(crate) fn compress(&mut self, op: &X86Opcode, size: usize) -> Execution {
+        let source = self.read_blocks(op, &op.operands[1], false)?;
+        let bits = self.opmask_bits(op);
+        let packed: Vec<u128> = (0..blocks(op) * 16 / size)
+            .filter(|&i| bits >> i & 1 != 0)
+            .map(|i| element(&source, i, size))
+            .collect();
+        match op.operands[0] {
+            Operand::Memory(memory) => {
+                let address = self.effective_address(op, &memory);
+                for (i, value) in packed.iter().enumerate() {
+                    self.store_bytes(address.wrapping_add((i * size) as u64), &value.to_le_bytes()[..size])?;
+                }
+                Ok(())
+            }
+            destination => {
+                let mut result = match op.vex.is_some_and(|vex| vex.z) {
+                    true => [0; BLOCKS],
+                    false => self.read_blocks(op, &destination, false)?,
+                };
+                for (i, &value) in packed.iter().enumerate() {
+                    set_element(&mut result, i, size, value);
+                }
+                self.write_blocks(op, &destination, result, false)
+            }
+        }
+    }
+
+    /// VEXPAND and VPEXPAND: consecutive elements of the source into the elements the opmask
+    /// selects. Only as many elements of a memory source are read as are selected
+    pub(crate) fn expand(&mut self, op: &X86Opcode, size: usize) -> Execution {
+        let bits = self.opmask_bits(op);
+        let selected: Vec<usize> = (0..blocks(op) * 16 / size).filter(|&i| bits >> i & 1 != 0).collect();
+        let values: Vec<u128> = match op.operands[1] {
+            Operand::Memory(memory) => {
+                let address = self.effective_address(op, &memory);
+                let bytes = self.load_bytes(address, selected.len() * size)?;
+                bytes.chunks(size).map(|chunk| chunk.iter().rev().fold(0, |value, &byte| value << 8 | byte as u128)).collect()
+            }
+            source => {
+                let source = self.read_blocks(op, &source, false)?;
+                (0..selected.len()).map(|i| element(&source, i, size)).collect()
+            }
+        };
+        let mut result = match op.vex.is_some_and(|vex| vex.z) {
+            true => [0; BLOCKS],
+            false => self.read_blocks(op, &op.operands[0], false)?,
+        };
+        for (&i, value) in selected.iter().zip(values) {
+            set_element(&mut result, i, size, value);
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VPERMT2 and VPERMI2: elements picked from a table of two vectors. VPERMT2 has the
+    /// indices in its first source and the table in the destination and second source, VPERMI2
+    /// the indices in the destination and the table in the two sources
+    pub(crate) fn permute_two_tables(&mut self, op: &X86Opcode, size: usize, indices_in_destination: bool) -> Execution {
+        let destination = self.read_blocks(op, &op.operands[0], false)?;
+        let (a, b) = self.read_sources(op)?;
+        let (indices, low) = match indices_in_destination {
+            true => (destination, a),
+            false => (a, destination),
+        };
+        let count = blocks(op) * 16 / size;
+        let mut result = [0; BLOCKS];
+        for i in 0..count {
+            let index = element(&indices, i, size) as usize % (2 * count);
+            let value = match index < count {
+                true => element(&low, index, size),
+                false => element(&b, index - count, size),
+            };
+            set_element(&mut result, i, size, value);
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VALIGND and VALIGNQ: the first source above the second, shifted down by as many
+    /// elements as the immediate says
+    pub(crate) fn align_elements(&mut self, op: &X86Opcode, size: usize) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let count = blocks(op) * 16 / size;
+        let shift = immediate(op) as usize % count;
+        let mut result = [0; BLOCKS];
+        for i in 0..count {
+            let value = match i + shift < count {
+                true => element(&b, i + shift, size),
+                false => element(&a, i + shift - count, size),
+            };
+            set_element(&mut result, i, size, value);
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VSHUFF32X4, VSHUFF64X2, VSHUFI32X4 and VSHUFI64X2: the lower half of the result from
+    /// blocks of the first source, the upper half from blocks of the second, each picked by a
+    /// field of the immediate (one bit at 256 bits, two at 512)
+    pub(crate) fn shuffle_blocks(&mut self, op: &X86Opcode) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let count = blocks(op);
+        let bits = if count == 4 { 2 } else { 1 };
+        let control = immediate(op) as usize;
+        let mut result = [0; BLOCKS];
+        for (i, block) in result.iter_mut().take(count).enumerate() {
+            let source = if i < count / 2 { &a } else { &b };
+            *block = source[control >> (i * bits) & (count - 1)];
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VPMOV down conversions from `from` to `to` byte elements: truncated, or saturated as
+    /// signed (VPMOVS) or unsigned (VPMOVUS) values
+    pub(crate) fn narrow(&mut self, op: &X86Opcode, from: usize, to: usize, saturate: Option<bool>) -> Execution {
+        let source = self.read_blocks(op, &op.operands[1], false)?;
+        let mut result = [0; BLOCKS];
+        for i in 0..blocks(op) * 16 / from {
+            let value = element(&source, i, from) as u64;
+            let value = match saturate {
+                None => value & mask(to),
+                Some(true) => {
+                    let limit = i64::MAX >> (64 - to * 8);
+                    (sign_extend(value, from) as i64).clamp(!limit, limit) as u64 & mask(to)
+                }
+                Some(false) => value.min(mask(to)),
+            };
+            set_element(&mut result, i, to, value as u128);
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VPCONFLICTD and VPCONFLICTQ: for each element, a bit for every earlier element equal to it
+    pub(crate) fn conflicts(&mut self, op: &X86Opcode, size: usize) -> Execution {
+        let source = self.read_blocks(op, &op.operands[1], false)?;
+        let mut result = [0; BLOCKS];
+        for i in 0..blocks(op) * 16 / size {
+            let value = element(&source, i, size);
+            let bits = (0..i).filter(|&j| element(&source, j, size) == value).fold(0, |bits, j| bits | 1 << j);
+            set_element(&mut result, i, size, bits);
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VPMULTISHIFTQB: each byte of the result the eight bits of the matching quadword of the
+    /// second source from the bit the same byte of the first source gives, wrapping round
+    pub(crate) fn multishift(&mut self, op: &X86Opcode) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let mut result = [0; BLOCKS];
+        for i in 0..blocks(op) * 16 {
+            let data = element(&b, i / 8, 8) as u64;
+            let control = element(&a, i, 1) as u32 & 63;
+            set_element(&mut result, i, 1, (data.rotate_right(control) & 0xFF) as u128);
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VPBROADCASTMB2Q and VPBROADCASTMW2D: the low `from` bytes of an opmask, zero extended
+    /// into every element
+    pub(crate) fn broadcast_opmask(&mut self, op: &X86Opcode, from: usize, size: usize) -> Execution {
+        let value = self.opmask(opmask_register(op, &op.operands[1])?) & mask(from);
+        let mut result = [0; BLOCKS];
+        for i in 0..blocks(op) * 16 / size {
+            set_element(&mut result, i, size, value as u128);
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VDBPSADBW: in each block the dwords of the second source are shuffled by the immediate as
+    /// PSHUFD does, then each quadword gives four word sums of absolute differences. The first
+    /// two compare the low dword of the first source with bytes 0 to 3 and 1 to 4 of the
+    /// shuffled quadword, the last two its high dword with bytes 2 to 5 and 3 to 6
+    pub(crate) fn double_block_sums(&mut self, op: &X86Opcode) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let control = immediate(op) as usize;
+        let mut result = [0; BLOCKS];
+        for (i, block) in result.iter_mut().take(blocks(op)).enumerate() {
+            let shuffled = (0..4).fold(0, |value, j| value | lane(b[i], control >> (j * 2) & 3, 4) << (j * 32));
+            for word in 0..8 {
+                let (quadword, k) = (word / 4 * 8, word % 4);
+                let sum: u128 = (0..4)
+                    .map(|j| lane(a[i], quadword + k / 2 * 4 + j, 1).abs_diff(lane(shuffled, quadword + k + j, 1)))
+                    .sum();
+                *block |= sum << (word * 16);
+            }
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VGETMANT: the significands of the source, normalized as the immediate says (see
+    /// `softfloat::mantissa`)
+    pub(crate) fn mantissa(&mut self, op: &X86Opcode, f: Format, lanes: Lanes) -> Execution {
+        let size = f.bits() as usize / 8;
+        let (a, b) = self.read_sources(op)?;
+        let control = immediate(op) as u8;
+        let mut env = self.float_environment(op);
+        let result = each_lane(op, (&a, &b), size, lanes, self.write_mask(op), |_, y| softfloat::mantissa(f, y, control, &mut env));
+        self.float_exceptions(op, env.flags)?;
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VRANGE: the minimum or maximum of each pair of elements as the immediate says (see
+    /// `softfloat::range`)
+    pub(crate) fn range(&mut self, op: &X86Opcode, f: Format, lanes: Lanes) -> Execution {
+        let size = f.bits() as usize / 8;
+        let (a, b) = self.read_sources(op)?;
+        let control = immediate(op) as u8;
+        let mut env = self.float_environment(op);
+        let result = each_lane(op, (&a, &b), size, lanes, self.write_mask(op), |x, y| softfloat::range(f, x, y, control, &mut env));
+        self.float_exceptions(op, env.flags)?;
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VFPCLASS: the opmask bits of the elements in any of the classes the immediate picks,
+    /// from bit 0 up QNaN, +0, -0, +inf, -inf, denormal, negative finite and SNaN. DAZ does
+    /// not apply
+    pub(crate) fn float_class(&mut self, op: &X86Opcode, f: Format, lanes: Lanes) -> Execution {
+        let size = f.bits() as usize / 8;
+        let source = self.read_blocks(op, &op.operands[1], false)?;
+        let control = immediate(op) as u8;
+        let count = match lanes {
+            Lanes::Packed => blocks(op) * 16 / size,
+            Lanes::Scalar => 1,
+        };
+        let mut result = [0; BLOCKS];
+        for i in 0..count {
+            let value = element(&source, i, size);
+            let negative = f.is_negative(value) as u8;
+            let classes = match softfloat::classify(f, value) {
+                Class::Nan if f.is_signaling(value) => 1 << 7,
+                Class::Nan => 1 << 0,
+                Class::Zero => 1 << (1 + negative),
+                Class::Infinity => 1 << (3 + negative),
+                Class::Denormal => 1 << 5 | negative << 6,
+                _ => negative << 6,
+            };
+            if classes & control != 0 {
+                set_element(&mut result, i, size, lane_mask(size));
+            }
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VFIXUPIMM: each element of the first source, classed as a QNaN, SNaN, zero, +1, -inf,
+    /// +inf, negative or positive value, picks a four bit response from the matching element
+    /// of the second. The response keeps the destination, passes the source through or gives
+    /// one of a set of special values. The immediate says which classes raise divide by zero
+    /// or invalid. Under DAZ a denormal is +0
+    pub(crate) fn fixup(&mut self, op: &X86Opcode, f: Format, lanes: Lanes) -> Execution {
+        let size = f.bits() as usize / 8;
+        let destination = self.read_blocks(op, &op.operands[0], false)?;
+        let (a, b) = self.read_sources(op)?;
+        let control = immediate(op) as u8;
+        let env = self.float_environment(op);
+        let mut exact = env;
+        let one = softfloat::from_int(f, 1, &mut exact);
+        let pi = softfloat::convert(EXTENDED, f, softfloat::constant(Constant::Pi, Rounding::Nearest), &mut exact);
+        let constants = [
+            softfloat::from_int(f, -1, &mut exact),
+            one,
+            softfloat::scale(f, one, -1, &mut exact),
+            softfloat::from_int(f, 90, &mut exact),
+            softfloat::scale(f, pi, -1, &mut exact),
+            f.max_finite(false),
+            f.max_finite(true),
+        ];
+        let mut flags = 0;
+        let mut result = [0; BLOCKS];
+        let count = match lanes {
+            Lanes::Packed => blocks(op) * 16 / size,
+            Lanes::Scalar => {
+                result[0] = a[0];
+                1
+            }
+        };
+        let mask = self.write_mask(op);
+        for i in (0..count).filter(|&i| mask.is_none_or(|mask| mask.selected(i))) {
+            let source = match softfloat::classify(f, element(&a, i, size)) {
+                Class::Denormal if env.denormals_are_zero => f.zero(false),
+                _ => element(&a, i, size),
+            };
+            let negative = f.is_negative(source);
+            // the class, and the immediate bits that make it raise divide by zero and invalid
+            let (class, raises) = match softfloat::classify(f, source) {
+                Class::Nan if f.is_signaling(source) => (1, (0, 1 << 4)),
+                Class::Nan => (0, (0, 0)),
+                Class::Zero => (2, (1 << 0, 1 << 1)),
+                _ if source == one => (3, (1 << 2, 1 << 3)),
+                Class::Infinity if negative => (4, (0, 1 << 5)),
+                Class::Infinity => (5, (0, 1 << 7)),
+                _ if negative => (6, (0, 1 << 6)),
+                _ => (7, (0, 0)),
+            };
+            if control & raises.0 != 0 {
+                flags |= DIVIDE_BY_ZERO;
+            }
+            if control & raises.1 != 0 {
+                flags |= INVALID;
+            }
+            let value = match element(&b, i, size) >> (class * 4) & 0xF {
+                0 => element(&destination, i, size),
+                1 => source,
+                2 if f.is_nan(source) => f.quiet(source),
+                2 | 3 => f.default_nan(),
+                4 => f.infinity(true),
+                5 => f.infinity(false),
+                6 => f.infinity(negative),
+                7 => f.zero(true),
+                8 => f.zero(false),
+                response => constants[response as usize - 9],
+            };
+            set_element(&mut result, i, size, value);
+        }
+        self.float_exceptions(op, flags)?;
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// The EVEX gathers: as the AVX2 ones, with the opmask saying which elements to load and
+    /// cleared as they are. #UD without an opmask or if the destination is also the index
+    pub(crate) fn gather_by_opmask(&mut self, op: &X86Opcode, index_size: usize, size: usize) -> Execution {
+        let (Operand::Register(destination), Operand::Memory(memory)) = (op.operands[0], op.operands[1]) else {
+            return Err(unsupported(op));
+        };
+        let mask_register = op.vex.map_or(0, |vex| vex.aaa);
+        if mask_register == 0 || memory.index.is_some_and(|index| index.number() == destination.number()) {
+            return Err(Exception::InvalidOpcode.into());
+        }
+        let count = blocks(op) * 16 / index_size.max(size);
+        let mut result = self.read_blocks(op, &op.operands[0], false)?;
+        if op.vex.is_some_and(|vex| vex.z) {
+            result = [0; BLOCKS];
+        }
+        for i in 0..count {
+            let bits = self.opmask(mask_register);
+            if bits >> i & 1 == 0 {
+                continue;
+            }
+            let address = self.element_address(op, &memory, index_size, i);
+            match self.load(address, size) {
+                Ok(value) => {
+                    set_element(&mut result, i, size, value as u128);
+                    self.set_opmask(mask_register, bits & !(1 << i));
+                }
+                Err(exception) => {
+                    self.write_blocks(op, &op.operands[0], result, false)?;
+                    return Err(exception.into());
+                }
+            }
+        }
+        self.set_opmask(mask_register, 0);
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VSCATTER and VPSCATTER: the elements the opmask selects stored through a vector of
+    /// indices, lowest first, clearing their opmask bits as they go. #UD without an opmask
+    pub(crate) fn scatter(&mut self, op: &X86Opcode, index_size: usize, size: usize) -> Execution {
+        let Operand::Memory(memory) = op.operands[0] else {
+            return Err(unsupported(op));
+        };
+        let mask_register = op.vex.map_or(0, |vex| vex.aaa);
+        if mask_register == 0 {
+            return Err(Exception::InvalidOpcode.into());
+        }
+        let source = self.read_blocks(op, &op.operands[1], false)?;
+        for i in 0..blocks(op) * 16 / index_size.max(size) {
+            let bits = self.opmask(mask_register);
+            if bits >> i & 1 == 0 {
+                continue;
+            }
+            let address = self.element_address(op, &memory, index_size, i);
+            self.store(address, size, element(&source, i, size) as u64)?;
+            self.set_opmask(mask_register, bits & !(1 << i));
+        }
+        self.set_opmask(mask_register, 0);
+        Ok(())
+    }
+}
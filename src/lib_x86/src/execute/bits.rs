@@ -0,0 +1,244 @@
+//! Bit manipulation: BT, BTS, BTR, BTC, BSF, BSR, POPCNT, LZCNT, TZCNT, the BMI1 / BMI2
+//! ANDN, BEXTR, BLSI, BLSMSK, BLSR, BZHI, PDEP and PEXT, the ADX ADCX and ADOX, and BSWAP
+//!
+//! Where the SDM leaves flags undefined they are set the way Intel hardware sets them.
+use crate::execute::operands::{mask, operand_size};
+use crate::execute::shift::{flag, result_flags};
+use crate::execute::{sign_extend, Execution};
+use crate::flags::{AsRFlags, RFlags, ARITHMETIC_FLAGS};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{Operand, X86Opcode};
+
+/// What BT and friends do to the bit once it has been copied to CF
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BitTest {
+    Test,
+    Set,
+    Reset,
+    Complement,
+}
+
+/// The BMI1 / BMI2 operations on a single source, `src & -src` and the like
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LowestBit {
+    /// BLSI, isolates the lowest set bit
+    Isolate,
+    /// BLSMSK, sets every bit up to and including the lowest set bit
+    Mask,
+    /// BLSR, clears the lowest set bit
+    Reset,
+}
+
+const CF: u64 = RFlags::Carry as u64;
+const ZF: u64 = RFlags::Zero as u64;
+const SF: u64 = RFlags::Sign as u64;
+
+impl X86Machine {
+    /// BT, BTS, BTR and BTC. With a register bit offset and a memory operand the offset is
+    /// signed and may reach outside the operand, so it picks the operand sized word to use
+    /// first. An immediate offset (and any offset into a register) is taken modulo the size
+    pub(crate) fn bit_test(&mut self, op: &X86Opcode, test: BitTest) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let bits = size as u64 * 8;
+        let offset = self.read_operand(op, &op.operands[1])?;
+
+        let (value, address) = match (op.operands[0], op.operands[1]) {
+            (Operand::Memory(memory), Operand::Register(_)) => {
+                let words = sign_extend(offset, size) as i64 >> bits.trailing_zeros();
+                let address = self
+                    .effective_address(op, &memory)
+                    .wrapping_add((words as u64).wrapping_mul(size as u64));
+                (self.load(address, size)?, Some(address))
+            }
+            (ref destination, _) => (self.read_operand(op, destination)?, None),
+        };
+
+        let bit = 1 << (offset % bits);
+        self.flags.update(CF, flag(value & bit != 0, RFlags::Carry));
+        let result = match test {
+            BitTest::Test => return Ok(()),
+            BitTest::Set => value | bit,
+            BitTest::Reset => value & !bit,
+            BitTest::Complement => value ^ bit,
+        };
+        match address {
+            Some(address) => Ok(self.store(address, size, result)?),
+            None => self.write_operand(op, &op.operands[0], result),
+        }
+    }
+
+    /// BSF and BSR. A zero source sets ZF and leaves the destination as it was. Intel hardware
+    /// sets PF from the index found (as if 0 for a zero source) and clears the other flags
+    pub(crate) fn bit_scan(&mut self, op: &X86Opcode, forward: bool) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let source = self.read_operand(op, &op.operands[1])? & mask(size);
+        let index = match (source, forward) {
+            (0, _) => 0,
+            (_, true) => source.trailing_zeros() as u64,
+            (_, false) => 63 - source.leading_zeros() as u64,
+        };
+        if source != 0 {
+            self.write_operand(op, &op.operands[0], index)?;
+        }
+        let flags = result_flags(index, size) & RFlags::Parity as u64 | flag(source == 0, RFlags::Zero);
+        self.flags.update(ARITHMETIC_FLAGS, flags);
+        Ok(())
+    }
+
+    /// POPCNT: ZF says whether the source was zero, the other flags are cleared
+    pub(crate) fn popcnt(&mut self, op: &X86Opcode) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let source = self.read_operand(op, &op.operands[1])? & mask(size);
+        self.write_operand(op, &op.operands[0], source.count_ones() as u64)?;
+        self.flags.update(ARITHMETIC_FLAGS, flag(source == 0, RFlags::Zero));
+        Ok(())
+    }
+
+    /// LZCNT and TZCNT. Unlike BSR / BSF a zero source gives the operand size in bits, and CF
+    /// says that happened
+    pub(crate) fn count_zeros(&mut self, op: &X86Opcode, leading: bool) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let source = self.read_operand(op, &op.operands[1])? & mask(size);
+        let bits = size as u32 * 8;
+        let count = match leading {
+            true => source.leading_zeros() - (64 - bits),
+            false => source.trailing_zeros().min(bits),
+        } as u64;
+        self.write_operand(op, &op.operands[0], count)?;
+        let flags = flag(source == 0, RFlags::Carry) | flag(count == 0, RFlags::Zero);
+        self.flags.update(ARITHMETIC_FLAGS, flags);
+        Ok(())
+    }
+
+    /// ANDN: `!first source & second source`
+    pub(crate) fn andn(&mut self, op: &X86Opcode) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let a = self.read_operand(op, &op.operands[1])?;
+        let b = self.read_operand(op, &op.operands[2])?;
+        let result = !a & b & mask(size);
+        self.write_operand(op, &op.operands[0], result)?;
+        self.flags.update(ARITHMETIC_FLAGS, result_flags(result, size) & (ZF | SF));
+        Ok(())
+    }
+
+    /// BEXTR: the field of the source starting at bit `control[7:0]`, `control[15:8]` bits long
+    pub(crate) fn bextr(&mut self, op: &X86Opcode) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let source = self.read_operand(op, &op.operands[1])? & mask(size);
+        let control = self.read_operand(op, &op.operands[2])?;
+        let (start, length) = (control & 0xFF, control >> 8 & 0xFF);
+        let field = source.checked_shr(start as u32).unwrap_or(0);
+        let result = match length {
+            0..64 => field & ((1 << length) - 1),
+            _ => field,
+        };
+        self.write_operand(op, &op.operands[0], result)?;
+        self.flags.update(ARITHMETIC_FLAGS, flag(result == 0, RFlags::Zero));
+        Ok(())
+    }
+
+    /// BLSI, BLSMSK and BLSR. CF is set when the source is not zero for BLSI, and when it is
+    /// zero for the other two
+    pub(crate) fn lowest_bit(&mut self, op: &X86Opcode, lowest: LowestBit) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let source = self.read_operand(op, &op.operands[1])? & mask(size);
+        let (result, carry) = match lowest {
+            LowestBit::Isolate => (source & source.wrapping_neg(), source != 0),
+            LowestBit::Mask => (source ^ source.wrapping_sub(1), source == 0),
+            LowestBit::Reset => (source & source.wrapping_sub(1), source == 0),
+        };
+        let result = result & mask(size);
+        self.write_operand(op, &op.operands[0], result)?;
+        let flags = result_flags(result, size) & (ZF | SF) | flag(carry, RFlags::Carry);
+        self.flags.update(ARITHMETIC_FLAGS, flags);
+        Ok(())
+    }
+
+    /// BZHI: the source with every bit from `index[7:0]` up cleared. CF is set when the index
+    /// is past the top of the operand, in which case the source is copied as it is
+    pub(crate) fn bzhi(&mut self, op: &X86Opcode) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let source = self.read_operand(op, &op.operands[1])? & mask(size);
+        let index = self.read_operand(op, &op.operands[2])? & 0xFF;
+        let bits = size as u64 * 8;
+        let (result, carry) = match index < bits {
+            true => (source & ((1 << index) - 1), false),
+            false => (source, true),
+        };
+        self.write_operand(op, &op.operands[0], result)?;
+        let flags = result_flags(result, size) & (ZF | SF) | flag(carry, RFlags::Carry);
+        self.flags.update(ARITHMETIC_FLAGS, flags);
+        Ok(())
+    }
+
+    /// PDEP: the low bits of the source are scattered to the set bits of the mask, in order
+    pub(crate) fn pdep(&mut self, op: &X86Opcode) -> Execution {
+        let source = self.read_operand(op, &op.operands[1])?;
+        let mut selector = self.read_operand(op, &op.operands[2])?;
+        let (mut result, mut next) = (0, 0);
+        while selector != 0 {
+            let bit = selector & selector.wrapping_neg();
+            if source >> next & 1 != 0 {
+                result |= bit;
+            }
+            selector &= selector - 1;
+            next += 1;
+        }
+        self.write_operand(op, &op.operands[0], result)
+    }
+
+    /// PEXT: the bits of the source under the set bits of the mask, packed into the low bits
+    pub(crate) fn pext(&mut self, op: &X86Opcode) -> Execution {
+        let source = self.read_operand(op, &op.operands[1])?;
+        let mut selector = self.read_operand(op, &op.operands[2])?;
+        let (mut result, mut next) = (0, 0);
+        while selector != 0 {
+            let bit = selector & selector.wrapping_neg();
+            if source & bit != 0 {
+                result |= 1 << next;
+            }
+            selector &= selector - 1;
+            next += 1;
+        }
+        self.write_operand(op, &op.operands[0], result)
+    }
+
+    /// ADCX and ADOX: unsigned add with carry in and out of CF or OF respectively, leaving
+    /// every other flag alone so that two carry chains can be interleaved
+    pub(crate) fn add_carry(&mut self, op: &X86Opcode, carry: RFlags) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let a = self.read_operand(op, &op.operands[0])?;
+        let b = self.read_operand(op, &op.operands[1])? & mask(size);
+        let sum = a as u128 + b as u128 + self.flags.is_set(carry) as u128;
+        self.write_operand(op, &op.operands[0], sum as u64 & mask(size))?;
+        self.flags.update(carry as u64, flag(sum >> (size * 8) != 0, carry));
+        Ok(())
+    }
+
+    /// CRC32: folds the bytes of the source, least significant first, into the CRC-32C
+    /// (Castagnoli polynomial, bit reflected) held in the low doubleword of the destination
+    pub(crate) fn crc32(&mut self, op: &X86Opcode) -> Execution {
+        let size = operand_size(&op.operands[1]);
+        let source = self.read_operand(op, &op.operands[1])?;
+        let mut crc = self.read_operand(op, &op.operands[0])? as u32;
+        for byte in &source.to_le_bytes()[..size] {
+            crc ^= *byte as u32;
+            for _ in 0..8 {
+                crc = crc >> 1 ^ 0x82F6_3B78 & (crc & 1).wrapping_neg();
+            }
+        }
+        self.write_operand(op, &op.operands[0], crc as u64)
+    }
+
+    /// BSWAP: the bytes of the register in reverse order. The 16-bit form is undefined, Intel
+    /// hardware clears the register
+    pub(crate) fn bswap(&mut self, op: &X86Opcode) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let value = self.read_operand(op, &op.operands[0])?;
+        let swapped = match size {
+            2 => 0,
+            _ => value.swap_bytes() >> (64 - size * 8),
+        };
+        self.write_operand(op, &op.operands[0], swapped)
+    }
+}
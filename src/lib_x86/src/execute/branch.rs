@@ -0,0 +1,144 @@
+//! Control transfers: JMP, Jcc, CALL, RET, LOOP(cc), JCXZ / JECXZ / JRCXZ, and the SETcc and
+//! CMOVcc instructions that share Jcc's condition codes
+//!
+//! Segmentation is flat, so the far forms only use the offset of their target. CALLF pushes 0
+//! for CS and RETF discards the CS it pops. The near CALL and RET push and pop a return address
+//! of the operand size, so with a 66 prefix it is 16 bits and the target is truncated to match.
+//! Under MPX the near JMP, Jcc, CALL and RET can reset the bounds registers, see
+//! `legacy_branch`.
+use crate::execute::operands::mask;
+use crate::execute::{unsupported, Execution, Trap};
+use crate::flags::{AsRFlags, Condition, RFlags};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{Operand, Register, X86Opcode};
+
+impl X86Machine {
+    pub(crate) fn jmp(&mut self, op: &X86Opcode) -> Execution {
+        self.branch(op)?;
+        self.legacy_branch(op);
+        Ok(())
+    }
+
+    pub(crate) fn jcc(&mut self, op: &X86Opcode, condition: Condition) -> Execution {
+        if condition.holds(&self.flags) {
+            self.branch(op)?;
+        }
+        self.legacy_branch(op);
+        Ok(())
+    }
+
+    pub(crate) fn call(&mut self, op: &X86Opcode) -> Execution {
+        let size = op.operand_size as usize;
+        let target = self.read_operand(op, &op.operands[0])? & mask(size);
+        self.push(op.next_ip() & mask(size), size)?;
+        self.instruction_counter = target;
+        self.legacy_branch(op);
+        Ok(())
+    }
+
+    /// RET, with an optional number of bytes to release after the return address
+    pub(crate) fn ret(&mut self, op: &X86Opcode) -> Execution {
+        let target = self.pop(op.operand_size as usize)?;
+        self.release(op);
+        self.instruction_counter = target;
+        self.legacy_branch(op);
+        Ok(())
+    }
+
+    pub(crate) fn jmp_far(&mut self, op: &X86Opcode) -> Execution {
+        let (offset, _) = self.far_target(op)?;
+        self.instruction_counter = offset;
+        Ok(())
+    }
+
+    pub(crate) fn call_far(&mut self, op: &X86Opcode) -> Execution {
+        let (offset, size) = self.far_target(op)?;
+        self.push(0, size)?;
+        self.push(op.next_ip(), size)?;
+        self.instruction_counter = offset;
+        Ok(())
+    }
+
+    pub(crate) fn ret_far(&mut self, op: &X86Opcode) -> Execution {
+        let size = op.operand_size as usize;
+        let target = self.pop(size)?;
+        self.pop(size)?;
+        self.release(op);
+        self.instruction_counter = target;
+        Ok(())
+    }
+
+    /// LOOP, LOOPE and LOOPNE: decrement the count register, without touching the flags, and
+    /// branch while it is not zero and (for the conditional forms) ZF matches
+    pub(crate) fn loop_cc(&mut self, op: &X86Opcode, zero: Option<bool>) -> Execution {
+        let counter = counter(op.address_size);
+        let count = self.register(counter).wrapping_sub(1) & mask(op.address_size as usize);
+        self.set_register(counter, count);
+        if count != 0 && zero.is_none_or(|zero| self.flags.is_set(RFlags::Zero) == zero) {
+            self.branch(op)?;
+        }
+        Ok(())
+    }
+
+    /// JCXZ, JECXZ and JRCXZ, `size` being the width of the count register tested
+    pub(crate) fn jump_if_counter_zero(&mut self, op: &X86Opcode, size: u8) -> Execution {
+        if self.register(counter(size)) == 0 {
+            self.branch(op)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn setcc(&mut self, op: &X86Opcode, condition: Condition) -> Execution {
+        let value = condition.holds(&self.flags) as u64;
+        self.write_operand(op, &op.operands[0], value)
+    }
+
+    /// CMOVcc. The source is read even if the condition does not hold, and a 32-bit
+    /// destination has its upper half cleared either way
+    pub(crate) fn cmovcc(&mut self, op: &X86Opcode, condition: Condition) -> Execution {
+        let source = self.read_operand(op, &op.operands[1])?;
+        let value = match condition.holds(&self.flags) {
+            true => source,
+            false => self.read_operand(op, &op.operands[0])?,
+        };
+        self.write_operand(op, &op.operands[0], value)
+    }
+
+    /// Moves to the target of a near JMP, Jcc, LOOP or JCXZ
+    fn branch(&mut self, op: &X86Opcode) -> Execution {
+        self.instruction_counter = self.read_operand(op, &op.operands[0])?;
+        Ok(())
+    }
+
+    /// Target offset of a far JMP / CALL, along with the size of the offset
+    fn far_target(&self, op: &X86Opcode) -> Result<(u64, usize), Trap> {
+        match op.operands[0] {
+            Operand::Memory(memory) => {
+                // m16:16, m16:32 or m16:64, offset first
+                let size = memory.size as usize - 2;
+                let address = self.effective_address(op, &memory);
+                Ok((self.load(address, size)?, size))
+            }
+            Operand::FarPointer { offset, .. } => Ok((offset as u64, op.operand_size as usize)),
+            _ => Err(unsupported(op)),
+        }
+    }
+
+    /// Pops the immediate of `ret imm16` / `retf imm16` worth of bytes
+    fn release(&mut self, op: &X86Opcode) {
+        if let Some(Operand::Immediate(bytes)) = op.operands.first() {
+            let rsp = self.register(Register::RSP).wrapping_add(bytes.value);
+            self.set_register(Register::RSP, rsp);
+        }
+    }
+}
+
+/// CX, ECX or RCX
+fn counter(size: u8) -> Register {
+    match size {
+        2 => Register::CX,
+        4 => Register::ECX,
+        _ => Register::RCX,
+    }
+}
+
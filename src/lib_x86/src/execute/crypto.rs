@@ -0,0 +1,282 @@
+//! AES-NI, PCLMULQDQ and the SHA extensions
+//!
+//! The AES instructions each run one round of AES on a 16 byte state, whose bytes are in the
+//! order FIPS 197 numbers them: byte 4c + r of the register is row r of column c. The S-box
+//! and its inverse are worked out from their definition at compile time. The VEX and EVEX forms
+//! (VAES) run the round on each 128-bit block of the vector, and VPCLMULQDQ multiplies in each
+//! block the same way.
+//!
+//! The SHA instructions only have legacy SSE forms. They work on the four doublewords of an
+//! XMM register, which the SDM numbers from bits 127:96 down for SHA-1 and from bits 31:0 up
+//! for the SHA-256 message schedule; `dwords` has the lowest first.
+use crate::execute::operands::{blockwise, immediate};
+use crate::execute::Execution;
+use crate::x86::X86Machine;
+use lib_opcode::prelude::X86Opcode;
+
+/// Which of AESENC, AESENCLAST, AESDEC and AESDECLAST
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AesRound {
+    Encrypt,
+    EncryptLast,
+    Decrypt,
+    DecryptLast,
+}
+
+/// Multiplication by x in GF(2^8), modulo the AES polynomial x^8 + x^4 + x^3 + x + 1
+const fn xtime(a: u8) -> u8 {
+    a << 1 ^ if a & 0x80 != 0 { 0x1B } else { 0 }
+}
+
+const fn gf_multiply(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a = xtime(a);
+        b >>= 1;
+    }
+    product
+}
+
+/// The S-box: the multiplicative inverse, x^254, then the affine transformation
+const fn sbox() -> [u8; 256] {
+    let mut table = [0; 256];
+    let mut x = 0;
+    while x < 256 {
+        let (mut inverse, mut power, mut i) = (1, x as u8, 1);
+        while i < 8 {
+            power = gf_multiply(power, power);
+            inverse = gf_multiply(inverse, power);
+            i += 1;
+        }
+        table[x] = inverse
+            ^ inverse.rotate_left(1)
+            ^ inverse.rotate_left(2)
+            ^ inverse.rotate_left(3)
+            ^ inverse.rotate_left(4)
+            ^ 0x63;
+        x += 1;
+    }
+    table
+}
+
+const fn inverse_sbox(sbox: &[u8; 256]) -> [u8; 256] {
+    let mut table = [0; 256];
+    let mut x = 0;
+    while x < 256 {
+        table[sbox[x] as usize] = x as u8;
+        x += 1;
+    }
+    table
+}
+
+const SBOX: [u8; 256] = sbox();
+const INVERSE_SBOX: [u8; 256] = inverse_sbox(&SBOX);
+
+/// SHA-1 round constants, one for each 20 rounds
+const SHA1_K: [u32; 4] = [0x5A82_7999, 0x6ED9_EBA1, 0x8F1B_BCDC, 0xCA62_C1D6];
+
+fn sub_bytes(state: [u8; 16], table: &[u8; 256]) -> [u8; 16] {
+    state.map(|byte| table[byte as usize])
+}
+
+/// Row r moves r columns to the left, or to the right for the inverse
+fn shift_rows(state: [u8; 16], inverse: bool) -> [u8; 16] {
+    core::array::from_fn(|i| {
+        let (column, row) = (i / 4, i % 4);
+        let column = match inverse {
+            true => (column + 4 - row) % 4,
+            false => (column + row) % 4,
+        };
+        state[column * 4 + row]
+    })
+}
+
+/// Each column multiplied by {03}x^3 + {01}x^2 + {01}x + {02}, or by the inverse
+/// {0b}x^3 + {0d}x^2 + {09}x + {0e}
+fn mix_columns(state: [u8; 16], inverse: bool) -> [u8; 16] {
+    let coefficients: [u8; 4] = match inverse {
+        true => [0x0E, 0x0B, 0x0D, 0x09],
+        false => [0x02, 0x03, 0x01, 0x01],
+    };
+    core::array::from_fn(|i| {
+        let (column, row) = (i / 4, i % 4);
+        (0..4).fold(0, |sum, j| sum ^ gf_multiply(coefficients[j], state[column * 4 + (row + j) % 4]))
+    })
+}
+
+fn aes_round(state: u128, key: u128, round: AesRound) -> u128 {
+    let state = state.to_le_bytes();
+    let state = match round {
+        AesRound::Encrypt => mix_columns(sub_bytes(shift_rows(state, false), &SBOX), false),
+        AesRound::EncryptLast => sub_bytes(shift_rows(state, false), &SBOX),
+        AesRound::Decrypt => mix_columns(sub_bytes(shift_rows(state, true), &INVERSE_SBOX), true),
+        AesRound::DecryptLast => sub_bytes(shift_rows(state, true), &INVERSE_SBOX),
+    };
+    u128::from_le_bytes(state) ^ key
+}
+
+fn sub_word(word: u32) -> u32 {
+    u32::from_le_bytes(word.to_le_bytes().map(|byte| SBOX[byte as usize]))
+}
+
+/// Carry-less product of two quadwords
+fn carryless_multiply(a: u64, b: u64) -> u128 {
+    (0..64).filter(|i| b >> i & 1 != 0).fold(0, |product, i| product ^ (a as u128) << i)
+}
+
+/// The doublewords of an XMM value, lowest first
+fn dwords(value: u128) -> [u32; 4] {
+    core::array::from_fn(|i| (value >> (i * 32)) as u32)
+}
+
+fn from_dwords(dwords: [u32; 4]) -> u128 {
+    dwords.iter().rev().fold(0, |value, &dword| value << 32 | dword as u128)
+}
+
+/// The SHA-1 round function for rounds 0-19, 20-39, 40-59 and 60-79
+fn sha1_function(function: u64, b: u32, c: u32, d: u32) -> u32 {
+    match function & 3 {
+        0 => (b & c) ^ (!b & d),
+        2 => (b & c) ^ (b & d) ^ (c & d),
+        _ => b ^ c ^ d,
+    }
+}
+
+fn sigma0(x: u32) -> u32 {
+    x.rotate_right(7) ^ x.rotate_right(18) ^ x >> 3
+}
+
+fn sigma1(x: u32) -> u32 {
+    x.rotate_right(17) ^ x.rotate_right(19) ^ x >> 10
+}
+
+impl X86Machine {
+    /// AESENC, AESENCLAST, AESDEC and AESDECLAST: a round of the state in the first source
+    /// with the round key in the second
+    pub(crate) fn aes(&mut self, op: &X86Opcode, round: AesRound) -> Execution {
+        let (state, key) = self.read_sources(op)?;
+        let result = blockwise(op, &state, &key, |state, key| aes_round(state, key, round));
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// AESIMC: InvMixColumns of a round key, for the equivalent inverse cipher AESDEC runs
+    pub(crate) fn aesimc(&mut self, op: &X86Opcode) -> Execution {
+        let key = self.read_vector(op, &op.operands[1], op.vex.is_none())?;
+        let result = mix_columns(key.to_le_bytes(), true);
+        self.write_vector(op, &op.operands[0], u128::from_le_bytes(result), false)
+    }
+
+    /// AESKEYGENASSIST: SubWord of doublewords 1 and 3 of the source, and RotWord of those
+    /// with the round constant in imm8 added
+    pub(crate) fn aeskeygenassist(&mut self, op: &X86Opcode) -> Execution {
+        let source = dwords(self.read_vector(op, &op.operands[1], op.vex.is_none())?);
+        let constant = immediate(op) as u32 & 0xFF;
+        let (x1, x3) = (sub_word(source[1]), sub_word(source[3]));
+        let result = [x1, x1.rotate_right(8) ^ constant, x3, x3.rotate_right(8) ^ constant];
+        self.write_vector(op, &op.operands[0], from_dwords(result), false)
+    }
+
+    /// PCLMULQDQ: the carry-less product of the quadwords imm8 bits 0 and 4 pick from the
+    /// sources
+    pub(crate) fn pclmulqdq(&mut self, op: &X86Opcode) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let selector = immediate(op);
+        let result = blockwise(op, &a, &b, |a, b| {
+            let a = (a >> (64 * (selector & 1))) as u64;
+            let b = (b >> (64 * (selector >> 4 & 1))) as u64;
+            carryless_multiply(a, b)
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// SHA1RNDS4: four rounds on A, B, C and D in the destination, with the message words in
+    /// the source, the first of them with E added. imm8 picks the round function and constant
+    pub(crate) fn sha1rnds4(&mut self, op: &X86Opcode) -> Execution {
+        let function = immediate(op);
+        let constant = SHA1_K[function as usize & 3];
+        self.sha(op, |state, words| {
+            let [d, c, b, a] = state;
+            let (mut a, mut b, mut c, mut d, mut e) = (a, b, c, d, 0u32);
+            for word in words.into_iter().rev() {
+                let next = sha1_function(function, b, c, d)
+                    .wrapping_add(a.rotate_left(5))
+                    .wrapping_add(word)
+                    .wrapping_add(e)
+                    .wrapping_add(constant);
+                (a, b, c, d, e) = (next, a, b.rotate_left(30), c, d);
+            }
+            [d, c, b, a]
+        })
+    }
+
+    /// SHA1NEXTE: E for the next four rounds, A of the destination rotated by 30, added to the
+    /// first message word of the source
+    pub(crate) fn sha1nexte(&mut self, op: &X86Opcode) -> Execution {
+        self.sha(op, |state, [w3, w2, w1, w0]| [w3, w2, w1, w0.wrapping_add(state[3].rotate_left(30))])
+    }
+
+    /// SHA1MSG1: the first XORs of the next four message words, from the previous 16
+    pub(crate) fn sha1msg1(&mut self, op: &X86Opcode) -> Execution {
+        self.sha(op, |[w3, w2, w1, w0], [_, _, w5, w4]| [w3 ^ w5, w2 ^ w4, w1 ^ w3, w0 ^ w2])
+    }
+
+    /// SHA1MSG2: the next four message words from the partial ones and the previous four
+    pub(crate) fn sha1msg2(&mut self, op: &X86Opcode) -> Execution {
+        self.sha(op, |partial, [w15, w14, w13, _]| {
+            let w16 = (partial[3] ^ w13).rotate_left(1);
+            let w17 = (partial[2] ^ w14).rotate_left(1);
+            let w18 = (partial[1] ^ w15).rotate_left(1);
+            let w19 = (partial[0] ^ w16).rotate_left(1);
+            [w19, w18, w17, w16]
+        })
+    }
+
+    /// SHA256RNDS2: two rounds, with C, D, G and H in the destination, A, B, E and F in the
+    /// source and the message words plus round constants in the low half of XMM0. The
+    /// destination gets the new A, B, E and F
+    pub(crate) fn sha256rnds2(&mut self, op: &X86Opcode) -> Execution {
+        let [wk0, wk1, ..] = dwords(self.xmm(0));
+        self.sha(op, |[h, g, d, c], [f, e, b, a]| {
+            let (mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h) = (a, b, c, d, e, f, g, h);
+            for wk in [wk0, wk1] {
+                let choose = (e & f) ^ (!e & g);
+                let majority = (a & b) ^ (a & c) ^ (b & c);
+                let sum1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+                let sum0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+                let t1 = h.wrapping_add(sum1).wrapping_add(choose).wrapping_add(wk);
+                (h, g, f, e) = (g, f, e, t1.wrapping_add(d));
+                (d, c, b, a) = (c, b, a, t1.wrapping_add(sum0).wrapping_add(majority));
+            }
+            [f, e, b, a]
+        })
+    }
+
+    /// SHA256MSG1: W(t-16) + σ0(W(t-15)) for the next four message words
+    pub(crate) fn sha256msg1(&mut self, op: &X86Opcode) -> Execution {
+        self.sha(op, |[w0, w1, w2, w3], [w4, ..]| {
+            [w0.wrapping_add(sigma0(w1)), w1.wrapping_add(sigma0(w2)), w2.wrapping_add(sigma0(w3)), w3.wrapping_add(sigma0(w4))]
+        })
+    }
+
+    /// SHA256MSG2: the next four message words, adding σ1 of the two before each to the
+    /// partial sums in the destination
+    pub(crate) fn sha256msg2(&mut self, op: &X86Opcode) -> Execution {
+        self.sha(op, |partial, [_, _, w14, w15]| {
+            let w16 = partial[0].wrapping_add(sigma1(w14));
+            let w17 = partial[1].wrapping_add(sigma1(w15));
+            let w18 = partial[2].wrapping_add(sigma1(w16));
+            let w19 = partial[3].wrapping_add(sigma1(w17));
+            [w16, w17, w18, w19]
+        })
+    }
+
+    /// `f` on the doublewords of the destination and source, into the destination
+    fn sha(&mut self, op: &X86Opcode, f: impl FnOnce([u32; 4], [u32; 4]) -> [u32; 4]) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let result = f(dwords(a[0]), dwords(b[0]));
+        self.write_vector(op, &op.operands[0], from_dwords(result), false)
+    }
+}
@@ -0,0 +1,42 @@
+//! Data movement: MOV, MOVZX, MOVSX(D), LEA and XLATB
+use crate::execute::operands::operand_size;
+use crate::execute::{sign_extend, unsupported, Execution};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{MemoryOperand, Operand, Register, X86Opcode};
+
+impl X86Machine {
+    /// MOV and MOVZX, operands are read zero extended
+    pub(crate) fn mov(&mut self, op: &X86Opcode) -> Execution {
+        let value = self.read_operand(op, &op.operands[1])?;
+        self.write_operand(op, &op.operands[0], value)
+    }
+
+    pub(crate) fn movsx(&mut self, op: &X86Opcode) -> Execution {
+        let value = self.read_operand(op, &op.operands[1])?;
+        let value = sign_extend(value, operand_size(&op.operands[1]));
+        self.write_operand(op, &op.operands[0], value)
+    }
+
+    pub(crate) fn lea(&mut self, op: &X86Opcode) -> Execution {
+        let Operand::Memory(memory) = op.operands[1] else {
+            return Err(unsupported(op));
+        };
+        let address = self.address(op, &memory);
+        self.write_operand(op, &op.operands[0], address)
+    }
+
+    /// XLATB: AL becomes the byte at RBX + AL (EBX + AL with a 32-bit address size)
+    pub(crate) fn xlatb(&mut self, op: &X86Opcode) -> Execution {
+        let table = MemoryOperand {
+            segment: op.prefixes.segment,
+            base: Some(if op.address_size == 4 { Register::EBX } else { Register::RBX }),
+            index: Some(Register::AL),
+            scale: 1,
+            displacement: 0,
+            size: 1,
+        };
+        let value = self.read_operand(op, &Operand::Memory(table))?;
+        self.set_register(Register::AL, value);
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+//! MMX
+//!
+//! MM0 to MM7 are not registers of their own but the low 64 bits, the significand, of the
+//! physical x87 registers in `Fpu::registers`. The integer instructions share the handlers of
+//! their SSE forms, which read and write an MMX operand as the low 64 bits of a vector.
+//!
+//! Every instruction with an MMX register operand first raises any pending #MF, then puts the
+//! x87 stack in MMX mode: TOP is zeroed and every register tagged valid. Writing an MMX register
+//! sets the sign and exponent of its x87 register to all ones, so x87 code reads it as a NaN.
+//! EMMS tags the registers empty again, which is how MMX code hands the stack back to x87 code.
+use crate::execute::operands::lane;
+use crate::execute::Execution;
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{Operand, Register, X86Opcode};
+
+/// Sign and exponent of an x87 register after an MMX write
+const MMX_EXPONENT: u128 = 0xFFFF << 64;
+
+/// Whether an instruction touches an MMX register, and so makes the transition to MMX mode.
+/// CVTPI2PS and CVTPI2PD from memory do not
+pub(crate) fn is_mmx(op: &X86Opcode) -> bool {
+    op.operands.iter().any(|operand| matches!(operand, Operand::Register(Register::Mmx(_))))
+}
+
+impl X86Machine {
+    /// Value of MMX register `n`, the significand of physical x87 register `n`
+    pub fn mm(&self, n: u8) -> u64 {
+        self.fpu_register(n) as u64
+    }
+
+    /// Writes MMX register `n`, with the sign and exponent of the x87 register all ones
+    pub fn set_mm(&mut self, n: u8, value: u64) {
+        self.set_fpu_register(n, MMX_EXPONENT | value as u128);
+    }
+
+    /// The transition to MMX mode, made before the instruction runs
+    pub(crate) fn enter_mmx(&mut self) -> Execution {
+        self.fwait()?;
+        self.set_fpu_top(0);
+        self.set_fpu_tag_word(0);
+        Ok(())
+    }
+
+    /// EMMS: every x87 register tagged empty
+    pub(crate) fn emms(&mut self) -> Execution {
+        self.fwait()?;
+        self.set_fpu_tag_word(0xFFFF);
+        Ok(())
+    }
+
+    /// MASKMOVQ and MASKMOVDQU: the bytes of the first operand whose byte in the second has its
+    /// top bit set, stored at DS:RDI (EDI under a 32-bit address size). The others are not
+    /// touched, and cannot fault
+    pub(crate) fn masked_store(&mut self, op: &X86Opcode) -> Execution {
+        let value = self.read_vector(op, &op.operands[0], false)?;
+        let mask = self.read_vector(op, &op.operands[1], false)?;
+        let destination = match op.address_size {
+            4 => Register::EDI,
+            _ => Register::RDI,
+        };
+        let address = match op.prefixes.segment {
+            Some(segment @ (Register::FS | Register::GS)) => self.register(destination).wrapping_add(self.segment_base(segment)),
+            _ => self.register(destination),
+        };
+        let size = match op.operands[0] {
+            Operand::Register(Register::Mmx(_)) => 8,
+            _ => 16,
+        };
+        for i in (0..size).filter(|&i| lane(mask, i, 1) & 0x80 != 0) {
+            self.store(address.wrapping_add(i as u64), 1, lane(value, i, 1) as u64)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+//! MPX
+//!
+//! BND0 to BND3 each hold a lower bound in their low 64 bits and the one's complement of an
+//! upper bound in the high 64, so their initial state, all zeros, bounds the whole address
+//! space. BNDMK makes bounds, BNDCL, BNDCU and BNDCN check an address against them and raise
+//! #BR when it is outside, and BNDMOV copies them between registers and memory.
+//!
+//! BNDLDX and BNDSTX keep the bounds of pointers stored in memory in a two level structure,
+//! walked much like a page table. The bounds directory is at the base address in BNDCFGU. Bits
+//! 47:20 of the address a pointer is stored at select one of its 8 byte entries, which points
+//! at a bounds table, and bits 19:3 a 32 byte entry of that table: the lower bound, the upper
+//! bound, the pointer value the bounds belong to and a reserved quadword. A directory entry
+//! without its valid bit is #BR, with the address of the entry in BNDSTATUS.
+//!
+//! MPX is on when XCR0 enables both of its state components and BNDCFGU.En is set. There are
+//! no MSRs, so BNDCFGU configures it at CPL 0 too, in place of BNDCFGS. Otherwise the
+//! instructions are the hint NOPs processors without MPX see. While it is on a near CALL, RET,
+//! JMP or Jcc without a BND (F2) prefix puts BND0-3 back in their initial state, unless
+//! BNDCFGU.BNDPRESERVE is set, so code built without MPX is not checked against stale bounds.
+use crate::execute::xsave::MPX_STATE;
+use crate::execute::{unsupported, Exception, Execution, Trap};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{MemoryOperand, Operand, Register, X86Opcode};
+
+/// BNDCFGU.En
+const ENABLE: u64 = 1 << 0;
+/// BNDCFGU.BNDPRESERVE
+const PRESERVE: u64 = 1 << 1;
+/// BNDCFGU bits 63:12, the base address of the bounds directory
+const DIRECTORY: u64 = !0xFFF;
+/// Bounds directory entry bit 0, set when it points at a bounds table
+const VALID: u64 = 1 << 0;
+/// BNDSTATUS error codes: a bounds check failed, or BNDLDX / BNDSTX met an invalid directory
+/// entry
+const OUT_OF_BOUNDS: u64 = 1;
+const INVALID_ENTRY: u64 = 2;
+
+/// What BNDCL, BNDCU and BNDCN compare an address with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BoundCheck {
+    /// #BR below the lower bound
+    Lower,
+    /// #BR above the upper bound
+    Upper,
+    /// #BR above the upper bound as it is stored, without taking its one's complement
+    UpperRaw,
+}
+
+fn lower(bounds: u128) -> u64 {
+    bounds as u64
+}
+
+/// The upper bound as it is stored, in one's complement
+fn upper(bounds: u128) -> u64 {
+    (bounds >> 64) as u64
+}
+
+fn bounds(lower: u64, upper: u64) -> u128 {
+    (upper as u128) << 64 | lower as u128
+}
+
+fn bound_register(op: &X86Opcode, operand: &Operand) -> Result<u8, Trap> {
+    match *operand {
+        Operand::Register(Register::Bound(n)) => Ok(n),
+        _ => Err(unsupported(op)),
+    }
+}
+
+/// The memory operand of BNDMK, BNDLDX or BNDSTX, #UD if it is RIP relative
+fn memory(op: &X86Opcode) -> Result<MemoryOperand, Exception> {
+    match op.memory_operand() {
+        Some(memory) if !matches!(memory.base, Some(Register::Rip | Register::Eip)) => Ok(*memory),
+        _ => Err(Exception::InvalidOpcode),
+    }
+}
+
+impl X86Machine {
+    /// Bounds register `n`: the lower bound in bits 63:0, and the upper bound in one's
+    /// complement in bits 127:64
+    pub fn bnd(&self, n: u8) -> u128 {
+        let start = n as usize * 16;
+        u128::from_le_bytes(self.bounds_registers.0[start..start + 16].try_into().expect("16 bytes"))
+    }
+
+    pub fn set_bnd(&mut self, n: u8, value: u128) {
+        let start = n as usize * 16;
+        self.bounds_registers.0[start..start + 16].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// BNDCFGU: bit 0 enables MPX, bit 1 keeps the bounds registers across legacy branches and
+    /// bits 63:12 are the base address of the bounds directory
+    pub fn bndcfgu(&self) -> u64 {
+        u64::from_le_bytes(self.bound_config_registers.0[..8].try_into().expect("8 bytes"))
+    }
+
+    pub fn set_bndcfgu(&mut self, value: u64) {
+        self.bound_config_registers.0[..8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// BNDSTATUS: why the last #BR an MPX instruction raised was raised
+    pub fn bndstatus(&self) -> u64 {
+        u64::from_le_bytes(self.bound_config_registers.0[8..].try_into().expect("8 bytes"))
+    }
+
+    pub fn set_bndstatus(&mut self, value: u64) {
+        self.bound_config_registers.0[8..].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn mpx_enabled(&self) -> bool {
+        self.xcr0() & MPX_STATE == MPX_STATE && self.bndcfgu() & ENABLE != 0
+    }
+
+    /// Puts BND0-3 in their initial state after a near branch without a BND prefix, if MPX is
+    /// on and BNDPRESERVE is clear
+    pub(crate) fn legacy_branch(&mut self, op: &X86Opcode) {
+        if self.mpx_enabled() && self.bndcfgu() & PRESERVE == 0 && !op.has_bnd_prefix() {
+            (0..4).for_each(|n| self.set_bnd(n, 0));
+        }
+    }
+
+    /// BNDMK: the base register of the operand as the lower bound, and the address it computes
+    /// as the upper
+    pub(crate) fn bndmk(&mut self, op: &X86Opcode) -> Execution {
+        if !self.mpx_enabled() {
+            return Ok(());
+        }
+        let memory = memory(op)?;
+        let n = bound_register(op, &op.operands[0])?;
+        let lower = memory.base.map_or(0, |base| self.register(base));
+        let upper = self.address(op, &memory);
+        self.set_bnd(n, bounds(lower, !upper));
+        Ok(())
+    }
+
+    /// BNDCL, BNDCU and BNDCN: a register, or the address a memory operand computes without
+    /// accessing it, against the bounds. #BR with BNDSTATUS 1 when it is outside
+    pub(crate) fn bound_check(&mut self, op: &X86Opcode, check: BoundCheck) -> Execution {
+        if !self.mpx_enabled() {
+            return Ok(());
+        }
+        let bounds = self.bnd(bound_register(op, &op.operands[0])?);
+        let address = match op.operands[1] {
+            Operand::Memory(memory) => self.address(op, &memory),
+            ref operand => self.read_operand(op, operand)?,
+        };
+        let outside = match check {
+            BoundCheck::Lower => address < lower(bounds),
+            BoundCheck::Upper => address > !upper(bounds),
+            BoundCheck::UpperRaw => address > upper(bounds),
+        };
+        if outside {
+            self.set_bndstatus(OUT_OF_BOUNDS);
+            return Err(Exception::BoundRange.into());
+        }
+        Ok(())
+    }
+
+    /// BNDMOV: both bounds, between bounds registers or to and from 16 bytes of memory
+    pub(crate) fn bndmov(&mut self, op: &X86Opcode) -> Execution {
+        if !self.mpx_enabled() {
+            return Ok(());
+        }
+        let value = match op.operands[1] {
+            ref operand @ Operand::Memory(_) => self.read_vector(op, operand, false)?,
+            ref operand => self.bnd(bound_register(op, operand)?),
+        };
+        match op.operands[0] {
+            ref operand @ Operand::Memory(_) => self.write_vector(op, operand, value, false),
+            ref operand => {
+                self.set_bnd(bound_register(op, operand)?, value);
+                Ok(())
+            }
+        }
+    }
+
+    /// BNDLDX: the bounds from the table entry for the pointer stored at base + displacement,
+    /// if the entry is for the pointer value in the index register. Otherwise the bounds are
+    /// put in their initial state
+    pub(crate) fn bndldx(&mut self, op: &X86Opcode) -> Execution {
+        if !self.mpx_enabled() {
+            return Ok(());
+        }
+        let n = bound_register(op, &op.operands[0])?;
+        let (entry, pointer) = self.bounds_table_entry(op)?;
+        let value = match self.load(entry + 16, 8)? == pointer {
+            true => u128::from_le_bytes(self.load_bytes(entry, 16)?.try_into().expect("16 bytes")),
+            false => 0,
+        };
+        self.set_bnd(n, value);
+        Ok(())
+    }
+
+    /// BNDSTX: the bounds and the pointer value in the index register, to the table entry for
+    /// the pointer stored at base + displacement
+    pub(crate) fn bndstx(&mut self, op: &X86Opcode) -> Execution {
+        if !self.mpx_enabled() {
+            return Ok(());
+        }
+        let value = self.bnd(bound_register(op, &op.operands[1])?);
+        let (entry, pointer) = self.bounds_table_entry(op)?;
+        let bytes = [value.to_le_bytes().as_slice(), &pointer.to_le_bytes()].concat();
+        Ok(self.store_bytes(entry, &bytes)?)
+    }
+
+    /// Walks the bounds directory for the operand of BNDLDX or BNDSTX, giving the address of the
+    /// bounds table entry along with the pointer value, zero without an index register. #BR if
+    /// the directory entry is not valid
+    fn bounds_table_entry(&mut self, op: &X86Opcode) -> Result<(u64, u64), Trap> {
+        let memory = memory(op)?;
+        let address = self.effective_address(op, &MemoryOperand { index: None, ..memory });
+        let pointer = memory.index.map_or(0, |index| self.register(index));
+        let directory_entry = (self.bndcfgu() & DIRECTORY).wrapping_add((address >> 20 & 0xFFF_FFFF) << 3);
+        let table = self.load(directory_entry, 8)?;
+        if table & VALID == 0 {
+            self.set_bndstatus(directory_entry | INVALID_ENTRY);
+            return Err(Exception::BoundRange.into());
+        }
+        let entry = (table & !0b111).wrapping_add((address >> 3 & 0x1_FFFF) << 5);
+        Ok((entry, pointer))
+    }
+}
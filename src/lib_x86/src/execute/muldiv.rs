@@ -0,0 +1,153 @@
+//! Multiplication and division: MUL, IMUL, DIV, IDIV and the BMI2 flagless MULX, along with
+//! CBW / CWD and their wider forms, which sign extend the accumulator ahead of them
+//!
+//! The one operand forms work on the accumulator pair for the operand size: AH:AL, DX:AX,
+//! EDX:EAX or RDX:RAX. A zero divisor or a quotient that does not fit raises #DE.
+use crate::execute::operands::{mask, operand_size};
+use crate::execute::{sign_extend, Exception, Execution};
+use crate::flags::{AsRFlags, RFlags, ARITHMETIC_FLAGS};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{Register, X86Opcode};
+
+/// Low and high halves of the accumulator pair
+fn accumulator(size: usize) -> (Register, Register) {
+    match size {
+        1 => (Register::AL, Register::AH),
+        2 => (Register::AX, Register::DX),
+        4 => (Register::EAX, Register::EDX),
+        _ => (Register::RAX, Register::RDX),
+    }
+}
+
+/// Sign extends the low `size` bytes of `value` to 128 bits
+fn signed(value: u64, size: usize) -> i128 {
+    sign_extend(value, size) as i64 as i128
+}
+
+impl X86Machine {
+    fn read_accumulator(&self, size: usize) -> (u64, u64) {
+        let (low, high) = accumulator(size);
+        (self.register(low), self.register(high))
+    }
+
+    fn write_accumulator(&mut self, size: usize, low: u64, high: u64) {
+        let (low_register, high_register) = accumulator(size);
+        self.set_register(low_register, low & mask(size));
+        self.set_register(high_register, high & mask(size));
+    }
+
+    /// CF and OF say whether the upper half of the product is needed. The rest are undefined,
+    /// Intel hardware takes SF and PF from the low half and clears ZF and AF
+    fn multiply_flags(&mut self, low: u64, overflow: bool, size: usize) {
+        let mut flags = 0;
+        if overflow {
+            flags |= RFlags::Carry as u64 | RFlags::Overflow as u64;
+        }
+        if low >> (size * 8 - 1) & 1 != 0 {
+            flags |= RFlags::Sign as u64;
+        }
+        if (low as u8).count_ones().is_multiple_of(2) {
+            flags |= RFlags::Parity as u64;
+        }
+        self.flags.update(ARITHMETIC_FLAGS, flags);
+    }
+
+    /// MUL: unsigned accumulator times the operand
+    pub(crate) fn mul(&mut self, op: &X86Opcode) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let bits = size * 8;
+        let source = self.read_operand(op, &op.operands[0])? & mask(size);
+        let (a, _) = self.read_accumulator(size);
+        let product = a as u128 * source as u128;
+        let (low, high) = (product as u64 & mask(size), (product >> bits) as u64);
+        self.write_accumulator(size, low, high);
+        self.multiply_flags(low, high != 0, size);
+        Ok(())
+    }
+
+    /// IMUL, all three forms
+    pub(crate) fn imul(&mut self, op: &X86Opcode) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let bits = size * 8;
+        let (a, b) = match op.operands.len() {
+            1 => (self.read_accumulator(size).0, self.read_operand(op, &op.operands[0])?),
+            2 => (self.read_operand(op, &op.operands[0])?, self.read_operand(op, &op.operands[1])?),
+            _ => (self.read_operand(op, &op.operands[1])?, self.read_operand(op, &op.operands[2])?),
+        };
+        let product = signed(a, size) * signed(b, size);
+        let low = product as u64 & mask(size);
+        let overflow = product != signed(low, size);
+        match op.operands.len() {
+            1 => self.write_accumulator(size, low, (product >> bits) as u64),
+            _ => self.write_operand(op, &op.operands[0], low)?,
+        }
+        self.multiply_flags(low, overflow, size);
+        Ok(())
+    }
+
+    /// DIV: unsigned accumulator pair divided by the operand, quotient in the low half and
+    /// remainder in the high half
+    pub(crate) fn div(&mut self, op: &X86Opcode) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let bits = size * 8;
+        let divisor = self.read_operand(op, &op.operands[0])? & mask(size);
+        if divisor == 0 {
+            return Err(Exception::DivideError.into());
+        }
+        let (low, high) = self.read_accumulator(size);
+        let dividend = (high as u128) << bits | low as u128;
+        let quotient = dividend / divisor as u128;
+        if quotient > mask(size) as u128 {
+            return Err(Exception::DivideError.into());
+        }
+        let remainder = (dividend % divisor as u128) as u64;
+        self.write_accumulator(size, quotient as u64, remainder);
+        Ok(())
+    }
+
+    /// IDIV: like DIV but signed, with the quotient rounded toward zero
+    pub(crate) fn idiv(&mut self, op: &X86Opcode) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let bits = size * 8;
+        let divisor = signed(self.read_operand(op, &op.operands[0])?, size);
+        let (low, high) = self.read_accumulator(size);
+        let dividend = ((high as u128) << bits | low as u128) as i128;
+        // the pair is twice the operand size, sign extend it from there
+        let dividend = dividend << (128 - 2 * bits) >> (128 - 2 * bits);
+        let quotient = dividend.checked_div(divisor).ok_or(Exception::DivideError)?;
+        if quotient != signed(quotient as u64, size) {
+            return Err(Exception::DivideError.into());
+        }
+        let remainder = dividend % divisor;
+        self.write_accumulator(size, quotient as u64, remainder as u64);
+        Ok(())
+    }
+
+    /// MULX: unsigned EDX / RDX times the third operand, high half to the first operand and
+    /// low half to the second. When both name the same register it gets the high half
+    pub(crate) fn mulx(&mut self, op: &X86Opcode) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let multiplicand = self.read_accumulator(size).1;
+        let source = self.read_operand(op, &op.operands[2])? & mask(size);
+        let product = multiplicand as u128 * source as u128;
+        self.write_operand(op, &op.operands[1], product as u64 & mask(size))?;
+        self.write_operand(op, &op.operands[0], (product >> (size * 8)) as u64)
+    }
+
+    /// CBW, CWDE and CDQE: the low half of the accumulator sign extended into all of it
+    pub(crate) fn extend_accumulator(&mut self, op: &X86Opcode) -> Execution {
+        let size = op.operand_size as usize;
+        let half = self.register(accumulator(size / 2).0);
+        self.set_register(accumulator(size).0, sign_extend(half, size / 2) & mask(size));
+        Ok(())
+    }
+
+    /// CWD, CDQ and CQO: DX, EDX or RDX filled with the sign of the accumulator
+    pub(crate) fn extend_into_high(&mut self, op: &X86Opcode) -> Execution {
+        let size = op.operand_size as usize;
+        let (low, _) = self.read_accumulator(size);
+        let high = (sign_extend(low, size) as i64 >> 63) as u64;
+        self.write_accumulator(size, low, high);
+        Ok(())
+    }
+}
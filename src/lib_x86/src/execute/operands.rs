@@ -0,0 +1,445 @@
+//! Register, memory and operand access for instruction implementations
+use crate::execute::atomic::LockedAccess;
+use crate::execute::avx512::WriteMask;
+use crate::execute::{unsupported, Exception, Trap};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{MemoryOperand, Operand, Register, X86Opcode};
+
+/// Sign extends the low `size` bytes of `value` to 64 bits
+pub(crate) fn sign_extend(value: u64, size: usize) -> u64 {
+    let shift = 64 - size * 8;
+    (((value << shift) as i64) >> shift) as u64
+}
+
+/// All ones in the low `size` bytes
+pub(crate) fn mask(size: usize) -> u64 {
+    match size {
+        8 => u64::MAX,
+        _ => (1 << (size * 8)) - 1,
+    }
+}
+
+/// All ones in the low `size` bytes of a vector
+pub(crate) fn lane_mask(size: usize) -> u128 {
+    match size {
+        16 => u128::MAX,
+        _ => (1 << (size * 8)) - 1,
+    }
+}
+
+/// Lane `index` of a vector of `size` byte lanes
+pub(crate) fn lane(value: u128, index: usize, size: usize) -> u128 {
+    value >> (index * size * 8) & lane_mask(size)
+}
+
+pub(crate) fn with_lane(value: u128, index: usize, size: usize, lane: u128) -> u128 {
+    let shift = index * size * 8;
+    value & !(lane_mask(size) << shift) | (lane & lane_mask(size)) << shift
+}
+
+/// 128-bit blocks in a ZMM register
+pub(crate) const BLOCKS: usize = 4;
+
+/// A vector register or operand as 128-bit blocks, lowest first. Blocks past the width of the
+/// operand are zero
+pub(crate) type Vector = [u128; BLOCKS];
+
+/// Element `index` of `size` bytes, counting across the blocks of a vector
+pub(crate) fn element(vector: &Vector, index: usize, size: usize) -> u128 {
+    let per_block = 16 / size;
+    lane(vector[index / per_block], index % per_block, size)
+}
+
+pub(crate) fn set_element(vector: &mut Vector, index: usize, size: usize, value: u128) {
+    let per_block = 16 / size;
+    let block = &mut vector[index / per_block];
+    *block = with_lane(*block, index % per_block, size, value);
+}
+
+/// Blocks the vector length of an instruction covers, 1 for legacy SSE
+pub(crate) fn blocks(op: &X86Opcode) -> usize {
+    (op.vector_length as usize / 16).min(BLOCKS)
+}
+
+/// `f` on each pair of blocks within the vector length of `op`, the rest zero. Most AVX
+/// instructions work within 128-bit blocks like this rather than across the whole register
+pub(crate) fn blockwise(op: &X86Opcode, a: &Vector, b: &Vector, mut f: impl FnMut(u128, u128) -> u128) -> Vector {
+    let mut result = [0; BLOCKS];
+    for i in 0..blocks(op) {
+        result[i] = f(a[i], b[i]);
+    }
+    result
+}
+
+/// The two source operands of a vector instruction: VEX.vvvv and ModR/M.rm after the
+/// destination of a VEX or EVEX encoding that has both, otherwise the destination and source
+pub(crate) fn sources(op: &X86Opcode) -> (&Operand, &Operand) {
+    match op.vex.is_some() && op.operands.len() >= 3 && !matches!(op.operands[2], Operand::Immediate(_)) {
+        true => (&op.operands[1], &op.operands[2]),
+        false => (&op.operands[0], &op.operands[1]),
+    }
+}
+
+/// The imm8 of a vector instruction, which is always its last operand
+pub(crate) fn immediate(op: &X86Opcode) -> u64 {
+    match op.operands.last() {
+        Some(Operand::Immediate(immediate)) => immediate.value,
+        _ => 0,
+    }
+}
+
+impl X86Machine {
+    /// Value of a general purpose register (or RIP), zero extended
+    ///
+    /// Panics for any other register
+    pub fn register(&self, register: Register) -> u64 {
+        let bytes = &self.gp_registers.0;
+        let (start, size) = match register {
+            Register::Gpr8High(n) => (n as usize * 8 + 1, 1),
+            Register::Gpr8(n) | Register::Gpr16(n) | Register::Gpr32(n) | Register::Gpr64(n) => {
+                (n as usize * 8, register.size() as usize)
+            }
+            Register::Rip => return self.instruction_counter,
+            Register::Eip => return self.instruction_counter & mask(4),
+            _ => panic!("{register} is not a general purpose register"),
+        };
+        let mut value = [0; 8];
+        value[..size].copy_from_slice(&bytes[start..start + size]);
+        u64::from_le_bytes(value)
+    }
+
+    /// Writes a general purpose register (or RIP). Only the bits of the register are used, and
+    /// as on hardware writing a 32-bit register clears the upper half of the 64-bit one
+    ///
+    /// Panics for any other register
+    pub fn set_register(&mut self, register: Register, value: u64) {
+        let bytes = &mut self.gp_registers.0;
+        let (start, size) = match register {
+            Register::Gpr8High(n) => (n as usize * 8 + 1, 1),
+            Register::Gpr32(n) => (n as usize * 8, 8),
+            Register::Gpr8(n) | Register::Gpr16(n) | Register::Gpr64(n) => {
+                (n as usize * 8, register.size() as usize)
+            }
+            Register::Rip | Register::Eip => {
+                self.instruction_counter = value & mask(register.size() as usize);
+                return;
+            }
+            _ => panic!("{register} is not a general purpose register"),
+        };
+        let value = match register {
+            Register::Gpr32(_) => value & mask(4),
+            _ => value,
+        };
+        bytes[start..start + size].copy_from_slice(&value.to_le_bytes()[..size]);
+    }
+
+    /// Base address of a segment register. Only FS and GS have one in 64-bit mode
+    pub fn segment_base(&self, segment: Register) -> u64 {
+        let start = segment.number() as usize * 8;
+        u64::from_le_bytes(self.segment_pointers.0[start..start + 8].try_into().expect("8 bytes"))
+    }
+
+    pub fn set_segment_base(&mut self, segment: Register, base: u64) {
+        let start = segment.number() as usize * 8;
+        self.segment_pointers.0[start..start + 8].copy_from_slice(&base.to_le_bytes());
+    }
+
+    /// Value of XMM register `n`, the low quarter of ZMM register `n`
+    pub fn xmm(&self, n: u8) -> u128 {
+        self.vector(n)[0]
+    }
+
+    /// Writes XMM register `n`, leaving the rest of the ZMM register as it is, which is what
+    /// legacy SSE instructions do
+    pub fn set_xmm(&mut self, n: u8, value: u128) {
+        let mut vector = self.vector(n);
+        vector[0] = value;
+        self.set_vector(n, vector);
+    }
+
+    /// Value of YMM register `n`, low half first
+    pub fn ymm(&self, n: u8) -> [u128; 2] {
+        let vector = self.vector(n);
+        [vector[0], vector[1]]
+    }
+
+    /// Writes YMM register `n`, leaving the upper half of the ZMM register as it is
+    pub fn set_ymm(&mut self, n: u8, value: [u128; 2]) {
+        let mut vector = self.vector(n);
+        vector[..2].copy_from_slice(&value);
+        self.set_vector(n, vector);
+    }
+
+    /// Value of ZMM register `n`, lowest block first
+    pub fn zmm(&self, n: u8) -> [u128; 4] {
+        self.vector(n)
+    }
+
+    pub fn set_zmm(&mut self, n: u8, value: [u128; 4]) {
+        self.set_vector(n, value);
+    }
+
+    /// Value of opmask register `n`, k0 to k7
+    pub fn opmask(&self, n: u8) -> u64 {
+        let start = n as usize * 8;
+        u64::from_le_bytes(self.opmask_registers.0[start..start + 8].try_into().expect("8 bytes"))
+    }
+
+    pub fn set_opmask(&mut self, n: u8, value: u64) {
+        let start = n as usize * 8;
+        self.opmask_registers.0[start..start + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn vector(&self, n: u8) -> Vector {
+        let start = n as usize * BLOCKS * 16;
+        let bytes = &self.vector_registers.0[start..start + BLOCKS * 16];
+        core::array::from_fn(|i| u128::from_le_bytes(bytes[i * 16..i * 16 + 16].try_into().expect("16 bytes")))
+    }
+
+    pub(crate) fn set_vector(&mut self, n: u8, value: Vector) {
+        let start = n as usize * BLOCKS * 16;
+        for (i, block) in value.iter().enumerate() {
+            let offset = start + i * 16;
+            self.vector_registers.0[offset..offset + 16].copy_from_slice(&block.to_le_bytes());
+        }
+    }
+
+    pub fn mxcsr(&self) -> u32 {
+        u32::from_le_bytes(self.mxcsr_register.0)
+    }
+
+    /// Sets MXCSR as it is, without the check LDMXCSR makes for reserved bits
+    pub fn set_mxcsr(&mut self, value: u32) {
+        self.mxcsr_register.0 = value.to_le_bytes();
+    }
+
+    /// `base + index * scale + displacement` of a memory operand, without any segment base,
+    /// which is what `lea` computes
+    pub(crate) fn address(&self, op: &X86Opcode, memory: &MemoryOperand) -> u64 {
+        let mut address = memory.displacement as u64;
+        if let Some(base) = memory.base {
+            let base = match base {
+                Register::Rip | Register::Eip => op.next_ip(),
+                base => self.register(base),
+            };
+            address = address.wrapping_add(base);
+        }
+        if let Some(index) = memory.index {
+            address = address.wrapping_add(self.register(index).wrapping_mul(memory.scale as u64));
+        }
+        match op.address_size {
+            8 => address,
+            size => address & mask(size as usize),
+        }
+    }
+
+    /// Linear address a memory operand accesses
+    pub(crate) fn effective_address(&self, op: &X86Opcode, memory: &MemoryOperand) -> u64 {
+        let address = self.address(op, memory);
+        match memory.segment {
+            Some(segment @ (Register::FS | Register::GS)) => address.wrapping_add(self.segment_base(segment)),
+            _ => address,
+        }
+    }
+
+    /// A copy of `size` bytes of guest memory at `address`
+    pub(crate) fn load_bytes(&self, address: u64, size: usize) -> Result<Vec<u8>, Exception> {
+        usize::try_from(address)
+            .ok()
+            .and_then(|start| self.memory.read(start, size).ok())
+            .ok_or(Exception::PageFault(address))
+    }
+
+    pub(crate) fn store_bytes(&mut self, address: u64, bytes: &[u8]) -> Result<(), Exception> {
+        let start = usize::try_from(address).map_err(|_| Exception::PageFault(address))?;
+        self.memory
+            .write(start, bytes)
+            .map_err(|_| Exception::PageFault(address))
+    }
+
+    /// Little endian value of up to 8 bytes of guest memory
+    pub(crate) fn load(&self, address: u64, size: usize) -> Result<u64, Exception> {
+        let value = usize::try_from(address)
+            .ok()
+            .and_then(|start| self.memory.load(start, size).ok())
+            .ok_or(Exception::PageFault(address))?;
+        if self.locked_access.get() == Some(LockedAccess::Armed) {
+            self.locked_access.set(Some(LockedAccess::Read { address, size, value }));
+        }
+        Ok(value)
+    }
+
+    /// Stores the low `size` bytes of `value`. For a locked instruction, a store to what it
+    /// read only happens if that is still there, see `locked`
+    pub(crate) fn store(&mut self, address: u64, size: usize, value: u64) -> Result<(), Exception> {
+        let start = usize::try_from(address).map_err(|_| Exception::PageFault(address))?;
+        match self.locked_access.get() {
+            Some(LockedAccess::Read { address: read, size: read_size, value: current }) if (read, read_size) == (address, size) => {
+                let exchanged = self.memory.compare_exchange(start, size, current, value);
+                let access = match exchanged.map_err(|_| Exception::PageFault(address))? {
+                    Ok(_) => LockedAccess::Done,
+                    Err(_) => LockedAccess::Lost,
+                };
+                self.locked_access.set(Some(access));
+                Ok(())
+            }
+            _ => self.memory.store(start, size, value).map_err(|_| Exception::PageFault(address)),
+        }
+    }
+
+    /// Value of a register, memory or immediate operand, zero extended
+    pub(crate) fn read_operand(&self, op: &X86Opcode, operand: &Operand) -> Result<u64, Trap> {
+        match *operand {
+            Operand::Register(register) if register.is_gpr() => Ok(self.register(register)),
+            Operand::Memory(memory) if memory.size <= 8 => {
+                Ok(self.load(self.effective_address(op, &memory), memory.size as usize)?)
+            }
+            Operand::Immediate(immediate) => Ok(immediate.value),
+            Operand::Branch(target) => Ok(target),
+            _ => Err(unsupported(op)),
+        }
+    }
+
+    /// Writes a register or memory operand, truncating `value` to its size
+    pub(crate) fn write_operand(&mut self, op: &X86Opcode, operand: &Operand, value: u64) -> Result<(), Trap> {
+        match *operand {
+            Operand::Register(register) if register.is_gpr() => {
+                self.set_register(register, value);
+                Ok(())
+            }
+            Operand::Memory(memory) if memory.size <= 8 => {
+                let address = self.effective_address(op, &memory);
+                Ok(self.store(address, memory.size as usize, value)?)
+            }
+            _ => Err(unsupported(op)),
+        }
+    }
+
+    /// Value of an XMM register or up to 16 bytes of memory, zero extended. An `aligned` 16
+    /// byte memory operand that is not on a 16 byte boundary is #GP. General purpose registers
+    /// and immediates are read as for `read_operand`, for the instructions that mix them in
+    pub(crate) fn read_vector(&self, op: &X86Opcode, operand: &Operand, aligned: bool) -> Result<u128, Trap> {
+        Ok(self.read_blocks(op, operand, aligned)?[0])
+    }
+
+    /// Writes a whole XMM register, or the low bytes of `value` to a memory operand or general
+    /// purpose register. See `write_blocks` for what happens to the rest of the YMM register
+    pub(crate) fn write_vector(&mut self, op: &X86Opcode, operand: &Operand, value: u128, aligned: bool) -> Result<(), Trap> {
+        let mut blocks = [0; BLOCKS];
+        blocks[0] = value;
+        self.write_blocks(op, operand, blocks, aligned)
+    }
+
+    /// The two sources of a vector instruction (see `sources`). Legacy SSE encodings need a
+    /// full width memory operand to be aligned, VEX and EVEX ones do not
+    pub(crate) fn read_sources(&self, op: &X86Opcode) -> Result<(Vector, Vector), Trap> {
+        let (a, b) = sources(op);
+        let aligned = op.vex.is_none();
+        Ok((self.read_blocks(op, a, aligned)?, self.read_blocks(op, b, aligned)?))
+    }
+
+    /// As `read_vector`, for operands as wide as a ZMM register. An `aligned` memory operand
+    /// has to be aligned to its size. An EVEX embedded broadcast repeats its one element
+    /// across the vector length, and a masked EVEX load does not fault on the elements the
+    /// mask leaves out. An opmask register reads as elements of all ones where its bits are set
+    pub(crate) fn read_blocks(&self, op: &X86Opcode, operand: &Operand, aligned: bool) -> Result<Vector, Trap> {
+        let mut blocks = [0; BLOCKS];
+        match *operand {
+            Operand::Register(Register::Xmm(n) | Register::Ymm(n) | Register::Zmm(n)) => {
+                let width = operand_size(operand) / 16;
+                blocks[..width].copy_from_slice(&self.vector(n)[..width]);
+            }
+            Operand::Register(Register::Opmask(n)) => blocks = self.opmask_lanes(op, n),
+            Operand::Register(Register::Mmx(n)) => blocks[0] = self.mm(n) as u128,
+            Operand::Register(_) | Operand::Immediate(_) => blocks[0] = self.read_operand(op, operand)? as u128,
+            Operand::Memory(memory) if op.broadcast => {
+                let size = memory.size as usize;
+                let value = self.load_bytes(self.effective_address(op, &memory), size)?;
+                let mut element = [0; 16];
+                element[..size].copy_from_slice(&value);
+                for i in 0..op.vector_length as usize / size {
+                    set_element(&mut blocks, i, size, u128::from_le_bytes(element));
+                }
+            }
+            Operand::Memory(memory) if memory.size as usize <= BLOCKS * 16 => {
+                let address = self.vector_address(op, &memory, aligned)?;
+                let size = memory.size as usize;
+                let mut value = [0; BLOCKS * 16];
+                match (self.load_bytes(address, size), self.write_mask(op)) {
+                    (Ok(bytes), _) => value[..size].copy_from_slice(&bytes),
+                    (Err(_), Some(mask)) => {
+                        for i in (0..size / mask.size).filter(|&i| mask.selected(i)) {
+                            let at = i * mask.size;
+                            value[at..at + mask.size].copy_from_slice(&self.load_bytes(address + at as u64, mask.size)?);
+                        }
+                    }
+                    (Err(exception), None) => return Err(exception.into()),
+                }
+                for (i, block) in blocks.iter_mut().enumerate() {
+                    *block = u128::from_le_bytes(value[i * 16..i * 16 + 16].try_into().expect("16 bytes"));
+                }
+            }
+            _ => return Err(unsupported(op)),
+        }
+        Ok(blocks)
+    }
+
+    /// Writes a vector register, or the low bytes of `value` to a memory operand or general
+    /// purpose register. A legacy SSE encoding writing an XMM register leaves the rest of the
+    /// ZMM register alone, a VEX or EVEX encoding clears everything past the destination.
+    /// EVEX masking keeps or clears the elements the mask leaves out, and a masked store does
+    /// not touch them. An opmask destination gets the top bit of each element, and an MMX one
+    /// the low 64 bits
+    pub(crate) fn write_blocks(&mut self, op: &X86Opcode, operand: &Operand, value: Vector, aligned: bool) -> Result<(), Trap> {
+        match *operand {
+            Operand::Register(Register::Xmm(n)) if op.vex.is_none() => self.set_xmm(n, value[0]),
+            Operand::Register(Register::Xmm(n) | Register::Ymm(n) | Register::Zmm(n)) => {
+                let value = match self.write_mask(op) {
+                    Some(mask) => mask.merge(value, self.vector(n)),
+                    None => value,
+                };
+                let width = operand_size(operand) / 16;
+                let mut blocks = [0; BLOCKS];
+                blocks[..width].copy_from_slice(&value[..width]);
+                self.set_vector(n, blocks);
+            }
+            Operand::Register(Register::Opmask(n)) => self.set_opmask_lanes(op, n, value),
+            Operand::Register(Register::Mmx(n)) => self.set_mm(n, value[0] as u64),
+            Operand::Register(register) if register.is_gpr() => self.set_register(register, value[0] as u64),
+            Operand::Memory(memory) if memory.size as usize <= BLOCKS * 16 => {
+                let address = self.vector_address(op, &memory, aligned)?;
+                let bytes: Vec<u8> = value.iter().flat_map(|block| block.to_le_bytes()).collect();
+                let size = memory.size as usize;
+                match self.write_mask(op) {
+                    Some(mask) => {
+                        for i in (0..size / mask.size).filter(|&i| mask.selected(i)) {
+                            let at = i * mask.size;
+                            self.store_bytes(address + at as u64, &bytes[at..at + mask.size])?;
+                        }
+                    }
+                    None => self.store_bytes(address, &bytes[..size])?,
+                }
+            }
+            _ => return Err(unsupported(op)),
+        }
+        Ok(())
+    }
+
+    fn vector_address(&self, op: &X86Opcode, memory: &MemoryOperand, aligned: bool) -> Result<u64, Exception> {
+        let address = self.effective_address(op, memory);
+        match aligned && memory.size >= 16 && !address.is_multiple_of(memory.size as u64) {
+            true => Err(Exception::GeneralProtection),
+            false => Ok(address),
+        }
+    }
+}
+
+/// Width in bytes of a register, memory or immediate operand
+pub(crate) fn operand_size(operand: &Operand) -> usize {
+    match operand {
+        Operand::Register(register) => register.size() as usize,
+        Operand::Memory(memory) => memory.size as usize,
+        Operand::Immediate(immediate) => immediate.size as usize,
+        Operand::Branch(_) | Operand::FarPointer { .. } => 8,
+    }
+}
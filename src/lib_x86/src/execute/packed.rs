@@ -0,0 +1,560 @@
+//! Packed integer instructions on the XMM registers, SSE2 through SSE4.2: arithmetic,
+//! comparisons, shifts, shuffles, packs and unpacks, blends, inserts and extracts, and the
+//! PCMPxSTRx string comparisons. The float shuffles, blends and unpacks share the code, as they
+//! only move lanes around
+//!
+//! Lanes are handled as `u64`s of the lane size, so that an operation is written once for
+//! bytes, words, doublewords and quadwords. Nothing here touches MXCSR.
+//!
+//! The VEX forms run on the YMM registers. AVX2 does almost all of these within each 128-bit
+//! block, unpacks, packs and shuffles included, so most of them are written for a block and
+//! run over the blocks of the vector length.
+//!
+//! The MMX forms have a single 8 byte block, in the low half of a 16 byte one with the rest zero.
+//! Lanewise operations need nothing more, those that move lanes across the block ask `width`.
+use crate::execute::mmx::is_mmx;
+use crate::execute::operands::{
+    blocks, blockwise, element, immediate, lane, mask, operand_size, set_element, sign_extend, sources, with_lane, BLOCKS,
+};
+use crate::execute::shift::{flag, Shift};
+use crate::execute::Execution;
+use crate::flags::{RFlags, ARITHMETIC_FLAGS};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{Operand, Register, X86Opcode};
+
+/// Bytes in an XMM register
+const WIDTH: usize = 16;
+
+/// Bytes in a block of an instruction's operands, 8 for MMX
+fn width(op: &X86Opcode) -> usize {
+    match is_mmx(op) {
+        true => 8,
+        false => WIDTH,
+    }
+}
+
+/// An operation on each pair of lanes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Lanewise {
+    Add,
+    Sub,
+    AddSaturate { signed: bool },
+    SubSaturate { signed: bool },
+    /// all ones where the lanes are equal
+    Equal,
+    /// all ones where the first is greater, signed
+    Greater,
+    Min { signed: bool },
+    Max { signed: bool },
+    /// PAVG, unsigned and rounded up
+    Average,
+    MulLow,
+    MulHigh { signed: bool },
+    /// PMULHRSW, the signed product rounded to its bits 30:15
+    MulHighRound,
+    /// PSIGN, the first negated, zeroed or kept by the sign of the second
+    Sign,
+    /// PABS of the second
+    Abs,
+    /// VPSLLV, VPSRLV and VPSRAV, the first shifted by the second. A count past the end of
+    /// the lane clears it, or fills it with the sign for an arithmetic shift. VPROLV and
+    /// VPRORV rotate by the count modulo the lane width
+    Shift(Shift),
+    /// VPLZCNT of the second
+    LeadingZeros,
+}
+
+impl Lanewise {
+    fn apply(self, a: u64, b: u64, size: usize) -> u64 {
+        let bits = size * 8;
+        let (x, y) = (sign_extend(a, size) as i64, sign_extend(b, size) as i64);
+        let (low, high) = (i64::MIN >> (64 - bits), i64::MAX >> (64 - bits));
+        let all = mask(size);
+        let result = match self {
+            Lanewise::Add => a.wrapping_add(b),
+            Lanewise::Sub => a.wrapping_sub(b),
+            Lanewise::AddSaturate { signed: true } => (x + y).clamp(low, high) as u64,
+            Lanewise::AddSaturate { signed: false } => (a + b).min(all),
+            Lanewise::SubSaturate { signed: true } => (x - y).clamp(low, high) as u64,
+            Lanewise::SubSaturate { signed: false } => a.saturating_sub(b),
+            Lanewise::Equal => all * (a == b) as u64,
+            Lanewise::Greater => all * (x > y) as u64,
+            Lanewise::Min { signed: true } => x.min(y) as u64,
+            Lanewise::Min { signed: false } => a.min(b),
+            Lanewise::Max { signed: true } => x.max(y) as u64,
+            Lanewise::Max { signed: false } => a.max(b),
+            Lanewise::Average => (a + b + 1) >> 1,
+            Lanewise::MulLow => a.wrapping_mul(b),
+            Lanewise::MulHigh { signed: true } => ((x * y) >> bits) as u64,
+            Lanewise::MulHigh { signed: false } => (a * b) >> bits,
+            Lanewise::MulHighRound => ((((x * y) >> 14) + 1) >> 1) as u64,
+            Lanewise::Sign => match y.signum() {
+                -1 => a.wrapping_neg(),
+                0 => 0,
+                _ => a,
+            },
+            Lanewise::Abs => y.unsigned_abs(),
+            Lanewise::Shift(shift) => match (shift, b < bits as u64) {
+                (Shift::Shl, true) => a << b,
+                (Shift::Shr, true) => a >> b,
+                (Shift::Sar, _) => (x >> b.min(bits as u64 - 1)) as u64,
+                (Shift::Rol | Shift::Ror, _) if b.is_multiple_of(bits as u64) => a,
+                (Shift::Rol, _) => a << (b % bits as u64) | a >> (bits as u64 - b % bits as u64),
+                (Shift::Ror, _) => a >> (b % bits as u64) | a << (bits as u64 - b % bits as u64),
+                _ => 0,
+            },
+            Lanewise::LeadingZeros => (b.leading_zeros() as usize - (64 - bits)) as u64,
+        };
+        result & all
+    }
+}
+
+/// Builds a vector of `count` lanes of `size` bytes from a function of the lane index
+fn lanes(count: usize, size: usize, mut value: impl FnMut(usize) -> u128) -> u128 {
+    (0..count).fold(0, |result, i| with_lane(result, i, size, value(i)))
+}
+
+/// Whether the lanes of a block with index `index` of a vector of `count` lanes per block
+/// take the source, by bit `index` of the immediate. Eight bits cover lanes past the first
+/// block for doublewords and quadwords, and are repeated for the words of PBLENDW
+fn selected(control: u128, block: usize, count: usize, index: usize) -> bool {
+    control >> ((block * count + index) % 8) & 1 != 0
+}
+
+impl X86Machine {
+    /// `lanewise` on each pair of `size` byte lanes
+    pub(crate) fn packed(&mut self, op: &X86Opcode, lanewise: Lanewise, size: usize) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let result = blockwise(op, &a, &b, |a, b| {
+            lanes(WIDTH / size, size, |i| lanewise.apply(lane(a, i, size) as u64, lane(b, i, size) as u64, size) as u128)
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// PHADD and PHSUB: `lanewise` on adjacent lanes, of the destination for the low half of
+    /// the result and of the source for the high half
+    pub(crate) fn horizontal(&mut self, op: &X86Opcode, lanewise: Lanewise, size: usize) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let width = width(op);
+        let half = width / size / 2;
+        let result = blockwise(op, &a, &b, |a, b| {
+            lanes(width / size, size, |i| {
+                let source = if i < half { a } else { b };
+                let j = i % half * 2;
+                lanewise.apply(lane(source, j, size) as u64, lane(source, j + 1, size) as u64, size) as u128
+            })
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// PMULUDQ and PMULDQ: the even doublewords multiplied into quadwords
+    pub(crate) fn multiply_wide(&mut self, op: &X86Opcode, signed: bool) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let result = blockwise(op, &a, &b, |a, b| {
+            lanes(2, 8, |i| {
+                let (x, y) = (lane(a, 2 * i, 4) as u64, lane(b, 2 * i, 4) as u64);
+                match signed {
+                    true => (sign_extend(x, 4) as i64 * sign_extend(y, 4) as i64) as u64 as u128,
+                    false => (x * y) as u128,
+                }
+            })
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// PMADDWD (signed words) and PMADDUBSW (unsigned bytes by signed bytes, saturated): adjacent
+    /// products added into lanes twice as wide
+    pub(crate) fn multiply_add(&mut self, op: &X86Opcode, bytes: bool) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let size = if bytes { 1 } else { 2 };
+        let result = blockwise(op, &a, &b, |a, b| {
+            let product = |j: usize| {
+                let x = lane(a, j, size) as u64;
+                let x = if bytes { x as i64 } else { sign_extend(x, size) as i64 };
+                x * sign_extend(lane(b, j, size) as u64, size) as i64
+            };
+            lanes(WIDTH / size / 2, size * 2, |i| {
+                let sum = product(2 * i) + product(2 * i + 1);
+                let sum = match bytes {
+                    true => sum.clamp(i16::MIN as i64, i16::MAX as i64),
+                    false => sum,
+                };
+                sum as u64 as u128
+            })
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// PSADBW: the absolute differences of the bytes of each half, summed into its low word
+    pub(crate) fn sum_absolute_differences(&mut self, op: &X86Opcode) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let result = blockwise(op, &a, &b, |a, b| {
+            lanes(2, 8, |half| {
+                (half * 8..half * 8 + 8)
+                    .map(|j| (lane(a, j, 1) as u64).abs_diff(lane(b, j, 1) as u64))
+                    .sum::<u64>() as u128
+            })
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// MPSADBW: eight sums of the absolute differences between the doubleword of the second
+    /// source bits 1:0 of the immediate pick and the runs of four bytes of the first starting at
+    /// each of the eight bytes from byte 0, or byte 4 when bit 2 is set. VMPSADBW takes the next
+    /// three bits for the high block
+    pub(crate) fn multiple_sums_of_absolute_differences(&mut self, op: &X86Opcode) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let mut control = immediate(op) as usize;
+        let result = blockwise(op, &a, &b, |a, b| {
+            let (source, destination) = ((control & 3) * 4, (control >> 2 & 1) * 4);
+            control >>= 3;
+            lanes(8, 2, |i| {
+                (0..4)
+                    .map(|j| (lane(a, destination + i + j, 1) as u64).abs_diff(lane(b, source + j, 1) as u64))
+                    .sum::<u64>() as u128
+            })
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// PHMINPOSUW: the smallest unsigned word in the low word and its index in the next,
+    /// the rest cleared
+    pub(crate) fn minimum_position(&mut self, op: &X86Opcode) -> Execution {
+        let source = self.read_vector(op, &op.operands[1], op.vex.is_none())?;
+        let (index, minimum) = (0..8).map(|i| (i, lane(source, i, 2))).min_by_key(|&(i, word)| (word, i)).unwrap_or_default();
+        self.write_vector(op, &op.operands[0], minimum | (index as u128) << 16, false)
+    }
+
+    /// PSLL, PSRL and PSRA by an immediate or by the low quadword of a vector, every lane by
+    /// the same count, and the EVEX VPROL and VPROR by an immediate. The value is the second to last operand, as the VEX immediate forms
+    /// have the destination in VEX.vvvv
+    pub(crate) fn shift_lanes(&mut self, op: &X86Opcode, shift: Shift, size: usize) -> Execution {
+        let last = op.operands.len() - 1;
+        let aligned = op.vex.is_none();
+        let value = self.read_blocks(op, &op.operands[last - 1], aligned)?;
+        let count = self.read_vector(op, &op.operands[last], aligned)? as u64;
+        let result = blockwise(op, &value, &value, |a, _| {
+            lanes(WIDTH / size, size, |i| Lanewise::Shift(shift).apply(lane(a, i, size) as u64, count, size) as u128)
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// PSLLDQ and PSRLDQ, by whole bytes within each block
+    pub(crate) fn shift_bytes(&mut self, op: &X86Opcode, left: bool) -> Execution {
+        let value = self.read_blocks(op, &op.operands[op.operands.len() - 2], false)?;
+        let count = immediate(op);
+        let result = blockwise(op, &value, &value, |a, _| match (count, left) {
+            (16.., _) => 0,
+            (_, true) => a << (count * 8),
+            (_, false) => a >> (count * 8),
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// PACKSS and PACKUS: the signed `size` byte lanes of the destination and then the source,
+    /// saturated to half their width as signed or unsigned
+    pub(crate) fn pack(&mut self, op: &X86Opcode, size: usize, signed: bool) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let half = size / 2;
+        let (low, high) = match signed {
+            true => (i64::MIN >> (64 - half * 8), i64::MAX >> (64 - half * 8)),
+            false => (0, mask(half) as i64),
+        };
+        let count = width(op) / size;
+        let result = blockwise(op, &a, &b, |a, b| {
+            lanes(2 * count, half, |i| {
+                let source = if i < count { a } else { b };
+                let value = sign_extend(lane(source, i % count, size) as u64, size) as i64;
+                value.clamp(low, high) as u64 as u128
+            })
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// PUNPCKL and PUNPCKH, and UNPCKLPS and friends: the lanes of the low or high halves of
+    /// the destination and source, interleaved
+    pub(crate) fn unpack(&mut self, op: &X86Opcode, size: usize, high: bool) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let width = width(op);
+        let base = if high { width / size / 2 } else { 0 };
+        let result = blockwise(op, &a, &b, |a, b| {
+            lanes(width / size, size, |i| {
+                let source = if i % 2 == 0 { a } else { b };
+                lane(source, base + i / 2, size)
+            })
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// PSHUFB: each byte of the source picks a byte of the destination, or zero when its top
+    /// bit is set
+    pub(crate) fn shuffle_bytes(&mut self, op: &X86Opcode) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let width = width(op);
+        let result = blockwise(op, &a, &b, |a, b| {
+            lanes(width, 1, |i| {
+                let selector = lane(b, i, 1) as usize;
+                match selector & 0x80 {
+                    0 => lane(a, selector % width, 1),
+                    _ => 0,
+                }
+            })
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// PSHUFD, PSHUFLW and PSHUFHW: the four `size` byte lanes from `first` each take the lane
+    /// of the source a two bit field of the immediate picks, the others are copied
+    pub(crate) fn shuffle(&mut self, op: &X86Opcode, size: usize, first: usize) -> Execution {
+        let source = self.read_blocks(op, &op.operands[1], op.vex.is_none())?;
+        let control = immediate(op) as usize;
+        let result = blockwise(op, &source, &source, |source, _| {
+            lanes(WIDTH / size, size, |i| match i.checked_sub(first) {
+                Some(j @ 0..4) => lane(source, first + (control >> (2 * j) & 3), size),
+                _ => lane(source, i, size),
+            })
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// MOVSLDUP, MOVSHDUP and MOVDDUP: the even lanes of the source, or the `odd` ones, each
+    /// copied to the other lane of its pair
+    pub(crate) fn duplicate(&mut self, op: &X86Opcode, size: usize, odd: bool) -> Execution {
+        let source = self.read_blocks(op, &op.operands[1], op.vex.is_none())?;
+        let result = blockwise(op, &source, &source, |source, _| {
+            lanes(WIDTH / size, size, |i| lane(source, i & !1 | odd as usize, size))
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// SHUFPS and SHUFPD: the low half of each block picked from the first source and the
+    /// high half from the second, by consecutive fields of the immediate. VSHUFPS uses the
+    /// same fields for every block, VSHUFPD carries on into the next bits
+    pub(crate) fn shuffle_float(&mut self, op: &X86Opcode, size: usize) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let count = WIDTH / size;
+        let bits = count.trailing_zeros() as usize;
+        let mut control = immediate(op) as usize;
+        let result = blockwise(op, &a, &b, |a, b| {
+            let block = lanes(count, size, |i| {
+                let source = if i < count / 2 { a } else { b };
+                lane(source, control >> (i * bits) & (count - 1), size)
+            });
+            control >>= (count * bits) % 8;
+            block
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// PALIGNR: in each block, the first source above the second, shifted right by the
+    /// immediate in bytes. The two MMX operands fit in a block together
+    pub(crate) fn align(&mut self, op: &X86Opcode) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let shift = immediate(op) as u32 * 8;
+        let mmx = is_mmx(op);
+        let result = blockwise(op, &a, &b, |a, b| match shift {
+            _ if mmx => (a << 64 | b).checked_shr(shift).unwrap_or(0),
+            0 => b,
+            1..128 => b >> shift | a << (128 - shift),
+            128..256 => a >> (shift - 128),
+            _ => 0,
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// PMOVZX and PMOVSX: the low lanes of the source widened from `from` to `to` bytes, across
+    /// the whole vector
+    pub(crate) fn extend(&mut self, op: &X86Opcode, from: usize, to: usize, signed: bool) -> Execution {
+        let source = self.read_vector(op, &op.operands[1], false)?;
+        let mut result = [0; BLOCKS];
+        for i in 0..blocks(op) * WIDTH / to {
+            let value = lane(source, i, from) as u64;
+            let value = match signed {
+                true => sign_extend(value, from) as u128,
+                false => value as u128,
+            };
+            set_element(&mut result, i, to, value);
+        }
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// PTEST: ZF when the AND of the operands is zero and CF when the source AND NOT the
+    /// destination is, the other flags are cleared. VTESTPS and VTESTPD (`signs` the lane
+    /// size) only look at the sign bit of each lane
+    pub(crate) fn ptest(&mut self, op: &X86Opcode, signs: Option<usize>) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let bits = match signs {
+            Some(size) => lanes(WIDTH / size, size, |_| 1 << (size * 8 - 1)),
+            None => u128::MAX,
+        };
+        let both = blockwise(op, &a, &b, |a, b| a & b & bits);
+        let source_only = blockwise(op, &a, &b, |a, b| !a & b & bits);
+        let flags = flag(both.iter().all(|&block| block == 0), RFlags::Zero)
+            | flag(source_only.iter().all(|&block| block == 0), RFlags::Carry);
+        self.flags.update(ARITHMETIC_FLAGS, flags);
+        Ok(())
+    }
+
+    /// PBLENDW, BLENDPS, BLENDPD and VPBLENDD take the lanes of the second source the bits of
+    /// the immediate pick, PBLENDVB, BLENDVPS and BLENDVPD those where the lane of a mask has
+    /// its top bit set. The mask is XMM0 for the legacy forms and the fourth operand for VEX
+    pub(crate) fn blend(&mut self, op: &X86Opcode, size: usize, variable: bool) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let count = WIDTH / size;
+        let mask = match (variable, op.vex) {
+            (true, Some(_)) => self.read_blocks(op, &op.operands[3], false)?,
+            (true, None) => [self.xmm(0); BLOCKS],
+            (false, _) => [0; BLOCKS],
+        };
+        let control = immediate(op) as u128;
+        let mut block = 0;
+        let result = blockwise(op, &a, &b, |a, b| {
+            let take = |i: usize| match variable {
+                true => lane(mask[block], i, size) >> (size * 8 - 1) != 0,
+                false => selected(control, block, count, i),
+            };
+            let result = lanes(count, size, |i| match take(i) {
+                false => lane(a, i, size),
+                true => lane(b, i, size),
+            });
+            block += 1;
+            result
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// PEXTRB, PEXTRW, PEXTRD and PEXTRQ, zero extended into a register, and EXTRACTPS, which
+    /// is PEXTRD for a single
+    pub(crate) fn extract(&mut self, op: &X86Opcode, size: usize) -> Execution {
+        let source = self.read_vector(op, &op.operands[1], false)?;
+        let index = immediate(op) as usize % (width(op) / size);
+        self.write_vector(op, &op.operands[0], lane(source, index, size), false)
+    }
+
+    /// PINSRB, PINSRW, PINSRD and PINSRQ. The VEX forms take the rest of the lanes from
+    /// VEX.vvvv
+    pub(crate) fn insert(&mut self, op: &X86Opcode, size: usize) -> Execution {
+        let last = op.operands.len() - 1;
+        let destination = self.read_vector(op, &op.operands[last - 2], false)?;
+        let value = self.read_vector(op, &op.operands[last - 1], false)?;
+        let index = immediate(op) as usize % (width(op) / size);
+        self.write_vector(op, &op.operands[0], with_lane(destination, index, size, value), false)
+    }
+
+    /// INSERTPS: a single of the source, the lane bits 7:6 of the immediate pick from a
+    /// register or the one in memory, into the lane bits 5:4 pick. The lanes the low four bits
+    /// pick are then cleared. The VEX form takes the rest of the lanes from VEX.vvvv
+    pub(crate) fn insert_float(&mut self, op: &X86Opcode) -> Execution {
+        let (base, source) = sources(op);
+        let control = immediate(op) as usize;
+        let value = match source {
+            Operand::Memory(_) => self.read_vector(op, source, false)?,
+            _ => lane(self.read_vector(op, source, false)?, control >> 6, 4),
+        };
+        let inserted = with_lane(self.read_vector(op, base, false)?, control >> 4 & 3, 4, value);
+        let result = lanes(4, 4, |i| match control >> i & 1 {
+            0 => lane(inserted, i, 4),
+            _ => 0,
+        });
+        self.write_vector(op, &op.operands[0], result, false)
+    }
+
+    /// PMOVMSKB, MOVMSKPS and MOVMSKPD: the top bit of each lane across the vector, into a
+    /// register
+    pub(crate) fn move_mask(&mut self, op: &X86Opcode, size: usize) -> Execution {
+        let source = self.read_blocks(op, &op.operands[1], false)?;
+        let bits = (0..blocks(op) * WIDTH / size)
+            .fold(0, |bits, i| bits | (element(&source, i, size) >> (size * 8 - 1)) << i);
+        self.write_operand(op, &op.operands[0], bits as u64)
+    }
+
+    /// MOVD and MOVQ between XMM registers, general purpose registers and memory. Into an XMM
+    /// register the rest of it is cleared
+    pub(crate) fn move_low(&mut self, op: &X86Opcode) -> Execution {
+        let size = match (op.operands[0], op.operands[1]) {
+            (Operand::Register(Register::Xmm(_)), Operand::Register(Register::Xmm(_))) => 8,
+            (Operand::Register(Register::Xmm(_)), source) => operand_size(&source),
+            (destination, _) => operand_size(&destination),
+        };
+        let value = lane(self.read_vector(op, &op.operands[1], false)?, 0, size);
+        self.write_vector(op, &op.operands[0], value, false)
+    }
+
+    /// PCMPESTRI, PCMPESTRM, PCMPISTRI and PCMPISTRM. The immediate says whether the elements
+    /// are signed or unsigned bytes or words, how the destination (a set, ranges or a
+    /// substring) is compared with the source, whether to negate the result, and which index
+    /// or what kind of mask to give. The explicit forms take the lengths from EAX and EDX
+    /// (RAX and RDX with REX.W), the implicit ones stop at the first zero element
+    pub(crate) fn compare_strings(&mut self, op: &X86Opcode, explicit: bool, index: bool) -> Execution {
+        let a = self.read_vector(op, &op.operands[0], false)?;
+        let b = self.read_vector(op, &op.operands[1], false)?;
+        let control = self.read_operand(op, &op.operands[2])?;
+        let size = if control & 1 != 0 { 2 } else { 1 };
+        let count = WIDTH / size;
+        let element = |value: u128, i: usize| match control & 2 {
+            0 => lane(value, i, size) as i64,
+            _ => sign_extend(lane(value, i, size) as u64, size) as i64,
+        };
+        let length = |value: u128, register: u8| match explicit {
+            true => {
+                let length = match op.operand_size {
+                    8 => self.register(Register::Gpr64(register)) as i64,
+                    _ => sign_extend(self.register(Register::Gpr32(register)), 4) as i64,
+                };
+                length.unsigned_abs().min(count as u64) as usize
+            }
+            false => (0..count).find(|&i| lane(value, i, size) == 0).unwrap_or(count),
+        };
+        let (length_a, length_b) = (length(a, 0), length(b, 2));
+
+        // invalid elements compare as the SDM's table has it
+        let matched = |i: usize| match control >> 2 & 3 {
+            // equal any
+            0 => i < length_b && (0..length_a).any(|j| element(a, j) == element(b, i)),
+            // ranges, pairs of bounds in the destination
+            1 => {
+                i < length_b
+                    && (0..length_a.saturating_sub(1))
+                        .step_by(2)
+                        .any(|j| element(a, j) <= element(b, i) && element(b, i) <= element(a, j + 1))
+            }
+            // equal each
+            2 => match (i < length_a, i < length_b) {
+                (true, true) => element(a, i) == element(b, i),
+                (valid_a, valid_b) => !valid_a && !valid_b,
+            },
+            // equal ordered, the destination as a substring starting at each element
+            _ => (0..count - i).all(|j| match (j < length_a, i + j < length_b) {
+                (false, _) => true,
+                (true, false) => false,
+                (true, true) => element(a, j) == element(b, i + j),
+            }),
+        };
+        let matches = (0..count).fold(0u32, |bits, i| bits | (matched(i) as u32) << i);
+        let result = match control >> 4 & 3 {
+            1 => !matches & ((1 << count) - 1),
+            // negated only where the source is valid
+            3 => matches ^ ((1 << length_b) - 1),
+            _ => matches,
+        };
+
+        match (index, control & 0x40 != 0) {
+            (true, most_significant) => {
+                let found = match (result, most_significant) {
+                    (0, _) => count as u32,
+                    (_, false) => result.trailing_zeros(),
+                    (_, true) => 31 - result.leading_zeros(),
+                };
+                self.set_register(Register::ECX, found as u64);
+            }
+            (false, false) => self.set_xmm(0, result as u128),
+            (false, true) => self.set_xmm(0, lanes(count, size, |i| (result >> i & 1) as u128 * u128::MAX)),
+        }
+        let flags = flag(result != 0, RFlags::Carry)
+            | flag(length_b < count, RFlags::Zero)
+            | flag(length_a < count, RFlags::Sign)
+            | flag(result & 1 != 0, RFlags::Overflow);
+        self.flags.update(ARITHMETIC_FLAGS, flags);
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+//! Shifts and rotates: SHL/SAL, SHR, SAR, ROL, ROR, RCL, RCR, SHLD, SHRD, and the BMI2 flagless
+//! SHLX, SHRX, SARX and RORX
+//!
+//! The count is masked to 6 bits for 64-bit operands and 5 bits otherwise, and a masked count
+//! of 0 leaves the flags alone. Where the SDM leaves flags undefined they are set the way Intel
+//! hardware sets them: AF is cleared, and OF for a count above 1 is the OF a shift by 1 would
+//! have given, except that ROL and ROR by an immediate other than 1 leave it alone.
+use crate::execute::operands::{mask, operand_size};
+use crate::execute::{sign_extend, Execution};
+use crate::flags::{AsRFlags, RFlags, ARITHMETIC_FLAGS};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{Operand, X86Opcode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Shift {
+    Shl,
+    Shr,
+    Sar,
+    Rol,
+    Ror,
+    Rcl,
+    Rcr,
+}
+
+/// Masks a shift count the way the hardware does
+fn masked_count(count: u64, size: usize) -> u32 {
+    (count & if size == 8 { 0x3F } else { 0x1F }) as u32
+}
+
+pub(crate) fn flag(set: bool, flag: RFlags) -> u64 {
+    if set { flag as u64 } else { 0 }
+}
+
+/// ZF, SF and PF of a result
+pub(crate) fn result_flags(result: u64, size: usize) -> u64 {
+    flag(result == 0, RFlags::Zero)
+        | flag(msb(result, size), RFlags::Sign)
+        | flag((result as u8).count_ones().is_multiple_of(2), RFlags::Parity)
+}
+
+/// Top bit of a `size` byte value
+pub(crate) fn msb(value: u64, size: usize) -> bool {
+    value >> (size * 8 - 1) & 1 != 0
+}
+
+/// Second to top bit of a `size` byte value
+fn msb2(value: u64, size: usize) -> bool {
+    value >> (size * 8 - 2) & 1 != 0
+}
+
+/// Rotates the low `size` bytes of `value` left by `n` < `size * 8`
+fn rotate_left(value: u64, n: u32, size: usize) -> u64 {
+    match n {
+        0 => value,
+        n => ((value << n) | (value >> (size as u32 * 8 - n))) & mask(size),
+    }
+}
+
+const CARRY_OVERFLOW: u64 = RFlags::Carry as u64 | RFlags::Overflow as u64;
+
+impl Shift {
+    /// Result and the flags in `affected` for a non zero masked count
+    fn apply(self, value: u64, count: u32, carry: bool, size: usize) -> (u64, u64, u64) {
+        let bits = size as u32 * 8;
+        match self {
+            Shift::Shl => {
+                let wide = (value as u128) << count;
+                let result = wide as u64 & mask(size);
+                let cf = (wide >> bits) & 1 != 0;
+                let flags = result_flags(result, size)
+                    | flag(cf, RFlags::Carry)
+                    | flag(msb(value, size) ^ msb2(value, size), RFlags::Overflow);
+                (result, flags, ARITHMETIC_FLAGS)
+            }
+            Shift::Shr => {
+                let result = value.checked_shr(count).unwrap_or(0);
+                let cf = value.checked_shr(count - 1).unwrap_or(0) & 1 != 0;
+                let flags = result_flags(result, size)
+                    | flag(cf, RFlags::Carry)
+                    | flag(msb(value, size), RFlags::Overflow);
+                (result, flags, ARITHMETIC_FLAGS)
+            }
+            Shift::Sar => {
+                let signed = sign_extend(value, size) as i64;
+                let result = (signed >> count.min(63)) as u64 & mask(size);
+                let cf = (signed >> (count - 1).min(63)) & 1 != 0;
+                let flags = result_flags(result, size) | flag(cf, RFlags::Carry);
+                (result, flags, ARITHMETIC_FLAGS)
+            }
+            Shift::Rol => {
+                let result = rotate_left(value, count % bits, size);
+                let cf = result & 1 != 0;
+                let of = msb(value, size) ^ msb2(value, size);
+                let flags = flag(cf, RFlags::Carry) | flag(of, RFlags::Overflow);
+                (result, flags, CARRY_OVERFLOW)
+            }
+            Shift::Ror => {
+                let result = rotate_left(value, (bits - count % bits) % bits, size);
+                let flags = flag(msb(result, size), RFlags::Carry)
+                    | flag(msb(value, size) ^ (value & 1 != 0), RFlags::Overflow);
+                (result, flags, CARRY_OVERFLOW)
+            }
+            Shift::Rcl | Shift::Rcr => {
+                // rotate the `bits + 1` wide value carry:operand
+                let width = bits + 1;
+                let n = count % width;
+                if n == 0 {
+                    // a whole turn, which the hardware treats like a zero count
+                    return (value, 0, 0);
+                }
+                let n = if self == Shift::Rcr { (width - n) % width } else { n };
+                let wide = ((carry as u128) << bits) | value as u128;
+                let rotated = ((wide << n) | (wide >> (width - n))) & ((1u128 << width) - 1);
+                let result = rotated as u64 & mask(size);
+                let cf = rotated >> bits != 0;
+                let of = match self {
+                    Shift::Rcl => msb(value, size) ^ msb2(value, size),
+                    _ => msb(value, size) ^ carry,
+                };
+                let flags = flag(cf, RFlags::Carry) | flag(of, RFlags::Overflow);
+                (result, flags, CARRY_OVERFLOW)
+            }
+        }
+    }
+}
+
+impl X86Machine {
+    pub(crate) fn shift(&mut self, op: &X86Opcode, shift: Shift) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let value = self.read_operand(op, &op.operands[0])?;
+        let count = masked_count(self.read_operand(op, &op.operands[1])?, size);
+        if count == 0 {
+            // the destination is still written, which matters for the upper half of 64-bit
+            // registers
+            return self.write_operand(op, &op.operands[0], value);
+        }
+        let carry = self.flags.is_set(RFlags::Carry);
+        let (result, flags, mut affected) = shift.apply(value, count, carry, size);
+        let immediate = matches!(op.operands[1], Operand::Immediate(_));
+        if matches!(shift, Shift::Rol | Shift::Ror) && immediate && count != 1 {
+            affected &= !(RFlags::Overflow as u64);
+        }
+        self.write_operand(op, &op.operands[0], result)?;
+        self.flags.update(affected, flags);
+        Ok(())
+    }
+
+    /// SHLD and SHRD, shifting bits of the second operand into the first
+    pub(crate) fn double_shift(&mut self, op: &X86Opcode, left: bool) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let bits = size as u32 * 8;
+        let dest = self.read_operand(op, &op.operands[0])?;
+        let source = self.read_operand(op, &op.operands[1])?;
+        let count = masked_count(self.read_operand(op, &op.operands[2])?, size);
+        if count == 0 {
+            return self.write_operand(op, &op.operands[0], dest);
+        }
+
+        // 16-bit counts can reach past the source, hardware carries on into the destination
+        // again: dest:source:dest. Wider operands only ever need two
+        let (dest_wide, source_wide) = (dest as u128, source as u128);
+        let (pattern, width) = match (size, left) {
+            (2, _) => (dest_wide << 32 | source_wide << 16 | dest_wide, 48),
+            (_, true) => (dest_wide << bits | source_wide, 2 * bits),
+            (_, false) => (source_wide << bits | dest_wide, 2 * bits),
+        };
+        let (result, cf, of) = match left {
+            true => (
+                (pattern << count >> (width - bits)) as u64,
+                pattern >> (width - count) & 1 != 0,
+                msb(dest, size) ^ msb2(dest, size),
+            ),
+            false => (
+                (pattern >> count) as u64,
+                pattern >> (count - 1) & 1 != 0,
+                msb(dest, size) ^ (source & 1 != 0),
+            ),
+        };
+        let result = result & mask(size);
+        let flags = result_flags(result, size) | flag(cf, RFlags::Carry) | flag(of, RFlags::Overflow);
+        self.write_operand(op, &op.operands[0], result)?;
+        self.flags.update(ARITHMETIC_FLAGS, flags);
+        Ok(())
+    }
+
+    /// SHLX, SHRX and SARX: no flags, and the count comes from a register
+    pub(crate) fn shift_flagless(&mut self, op: &X86Opcode, shift: Shift) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let value = self.read_operand(op, &op.operands[1])?;
+        let count = masked_count(self.read_operand(op, &op.operands[2])?, size);
+        let result = match shift {
+            Shift::Shl => value << count,
+            Shift::Shr => value >> count,
+            _ => (sign_extend(value, size) as i64 >> count) as u64,
+        };
+        self.write_operand(op, &op.operands[0], result & mask(size))
+    }
+
+    /// RORX: rotate by an immediate without touching the flags
+    pub(crate) fn rorx(&mut self, op: &X86Opcode) -> Execution {
+        let size = operand_size(&op.operands[0]);
+        let bits = size as u32 * 8;
+        let value = self.read_operand(op, &op.operands[1])?;
+        let count = masked_count(self.read_operand(op, &op.operands[2])?, size);
+        self.write_operand(op, &op.operands[0], rotate_left(value, (bits - count) % bits, size))
+    }
+}
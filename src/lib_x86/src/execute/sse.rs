@@ -0,0 +1,544 @@
+//! SSE through SSE4.1 floating point: moves, arithmetic, comparisons, rounding and conversions
+//! on the XMM registers, the F16C half precision conversions, and the AVX-512 variations on
+//! them (unsigned and quadword conversions, VRNDSCALE, VREDUCE, VSCALEF, VGETEXP, VRCP14 and
+//! VRSQRT14), done in `softfloat` under the rounding mode, DAZ / FTZ and exception masks of
+//! MXCSR. Their VEX forms run the same code over the YMM registers, 128 bits at a time
+//!
+//! Exceptions are gathered across every lane of an instruction. If any of them is unmasked the
+//! instruction raises #XM without writing its destination, otherwise they are ORed into the
+//! MXCSR flags.
+use crate::execute::operands::{
+    blocks, blockwise, element, immediate, lane, operand_size, set_element, sign_extend, sources, with_lane, Vector, BLOCKS,
+};
+use crate::execute::avx512::WriteMask;
+use crate::execute::shift::flag;
+use crate::execute::{Exception, Execution};
+use crate::flags::{RFlags, ARITHMETIC_FLAGS};
+use crate::softfloat::{self, Class, Environment, Format, Rounding, DENORMAL, DIVIDE_BY_ZERO, HALF, INVALID, PRECISION, SINGLE, UNDERFLOW};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{Encoding, Mnemonic, Operand, Register, X86Opcode};
+use std::cmp::Ordering;
+
+/// MXCSR after reset: every exception masked, round to nearest
+pub(crate) const MXCSR_DEFAULT: u32 = 0x1F80;
+/// The MXCSR bits that exist, LDMXCSR of anything else is #GP
+pub(crate) const MXCSR_MASK: u32 = 0xFFFF;
+const DENORMALS_ARE_ZERO: u32 = 1 << 6;
+const MASKS: u32 = 7;
+const ROUNDING_CONTROL: u32 = 13;
+const FLUSH_TO_ZERO: u32 = 1 << 15;
+/// Exceptions found from the operands alone, before there is a result
+const PRECOMPUTATION: u8 = INVALID | DENORMAL | DIVIDE_BY_ZERO;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Arithmetic {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Min,
+    Max,
+    /// of the source, the destination only supplies the lanes a scalar form leaves alone
+    Sqrt,
+    /// the first times two to the power of the second rounded down
+    Scale,
+    /// of the source as `Sqrt`, its exponent
+    Exponent,
+}
+
+impl Arithmetic {
+    pub(crate) fn apply(self, f: Format, a: u128, b: u128, env: &mut Environment) -> u128 {
+        match self {
+            Arithmetic::Add => softfloat::add(f, a, b, env),
+            Arithmetic::Sub => softfloat::sub(f, a, b, env),
+            Arithmetic::Mul => softfloat::mul(f, a, b, env),
+            Arithmetic::Div => softfloat::div(f, a, b, env),
+            Arithmetic::Min => softfloat::select(f, a, b, false, env),
+            Arithmetic::Max => softfloat::select(f, a, b, true, env),
+            Arithmetic::Sqrt => softfloat::sqrt(f, b, env),
+            Arithmetic::Scale => softfloat::scale_by(f, a, b, env),
+            Arithmetic::Exponent => softfloat::logb(f, b, env),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Logic {
+    And,
+    /// `!destination & source`
+    AndNot,
+    Or,
+    Xor,
+}
+
+/// Every lane of the register, or only the lowest with the rest kept from the destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Lanes {
+    Packed,
+    Scalar,
+}
+
+/// What a lane of a conversion holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Element {
+    Float(Format),
+    Int32,
+    Int64,
+    Uint32,
+    Uint64,
+}
+
+impl Element {
+    fn size(self) -> usize {
+        match self {
+            Element::Float(f) => f.bits() as usize / 8,
+            Element::Int32 | Element::Uint32 => 4,
+            Element::Int64 | Element::Uint64 => 8,
+        }
+    }
+
+    /// An integer element to `f`
+    fn to_float(self, f: Format, value: u128, env: &mut Environment) -> u128 {
+        match self {
+            Element::Int32 | Element::Int64 => softfloat::from_int(f, sign_extend(value as u64, self.size()) as i64, env),
+            _ => softfloat::from_uint(f, value as u64, env),
+        }
+    }
+
+    /// `value` of format `f` rounded to an integer element
+    fn round_from(self, f: Format, value: u128, rounding: Rounding, env: &mut Environment) -> u128 {
+        let bits = self.size() as u32 * 8;
+        let value = match self {
+            Element::Int32 | Element::Int64 => softfloat::to_int(f, value, bits, rounding, env),
+            _ => softfloat::to_uint(f, value, bits, rounding, env),
+        };
+        value as u128
+    }
+}
+
+/// CVTPI2PS, CVTPI2PD and the conversions the other way into an MMX register
+fn is_mmx_conversion(op: &X86Opcode) -> bool {
+    use Mnemonic as M;
+    matches!(op.mnemonic, M::Cvtpi2ps | M::Cvtpi2pd | M::Cvtps2pi | M::Cvttps2pi | M::Cvtpd2pi | M::Cvttpd2pi)
+}
+
+/// `f` on each pair of `size` byte lanes of the vector length, or for a scalar instruction on
+/// the lowest lane only, with the rest of the low block of `a` kept. Lanes an EVEX `mask`
+/// leaves out are not computed, so they raise no exceptions
+pub(crate) fn each_lane(
+    op: &X86Opcode,
+    (a, b): (&Vector, &Vector),
+    size: usize,
+    lanes: Lanes,
+    mask: Option<WriteMask>,
+    mut f: impl FnMut(u128, u128) -> u128,
+) -> Vector {
+    let (count, mut result) = match lanes {
+        Lanes::Packed => (blocks(op) * 16 / size, [0; BLOCKS]),
+        Lanes::Scalar => {
+            let mut result = [0; BLOCKS];
+            result[0] = a[0];
+            (1, result)
+        }
+    };
+    for i in (0..count).filter(|&i| mask.is_none_or(|mask| mask.selected(i))) {
+        set_element(&mut result, i, size, f(element(a, i, size), element(b, i, size)));
+    }
+    result
+}
+
+impl X86Machine {
+    /// The `softfloat` environment MXCSR sets up, with the rounding mode an EVEX instruction
+    /// embeds in place of MXCSR.RC
+    pub(crate) fn float_environment(&self, op: &X86Opcode) -> Environment {
+        let mxcsr = self.mxcsr();
+        let rounding = op.rounding.map_or(mxcsr >> ROUNDING_CONTROL, |rounding| rounding as u32);
+        Environment {
+            rounding: Rounding::from_bits(rounding),
+            denormals_are_zero: mxcsr & DENORMALS_ARE_ZERO != 0,
+            flush_to_zero: mxcsr & FLUSH_TO_ZERO != 0,
+            underflow_masked: mxcsr & (UNDERFLOW as u32) << MASKS != 0,
+            precision: None,
+            flags: 0,
+            rounded_up: false,
+        }
+    }
+
+    /// Records the exceptions an instruction raised, #XM if any is unmasked. An unmasked
+    /// invalid, denormal or divide by zero stops the instruction before it has a result, so
+    /// then only those are recorded. An EVEX instruction with embedded rounding or SAE
+    /// suppresses all of them
+    pub(crate) fn float_exceptions(&mut self, op: &X86Opcode, raised: u8) -> Result<(), Exception> {
+        if op.sae || op.rounding.is_some() {
+            return Ok(());
+        }
+        let mxcsr = self.mxcsr();
+        let unmasked = raised & !(mxcsr >> MASKS) as u8;
+        let recorded = match unmasked & PRECOMPUTATION {
+            0 => raised,
+            _ => raised & PRECOMPUTATION,
+        };
+        self.set_mxcsr(mxcsr | recorded as u32);
+        match unmasked {
+            0 => Ok(()),
+            _ => Err(Exception::SimdFloatingPoint),
+        }
+    }
+
+    /// MOVAPS, MOVUPS, MOVAPD and MOVUPD
+    pub(crate) fn move_vector(&mut self, op: &X86Opcode, aligned: bool) -> Execution {
+        let value = self.read_blocks(op, &op.operands[1], aligned)?;
+        self.write_blocks(op, &op.operands[0], value, aligned)
+    }
+
+    /// MOVSS and MOVSD. Between registers only the low lane is copied, a load clears the rest
+    /// of the register and a store writes only the low lane. The VEX register form takes the
+    /// rest of the low lanes from its first source
+    pub(crate) fn move_scalar(&mut self, op: &X86Opcode, size: usize) -> Execution {
+        let (a, b) = sources(op);
+        let value = self.read_vector(op, b, false)?;
+        let value = match (a, b) {
+            (Operand::Register(Register::Xmm(_)), Operand::Register(_)) => with_lane(self.read_vector(op, a, false)?, 0, size, value),
+            _ => value,
+        };
+        self.write_vector(op, &op.operands[0], value, false)
+    }
+
+    /// MOVLPS, MOVHPS, MOVLPD and MOVHPD, and MOVHLPS and MOVLHPS: a quadword into the low
+    /// or `high` half of the destination, from memory or the other half of a register, with
+    /// the other half kept (taken from the first source in the VEX forms). The stores write
+    /// that half of the register to memory
+    pub(crate) fn move_half(&mut self, op: &X86Opcode, high: bool) -> Execution {
+        if let Operand::Memory(_) = op.operands[0] {
+            let value = lane(self.read_vector(op, &op.operands[1], false)?, high as usize, 8);
+            return self.write_vector(op, &op.operands[0], value, false);
+        }
+        let (a, b) = sources(op);
+        let from = match b {
+            Operand::Memory(_) => 0,
+            _ => !high as usize,
+        };
+        let value = lane(self.read_vector(op, b, false)?, from, 8);
+        let value = with_lane(self.read_vector(op, a, false)?, high as usize, 8, value);
+        self.write_vector(op, &op.operands[0], value, false)
+    }
+
+    /// ADD, SUB, MUL, DIV, MIN, MAX and SQRT in their PS, PD, SS and SD forms
+    pub(crate) fn float_arithmetic(&mut self, op: &X86Opcode, arithmetic: Arithmetic, f: Format, lanes: Lanes) -> Execution {
+        let size = f.bits() as usize / 8;
+        let (a, b) = self.read_sources(op)?;
+        let mut env = self.float_environment(op);
+        let result = each_lane(op, (&a, &b), size, lanes, self.write_mask(op), |x, y| arithmetic.apply(f, x, y, &mut env));
+        self.float_exceptions(op, env.flags)?;
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// HADDPS, HADDPD, HSUBPS and HSUBPD: `arithmetic` on adjacent lanes, of the first source
+    /// for the low half of each block and of the second for the high half
+    pub(crate) fn float_horizontal(&mut self, op: &X86Opcode, arithmetic: Arithmetic, f: Format) -> Execution {
+        let size = f.bits() as usize / 8;
+        let half = 16 / size / 2;
+        let (a, b) = self.read_sources(op)?;
+        let mut env = self.float_environment(op);
+        let result = blockwise(op, &a, &b, |a, b| {
+            (0..half * 2).fold(0, |result, i| {
+                let source = if i < half { a } else { b };
+                let j = i % half * 2;
+                with_lane(result, i, size, arithmetic.apply(f, lane(source, j, size), lane(source, j + 1, size), &mut env))
+            })
+        });
+        self.float_exceptions(op, env.flags)?;
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// ADDSUBPS and ADDSUBPD: the even lanes subtracted and the odd ones added
+    pub(crate) fn float_add_subtract(&mut self, op: &X86Opcode, f: Format) -> Execution {
+        let size = f.bits() as usize / 8;
+        let (a, b) = self.read_sources(op)?;
+        let mut env = self.float_environment(op);
+        let mut result = [0; BLOCKS];
+        for i in 0..blocks(op) * 16 / size {
+            let arithmetic = match i % 2 {
+                0 => Arithmetic::Sub,
+                _ => Arithmetic::Add,
+            };
+            set_element(&mut result, i, size, arithmetic.apply(f, element(&a, i, size), element(&b, i, size), &mut env));
+        }
+        self.float_exceptions(op, env.flags)?;
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// ROUNDPS, ROUNDPD, ROUNDSS and ROUNDSD: to an integer in the same format, by the rounding
+    /// mode in the low two bits of the immediate, or by MXCSR when bit 2 is set. Bit 3 keeps an
+    /// inexact result from raising precision. Denormal operands are not reported. The EVEX
+    /// VRNDSCALE forms keep as many fraction bits as the high four bits of the immediate say,
+    /// and VREDUCE (`reduce`) gives what that rounding takes off, +0 for an infinity
+    pub(crate) fn round(&mut self, op: &X86Opcode, f: Format, lanes: Lanes, reduce: bool) -> Execution {
+        let size = f.bits() as usize / 8;
+        let (a, b) = self.read_sources(op)?;
+        let control = immediate(op) as u32;
+        let mut env = self.float_environment(op);
+        if control & 4 == 0 {
+            env.rounding = Rounding::from_bits(control);
+        }
+        let rounding = env.rounding;
+        let bits = match op.encoding {
+            Encoding::Evex => (control >> 4) as i32,
+            _ => 0,
+        };
+        let result = each_lane(op, (&a, &b), size, lanes, self.write_mask(op), |_, y| match reduce {
+            false => softfloat::round_to_fraction(f, y, bits, rounding, &mut env),
+            true if softfloat::classify(f, y) == Class::Infinity => f.zero(false),
+            true => {
+                let rounded = softfloat::round_to_fraction(f, y, bits, rounding, &mut env);
+                softfloat::sub(f, y, rounded, &mut env)
+            }
+        });
+        let suppressed = match control & 8 {
+            0 => DENORMAL,
+            _ => DENORMAL | PRECISION,
+        };
+        self.float_exceptions(op, env.flags & !suppressed)?;
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// RCP, RSQRT and their VRCP14 and VRSQRT14 successors, `1 / b` and `1 / sqrt(b)`, here to
+    /// within a unit in the last place rather than the 2^-12 (2^-14) relative error promised.
+    /// They round to nearest whatever MXCSR.RC says and raise no exceptions. The SSE forms
+    /// also take denormals as zero and flush tiny results to zero, whatever DAZ and FTZ say
+    pub(crate) fn reciprocal(&mut self, op: &X86Opcode, f: Format, lanes: Lanes, root: bool) -> Execution {
+        let size = f.bits() as usize / 8;
+        let (a, b) = self.read_sources(op)?;
+        let mut env = self.float_environment(op);
+        env.rounding = Rounding::Nearest;
+        if op.encoding != Encoding::Evex {
+            env.denormals_are_zero = true;
+            env.flush_to_zero = true;
+            env.underflow_masked = true;
+        }
+        let one = softfloat::from_int(f, 1, &mut env);
+        let result = each_lane(op, (&a, &b), size, lanes, self.write_mask(op), |_, y| match root {
+            true => softfloat::div(f, one, softfloat::sqrt(f, y, &mut env), &mut env),
+            false => softfloat::div(f, one, y, &mut env),
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// DPPS and DPPD: in each block, the products of the lanes the high four bits of the
+    /// immediate pick (the others +0.0) added up in pairs, and the sum written to the lanes the
+    /// low four bits pick, the others cleared
+    pub(crate) fn dot_product(&mut self, op: &X86Opcode, f: Format) -> Execution {
+        let size = f.bits() as usize / 8;
+        let count = 16 / size;
+        let (a, b) = self.read_sources(op)?;
+        let control = immediate(op) as u32;
+        let mut env = self.float_environment(op);
+        let result = blockwise(op, &a, &b, |a, b| {
+            let mut sums = [0; 4];
+            for (i, product) in sums.iter_mut().enumerate().take(count) {
+                *product = match control >> (4 + i) & 1 {
+                    0 => f.zero(false),
+                    _ => softfloat::mul(f, lane(a, i, size), lane(b, i, size), &mut env),
+                };
+            }
+            let mut width = count;
+            while width > 1 {
+                width /= 2;
+                for i in 0..width {
+                    sums[i] = softfloat::add(f, sums[2 * i], sums[2 * i + 1], &mut env);
+                }
+            }
+            (0..count).filter(|&i| control >> i & 1 != 0).fold(0, |result, i| with_lane(result, i, size, sums[0]))
+        });
+        self.float_exceptions(op, env.flags)?;
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// ANDPS, ANDNPS, ORPS and XORPS, and their PD twins. Bitwise, so no exceptions
+    pub(crate) fn float_logic(&mut self, op: &X86Opcode, logic: Logic) -> Execution {
+        let (a, b) = self.read_sources(op)?;
+        let result = blockwise(op, &a, &b, |a, b| match logic {
+            Logic::And => a & b,
+            Logic::AndNot => !a & b,
+            Logic::Or => a | b,
+            Logic::Xor => a ^ b,
+        });
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// CMPPS, CMPPD, CMPSS and CMPSD: each lane becomes all ones where the predicate in the
+    /// immediate holds and zero where it does not. The less than predicates (and their
+    /// negations) are signaling, the others only complain about signaling NaNs. VEX widens
+    /// the predicate to five bits: 8 to 15 add the rest of the relations, and 16 to 31 repeat
+    /// 0 to 15 with signaling and quiet swapped
+    pub(crate) fn float_compare(&mut self, op: &X86Opcode, f: Format, lanes: Lanes) -> Execution {
+        let size = f.bits() as usize / 8;
+        let (a, b) = self.read_sources(op)?;
+        let predicate = match op.vex {
+            Some(_) => immediate(op) & 0x1F,
+            None => immediate(op) & 7,
+        };
+        let signaling = matches!(predicate & 0xF, 1 | 2 | 5 | 6 | 9 | 10 | 13 | 14) != (predicate & 0x10 != 0);
+        let mut env = self.float_environment(op);
+        let result = each_lane(op, (&a, &b), size, lanes, self.write_mask(op), |x, y| {
+            let order = softfloat::compare(f, x, y, signaling, &mut env);
+            let (less, equal, greater) = (order == Some(Ordering::Less), order == Some(Ordering::Equal), order == Some(Ordering::Greater));
+            let unordered = order.is_none();
+            let holds = match predicate & 0xF {
+                0 => equal,
+                1 => less,
+                2 => less || equal,
+                3 => unordered,
+                4 => !equal,
+                5 => !less,
+                6 => !less && !equal,
+                7 => !unordered,
+                8 => equal || unordered,
+                9 => less || unordered,
+                10 => !greater,
+                11 => false,
+                12 => less || greater,
+                13 => greater || equal,
+                14 => greater,
+                _ => true,
+            };
+            match holds {
+                true => u128::MAX,
+                false => 0,
+            }
+        });
+        self.float_exceptions(op, env.flags)?;
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// COMISS, UCOMISS, COMISD and UCOMISD: ZF, PF and CF say equal, unordered and less than
+    /// (unordered sets all three), OF, SF and AF are cleared. COMI is signaling, UCOMI is not
+    pub(crate) fn float_compare_flags(&mut self, op: &X86Opcode, f: Format, signaling: bool) -> Execution {
+        let size = f.bits() as usize / 8;
+        let a = lane(self.read_vector(op, &op.operands[0], false)?, 0, size);
+        let b = lane(self.read_vector(op, &op.operands[1], false)?, 0, size);
+        let mut env = self.float_environment(op);
+        let order = softfloat::compare(f, a, b, signaling, &mut env);
+        self.float_exceptions(op, env.flags)?;
+        let (zero, parity, carry) = match order {
+            None => (true, true, true),
+            Some(Ordering::Greater) => (false, false, false),
+            Some(Ordering::Less) => (false, false, true),
+            Some(Ordering::Equal) => (true, false, false),
+        };
+        let flags = flag(zero, RFlags::Zero) | flag(parity, RFlags::Parity) | flag(carry, RFlags::Carry);
+        self.flags.update(ARITHMETIC_FLAGS, flags);
+        Ok(())
+    }
+
+    /// The packed and scalar floating point conversions other than to and from general purpose
+    /// registers. As many lanes are converted as fit the wider of the two elements, the rest of
+    /// the destination is cleared by a packed conversion and kept by a scalar one. `truncate`
+    /// rounds toward zero whatever MXCSR says
+    pub(crate) fn convert(&mut self, op: &X86Opcode, from: Element, to: Element, lanes: Lanes, truncate: bool) -> Execution {
+        let (base, source) = sources(op);
+        let source = self.read_blocks(op, source, op.vex.is_none())?;
+        let (count, mut result) = match lanes {
+            // to and from MMX, two lanes with the rest of an XMM destination kept
+            Lanes::Packed if is_mmx_conversion(op) => (2, self.read_blocks(op, base, false)?),
+            Lanes::Packed => (blocks(op) * 16 / from.size().max(to.size()), [0; BLOCKS]),
+            Lanes::Scalar => (1, self.read_blocks(op, base, false)?),
+        };
+        let mut env = self.float_environment(op);
+        let rounding = match truncate {
+            true => Rounding::TowardZero,
+            false => env.rounding,
+        };
+        let mask = self.write_mask(op);
+        for i in (0..count).filter(|&i| mask.is_none_or(|mask| mask.selected(i))) {
+            let value = element(&source, i, from.size());
+            let value = match (from, to) {
+                (Element::Float(from), Element::Float(to)) => softfloat::convert(from, to, value, &mut env),
+                (from, Element::Float(to)) => from.to_float(to, value, &mut env),
+                (Element::Float(from), to) => to.round_from(from, value, rounding, &mut env),
+                _ => value,
+            };
+            set_element(&mut result, i, to.size(), value);
+        }
+        self.float_exceptions(op, env.flags)?;
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// VCVTPH2PS (`widen`) and VCVTPS2PH, between half and single precision over the lanes of
+    /// the single precision side. DAZ is not applied to half precision operands nor FTZ to half
+    /// precision results, and VCVTPS2PH rounds as the low two bits of its immediate say unless
+    /// bit 2 leaves it to MXCSR
+    pub(crate) fn convert_half(&mut self, op: &X86Opcode, widen: bool) -> Execution {
+        let source = self.read_blocks(op, &op.operands[1], false)?;
+        let mut env = self.float_environment(op);
+        let (from, to) = match widen {
+            true => {
+                env.denormals_are_zero = false;
+                (HALF, SINGLE)
+            }
+            false => {
+                let control = immediate(op) as u32;
+                if control & 4 == 0 {
+                    env.rounding = Rounding::from_bits(control);
+                }
+                env.flush_to_zero = false;
+                (SINGLE, HALF)
+            }
+        };
+        let (from_size, to_size) = (from.bits() as usize / 8, to.bits() as usize / 8);
+        let mut result = [0; BLOCKS];
+        let mask = self.write_mask(op);
+        for i in (0..blocks(op) * 4).filter(|&i| mask.is_none_or(|mask| mask.selected(i))) {
+            set_element(&mut result, i, to_size, softfloat::convert(from, to, element(&source, i, from_size), &mut env));
+        }
+        self.float_exceptions(op, env.flags)?;
+        self.write_blocks(op, &op.operands[0], result, false)
+    }
+
+    /// CVTSI2SS and CVTSI2SD from a 32 or 64-bit integer, or VCVTUSI2SS and VCVTUSI2SD from an
+    /// unsigned one, into the low lane
+    pub(crate) fn convert_from_integer(&mut self, op: &X86Opcode, f: Format, signed: bool) -> Execution {
+        let (base, source) = sources(op);
+        let value = self.read_operand(op, source)?;
+        let mut env = self.float_environment(op);
+        let value = match signed {
+            true => softfloat::from_int(f, sign_extend(value, operand_size(source)) as i64, &mut env),
+            false => softfloat::from_uint(f, value, &mut env),
+        };
+        self.float_exceptions(op, env.flags)?;
+        let base = self.read_vector(op, base, false)?;
+        self.write_vector(op, &op.operands[0], with_lane(base, 0, f.bits() as usize / 8, value), false)
+    }
+
+    /// CVTSS2SI, CVTSD2SI and their truncating CVTT forms, to a 32 or 64-bit register, or to an
+    /// unsigned integer for the VCVT..2USI forms
+    pub(crate) fn convert_to_integer(&mut self, op: &X86Opcode, f: Format, truncate: bool, signed: bool) -> Execution {
+        let value = lane(self.read_vector(op, &op.operands[1], false)?, 0, f.bits() as usize / 8);
+        let bits = operand_size(&op.operands[0]) as u32 * 8;
+        let mut env = self.float_environment(op);
+        let rounding = match truncate {
+            true => Rounding::TowardZero,
+            false => env.rounding,
+        };
+        let value = match signed {
+            true => softfloat::to_int(f, value, bits, rounding, &mut env),
+            false => softfloat::to_uint(f, value, bits, rounding, &mut env),
+        };
+        self.float_exceptions(op, env.flags)?;
+        self.write_operand(op, &op.operands[0], value)
+    }
+
+    /// LDMXCSR, #GP for any reserved bit
+    pub(crate) fn ldmxcsr(&mut self, op: &X86Opcode) -> Execution {
+        let value = self.read_operand(op, &op.operands[0])? as u32;
+        if value & !MXCSR_MASK != 0 {
+            return Err(Exception::GeneralProtection.into());
+        }
+        self.set_mxcsr(value);
+        Ok(())
+    }
+
+    pub(crate) fn stmxcsr(&mut self, op: &X86Opcode) -> Execution {
+        self.write_operand(op, &op.operands[0], self.mxcsr() as u64)
+    }
+}
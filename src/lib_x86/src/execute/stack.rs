@@ -0,0 +1,170 @@
+//! The stack, addressed through RSP: PUSH, POP, PUSHF, POPF, ENTER and LEAVE. LAHF and SAHF,
+//! which move the low byte of the flags through AH instead, are here with PUSHF and POPF
+//!
+//! Any stack access outside guest memory raises #SS with RSP left as it was, so the
+//! instruction can be restarted. Segment selectors are not modelled: pushing a segment
+//! register pushes 0, and popping one discards the value and keeps the segment's base.
+use crate::execute::{Exception, Execution};
+use crate::flags::RFlags;
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{Operand, Register, X86Opcode};
+
+/// Flags POPF may change at any privilege level
+const POPF_FLAGS: u64 = RFlags::Carry as u64
+    | RFlags::Parity as u64
+    | RFlags::AuxCarry as u64
+    | RFlags::Zero as u64
+    | RFlags::Sign as u64
+    | RFlags::Trap as u64
+    | RFlags::Direction as u64
+    | RFlags::Overflow as u64
+    | RFlags::NestedTask as u64
+    | RFlags::AlignmentCheck as u64
+    | RFlags::CanUseCpuidInstruction as u64;
+
+/// Flags LAHF and SAHF move through AH
+const SAHF_FLAGS: u64 = RFlags::Carry as u64
+    | RFlags::Parity as u64
+    | RFlags::AuxCarry as u64
+    | RFlags::Zero as u64
+    | RFlags::Sign as u64;
+
+const IOPL: u64 = RFlags::IOPrivilegeLevelLow as u64 | RFlags::IOPrivilegeLevelHigh as u64;
+
+impl X86Machine {
+    /// Pushes the low `size` bytes of `value`. RSP is only moved once the write succeeded
+    pub(crate) fn push(&mut self, value: u64, size: usize) -> Result<(), Exception> {
+        let top = self.register(Register::RSP).wrapping_sub(size as u64);
+        self.store(top, size, value).map_err(|_| Exception::StackFault)?;
+        self.set_register(Register::RSP, top);
+        Ok(())
+    }
+
+    /// Pops `size` bytes, zero extended
+    pub(crate) fn pop(&mut self, size: usize) -> Result<u64, Exception> {
+        let top = self.register(Register::RSP);
+        let value = self.load(top, size).map_err(|_| Exception::StackFault)?;
+        self.set_register(Register::RSP, top.wrapping_add(size as u64));
+        Ok(value)
+    }
+
+    /// PUSH of a register, memory, immediate or segment register. `push rsp` pushes the value
+    /// from before the push
+    pub(crate) fn push_operand(&mut self, op: &X86Opcode) -> Execution {
+        let value = match op.operands[0] {
+            Operand::Register(Register::Segment(_)) => 0,
+            ref operand => self.read_operand(op, operand)?,
+        };
+        Ok(self.push(value, op.operand_size as usize)?)
+    }
+
+    /// POP to a register, memory or segment register. A memory destination addressed
+    /// through RSP sees the value RSP has after the pop
+    pub(crate) fn pop_operand(&mut self, op: &X86Opcode) -> Execution {
+        let rsp = self.register(Register::RSP);
+        let value = self.pop(op.operand_size as usize)?;
+        let written = match op.operands[0] {
+            Operand::Register(Register::Segment(_)) => Ok(()),
+            ref operand => self.write_operand(op, operand, value),
+        };
+        if written.is_err() {
+            self.set_register(Register::RSP, rsp);
+        }
+        written
+    }
+
+    /// PUSHF / PUSHFQ. VM and RF read as 0 in the pushed image
+    pub(crate) fn pushf(&mut self, op: &X86Opcode) -> Execution {
+        let flags = self.flags.value() & !(RFlags::Virtual8086 as u64 | RFlags::Resume as u64);
+        Ok(self.push(flags, op.operand_size as usize)?)
+    }
+
+    /// POPF / POPFQ. What may change depends on `privilege_level`: IOPL only at CPL 0, IF
+    /// only when CPL <= IOPL, and the virtual-8086 flags never. RF is cleared
+    pub(crate) fn popf(&mut self, op: &X86Opcode) -> Execution {
+        let size = op.operand_size as usize;
+        let popped = self.pop(size)?;
+        let current = self.flags.value();
+
+        let mut writable = POPF_FLAGS;
+        let iopl = (current & IOPL) >> 12;
+        if self.privilege_level == 0 {
+            writable |= IOPL;
+        }
+        if self.privilege_level as u64 <= iopl {
+            writable |= RFlags::Interrupt as u64;
+        }
+        if size == 2 {
+            writable &= 0xFFFF;
+        }
+        let flags = (current & !writable & !(RFlags::Resume as u64)) | (popped & writable);
+        self.flags.set_value(flags);
+        Ok(())
+    }
+
+    /// LAHF: SF, ZF, AF, PF and CF into AH, along with bit 1, which is always set
+    pub(crate) fn lahf(&mut self) -> Execution {
+        let flags = self.flags.value() & (SAHF_FLAGS | RFlags::Reserved_1 as u64);
+        self.set_register(Register::AH, flags);
+        Ok(())
+    }
+
+    /// SAHF: SF, ZF, AF, PF and CF from AH
+    pub(crate) fn sahf(&mut self) -> Execution {
+        self.flags.update(SAHF_FLAGS, self.register(Register::AH));
+        Ok(())
+    }
+
+    /// ENTER: pushes RBP, copies `nesting level - 1` frame pointers from the enclosing frame
+    /// followed by the new one, points RBP at the new frame and reserves `size` bytes below
+    pub(crate) fn enter(&mut self, op: &X86Opcode) -> Execution {
+        let (Operand::Immediate(size), Operand::Immediate(level)) = (op.operands[0], op.operands[1]) else {
+            return Err(Exception::InvalidOpcode.into());
+        };
+        let slot = op.operand_size as usize;
+        let (rsp, rbp) = (self.register(Register::RSP), self.register(Register::RBP));
+        let entered = self.enter_frame(size.value, level.value % 32, slot);
+        if entered.is_err() {
+            self.set_register(Register::RSP, rsp);
+            self.set_register(Register::RBP, rbp);
+        }
+        Ok(entered?)
+    }
+
+    fn enter_frame(&mut self, size: u64, level: u64, slot: usize) -> Result<(), Exception> {
+        self.push(self.register(Register::RBP), slot)?;
+        let frame = self.register(Register::RSP);
+        if level > 0 {
+            let mut rbp = self.register(Register::RBP);
+            for _ in 1..level {
+                rbp = rbp.wrapping_sub(slot as u64);
+                let pointer = self.load(rbp, slot).map_err(|_| Exception::StackFault)?;
+                self.push(pointer, slot)?;
+            }
+            self.push(frame, slot)?;
+        }
+        let rsp = self.register(Register::RSP).wrapping_sub(size);
+        // the reserved space has to be inside the stack as well
+        self.load(rsp, 1).map_err(|_| Exception::StackFault)?;
+        self.set_register(Register::RBP, frame);
+        self.set_register(Register::RSP, rsp);
+        Ok(())
+    }
+
+    /// LEAVE: RSP = RBP, then pops RBP
+    pub(crate) fn leave(&mut self, op: &X86Opcode) -> Execution {
+        let rsp = self.register(Register::RSP);
+        self.set_register(Register::RSP, self.register(Register::RBP));
+        match self.pop(op.operand_size as usize) {
+            Ok(rbp) => {
+                let register = if op.operand_size == 2 { Register::BP } else { Register::RBP };
+                self.set_register(register, rbp);
+                Ok(())
+            }
+            Err(exception) => {
+                self.set_register(Register::RSP, rsp);
+                Err(exception.into())
+            }
+        }
+    }
+}
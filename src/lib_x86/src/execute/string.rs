@@ -0,0 +1,167 @@
+//! String instructions: MOVS, STOS, LODS, SCAS and CMPS, with REP / REPE / REPNE
+//!
+//! The source is DS:RSI (FS and GS overrides apply their base) and the destination ES:RDI,
+//! with ESI / EDI / ECX under a 32-bit address size. Each element is committed to RSI, RDI and
+//! RCX before the next is started, so when an element faults the instruction restarts from
+//! that element rather than from the beginning.
+use crate::execute::operands::mask;
+use crate::execute::{Exception, Execution};
+use crate::flags::{AsRFlags, FlagOp, RFlags};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{Register, RepPrefix, X86Opcode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StringOp {
+    Movs,
+    Stos,
+    Lods,
+    Scas,
+    Cmps,
+}
+
+/// Source, destination and count registers for an address size
+fn string_registers(address_size: u8) -> (Register, Register, Register) {
+    match address_size {
+        4 => (Register::ESI, Register::EDI, Register::ECX),
+        _ => (Register::RSI, Register::RDI, Register::RCX),
+    }
+}
+
+fn accumulator(size: usize) -> Register {
+    match size {
+        1 => Register::AL,
+        2 => Register::AX,
+        4 => Register::EAX,
+        _ => Register::RAX,
+    }
+}
+
+impl X86Machine {
+    /// CLD / STD: whether string instructions walk up or down memory
+    pub(crate) fn direction(&mut self, backwards: bool) -> Execution {
+        match backwards {
+            true => self.flags.set(RFlags::Direction),
+            false => self.flags.clear(RFlags::Direction),
+        }
+        Ok(())
+    }
+
+    /// A string instruction working on `size` byte elements
+    pub(crate) fn string(&mut self, op: &X86Opcode, string: StringOp, size: usize) -> Execution {
+        let Some(rep) = op.prefixes.rep else {
+            return Ok(self.string_element(op, string, size)?);
+        };
+        if self.bulk_string(op, string, size) {
+            return Ok(());
+        }
+
+        let (_, _, count) = string_registers(op.address_size);
+        while self.register(count) != 0 {
+            self.string_element(op, string, size)?;
+            self.set_register(count, self.register(count) - 1);
+            if matches!(string, StringOp::Scas | StringOp::Cmps) {
+                // REPE carries on while equal, REPNE while not
+                let equal = self.flags.is_set(RFlags::Zero);
+                if equal != (rep == RepPrefix::Rep) {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// One element, moving RSI / RDI on to the next
+    fn string_element(&mut self, op: &X86Opcode, string: StringOp, size: usize) -> Result<(), Exception> {
+        let (source, destination, _) = string_registers(op.address_size);
+        let step = match self.flags.is_set(RFlags::Direction) {
+            true => (size as u64).wrapping_neg(),
+            false => size as u64,
+        };
+        let source_address = self.string_source(op);
+        let destination_address = self.register(destination);
+
+        match string {
+            StringOp::Movs => {
+                let value = self.load(source_address, size)?;
+                self.store(destination_address, size, value)?;
+            }
+            StringOp::Stos => {
+                let value = self.register(accumulator(size));
+                self.store(destination_address, size, value)?;
+            }
+            StringOp::Lods => {
+                let value = self.load(source_address, size)?;
+                self.set_register(accumulator(size), value);
+            }
+            StringOp::Scas => {
+                let a = self.register(accumulator(size));
+                let b = self.load(destination_address, size)?;
+                self.flags.defer(FlagOp::Sub, a, b, a.wrapping_sub(b) & mask(size), size, false);
+            }
+            StringOp::Cmps => {
+                let a = self.load(source_address, size)?;
+                let b = self.load(destination_address, size)?;
+                self.flags.defer(FlagOp::Sub, a, b, a.wrapping_sub(b) & mask(size), size, false);
+            }
+        }
+
+        if matches!(string, StringOp::Movs | StringOp::Lods | StringOp::Cmps) {
+            self.set_register(source, self.register(source).wrapping_add(step));
+        }
+        if !matches!(string, StringOp::Lods) {
+            self.set_register(destination, destination_address.wrapping_add(step));
+        }
+        Ok(())
+    }
+
+    /// Linear address of the source element
+    fn string_source(&self, op: &X86Opcode) -> u64 {
+        let (source, _, _) = string_registers(op.address_size);
+        let address = self.register(source);
+        match op.prefixes.segment {
+            Some(segment @ (Register::FS | Register::GS)) => address.wrapping_add(self.segment_base(segment)),
+            _ => address,
+        }
+    }
+
+    /// `rep movsb` and `rep stosb` as a single copy / fill, which is what memcpy and memset
+    /// spend their time in. Only taken going forwards with 64-bit addressing, when the whole
+    /// string is in memory and a copy does not overlap its own output, so the result is the
+    /// same as going byte by byte. Returns false if the string still has to be done
+    fn bulk_string(&mut self, op: &X86Opcode, string: StringOp, size: usize) -> bool {
+        if size != 1 || op.address_size != 8 || self.flags.is_set(RFlags::Direction) {
+            return false;
+        }
+        let count = self.register(Register::RCX);
+        let destination = self.register(Register::RDI);
+        let (Ok(len), Ok(to)) = (usize::try_from(count), usize::try_from(destination)) else {
+            return false;
+        };
+
+        let done = match string {
+            StringOp::Stos => {
+                let byte = self.register(Register::AL) as u8;
+                self.memory.fill(to, len, byte).is_ok()
+            }
+            StringOp::Movs => {
+                let source = self.string_source(op);
+                let Ok(from) = usize::try_from(source) else {
+                    return false;
+                };
+                // copying forwards onto the bytes still to be read repeats a pattern instead
+                let overlaps = to > from && to - from < len;
+                !overlaps && self.memory.copy(from, to, len).is_ok()
+            }
+            _ => false,
+        };
+        if done {
+            if string == StringOp::Movs {
+                let rsi = self.register(Register::RSI).wrapping_add(count);
+                self.set_register(Register::RSI, rsi);
+            }
+            self.set_register(Register::RDI, destination.wrapping_add(count));
+            self.set_register(Register::RCX, 0);
+        }
+        done
+    }
+}
@@ -0,0 +1,48 @@
+//! Interrupts, system calls and CPUID
+use crate::cpuid::{xsave_compacted_size, xsave_size};
+use crate::execute::{Execution, Fault, StopReason, Trap};
+use crate::functions::SystemFunction;
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{Register, X86Opcode};
+
+impl X86Machine {
+    pub(crate) fn int(&mut self, op: &X86Opcode) -> Execution {
+        let vector = self.read_operand(op, &op.operands[0])?;
+        Err(Trap::Interrupt(vector as u8))
+    }
+
+    /// Calls the entry in `syscalls` numbered by RAX. As on hardware RCX gets the return
+    /// address and R11 the flags
+    pub(crate) fn syscall(&mut self, op: &X86Opcode) -> Execution {
+        let number = self.register(Register::RAX);
+        let function = usize::try_from(number).map_or(SystemFunction::Unimplemented, |n| self.syscalls.get(n));
+        if let SystemFunction::Unimplemented = function {
+            return Err(Trap::Stop(StopReason::Fault(Fault::SyscallNotFound {
+                number,
+                address: op.ip,
+            })));
+        }
+        self.set_register(Register::RCX, op.next_ip());
+        self.set_register(Register::R11, self.flags.value());
+        function.call(self);
+        Ok(())
+    }
+
+    /// CPUID: leaf EAX, subleaf ECX, answered from `cpu_profile`
+    pub(crate) fn cpuid(&mut self) -> Execution {
+        let leaf = self.register(Register::EAX) as u32;
+        let subleaf = self.register(Register::ECX) as u32;
+        let [eax, mut ebx, ecx, edx] = self.cpu_profile.leaf(leaf, subleaf);
+        // the XSAVE area sizes for the components XCR0 enables
+        match (leaf, subleaf) {
+            (0xD, 0) => ebx = xsave_size(self.xcr0()),
+            (0xD, 1) => ebx = xsave_compacted_size(self.xcr0()),
+            _ => {}
+        }
+        self.set_register(Register::EAX, eax as u64);
+        self.set_register(Register::EBX, ebx as u64);
+        self.set_register(Register::ECX, ecx as u64);
+        self.set_register(Register::EDX, edx as u64);
+        Ok(())
+    }
+}
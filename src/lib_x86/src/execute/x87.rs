@@ -0,0 +1,1156 @@
+//! x87 floating point
+//!
+//! The eight 80 bit registers of `Fpu::registers` are a stack: ST(i) is physical register
+//! (TOP + i) mod 8, with TOP in the status word and each register tagged valid, zero, special
+//! or empty in the tag word. Values stay in the extended format throughout and the arithmetic
+//! is done in `softfloat`, rounded to the precision control of the control word, so results
+//! are those of the hardware to the bit rather than those of a host `f64`.
+//!
+//! Exceptions are recorded in the status word. When one is unmasked the error summary is set
+//! and, unless it is only a precision exception, the instruction neither writes its result nor
+//! pops (nor, for an overflow or underflow, delivers the exponent adjusted result hardware
+//! does). The #MF it calls for is raised by the next x87 instruction that waits, or WAIT.
+use crate::execute::operands::sign_extend;
+use crate::execute::shift::flag;
+use crate::execute::sse::Arithmetic;
+use crate::execute::{unsupported, Exception, Execution, Trap};
+use crate::flags::{Condition, RFlags, ARITHMETIC_FLAGS};
+use crate::softfloat::{
+    self, Class, Constant, Environment, Format, Rounding, DENORMAL, DIVIDE_BY_ZERO, DOUBLE, EXTENDED, INVALID, PRECISION, SINGLE,
+    UNDERFLOW,
+};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{Mnemonic, Operand, Register, X86Opcode};
+use std::cmp::Ordering;
+
+/// The control word after FNINIT: every exception masked, 64 bit precision, round to nearest
+const FPU_CONTROL_DEFAULT: u16 = 0x037F;
+const EXCEPTION_FLAGS: u16 = 0x3F;
+const STACK_FAULT: u16 = 1 << 6;
+const ERROR_SUMMARY: u16 = 1 << 7;
+const C0: u16 = 1 << 8;
+const C1: u16 = 1 << 9;
+const C2: u16 = 1 << 10;
+const C3: u16 = 1 << 14;
+const BUSY: u16 = 1 << 15;
+const TOP: u16 = 11;
+const PRECISION_CONTROL: u16 = 8;
+const ROUNDING_CONTROL: u16 = 10;
+
+const TAG_VALID: u16 = 0;
+const TAG_ZERO: u16 = 1;
+const TAG_SPECIAL: u16 = 2;
+const TAG_EMPTY: u16 = 3;
+
+/// The QNaN a masked invalid operation gives
+const INDEFINITE: u128 = EXTENDED.default_nan();
+const SIGN: u128 = 1 << 79;
+/// Packed BCD indefinite, stored by a masked invalid FBSTP
+const BCD_INDEFINITE: [u8; 10] = [0, 0, 0, 0, 0, 0, 0, 0xC0, 0xFF, 0xFF];
+/// The largest magnitude 18 BCD digits hold
+const BCD_LIMIT: u64 = 999_999_999_999_999_999;
+
+fn flag16(set: bool, flag: u16) -> u16 {
+    if set { flag } else { 0 }
+}
+
+/// What the tag word says of a value
+fn tag(value: u128) -> u16 {
+    match softfloat::classify(EXTENDED, value) {
+        Class::Zero => TAG_ZERO,
+        Class::Normal => TAG_VALID,
+        _ => TAG_SPECIAL,
+    }
+}
+
+fn is_unsupported(value: u128) -> bool {
+    softfloat::classify(EXTENDED, value) == Class::Unsupported
+}
+
+/// The result of an operation when an operand is a NaN or in a format the x87 does not
+/// support: for an unsupported one the indefinite, otherwise the NaN made quiet, or of two the
+/// QNaN over the SNaN and then the one with the larger significand. `INVALID` for either kind
+/// of operand that is not a QNaN
+fn nan_operands(values: &[u128], env: &mut Environment) -> Option<u128> {
+    if values.iter().any(|&value| is_unsupported(value)) {
+        env.flags |= INVALID;
+        return Some(INDEFINITE);
+    }
+    if values.iter().any(|&value| EXTENDED.is_signaling(value)) {
+        env.flags |= INVALID;
+    }
+    let significand = |value: u128| value as u64;
+    values
+        .iter()
+        .copied()
+        .filter(|&value| EXTENDED.is_nan(value))
+        .reduce(|a, b| match (EXTENDED.is_signaling(a), EXTENDED.is_signaling(b)) {
+            (true, false) => b,
+            (false, true) => a,
+            _ if significand(b) > significand(a) => b,
+            _ => a,
+        })
+        .map(|nan| EXTENDED.quiet(nan))
+}
+
+/// An unordered compare when either is unsupported, which is invalid
+fn compare(a: u128, b: u128, signaling: bool, env: &mut Environment) -> Option<Ordering> {
+    if is_unsupported(a) || is_unsupported(b) {
+        env.flags |= INVALID;
+        return None;
+    }
+    softfloat::compare(EXTENDED, a, b, signaling, env)
+}
+
+/// C3, C2 and C0 of a comparison, 000 for greater, 001 for less, 100 for equal and 111 for
+/// unordered
+fn compare_codes(order: Option<Ordering>) -> u16 {
+    match order {
+        Some(Ordering::Greater) => 0,
+        Some(Ordering::Less) => C0,
+        Some(Ordering::Equal) => C3,
+        None => C3 | C2 | C0,
+    }
+}
+
+/// A value in `f`, for a store to memory. `DENORMAL` is not raised by stores
+fn narrow(value: u128, f: Format, env: &mut Environment) -> u128 {
+    let narrowed = match is_unsupported(value) {
+        true => {
+            env.flags |= INVALID;
+            f.default_nan()
+        }
+        false => softfloat::convert(EXTENDED, f, value, env),
+    };
+    env.flags &= !DENORMAL;
+    narrowed
+}
+
+/// Whether the instruction takes a memory operand as an integer
+fn integer_form(op: &X86Opcode) -> bool {
+    use Mnemonic as M;
+    matches!(
+        op.mnemonic,
+        M::Fild | M::Fiadd | M::Fisub | M::Fisubr | M::Fimul | M::Fidiv | M::Fidivr | M::Ficom | M::Ficomp
+    )
+}
+
+/// Length of the environment image and of each of its fields: 14 and 2 bytes with a 16 bit
+/// operand size, otherwise 28 and 4
+fn environment_layout(op: &X86Opcode) -> (usize, usize) {
+    match op.operand_size {
+        2 => (14, 2),
+        _ => (28, 4),
+    }
+}
+
+/// i of an ST(i) operand
+fn st_index(op: &X86Opcode, operand: &Operand) -> Result<u8, Trap> {
+    match *operand {
+        Operand::Register(Register::St(i)) => Ok(i),
+        _ => Err(unsupported(op)),
+    }
+}
+
+impl X86Machine {
+    pub fn fpu_control_word(&self) -> u16 {
+        u16::from_le_bytes(self.fpu.control_register.0)
+    }
+
+    pub fn set_fpu_control_word(&mut self, value: u16) {
+        self.fpu.control_register.0 = value.to_le_bytes();
+    }
+
+    pub fn fpu_status_word(&self) -> u16 {
+        u16::from_le_bytes(self.fpu.status_register.0)
+    }
+
+    pub fn set_fpu_status_word(&mut self, value: u16) {
+        self.fpu.status_register.0 = value.to_le_bytes();
+    }
+
+    /// Two bits for each physical register, R0 in the lowest
+    pub fn fpu_tag_word(&self) -> u16 {
+        u16::from_le_bytes(self.fpu.tag_register.0)
+    }
+
+    pub fn set_fpu_tag_word(&mut self, value: u16) {
+        self.fpu.tag_register.0 = value.to_le_bytes();
+    }
+
+    /// The one bit a register tag word FXSAVE stores, set for each physical register that is
+    /// not empty
+    pub(crate) fn fpu_abridged_tag_word(&self) -> u8 {
+        (0..8).fold(0, |tags, n| tags | ((self.fpu_tag(n) != TAG_EMPTY) as u8) << n)
+    }
+
+    /// Sets the tag word from an abridged one, tagging each register that is not empty by its
+    /// contents
+    pub(crate) fn set_fpu_abridged_tag_word(&mut self, tags: u8) {
+        let word = (0..8).fold(0, |word, n| {
+            let tag = match tags >> n & 1 {
+                0 => TAG_EMPTY,
+                _ => tag(self.fpu_register(n)),
+            };
+            word | tag << (n * 2)
+        });
+        self.set_fpu_tag_word(word);
+    }
+
+    /// The 11 bit opcode of the last non-control instruction: the low three bits of its first
+    /// byte and its ModR/M byte
+    pub(crate) fn fpu_opcode(&self) -> u16 {
+        u16::from_le_bytes(self.fpu.opcopde_register.0)
+    }
+
+    pub(crate) fn fpu_instruction_pointer(&self) -> u64 {
+        u64::from_le_bytes(self.fpu.fpu_instruction_pointer.0)
+    }
+
+    pub(crate) fn fpu_data_pointer(&self) -> u64 {
+        u64::from_le_bytes(self.fpu.fpu_data_pointer.0)
+    }
+
+    pub(crate) fn set_fpu_last_instruction(&mut self, opcode: u16, instruction: u64, data: u64) {
+        self.fpu.opcopde_register.0 = (opcode & 0x7FF).to_le_bytes();
+        self.fpu.fpu_instruction_pointer.0 = instruction.to_le_bytes();
+        self.fpu.fpu_data_pointer.0 = data.to_le_bytes();
+    }
+
+    /// Physical register `n`, R0 to R7, whatever its tag says
+    pub(crate) fn fpu_register(&self, n: u8) -> u128 {
+        let start = n as usize * 10;
+        let mut bytes = [0; 16];
+        bytes[..10].copy_from_slice(&self.fpu.registers.0[start..start + 10]);
+        u128::from_le_bytes(bytes)
+    }
+
+    pub(crate) fn set_fpu_register(&mut self, n: u8, value: u128) {
+        let start = n as usize * 10;
+        self.fpu.registers.0[start..start + 10].copy_from_slice(&value.to_le_bytes()[..10]);
+    }
+
+    /// ST(i) as an 80 bit extended value, whatever its tag says
+    pub fn st(&self, i: u8) -> u128 {
+        self.fpu_register(self.physical(i))
+    }
+
+    fn fpu_top(&self) -> u8 {
+        (self.fpu_status_word() >> TOP & 7) as u8
+    }
+
+    pub(crate) fn set_fpu_top(&mut self, top: u8) {
+        let status = self.fpu_status_word() & !(7 << TOP);
+        self.set_fpu_status_word(status | (top as u16 & 7) << TOP);
+    }
+
+    /// The physical register ST(i) is
+    fn physical(&self, i: u8) -> u8 {
+        self.fpu_top().wrapping_add(i) & 7
+    }
+
+    fn fpu_tag(&self, physical: u8) -> u16 {
+        self.fpu_tag_word() >> (physical * 2) & 3
+    }
+
+    fn set_fpu_tag(&mut self, physical: u8, tag: u16) {
+        let shift = physical * 2;
+        self.set_fpu_tag_word(self.fpu_tag_word() & !(3 << shift) | tag << shift);
+    }
+
+    /// ST(i), or `None` when it is empty
+    fn read_st(&self, i: u8) -> Option<u128> {
+        match self.fpu_tag(self.physical(i)) {
+            TAG_EMPTY => None,
+            _ => Some(self.st(i)),
+        }
+    }
+
+    /// Sets ST(i) and its tag
+    fn write_st(&mut self, i: u8, value: u128) {
+        let physical = self.physical(i);
+        self.set_fpu_register(physical, value);
+        self.set_fpu_tag(physical, tag(value));
+    }
+
+    /// Whether there is an empty register for a push to use
+    fn can_push(&self) -> bool {
+        self.fpu_tag(self.physical(7)) == TAG_EMPTY
+    }
+
+    fn fpu_push(&mut self, value: u128) {
+        self.set_fpu_top(self.fpu_top().wrapping_sub(1));
+        self.write_st(0, value);
+    }
+
+    fn fpu_pop(&mut self) {
+        self.set_fpu_tag(self.physical(0), TAG_EMPTY);
+        self.set_fpu_top(self.fpu_top().wrapping_add(1));
+    }
+
+    /// Sets the condition code bits in `mask` to those of `codes`
+    fn set_condition(&mut self, mask: u16, codes: u16) {
+        self.set_fpu_status_word(self.fpu_status_word() & !mask | codes & mask);
+    }
+
+    /// The `softfloat` environment of the control word. Precision control only applies to the
+    /// instructions that set `precision` from `fpu_precision`
+    fn fpu_environment(&self) -> Environment {
+        let control = self.fpu_control_word();
+        Environment {
+            rounding: Rounding::from_bits((control >> ROUNDING_CONTROL) as u32),
+            denormals_are_zero: false,
+            flush_to_zero: false,
+            underflow_masked: control & UNDERFLOW as u16 != 0,
+            precision: None,
+            flags: 0,
+            rounded_up: false,
+        }
+    }
+
+    /// The significand bits precision control rounds FADD, FSUB, FMUL, FDIV and FSQRT to
+    fn fpu_precision(&self) -> Option<u32> {
+        match self.fpu_control_word() >> PRECISION_CONTROL & 3 {
+            0 => Some(24),
+            2 => Some(53),
+            _ => None,
+        }
+    }
+
+    /// Sets the error summary and busy bits when an exception flag is set that the control
+    /// word does not mask, clears them otherwise
+    fn update_error_summary(&mut self) {
+        let status = self.fpu_status_word();
+        let pending = status & !self.fpu_control_word() & EXCEPTION_FLAGS != 0;
+        let summary = flag16(pending, ERROR_SUMMARY | BUSY);
+        self.set_fpu_status_word(status & !(ERROR_SUMMARY | BUSY) | summary);
+    }
+
+    /// Records the exceptions `env` raised, with C1 saying whether an inexact result was
+    /// rounded up. Whether the instruction goes on to write its result, which it does not if
+    /// anything but a precision exception is unmasked
+    fn fpu_exceptions(&mut self, env: &Environment) -> bool {
+        let raised = env.flags as u16;
+        let unmasked = raised & !self.fpu_control_word() & EXCEPTION_FLAGS;
+        let rounded_up = raised & PRECISION as u16 != 0 && env.rounded_up;
+        let status = (self.fpu_status_word() | raised) & !C1 | flag16(rounded_up, C1);
+        self.set_fpu_status_word(status | flag16(unmasked != 0, ERROR_SUMMARY | BUSY));
+        unmasked & !(PRECISION as u16) == 0
+    }
+
+    /// A stack overflow (a push with no empty register) or underflow (an empty operand), an
+    /// invalid operation with SF set and C1 telling which. Whether it is masked, so that the
+    /// instruction goes on with the indefinite
+    fn stack_fault(&mut self, overflow: bool) -> bool {
+        let masked = self.fpu_control_word() & INVALID as u16 != 0;
+        let status = self.fpu_status_word() & !C1 | INVALID as u16 | STACK_FAULT | flag16(overflow, C1);
+        self.set_fpu_status_word(status | flag16(!masked, ERROR_SUMMARY | BUSY));
+        masked
+    }
+
+    /// The masked response to an empty operand: the indefinite in ST(`destination`), and then
+    /// `pops` pops
+    fn stack_underflow(&mut self, destination: u8, pops: u8) -> Execution {
+        if self.stack_fault(false) {
+            self.write_st(destination, INDEFINITE);
+            for _ in 0..pops {
+                self.fpu_pop();
+            }
+        }
+        Ok(())
+    }
+
+    /// The masked response to a push with no empty register is to push the indefinite
+    fn stack_overflow(&mut self) -> Execution {
+        if self.stack_fault(true) {
+            self.fpu_push(INDEFINITE);
+        }
+        Ok(())
+    }
+
+    /// WAIT / FWAIT, and the start of every x87 instruction without an FN mnemonic: #MF if an
+    /// earlier instruction left an unmasked exception
+    pub(crate) fn fwait(&mut self) -> Execution {
+        match self.fpu_status_word() & ERROR_SUMMARY {
+            0 => Ok(()),
+            _ => Err(Exception::FloatingPoint.into()),
+        }
+    }
+
+    /// The start of a non-control x87 instruction: `fwait`, and then the instruction becomes
+    /// the last one, in FOP, FIP and FDP
+    fn fpu_begin(&mut self, op: &X86Opcode) -> Execution {
+        self.fwait()?;
+        let modrm = op.modrm.map_or(0, |modrm| (modrm.mode << 6 | modrm.reg << 3 | modrm.rm) as u16);
+        let data = op.operands.iter().find_map(|operand| match operand {
+            Operand::Memory(memory) => Some(self.effective_address(op, memory)),
+            _ => None,
+        });
+        let data = data.unwrap_or(self.fpu_data_pointer());
+        self.set_fpu_last_instruction((op.opcode as u16 & 7) << 8 | modrm, op.ip, data);
+        Ok(())
+    }
+
+    /// A memory operand as an extended value: an integer for the FI instructions, otherwise a
+    /// single, double or extended float. Only the first two can raise exceptions, `INVALID` for
+    /// an SNaN and `DENORMAL`
+    fn load_fpu_operand(&mut self, op: &X86Opcode, operand: &Operand, env: &mut Environment) -> Result<u128, Trap> {
+        let Operand::Memory(memory) = *operand else {
+            return Err(unsupported(op));
+        };
+        let address = self.effective_address(op, &memory);
+        let size = memory.size as usize;
+        if size == 10 {
+            let mut bytes = [0; 16];
+            bytes[..10].copy_from_slice(&self.load_bytes(address, 10)?);
+            return Ok(u128::from_le_bytes(bytes));
+        }
+        let value = self.load(address, size)?;
+        Ok(match (integer_form(op), size) {
+            (true, _) => softfloat::from_int(EXTENDED, sign_extend(value, size) as i64, env),
+            (false, 4) => softfloat::convert(SINGLE, EXTENDED, value as u128, env),
+            (false, _) => softfloat::convert(DOUBLE, EXTENDED, value as u128, env),
+        })
+    }
+
+    /// FLD from memory in any of the three formats or from ST(i), and FILD
+    pub(crate) fn fld(&mut self, op: &X86Opcode) -> Execution {
+        self.fpu_begin(op)?;
+        let mut env = self.fpu_environment();
+        let value = match op.operands[0] {
+            Operand::Register(Register::St(i)) => self.read_st(i),
+            operand => Some(self.load_fpu_operand(op, &operand, &mut env)?),
+        };
+        if !self.can_push() {
+            return self.stack_overflow();
+        }
+        let Some(value) = value else {
+            if self.stack_fault(false) {
+                self.fpu_push(INDEFINITE);
+            }
+            return Ok(());
+        };
+        if self.fpu_exceptions(&env) {
+            self.fpu_push(value);
+        }
+        Ok(())
+    }
+
+    /// FLD1, FLDZ, FLDPI and the other constants, the irrational ones rounded under the
+    /// rounding control
+    pub(crate) fn fld_constant(&mut self, op: &X86Opcode, constant: Constant) -> Execution {
+        self.fpu_begin(op)?;
+        if !self.can_push() {
+            return self.stack_overflow();
+        }
+        let value = softfloat::constant(constant, self.fpu_environment().rounding);
+        self.fpu_push(value);
+        self.set_condition(C1, 0);
+        Ok(())
+    }
+
+    /// FBLD, an 18 digit packed BCD integer with the sign in the top bit of its tenth byte
+    pub(crate) fn fbld(&mut self, op: &X86Opcode) -> Execution {
+        self.fpu_begin(op)?;
+        let Operand::Memory(memory) = op.operands[0] else {
+            return Err(unsupported(op));
+        };
+        let bytes = self.load_bytes(self.effective_address(op, &memory), 10)?;
+        let digits = bytes[..9]
+            .iter()
+            .rev()
+            .fold(0, |value: i64, &pair| value * 100 + (pair >> 4) as i64 * 10 + (pair & 15) as i64);
+        let negative = bytes[9] & 0x80 != 0;
+        if !self.can_push() {
+            return self.stack_overflow();
+        }
+        let mut env = self.fpu_environment();
+        let sign = match negative {
+            true => SIGN,
+            false => 0,
+        };
+        let value = softfloat::from_int(EXTENDED, digits, &mut env) | sign;
+        self.fpu_push(value);
+        self.set_condition(C1, 0);
+        Ok(())
+    }
+
+    /// FST and FSTP, to ST(i) or to memory in any of the three formats. Storing to ST(i) or as
+    /// an extended value is exact
+    pub(crate) fn fst(&mut self, op: &X86Opcode, pop: bool) -> Execution {
+        self.fpu_begin(op)?;
+        let value = self.read_st(0);
+        let memory = match op.operands[0] {
+            Operand::Register(Register::St(i)) => {
+                let Some(value) = value else {
+                    return self.stack_underflow(i, pop as u8);
+                };
+                self.write_st(i, value);
+                self.set_condition(C1, 0);
+                if pop {
+                    self.fpu_pop();
+                }
+                return Ok(());
+            }
+            Operand::Memory(memory) => memory,
+            _ => return Err(unsupported(op)),
+        };
+        let address = self.effective_address(op, &memory);
+        let size = memory.size as usize;
+        let mut env = self.fpu_environment();
+        let stored = match (value, size) {
+            (None, _) if !self.stack_fault(false) => return Ok(()),
+            (None, 4) => SINGLE.default_nan(),
+            (None, 8) => DOUBLE.default_nan(),
+            (None, _) => INDEFINITE,
+            (Some(value), 4) => narrow(value, SINGLE, &mut env),
+            (Some(value), 8) => narrow(value, DOUBLE, &mut env),
+            (Some(value), _) => value,
+        };
+        if value.is_some() && !self.fpu_exceptions(&env) {
+            return Ok(());
+        }
+        self.store_bytes(address, &stored.to_le_bytes()[..size])?;
+        if pop {
+            self.fpu_pop();
+        }
+        Ok(())
+    }
+
+    /// FIST, FISTP and FISTTP, which truncates whatever the rounding control says. What does
+    /// not fit is invalid, and stored as the integer indefinite when that is masked
+    pub(crate) fn fist(&mut self, op: &X86Opcode, pop: bool, truncate: bool) -> Execution {
+        self.fpu_begin(op)?;
+        let Operand::Memory(memory) = op.operands[0] else {
+            return Err(unsupported(op));
+        };
+        let address = self.effective_address(op, &memory);
+        let size = memory.size as usize;
+        let indefinite = 1u64 << (size * 8 - 1);
+        let mut env = self.fpu_environment();
+        let rounding = match truncate {
+            true => Rounding::TowardZero,
+            false => env.rounding,
+        };
+        let value = match self.read_st(0) {
+            None if !self.stack_fault(false) => return Ok(()),
+            None => indefinite,
+            Some(value) if is_unsupported(value) => {
+                env.flags |= INVALID;
+                indefinite
+            }
+            Some(value) => softfloat::to_int(EXTENDED, value, size as u32 * 8, rounding, &mut env),
+        };
+        if !self.fpu_exceptions(&env) {
+            return Ok(());
+        }
+        self.store(address, size, value)?;
+        if pop {
+            self.fpu_pop();
+        }
+        Ok(())
+    }
+
+    /// FBSTP, ST(0) rounded to an integer under the rounding control, as packed BCD
+    pub(crate) fn fbstp(&mut self, op: &X86Opcode) -> Execution {
+        self.fpu_begin(op)?;
+        let Operand::Memory(memory) = op.operands[0] else {
+            return Err(unsupported(op));
+        };
+        let address = self.effective_address(op, &memory);
+        let mut env = self.fpu_environment();
+        let bytes = match self.read_st(0) {
+            None if !self.stack_fault(false) => return Ok(()),
+            None => BCD_INDEFINITE,
+            Some(value) => {
+                let integer = match is_unsupported(value) {
+                    true => None,
+                    false => Some(softfloat::to_int(EXTENDED, value, 64, env.rounding, &mut env) as i64),
+                };
+                match integer.filter(|integer| integer.unsigned_abs() <= BCD_LIMIT) {
+                    Some(integer) => {
+                        let mut bytes = [0; 10];
+                        let mut magnitude = integer.unsigned_abs();
+                        for pair in bytes[..9].iter_mut() {
+                            *pair = (magnitude % 10) as u8 | ((magnitude / 10 % 10) as u8) << 4;
+                            magnitude /= 100;
+                        }
+                        bytes[9] = flag16(EXTENDED.is_negative(value), 0x80) as u8;
+                        bytes
+                    }
+                    None => {
+                        // NaNs and infinities have already raised it
+                        env.flags = env.flags & !PRECISION | INVALID;
+                        BCD_INDEFINITE
+                    }
+                }
+            }
+        };
+        if !self.fpu_exceptions(&env) {
+            return Ok(());
+        }
+        self.store_bytes(address, &bytes)?;
+        self.fpu_pop();
+        Ok(())
+    }
+
+    /// FXCH. An empty register swaps as the indefinite, when that is masked
+    pub(crate) fn fxch(&mut self, op: &X86Opcode) -> Execution {
+        self.fpu_begin(op)?;
+        let i = st_index(op, &op.operands[0])?;
+        let (a, b) = match (self.read_st(0), self.read_st(i)) {
+            (Some(a), Some(b)) => {
+                self.set_condition(C1, 0);
+                (a, b)
+            }
+            (a, b) => match self.stack_fault(false) {
+                true => (a.unwrap_or(INDEFINITE), b.unwrap_or(INDEFINITE)),
+                false => return Ok(()),
+            },
+        };
+        self.write_st(0, b);
+        self.write_st(i, a);
+        Ok(())
+    }
+
+    /// FFREE, tagging ST(i) empty
+    pub(crate) fn ffree(&mut self, op: &X86Opcode) -> Execution {
+        self.fpu_begin(op)?;
+        let i = st_index(op, &op.operands[0])?;
+        self.set_fpu_tag(self.physical(i), TAG_EMPTY);
+        Ok(())
+    }
+
+    /// FINCSTP and FDECSTP, which move TOP without touching the tags
+    pub(crate) fn rotate_fpu_stack(&mut self, op: &X86Opcode, increment: bool) -> Execution {
+        self.fpu_begin(op)?;
+        let top = match increment {
+            true => self.fpu_top().wrapping_add(1),
+            false => self.fpu_top().wrapping_sub(1),
+        };
+        self.set_fpu_top(top);
+        self.set_condition(C1, 0);
+        Ok(())
+    }
+
+    /// FADD, FSUB, FMUL and FDIV, their FI forms and those that pop. `reverse` swaps the
+    /// operands, for FSUBR and FDIVR. With one operand that is the source and ST(0) the
+    /// destination, otherwise the first is the destination
+    pub(crate) fn farith(&mut self, op: &X86Opcode, arithmetic: Arithmetic, reverse: bool, pop: bool) -> Execution {
+        self.fpu_begin(op)?;
+        let mut env = self.fpu_environment();
+        let (destination, source) = match *op.operands {
+            [Operand::Register(Register::St(d)), Operand::Register(Register::St(s))] => (d, self.read_st(s)),
+            [memory] => (0, Some(self.load_fpu_operand(op, &memory, &mut env)?)),
+            _ => return Err(unsupported(op)),
+        };
+        let (Some(a), Some(b)) = (self.read_st(destination), source) else {
+            return self.stack_underflow(destination, pop as u8);
+        };
+        let (a, b) = match reverse {
+            true => (b, a),
+            false => (a, b),
+        };
+        env.precision = self.fpu_precision();
+        let result = match nan_operands(&[a, b], &mut env) {
+            Some(nan) => nan,
+            None => arithmetic.apply(EXTENDED, a, b, &mut env),
+        };
+        if self.fpu_exceptions(&env) {
+            self.write_st(destination, result);
+            if pop {
+                self.fpu_pop();
+            }
+        }
+        Ok(())
+    }
+
+    /// FCOM, FUCOM and FICOM, with the pops of FCOMP, FCOMPP and the rest: ST(0) against the
+    /// operand, or against ST(1) without one. FUCOM only finds an SNaN invalid
+    pub(crate) fn fcom(&mut self, op: &X86Opcode, signaling: bool, pops: u8) -> Execution {
+        self.fpu_begin(op)?;
+        let mut env = self.fpu_environment();
+        let source = match op.operands.first() {
+            Some(&Operand::Register(Register::St(i))) => self.read_st(i),
+            Some(memory) => Some(self.load_fpu_operand(op, memory, &mut env)?),
+            None => self.read_st(1),
+        };
+        let order = match (self.read_st(0), source) {
+            (Some(a), Some(b)) => compare(a, b, signaling, &mut env),
+            _ if !self.stack_fault(false) => return Ok(()),
+            _ => None,
+        };
+        if !self.fpu_exceptions(&env) {
+            return Ok(());
+        }
+        self.set_condition(C3 | C2 | C0, compare_codes(order));
+        for _ in 0..pops {
+            self.fpu_pop();
+        }
+        Ok(())
+    }
+
+    /// FTST, ST(0) against +0.0
+    pub(crate) fn ftst(&mut self, op: &X86Opcode) -> Execution {
+        self.fpu_begin(op)?;
+        let mut env = self.fpu_environment();
+        let order = match self.read_st(0) {
+            Some(value) => compare(value, EXTENDED.zero(false), true, &mut env),
+            None if !self.stack_fault(false) => return Ok(()),
+            None => None,
+        };
+        if self.fpu_exceptions(&env) {
+            self.set_condition(C3 | C2 | C0, compare_codes(order));
+        }
+        Ok(())
+    }
+
+    /// FCOMI, FUCOMI and the forms of them that pop, comparing ST(0) with ST(i) into ZF, PF
+    /// and CF as COMISS does
+    pub(crate) fn fcomi(&mut self, op: &X86Opcode, signaling: bool, pop: bool) -> Execution {
+        self.fpu_begin(op)?;
+        let i = st_index(op, &op.operands[1])?;
+        let mut env = self.fpu_environment();
+        let order = match (self.read_st(0), self.read_st(i)) {
+            (Some(a), Some(b)) => compare(a, b, signaling, &mut env),
+            _ if !self.stack_fault(false) => return Ok(()),
+            _ => None,
+        };
+        if !self.fpu_exceptions(&env) {
+            return Ok(());
+        }
+        let (zero, parity, carry) = match order {
+            None => (true, true, true),
+            Some(Ordering::Greater) => (false, false, false),
+            Some(Ordering::Less) => (false, false, true),
+            Some(Ordering::Equal) => (true, false, false),
+        };
+        let flags = flag(zero, RFlags::Zero) | flag(parity, RFlags::Parity) | flag(carry, RFlags::Carry);
+        self.flags.update(ARITHMETIC_FLAGS, flags);
+        if pop {
+            self.fpu_pop();
+        }
+        Ok(())
+    }
+
+    /// FXAM, the class of ST(0) in C3, C2 and C0 and its sign in C1
+    pub(crate) fn fxam(&mut self, op: &X86Opcode) -> Execution {
+        self.fpu_begin(op)?;
+        let codes = match self.read_st(0).map(|value| softfloat::classify(EXTENDED, value)) {
+            Some(Class::Unsupported) => 0,
+            Some(Class::Nan) => C0,
+            Some(Class::Normal) => C2,
+            Some(Class::Infinity) => C2 | C0,
+            Some(Class::Zero) => C3,
+            None => C3 | C0,
+            Some(Class::Denormal) => C3 | C2,
+        };
+        let sign = flag16(EXTENDED.is_negative(self.st(0)), C1);
+        self.set_condition(C3 | C2 | C1 | C0, codes | sign);
+        Ok(())
+    }
+
+    /// FCHS and FABS, which only touch the sign and raise nothing for a NaN
+    pub(crate) fn fsign(&mut self, op: &X86Opcode, absolute: bool) -> Execution {
+        self.fpu_begin(op)?;
+        let Some(value) = self.read_st(0) else {
+            return self.stack_underflow(0, 0);
+        };
+        let value = match absolute {
+            true => value & !SIGN,
+            false => value ^ SIGN,
+        };
+        self.write_st(0, value);
+        self.set_condition(C1, 0);
+        Ok(())
+    }
+
+    /// FCMOVcc, ST(i) to ST(0) when `condition` holds
+    pub(crate) fn fcmov(&mut self, op: &X86Opcode, condition: Condition) -> Execution {
+        self.fpu_begin(op)?;
+        let i = st_index(op, &op.operands[1])?;
+        let (Some(_), Some(value)) = (self.read_st(0), self.read_st(i)) else {
+            return self.stack_underflow(0, 0);
+        };
+        if condition.holds(&self.flags) {
+            self.write_st(0, value);
+        }
+        self.set_condition(C1, 0);
+        Ok(())
+    }
+
+    /// Replaces ST(0) by `f` of it, once NaN and unsupported operands are dealt with.
+    /// `precision` applies precision control
+    fn fpu_unary(&mut self, op: &X86Opcode, precision: bool, f: impl FnOnce(u128, &mut Environment) -> u128) -> Execution {
+        self.fpu_begin(op)?;
+        let Some(value) = self.read_st(0) else {
+            return self.stack_underflow(0, 0);
+        };
+        let mut env = self.fpu_environment();
+        if precision {
+            env.precision = self.fpu_precision();
+        }
+        let result = match nan_operands(&[value], &mut env) {
+            Some(nan) => nan,
+            None => f(value, &mut env),
+        };
+        if self.fpu_exceptions(&env) {
+            self.write_st(0, result);
+        }
+        Ok(())
+    }
+
+    /// `f` of ST(0) and ST(1), once NaN and unsupported operands are dealt with, to
+    /// ST(`destination`) and then popped if `pop`
+    fn fpu_binary(
+        &mut self,
+        op: &X86Opcode,
+        destination: u8,
+        pop: bool,
+        f: impl FnOnce(u128, u128, &mut Environment) -> u128,
+    ) -> Execution {
+        self.fpu_begin(op)?;
+        let (Some(a), Some(b)) = (self.read_st(0), self.read_st(1)) else {
+            return self.stack_underflow(destination, pop as u8);
+        };
+        let mut env = self.fpu_environment();
+        let result = match nan_operands(&[a, b], &mut env) {
+            Some(nan) => nan,
+            None => f(a, b, &mut env),
+        };
+        if self.fpu_exceptions(&env) {
+            self.write_st(destination, result);
+            if pop {
+                self.fpu_pop();
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn fsqrt(&mut self, op: &X86Opcode) -> Execution {
+        self.fpu_unary(op, true, |value, env| softfloat::sqrt(EXTENDED, value, env))
+    }
+
+    /// FRNDINT, to an integer under the rounding control
+    pub(crate) fn frndint(&mut self, op: &X86Opcode) -> Execution {
+        self.fpu_unary(op, false, |value, env| {
+            let rounding = env.rounding;
+            softfloat::round_to_integral(EXTENDED, value, rounding, env)
+        })
+    }
+
+    /// F2XM1, 2^ST(0) - 1
+    pub(crate) fn f2xm1(&mut self, op: &X86Opcode) -> Execution {
+        self.fpu_unary(op, false, softfloat::exp2_minus_one)
+    }
+
+    /// FSCALE, ST(0) * 2^ST(1) with ST(1) truncated to an integer
+    pub(crate) fn fscale(&mut self, op: &X86Opcode) -> Execution {
+        self.fpu_binary(op, 0, false, |a, b, env| {
+            let class = softfloat::classify(EXTENDED, a);
+            let negative = EXTENDED.is_negative(b);
+            match softfloat::classify(EXTENDED, b) {
+                Class::Infinity => match (class, negative) {
+                    (Class::Zero, false) | (Class::Infinity, true) => {
+                        env.flags |= INVALID;
+                        INDEFINITE
+                    }
+                    (Class::Zero | Class::Infinity, _) => a,
+                    (_, false) => EXTENDED.infinity(EXTENDED.is_negative(a)),
+                    (_, true) => EXTENDED.zero(EXTENDED.is_negative(a)),
+                },
+                other => {
+                    if other == Class::Denormal {
+                        env.flags |= DENORMAL;
+                    }
+                    // far enough past the exponent range that anything over it is the same
+                    let limit = 1 << 20;
+                    let mut scratch = Environment { flags: 0, ..*env };
+                    let n = softfloat::to_int(EXTENDED, b, 32, Rounding::TowardZero, &mut scratch) as i32;
+                    let n = match scratch.flags & INVALID {
+                        0 => n.clamp(-limit, limit),
+                        _ if negative => -limit,
+                        _ => limit,
+                    };
+                    softfloat::scale(EXTENDED, a, n, env)
+                }
+            }
+        })
+    }
+
+    /// FXTRACT, ST(0) split into its exponent, left in ST(0), and its significand, pushed
+    pub(crate) fn fxtract(&mut self, op: &X86Opcode) -> Execution {
+        self.fpu_begin(op)?;
+        let value = self.read_st(0);
+        if value.is_none() || !self.can_push() {
+            if self.stack_fault(value.is_some()) {
+                self.write_st(0, INDEFINITE);
+                self.fpu_push(INDEFINITE);
+            }
+            return Ok(());
+        }
+        let value = value.expect("checked above");
+        let mut env = self.fpu_environment();
+        let (exponent, significand) = match nan_operands(&[value], &mut env) {
+            Some(nan) => (nan, nan),
+            None => match softfloat::classify(EXTENDED, value) {
+                Class::Zero => {
+                    env.flags |= DIVIDE_BY_ZERO;
+                    (EXTENDED.infinity(true), value)
+                }
+                Class::Infinity => (EXTENDED.infinity(false), value),
+                _ => softfloat::extract(EXTENDED, value, &mut env),
+            },
+        };
+        if self.fpu_exceptions(&env) {
+            self.write_st(0, exponent);
+            self.fpu_push(significand);
+        }
+        Ok(())
+    }
+
+    /// FPREM and FPREM1 (`nearest`), the partial remainder of ST(0) by ST(1). C2 is set when
+    /// the reduction is incomplete, otherwise C0, C3 and C1 are the low three bits of the
+    /// quotient
+    pub(crate) fn fprem(&mut self, op: &X86Opcode, nearest: bool) -> Execution {
+        self.fpu_begin(op)?;
+        let (Some(a), Some(b)) = (self.read_st(0), self.read_st(1)) else {
+            return self.stack_underflow(0, 0);
+        };
+        let mut env = self.fpu_environment();
+        let (result, quotient) = match nan_operands(&[a, b], &mut env) {
+            Some(nan) => (nan, Some(0)),
+            None => softfloat::remainder(EXTENDED, a, b, nearest, &mut env),
+        };
+        if !self.fpu_exceptions(&env) {
+            return Ok(());
+        }
+        self.write_st(0, result);
+        let codes = match quotient {
+            None => C2,
+            Some(q) => flag16(q & 4 != 0, C0) | flag16(q & 2 != 0, C3) | flag16(q & 1 != 0, C1),
+        };
+        self.set_condition(C3 | C2 | C1 | C0, codes);
+        Ok(())
+    }
+
+    /// FYL2X, ST(1) * log2(ST(0)), and FYL2XP1, ST(1) * log2(ST(0) + 1), into ST(1) before
+    /// popping
+    pub(crate) fn fyl2x(&mut self, op: &X86Opcode, plus_one: bool) -> Execution {
+        self.fpu_binary(op, 1, true, |x, y, env| softfloat::y_log2_x(x, y, plus_one, env))
+    }
+
+    /// FPATAN, the angle of the point (ST(0), ST(1)) into ST(1) before popping
+    pub(crate) fn fpatan(&mut self, op: &X86Opcode) -> Execution {
+        self.fpu_binary(op, 1, true, |x, y, env| softfloat::atan2(y, x, env))
+    }
+
+    /// FSIN, FCOS and FSINCOS, which leaves the sine in ST(1) and the cosine in ST(0). An
+    /// operand of 2^63 or more is out of range: C2 is set and the stack left as it is
+    pub(crate) fn fsincos(&mut self, op: &X86Opcode, sin: bool, cos: bool) -> Execution {
+        self.fpu_begin(op)?;
+        let both = sin && cos;
+        let Some(value) = self.read_st(0) else {
+            return self.stack_underflow(0, 0);
+        };
+        if both && !self.can_push() {
+            return self.stack_overflow();
+        }
+        let mut env = self.fpu_environment();
+        let (s, c) = match nan_operands(&[value], &mut env) {
+            Some(nan) => (nan, nan),
+            None => match softfloat::sin_cos(value, &mut env) {
+                Some(pair) => pair,
+                None => {
+                    self.set_condition(C2, C2);
+                    return Ok(());
+                }
+            },
+        };
+        if !self.fpu_exceptions(&env) {
+            return Ok(());
+        }
+        self.set_condition(C2, 0);
+        match both {
+            true => {
+                self.write_st(0, s);
+                self.fpu_push(c);
+            }
+            false => self.write_st(0, if sin { s } else { c }),
+        }
+        Ok(())
+    }
+
+    /// FPTAN, the tangent of ST(0) into it and then 1.0 pushed. Out of range as for FSIN
+    pub(crate) fn fptan(&mut self, op: &X86Opcode) -> Execution {
+        self.fpu_begin(op)?;
+        let Some(value) = self.read_st(0) else {
+            return self.stack_underflow(0, 0);
+        };
+        if !self.can_push() {
+            return self.stack_overflow();
+        }
+        let mut env = self.fpu_environment();
+        let tangent = match nan_operands(&[value], &mut env) {
+            Some(nan) => nan,
+            None => match softfloat::tan(value, &mut env) {
+                Some(tangent) => tangent,
+                None => {
+                    self.set_condition(C2, C2);
+                    return Ok(());
+                }
+            },
+        };
+        if !self.fpu_exceptions(&env) {
+            return Ok(());
+        }
+        self.set_condition(C2, 0);
+        self.write_st(0, tangent);
+        self.fpu_push(softfloat::constant(Constant::One, env.rounding));
+        Ok(())
+    }
+
+    pub(crate) fn fnop(&mut self, op: &X86Opcode) -> Execution {
+        self.fpu_begin(op)
+    }
+
+    /// FLDCW. Unmasking an exception whose flag is set leaves it pending
+    pub(crate) fn fldcw(&mut self, op: &X86Opcode) -> Execution {
+        self.fwait()?;
+        let value = self.read_operand(op, &op.operands[0])?;
+        self.set_fpu_control_word(value as u16);
+        self.update_error_summary();
+        Ok(())
+    }
+
+    pub(crate) fn fnstcw(&mut self, op: &X86Opcode) -> Execution {
+        self.write_operand(op, &op.operands[0], self.fpu_control_word() as u64)
+    }
+
+    /// FNSTSW, to AX or memory
+    pub(crate) fn fnstsw(&mut self, op: &X86Opcode) -> Execution {
+        self.write_operand(op, &op.operands[0], self.fpu_status_word() as u64)
+    }
+
+    /// FNCLEX, clearing the exception flags, stack fault, error summary and busy bits
+    pub(crate) fn fnclex(&mut self) -> Execution {
+        let status = self.fpu_status_word() & !(EXCEPTION_FLAGS | STACK_FAULT | ERROR_SUMMARY | BUSY);
+        self.set_fpu_status_word(status);
+        Ok(())
+    }
+
+    /// FNINIT: the default control word, every register empty and everything else cleared
+    pub(crate) fn fninit(&mut self) -> Execution {
+        self.set_fpu_control_word(FPU_CONTROL_DEFAULT);
+        self.set_fpu_status_word(0);
+        self.set_fpu_tag_word(0xFFFF);
+        self.set_fpu_last_instruction(0, 0, 0);
+        Ok(())
+    }
+
+    /// The environment FNSTENV and FNSAVE store: the 28 byte protected mode layout, or the 14
+    /// byte one with a 16 bit operand size. Selectors are stored as zero
+    fn fpu_environment_image(&self, op: &X86Opcode) -> Vec<u8> {
+        let words = [self.fpu_control_word(), self.fpu_status_word(), self.fpu_tag_word()];
+        let (instruction, data) = (self.fpu_instruction_pointer(), self.fpu_data_pointer());
+        let mut image = Vec::with_capacity(28);
+        match op.operand_size {
+            2 => {
+                for word in words.into_iter().chain([instruction as u16, 0, data as u16, 0]) {
+                    image.extend_from_slice(&word.to_le_bytes());
+                }
+            }
+            _ => {
+                let opcode = (self.fpu_opcode() as u32) << 16;
+                for dword in words.map(u32::from).into_iter().chain([instruction as u32, opcode, data as u32, 0]) {
+                    image.extend_from_slice(&dword.to_le_bytes());
+                }
+            }
+        }
+        image
+    }
+
+    /// Loads an environment image, the reverse of `fpu_environment_image`, returning its
+    /// length. Only whether a register is empty is taken from the tag word, the other tags
+    /// are worked out from the registers
+    fn load_fpu_environment_image(&mut self, op: &X86Opcode, address: u64) -> Result<usize, Trap> {
+        let (size, field) = environment_layout(op);
+        let image = self.load_bytes(address, size)?;
+        let read = |i: usize| {
+            let mut bytes = [0; 4];
+            bytes[..field].copy_from_slice(&image[i * field..(i + 1) * field]);
+            u32::from_le_bytes(bytes)
+        };
+        let (control, status, tags) = (read(0) as u16, read(1) as u16, read(2) as u16);
+        let (instruction, opcode, data) = match field {
+            2 => (read(3), self.fpu_opcode(), read(5)),
+            _ => (read(3), (read(4) >> 16) as u16, read(5)),
+        };
+        self.set_fpu_control_word(control);
+        self.set_fpu_status_word(status);
+        self.set_fpu_last_instruction(opcode, instruction as u64, data as u64);
+        let mut tag_word = 0;
+        for physical in 0..8 {
+            let tag = match tags >> (physical * 2) & 3 {
+                TAG_EMPTY => TAG_EMPTY,
+                _ => tag(self.fpu_register(physical)),
+            };
+            tag_word |= tag << (physical * 2);
+        }
+        self.set_fpu_tag_word(tag_word);
+        self.update_error_summary();
+        Ok(size)
+    }
+
+    fn fpu_memory_address(&self, op: &X86Opcode) -> Result<u64, Trap> {
+        match op.operands[0] {
+            Operand::Memory(memory) => Ok(self.effective_address(op, &memory)),
+            _ => Err(unsupported(op)),
+        }
+    }
+
+    /// FNSTENV, which then masks every exception
+    pub(crate) fn fnstenv(&mut self, op: &X86Opcode) -> Execution {
+        let address = self.fpu_memory_address(op)?;
+        self.store_bytes(address, &self.fpu_environment_image(op))?;
+        self.set_fpu_control_word(self.fpu_control_word() | EXCEPTION_FLAGS);
+        Ok(())
+    }
+
+    pub(crate) fn fldenv(&mut self, op: &X86Opcode) -> Execution {
+        self.fwait()?;
+        let address = self.fpu_memory_address(op)?;
+        self.load_fpu_environment_image(op, address)?;
+        Ok(())
+    }
+
+    /// FNSAVE, the environment followed by ST(0) to ST(7), and then FNINIT
+    pub(crate) fn fnsave(&mut self, op: &X86Opcode) -> Execution {
+        let address = self.fpu_memory_address(op)?;
+        let mut image = self.fpu_environment_image(op);
+        for i in 0..8 {
+            image.extend_from_slice(&self.st(i).to_le_bytes()[..10]);
+        }
+        self.store_bytes(address, &image)?;
+        self.fninit()
+    }
+
+    /// FRSTOR, the reverse of FNSAVE. The tags are worked out from the registers loaded
+    pub(crate) fn frstor(&mut self, op: &X86Opcode) -> Execution {
+        self.fwait()?;
+        let address = self.fpu_memory_address(op)?;
+        let (size, field) = environment_layout(op);
+        // the registers go in first, under the TOP being loaded, for the tags to be worked
+        // out from
+        let status = self.load(address + field as u64, 2)? as u16;
+        let registers = self.load_bytes(address + size as u64, 80)?;
+        self.set_fpu_top((status >> TOP & 7) as u8);
+        for (i, value) in registers.chunks(10).enumerate() {
+            let mut bytes = [0; 16];
+            bytes[..10].copy_from_slice(value);
+            self.set_fpu_register(self.physical(i as u8), u128::from_le_bytes(bytes));
+        }
+        self.load_fpu_environment_image(op, address)?;
+        Ok(())
+    }
+}
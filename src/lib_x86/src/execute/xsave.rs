@@ -0,0 +1,415 @@
+//! FXSAVE / FXRSTOR, the XSAVE family and XCR0
+//!
+//! State is laid out as the SDM's XSAVE area. The 512 byte legacy region FXSAVE also uses has
+//! the x87 (and so MMX) state, MXCSR and XMM0-15. A 64 byte header follows with XSTATE_BV, the
+//! components the area holds, and XCOMP_BV. After that come the extended components at the
+//! offsets CPUID leaf 0xD reports: the upper halves of YMM0-15, the MPX bounds registers and
+//! BNDCFGU / BNDSTATUS, the opmask registers, the upper halves of ZMM0-15 and ZMM16-31. In the
+//! compacted form XSAVEC writes, XCOMP_BV has bit 63 set along with the components, which are
+//! packed one after the other from offset 576.
+//!
+//! XCR0 says which components are enabled, and the VEX and EVEX instructions need theirs to
+//! be. The builder enables every component the CPU profile supports, as an OS would, and XSETBV
+//! changes it at CPL 0. XSAVE sets XSTATE_BV for every component it saves rather than tracking
+//! which are in their initial state, which the SDM allows, and XSAVEOPT is XSAVE.
+//!
+//! `xsave_area` and `restore_xsave_area` give the host the same image, as a canonical export
+//! format for the vector state.
+use crate::cpuid::{
+    xsave_compacted_size, xsave_component, xsave_components, xsave_size, XCR0_AVX, XCR0_BNDCSR, XCR0_BNDREGS,
+    XCR0_HI16_ZMM, XCR0_OPMASK, XCR0_SSE, XCR0_X87, XCR0_ZMM_HI256, XSAVE_EXTENDED,
+};
+use crate::execute::sse::{MXCSR_DEFAULT, MXCSR_MASK};
+use crate::execute::{Exception, Execution};
+use crate::x86::X86Machine;
+use lib_opcode::prelude::{Encoding, Operand, Register, X86Opcode};
+
+const LEGACY_SIZE: usize = 512;
+/// XCOMP_BV bit marking the compacted form
+const COMPACTED: u64 = 1 << 63;
+/// Components whose instructions are EVEX encoded
+const AVX512_STATE: u64 = XCR0_OPMASK | XCR0_ZMM_HI256 | XCR0_HI16_ZMM;
+/// Components MPX needs
+pub(crate) const MPX_STATE: u64 = XCR0_BNDREGS | XCR0_BNDCSR;
+
+/// Offset in the legacy region of ST(i), each in 16 bytes
+const ST: usize = 32;
+/// Offset in the legacy region of XMMn, each in 16 bytes
+const XMM: usize = 160;
+const MXCSR: usize = 24;
+
+fn word(area: &[u8], offset: usize, size: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes[..size].copy_from_slice(&area[offset..offset + size]);
+    u64::from_le_bytes(bytes)
+}
+
+fn set_word(area: &mut [u8], offset: usize, size: usize, value: u64) {
+    area[offset..offset + size].copy_from_slice(&value.to_le_bytes()[..size]);
+}
+
+/// Where each extended component of `components` is in an area of the standard or compacted
+/// form, with its size
+fn extended_layout(components: u64, compacted: bool) -> impl Iterator<Item = (u32, usize, usize)> {
+    let mut next = XSAVE_EXTENDED as usize;
+    xsave_components(components).filter_map(move |component| {
+        let (size, offset) = xsave_component(component)?;
+        let offset = match compacted {
+            true => {
+                next += size as usize;
+                next - size as usize
+            }
+            false => offset as usize,
+        };
+        Some((component, offset, size as usize))
+    })
+}
+
+impl X86Machine {
+    /// XCR0, the state components XSAVE manages and the instructions that use them may run
+    pub fn xcr0(&self) -> u64 {
+        u64::from_le_bytes(self.xcr0_register.0)
+    }
+
+    /// Sets XCR0 as it is, without the checks XSETBV makes
+    pub fn set_xcr0(&mut self, value: u64) {
+        self.xcr0_register.0 = value.to_le_bytes();
+    }
+
+    /// #UD for an instruction on state XCR0 does not enable: a VEX instruction on the vector
+    /// registers (or VZEROUPPER and VLDMXCSR, which have none) needs SSE and AVX state, and an
+    /// EVEX one or one on the opmask registers the AVX-512 state as well. The VEX forms of the
+    /// BMI instructions only have general purpose registers and need nothing
+    pub(crate) fn check_xcr0(&self, op: &X86Opcode) -> Result<(), Exception> {
+        let registers = || {
+            op.operands.iter().filter_map(|operand| match operand {
+                Operand::Register(register) => Some(*register),
+                _ => None,
+            })
+        };
+        let vector = registers().any(|register| matches!(register, Register::Xmm(_) | Register::Ymm(_)));
+        let avx512 = registers().any(|register| matches!(register, Register::Zmm(_) | Register::Opmask(_)));
+        let needed = match op.encoding {
+            Encoding::Evex => XCR0_SSE | XCR0_AVX | AVX512_STATE,
+            _ if avx512 => XCR0_SSE | XCR0_AVX | AVX512_STATE,
+            Encoding::Vex if vector || registers().next().is_none() => XCR0_SSE | XCR0_AVX,
+            _ => 0,
+        };
+        match self.xcr0() & needed == needed {
+            true => Ok(()),
+            false => Err(Exception::InvalidOpcode),
+        }
+    }
+
+    /// XGETBV: XCR0 into EDX:EAX for ECX = 0. There is no other register to read
+    pub(crate) fn xgetbv(&mut self) -> Execution {
+        if self.register(Register::ECX) != 0 {
+            return Err(Exception::GeneralProtection.into());
+        }
+        self.set_edx_eax(self.xcr0());
+        Ok(())
+    }
+
+    /// XSETBV: EDX:EAX into XCR0 for ECX = 0, at CPL 0. x87 state cannot be disabled, AVX needs
+    /// SSE, the two MPX components go together and so do the three AVX-512 ones, which need AVX
+    pub(crate) fn xsetbv(&mut self) -> Execution {
+        let value = self.edx_eax();
+        let avx512 = value & AVX512_STATE;
+        let valid = self.privilege_level == 0
+            && self.register(Register::ECX) == 0
+            && value & !self.cpu_profile.supported_xcr0() == 0
+            && value & XCR0_X87 != 0
+            && (value & XCR0_AVX == 0 || value & XCR0_SSE != 0)
+            && (value & MPX_STATE == 0 || value & MPX_STATE == MPX_STATE)
+            && (avx512 == 0 || avx512 == AVX512_STATE && value & XCR0_AVX != 0);
+        match valid {
+            true => {
+                self.set_xcr0(value);
+                Ok(())
+            }
+            false => Err(Exception::GeneralProtection.into()),
+        }
+    }
+
+    /// FXSAVE and FXSAVE64: the legacy region, to a 16 byte aligned operand
+    pub(crate) fn fxsave(&mut self, op: &X86Opcode, wide: bool) -> Execution {
+        let address = self.state_address(op, 16)?;
+        let mut area = self.load_bytes(address, LEGACY_SIZE)?;
+        self.save_x87(&mut area, wide);
+        self.save_mxcsr(&mut area);
+        self.save_xmm(&mut area);
+        Ok(self.store_bytes(address, &area)?)
+    }
+
+    /// FXRSTOR and FXRSTOR64. #GP for reserved MXCSR bits, with nothing loaded
+    pub(crate) fn fxrstor(&mut self, op: &X86Opcode, wide: bool) -> Execution {
+        let address = self.state_address(op, 16)?;
+        let area = self.load_bytes(address, LEGACY_SIZE)?;
+        let mxcsr = self.checked_mxcsr(&area)?;
+        self.restore_x87(&area, wide);
+        self.set_mxcsr(mxcsr);
+        self.restore_xmm(&area);
+        Ok(())
+    }
+
+    /// XSAVE, XSAVEOPT and XSAVEC (and their 64 forms): the components XCR0 enables and EDX:EAX
+    /// requests, to a 64 byte aligned operand. XSAVE only writes the components it saves and
+    /// their XSTATE_BV bits, XSAVEC the whole compacted area and header
+    pub(crate) fn xsave(&mut self, op: &X86Opcode, wide: bool, compacted: bool) -> Execution {
+        let address = self.state_address(op, 64)?;
+        let requested = self.xcr0() & self.edx_eax();
+        let size = match compacted {
+            true => xsave_compacted_size(requested),
+            false => xsave_size(self.xcr0()),
+        };
+        let mut area = self.load_bytes(address, size as usize)?;
+        self.save_state(&mut area, requested, wide, compacted);
+        Ok(self.store_bytes(address, &area)?)
+    }
+
+    /// XRSTOR and XRSTOR64: the components XCR0 enables and EDX:EAX requests, from either form
+    pub(crate) fn xrstor(&mut self, op: &X86Opcode, wide: bool) -> Execution {
+        let address = self.state_address(op, 64)?;
+        let header = self.load_bytes(address + LEGACY_SIZE as u64, 16)?;
+        let size = match word(&header, 8, 8) {
+            components if components & COMPACTED != 0 => xsave_compacted_size(components & !COMPACTED),
+            _ => xsave_size(self.xcr0()),
+        };
+        let area = self.load_bytes(address, size as usize)?;
+        let requested = self.xcr0() & self.edx_eax();
+        Ok(self.restore_state(&area, requested, wide)?)
+    }
+
+    /// The state of every component XCR0 enables, as XSAVE64 stores it in the standard form
+    pub fn xsave_area(&self) -> Vec<u8> {
+        let mut area = vec![0; xsave_size(self.xcr0()) as usize];
+        self.save_state(&mut area, self.xcr0(), true, false);
+        area
+    }
+
+    /// Loads an XSAVE area of either form as XRSTOR64 would, requesting every component XCR0
+    /// enables. Fails with the exception XRSTOR raises for a malformed header or MXCSR, or
+    /// #GP for an area too short for its header
+    pub fn restore_xsave_area(&mut self, area: &[u8]) -> Result<(), Exception> {
+        if area.len() < XSAVE_EXTENDED as usize {
+            return Err(Exception::GeneralProtection);
+        }
+        let components = word(area, LEGACY_SIZE + 8, 8);
+        let size = match components & COMPACTED {
+            0 => xsave_size(self.xcr0()),
+            _ => xsave_compacted_size(components & !COMPACTED),
+        };
+        match area.len() >= size as usize {
+            true => self.restore_state(area, self.xcr0(), true),
+            false => Err(Exception::GeneralProtection),
+        }
+    }
+
+    fn edx_eax(&self) -> u64 {
+        self.register(Register::EDX) << 32 | self.register(Register::EAX)
+    }
+
+    fn set_edx_eax(&mut self, value: u64) {
+        self.set_register(Register::EAX, value & 0xFFFF_FFFF);
+        self.set_register(Register::EDX, value >> 32);
+    }
+
+    /// The address of the memory operand, #GP unless it is aligned to `alignment`
+    fn state_address(&self, op: &X86Opcode, alignment: u64) -> Result<u64, Exception> {
+        let address = op
+            .memory_operand()
+            .map(|memory| self.effective_address(op, memory))
+            .ok_or(Exception::InvalidOpcode)?;
+        match address.is_multiple_of(alignment) {
+            true => Ok(address),
+            false => Err(Exception::GeneralProtection),
+        }
+    }
+
+    /// The `requested` components into `area`, sized for the form, with the header updated
+    fn save_state(&self, area: &mut [u8], requested: u64, wide: bool, compacted: bool) {
+        if requested & XCR0_X87 != 0 {
+            self.save_x87(area, wide);
+        }
+        if requested & (XCR0_SSE | XCR0_AVX) != 0 {
+            self.save_mxcsr(area);
+        }
+        if requested & XCR0_SSE != 0 {
+            self.save_xmm(area);
+        }
+        for (component, offset, size) in extended_layout(requested, compacted) {
+            self.save_extended(component, &mut area[offset..offset + size]);
+        }
+        let header = &mut area[LEGACY_SIZE..XSAVE_EXTENDED as usize];
+        match compacted {
+            true => {
+                header.fill(0);
+                set_word(header, 0, 8, requested);
+                set_word(header, 8, 8, requested | COMPACTED);
+            }
+            false => {
+                let saved = word(header, 0, 8) | requested;
+                set_word(header, 0, 8, saved);
+            }
+        }
+    }
+
+    /// The `requested` components from `area`, each that XSTATE_BV does not have put in its
+    /// initial state. Nothing is loaded if the header or MXCSR is invalid
+    fn restore_state(&mut self, area: &[u8], requested: u64, wide: bool) -> Result<(), Exception> {
+        let header = &area[LEGACY_SIZE..XSAVE_EXTENDED as usize];
+        let (saved, components) = (word(header, 0, 8), word(header, 8, 8));
+        let compacted = components & COMPACTED != 0;
+        let reserved = header[16..].iter().any(|&byte| byte != 0);
+        let valid = match compacted {
+            true => components & !COMPACTED & !self.xcr0() == 0 && saved & !components == 0,
+            false => components == 0 && saved & !self.xcr0() == 0,
+        };
+        if reserved || !valid {
+            return Err(Exception::GeneralProtection);
+        }
+        // the standard form always has MXCSR, the compacted one only with SSE or AVX state
+        let mxcsr = match requested & (XCR0_SSE | XCR0_AVX) {
+            0 => None,
+            _ if !compacted || saved & (XCR0_SSE | XCR0_AVX) != 0 => Some(self.checked_mxcsr(area)?),
+            _ => Some(MXCSR_DEFAULT),
+        };
+
+        let restored = requested & saved;
+        match (requested & XCR0_X87 != 0, restored & XCR0_X87 != 0) {
+            (true, true) => self.restore_x87(area, wide),
+            (true, false) => self.init_x87(),
+            _ => {}
+        }
+        if let Some(mxcsr) = mxcsr {
+            self.set_mxcsr(mxcsr);
+        }
+        match (requested & XCR0_SSE != 0, restored & XCR0_SSE != 0) {
+            (true, true) => self.restore_xmm(area),
+            (true, false) => (0..16).for_each(|n| self.set_xmm(n, 0)),
+            _ => {}
+        }
+        let layout: Vec<_> = extended_layout(if compacted { components } else { self.xcr0() }, compacted).collect();
+        for (component, (size, _)) in xsave_components(requested).filter_map(|component| Some((component, xsave_component(component)?))) {
+            match layout.iter().find(|&&(saved, ..)| saved == component) {
+                Some(&(_, offset, size)) if restored >> component & 1 != 0 => {
+                    self.restore_extended(component, &area[offset..offset + size])
+                }
+                _ => self.restore_extended(component, &vec![0; size as usize]),
+            }
+        }
+        Ok(())
+    }
+
+    /// FCW, FSW, the abridged tag word, FOP, FIP and FDP, and ST(0) to ST(7). The 64-bit forms
+    /// have 8 byte pointers, the others a 4 byte offset with the selector stored as zero
+    fn save_x87(&self, area: &mut [u8], wide: bool) {
+        set_word(area, 0, 2, self.fpu_control_word() as u64);
+        set_word(area, 2, 2, self.fpu_status_word() as u64);
+        set_word(area, 4, 2, self.fpu_abridged_tag_word() as u64);
+        set_word(area, 6, 2, self.fpu_opcode() as u64);
+        match wide {
+            true => {
+                set_word(area, 8, 8, self.fpu_instruction_pointer());
+                set_word(area, 16, 8, self.fpu_data_pointer());
+            }
+            false => {
+                set_word(area, 8, 8, self.fpu_instruction_pointer() & 0xFFFF_FFFF);
+                set_word(area, 16, 8, self.fpu_data_pointer() & 0xFFFF_FFFF);
+            }
+        }
+        for i in 0..8 {
+            let offset = ST + i * 16;
+            area[offset..offset + 16].copy_from_slice(&self.st(i as u8).to_le_bytes()[..16]);
+        }
+    }
+
+    fn restore_x87(&mut self, area: &[u8], wide: bool) {
+        self.set_fpu_control_word(word(area, 0, 2) as u16);
+        self.set_fpu_status_word(word(area, 2, 2) as u16);
+        let pointer = if wide { 8 } else { 4 };
+        self.set_fpu_last_instruction(word(area, 6, 2) as u16, word(area, 8, pointer), word(area, 16, pointer));
+        let top = word(area, 2, 2) >> 11 & 7;
+        for i in 0..8 {
+            let offset = ST + i * 16;
+            self.set_fpu_register((top as u8 + i as u8) & 7, word(area, offset, 8) as u128 | (word(area, offset + 8, 2) as u128) << 64);
+        }
+        self.set_fpu_abridged_tag_word(area[4]);
+    }
+
+    /// The x87 state after FNINIT, with every register zero
+    fn init_x87(&mut self) {
+        self.fninit().expect("FNINIT cannot fault");
+        (0..8).for_each(|n| self.set_fpu_register(n, 0));
+    }
+
+    fn save_mxcsr(&self, area: &mut [u8]) {
+        set_word(area, MXCSR, 4, self.mxcsr() as u64);
+        set_word(area, MXCSR + 4, 4, MXCSR_MASK as u64);
+    }
+
+    /// MXCSR from an area, #GP if it sets reserved bits
+    fn checked_mxcsr(&self, area: &[u8]) -> Result<u32, Exception> {
+        match word(area, MXCSR, 4) as u32 {
+            mxcsr if mxcsr & !MXCSR_MASK != 0 => Err(Exception::GeneralProtection),
+            mxcsr => Ok(mxcsr),
+        }
+    }
+
+    fn save_xmm(&self, area: &mut [u8]) {
+        for n in 0..16 {
+            let offset = XMM + n * 16;
+            area[offset..offset + 16].copy_from_slice(&self.xmm(n as u8).to_le_bytes());
+        }
+    }
+
+    fn restore_xmm(&mut self, area: &[u8]) {
+        for n in 0..16 {
+            let offset = XMM + n * 16;
+            self.set_xmm(n as u8, u128::from_le_bytes(area[offset..offset + 16].try_into().expect("16 bytes")));
+        }
+    }
+
+    /// An extended component into the bytes the layout gives it
+    fn save_extended(&self, component: u32, bytes: &mut [u8]) {
+        if component == 4 {
+            set_word(bytes, 0, 8, self.bndcfgu());
+            set_word(bytes, 8, 8, self.bndstatus());
+            return;
+        }
+        let blocks: Vec<u128> = match component {
+            2 => (0..16).map(|n| self.vector(n)[1]).collect(),
+            3 => (0..4).map(|n| self.bnd(n)).collect(),
+            5 => (0..8).map(|n| self.opmask(n) as u128).collect(),
+            6 => (0..16).flat_map(|n| [self.vector(n)[2], self.vector(n)[3]]).collect(),
+            _ => (16..32).flat_map(|n| self.vector(n)).collect(),
+        };
+        let size = bytes.len() / blocks.len();
+        for (chunk, block) in bytes.chunks_mut(size).zip(blocks) {
+            chunk.copy_from_slice(&block.to_le_bytes()[..size]);
+        }
+    }
+
+    fn restore_extended(&mut self, component: u32, bytes: &[u8]) {
+        let block = |i: usize| u128::from_le_bytes(bytes[i * 16..i * 16 + 16].try_into().expect("16 bytes"));
+        match component {
+            2 => (0..16).for_each(|n| {
+                let mut vector = self.vector(n as u8);
+                vector[1] = block(n);
+                self.set_vector(n as u8, vector);
+            }),
+            3 => (0..4).for_each(|n| self.set_bnd(n as u8, block(n))),
+            4 => {
+                self.set_bndcfgu(word(bytes, 0, 8));
+                self.set_bndstatus(word(bytes, 8, 8));
+            }
+            5 => (0..8).for_each(|n| self.set_opmask(n as u8, word(bytes, n * 8, 8))),
+            6 => (0..16).for_each(|n| {
+                let mut vector = self.vector(n as u8);
+                vector[2..].copy_from_slice(&[block(2 * n), block(2 * n + 1)]);
+                self.set_vector(n as u8, vector);
+            }),
+            _ => (0..16).for_each(|n| self.set_vector(16 + n as u8, core::array::from_fn(|i| block(4 * n + i)))),
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use lib_opcode::prelude::Mnemonic;
+use std::fmt;
 use std::ops::{BitOr, BitOrAssign};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -272,3 +274,271 @@ impl BitOr<RFlags> for RFlags {
         rhs as u64 | self as u64
     }
 }
+
+/// The flags set by arithmetic instructions: CF, PF, AF, ZF, SF and OF
+pub const ARITHMETIC_FLAGS: u64 = RFlags::Carry as u64
+    | RFlags::Parity as u64
+    | RFlags::AuxCarry as u64
+    | RFlags::Zero as u64
+    | RFlags::Sign as u64
+    | RFlags::Overflow as u64;
+
+/// Kind of the instruction whose flags are still to be worked out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FlagOp {
+    /// ADD and ADC, `carry` is the carry in
+    Add,
+    /// SUB, SBB, CMP and NEG, `carry` is the borrow in
+    Sub,
+    /// AND, OR, XOR and TEST
+    Logic,
+    /// like `Add` / `Sub` but leaving CF alone
+    Inc,
+    Dec,
+}
+
+/// Operands and result of the last flag setting instruction
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+    op: FlagOp,
+    a: u64,
+    b: u64,
+    result: u64,
+    /// operand size in bytes
+    size: u8,
+    carry: bool,
+}
+
+impl Pending {
+    fn affected(&self) -> u64 {
+        match self.op {
+            FlagOp::Inc | FlagOp::Dec => ARITHMETIC_FLAGS & !(RFlags::Carry as u64),
+            _ => ARITHMETIC_FLAGS,
+        }
+    }
+
+    fn sign_bit(&self) -> u64 {
+        1 << (self.size * 8 - 1)
+    }
+
+    /// Works out a single arithmetic flag
+    fn is_set(&self, flag: RFlags) -> bool {
+        let (a, b, result) = (self.a, self.b, self.result);
+        match (flag, self.op) {
+            (RFlags::Zero, _) => result == 0,
+            (RFlags::Sign, _) => result & self.sign_bit() != 0,
+            (RFlags::Parity, _) => (result as u8).count_ones().is_multiple_of(2),
+            (_, FlagOp::Logic) => false,
+            (RFlags::AuxCarry, _) => (a ^ b ^ result) & 0x10 != 0,
+            (RFlags::Carry, FlagOp::Add) => {
+                (a as u128 + b as u128 + self.carry as u128) >> (self.size * 8) != 0
+            }
+            (RFlags::Carry, _) => (a as u128) < b as u128 + self.carry as u128,
+            (RFlags::Overflow, FlagOp::Add | FlagOp::Inc) => (a ^ result) & (b ^ result) & self.sign_bit() != 0,
+            (RFlags::Overflow, _) => (a ^ b) & (a ^ result) & self.sign_bit() != 0,
+            _ => false,
+        }
+    }
+
+    fn evaluate(&self) -> u64 {
+        [RFlags::Carry, RFlags::Parity, RFlags::AuxCarry, RFlags::Zero, RFlags::Sign, RFlags::Overflow]
+            .into_iter()
+            .filter(|&flag| self.is_set(flag))
+            .fold(0, |bits, flag| bits | flag as u64)
+    }
+}
+
+/// RFLAGS
+///
+/// Arithmetic instructions only record their operands and result here, the flags themselves
+/// are worked out when something reads them (a Jcc / SETcc / CMOVcc, PUSHF, or code outside
+/// the machine). Most flag results are overwritten before anything looks at them, so this
+/// saves doing the work at all.
+///
+/// Bit 1 is reserved and always reads as set, whatever is written to the register.
+#[derive(Clone, Copy)]
+pub struct Flags {
+    /// every flag not covered by `pending`
+    bits: u64,
+    pending: Option<Pending>,
+}
+
+impl Flags {
+    pub fn new(bits: u64) -> Flags {
+        Flags {
+            bits: bits | RFlags::Reserved_1 as u64,
+            pending: None,
+        }
+    }
+
+    /// The whole register, working out any pending flags
+    pub fn value(&self) -> u64 {
+        match &self.pending {
+            None => self.bits,
+            Some(pending) => {
+                let affected = pending.affected();
+                (self.bits & !affected) | (pending.evaluate() & affected)
+            }
+        }
+    }
+
+    pub fn set_value(&mut self, bits: u64) {
+        *self = Flags::new(bits);
+    }
+
+    /// Replaces the flags in `affected` with those in `flags`
+    pub fn update(&mut self, affected: u64, flags: u64) {
+        let bits = self.value();
+        *self = Flags::new((bits & !affected) | (flags & affected));
+    }
+
+    /// Records an arithmetic result to work the flags out from later
+    pub(crate) fn defer(&mut self, op: FlagOp, a: u64, b: u64, result: u64, size: usize, carry: bool) {
+        // flags the new operation leaves alone may still depend on the previous one
+        if matches!(op, FlagOp::Inc | FlagOp::Dec) && self.pending.is_some() {
+            self.bits = self.value();
+        }
+        self.pending = Some(Pending {
+            op,
+            a,
+            b,
+            result,
+            size: size as u8,
+            carry,
+        });
+    }
+}
+
+impl Default for Flags {
+    fn default() -> Flags {
+        Flags::new(0)
+    }
+}
+
+impl AsRFlags for Flags {
+    fn set(&mut self, flag: RFlags) {
+        self.update(flag as u64, flag as u64);
+    }
+
+    fn clear(&mut self, flag: RFlags) {
+        self.update(flag as u64, 0);
+    }
+
+    fn is_set(&self, flag: RFlags) -> bool {
+        match &self.pending {
+            Some(pending) if pending.affected() & flag as u64 != 0 => pending.is_set(flag),
+            _ => self.bits.is_set(flag),
+        }
+    }
+}
+
+impl PartialEq for Flags {
+    fn eq(&self, other: &Self) -> bool {
+        self.value() == other.value()
+    }
+}
+
+impl Eq for Flags {}
+
+impl fmt::Debug for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Flags({:#x})", self.value())
+    }
+}
+
+/// The 16 condition codes of Jcc, SETcc and CMOVcc, in encoding order
+///
+/// Works on anything implementing `AsRFlags`, so a plain `u64` of saved flags can be tested as
+/// well as a machine's `Flags`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Condition {
+    /// O: OF
+    Overflow,
+    /// NO: !OF
+    NotOverflow,
+    /// B / C / NAE: CF
+    Below,
+    /// AE / NC / NB: !CF
+    AboveOrEqual,
+    /// E / Z: ZF
+    Equal,
+    /// NE / NZ: !ZF
+    NotEqual,
+    /// BE / NA: CF or ZF
+    BelowOrEqual,
+    /// A / NBE: !CF and !ZF
+    Above,
+    /// S: SF
+    Sign,
+    /// NS: !SF
+    NotSign,
+    /// P / PE: PF
+    Parity,
+    /// NP / PO: !PF
+    NotParity,
+    /// L / NGE: SF != OF
+    Less,
+    /// GE / NL: SF == OF
+    GreaterOrEqual,
+    /// LE / NG: ZF or SF != OF
+    LessOrEqual,
+    /// G / NLE: !ZF and SF == OF
+    Greater,
+}
+
+impl Condition {
+    /// The condition in the low 4 bits of a Jcc / SETcc / CMOVcc opcode
+    pub fn from_code(code: u8) -> Condition {
+        use Condition::*;
+        [
+            Overflow, NotOverflow, Below, AboveOrEqual, Equal, NotEqual, BelowOrEqual, Above, Sign, NotSign,
+            Parity, NotParity, Less, GreaterOrEqual, LessOrEqual, Greater,
+        ][code as usize & 0xF]
+    }
+
+    pub fn code(&self) -> u8 {
+        *self as u8
+    }
+
+    /// The condition a Jcc, SETcc or CMOVcc tests
+    pub fn from_mnemonic(mnemonic: Mnemonic) -> Option<Condition> {
+        use Mnemonic as M;
+        Some(match mnemonic {
+            M::Jo | M::Seto | M::Cmovo => Condition::Overflow,
+            M::Jno | M::Setno | M::Cmovno => Condition::NotOverflow,
+            M::Jb | M::Setb | M::Cmovb => Condition::Below,
+            M::Jae | M::Setae | M::Cmovae => Condition::AboveOrEqual,
+            M::Je | M::Sete | M::Cmove => Condition::Equal,
+            M::Jne | M::Setne | M::Cmovne => Condition::NotEqual,
+            M::Jbe | M::Setbe | M::Cmovbe => Condition::BelowOrEqual,
+            M::Ja | M::Seta | M::Cmova => Condition::Above,
+            M::Js | M::Sets | M::Cmovs => Condition::Sign,
+            M::Jns | M::Setns | M::Cmovns => Condition::NotSign,
+            M::Jp | M::Setp | M::Cmovp => Condition::Parity,
+            M::Jnp | M::Setnp | M::Cmovnp => Condition::NotParity,
+            M::Jl | M::Setl | M::Cmovl => Condition::Less,
+            M::Jge | M::Setge | M::Cmovge => Condition::GreaterOrEqual,
+            M::Jle | M::Setle | M::Cmovle => Condition::LessOrEqual,
+            M::Jg | M::Setg | M::Cmovg => Condition::Greater,
+            _ => return None,
+        })
+    }
+
+    /// Whether the condition holds. Only the flags it depends on are read, which for `Flags`
+    /// means only those are worked out
+    pub fn holds(&self, flags: &impl AsRFlags) -> bool {
+        let flag = |flag| flags.is_set(flag);
+        let holds = match self.code() >> 1 {
+            0 => flag(RFlags::Overflow),
+            1 => flag(RFlags::Carry),
+            2 => flag(RFlags::Zero),
+            3 => flag(RFlags::Carry) || flag(RFlags::Zero),
+            4 => flag(RFlags::Sign),
+            5 => flag(RFlags::Parity),
+            6 => flag(RFlags::Sign) != flag(RFlags::Overflow),
+            _ => flag(RFlags::Zero) || flag(RFlags::Sign) != flag(RFlags::Overflow),
+        };
+        // odd codes are the negation of the one before them
+        holds != (self.code() & 1 != 0)
+    }
+}
@@ -47,8 +47,8 @@ impl SystemFunction {
                 (*ptr)(machine);
             }
             SystemFunction::Pointer(ptr) => {
+                // execution carries on from here once the current instruction is done
                 machine.set_instruction_counter(*ptr)
-                // machine.resume()
             }
         }
     }
@@ -57,5 +57,35 @@ impl SystemFunction {
 #[derive(Debug, Clone, Copy)]
 pub struct InterruptVector(pub(crate) [SystemFunction; 255]);
 
+impl InterruptVector {
+    pub fn empty() -> InterruptVector {
+        InterruptVector([SystemFunction::default(); 255])
+    }
+
+    /// Handler for an interrupt vector, `Unimplemented` for vectors out of range
+    pub fn get(&self, vector: usize) -> SystemFunction {
+        self.0.get(vector).copied().unwrap_or_default()
+    }
+
+    pub fn set(&mut self, vector: usize, function: SystemFunction) {
+        self.0[vector] = function;
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct SyscallVector(pub(crate) [SystemFunction; 1024]);
+
+impl SyscallVector {
+    pub fn empty() -> SyscallVector {
+        SyscallVector([SystemFunction::default(); 1024])
+    }
+
+    /// Function for a syscall number, `Unimplemented` for numbers out of range
+    pub fn get(&self, number: usize) -> SystemFunction {
+        self.0.get(number).copied().unwrap_or_default()
+    }
+
+    pub fn set(&mut self, number: usize, function: SystemFunction) {
+        self.0[number] = function;
+    }
+}
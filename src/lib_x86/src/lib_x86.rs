@@ -9,13 +9,19 @@ pub mod register_aliases;
 pub mod registers;
 pub mod x86;
 pub mod builders;
+pub mod cpuid;
+mod softfloat;
+pub mod execute;
 
 pub mod prelude {
+    pub use crate::cpuid::*;
     pub use crate::flags::*;
+    pub use crate::execute::*;
     pub use crate::functions::*;
     pub use crate::memory::*;
     pub use crate::types::*;
     pub use crate::x86::*;
+    pub use lib_opcode::registers::Register;
 }
 
 pub mod types {
@@ -1,35 +1,62 @@
+use crate::execute::mask;
 use crate::registers::Registers;
+use lib_opcode::prelude::{Bitness, Decoder, Formatter};
 use lib_types::error::VmRuntimeError;
 use lib_types::memory::ByteUnits;
+#[cfg(target_arch = "x86_64")]
+use std::arch::asm;
 use std::fmt;
-use std::ops::Deref;
+use std::fmt::Write;
+use std::slice;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
-#[derive(Clone)]
-pub struct ContiguousMemory(Vec<u8>);
+/// Guest memory, one contiguous block of bytes
+///
+/// A machine normally has its memory to itself. `share` hands the same bytes to other machines,
+/// which can then run on other threads as the virtual CPUs of one guest. The accesses x86 makes
+/// atomic, loads and stores of up to 8 bytes that stay within an aligned quadword, are host
+/// atomics here too, acquire loads and release stores as x86 orders them, and `compare_exchange`
+/// is what locked instructions use. Anything else is done a quadword at a time, which is also
+/// all x86 promises for it. Reads always go through the atomics, and so do writes while the
+/// bytes are shared; a machine with its memory to itself writes them as a plain slice
+pub struct ContiguousMemory(Arc<Bytes>);
 
-impl Deref for ContiguousMemory {
-    type Target = Vec<u8>;
+/// 16 bytes, aligned for CMPXCHG16B
+#[repr(C, align(16))]
+struct Line([AtomicU64; 2]);
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
+struct Bytes {
+    lines: Box<[Line]>,
+    len: usize,
+    /// Serialises the locked accesses no host atomic instruction covers
+    bus_lock: Mutex<()>,
 }
 
 impl fmt::Debug for ContiguousMemory {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        lib_utils::format_truncated_hex(f, &self.0)
+        lib_utils::format_truncated_hex(f, &self.to_vec())
     }
 }
 
 impl fmt::Display for ContiguousMemory {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        lib_utils::format_truncated_hex(f, &self.0)
+        lib_utils::format_truncated_hex(f, &self.to_vec())
+    }
+}
+
+impl Clone for ContiguousMemory {
+    /// A copy of the bytes that is not shared with anything, see `share` for that
+    fn clone(&self) -> Self {
+        let mut memory = Self::with_size(&ByteUnits::Bytes(self.len() as u64));
+        memory.write(0, &self.to_vec()).expect("same size");
+        memory
     }
 }
 
 #[derive(Debug, Clone, Default)]
 pub(crate) struct Fpu {
-    pub(crate) registers: Registers<80>, /* 8 x 80 bit registers, represented by u8s*/
+    pub(crate) registers: Registers<80>, /* 8 x 80 bit registers, represented by u8s. Aliases: MMn = low 64 bits of physical register n */
     pub(crate) control_register: Registers<{ 16 / 8 }>, /* 1x16 bit registers, represented by u8s*/
     pub(crate) status_register: Registers<{ 16 / 8 }>, /* 1x16 bit registers, represented by u8s*/
     pub(crate) tag_register: Registers<{ 16 / 8 }>, /* 1x16 bit registers, represented by u8s*/
@@ -39,84 +66,295 @@ pub(crate) struct Fpu {
 }
 impl ContiguousMemory {
     pub fn with_size(size: &ByteUnits) -> Self {
-        Self(vec![0; size.num_bytes() as usize])
+        let len = size.num_bytes() as usize;
+        // SAFETY: all zeros is a valid `AtomicU64`
+        let lines = unsafe { Box::<[Line]>::new_zeroed_slice(len.div_ceil(16)).assume_init() };
+        Self(Arc::new(Bytes {
+            lines,
+            len,
+            bus_lock: Mutex::new(()),
+        }))
     }
 
-    pub fn write(&mut self, addr: usize, data: &[u8]) -> Result<(), VmRuntimeError> {
-        // todo IMPORTANT this needs tests to make sure it doesnt have off by 1 or range inclusive/ exclusive errors
+    /// Another handle on the same bytes, for a machine that runs alongside this one
+    pub fn share(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+
+    /// Whether another machine may be accessing the bytes
+    pub fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.0) > 1
+    }
 
-        let len = data.len();
-        let allocated_len = self.0.len();
+    pub fn len(&self) -> usize {
+        self.0.len
+    }
 
-        let total = addr + len;
-        if total > allocated_len {
-            return Err(VmRuntimeError::OutOfBoundsError {
-                address: (addr + len) as u64,
-            });
-        }
+    pub fn is_empty(&self) -> bool {
+        self.0.len == 0
+    }
 
-        let _: Vec<_> = self
-            .0
-            .splice(addr..addr + len, data.iter().cloned())
-            .collect();
+    /// A copy of all the bytes
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut bytes = vec![0; self.0.len];
+        self.copy_out(0, &mut bytes);
+        bytes
+    }
 
-        let new_len = self.0.len();
+    /// The bytes as a plain slice, if no other machine holds them
+    fn exclusive(&mut self) -> Option<&mut [u8]> {
+        let bytes = Arc::get_mut(&mut self.0)?;
+        // SAFETY: the lines are `len` bytes and more of plain integers, and nothing else can
+        // reach them while this borrows the only handle mutably
+        Some(unsafe { slice::from_raw_parts_mut(bytes.lines.as_mut_ptr().cast(), bytes.len) })
+    }
 
-        if new_len > allocated_len {
-            println!("second");
-            Err(VmRuntimeError::OutOfBoundsError {
-                address: (addr + len) as u64,
-            })
-        } else {
-            Ok(())
+    /// The quadword `size` bytes from `addr` are in, and how far up it they start. `None` if
+    /// they cross into the next one
+    fn word(&self, addr: usize, size: usize) -> Option<(&AtomicU64, usize)> {
+        let offset = addr % 8;
+        match offset + size <= 8 {
+            true => Some((&self.0.lines[addr / 16].0[addr / 8 % 2], offset * 8)),
+            false => None,
         }
     }
-    pub fn write_rev(&mut self, addr: usize, data: &[u8]) -> Result<(), VmRuntimeError> {
-        let len = data.len();
-        let allocated_len = self.0.len();
-        let total = addr + len;
 
-        if total > allocated_len {
-            return Err(VmRuntimeError::OutOfBoundsError {
-                address: total as u64,
-            });
+    /// The quadwords the `len` bytes from `addr` are in, each with how far into it they start,
+    /// how many of them it holds and how far into the range those are
+    fn words(&self, addr: usize, len: usize) -> impl Iterator<Item = (&AtomicU64, usize, usize, usize)> {
+        let mut at = 0;
+        std::iter::from_fn(move || {
+            if at == len {
+                return None;
+            }
+            let offset = (addr + at) % 8;
+            let count = (8 - offset).min(len - at);
+            let (word, _) = self.word(addr + at, count).expect("within one quadword");
+            let item = (word, offset, count, at);
+            at += count;
+            Some(item)
+        })
+    }
+
+    /// Copies bytes already checked to be in bounds out of memory, a quadword at a time
+    fn copy_out(&self, addr: usize, data: &mut [u8]) {
+        for (word, offset, count, at) in self.words(addr, data.len()) {
+            let bytes = word.load(Ordering::Acquire).to_le_bytes();
+            data[at..at + count].copy_from_slice(&bytes[offset..offset + count]);
         }
+    }
 
-        // Write bytes in reverse order
-        for (i, &byte) in data.iter().rev().enumerate() {
-            self.0[addr + i] = byte;
+    /// Copies bytes already checked to be in bounds into memory, a quadword at a time, leaving
+    /// the rest of a quadword that is only partly written to whoever else writes it
+    fn copy_in(&self, addr: usize, data: &[u8]) {
+        for (word, offset, count, at) in self.words(addr, data.len()) {
+            let mut bytes = [0; 8];
+            bytes[offset..offset + count].copy_from_slice(&data[at..at + count]);
+            let (value, written) = (u64::from_le_bytes(bytes), mask(count) << (offset * 8));
+            match count {
+                8 => word.store(value, Ordering::Release),
+                _ => {
+                    let _ = word.fetch_update(Ordering::Release, Ordering::Relaxed, |old| Some(old & !written | value));
+                }
+            }
         }
+    }
 
+    fn check(&self, addr: usize, len: usize) -> Result<(), VmRuntimeError> {
+        match addr.checked_add(len).is_some_and(|end| end <= self.0.len) {
+            true => Ok(()),
+            false => Err(VmRuntimeError::OutOfBoundsError {
+                address: addr.wrapping_add(len) as u64,
+            }),
+        }
+    }
+
+    pub fn write(&mut self, addr: usize, data: &[u8]) -> Result<(), VmRuntimeError> {
+        // todo IMPORTANT this needs tests to make sure it doesnt have off by 1 or range inclusive/ exclusive errors
+        self.check(addr, data.len())?;
+        match self.exclusive() {
+            Some(bytes) => bytes[addr..addr + data.len()].copy_from_slice(data),
+            None => self.copy_in(addr, data),
+        }
         Ok(())
     }
 
+    pub fn write_rev(&mut self, addr: usize, data: &[u8]) -> Result<(), VmRuntimeError> {
+        let reversed: Vec<u8> = data.iter().rev().copied().collect();
+        self.write(addr, &reversed)
+    }
 
-    pub fn read(&self, addr: usize, len: usize) -> Result<&[u8], VmRuntimeError> {
-        let max = self.0.len();
+    /// A copy of the `len` bytes from `addr`
+    pub fn read(&self, addr: usize, len: usize) -> Result<Vec<u8>, VmRuntimeError> {
+        self.check(addr, len)?;
+        let mut bytes = vec![0; len];
+        self.copy_out(addr, &mut bytes);
+        Ok(bytes)
+    }
 
-        if addr + len > max {
+    pub fn read_byte(&self, addr: usize) -> Result<u8, VmRuntimeError> {
+        let max = self.len();
+        if addr >= max {
             return Err(VmRuntimeError::OutOfBoundsError {
-                address: (addr + len) as u64,
+                address: addr as u64,
             });
         }
 
-        let v: &[u8] = self.0[addr..addr + len].as_ref();
+        Ok(self.load(addr, 1)? as u8)
+    }
 
-        Ok(v)
+    /// Little endian value of `size` bytes, up to 8, from `addr`
+    pub fn load(&self, addr: usize, size: usize) -> Result<u64, VmRuntimeError> {
+        self.check(addr, size)?;
+        let value = match self.word(addr, size) {
+            Some((word, shift)) => word.load(Ordering::Acquire) >> shift,
+            None => {
+                let mut bytes = [0; 8];
+                self.copy_out(addr, &mut bytes[..size]);
+                u64::from_le_bytes(bytes)
+            }
+        };
+        Ok(value & mask(size))
     }
 
-    pub fn read_byte(&self, addr: usize) -> Result<u8, VmRuntimeError> {
-        let max = self.0.len();
-        if addr > max {
-            return Err(VmRuntimeError::OutOfBoundsError {
-                address: addr as u64,
-            });
+    /// Stores the low `size` bytes of `value`, up to 8, at `addr`
+    pub fn store(&mut self, addr: usize, size: usize, value: u64) -> Result<(), VmRuntimeError> {
+        self.check(addr, size)?;
+        let shared = self.is_shared();
+        let Some((word, shift)) = self.word(addr, size) else {
+            return self.write(addr, &value.to_le_bytes()[..size]);
+        };
+        let merge = |old: u64| old & !(mask(size) << shift) | (value & mask(size)) << shift;
+        match shared {
+            // the rest of the quadword may be being written too
+            true => {
+                let _ = word.fetch_update(Ordering::Release, Ordering::Relaxed, |old| Some(merge(old)));
+            }
+            false => word.store(merge(word.load(Ordering::Relaxed)), Ordering::Release),
+        }
+        Ok(())
+    }
+
+    /// Stores `new` in the `size` bytes, up to 8, at `addr` if they hold `current`. Gives back
+    /// what they held, as `Ok` if it was exchanged
+    ///
+    /// Bytes that cross into the next quadword are exchanged holding a lock, so they are only
+    /// atomic with respect to other locked accesses that have to do the same
+    pub fn compare_exchange(&mut self, addr: usize, size: usize, current: u64, new: u64) -> Result<Result<u64, u64>, VmRuntimeError> {
+        self.check(addr, size)?;
+        let (current, new) = (current & mask(size), new & mask(size));
+        let Some((word, shift)) = self.word(addr, size) else {
+            let _bus = self.0.bus_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let held = self.load(addr, size)?;
+            if held == current {
+                self.copy_in(addr, &new.to_le_bytes()[..size]);
+            }
+            return Ok(if held == current { Ok(held) } else { Err(held) });
+        };
+        let exchanged = word.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |old| {
+            (old >> shift & mask(size) == current).then(|| old & !(mask(size) << shift) | new << shift)
+        });
+        Ok(exchanged.map(|old| old >> shift & mask(size)).map_err(|old| old >> shift & mask(size)))
+    }
+
+    /// `compare_exchange` of the 16 bytes at `addr`, which has to be 16 byte aligned
+    pub fn compare_exchange_16(&mut self, addr: usize, current: u128, new: u128) -> Result<Result<u128, u128>, VmRuntimeError> {
+        self.check(addr, 16)?;
+        assert!(addr.is_multiple_of(16), "CMPXCHG16B operands are aligned");
+        let held = self.cmpxchg16b(&self.0.lines[addr / 16], current, new);
+        Ok(if held == current { Ok(held) } else { Err(held) })
+    }
+
+    /// The host's CMPXCHG16B, giving back what the line held
+    #[cfg(target_arch = "x86_64")]
+    fn cmpxchg16b(&self, line: &Line, current: u128, new: u128) -> u128 {
+        let (mut low, mut high) = (current as u64, (current >> 64) as u64);
+        // SAFETY: the line is 16 byte aligned and its atomics allow it to be written through a
+        // shared reference. RBX cannot be an operand, so the low half of `new` is swapped
+        // into it around the instruction
+        unsafe {
+            asm!(
+                "xchg {new_low}, rbx",
+                "lock cmpxchg16b xmmword ptr [{line}]",
+                "mov rbx, {new_low}",
+                line = in(reg) line.0.as_ptr(),
+                new_low = inout(reg) new as u64 => _,
+                in("rcx") (new >> 64) as u64,
+                inout("rax") low,
+                inout("rdx") high,
+                options(nostack),
+            );
+        }
+        (high as u128) << 64 | low as u128
+    }
+
+    /// Other hosts have no 16 byte compare-and-swap, so the line is exchanged holding a lock,
+    /// atomic with respect to other CMPXCHG16Bs only
+    #[cfg(not(target_arch = "x86_64"))]
+    fn cmpxchg16b(&self, line: &Line, current: u128, new: u128) -> u128 {
+        let _bus = self.0.bus_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let [low, high] = &line.0;
+        let held = (high.load(Ordering::SeqCst) as u128) << 64 | low.load(Ordering::SeqCst) as u128;
+        if held == current {
+            low.store(new as u64, Ordering::SeqCst);
+            high.store((new >> 64) as u64, Ordering::SeqCst);
         }
+        held
+    }
 
-        Ok(*(self.0.get(addr).expect("range already checked")))
+    /// Copies `len` bytes from `from` to `to` in one go. The ranges may overlap, the result is
+    /// as if the source had been read in full before writing (`memmove`)
+    pub fn copy(&mut self, from: usize, to: usize, len: usize) -> Result<(), VmRuntimeError> {
+        for start in [from, to] {
+            self.check(start, len)?;
+        }
+        match self.exclusive() {
+            Some(bytes) => bytes.copy_within(from..from + len, to),
+            None => {
+                let mut bytes = vec![0; len];
+                self.copy_out(from, &mut bytes);
+                self.copy_in(to, &bytes);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets `len` bytes from `addr` to `byte`
+    pub fn fill(&mut self, addr: usize, len: usize, byte: u8) -> Result<(), VmRuntimeError> {
+        self.check(addr, len)?;
+        match self.exclusive() {
+            Some(bytes) => bytes[addr..addr + len].fill(byte),
+            None => self.copy_in(addr, &vec![byte; len]),
+        }
+        Ok(())
     }
 
     pub fn dump_hex(&self) -> String {
-        lib_utils::dump_hex(&self.0)
+        lib_utils::dump_hex(&self.to_vec())
+    }
+
+    /// Disassembles up to `count` instructions starting at `addr`, one per line as
+    /// `address  bytes  text`. Stops early at the end of memory or at bytes that do not decode
+    pub fn disassemble(&self, addr: usize, count: usize, bitness: Bitness, formatter: &Formatter) -> String {
+        let mut out = String::new();
+        // no instruction is longer than 15 bytes
+        let len = count.saturating_mul(15).min(self.len().saturating_sub(addr));
+        let code = self.read(addr, len).unwrap_or_default();
+        let mut decoder = Decoder::new(bitness, &code, addr as u64);
+        for _ in 0..count {
+            let ip = decoder.ip();
+            let start = (ip - addr as u64) as usize;
+            let Some(result) = decoder.next() else { break };
+            let (length, text) = match &result {
+                Ok(opcode) => (opcode.length as usize, formatter.format(opcode)),
+                Err(e) => (1, format!("(bad: {e})")),
+            };
+            let bytes: Vec<String> = code[start..start + length].iter().map(|b| format!("{b:02X}")).collect();
+            writeln!(out, "{ip:016X}  {:<30}  {text}", bytes.join(" ")).expect("shouldnt fail writing");
+            if result.is_err() {
+                break;
+            }
+        }
+        out
     }
 }
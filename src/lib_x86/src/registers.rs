@@ -44,7 +44,7 @@ impl<const N: usize> Registers<N> {
             });
         }
 
-        if width % 8 != 0 {
+        if !width.is_multiple_of(8) {
             return Err(VmRuntimeError::InvalidAlias { offset, width });
         }
 
@@ -59,7 +59,7 @@ impl<const N: usize> Registers<N> {
                     });
                 }
 
-                if start % w != 0 {
+                if !start.is_multiple_of(*w) {
                     return Err(VmRuntimeError::InvalidAlias { offset, width });
                 }
             }
@@ -82,7 +82,7 @@ impl<const N: usize> Registers<N> {
         #[cfg(feature = "safety_checks")]
         {
             let width = alias.width;
-            if width % 8 != 0 {
+            if !width.is_multiple_of(8) {
                 return Err(VmRuntimeError::InvalidAlias { offset, width });
             }
             
@@ -101,9 +101,7 @@ impl<const N: usize> Registers<N> {
             Ok(())
         }
         #[cfg(not(feature = "safety_checks"))]
-        {
-            ()
-        }
+        {}
     }
 
     pub fn write_u16(
@@ -124,9 +122,7 @@ impl<const N: usize> Registers<N> {
             Ok(())
         }
         #[cfg(not(feature = "safety_checks"))]
-        {
-            ()
-        }
+        {}
     }
 
     pub fn write_u32(&mut self, alias: Alias, val: u32) -> SafetyResult<()> {
@@ -143,9 +139,7 @@ impl<const N: usize> Registers<N> {
             Ok(())
         }
         #[cfg(not(feature = "safety_checks"))]
-        {
-            ()
-        }
+        {}
     }
 
     pub fn write_u64(&mut self, alias: Alias, val: u64) -> SafetyResult<()>{
@@ -162,9 +156,7 @@ impl<const N: usize> Registers<N> {
             Ok(())
         }
         #[cfg(not(feature = "safety_checks"))]
-        {
-            ()
-        }
+        {}
     }
 
 
@@ -184,9 +176,7 @@ impl<const N: usize> Registers<N> {
             Ok(())
         }
         #[cfg(not(feature = "safety_checks"))]
-        {
-            ()
-        }
+        {}
     }
 
     pub fn new(width: RegisterWidth) -> Self {
@@ -200,7 +190,7 @@ impl<const N: usize> Registers<N> {
         #[cfg(feature = "safety_checks")]
         {
             let width = alias.width;
-            if width % 8 != 0 {
+            if !width.is_multiple_of(8) {
                 return Err(VmRuntimeError::InvalidAlias { offset, width });
             }
 
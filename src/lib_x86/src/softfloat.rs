@@ -0,0 +1,921 @@
+//! Software IEEE 754 binary floating point for the SSE and x87 units
+//!
+//! Host floating point only rounds to nearest and does not report exceptions, so any arithmetic
+//! the guest can observe is done here instead. Values are passed around as their encodings in
+//! the low bits of a `u128`, in one of the `Format`s. Every operation takes an `Environment`
+//! with the rounding mode and the DAZ / FTZ switches, and ORs the exceptions it raises into
+//! `Environment::flags`, which use the MXCSR (and x87 status word) bit positions.
+//!
+//! As on x86, tininess is detected after rounding and a NaN result is the first NaN operand
+//! made quiet.
+use std::cmp::Ordering;
+
+mod transcendental;
+
+pub(crate) use transcendental::{atan2, constant, exp2_minus_one, sin_cos, tan, y_log2_x, Constant};
+
+pub(crate) const INVALID: u8 = 1 << 0;
+pub(crate) const DENORMAL: u8 = 1 << 1;
+pub(crate) const DIVIDE_BY_ZERO: u8 = 1 << 2;
+pub(crate) const OVERFLOW: u8 = 1 << 3;
+pub(crate) const UNDERFLOW: u8 = 1 << 4;
+pub(crate) const PRECISION: u8 = 1 << 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Rounding {
+    Nearest,
+    Down,
+    Up,
+    TowardZero,
+}
+
+impl Rounding {
+    /// From the two bit rounding control field of MXCSR or the x87 control word
+    pub(crate) fn from_bits(bits: u32) -> Rounding {
+        match bits & 3 {
+            0 => Rounding::Nearest,
+            1 => Rounding::Down,
+            2 => Rounding::Up,
+            _ => Rounding::TowardZero,
+        }
+    }
+}
+
+/// A binary interchange format: sign, biased exponent and fraction, from the top bit down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Format {
+    exponent_bits: u32,
+    /// significand bits, including the integer bit
+    precision: u32,
+    /// whether the integer bit is stored, as it is for the x87 extended format
+    explicit_integer: bool,
+}
+
+pub(crate) const HALF: Format = Format { exponent_bits: 5, precision: 11, explicit_integer: false };
+pub(crate) const SINGLE: Format = Format { exponent_bits: 8, precision: 24, explicit_integer: false };
+pub(crate) const DOUBLE: Format = Format { exponent_bits: 11, precision: 53, explicit_integer: false };
+pub(crate) const EXTENDED: Format = Format { exponent_bits: 15, precision: 64, explicit_integer: true };
+
+impl Format {
+    /// Width of the encoding
+    pub(crate) const fn bits(&self) -> u32 {
+        1 + self.exponent_bits + self.fraction_bits()
+    }
+
+    const fn fraction_bits(&self) -> u32 {
+        match self.explicit_integer {
+            true => self.precision,
+            false => self.precision - 1,
+        }
+    }
+
+    const fn fraction_mask(&self) -> u128 {
+        (1 << self.fraction_bits()) - 1
+    }
+
+    const fn bias(&self) -> i32 {
+        (1 << (self.exponent_bits - 1)) - 1
+    }
+
+    const fn max_exponent(&self) -> u128 {
+        (1 << self.exponent_bits) - 1
+    }
+
+    const fn integer_bit(&self) -> u128 {
+        match self.explicit_integer {
+            true => 1 << (self.precision - 1),
+            false => 0,
+        }
+    }
+
+    const fn quiet_bit(&self) -> u128 {
+        1 << (self.precision - 2)
+    }
+
+    const fn sign(&self, sign: bool) -> u128 {
+        (sign as u128) << (self.bits() - 1)
+    }
+
+    pub(crate) const fn is_negative(&self, bits: u128) -> bool {
+        bits >> (self.bits() - 1) & 1 != 0
+    }
+
+    pub(crate) const fn zero(&self, sign: bool) -> u128 {
+        self.sign(sign)
+    }
+
+    pub(crate) const fn infinity(&self, sign: bool) -> u128 {
+        self.sign(sign) | self.max_exponent() << self.fraction_bits() | self.integer_bit()
+    }
+
+    /// The QNaN an invalid operation gives when no operand is a NaN, the "indefinite"
+    pub(crate) const fn default_nan(&self) -> u128 {
+        self.infinity(true) | self.quiet_bit()
+    }
+
+    /// The largest finite value with a significand of `precision` bits
+    const fn largest(&self, sign: bool, precision: u32) -> u128 {
+        let dropped = (1 << (self.precision - precision)) - 1;
+        self.sign(sign) | (self.max_exponent() - 1) << self.fraction_bits() | self.fraction_mask() & !dropped
+    }
+
+    /// The largest finite value
+    pub(crate) const fn max_finite(&self, sign: bool) -> u128 {
+        self.largest(sign, self.precision)
+    }
+
+    pub(crate) const fn is_nan(&self, bits: u128) -> bool {
+        let exponent = bits >> self.fraction_bits() & self.max_exponent();
+        exponent == self.max_exponent() && bits & self.fraction_mask() & !self.integer_bit() != 0
+    }
+
+    pub(crate) const fn is_signaling(&self, bits: u128) -> bool {
+        self.is_nan(bits) && bits & self.quiet_bit() == 0
+    }
+
+    pub(crate) const fn quiet(&self, bits: u128) -> u128 {
+        bits | self.quiet_bit()
+    }
+}
+
+/// Rounding mode and switches an operation runs under, and the exceptions it raised
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Environment {
+    pub(crate) rounding: Rounding,
+    /// DAZ, denormal operands are read as zero without raising `DENORMAL`
+    pub(crate) denormals_are_zero: bool,
+    /// FTZ, tiny results become zero when underflow is masked
+    pub(crate) flush_to_zero: bool,
+    /// A masked underflow is only raised when the tiny result is also inexact
+    pub(crate) underflow_masked: bool,
+    /// x87 precision control, the significand bits to round to when fewer than the format has.
+    /// The exponent range stays that of the format
+    pub(crate) precision: Option<u32>,
+    pub(crate) flags: u8,
+    /// Whether the last inexact result was rounded away from zero, for x87 C1
+    pub(crate) rounded_up: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Value {
+    Zero(bool),
+    /// `significand * 2^exponent`
+    Finite { sign: bool, exponent: i32, significand: u128, denormal: bool },
+    Infinity(bool),
+    Nan,
+}
+
+fn unpack(f: Format, bits: u128) -> Value {
+    let sign = f.is_negative(bits);
+    let exponent = (bits >> f.fraction_bits() & f.max_exponent()) as i32;
+    let fraction = bits & f.fraction_mask();
+    let shift = f.precision as i32 - 1;
+    match exponent {
+        _ if f.is_nan(bits) => Value::Nan,
+        _ if exponent as u128 == f.max_exponent() => Value::Infinity(sign),
+        0 if fraction == 0 => Value::Zero(sign),
+        0 => Value::Finite { sign, exponent: 1 - f.bias() - shift, significand: fraction, denormal: true },
+        _ => Value::Finite {
+            sign,
+            exponent: exponent - f.bias() - shift,
+            significand: fraction | 1 << shift,
+            denormal: false,
+        },
+    }
+}
+
+/// An operand once DAZ has been applied
+fn flushed(f: Format, bits: u128, env: &Environment) -> Value {
+    match unpack(f, bits) {
+        Value::Finite { sign, denormal: true, .. } if env.denormals_are_zero => Value::Zero(sign),
+        value => value,
+    }
+}
+
+/// As `flushed`, raising `DENORMAL` for a denormal that is kept
+fn operand(f: Format, bits: u128, env: &mut Environment) -> Value {
+    let value = flushed(f, bits, env);
+    if let Value::Finite { denormal: true, .. } = value {
+        env.flags |= DENORMAL;
+    }
+    value
+}
+
+fn negate(value: Value) -> Value {
+    match value {
+        Value::Zero(sign) => Value::Zero(!sign),
+        Value::Infinity(sign) => Value::Infinity(!sign),
+        Value::Finite { sign, exponent, significand, denormal } => Value::Finite { sign: !sign, exponent, significand, denormal },
+        Value::Nan => Value::Nan,
+    }
+}
+
+fn invalid(f: Format, env: &mut Environment) -> u128 {
+    env.flags |= INVALID;
+    f.default_nan()
+}
+
+/// The result when either operand is a NaN: the first one, made quiet. `INVALID` if either is
+/// signaling
+fn propagate(f: Format, a: u128, b: u128, env: &mut Environment) -> Option<u128> {
+    if f.is_signaling(a) || f.is_signaling(b) {
+        env.flags |= INVALID;
+    }
+    match (f.is_nan(a), f.is_nan(b)) {
+        (true, _) => Some(f.quiet(a)),
+        (_, true) => Some(f.quiet(b)),
+        _ => None,
+    }
+}
+
+/// `significand >> shift` rounded, and whether anything was lost. A negative shift is a left
+/// shift
+fn round_bits(significand: u128, shift: i32, sign: bool, rounding: Rounding) -> (u128, bool) {
+    if shift <= 0 {
+        return (significand << -shift, false);
+    }
+    let (kept, rest, half) = match shift {
+        1..128 => (significand >> shift, significand & ((1 << shift) - 1), 1 << (shift - 1)),
+        128 => (0, significand, 1 << 127),
+        // past the top of any significand, so well under half
+        _ => (0, significand, u128::MAX),
+    };
+    let up = match rounding {
+        Rounding::Nearest => rest > half || rest == half && kept & 1 == 1,
+        Rounding::TowardZero => false,
+        Rounding::Up => !sign && rest != 0,
+        Rounding::Down => sign && rest != 0,
+    };
+    (kept + up as u128, rest != 0)
+}
+
+/// Rounds `significand * 2^exponent` (not zero) to the format, or to fewer bits of
+/// significand under x87 precision control
+fn round_pack(f: Format, sign: bool, exponent: i32, significand: u128, env: &mut Environment) -> u128 {
+    let full = f.precision as i32;
+    let precision = env.precision.map_or(full, |bits| (bits as i32).min(full));
+    let top = 127 - significand.leading_zeros() as i32;
+    // exponent of the leading bit
+    let mut exponent = exponent + top;
+    let min_exponent = 1 - f.bias();
+
+    let (unbounded, _) = round_bits(significand, top - (precision - 1), sign, env.rounding);
+    let tiny = exponent + ((unbounded >> precision) as i32) < min_exponent;
+    if tiny && env.flush_to_zero && env.underflow_masked {
+        env.flags |= UNDERFLOW | PRECISION;
+        return f.zero(sign);
+    }
+
+    // rounded where the format's denormals or the precision end, whichever is higher, and
+    // then lined up as a significand of the full format
+    let aligned = top - (full - 1) + (min_exponent - exponent).max(0);
+    let shift = aligned.max(top - (precision - 1));
+    let (kept, inexact) = round_bits(significand, shift, sign, env.rounding);
+    env.rounded_up = kept != round_bits(significand, shift, sign, Rounding::TowardZero).0;
+    let mut kept = kept << (shift - aligned);
+    exponent = exponent.max(min_exponent);
+    if kept >> full != 0 {
+        kept >>= 1;
+        exponent += 1;
+    }
+    if tiny && (inexact || !env.underflow_masked) {
+        env.flags |= UNDERFLOW;
+    }
+    if inexact {
+        env.flags |= PRECISION;
+    }
+    if exponent > f.bias() {
+        env.flags |= OVERFLOW | PRECISION;
+        return match (env.rounding, sign) {
+            (Rounding::Nearest, _) | (Rounding::Up, false) | (Rounding::Down, true) => {
+                env.rounded_up = true;
+                f.infinity(sign)
+            }
+            _ => {
+                env.rounded_up = false;
+                f.largest(sign, precision as u32)
+            }
+        };
+    }
+    // a denormal that rounded up to the smallest normal gets its exponent here
+    let biased = match kept >> (full - 1) {
+        0 => 0,
+        _ => (exponent + f.bias()) as u128,
+    };
+    f.sign(sign) | biased << f.fraction_bits() | kept & f.fraction_mask()
+}
+
+/// Shifts the significand up so that its leading bit is bit 63
+fn normalize(exponent: i32, significand: u128) -> (i32, u128) {
+    let shift = significand.leading_zeros() as i32 - 64;
+    (exponent - shift, significand << shift)
+}
+
+pub(crate) fn add(f: Format, a: u128, b: u128, env: &mut Environment) -> u128 {
+    sum(f, a, b, false, env)
+}
+
+pub(crate) fn sub(f: Format, a: u128, b: u128, env: &mut Environment) -> u128 {
+    sum(f, a, b, true, env)
+}
+
+fn sum(f: Format, a: u128, b: u128, subtract: bool, env: &mut Environment) -> u128 {
+    if let Some(nan) = propagate(f, a, b, env) {
+        return nan;
+    }
+    let x = operand(f, a, env);
+    let y = match subtract {
+        true => negate(operand(f, b, env)),
+        false => operand(f, b, env),
+    };
+    let (big, small) = match (x, y) {
+        (Value::Infinity(s), Value::Infinity(t)) if s != t => return invalid(f, env),
+        (Value::Infinity(sign), _) | (_, Value::Infinity(sign)) => return f.infinity(sign),
+        (Value::Zero(s), Value::Zero(t)) => return f.zero(if s == t { s } else { env.rounding == Rounding::Down }),
+        (Value::Zero(_), Value::Finite { sign, exponent, significand, .. })
+        | (Value::Finite { sign, exponent, significand, .. }, Value::Zero(_)) => {
+            // exact, but a denormal still has to go through FTZ
+            return round_pack(f, sign, exponent, significand, env);
+        }
+        (Value::Finite { sign: s, exponent: e, significand: m, .. }, Value::Finite { sign: t, exponent: g, significand: n, .. }) => {
+            match e >= g {
+                true => ((s, e, m), (t, g, n)),
+                false => ((t, g, n), (s, e, m)),
+            }
+        }
+        _ => unreachable!("NaNs have been dealt with"),
+    };
+    // 62 bits below the larger operand keep the sum exact until the smaller one is shifted past
+    // them, and from then on a sticky bit is all rounding needs
+    let distance = (big.1 - small.1) as u32;
+    let aligned = small.2 << 62;
+    let aligned = match distance {
+        0..128 => aligned >> distance | (aligned & ((1 << distance) - 1) != 0) as u128,
+        _ => 1,
+    };
+    let large = big.2 << 62;
+    let (sign, significand) = match (big.0 == small.0, large >= aligned) {
+        (true, _) => (big.0, large + aligned),
+        (false, true) => (big.0, large - aligned),
+        (false, false) => (small.0, aligned - large),
+    };
+    match significand {
+        0 => f.zero(env.rounding == Rounding::Down),
+        _ => round_pack(f, sign, big.1 - 62, significand, env),
+    }
+}
+
+/// `a * b + c` with a single rounding, for the FMA instructions on `SINGLE` and `DOUBLE`. The
+/// product and the addend can be negated first, for the FMSUB, FNMADD and FNMSUB forms. A NaN
+/// operand gives the first of them made quiet, and infinity times zero is invalid even when
+/// the addend is a quiet NaN
+pub(crate) fn fused_multiply_add(
+    f: Format,
+    (a, b, c): (u128, u128, u128),
+    negate_product: bool,
+    negate_addend: bool,
+    env: &mut Environment,
+) -> u128 {
+    let infinity_times_zero = matches!(
+        (flushed(f, a, env), flushed(f, b, env)),
+        (Value::Infinity(_), Value::Zero(_)) | (Value::Zero(_), Value::Infinity(_))
+    );
+    if let Some(nan) = [a, b, c].into_iter().find(|&bits| f.is_nan(bits)) {
+        if infinity_times_zero || [a, b, c].into_iter().any(|bits| f.is_signaling(bits)) {
+            env.flags |= INVALID;
+        }
+        return f.quiet(nan);
+    }
+    let (x, y) = (operand(f, a, env), operand(f, b, env));
+    let z = match negate_addend {
+        true => negate(operand(f, c, env)),
+        false => operand(f, c, env),
+    };
+    let sign = (f.is_negative(a) != f.is_negative(b)) != negate_product;
+    let product = match (x, y) {
+        _ if infinity_times_zero => return invalid(f, env),
+        (Value::Infinity(_), _) | (_, Value::Infinity(_)) => Value::Infinity(sign),
+        (Value::Zero(_), _) | (_, Value::Zero(_)) => Value::Zero(sign),
+        (Value::Finite { exponent: e, significand: m, .. }, Value::Finite { exponent: g, significand: n, .. }) => {
+            Value::Finite { sign, exponent: e + g, significand: m * n, denormal: false }
+        }
+        _ => unreachable!("NaNs have been dealt with"),
+    };
+    let (big, small) = match (product, z) {
+        (Value::Infinity(s), Value::Infinity(t)) if s != t => return invalid(f, env),
+        (Value::Infinity(sign), _) | (_, Value::Infinity(sign)) => return f.infinity(sign),
+        (Value::Zero(s), Value::Zero(t)) => return f.zero(if s == t { s } else { env.rounding == Rounding::Down }),
+        (Value::Zero(_), Value::Finite { sign, exponent, significand, .. })
+        | (Value::Finite { sign, exponent, significand, .. }, Value::Zero(_)) => {
+            return round_pack(f, sign, exponent, significand, env);
+        }
+        (Value::Finite { sign: s, exponent: e, significand: m, .. }, Value::Finite { sign: t, exponent: g, significand: n, .. }) => {
+            // both with their leading bit at bit 125, which leaves room for the carry of the
+            // sum. A double product has 106 bits, so the smaller operand is exact until it
+            // is shifted far enough that only a sticky bit matters, as in `sum`
+            let top = |e: i32, m: u128| {
+                let shift = m.leading_zeros() as i32 - 2;
+                (e - shift, m << shift)
+            };
+            let ((e, m), (g, n)) = (top(e, m), top(g, n));
+            match (e, m) >= (g, n) {
+                true => ((s, e, m), (t, g, n)),
+                false => ((t, g, n), (s, e, m)),
+            }
+        }
+        _ => unreachable!("NaNs have been dealt with"),
+    };
+    let distance = (big.1 - small.1) as u32;
+    let aligned = match distance {
+        0..128 => small.2 >> distance | (small.2 & ((1 << distance) - 1) != 0) as u128,
+        _ => 1,
+    };
+    let (sign, significand) = match big.0 == small.0 {
+        true => (big.0, big.2 + aligned),
+        false => (big.0, big.2 - aligned),
+    };
+    match significand {
+        0 => f.zero(env.rounding == Rounding::Down),
+        _ => round_pack(f, sign, big.1, significand, env),
+    }
+}
+
+pub(crate) fn mul(f: Format, a: u128, b: u128, env: &mut Environment) -> u128 {
+    if let Some(nan) = propagate(f, a, b, env) {
+        return nan;
+    }
+    let (x, y) = (operand(f, a, env), operand(f, b, env));
+    let sign = f.is_negative(a) != f.is_negative(b);
+    match (x, y) {
+        (Value::Infinity(_), Value::Zero(_)) | (Value::Zero(_), Value::Infinity(_)) => invalid(f, env),
+        (Value::Infinity(_), _) | (_, Value::Infinity(_)) => f.infinity(sign),
+        (Value::Zero(_), _) | (_, Value::Zero(_)) => f.zero(sign),
+        (Value::Finite { exponent: e, significand: m, .. }, Value::Finite { exponent: g, significand: n, .. }) => {
+            round_pack(f, sign, e + g, m * n, env)
+        }
+        _ => unreachable!("NaNs have been dealt with"),
+    }
+}
+
+pub(crate) fn div(f: Format, a: u128, b: u128, env: &mut Environment) -> u128 {
+    if let Some(nan) = propagate(f, a, b, env) {
+        return nan;
+    }
+    let raised = env.flags;
+    let (x, y) = (operand(f, a, env), operand(f, b, env));
+    let sign = f.is_negative(a) != f.is_negative(b);
+    match (x, y) {
+        (Value::Infinity(_), Value::Infinity(_)) | (Value::Zero(_), Value::Zero(_)) => invalid(f, env),
+        (Value::Infinity(_), _) => f.infinity(sign),
+        (_, Value::Infinity(_)) | (Value::Zero(_), _) => f.zero(sign),
+        (_, Value::Zero(_)) => {
+            // and not a denormal dividend as well
+            env.flags = raised | DIVIDE_BY_ZERO;
+            f.infinity(sign)
+        }
+        (Value::Finite { exponent: e, significand: m, .. }, Value::Finite { exponent: g, significand: n, .. }) => {
+            let (e, m) = normalize(e, m);
+            let (g, n) = normalize(g, n);
+            // 64 or 65 quotient bits, two more from the remainder and a sticky bit
+            let numerator = m << 64;
+            let (quotient, remainder) = (numerator / n, numerator % n);
+            let (more, remainder) = ((remainder << 2) / n, (remainder << 2) % n);
+            let significand = quotient << 3 | more << 1 | (remainder != 0) as u128;
+            round_pack(f, sign, e - g - 67, significand, env)
+        }
+        _ => unreachable!("NaNs have been dealt with"),
+    }
+}
+
+pub(crate) fn sqrt(f: Format, a: u128, env: &mut Environment) -> u128 {
+    if f.is_nan(a) {
+        if f.is_signaling(a) {
+            env.flags |= INVALID;
+        }
+        return f.quiet(a);
+    }
+    // a negative denormal is invalid before it is a denormal
+    if let Value::Finite { sign: true, .. } = flushed(f, a, env) {
+        return invalid(f, env);
+    }
+    match operand(f, a, env) {
+        Value::Zero(sign) => f.zero(sign),
+        Value::Infinity(false) => f.infinity(false),
+        Value::Infinity(true) | Value::Finite { sign: true, .. } => invalid(f, env),
+        Value::Finite { exponent, significand, .. } => {
+            let (mut exponent, mut significand) = normalize(exponent, significand);
+            if exponent.rem_euclid(2) == 1 {
+                exponent -= 1;
+                significand <<= 1;
+            }
+            // 34 more pairs of zero bits give a root of at least 66 bits, enough to round
+            // the widest format, and whatever is left over is the sticky bit
+            const PAIRS: i32 = 34;
+            let (mut root, mut remainder) = (0u128, 0u128);
+            for pair in (0..33 + PAIRS).rev() {
+                let bits = match pair >= PAIRS {
+                    true => significand >> (2 * (pair - PAIRS)) & 3,
+                    false => 0,
+                };
+                remainder = remainder << 2 | bits;
+                let trial = root << 2 | 1;
+                root <<= 1;
+                if remainder >= trial {
+                    remainder -= trial;
+                    root |= 1;
+                }
+            }
+            round_pack(f, false, exponent / 2 - PAIRS - 1, root << 1 | (remainder != 0) as u128, env)
+        }
+        Value::Nan => unreachable!("NaNs have been dealt with"),
+    }
+}
+
+/// The encoding as an integer that orders the same way as the values, with both zeros (and
+/// denormals under DAZ) at 0
+fn ordered(f: Format, bits: u128, env: &mut Environment) -> i128 {
+    let magnitude = match operand(f, bits, env) {
+        Value::Zero(_) => 0,
+        _ => (bits & !f.sign(true)) as i128,
+    };
+    match f.is_negative(bits) {
+        true => -magnitude,
+        false => magnitude,
+    }
+}
+
+/// `None` when unordered. A signaling comparison raises `INVALID` for any NaN, a quiet one only
+/// for a signaling NaN
+pub(crate) fn compare(f: Format, a: u128, b: u128, signaling: bool, env: &mut Environment) -> Option<Ordering> {
+    if f.is_nan(a) || f.is_nan(b) {
+        if signaling || f.is_signaling(a) || f.is_signaling(b) {
+            env.flags |= INVALID;
+        }
+        return None;
+    }
+    let (x, y) = (ordered(f, a, env), ordered(f, b, env));
+    Some(x.cmp(&y))
+}
+
+/// MINPS / MAXPS rather than IEEE minimum / maximum: the second operand when either is a NaN
+/// (raising `INVALID` even for a quiet one) or when they compare equal, so that
+/// `min(-0, +0)` is +0
+pub(crate) fn select(f: Format, a: u128, b: u128, maximum: bool, env: &mut Environment) -> u128 {
+    let chosen = match f.is_nan(a) || f.is_nan(b) {
+        true => {
+            env.flags |= INVALID;
+            b
+        }
+        false => {
+            let (x, y) = (ordered(f, a, env), ordered(f, b, env));
+            match maximum && x > y || !maximum && x < y {
+                true => a,
+                false => b,
+            }
+        }
+    };
+    // under DAZ a denormal comes out as the zero it was read as
+    match flushed(f, chosen, env) {
+        Value::Zero(sign) => f.zero(sign),
+        _ => chosen,
+    }
+}
+
+/// VRANGE: the lesser or, with bit 0 of `control`, the greater of `a` and `b`, by value or by
+/// magnitude (bit 1), with -0 below +0. Bits 3:2 take the sign from the one chosen or from
+/// `a`, or make it positive or negative. A NaN in either gives the first NaN quieted
+pub(crate) fn range(f: Format, a: u128, b: u128, control: u8, env: &mut Environment) -> u128 {
+    if let Some(nan) = propagate(f, a, b, env) {
+        return nan;
+    }
+    let (x, y) = (ordered(f, a, env), ordered(f, b, env));
+    let (x, y) = match control & 2 {
+        0 => ((x, !f.is_negative(a)), (y, !f.is_negative(b))),
+        _ => ((x.abs(), false), (y.abs(), false)),
+    };
+    let chosen = match (control & 1 != 0, x <= y) {
+        (false, true) | (true, false) => a,
+        _ => b,
+    };
+    // under DAZ a denormal comes out as the zero it was read as
+    let chosen = match flushed(f, chosen, env) {
+        Value::Zero(sign) => f.zero(sign),
+        _ => chosen,
+    };
+    let magnitude = chosen & !f.sign(true);
+    match control >> 2 & 3 {
+        0 => chosen,
+        1 => magnitude | a & f.sign(true),
+        2 => magnitude,
+        _ => magnitude | f.sign(true),
+    }
+}
+
+/// To a `bits` wide signed integer, 32 or 64. NaNs, infinities and anything out of range raise
+/// `INVALID` and give the integer indefinite, `1 << (bits - 1)`
+pub(crate) fn to_int(f: Format, a: u128, bits: u32, rounding: Rounding, env: &mut Environment) -> u64 {
+    let indefinite = 1u64 << (bits - 1);
+    let (sign, magnitude, inexact) = integral(f, a, rounding, env);
+    let limit = 1u128 << (bits - 1);
+    if magnitude > limit || magnitude == limit && !sign {
+        env.flags |= INVALID;
+        return indefinite;
+    }
+    if inexact {
+        env.flags |= PRECISION;
+    }
+    let value = match sign {
+        true => (magnitude as u64).wrapping_neg(),
+        false => magnitude as u64,
+    };
+    value & (u64::MAX >> (64 - bits))
+}
+
+/// To a `bits` wide unsigned integer, 32 or 64. Negative values that do not round to zero raise
+/// `INVALID` as NaNs, infinities and anything too large do, and give all ones
+pub(crate) fn to_uint(f: Format, a: u128, bits: u32, rounding: Rounding, env: &mut Environment) -> u64 {
+    let limit = u64::MAX >> (64 - bits);
+    let (sign, magnitude, inexact) = integral(f, a, rounding, env);
+    if magnitude > limit as u128 || sign && magnitude != 0 {
+        env.flags |= INVALID;
+        return limit;
+    }
+    if inexact {
+        env.flags |= PRECISION;
+    }
+    magnitude as u64
+}
+
+/// The sign, magnitude rounded to an integer and whether that was inexact, with a magnitude of
+/// `u128::MAX` for NaNs, infinities and anything far out of range
+fn integral(f: Format, a: u128, rounding: Rounding, env: &mut Environment) -> (bool, u128, bool) {
+    match flushed(f, a, env) {
+        Value::Zero(sign) => (sign, 0, false),
+        Value::Finite { sign, exponent: exponent @ ..65, significand, .. } => {
+            let (magnitude, inexact) = round_bits(significand, -exponent, sign, rounding);
+            (sign, magnitude, inexact)
+        }
+        _ => (false, u128::MAX, false),
+    }
+}
+
+pub(crate) fn from_int(f: Format, value: i64, env: &mut Environment) -> u128 {
+    match value {
+        0 => f.zero(false),
+        _ => round_pack(f, value < 0, 0, value.unsigned_abs() as u128, env),
+    }
+}
+
+pub(crate) fn from_uint(f: Format, value: u64, env: &mut Environment) -> u128 {
+    match value {
+        0 => f.zero(false),
+        _ => round_pack(f, false, 0, value as u128, env),
+    }
+}
+
+/// Between formats. A NaN keeps the top of its payload
+pub(crate) fn convert(from: Format, to: Format, a: u128, env: &mut Environment) -> u128 {
+    if from.is_nan(a) {
+        if from.is_signaling(a) {
+            env.flags |= INVALID;
+        }
+        let payload = a & ((from.quiet_bit() << 1) - 1);
+        let payload = match to.precision >= from.precision {
+            true => payload << (to.precision - from.precision),
+            false => payload >> (from.precision - to.precision),
+        };
+        return to.default_nan() & !to.sign(true) | to.sign(from.is_negative(a)) | payload;
+    }
+    match operand(from, a, env) {
+        Value::Zero(sign) => to.zero(sign),
+        Value::Infinity(sign) => to.infinity(sign),
+        Value::Finite { sign, exponent, significand, .. } => round_pack(to, sign, exponent, significand, env),
+        Value::Nan => unreachable!("NaNs have been dealt with"),
+    }
+}
+
+/// What an encoding holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Class {
+    Zero,
+    Denormal,
+    Normal,
+    Infinity,
+    Nan,
+    /// an extended encoding whose integer bit disagrees with its exponent: unnormals,
+    /// pseudo-infinities and pseudo-NaNs. Pseudo-denormals read as denormals
+    Unsupported,
+}
+
+pub(crate) fn classify(f: Format, bits: u128) -> Class {
+    let exponent = bits >> f.fraction_bits() & f.max_exponent();
+    let integer = f.integer_bit() == 0 || bits & f.integer_bit() != 0;
+    match exponent {
+        0 if bits & f.fraction_mask() == 0 => Class::Zero,
+        0 => Class::Denormal,
+        _ if !integer => Class::Unsupported,
+        _ if f.is_nan(bits) => Class::Nan,
+        _ if exponent == f.max_exponent() => Class::Infinity,
+        _ => Class::Normal,
+    }
+}
+
+/// To an integer in the same format, FRNDINT and ROUNDSS, raising `PRECISION` if that changed
+/// the value
+pub(crate) fn round_to_integral(f: Format, a: u128, rounding: Rounding, env: &mut Environment) -> u128 {
+    round_to_fraction(f, a, 0, rounding, env)
+}
+
+/// To a multiple of `2^-bits` in the same format, as VRNDSCALE does with `bits` from its
+/// immediate
+pub(crate) fn round_to_fraction(f: Format, a: u128, bits: i32, rounding: Rounding, env: &mut Environment) -> u128 {
+    if f.is_nan(a) {
+        if f.is_signaling(a) {
+            env.flags |= INVALID;
+        }
+        return f.quiet(a);
+    }
+    match operand(f, a, env) {
+        Value::Finite { sign, exponent, significand, .. } if exponent < -bits => {
+            let shift = -bits - exponent;
+            let (kept, inexact) = round_bits(significand, shift, sign, rounding);
+            if inexact {
+                env.flags |= PRECISION;
+            }
+            env.rounded_up = kept << shift.min(127) > significand;
+            match kept {
+                0 => f.zero(sign),
+                _ => round_pack(f, sign, -bits, kept, &mut Environment { flags: 0, ..*env }),
+            }
+        }
+        Value::Zero(sign) => f.zero(sign),
+        _ => a,
+    }
+}
+
+/// The partial remainder of FPREM (`nearest` false, the quotient truncated) and FPREM1 (the
+/// quotient rounded to nearest even), with the low three bits of the quotient, or `None` for
+/// them when the exponents are 64 or more apart. Then only part of the reduction is done, by a
+/// quotient truncated to its top 63 bits, and the instruction is repeated to finish. The
+/// result is exact
+pub(crate) fn remainder(f: Format, a: u128, b: u128, nearest: bool, env: &mut Environment) -> (u128, Option<u8>) {
+    if let Some(nan) = propagate(f, a, b, env) {
+        return (nan, Some(0));
+    }
+    let (x, y) = (operand(f, a, env), operand(f, b, env));
+    let (sign, e, m, g, n) = match (x, y) {
+        (Value::Infinity(_), _) | (_, Value::Zero(_)) => return (invalid(f, env), Some(0)),
+        (Value::Zero(_), _) | (_, Value::Infinity(_)) => return (a, Some(0)),
+        (Value::Finite { sign, exponent: e, significand: m, .. }, Value::Finite { exponent: g, significand: n, .. }) => {
+            let ((e, m), (g, n)) = (normalize(e, m), normalize(g, n));
+            (sign, e, m, g, n)
+        }
+        _ => unreachable!("NaNs have been dealt with"),
+    };
+    let distance = e - g;
+    if distance >= 64 {
+        // the quotient's top bits, in units of 2^(distance - 63) divisors
+        let (_, remainder) = ((m << 63) / n, (m << 63) % n);
+        let result = match remainder {
+            0 => f.zero(sign),
+            _ => round_pack(f, sign, e - 63, remainder, env),
+        };
+        return (result, None);
+    }
+    if distance < -1 || distance < 0 && !nearest {
+        return (round_pack(f, sign, e, m, env), Some(0));
+    }
+    // both as integers in units of the lower exponent
+    let low = e.min(g);
+    let (dividend, divisor) = (m << (e - low), n << (g - low));
+    let (mut quotient, mut remainder) = (dividend / divisor, dividend % divisor);
+    let mut sign = sign;
+    if nearest && (remainder * 2 > divisor || remainder * 2 == divisor && quotient & 1 == 1) {
+        quotient += 1;
+        remainder = divisor - remainder;
+        sign = !sign;
+    }
+    let result = match remainder {
+        0 => f.zero(f.is_negative(a)),
+        _ => round_pack(f, sign, low, remainder, env),
+    };
+    (result, Some(quotient as u8 & 7))
+}
+
+/// FSCALE, `a * 2^n`
+pub(crate) fn scale(f: Format, a: u128, n: i32, env: &mut Environment) -> u128 {
+    if f.is_nan(a) {
+        if f.is_signaling(a) {
+            env.flags |= INVALID;
+        }
+        return f.quiet(a);
+    }
+    match operand(f, a, env) {
+        Value::Finite { sign, exponent, significand, .. } => round_pack(f, sign, exponent.saturating_add(n), significand, env),
+        _ => a,
+    }
+}
+
+/// VSCALEF, `a * 2^floor(b)`. An infinite `b` takes `a` to an infinity or a zero, which is
+/// invalid for a zero or an infinity going the other way
+pub(crate) fn scale_by(f: Format, a: u128, b: u128, env: &mut Environment) -> u128 {
+    if let Some(nan) = propagate(f, a, b, env) {
+        return nan;
+    }
+    let (x, y) = (operand(f, a, env), operand(f, b, env));
+    let sign = f.is_negative(a);
+    let n = match y {
+        Value::Zero(_) => 0,
+        Value::Finite { sign, exponent, significand, .. } => {
+            // anything past 2^20 over- or underflows every format
+            let magnitude = match exponent {
+                ..=20 => round_bits(significand, -exponent, sign, Rounding::Down).0.min(1 << 20) as i32,
+                _ => 1 << 20,
+            };
+            match sign {
+                true => -magnitude,
+                false => magnitude,
+            }
+        }
+        Value::Infinity(negative) => {
+            return match (x, negative) {
+                (Value::Zero(_), false) | (Value::Infinity(_), true) => invalid(f, env),
+                (_, true) => f.zero(sign),
+                (_, false) => f.infinity(sign),
+            };
+        }
+        Value::Nan => unreachable!("NaNs have been dealt with"),
+    };
+    match x {
+        Value::Finite { exponent, significand, .. } => round_pack(f, sign, exponent + n, significand, env),
+        Value::Zero(_) => f.zero(sign),
+        Value::Infinity(_) => f.infinity(sign),
+        Value::Nan => unreachable!("NaNs have been dealt with"),
+    }
+}
+
+/// VGETEXP, `floor(log2(|a|))` as a value of the format: minus infinity for a zero (without
+/// the divide by zero of IEEE logB) and infinity for an infinity
+pub(crate) fn logb(f: Format, a: u128, env: &mut Environment) -> u128 {
+    if f.is_nan(a) {
+        if f.is_signaling(a) {
+            env.flags |= INVALID;
+        }
+        return f.quiet(a);
+    }
+    match operand(f, a, env) {
+        Value::Zero(_) => f.infinity(true),
+        Value::Infinity(_) => f.infinity(false),
+        Value::Finite { exponent, significand, .. } => {
+            let (exponent, _) = normalize(exponent, significand);
+            from_int(f, exponent as i64 + 63, &mut Environment { flags: 0, ..*env })
+        }
+        Value::Nan => unreachable!("NaNs have been dealt with"),
+    }
+}
+
+/// VGETMANT, the significand of `a` in the interval bits 1:0 of `control` pick: [1, 2),
+/// [1/2, 2) taking off an even power of two, [1/2, 1) or [3/4, 3/2). Bit 2 makes the result
+/// positive and bit 3 makes a negative `a` invalid. Zeros and infinities give 1.0
+pub(crate) fn mantissa(f: Format, a: u128, control: u8, env: &mut Environment) -> u128 {
+    if f.is_nan(a) {
+        if f.is_signaling(a) {
+            env.flags |= INVALID;
+        }
+        return f.quiet(a);
+    }
+    let sign = f.is_negative(a) && control & 4 == 0;
+    let one = f.sign(sign) | (f.bias() as u128) << f.fraction_bits() | f.integer_bit();
+    match unpack(f, a) {
+        Value::Zero(_) | Value::Infinity(_) => return one,
+        _ if f.is_negative(a) && control & 8 != 0 => return invalid(f, env),
+        _ => {}
+    }
+    match operand(f, a, env) {
+        Value::Finite { exponent, significand, .. } => {
+            let (exponent, significand) = normalize(exponent, significand);
+            let halved = match control & 3 {
+                0 => false,
+                1 => (exponent + 63) & 1 != 0,
+                2 => true,
+                _ => significand >> 62 & 1 != 0,
+            };
+            round_pack(f, sign, -63 - halved as i32, significand, &mut Environment { flags: 0, ..*env })
+        }
+        // a denormal read as zero
+        _ => one,
+    }
+}
+
+/// FXTRACT of a finite value that is not zero: its exponent as a value of the format, and its
+/// significand with the exponent of 1.0
+pub(crate) fn extract(f: Format, a: u128, env: &mut Environment) -> (u128, u128) {
+    match operand(f, a, env) {
+        Value::Finite { sign, exponent, significand, .. } => {
+            let (exponent, significand) = normalize(exponent, significand);
+            let mut exact = Environment { flags: 0, ..*env };
+            (from_int(f, exponent as i64 + 63, &mut exact), round_pack(f, sign, -63, significand, &mut exact))
+        }
+        _ => unreachable!("only finite values that are not zero are extracted"),
+    }
+}
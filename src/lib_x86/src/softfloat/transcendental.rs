@@ -0,0 +1,481 @@
+//! The x87 transcendental instructions and constants
+//!
+//! These are evaluated on a 128 bit significand, twice what the extended format keeps, and
+//! rounded once at the end, so results are within an ulp of the true value. Range reduction
+//! for FSIN / FCOS / FSINCOS / FPTAN is by the 66 bit approximation of pi the hardware uses,
+//! exactly, so large arguments reduce as they do on a real x87.
+use super::{invalid, operand, round_pack, Environment, Rounding, Value, DIVIDE_BY_ZERO, EXTENDED};
+use std::cmp::Ordering;
+
+/// `significand * 2^exponent`, with the leading bit of the significand at bit 127, or zero
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Wide {
+    sign: bool,
+    exponent: i32,
+    significand: u128,
+}
+
+/// pi to 128 bits
+const PI: Wide = Wide { sign: false, exponent: -126, significand: 0xc90fdaa22168c234c4c6628b80dc1cd1 };
+const LN_2: Wide = Wide { sign: false, exponent: -128, significand: 0xb17217f7d1cf79abc9e3b39803f2f6af };
+const LOG2_10: Wide = Wide { sign: false, exponent: -126, significand: 0xd49a784bcd1b8afe492bf6ff4dafdb4c };
+const LOG2_E: Wide = Wide { sign: false, exponent: -127, significand: 0xb8aa3b295c17f0bbbe87fed0691d3e88 };
+const LOG10_2: Wide = Wide { sign: false, exponent: -129, significand: 0x9a209a84fbcff7988f8959ac0b7c9178 };
+/// pi / 2 as the 66 bits the x87 reduces arguments by
+const HALF_PI_66: Wide = Wide { sign: false, exponent: -127, significand: 0xc90fdaa22168c234c << 60 };
+
+impl Wide {
+    const ZERO: Wide = Wide { sign: false, exponent: 0, significand: 0 };
+    const ONE: Wide = Wide { sign: false, exponent: -127, significand: 1 << 127 };
+
+    fn new(sign: bool, exponent: i32, significand: u128) -> Wide {
+        match significand {
+            0 => Wide { sign, ..Wide::ZERO },
+            _ => {
+                let shift = significand.leading_zeros() as i32;
+                Wide { sign, exponent: exponent - shift, significand: significand << shift }
+            }
+        }
+    }
+
+    fn from_value(value: Value) -> Wide {
+        match value {
+            Value::Finite { sign, exponent, significand, .. } => Wide::new(sign, exponent, significand),
+            _ => Wide::ZERO,
+        }
+    }
+
+    fn from_int(value: i64) -> Wide {
+        Wide::new(value < 0, 0, value.unsigned_abs() as u128)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.significand == 0
+    }
+
+    /// Exponent of the leading bit
+    fn magnitude(&self) -> i32 {
+        self.exponent + 127
+    }
+
+    fn negate(self) -> Wide {
+        Wide { sign: !self.sign, ..self }
+    }
+
+    fn abs(self) -> Wide {
+        Wide { sign: false, ..self }
+    }
+
+    fn scale(self, n: i32) -> Wide {
+        Wide { exponent: self.exponent + n, ..self }
+    }
+
+    fn compare_magnitude(&self, other: &Wide) -> Ordering {
+        match (self.is_zero(), other.is_zero()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            _ => (self.exponent, self.significand).cmp(&(other.exponent, other.significand)),
+        }
+    }
+
+    /// With bits shifted out kept as a sticky lowest bit
+    fn add(self, other: Wide) -> Wide {
+        let (big, small) = match self.compare_magnitude(&other) {
+            Ordering::Less => (other, self),
+            _ => (self, other),
+        };
+        if small.is_zero() {
+            return big;
+        }
+        // one bit of headroom for the carry
+        let shift = (big.exponent - small.exponent + 1) as u32;
+        let a = big.significand >> 1 | big.significand & 1;
+        let b = match shift {
+            0..128 => small.significand >> shift | (small.significand & ((1 << shift) - 1) != 0) as u128,
+            _ => 1,
+        };
+        match big.sign == small.sign {
+            true => Wide::new(big.sign, big.exponent + 1, a + b),
+            false => Wide::new(big.sign, big.exponent + 1, a - b),
+        }
+    }
+
+    fn sub(self, other: Wide) -> Wide {
+        self.add(other.negate())
+    }
+
+    /// The top 128 bits of the 256 bit product, sticky
+    fn mul(self, other: Wide) -> Wide {
+        if self.is_zero() || other.is_zero() {
+            return Wide { sign: self.sign != other.sign, ..Wide::ZERO };
+        }
+        let (a1, a0) = (self.significand >> 64, self.significand & u64::MAX as u128);
+        let (b1, b0) = (other.significand >> 64, other.significand & u64::MAX as u128);
+        let (low, middle_a, middle_b, high) = (a0 * b0, a1 * b0, a0 * b1, a1 * b1);
+        let (middle, carry) = middle_a.overflowing_add(middle_b);
+        let (low, carry_low) = low.overflowing_add(middle << 64);
+        let high = high + (middle >> 64) + ((carry as u128) << 64) + carry_low as u128;
+        Wide::new(self.sign != other.sign, self.exponent + other.exponent + 128, high | (low != 0) as u128)
+    }
+
+    /// Bit by bit long division, sticky
+    fn div(self, other: Wide) -> Wide {
+        if self.is_zero() {
+            return Wide { sign: self.sign != other.sign, ..Wide::ZERO };
+        }
+        let (mut remainder, divisor) = (self.significand, other.significand);
+        let (mut quotient, mut carry) = (0u128, false);
+        for _ in 0..128 {
+            let bit = carry || remainder >= divisor;
+            if bit {
+                remainder = remainder.wrapping_sub(divisor);
+            }
+            quotient = quotient << 1 | bit as u128;
+            carry = remainder >> 127 != 0;
+            remainder <<= 1;
+        }
+        let sticky = (remainder != 0 || carry) as u128;
+        Wide::new(self.sign != other.sign, self.exponent - other.exponent - 127, quotient | sticky)
+    }
+
+    /// Newton's method from a double precision estimate, of a positive value
+    fn sqrt(self) -> Wide {
+        if self.is_zero() {
+            return self;
+        }
+        // an even exponent so that it halves exactly
+        let odd = self.magnitude() & 1;
+        let leading = (self.significand >> 75) as f64 / (1u64 << 52) as f64 * (1 + odd) as f64;
+        let estimate = leading.sqrt();
+        let mut root = Wide::new(false, (self.magnitude() - odd) / 2 - 52, (estimate * (1u64 << 52) as f64) as u128);
+        for _ in 0..3 {
+            root = root.add(self.div(root)).scale(-1);
+        }
+        root
+    }
+
+    /// Towards zero, saturating at `limit`
+    fn trunc(&self, limit: i64) -> i64 {
+        let magnitude = match self.magnitude() {
+            m if m < 0 || self.is_zero() => 0,
+            m if m >= 63 => limit,
+            m => ((self.significand >> (127 - m)) as i64).min(limit),
+        };
+        match self.sign {
+            true => -magnitude,
+            false => magnitude,
+        }
+    }
+
+    fn round(self, env: &mut Environment) -> u128 {
+        match self.is_zero() {
+            true => EXTENDED.zero(self.sign),
+            false => round_pack(EXTENDED, self.sign, self.exponent, self.significand, env),
+        }
+    }
+}
+
+/// Sums `term(k)` for k from `first` on until the terms stop mattering
+fn series(first: u32, mut term: impl FnMut(u32) -> Wide) -> Wide {
+    let mut sum = Wide::ZERO;
+    for k in first.. {
+        let next = term(k);
+        if next.is_zero() || !sum.is_zero() && next.magnitude() < sum.magnitude() - 130 {
+            break;
+        }
+        sum = sum.add(next);
+    }
+    sum
+}
+
+/// e^t - 1 for |t| below 1
+fn exp_minus_one(t: Wide) -> Wide {
+    let mut power = Wide::ONE;
+    series(1, |k| {
+        power = power.mul(t).div(Wide::from_int(k as i64));
+        power
+    })
+}
+
+/// atanh(s), for |s| well below 1
+fn atanh(s: Wide) -> Wide {
+    if s.is_zero() {
+        return s;
+    }
+    let square = s.mul(s);
+    let mut power = s.div(square);
+    series(0, |k| {
+        power = power.mul(square);
+        power.div(Wide::from_int(2 * k as i64 + 1))
+    })
+}
+
+/// log2 of a positive value
+fn log2(x: Wide) -> Wide {
+    // x = m * 2^e with m between sqrt(1/2) and sqrt(2), ln(m) = 2 atanh((m - 1) / (m + 1))
+    let mut e = x.magnitude();
+    let mut m = x.scale(-e);
+    if m.significand > 0xb504f333f9de6484597d89b3754abe9f {
+        m = m.scale(-1);
+        e += 1;
+    }
+    let ln = atanh(m.sub(Wide::ONE).div(m.add(Wide::ONE))).scale(1);
+    Wide::from_int(e as i64).add(ln.div(LN_2))
+}
+
+/// log2(1 + x), accurate for small x
+fn log2_plus_one(x: Wide) -> Wide {
+    if x.magnitude() >= -1 {
+        return log2(Wide::ONE.add(x));
+    }
+    let ln = atanh(x.div(Wide::ONE.scale(1).add(x))).scale(1);
+    ln.div(LN_2)
+}
+
+/// sin and cos of an argument reduced to about pi / 4 either side of zero
+fn sin_cos_reduced(r: Wide) -> (Wide, Wide) {
+    let square = r.mul(r).negate();
+    let mut power = r;
+    let sin = r.add(series(1, |k| {
+        power = power.mul(square).div(Wide::from_int((2 * k * (2 * k + 1)) as i64));
+        power
+    }));
+    let mut power = Wide::ONE;
+    let cos = Wide::ONE.add(series(1, |k| {
+        power = power.mul(square).div(Wide::from_int(((2 * k - 1) * 2 * k) as i64));
+        power
+    }));
+    (sin, cos)
+}
+
+/// atan of a value from 0 to 1
+fn atan_reduced(a: Wide) -> Wide {
+    // atan(a) = 2 atan(a / (1 + sqrt(1 + a^2))), twice, leaves the series at most tan(pi / 16)
+    let mut a = a;
+    for _ in 0..2 {
+        a = a.div(Wide::ONE.add(Wide::ONE.add(a.mul(a)).sqrt()));
+    }
+    let square = a.mul(a).negate();
+    let mut power = a.div(square);
+    series(0, |k| {
+        power = power.mul(square);
+        power.div(Wide::from_int(2 * k as i64 + 1))
+    })
+    .scale(2)
+}
+
+/// A finite operand reduced by multiples of the hardware's pi / 2: the remainder and the
+/// quotient's low two bits. `None` when it is 2^63 or more in magnitude, out of range
+fn reduce(x: Wide) -> Option<(Wide, u8)> {
+    if x.magnitude() >= 63 {
+        return None;
+    }
+    let quotient = x.abs().div(HALF_PI_66).add(Wide::ONE.scale(-1)).trunc(i64::MAX);
+    if quotient == 0 {
+        return Some((x, 0));
+    }
+    // q * pi/2 in two halves of 33 bits, so that each product and difference is exact
+    let high = Wide { significand: HALF_PI_66.significand & !0 << 95, ..HALF_PI_66 };
+    let low = HALF_PI_66.sub(high);
+    let q = Wide::from_int(quotient);
+    let remainder = x.abs().sub(q.mul(high)).sub(q.mul(low));
+    let remainder = match x.sign {
+        true => remainder.negate(),
+        false => remainder,
+    };
+    let quadrant = match x.sign {
+        true => (4 - (quotient & 3) as u8) & 3,
+        false => (quotient & 3) as u8,
+    };
+    Some((remainder, quadrant))
+}
+
+/// The constants FLD1, FLDZ, FLDPI, FLDL2T, FLDL2E, FLDLG2 and FLDLN2 load
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Constant {
+    One,
+    Zero,
+    Pi,
+    Log2Ten,
+    Log2E,
+    Log10Two,
+    LnTwo,
+}
+
+/// A constant rounded under `rounding`. Loading one raises no exceptions
+pub(crate) fn constant(constant: Constant, rounding: Rounding) -> u128 {
+    let value = match constant {
+        Constant::One => Wide::ONE,
+        Constant::Zero => Wide::ZERO,
+        Constant::Pi => PI,
+        Constant::Log2Ten => LOG2_10,
+        Constant::Log2E => LOG2_E,
+        Constant::Log10Two => LOG10_2,
+        Constant::LnTwo => LN_2,
+    };
+    let mut env = Environment {
+        rounding,
+        denormals_are_zero: false,
+        flush_to_zero: false,
+        underflow_masked: true,
+        precision: None,
+        flags: 0,
+        rounded_up: false,
+    };
+    value.round(&mut env)
+}
+
+/// F2XM1, 2^x - 1 of a value that is not a NaN
+pub(crate) fn exp2_minus_one(a: u128, env: &mut Environment) -> u128 {
+    let x = match operand(EXTENDED, a, env) {
+        Value::Infinity(true) => return round_pack(EXTENDED, true, 0, 1, &mut Environment { flags: 0, ..*env }),
+        Value::Zero(_) | Value::Infinity(false) => return a,
+        value => Wide::from_value(value),
+    };
+    let result = match (x.sign, x.magnitude()) {
+        // far out of the exponent range, an overflow
+        (false, 20..) => Wide::ONE.scale(1 << 20),
+        // far below, -1 and a bit, to round
+        (true, 9..) => Wide::ONE.negate().add(Wide::ONE.scale(-600)),
+        _ => {
+            let whole = x.trunc(i64::MAX);
+            let fractional = exp_minus_one(x.sub(Wide::from_int(whole)).mul(LN_2));
+            match whole {
+                0 => fractional,
+                _ => fractional.add(Wide::ONE).scale(whole as i32).sub(Wide::ONE),
+            }
+        }
+    };
+    result.round(env)
+}
+
+/// FYL2X, `y * log2(x)`, and FYL2XP1, `y * log2(x + 1)`, of values that are not NaNs
+pub(crate) fn y_log2_x(x: u128, y: u128, plus_one: bool, env: &mut Environment) -> u128 {
+    let (a, b) = (operand(EXTENDED, x, env), operand(EXTENDED, y, env));
+    // where the logarithm is zero, its sign, and whether it is negative
+    let (log_zero, log_negative) = match (plus_one, a) {
+        (false, Value::Finite { sign: false, exponent, significand, .. }) => {
+            let one = Wide::new(false, exponent, significand).compare_magnitude(&Wide::ONE);
+            (one == Ordering::Equal, one == Ordering::Less)
+        }
+        (false, Value::Zero(_) | Value::Infinity(false)) => (false, matches!(a, Value::Zero(_))),
+        (true, Value::Zero(sign)) => (true, sign),
+        (true, Value::Finite { sign, exponent, significand, .. }) => {
+            let one = Wide::new(false, exponent, significand).compare_magnitude(&Wide::ONE);
+            match (sign, one) {
+                (true, Ordering::Greater) => return invalid(EXTENDED, env),
+                (true, Ordering::Equal) => (false, true),
+                _ => (false, sign),
+            }
+        }
+        (true, Value::Infinity(false)) => (false, false),
+        _ => return invalid(EXTENDED, env),
+    };
+    let log_infinite = match (plus_one, a) {
+        (false, Value::Zero(_) | Value::Infinity(_)) | (true, Value::Infinity(_)) => true,
+        (true, Value::Finite { sign: true, exponent, significand, .. }) => {
+            Wide::new(false, exponent, significand).compare_magnitude(&Wide::ONE) == Ordering::Equal
+        }
+        _ => false,
+    };
+    let sign = match b {
+        Value::Zero(sign) | Value::Infinity(sign) | Value::Finite { sign, .. } => sign != log_negative,
+        Value::Nan => unreachable!("NaNs have been dealt with"),
+    };
+    match b {
+        // 0 * infinity, or infinity * 0
+        Value::Zero(_) if log_infinite => invalid(EXTENDED, env),
+        Value::Infinity(_) if log_zero => invalid(EXTENDED, env),
+        Value::Zero(_) => EXTENDED.zero(sign),
+        Value::Infinity(_) => EXTENDED.infinity(sign),
+        _ if log_zero => EXTENDED.zero(sign),
+        _ if log_infinite => {
+            // the logarithm of zero, rather than of infinity
+            if !matches!(a, Value::Infinity(_)) {
+                env.flags |= DIVIDE_BY_ZERO;
+            }
+            EXTENDED.infinity(sign)
+        }
+        _ => {
+            let log = match plus_one {
+                true => log2_plus_one(Wide::from_value(a)),
+                false => log2(Wide::from_value(a)),
+            };
+            log.mul(Wide::from_value(b)).round(env)
+        }
+    }
+}
+
+/// FSINCOS, or the sine or cosine alone, of a value that is not a NaN. `None` when it is out
+/// of range and left as it is
+pub(crate) fn sin_cos(a: u128, env: &mut Environment) -> Option<(u128, u128)> {
+    let x = match operand(EXTENDED, a, env) {
+        Value::Infinity(_) => {
+            let nan = invalid(EXTENDED, env);
+            return Some((nan, nan));
+        }
+        Value::Zero(_) => return Some((a, Wide::ONE.round(env))),
+        value => Wide::from_value(value),
+    };
+    let (r, quadrant) = reduce(x)?;
+    let (sin, cos) = sin_cos_reduced(r);
+    let (sin, cos) = match quadrant {
+        0 => (sin, cos),
+        1 => (cos, sin.negate()),
+        2 => (sin.negate(), cos.negate()),
+        _ => (cos.negate(), sin),
+    };
+    // each is rounded on its own, so one's flags do not hide the other's C1
+    let mut cos_env = *env;
+    let cos = cos.round(&mut cos_env);
+    let sin = sin.round(env);
+    env.flags |= cos_env.flags;
+    Some((sin, cos))
+}
+
+/// FPTAN of a value that is not a NaN, `None` when out of range
+pub(crate) fn tan(a: u128, env: &mut Environment) -> Option<u128> {
+    let x = match operand(EXTENDED, a, env) {
+        Value::Infinity(_) => return Some(invalid(EXTENDED, env)),
+        Value::Zero(_) => return Some(a),
+        value => Wide::from_value(value),
+    };
+    let (r, quadrant) = reduce(x)?;
+    let (sin, cos) = sin_cos_reduced(r);
+    let tan = match quadrant & 1 {
+        0 => sin.div(cos),
+        _ => cos.div(sin).negate(),
+    };
+    Some(tan.round(env))
+}
+
+/// FPATAN, the angle of the point (x, y) from -pi to pi, of values that are not NaNs
+pub(crate) fn atan2(y: u128, x: u128, env: &mut Environment) -> u128 {
+    let (b, a) = (operand(EXTENDED, y, env), operand(EXTENDED, x, env));
+    let sign = EXTENDED.is_negative(y);
+    let negative_x = EXTENDED.is_negative(x);
+    let angle = match (b, a) {
+        (Value::Zero(_), _) if !negative_x => return EXTENDED.zero(sign),
+        (Value::Zero(_), _) => PI,
+        (Value::Infinity(_), Value::Infinity(_)) => match negative_x {
+            false => PI.scale(-2),
+            true => PI.mul(Wide::from_int(3)).scale(-2),
+        },
+        (Value::Infinity(_), _) | (_, Value::Zero(_)) => PI.scale(-1),
+        (_, Value::Infinity(false)) => return EXTENDED.zero(sign),
+        (_, Value::Infinity(true)) => PI,
+        _ => {
+            let (v, u) = (Wide::from_value(b).abs(), Wide::from_value(a).abs());
+            let angle = match v.compare_magnitude(&u) {
+                Ordering::Greater => PI.scale(-1).sub(atan_reduced(u.div(v))),
+                _ => atan_reduced(v.div(u)),
+            };
+            match negative_x {
+                true => PI.sub(angle),
+                false => angle,
+            }
+        }
+    };
+    Wide { sign, ..angle }.round(env)
+}
@@ -1,3 +1,5 @@
+use std::cell::Cell;
+use std::collections::HashSet;
 use std::ops::DerefMut;
 use crate::functions::{InterruptVector, SyscallVector};
 use crate::memory::{ContiguousMemory, Fpu};
@@ -5,8 +7,11 @@ use crate::registers::Registers;
 use lib_types::error::{SafetyResult, VmRuntimeError};
 use lib_types::memory::ByteUnits;
 use crate::builders::MachineBuilder;
+use crate::cpuid::CpuProfile;
+use crate::execute::{Exception, LockedAccess, StopReason};
+use crate::flags::Flags;
 use crate::register_aliases::Alias;
-use crate::x86::dto::MemWriteDto;
+use lib_opcode::prelude::{Bitness, Formatter, Register};
 
 /// Represents a virtual x86_64 lib
 ///
@@ -18,22 +23,43 @@ use crate::x86::dto::MemWriteDto;
 pub struct X86Machine {
     /// General purpose registers
     pub(crate) segment_pointers: Registers<{ (8 * 64) / 8 }>, /* 6 x 64 registers,  represented by u8s. 6 are specified, 2 extras added for padding to a pow2 */
-    pub(crate) mmx_registers: Registers<{ (8 * 64) / 8 }>, /*  8 x 64 registers, represented by u8s */
     pub(crate) gp_registers: Registers<{ (16 * 64) / 8 }>, /* 16 x 64 registers, represented by u8s */
-    pub(crate) xmm_registers: Registers<{ (16 * 128) / 8 }>, /* 16 x 128 registers, represented by u8s */
-    pub(crate) ymm_registers: Registers<{ (16 * 256) / 8 }>, /* 16 x 256 registers, represented by u8s */
-    pub(crate) bounds_registers: Registers<{ (4 * 128) / 8 }>, /* 4 x 128 registers, represented by u8s. Aliases: upper = BNDCFGU, lower = BNDSTATUS */
+    pub(crate) vector_registers: Registers<{ (32 * 512) / 8 }>, /* 32 x 512 registers, represented by u8s. Aliases: XMMn = low quarter of ZMMn, YMMn = low half of ZMMn */
+    pub(crate) opmask_registers: Registers<{ (8 * 64) / 8 }>, /*  8 x 64 registers, represented by u8s */
+    pub(crate) bounds_registers: Registers<{ (4 * 128) / 8 }>, /* 4 x 128 registers, represented by u8s. Lower bound in the low half, upper bound (one's complement) in the high half */
+    pub(crate) bound_config_registers: Registers<{ (2 * 64) / 8 }>, /* 2 x 64 registers, represented by u8s. BNDCFGU, then BNDSTATUS */
     pub(crate) mxcsr_register: Registers<{ 32 / 8 }>, /* 1 x 32 register, represented by u8s */
+    pub(crate) xcr0_register: Registers<{ 64 / 8 }>, /* 1 x 64 register, represented by u8s */
 
     pub(crate) fpu: Fpu,
 
     /// Bitfield flag, aka RFLAGS, EFLAGS
-    pub flags: u64,
+    pub flags: Flags,
 
     /// AKA RIP
     pub instruction_counter: u64,
 
-    pub stack_pointer: u64,
+    /// Instructions a `run` / `run_until` call may execute before it stops with
+    /// `StopReason::BudgetExhausted`. Unlimited if `None`
+    pub instruction_budget: Option<u64>,
+
+    /// Number of instructions that have completed
+    pub instructions_retired: u64,
+
+    /// Addresses `run` / `run_until` stop at before executing the instruction there
+    pub breakpoints: HashSet<u64>,
+
+    /// Set by intrinsics to stop the machine once the current instruction completes
+    pub(crate) pending_stop: Option<StopReason>,
+
+    /// Where a locked instruction is with memory other machines share, see `locked`
+    pub(crate) locked_access: Cell<Option<LockedAccess>>,
+
+    /// Current privilege level, 0 to 3. Guest code runs as user code (3) unless set otherwise
+    pub privilege_level: u8,
+
+    /// The processor CPUID describes
+    pub cpu_profile: CpuProfile,
 
     /// registers are represented as contiguous memory instead of u32/64s
     /// because some operations act on segments of a particular register
@@ -54,7 +80,14 @@ impl X86Machine {
         self.gp_registers.dump_hex()
     }
 
+    /// The next `count` instructions from the instruction counter, for traces
+    pub fn disassemble_at_ip(&self, count: usize, formatter: &Formatter) -> String {
+        self.memory
+            .disassemble(self.instruction_counter as usize, count, Bitness::Bits64, formatter)
+    }
+
     pub fn write_to_gp_registers(&mut self, alias:Alias, bytes: &[u8]) {
+        #[allow(clippy::let_unit_value)]
         let _ = self.gp_registers.write_bytes(alias, bytes);
     }
     pub fn load_binary(&mut self, data: &[u8]) -> Result<(), VmRuntimeError> {
@@ -78,112 +111,44 @@ impl X86Machine {
         self.instruction_counter = ptr;
     }
     
-    fn read_register_bytes<'a>(&self, alias:&Alias) -> SafetyResult<&[u8]> {
-        self.gp_registers.read_bytes(&alias)
+    fn read_register_bytes(&self, alias:&Alias) -> SafetyResult<&[u8]> {
+        self.gp_registers.read_bytes(alias)
     }
 
-    pub fn push_gp_register_to_stack(&mut self, register: Alias) -> () /* modifying in place for most ops*/ {
-
-        let regs = &self.gp_registers;
-
-        let stack_mem = &mut self.memory;
-
-        let ptr = &mut self.stack_pointer;
-
-        let dto = MemWriteDto {
-            mem: stack_mem,
-            register: regs,
-            s_ptr: ptr,
-        };
-
-        Self::write_bytes_to_stack_memory(dto,&register);
-        // let bytes = self.read_register_bytes(register);
-        // self.write_bytes_to_stack(bytes);
-    }
-    //
-    // pub fn push_gp_register_to_stack(&mut self, register:Alias) -> () /* modifying in place for most ops*/ {
-    //     let mut bytes = { self.read_register_bytes(register) };
-    //
-    //     dbg!(&bytes);
-    //     #[cfg(feature = "safety_checks")]
-    //     {
-    //
-    //         self
-    //             .write_bytes_to_stack(
-    //                 bytes.unwrap()
-    //             );
-    //     }
-    //     #[cfg(not(feature = "safety_checks"))]
-    //     {
-    //         self.write_bytes_to_stack(bytes);
-    //
-    //     }
-    //
-    //     ()
-    // }
-
-    pub fn write_bytes_to_stack(&mut self, bytes: &[u8]) -> SafetyResult<()> {
-        //todo errors later
-
-        let sp = self.stack_pointer as usize;
-        let l = bytes.len();
-
-        // sp starts at max and counts down, so eg if sp is 999,999 and we write 4 bytes
-        //we go down to ...999, 9998, 9997, 9996
-
-        let invert_start = sp-l;
-        self.memory.write(invert_start,bytes);
-
-
+    /// Pushes the bytes of a general purpose register alias onto the guest stack
+    pub fn push_gp_register_to_stack(&mut self, register: Alias) -> Result<(), Exception> {
+        let bytes = self.read_register_bytes(&register);
         #[cfg(feature = "safety_checks")]
-        {
-            Ok(())
-        }
-
-        #[cfg(not(feature = "safety_checks"))] {
-
-            ()
-        }
-
+        let bytes = bytes.expect("general purpose register alias");
+        let bytes = bytes.to_vec();
+        self.write_bytes_to_stack(&bytes)
     }
 
-
-    pub fn write_bytes_to_stack_memory<const N : usize> (d: MemWriteDto<N>, alias:&Alias) -> SafetyResult<()> {
-
-        let mem = d.mem;
-        let register = d.register;
-        let mut ptr = d.s_ptr;
-
-        let bytes = register.read_bytes(&alias);
-
-
-        #[cfg(feature = "safety_checks")]
-            let bytes = bytes.unwrap();
-
-
-        let l = bytes.len() as u64;
-
-        let invert_start = *ptr-l;
-        mem.write(invert_start as usize,bytes);
-
-        *ptr -= l;
-
-
-        #[cfg(feature = "safety_checks")] {
-            Ok(())
-        }
-
+    /// Pops as many bytes as the alias is wide off the guest stack into it
+    pub fn pop_gp_register_from_stack(&mut self, register: Alias) -> Result<(), Exception> {
+        let bytes = self.read_bytes_from_stack(register.width as usize / 8)?;
+        self.write_to_gp_registers(register, &bytes);
+        Ok(())
     }
-}
 
+    /// Writes `bytes` just below RSP and moves RSP down over them. If they do not fit in
+    /// guest memory this is a stack fault, and RSP is left alone
+    pub fn write_bytes_to_stack(&mut self, bytes: &[u8]) -> Result<(), Exception> {
+        let top = self
+            .register(Register::RSP)
+            .checked_sub(bytes.len() as u64)
+            .ok_or(Exception::StackFault)?;
+        self.store_bytes(top, bytes).map_err(|_| Exception::StackFault)?;
+        self.set_register(Register::RSP, top);
+        Ok(())
+    }
 
-mod dto {
-    use crate::memory::ContiguousMemory;
-    use crate::registers::Registers;
-
-    pub struct MemWriteDto<'a,const N: usize > {
-        pub mem: &'a mut ContiguousMemory,
-        pub register: &'a Registers<N>,
-        pub s_ptr:&'a mut u64,
+    /// Reads `count` bytes from RSP up and moves RSP up past them, a stack fault if they
+    /// run past the end of guest memory
+    pub fn read_bytes_from_stack(&mut self, count: usize) -> Result<Vec<u8>, Exception> {
+        let top = self.register(Register::RSP);
+        let bytes = self.load_bytes(top, count).map_err(|_| Exception::StackFault)?;
+        self.set_register(Register::RSP, top + count as u64);
+        Ok(bytes)
     }
 }
@@ -9,9 +9,57 @@ mod tests {
     }
 }
 
+/// What the execution tests share: `source` assembled at `CODE` with a HLT after it, in a
+/// machine with 64 KiB of memory and the instruction counter on its first instruction
+#[cfg(test)]
+mod fixture {
+    use lib_opcode::prelude::assemble;
+    use lib_x86::prelude::*;
+
+    pub const CODE: u64 = 0x1000;
+
+    /// Instructions `run` may execute unless a test asks for more
+    pub const BUDGET: u64 = 1000;
+
+    /// Loads `source` and then hands the machine to `setup`, for state a test needs before it
+    /// runs
+    pub fn load_with(source: &str, budget: u64, setup: impl FnOnce(&mut X86Machine)) -> X86Machine {
+        let code = assemble(&format!("org {CODE}\n{source}\nhlt")).unwrap();
+        let mut machine = X86Machine::builder()
+            .memory(ByteUnits::KibiBytes(64))
+            .instruction_budget(budget)
+            .build_with_defaults();
+        machine.memory.write(CODE as usize, &code).unwrap();
+        machine.set_instruction_counter(CODE);
+        setup(&mut machine);
+        machine
+    }
+
+    pub fn load(source: &str) -> X86Machine {
+        load_with(source, BUDGET, |_| {})
+    }
+
+    /// Runs a loaded machine, which has to stop at the HLT
+    pub fn halt(machine: &mut X86Machine) {
+        assert_eq!(machine.run(), StopReason::Halted);
+    }
+
+    pub fn run_with(source: &str, budget: u64, setup: impl FnOnce(&mut X86Machine)) -> X86Machine {
+        let mut machine = load_with(source, budget, setup);
+        halt(&mut machine);
+        machine
+    }
+
+    pub fn run(source: &str) -> X86Machine {
+        run_with(source, BUDGET, |_| {})
+    }
+}
+
 #[cfg(test)]
 mod flags {
     use lib_x86::flags::*;
+    use lib_x86::prelude::Register;
+    use crate::fixture::run;
 
     #[test]
     fn set_flags() {
@@ -38,6 +86,50 @@ mod flags {
         assert!(!RFlags::is_set(flags, RFlags::Carry));
         assert!(RFlags::is_set(flags, RFlags::Interrupt));
     }
+
+    #[test]
+    fn lazy_flags_are_worked_out_when_read() {
+        let machine = run("mov al, 0xFF\nadd al, 1");
+        assert!(machine.flags.is_set(RFlags::Carry));
+        assert!(machine.flags.is_set(RFlags::Zero));
+        assert!(!machine.flags.is_set(RFlags::Sign));
+        let expected = RFlags::Carry | RFlags::Reserved_1 | RFlags::Parity as u64 | RFlags::AuxCarry as u64;
+        assert_eq!(machine.flags.value(), expected | RFlags::Zero as u64);
+
+        // inc / dec leave the carry of the instruction before them
+        let machine = run("mov al, 0xFF\nadd al, 1\ndec al");
+        assert!(machine.flags.is_set(RFlags::Carry));
+        assert!(machine.flags.is_set(RFlags::Sign));
+        assert!(!machine.flags.is_set(RFlags::Zero));
+    }
+
+    #[test]
+    fn flags_outside_arithmetic_survive() {
+        let mut machine = run("mov al, 0xFF\nadd al, 1");
+        machine.flags.set(RFlags::Direction);
+        machine.flags.clear(RFlags::Carry);
+        assert!(machine.flags.is_set(RFlags::Direction));
+        assert!(!machine.flags.is_set(RFlags::Carry));
+        assert!(machine.flags.is_set(RFlags::Zero));
+
+        let mut flags = Flags::new(RFlags::Interrupt as u64);
+        flags.update(ARITHMETIC_FLAGS, RFlags::Overflow as u64 | RFlags::Interrupt as u64);
+        assert_eq!(flags.value(), RFlags::Reserved_1 | RFlags::Interrupt | RFlags::Overflow as u64);
+        assert_eq!(flags, Flags::new(RFlags::Interrupt | RFlags::Overflow));
+    }
+
+    #[test]
+    fn bit_1_is_always_set() {
+        let machine = run("pushfq\npop rax");
+        assert_eq!(machine.register(Register::RAX), 0x2);
+
+        // nothing clears it, not POPF nor writes from outside the machine
+        let mut machine = run("push 0\npopfq\npushfq\npop rbx");
+        assert_eq!(machine.register(Register::RBX), 0x2);
+        machine.flags.set_value(0);
+        machine.flags.update(u64::MAX, 0);
+        assert_eq!(machine.flags.value(), 0x2);
+    }
 }
 
 #[cfg(test)]
@@ -105,12 +197,12 @@ mod memory {
         let part_2 = rand::random_range(1024..mem.len() - 1);
 
         for i in part_1..part_2 {
-            match mem.get(i) {
-                None => {
+            match mem.read_byte(i) {
+                Err(_) => {
                     assert!(false);
                 }
-                Some(v) => {
-                    assert_eq!(*v, 0);
+                Ok(v) => {
+                    assert_eq!(v, 0);
                 }
             }
         }
@@ -222,6 +314,18 @@ mod memory {
         assert!(end_byte.is_ok());
         assert_eq!(end_byte.unwrap(), 0);
     }
+
+    #[test]
+    fn ranges_past_the_end_of_the_address_space() {
+        let mut mem = ContiguousMemory::with_size(&DEFAULT_BYTE_SIZE);
+        let addr = usize::MAX - 1;
+
+        assert!(mem.read(addr, 4).is_err());
+        assert!(mem.write(addr, &[0xff; 4]).is_err());
+        assert!(mem.write_rev(addr, &[0xff; 4]).is_err());
+        assert!(mem.copy(addr, 0, 4).is_err());
+        assert!(mem.fill(addr, 4, 0xff).is_err());
+    }
 }
 
 #[cfg(test)]
@@ -592,6 +696,7 @@ mod stack {
     use lib_x86::builders::MachineOptions;
     use lib_x86::register_aliases::Alias;
     use lib_types::memory::ByteUnits;
+    use crate::fixture::load;
 
     #[test]
     fn write_bytes_to_stack() {
@@ -612,14 +717,14 @@ mod stack {
 
         /*        first write to stack        */
 
-        machine.push_gp_register_to_stack(ALIAS);
+        machine.push_gp_register_to_stack(ALIAS).unwrap();
         let mem = machine.memory.dump_hex();
         let hex = sanitise_mem_string(mem);
         assert!(hex.ends_with("00 11 EF CD AB"));
 
         /*        second write to stack - same size        */
 
-        machine.push_gp_register_to_stack(ALIAS);
+        machine.push_gp_register_to_stack(ALIAS).unwrap();
         let mem = machine.memory.dump_hex();
         let hex = sanitise_mem_string(mem);
         assert!(hex.ends_with("00 11 EF CD AB 11 EF CD AB"));
@@ -632,13 +737,141 @@ mod stack {
             offset: 0, //offset of 1x16 bit register, meaning we start at the 17th bit
         };
 
-        machine.push_gp_register_to_stack(ALIAS2);
+        machine.push_gp_register_to_stack(ALIAS2).unwrap();
 
         let mem = machine.memory.dump_hex();
         let hex = sanitise_mem_string(mem);
         assert!(hex.ends_with("00 11 EF 11 EF CD AB 11 EF CD AB"))
     }
 
+    #[test]
+    fn stack_helpers_use_rsp() {
+        use lib_x86::prelude::*;
+
+        let mut machine = MachineOptions::builder()
+            .memory(ByteUnits::Bytes(512))
+            .build_machine();
+        const ALIAS: Alias = Alias { width: 32, offset: 0 };
+        // offsets count in alias widths, so this is ECX
+        const ALIAS2: Alias = Alias { width: 32, offset: 2 };
+
+        // the stack starts at the top of memory
+        assert_eq!(machine.register(Register::RSP), 512);
+        machine.write_to_gp_registers(ALIAS, 0xabcdef11u32.to_le_bytes().as_slice());
+        machine.push_gp_register_to_stack(ALIAS).unwrap();
+        assert_eq!(machine.register(Register::RSP), 508);
+
+        machine.pop_gp_register_from_stack(ALIAS2).unwrap();
+        assert_eq!(machine.register(Register::RSP), 512);
+        assert_eq!(machine.register(Register::RCX), 0xabcdef11);
+
+        // running off either end is a stack fault that leaves RSP alone
+        assert_eq!(machine.read_bytes_from_stack(4), Err(Exception::StackFault));
+        machine.set_register(Register::RSP, 2);
+        assert_eq!(machine.write_bytes_to_stack(&[1, 2, 3, 4]), Err(Exception::StackFault));
+        assert_eq!(machine.register(Register::RSP), 2);
+    }
+
+    #[test]
+    fn push_and_pop() {
+        use lib_x86::prelude::*;
+
+        let mut machine = load(
+            "mov rax, 0x1122334455667788
+             push rax
+             push -2
+             push qword [rel value]
+             pop rbx
+             pop rcx
+             pop qword [rel value]
+             push fs
+             pop rdx
+             push ax
+             pop si
+             mov rdi, rsp
+             push rsp
+             pop r8
+             hlt
+             value: dq 0x99",
+        );
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.register(Register::RSP), 0x10000);
+        assert_eq!(machine.register(Register::RBX), 0x99);
+        assert_eq!(machine.register(Register::RCX), (-2i64) as u64);
+        assert_eq!(machine.register(Register::RDX), 0);
+        assert_eq!(machine.register(Register::SI), 0x7788);
+        // push rsp pushes the value from before the push
+        assert_eq!(machine.register(Register::R8), machine.register(Register::RDI));
+
+        let mut machine = load("mov rsp, 4\npush rax");
+        let fault = Fault::Exception { exception: Exception::StackFault, address: 0x1007 };
+        assert_eq!(machine.run(), StopReason::Fault(fault));
+        assert_eq!(machine.register(Register::RSP), 4);
+    }
+
+    #[test]
+    fn popf_depends_on_privilege() {
+        use lib_x86::prelude::*;
+
+        // CF, DF and OF change. IOPL needs CPL 0, and IF needs CPL <= IOPL
+        let source = "push 0x3E01\npopfq\npushfq\npop rbx";
+        let mut machine = load(source);
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.flags.value(), 0xC03);
+        assert_eq!(machine.register(Register::RBX), 0xC03);
+
+        let mut machine = load(source);
+        machine.privilege_level = 0;
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.flags.value(), 0x3E03);
+
+        // a 16-bit popf leaves the upper flags
+        let mut machine = load("push 0x40000\npopfq\npush word 0\npopf");
+        machine.privilege_level = 0;
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.flags.value(), RFlags::AlignmentCheck | RFlags::Reserved_1);
+    }
+
+    #[test]
+    fn lahf_and_sahf() {
+        use lib_x86::prelude::*;
+
+        // LAHF sets bit 1 as the flags register has it, SAHF only loads the arithmetic flags
+        let mut machine = load("mov eax, -1\nadd eax, 1\nlahf\nmov bl, ah\nmov ah, 0xFF\nstc\ncmc\nsahf");
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.register(Register::BL), 0x57);
+        assert_eq!(machine.flags.value() & 0x8D5, 0xD5);
+    }
+
+    #[test]
+    fn enter_and_leave() {
+        use lib_x86::prelude::*;
+
+        let mut machine = load(
+            "mov rbp, 0x1234
+             enter 32, 0
+             mov rbx, rbp
+             mov rcx, rsp
+             leave",
+        );
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.register(Register::RBX), 0x10000 - 8);
+        assert_eq!(machine.register(Register::RCX), 0x10000 - 8 - 32);
+        assert_eq!(machine.register(Register::RBP), 0x1234);
+        assert_eq!(machine.register(Register::RSP), 0x10000);
+
+        // nesting level 2 copies the enclosing frame pointer then pushes the new one
+        let mut machine = load("enter 0, 1\nenter 16, 2");
+        assert_eq!(machine.run(), StopReason::Halted);
+        let outer = 0x10000 - 8;
+        let inner = outer - 16;
+        assert_eq!(machine.register(Register::RBP), inner);
+        assert_eq!(machine.register(Register::RSP), inner - 16 - 16);
+        let frames = machine.memory.read(inner as usize - 16, 16).unwrap();
+        assert_eq!(frames[..8], inner.to_le_bytes());
+        assert_eq!(frames[8..], outer.to_le_bytes());
+    }
+
 
     /// turn the hex dump string (for visual debugging) to a form that easier to check
     /// for start/end matches for tests
@@ -653,3 +886,3652 @@ mod stack {
         mem_string.to_string()
     }
 }
+
+#[cfg(test)]
+mod decode {
+    use lib_opcode::prelude::*;
+
+    #[test]
+    fn decode_register_to_register() {
+        // add rax, rbx
+        let op = decode(&[0x48, 0x01, 0xD8]).unwrap();
+
+        assert_eq!(op.mnemonic, Mnemonic::Add);
+        assert_eq!(op.length, 3);
+        assert_eq!(op.operand_size, 8);
+        assert_eq!(op.operands(), &[Operand::Register(Register::RAX), Operand::Register(Register::RBX)]);
+    }
+
+    #[test]
+    fn decode_sib_memory_operand() {
+        // mov rax, [rbx+rcx*4+0x10]
+        let op = decode(&[0x48, 0x8B, 0x44, 0x8B, 0x10]).unwrap();
+
+        assert_eq!(op.mnemonic, Mnemonic::Mov);
+        assert_eq!(op.length, 5);
+        let mem = op.memory_operand().unwrap();
+        assert_eq!(mem.base, Some(Register::RBX));
+        assert_eq!(mem.index, Some(Register::RCX));
+        assert_eq!(mem.scale, 4);
+        assert_eq!(mem.displacement, 0x10);
+        assert_eq!(mem.size, 8);
+    }
+
+    #[test]
+    fn decode_rip_relative() {
+        // lea rdi, [rip-0x20]
+        let op = decode(&[0x48, 0x8D, 0x3D, 0xE0, 0xFF, 0xFF, 0xFF]).unwrap();
+
+        assert_eq!(op.mnemonic, Mnemonic::Lea);
+        let mem = op.memory_operand().unwrap();
+        assert_eq!(mem.base, Some(Register::Rip));
+        assert_eq!(mem.index, None);
+        assert_eq!(mem.displacement, -0x20);
+    }
+
+    #[test]
+    fn decode_rex_extended_registers() {
+        // mov r9d, dword [r12+r13*8]
+        let op = decode(&[0x47, 0x8B, 0x0C, 0xEC]).unwrap();
+
+        assert_eq!(op.operands()[0], Operand::Register(Register::Gpr32(9)));
+        let mem = op.memory_operand().unwrap();
+        assert_eq!(mem.base, Some(Register::Gpr64(12)));
+        assert_eq!(mem.index, Some(Register::Gpr64(13)));
+        assert_eq!(mem.scale, 8);
+        assert_eq!(mem.size, 4);
+    }
+
+    #[test]
+    fn decode_byte_registers_with_and_without_rex() {
+        // mov ah, bh / mov spl, dil
+        let op = decode(&[0x88, 0xFC]).unwrap();
+        assert_eq!(op.operands(), &[Operand::Register(Register::AH), Operand::Register(Register::BH)]);
+
+        let op = decode(&[0x40, 0x88, 0xFC]).unwrap();
+        assert_eq!(op.operands(), &[Operand::Register(Register::Gpr8(4)), Operand::Register(Register::Gpr8(7))]);
+    }
+
+    #[test]
+    fn decode_immediates() {
+        // mov rax, 0x1122334455667788
+        let op = decode(&[0x48, 0xB8, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11]).unwrap();
+        assert_eq!(op.length, 10);
+        assert_eq!(op.operands()[1], Operand::Immediate(Immediate { value: 0x1122334455667788, size: 8 }));
+
+        // sub rsp, -8 (imm8 sign extended to the operand size)
+        let op = decode(&[0x48, 0x83, 0xEC, 0xF8]).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Sub);
+        assert_eq!(op.operands()[1], Operand::Immediate(Immediate { value: 0xFFFF_FFFF_FFFF_FFF8, size: 8 }));
+
+        // add ax, 0x1234 (operand size override)
+        let op = decode(&[0x66, 0x05, 0x34, 0x12]).unwrap();
+        assert_eq!(op.operand_size, 2);
+        assert_eq!(op.operands(), &[Operand::Register(Register::AX), Operand::Immediate(Immediate { value: 0x1234, size: 2 })]);
+    }
+
+    #[test]
+    fn decode_mandatory_prefixes() {
+        let addps = decode(&[0x0F, 0x58, 0xC1]).unwrap();
+        let addpd = decode(&[0x66, 0x0F, 0x58, 0xC1]).unwrap();
+        let addss = decode(&[0xF3, 0x0F, 0x58, 0xC1]).unwrap();
+        let addsd = decode(&[0xF2, 0x0F, 0x58, 0xC1]).unwrap();
+
+        assert_eq!(addps.mnemonic, Mnemonic::Addps);
+        assert_eq!(addpd.mnemonic, Mnemonic::Addpd);
+        assert_eq!(addss.mnemonic, Mnemonic::Addss);
+        assert_eq!(addsd.mnemonic, Mnemonic::Addsd);
+        assert_eq!(addpd.operands(), &[Operand::Register(Register::Xmm(0)), Operand::Register(Register::Xmm(1))]);
+
+        let op = decode(&[0xF3, 0x0F, 0x1E, 0xFA]).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Endbr64);
+    }
+
+    #[test]
+    fn decode_three_byte_maps() {
+        // pshufb xmm1, xmm2
+        let op = decode(&[0x66, 0x0F, 0x38, 0x00, 0xCA]).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Pshufb);
+        assert_eq!(op.map, OpcodeMap::Map0F38);
+
+        // pextrd eax, xmm1, 2
+        let op = decode(&[0x66, 0x0F, 0x3A, 0x16, 0xC8, 0x02]).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Pextrd);
+        assert_eq!(op.length, 6);
+    }
+
+    #[test]
+    fn decode_group_opcodes() {
+        // not qword [rax] / neg qword [rax]
+        assert_eq!(decode(&[0x48, 0xF7, 0x10]).unwrap().mnemonic, Mnemonic::Not);
+        assert_eq!(decode(&[0x48, 0xF7, 0x18]).unwrap().mnemonic, Mnemonic::Neg);
+        // shl eax, 1
+        let op = decode(&[0xD1, 0xE0]).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Shl);
+        assert_eq!(op.operands()[1], Operand::Immediate(Immediate { value: 1, size: 1 }));
+    }
+
+    #[test]
+    fn decode_relative_branches() {
+        // call +0x10, located at 0x1000
+        let op = decode_at(Bitness::Bits64, &[0xE8, 0x10, 0x00, 0x00, 0x00], 0x1000).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Call);
+        assert_eq!(op.branch_target(), Some(0x1015));
+
+        // jne -2 (jump to self)
+        let op = decode_at(Bitness::Bits64, &[0x75, 0xFE], 0x2000).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Jne);
+        assert_eq!(op.branch_target(), Some(0x2000));
+    }
+
+    #[test]
+    fn decode_legacy_prefixes() {
+        // lock xadd [rdi], eax
+        let op = decode(&[0xF0, 0x0F, 0xC1, 0x07]).unwrap();
+        assert!(op.prefixes.lock);
+        assert!(op.accepts_lock());
+
+        // rep movsb
+        let op = decode(&[0xF3, 0xA4]).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Movsb);
+        assert_eq!(op.prefixes.rep, Some(RepPrefix::Rep));
+        assert!(op.is_string());
+
+        // mov eax, fs:[0x28] (32 bit address override with segment)
+        let op = decode(&[0x64, 0x67, 0x8B, 0x04, 0x25, 0x28, 0x00, 0x00, 0x00]).unwrap();
+        let mem = op.memory_operand().unwrap();
+        assert_eq!(mem.segment, Some(Register::FS));
+        assert_eq!(mem.base, None);
+        assert_eq!(mem.displacement, 0x28);
+        assert_eq!(op.address_size, 4);
+    }
+
+    #[test]
+    fn decode_32_and_16_bit_modes() {
+        // inc eax is a single byte in 32-bit mode, and a REX prefix in 64-bit mode
+        let op = decode_at(Bitness::Bits32, &[0x40], 0).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Inc);
+        assert_eq!(op.operands(), &[Operand::Register(Register::EAX)]);
+
+        // mov ax, [bx+si+4]
+        let op = decode_at(Bitness::Bits16, &[0x8B, 0x40, 0x04], 0).unwrap();
+        let mem = op.memory_operand().unwrap();
+        assert_eq!(op.operands()[0], Operand::Register(Register::AX));
+        assert_eq!(mem.base, Some(Register::BX));
+        assert_eq!(mem.index, Some(Register::SI));
+        assert_eq!(mem.displacement, 4);
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert_eq!(decode(&[]).unwrap_err(), DecodeError::UnexpectedEnd);
+        assert_eq!(decode(&[0x48, 0x8B]).unwrap_err(), DecodeError::UnexpectedEnd);
+        assert_eq!(decode(&[0x48, 0xB8, 0x00]).unwrap_err(), DecodeError::UnexpectedEnd);
+
+        // too many prefixes
+        let mut bytes = vec![0x66; 15];
+        bytes.push(0x90);
+        assert_eq!(decode(&bytes).unwrap_err(), DecodeError::TooLong);
+
+        // push es does not exist in 64-bit mode
+        assert!(matches!(decode(&[0x06]).unwrap_err(), DecodeError::InvalidOpcode { .. }));
+        assert!(decode_at(Bitness::Bits32, &[0x06], 0).is_ok());
+    }
+
+    #[test]
+    fn decoder_iterates_instructions() {
+        // push rbp; mov rbp, rsp; xor eax, eax; pop rbp; ret
+        let bytes = [0x55, 0x48, 0x89, 0xE5, 0x31, 0xC0, 0x5D, 0xC3];
+        let mnemonics: Vec<Mnemonic> = Decoder::new(Bitness::Bits64, &bytes, 0x400000)
+            .map(|op| op.unwrap().mnemonic)
+            .collect();
+
+        assert_eq!(mnemonics, [Mnemonic::Push, Mnemonic::Mov, Mnemonic::Xor, Mnemonic::Pop, Mnemonic::Ret]);
+    }
+
+    #[test]
+    fn decode_vex_two_and_three_byte_prefixes() {
+        // vaddps xmm0, xmm1, xmm2
+        let op = decode(&[0xC5, 0xF0, 0x58, 0xC2]).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Vaddps);
+        assert_eq!(op.encoding, Encoding::Vex);
+        assert_eq!(op.length, 4);
+        assert_eq!(op.vector_length, 16);
+        assert_eq!(
+            op.operands(),
+            &[Operand::Register(Register::Xmm(0)), Operand::Register(Register::Xmm(1)), Operand::Register(Register::Xmm(2))]
+        );
+
+        // vaddps ymm0, ymm1, ymm2
+        let op = decode(&[0xC5, 0xF4, 0x58, 0xC2]).unwrap();
+        assert_eq!(op.vector_length, 32);
+        assert_eq!(op.operands()[2], Operand::Register(Register::Ymm(2)));
+
+        // vfmadd231ps ymm0, ymm1, ymm2
+        let op = decode(&[0xC4, 0xE2, 0x75, 0xB8, 0xC2]).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Vfmadd231ps);
+        assert_eq!(op.map, OpcodeMap::Map0F38);
+        assert_eq!(op.operands()[1], Operand::Register(Register::Ymm(1)));
+
+        // vzeroupper
+        let op = decode(&[0xC5, 0xF8, 0x77]).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Vzeroupper);
+        assert!(op.operands().is_empty());
+    }
+
+    #[test]
+    fn decode_vex_general_purpose_instructions() {
+        // shlx eax, ebx, ecx / shlx rax, rbx, rcx
+        let op = decode(&[0xC4, 0xE2, 0x71, 0xF7, 0xC3]).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Shlx);
+        assert_eq!(
+            op.operands(),
+            &[Operand::Register(Register::EAX), Operand::Register(Register::EBX), Operand::Register(Register::ECX)]
+        );
+        let op = decode(&[0xC4, 0xE2, 0xF1, 0xF7, 0xC3]).unwrap();
+        assert_eq!(op.operands()[2], Operand::Register(Register::RCX));
+
+        // kmovd k1, eax
+        let op = decode(&[0xC5, 0xFB, 0x92, 0xC8]).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Kmovd);
+        assert_eq!(op.operands(), &[Operand::Register(Register::Opmask(1)), Operand::Register(Register::EAX)]);
+    }
+
+    #[test]
+    fn decode_vsib_gather() {
+        // vpgatherdd xmm0, [rax+xmm1*4], xmm2
+        let op = decode(&[0xC4, 0xE2, 0x69, 0x90, 0x04, 0x88]).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Vpgatherdd);
+        let mem = op.memory_operand().unwrap();
+        assert_eq!(mem.base, Some(Register::RAX));
+        assert_eq!(mem.index, Some(Register::Xmm(1)));
+        assert_eq!(mem.scale, 4);
+        assert_eq!(op.operands()[2], Operand::Register(Register::Xmm(2)));
+    }
+
+    #[test]
+    fn decode_evex_masking_and_extended_registers() {
+        // vaddps zmm0{k1}{z}, zmm1, zmm2
+        let op = decode(&[0x62, 0xF1, 0x74, 0xC9, 0x58, 0xC2]).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Vaddps);
+        assert_eq!(op.encoding, Encoding::Evex);
+        assert_eq!(op.vector_length, 64);
+        assert_eq!(op.opmask(), Some(Register::Opmask(1)));
+        assert!(op.zeroing());
+        assert_eq!(op.operands()[0], Operand::Register(Register::Zmm(0)));
+
+        // vaddps zmm16, zmm1, zmm2
+        let op = decode(&[0x62, 0xE1, 0x74, 0x48, 0x58, 0xC2]).unwrap();
+        assert_eq!(op.operands()[0], Operand::Register(Register::Zmm(16)));
+        assert_eq!(op.opmask(), None);
+        assert!(!op.zeroing());
+    }
+
+    #[test]
+    fn decode_evex_broadcast_and_compressed_displacement() {
+        // vaddps zmm0, zmm1, zmmword [rax+0x40]
+        let op = decode(&[0x62, 0xF1, 0x74, 0x48, 0x58, 0x40, 0x01]).unwrap();
+        let mem = op.memory_operand().unwrap();
+        assert_eq!(mem.displacement, 0x40);
+        assert_eq!(mem.size, 64);
+        assert!(!op.broadcast);
+
+        // vaddps zmm0, zmm1, dword [rax+0x40]{1to16}
+        let op = decode(&[0x62, 0xF1, 0x74, 0x58, 0x58, 0x40, 0x10]).unwrap();
+        let mem = op.memory_operand().unwrap();
+        assert_eq!(mem.displacement, 0x40);
+        assert_eq!(mem.size, 4);
+        assert!(op.broadcast);
+    }
+
+    #[test]
+    fn decode_evex_embedded_rounding() {
+        // vaddps zmm0, zmm1, zmm2, {rz-sae}
+        let op = decode(&[0x62, 0xF1, 0x74, 0x78, 0x58, 0xC2]).unwrap();
+        assert_eq!(op.rounding, Some(RoundingControl::Zero));
+        assert!(op.sae);
+        assert_eq!(op.vector_length, 64);
+    }
+
+    #[test]
+    fn decode_evex_vbmi() {
+        // vpmultishiftqb zmm0, zmm1, zmm2
+        let op = decode(&[0x62, 0xF2, 0xF5, 0x48, 0x83, 0xC2]).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Vpmultishiftqb);
+        assert_eq!(op.operands()[1], Operand::Register(Register::Zmm(1)));
+        assert_eq!(op.operands()[2], Operand::Register(Register::Zmm(2)));
+    }
+
+    #[test]
+    fn decode_evex_opmask_broadcasts() {
+        // vpbroadcastmb2q zmm0, k1 shares 0F38 2A with movntdqa, which takes 66 rather than F3
+        let op = decode(&[0x62, 0xF2, 0xFE, 0x48, 0x2A, 0xC1]).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Vpbroadcastmb2q);
+        assert_eq!(op.operands()[1], Operand::Register(Register::Opmask(1)));
+    }
+
+    #[test]
+    fn decode_vex_prefix_errors() {
+        // REX before a VEX prefix
+        assert!(decode(&[0x48, 0xC5, 0xF0, 0x58, 0xC2]).is_err());
+        // outside 64-bit mode C5 with a memory ModR/M is still lds
+        let op = decode_at(Bitness::Bits32, &[0xC5, 0x06, 0x00, 0x10, 0x00, 0x00], 0).unwrap();
+        assert_eq!(op.mnemonic, Mnemonic::Lds);
+    }
+}
+
+#[cfg(test)]
+mod encode {
+    use lib_opcode::prelude::*;
+
+    fn encode64(mnemonic: Mnemonic, operands: &[Arg]) -> Vec<u8> {
+        encode(Bitness::Bits64, &Instruction::new(mnemonic, operands), 0).unwrap()
+    }
+
+    #[test]
+    fn encode_register_and_immediate_forms() {
+        assert_eq!(encode64(Mnemonic::Mov, &[Register::RAX.into(), Register::RBX.into()]), [0x48, 0x89, 0xD8]);
+        assert_eq!(encode64(Mnemonic::Mov, &[Register::Gpr32(9).into(), Register::EAX.into()]), [0x41, 0x89, 0xC1]);
+        assert_eq!(encode64(Mnemonic::Mov, &[Register::Gpr8(4).into(), Register::AL.into()]), [0x40, 0x88, 0xC4]);
+
+        // the sign extended imm8 form is picked when the value allows it
+        assert_eq!(encode64(Mnemonic::Add, &[Register::RAX.into(), 1.into()]), [0x48, 0x83, 0xC0, 0x01]);
+        assert_eq!(encode64(Mnemonic::Add, &[Register::EAX.into(), 0x1000.into()]), [0x05, 0x00, 0x10, 0x00, 0x00]);
+        assert_eq!(encode64(Mnemonic::Mov, &[Register::EAX.into(), 0x12345678.into()]), [0xB8, 0x78, 0x56, 0x34, 0x12]);
+
+        // an explicitly sized immediate forces the encoding
+        assert_eq!(
+            encode64(Mnemonic::Mov, &[Register::RAX.into(), Imm64(1).into()]),
+            [0x48, 0xB8, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+
+        // 16-bit forms of instructions with a mandatory F3 / F2 prefix put 66 in front of it
+        assert_eq!(encode64(Mnemonic::Popcnt, &[Register::AX.into(), Register::CX.into()]), [0x66, 0xF3, 0x0F, 0xB8, 0xC1]);
+        assert_eq!(encode64(Mnemonic::Crc32, &[Register::EAX.into(), Register::CX.into()]), [0x66, 0xF2, 0x0F, 0x38, 0xF1, 0xC1]);
+
+        // ah and spl cannot appear in the same instruction
+        let mixed = Instruction::new(Mnemonic::Mov, &[Register::AH.into(), Register::Gpr8(4).into()]);
+        assert!(encode(Bitness::Bits64, &mixed, 0).is_err());
+    }
+
+    #[test]
+    fn encode_memory_operands() {
+        let mem = Mem::base(Register::RBX).index(Register::RCX, 4).disp(0x10);
+        assert_eq!(encode64(Mnemonic::Mov, &[Register::RAX.into(), mem.into()]), [0x48, 0x8B, 0x44, 0x8B, 0x10]);
+
+        // rsp as a base needs a SIB, rbp and r13 need a displacement
+        assert_eq!(encode64(Mnemonic::Mov, &[Mem::base(Register::RSP).into(), Register::EAX.into()]), [0x89, 0x04, 0x24]);
+        assert_eq!(encode64(Mnemonic::Mov, &[Register::EAX.into(), Mem::base(Register::RBP).into()]), [0x8B, 0x45, 0x00]);
+        assert_eq!(encode64(Mnemonic::Mov, &[Register::EAX.into(), Mem::base(Register::Gpr64(13)).into()]), [0x41, 0x8B, 0x45, 0x00]);
+
+        let rip = Mem::base(Register::Rip).disp(-0x20);
+        assert_eq!(encode64(Mnemonic::Lea, &[Register::RDI.into(), rip.into()]), [0x48, 0x8D, 0x3D, 0xE0, 0xFF, 0xFF, 0xFF]);
+
+        // an absolute address in 64-bit mode goes through a SIB without base or index
+        let absolute = Mem::absolute(0x1000);
+        assert_eq!(encode64(Mnemonic::Mov, &[Register::EAX.into(), absolute.into()]), [0x8B, 0x04, 0x25, 0x00, 0x10, 0x00, 0x00]);
+
+        // rsp cannot be an index
+        let bad = Instruction::new(Mnemonic::Mov, &[Register::EAX.into(), Mem::base(Register::RAX).index(Register::RSP, 1).into()]);
+        assert!(encode(Bitness::Bits64, &bad, 0).is_err());
+    }
+
+    #[test]
+    fn encode_requires_sizes_it_cannot_infer() {
+        let inc = Instruction::new(Mnemonic::Inc, &[Mem::base(Register::RAX).into()]);
+        assert_eq!(encode(Bitness::Bits64, &inc, 0), Err(EncodeError::AmbiguousOperandSize(Mnemonic::Inc)));
+
+        assert_eq!(encode64(Mnemonic::Inc, &[Mem::base(Register::RAX).dword().into()]), [0xFF, 0x00]);
+        assert_eq!(encode64(Mnemonic::Inc, &[Mem::base(Register::RAX).qword().into()]), [0x48, 0xFF, 0x00]);
+
+        // string instructions take their size from the instruction
+        let movsq = Instruction::new(Mnemonic::Movs, &[]).operand_size(8).rep();
+        assert_eq!(encode(Bitness::Bits64, &movsq, 0).unwrap(), [0xF3, 0x48, 0xA5]);
+    }
+
+    #[test]
+    fn encode_vex_and_evex() {
+        let (ymm, zmm) = (Register::Ymm, Register::Zmm);
+        assert_eq!(encode64(Mnemonic::Vaddps, &[ymm(0).into(), ymm(1).into(), ymm(2).into()]), [0xC5, 0xF4, 0x58, 0xC2]);
+        assert_eq!(
+            encode64(Mnemonic::Shlx, &[Register::EAX.into(), Register::EBX.into(), Register::ECX.into()]),
+            [0xC4, 0xE2, 0x71, 0xF7, 0xC3]
+        );
+
+        // zmm registers need EVEX, and vaddpd is EVEX.W1
+        assert_eq!(encode64(Mnemonic::Vaddpd, &[zmm(0).into(), zmm(1).into(), zmm(2).into()]), [0x62, 0xF1, 0xF5, 0x48, 0x58, 0xC2]);
+
+        let masked = Instruction::new(Mnemonic::Vaddps, &[zmm(0).into(), zmm(1).into(), zmm(2).into()])
+            .mask(Register::Opmask(1))
+            .zeroing();
+        assert_eq!(encode(Bitness::Bits64, &masked, 0).unwrap(), [0x62, 0xF1, 0x74, 0xC9, 0x58, 0xC2]);
+
+        // disp8 is scaled by the element size when broadcasting
+        let broadcast = Mem::base(Register::RAX).disp(0x40).broadcast(4);
+        assert_eq!(
+            encode64(Mnemonic::Vaddps, &[zmm(0).into(), zmm(1).into(), broadcast.into()]),
+            [0x62, 0xF1, 0x74, 0x58, 0x58, 0x40, 0x10]
+        );
+
+        let rounded = Instruction::new(Mnemonic::Vaddps, &[zmm(0).into(), zmm(1).into(), zmm(2).into()])
+            .rounding(RoundingControl::Zero);
+        assert_eq!(encode(Bitness::Bits64, &rounded, 0).unwrap(), [0x62, 0xF1, 0x74, 0x78, 0x58, 0xC2]);
+
+        // EVEX only, in the 0F38 map
+        assert_eq!(
+            encode64(Mnemonic::Vpmultishiftqb, &[zmm(0).into(), zmm(1).into(), zmm(2).into()]),
+            [0x62, 0xF2, 0xF5, 0x48, 0x83, 0xC2]
+        );
+    }
+
+    #[test]
+    fn encode_round_trips_through_the_decoder() {
+        let instructions = [
+            Instruction::new(Mnemonic::Imul, &[Register::Gpr64(12).into(), Register::RDX.into(), (-5).into()]),
+            Instruction::new(Mnemonic::Shl, &[Register::Gpr16(10).into(), Register::CL.into()]),
+            Instruction::new(Mnemonic::Movzx, &[Register::EAX.into(), Mem::base(Register::RSI).byte().into()]),
+            Instruction::new(Mnemonic::Cmpxchg, &[Mem::base(Register::RDI).into(), Register::RCX.into()]).lock(),
+            Instruction::new(Mnemonic::Pshufd, &[Register::Xmm(9).into(), Register::Xmm(2).into(), 0x1B.into()]),
+            Instruction::new(Mnemonic::Vpgatherdd, &[
+                Register::Xmm(0).into(),
+                Mem::base(Register::RAX).index(Register::Xmm(1), 4).into(),
+                Register::Xmm(2).into(),
+            ]),
+            Instruction::new(Mnemonic::Fadd, &[Register::St(0).into(), Register::St(3).into()]),
+        ];
+        for instruction in &instructions {
+            let bytes = encode(Bitness::Bits64, instruction, 0x1000).unwrap();
+            let decoded = decode_at(Bitness::Bits64, &bytes, 0x1000).unwrap();
+            assert_eq!(decoded.mnemonic, instruction.mnemonic);
+            assert_eq!(decoded.length as usize, bytes.len());
+            assert_eq!(decoded.operands().len(), instruction.operands.len());
+            assert_eq!(decoded.prefixes.lock, instruction.lock);
+        }
+    }
+
+    #[test]
+    fn encode_other_modes() {
+        let push = Instruction::new(Mnemonic::Push, &[Register::Gpr32(5).into()]);
+        assert_eq!(encode(Bitness::Bits32, &push, 0).unwrap(), [0x55]);
+
+        // 40+r is inc outside of 64-bit mode
+        let inc = Instruction::new(Mnemonic::Inc, &[Register::EAX.into()]);
+        assert_eq!(encode(Bitness::Bits32, &inc, 0).unwrap(), [0x40]);
+
+        let load = Instruction::new(Mnemonic::Mov, &[Register::AX.into(), Mem::base(Register::BX).index(Register::SI, 1).into()]);
+        assert_eq!(encode(Bitness::Bits16, &load, 0).unwrap(), [0x8B, 0x00]);
+        assert_eq!(encode(Bitness::Bits32, &load, 0).unwrap(), [0x67, 0x66, 0x8B, 0x00]);
+
+        // no REX outside of 64-bit mode
+        let r8 = Instruction::new(Mnemonic::Inc, &[Register::Gpr32(8).into()]);
+        assert!(encode(Bitness::Bits32, &r8, 0).is_err());
+    }
+
+    #[test]
+    fn assembler_labels_and_fixups() {
+        let mut asm = Assembler::new(Bitness::Bits64);
+        let done = asm.new_label();
+        asm.mov(Register::ECX, 10).unwrap();
+        let top = asm.here();
+        asm.dec(Register::ECX).unwrap();
+        asm.jnz(top).unwrap();
+        asm.jmp(done).unwrap();
+        asm.hlt().unwrap();
+        asm.bind(done).unwrap();
+        asm.ret().unwrap();
+
+        let code = asm.assemble(0x1000).unwrap();
+        assert_eq!(code, [0xB9, 0x0A, 0x00, 0x00, 0x00, 0xFF, 0xC9, 0x75, 0xFC, 0xEB, 0x01, 0xF4, 0xC3]);
+        assert_eq!(asm.label_address(top), Some(0x1005));
+        assert_eq!(asm.label_address(done), Some(0x100C));
+    }
+
+    #[test]
+    fn assembler_widens_branches_that_do_not_reach() {
+        let mut asm = Assembler::new(Bitness::Bits64);
+        let far = asm.new_label();
+        asm.je(far).unwrap();
+        asm.call(far).unwrap();
+        asm.db(&[0x90; 200]);
+        asm.bind(far).unwrap();
+        asm.ret().unwrap();
+
+        let code = asm.assemble(0).unwrap();
+        // je rel32 (6 bytes) then call rel32 (5 bytes)
+        assert_eq!(&code[..2], [0x0F, 0x84]);
+        assert_eq!(i32::from_le_bytes(code[2..6].try_into().unwrap()), 205);
+        assert_eq!(code[6], 0xE8);
+        assert_eq!(i32::from_le_bytes(code[7..11].try_into().unwrap()), 200);
+        assert_eq!(code.len(), 212);
+    }
+
+    #[test]
+    fn assembler_rip_relative_labels_and_data() {
+        let mut asm = Assembler::new(Bitness::Bits64);
+        let value = asm.new_label();
+        asm.mov(Register::RAX, Mem::label(value)).unwrap();
+        asm.ret().unwrap();
+        asm.bind(value).unwrap();
+        asm.dq(&[0x1122334455667788]);
+
+        let code = asm.assemble(0x400000).unwrap();
+        // mov rax, [rip+1] skips over the ret
+        assert_eq!(&code[..8], [0x48, 0x8B, 0x05, 0x01, 0x00, 0x00, 0x00, 0xC3]);
+        assert_eq!(&code[8..], 0x1122334455667788u64.to_le_bytes());
+    }
+
+    #[test]
+    fn assembler_errors() {
+        let mut asm = Assembler::new(Bitness::Bits64);
+        let nowhere = asm.new_label();
+        asm.jmp(nowhere).unwrap();
+        assert_eq!(asm.assemble(0), Err(EncodeError::UnboundLabel(nowhere)));
+
+        asm.bind(nowhere).unwrap();
+        assert_eq!(asm.bind(nowhere), Err(EncodeError::LabelAlreadyBound(nowhere)));
+
+        // operands are checked as instructions are added
+        assert_eq!(asm.mov(Register::RAX, Register::EBX), Err(EncodeError::InvalidOperands(Mnemonic::Mov)));
+    }
+}
+
+#[cfg(test)]
+mod assemble {
+    use lib_opcode::prelude::*;
+
+    fn encode64(instruction: Instruction) -> Vec<u8> {
+        encode(Bitness::Bits64, &instruction, 0).unwrap()
+    }
+
+    #[test]
+    fn assemble_operand_syntax() {
+        let code = assemble(
+            "mov rax, qword ptr [rax+rcx*8+0x10]
+             mov dword [rbp - 8], 0FFh
+             add rax, 1
+             mov eax, fs:[0x28]
+             lea rdi, [rsp + 2*rsi + (3 << 2)]
+             lock xadd [rbx], eax
+             rep stosq
+             movsb
+             movsd xmm0, [rax]",
+        )
+        .unwrap();
+
+        let expected = [
+            encode64(Instruction::new(
+                Mnemonic::Mov,
+                &[Register::RAX.into(), Mem::base(Register::RAX).index(Register::RCX, 8).disp(0x10).qword().into()],
+            )),
+            encode64(Instruction::new(Mnemonic::Mov, &[Mem::base(Register::RBP).disp(-8).dword().into(), Imm32(0xFF).into()])),
+            encode64(Instruction::new(Mnemonic::Add, &[Register::RAX.into(), 1.into()])),
+            encode64(Instruction::new(Mnemonic::Mov, &[Register::EAX.into(), Mem::absolute(0x28).segment(Register::FS).into()])),
+            encode64(Instruction::new(
+                Mnemonic::Lea,
+                &[Register::RDI.into(), Mem::base(Register::RSP).index(Register::RSI, 2).disp(12).into()],
+            )),
+            encode64(Instruction::new(Mnemonic::Xadd, &[Mem::base(Register::RBX).into(), Register::EAX.into()]).lock()),
+            encode64(Instruction::new(Mnemonic::Stos, &[]).operand_size(8).rep()),
+            vec![0xA4],
+            encode64(Instruction::new(Mnemonic::Movsd, &[Register::Xmm(0).into(), Mem::base(Register::RAX).into()])),
+        ]
+        .concat();
+        assert_eq!(code, expected);
+    }
+
+    #[test]
+    fn assemble_labels_and_branches() {
+        let object = assemble_object(
+            "        org 0x1000
+             start:  mov ecx, 10
+             .again: dec ecx
+                     jnz .again
+                     jmp done
+                     times 200 nop
+             done:   call start
+                     hlt",
+        )
+        .unwrap();
+
+        assert_eq!(object.symbol("start"), Some(0x1000));
+        assert_eq!(object.symbol("start.again"), Some(0x1005));
+        let done = object.symbol("done").unwrap();
+
+        let code = object.flat();
+        // dec ecx, then a short jnz back to it
+        assert_eq!(&code[5..9], [0xFF, 0xC9, 0x75, 0xFC]);
+        // done is out of short range, so the jump is near
+        assert_eq!(code[9], 0xE9);
+        assert_eq!(i32::from_le_bytes(code[10..14].try_into().unwrap()) as u64, done - 0x100E);
+        assert_eq!(done, 0x100E + 200);
+
+        let mut decoder = Decoder::new(Bitness::Bits64, &code[(done - 0x1000) as usize..], done);
+        let call = decoder.next().unwrap().unwrap();
+        assert_eq!(call.branch_target(), Some(0x1000));
+    }
+
+    #[test]
+    fn assemble_data_directives() {
+        let object = assemble_object(
+            "        bits 16
+                     org 0x7C00
+                     jmp short boot
+             msg     db 'hi', `\\n`, 0
+             len     equ $ - msg
+             words:  dw 1, 'ab', len
+                     dd 0x12345678
+                     dq words
+             buffer: resb 4
+             boot:   hlt
+                     times 510 - ($ - $$) db 0
+                     dw 0xAA55",
+        )
+        .unwrap();
+
+        assert_eq!(object.bitness, Bitness::Bits16);
+        assert_eq!(object.symbol("len"), Some(4));
+        let code = object.flat();
+        assert_eq!(code.len(), 512);
+        assert_eq!(&code[2..6], b"hi\n\0");
+        assert_eq!(&code[6..12], [1, 0, b'a', b'b', 4, 0]);
+        assert_eq!(&code[12..16], 0x12345678u32.to_le_bytes());
+        assert_eq!(&code[16..24], 0x7C06u64.to_le_bytes());
+        assert_eq!(&code[24..28], [0; 4]);
+        assert_eq!(object.symbol("boot"), Some(0x7C00 + 28));
+        assert_eq!(&code[..2], [0xEB, 26]);
+        assert_eq!(&code[510..], [0x55, 0xAA]);
+    }
+
+    #[test]
+    fn assemble_sections() {
+        let object = assemble_object(
+            "section .text
+                 lea rsi, [msg]
+                 mov eax, [rel count + 4]
+                 ret
+             section .data align=16
+             msg:    db \"abc\"
+                     align 4
+             count:  dd 7, 8
+             section .text
+                 nop",
+        )
+        .unwrap();
+
+        let text = object.section(".text").unwrap();
+        let data = object.section(".data").unwrap();
+        assert_eq!(text.address, 0);
+        // lea (7) + mov (6) + ret + nop
+        assert_eq!(text.bytes.len(), 15);
+        assert_eq!(data.address, 16);
+        assert_eq!(data.bytes, [b'a', b'b', b'c', 0, 7, 0, 0, 0, 8, 0, 0, 0]);
+        assert_eq!(object.symbol("count"), Some(20));
+
+        let mut decoder = Decoder::new(Bitness::Bits64, &text.bytes, 0);
+        let lea = decoder.next().unwrap().unwrap();
+        assert_eq!(lea.memory_operand().unwrap().base, Some(Register::Rip));
+        assert_eq!(lea.next_ip() as i64 + lea.memory_operand().unwrap().displacement, 16);
+        let mov = decoder.next().unwrap().unwrap();
+        assert_eq!(mov.next_ip() as i64 + mov.memory_operand().unwrap().displacement, 24);
+
+        // the gap before .data is filled in the flat image
+        let flat = object.flat();
+        assert_eq!(flat.len(), 28);
+        assert_eq!(flat[15], 0);
+    }
+
+    #[test]
+    fn assemble_evex_decorations() {
+        let code = assemble("vaddps zmm1 {k1}{z}, zmm2, dword [rax]{1to16}\nvaddps zmm1, zmm2, zmm3, {rz-sae}").unwrap();
+        let expected = [
+            encode64(
+                Instruction::new(
+                    Mnemonic::Vaddps,
+                    &[Register::Zmm(1).into(), Register::Zmm(2).into(), Mem::base(Register::RAX).broadcast(4).into()],
+                )
+                .mask(Register::Opmask(1))
+                .zeroing(),
+            ),
+            encode64(
+                Instruction::new(Mnemonic::Vaddps, &[Register::Zmm(1).into(), Register::Zmm(2).into(), Register::Zmm(3).into()])
+                    .rounding(RoundingControl::Zero),
+            ),
+        ]
+        .concat();
+        assert_eq!(code, expected);
+    }
+
+    #[test]
+    fn assemble_errors() {
+        let error = assemble("nop\n  frobnicate eax").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(error.kind, AsmErrorKind::UnknownMnemonic("frobnicate".to_string()));
+
+        let error = assemble("jmp nowhere").unwrap_err();
+        assert_eq!(error.kind, AsmErrorKind::UndefinedSymbol("nowhere".to_string()));
+
+        let error = assemble("a: nop\na: nop").unwrap_err();
+        assert_eq!((error.line, error.kind), (2, AsmErrorKind::DuplicateSymbol("a".to_string())));
+
+        let error = assemble("mov eax, [rax+rbx+rcx]").unwrap_err();
+        assert!(matches!(error.kind, AsmErrorKind::Syntax(_)));
+
+        let error = assemble("mov [rax], 1").unwrap_err();
+        assert_eq!(error.kind, AsmErrorKind::Encode(EncodeError::AmbiguousOperandSize(Mnemonic::Mov)));
+
+        let error = assemble("start: nop\nnop\nnop\ntimes 2 - ($ - start) nop").unwrap_err();
+        assert_eq!(error.kind, AsmErrorKind::NegativeCount);
+
+        assert_eq!(assemble("nop\nbits 32").unwrap_err().line, 2);
+        assert_eq!(error.to_string(), "line 4: negative repeat count");
+    }
+}
+
+#[cfg(test)]
+mod format {
+    use lib_opcode::prelude::*;
+
+    fn formatted(syntax: Syntax, bytes: &[u8]) -> String {
+        let op = decode_at(Bitness::Bits64, bytes, 0x1000).unwrap();
+        Formatter::new(syntax).format(&op)
+    }
+
+    #[test]
+    fn format_intel() {
+        let bytes = [0x48, 0x8B, 0x44, 0xC8, 0x10];
+        assert_eq!(formatted(Syntax::Nasm, &bytes), "mov rax, qword [rax+rcx*8+0x10]");
+        assert_eq!(formatted(Syntax::Masm, &bytes), "mov rax, qword ptr [rax+rcx*8+10h]");
+
+        let fs = [0x64, 0x48, 0x8B, 0x04, 0x25, 0x28, 0x00, 0x00, 0x00];
+        assert_eq!(formatted(Syntax::Nasm, &fs), "mov rax, qword [fs:0x28]");
+        assert_eq!(formatted(Syntax::Masm, &fs), "mov rax, qword ptr fs:[28h]");
+
+        let lea = [0x48, 0x8D, 0x3D, 0x00, 0x01, 0x00, 0x00];
+        assert_eq!(formatted(Syntax::Nasm, &lea), "lea rdi, [rel 0x1107]");
+        assert_eq!(formatted(Syntax::Nasm, &[0xF3, 0x48, 0xAB]), "rep stosq");
+        assert_eq!(formatted(Syntax::Masm, &[0xD9, 0xC1]), "fld st(1)");
+        assert_eq!(formatted(Syntax::Nasm, &[0x62, 0xF1, 0xFD, 0x49, 0x58, 0xC1]), "vaddpd zmm0{k1}, zmm0, zmm1");
+
+        // Display uses NASM syntax
+        let op = decode_at(Bitness::Bits64, &[0xE8, 0x10, 0x00, 0x00, 0x00], 0x1000).unwrap();
+        assert_eq!(op.to_string(), "call 0x1015");
+    }
+
+    #[test]
+    fn format_att() {
+        assert_eq!(formatted(Syntax::Att, &[0x48, 0x8B, 0x44, 0xC8, 0x10]), "mov 0x10(%rax,%rcx,8), %rax");
+        assert_eq!(formatted(Syntax::Att, &[0x64, 0x48, 0x8B, 0x04, 0x25, 0x28, 0x00, 0x00, 0x00]), "mov %fs:0x28, %rax");
+        assert_eq!(formatted(Syntax::Att, &[0x48, 0x0F, 0xB6, 0xC0]), "movzbq %al, %rax");
+        assert_eq!(formatted(Syntax::Att, &[0x48, 0x98]), "cltq");
+        assert_eq!(formatted(Syntax::Att, &[0xFF, 0xD0]), "call *%rax");
+        assert_eq!(formatted(Syntax::Att, &[0xC7, 0x00, 0x01, 0x00, 0x00, 0x00]), "movl $0x1, (%rax)");
+        assert_eq!(formatted(Syntax::Att, &[0x62, 0xF1, 0xFD, 0x49, 0x58, 0xC1]), "vaddpd %zmm1, %zmm0, %zmm0{%k1}");
+        // the immediates of ENTER are not reversed
+        assert_eq!(formatted(Syntax::Att, &[0xC8, 0x10, 0x00, 0x01]), "enter $0x10, $0x1");
+    }
+
+    #[test]
+    fn format_go() {
+        assert_eq!(formatted(Syntax::Go, &[0x48, 0x8B, 0x44, 0xC8, 0x10]), "MOVQ 0x10(AX)(CX*8), AX");
+        assert_eq!(formatted(Syntax::Go, &[0x48, 0x8D, 0x3D, 0x00, 0x01, 0x00, 0x00]), "LEAQ 0x100(IP), DI");
+        assert_eq!(formatted(Syntax::Go, &[0x48, 0x0F, 0xB6, 0xC0]), "MOVBQZX AL, AX");
+        assert_eq!(formatted(Syntax::Go, &[0xF3, 0x48, 0xAB]), "REP; STOSQ");
+        assert_eq!(formatted(Syntax::Go, &[0x62, 0xF1, 0xFD, 0x49, 0x58, 0xC1]), "VADDPD Z1, Z0, K1, Z0");
+    }
+
+    #[test]
+    fn format_options() {
+        let op = decode_at(Bitness::Bits64, &[0x48, 0x83, 0xC0, 0xF0], 0).unwrap();
+        let formatter = Formatter::new(Syntax::Nasm).uppercase(true).hex_style(HexStyle::Suffix);
+        assert_eq!(formatter.format(&op), "ADD RAX, 0FFFFFFFFFFFFFFF0h");
+
+        let resolver = |address: u64| (address == 0x1015).then(|| "memcpy".to_string());
+        let call = decode_at(Bitness::Bits64, &[0xE8, 0x10, 0x00, 0x00, 0x00], 0x1000).unwrap();
+        let lea = decode_at(Bitness::Bits64, &[0x48, 0x8D, 0x3D, 0x0E, 0x00, 0x00, 0x00], 0x1000).unwrap();
+        for (syntax, call_text, lea_text) in [
+            (Syntax::Nasm, "call memcpy", "lea rdi, [rel memcpy]"),
+            (Syntax::Att, "call memcpy", "lea memcpy(%rip), %rdi"),
+            (Syntax::Go, "CALL memcpy(SB)", "LEAQ memcpy(SB), DI"),
+        ] {
+            let formatter = Formatter::new(syntax).symbols(&resolver);
+            assert_eq!(formatter.format(&call), call_text);
+            assert_eq!(formatter.format(&lea), lea_text);
+        }
+    }
+
+    #[test]
+    fn format_nasm_round_trips_through_the_assembler() {
+        let source = "start: push rbp
+             mov rbp, rsp
+             lea rdi, [rel data]
+             vaddps zmm1{k2}{z}, zmm2, dword [rax+0x40]{1to16}
+             vaddpd zmm0, zmm1, zmm2, {rz-sae}
+             jrcxz start
+             push 0x190
+             call start
+             data: dq 0";
+        let object = assemble_object(&format!("org 0x401000\n{source}")).unwrap();
+        let bytes = object.flat();
+        let text: Vec<String> = Decoder::new(Bitness::Bits64, &bytes[..bytes.len() - 8], object.base)
+            .map(|op| op.unwrap().to_string())
+            .collect();
+
+        let again = assemble(&format!("org 0x401000\n{}", text.join("\n"))).unwrap();
+        assert_eq!(again, bytes[..bytes.len() - 8]);
+    }
+
+    #[test]
+    fn disassemble_memory() {
+        use lib_types::memory::ByteUnits;
+        use lib_x86::builders::MachineOptions;
+
+        let mut machine = MachineOptions::builder()
+            .memory(ByteUnits::Bytes(64))
+            .build_machine();
+        machine.memory.write(0x10, &[0x48, 0x01, 0xD8, 0xC3, 0x06]).unwrap();
+        machine.set_instruction_counter(0x10);
+
+        let text = machine.disassemble_at_ip(2, &Formatter::new(Syntax::Att));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0000000000000010  48 01 D8"));
+        assert!(lines[0].ends_with("add %rbx, %rax"));
+        assert!(lines[1].ends_with("ret"));
+
+        // stops at bytes that do not decode
+        let text = machine.memory.disassemble(0x13, 10, Bitness::Bits64, &Formatter::default());
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.lines().nth(1).unwrap().contains("(bad"));
+    }
+}
+
+#[cfg(test)]
+mod execute {
+    use lib_opcode::prelude::Mnemonic;
+    use lib_x86::prelude::*;
+    use crate::fixture::{load, CODE};
+
+    fn exit_with_rdi(machine: &mut X86Machine) {
+        let status = machine.register(Register::RDI);
+        machine.exit(status);
+    }
+
+    #[test]
+    fn step_moves_data_and_advances_rip() {
+        let mut machine = load(
+            "mov rax, -1
+             mov eax, 0x12345678
+             mov rbx, rax
+             mov bl, 0xFF
+             mov bh, 0x11
+             mov word [data], bx
+             movzx ecx, byte [data + 1]
+             movsx rdx, word [data]
+             lea rsi, [rbx + rcx*4 + 8]
+             lea rdi, [rel data]
+             hlt
+             data: dq 0",
+        );
+        assert_eq!(machine.step(), None);
+        assert_eq!(machine.register(Register::RAX), u64::MAX);
+        assert_eq!(machine.instruction_counter, CODE + 7);
+
+        assert_eq!(machine.step(), None);
+        // 32-bit writes clear the upper half
+        assert_eq!(machine.register(Register::RAX), 0x12345678);
+
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.register(Register::RBX), 0x123411FF);
+        assert_eq!(machine.register(Register::RCX), 0x11);
+        assert_eq!(machine.register(Register::RDX), 0x11FF);
+        assert_eq!(machine.register(Register::RSI), 0x123411FF + 0x44 + 8);
+        assert_eq!(machine.register(Register::RDI), machine.instruction_counter);
+        assert_eq!(machine.instructions_retired, 11);
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut machine = load("nop\nint3\nnop\ntarget: nop\nhlt");
+        assert_eq!(machine.run(), StopReason::Breakpoint(CODE + 1));
+        assert_eq!(machine.instruction_counter, CODE + 2);
+
+        machine.breakpoints.insert(CODE + 3);
+        assert_eq!(machine.run(), StopReason::Breakpoint(CODE + 3));
+        // resuming runs past the breakpoint it stopped at
+        assert_eq!(machine.run(), StopReason::Halted);
+    }
+
+    #[test]
+    fn faults_leave_rip_on_the_instruction() {
+        let mut machine = load("nop\nud2");
+        let fault = Fault::Exception { exception: Exception::InvalidOpcode, address: CODE + 1 };
+        assert_eq!(machine.run(), StopReason::Fault(fault));
+        assert_eq!(machine.instruction_counter, CODE + 1);
+        assert_eq!(machine.instructions_retired, 1);
+
+        let mut machine = load("mov rax, [0x7FFFFFFF]");
+        let fault = Fault::Exception { exception: Exception::PageFault(0x7FFFFFFF), address: CODE };
+        assert_eq!(machine.run(), StopReason::Fault(fault));
+
+        let mut machine = load("int 0x80");
+        let fault = Fault::InterruptNotFound { vector: 0x80, address: CODE };
+        assert_eq!(machine.run(), StopReason::Fault(fault));
+        assert_eq!(machine.instruction_counter, CODE);
+
+        let mut machine = load("rdrand eax");
+        let fault = Fault::Unsupported { mnemonic: Mnemonic::Rdrand, address: CODE };
+        assert_eq!(machine.step(), Some(StopReason::Fault(fault)));
+    }
+
+    #[test]
+    fn exceptions_go_through_the_interrupt_vector() {
+        fn skip_ud2(machine: &mut X86Machine) {
+            machine.instruction_counter += 2;
+        }
+        let mut machine = load("ud2\nmov eax, 1\nhlt");
+        machine
+            .interrupts
+            .set(6, SystemFunction::IntrinsicFunction(Intrinsic::from_ptr(skip_ud2)));
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.register(Register::RAX), 1);
+
+        // a handler in guest code
+        let mut machine = load("int 0x80\nhlt\nhandler: mov eax, 2\nhlt");
+        machine.interrupts.set(0x80, SystemFunction::Pointer(CODE + 3));
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.register(Register::RAX), 2);
+    }
+
+    #[test]
+    fn syscalls_and_exit() {
+        let mut machine = load("mov eax, 60\nmov edi, 3\nsyscall\nhlt");
+        machine
+            .syscalls
+            .set(60, SystemFunction::IntrinsicFunction(Intrinsic::from_ptr(exit_with_rdi)));
+        assert_eq!(machine.run(), StopReason::Exit(3));
+        assert_eq!(machine.register(Register::RCX), machine.instruction_counter);
+
+        let mut machine = load("mov eax, 1000\nsyscall");
+        let fault = Fault::SyscallNotFound { number: 1000, address: CODE + 5 };
+        assert_eq!(machine.run(), StopReason::Fault(fault));
+    }
+
+    #[test]
+    fn xlatb_looks_up_al() {
+        let mut machine = load("lea rbx, [table]\nmov eax, 0x1202\nxlatb\nhlt\ntable: db 10, 20, 30");
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.register(Register::RAX), 0x121E);
+    }
+
+    #[test]
+    fn budget_and_run_until() {
+        let mut machine = load("spin: jmp spin");
+        machine.instruction_budget = Some(0);
+        assert_eq!(machine.run(), StopReason::BudgetExhausted);
+
+        let mut machine = load("nop\nnop\nnop\nhlt");
+        let reason = machine.run_until(|m| m.instruction_counter == CODE + 2);
+        assert_eq!((reason, machine.instructions_retired), (StopReason::Condition, 2));
+        assert_eq!(machine.run(), StopReason::Halted);
+    }
+}
+
+#[cfg(test)]
+mod alu {
+    use lib_x86::prelude::*;
+    use crate::fixture::run;
+
+    fn flags(machine: &X86Machine) -> Vec<RFlags> {
+        [RFlags::Carry, RFlags::Parity, RFlags::AuxCarry, RFlags::Zero, RFlags::Sign, RFlags::Overflow]
+            .into_iter()
+            .filter(|f| machine.flags.is_set(*f))
+            .collect()
+    }
+
+    #[test]
+    fn add_and_sub_flags() {
+        let machine = run("mov al, 0x7F\nadd al, 1");
+        assert_eq!(machine.register(Register::AL), 0x80);
+        assert_eq!(flags(&machine), [RFlags::AuxCarry, RFlags::Sign, RFlags::Overflow]);
+
+        let machine = run("mov eax, -1\nadd eax, 1");
+        assert_eq!(machine.register(Register::RAX), 0);
+        assert_eq!(flags(&machine), [RFlags::Carry, RFlags::Parity, RFlags::AuxCarry, RFlags::Zero]);
+
+        let machine = run("mov rax, 0x8000000000000000\nsub rax, 1");
+        assert_eq!(machine.register(Register::RAX), 0x7FFFFFFFFFFFFFFF);
+        assert_eq!(flags(&machine), [RFlags::Parity, RFlags::AuxCarry, RFlags::Overflow]);
+
+        let machine = run("mov cx, 1\ncmp cx, 2");
+        assert_eq!(machine.register(Register::CX), 1);
+        assert_eq!(flags(&machine), [RFlags::Carry, RFlags::Parity, RFlags::AuxCarry, RFlags::Sign]);
+
+        // multi-word arithmetic through the carry
+        let machine = run(
+            "mov rax, -1\nmov rdx, 0\nadd rax, 1\nadc rdx, 0
+             mov rbx, 0\nmov rcx, 5\nsub rbx, 1\nsbb rcx, 0",
+        );
+        assert_eq!(machine.register(Register::RDX), 1);
+        assert_eq!(machine.register(Register::RCX), 4);
+    }
+
+    #[test]
+    fn logic_clears_carry_and_overflow() {
+        let machine = run("mov eax, -1\nadd eax, 0x80000000\nand eax, 0x0F");
+        assert_eq!(machine.register(Register::RAX), 0x0F);
+        assert_eq!(flags(&machine), [RFlags::Parity]);
+
+        let machine = run("mov rdx, 0x1234\nxor edx, edx");
+        assert_eq!(machine.register(Register::RDX), 0);
+        assert_eq!(flags(&machine), [RFlags::Parity, RFlags::Zero]);
+
+        let machine = run("mov bl, 0x81\ntest bl, 0x80\nor bh, 3");
+        assert_eq!(machine.register(Register::RBX), 0x0381);
+        assert_eq!(flags(&machine), [RFlags::Parity]);
+    }
+
+    #[test]
+    fn unary_operations() {
+        // inc and dec leave CF alone
+        let machine = run("mov eax, -1\nadd eax, 1\nmov ecx, 0x7FFFFFFF\ninc ecx");
+        assert_eq!(machine.register(Register::RCX), 0x80000000);
+        assert_eq!(flags(&machine), [RFlags::Carry, RFlags::Parity, RFlags::AuxCarry, RFlags::Sign, RFlags::Overflow]);
+
+        let machine = run("mov ax, 0\ndec ax");
+        assert_eq!(machine.register(Register::RAX), 0xFFFF);
+
+        let machine = run("mov rax, 5\nneg rax");
+        assert_eq!(machine.register(Register::RAX), (-5i64) as u64);
+        assert!(machine.flags.is_set(RFlags::Carry));
+        let machine = run("mov rax, 0\nneg rax");
+        assert_eq!(flags(&machine), [RFlags::Parity, RFlags::Zero]);
+
+        let machine = run("mov rax, -1\nnot eax");
+        assert_eq!(machine.register(Register::RAX), 0);
+        assert_eq!(machine.flags.value() & ARITHMETIC_FLAGS, 0);
+    }
+
+    #[test]
+    fn memory_operands_and_zero_extension() {
+        let machine = run(
+            "mov rax, -1
+             mov eax, eax
+             mov rbx, -1
+             add bx, 1
+             mov rcx, -1
+             add ecx, 0
+             mov qword [value], 10
+             add qword [value], -3
+             sub dword [value + 4], 1
+             mov rdx, [value]
+             hlt
+             value: dq 0",
+        );
+        assert_eq!(machine.register(Register::RAX), 0xFFFFFFFF);
+        assert_eq!(machine.register(Register::RBX), 0xFFFFFFFFFFFF0000);
+        assert_eq!(machine.register(Register::RCX), 0xFFFFFFFF);
+        assert_eq!(machine.register(Register::RDX), 0xFFFFFFFF00000007);
+    }
+
+    #[test]
+    fn carry_instructions() {
+        let machine = run("stc\nmov eax, 1\nadc eax, 0\nclc\nadc eax, 0\ncmc\ncmc\ncmc\nsetc bl");
+        assert_eq!(machine.register(Register::RAX), 2);
+        assert_eq!(machine.register(Register::BL), 1);
+
+        // CMC flips a carry still to be worked out and leaves the other flags alone
+        let machine = run("mov eax, -1\nadd eax, 1\ncmc");
+        assert_eq!(flags(&machine), [RFlags::Parity, RFlags::AuxCarry, RFlags::Zero]);
+    }
+}
+
+#[cfg(test)]
+mod shift {
+    use lib_x86::prelude::*;
+    use crate::fixture::run;
+
+    fn flags(machine: &X86Machine) -> Vec<RFlags> {
+        [RFlags::Carry, RFlags::Parity, RFlags::AuxCarry, RFlags::Zero, RFlags::Sign, RFlags::Overflow]
+            .into_iter()
+            .filter(|f| machine.flags.is_set(*f))
+            .collect()
+    }
+
+    #[test]
+    fn shifts() {
+        let machine = run("mov al, 0x81\nshl al, 1");
+        assert_eq!(machine.register(Register::AL), 0x02);
+        assert_eq!(flags(&machine), [RFlags::Carry, RFlags::Overflow]);
+
+        let machine = run("mov ax, 0x8001\nsar ax, 1");
+        assert_eq!(machine.register(Register::AX), 0xC000);
+        assert_eq!(flags(&machine), [RFlags::Carry, RFlags::Parity, RFlags::Sign]);
+
+        let machine = run("mov rdx, -8\nmov cl, 2\nsar rdx, cl");
+        assert_eq!(machine.register(Register::RDX), (-2i64) as u64);
+
+        // OF for counts above 1 is what a shift by 1 gives
+        let machine = run("mov eax, 0x80000000\nshr eax, 31");
+        assert_eq!(machine.register(Register::RAX), 1);
+        assert_eq!(flags(&machine), [RFlags::Overflow]);
+    }
+
+    #[test]
+    fn counts_are_masked() {
+        let machine = run("mov eax, 1\nshl eax, 33\nmov rdx, 1\nshl rdx, 65");
+        assert_eq!(machine.register(Register::RAX), 2);
+        assert_eq!(machine.register(Register::RDX), 2);
+
+        // a count of 0 leaves the flags, but the 32-bit destination is still zero extended
+        let machine = run("mov eax, -1\nadd eax, 1\nmov rbx, -1\nmov cl, 32\nshl ebx, cl");
+        assert_eq!(machine.register(Register::RBX), 0xFFFFFFFF);
+        assert_eq!(flags(&machine), [RFlags::Carry, RFlags::Parity, RFlags::AuxCarry, RFlags::Zero]);
+    }
+
+    #[test]
+    fn rotates() {
+        let machine = run("mov al, 0x81\nrol al, 1");
+        assert_eq!(machine.register(Register::AL), 0x03);
+        assert_eq!(flags(&machine), [RFlags::Carry, RFlags::Overflow]);
+
+        let machine = run("mov eax, 1\nror eax, 4");
+        assert_eq!(machine.register(Register::RAX), 0x10000000);
+        assert!(!machine.flags.is_set(RFlags::Carry));
+
+        // through the carry, set here by the add
+        let machine = run("mov al, 0xFF\nadd al, 1\nmov bl, 0x80\nrcl bl, 1");
+        assert_eq!(machine.register(Register::BL), 0x01);
+        assert!(machine.flags.is_set(RFlags::Carry));
+        let machine = run("mov al, 0xFF\nadd al, 1\nmov bl, 0\nrcr bl, 1");
+        assert_eq!(machine.register(Register::BL), 0x80);
+        assert!(!machine.flags.is_set(RFlags::Carry));
+
+        // 9 bits around an 8-bit rcl is a whole turn
+        let machine = run("mov al, 0xFF\nadd al, 1\nmov bl, 0x5A\nrcl bl, 9");
+        assert_eq!(machine.register(Register::BL), 0x5A);
+        assert_eq!(flags(&machine), [RFlags::Carry, RFlags::Parity, RFlags::AuxCarry, RFlags::Zero]);
+    }
+
+    #[test]
+    fn double_precision_shifts() {
+        let machine = run("mov eax, 0x12345678\nmov edx, 0x9ABCDEF0\nshld eax, edx, 8");
+        assert_eq!(machine.register(Register::RAX), 0x3456789A);
+        assert!(!machine.flags.is_set(RFlags::Carry));
+
+        let machine = run("mov rax, 1\nmov rdx, 1\nshrd rax, rdx, 1");
+        assert_eq!(machine.register(Register::RAX), 0x8000000000000000);
+        assert!(machine.flags.is_set(RFlags::Carry));
+
+        // 16-bit counts past the source carry on into the destination
+        let machine = run("mov ax, 0xAAC3\nmov cx, 0\nshrd ax, cx, 20");
+        assert_eq!(machine.register(Register::AX), 0x3000);
+    }
+
+    #[test]
+    fn bmi2_shifts_leave_flags() {
+        let machine = run("mov eax, -1\nadd eax, 1\nmov rax, 1\nmov ecx, 65\nshlx rbx, rax, rcx");
+        assert_eq!(machine.register(Register::RBX), 2);
+        assert_eq!(flags(&machine), [RFlags::Carry, RFlags::Parity, RFlags::AuxCarry, RFlags::Zero]);
+
+        let machine = run("mov eax, 0x80000000\nmov ecx, 4\nsarx edx, eax, ecx\nshrx esi, eax, ecx");
+        assert_eq!(machine.register(Register::RDX), 0xF8000000);
+        assert_eq!(machine.register(Register::RSI), 0x08000000);
+
+        let machine = run("mov eax, 0x12345678\nrorx ebx, eax, 8");
+        assert_eq!(machine.register(Register::RBX), 0x78123456);
+        assert_eq!(machine.flags.value() & ARITHMETIC_FLAGS, 0);
+    }
+}
+
+#[cfg(test)]
+mod muldiv {
+    use lib_x86::functions::{Intrinsic, SystemFunction};
+    use lib_x86::prelude::*;
+    use crate::fixture::{load, run, CODE};
+
+    #[test]
+    fn multiply() {
+        let machine = run("mov rax, -1\nmov rcx, 16\nmul rcx");
+        assert_eq!(machine.register(Register::RAX), 0xFFFFFFFFFFFFFFF0);
+        assert_eq!(machine.register(Register::RDX), 0xF);
+        assert!(machine.flags.is_set(RFlags::Carry) && machine.flags.is_set(RFlags::Overflow));
+
+        // 8-bit products fill AX, 32-bit ones clear the upper halves of RDX:RAX
+        let machine = run("mov rdx, -1\nmov al, 200\nmov cl, 3\nmul cl\nmov ebx, 2\nmul ebx");
+        assert_eq!(machine.register(Register::RAX), 1200);
+        assert_eq!(machine.register(Register::RDX), 0);
+        assert!(!machine.flags.is_set(RFlags::Carry));
+
+        let machine = run("mov rax, -3\nmov rcx, 5\nimul rcx");
+        assert_eq!(machine.register(Register::RAX), (-15i64) as u64);
+        assert_eq!(machine.register(Register::RDX), u64::MAX);
+        assert!(!machine.flags.is_set(RFlags::Overflow));
+    }
+
+    #[test]
+    fn imul_forms() {
+        let machine = run("mov ecx, -7\nmov ebx, 6\nimul ebx, ecx\nimul esi, ecx, 100");
+        assert_eq!(machine.register(Register::RBX), (-42i32) as u32 as u64);
+        assert_eq!(machine.register(Register::RSI), (-700i32) as u32 as u64);
+        assert!(!machine.flags.is_set(RFlags::Carry));
+
+        // the product no longer fits the destination
+        let machine = run("mov ax, 0x4000\nimul ax, ax, 2");
+        assert_eq!(machine.register(Register::AX), 0x8000);
+        assert!(machine.flags.is_set(RFlags::Carry) && machine.flags.is_set(RFlags::Overflow));
+    }
+
+    #[test]
+    fn divide() {
+        let machine = run("mov rdx, 1\nmov rax, 5\nmov rcx, 2\ndiv rcx");
+        assert_eq!(machine.register(Register::RAX), 0x8000000000000002);
+        assert_eq!(machine.register(Register::RDX), 1);
+
+        // quotient in AL, remainder in AH
+        let machine = run("mov ax, 1003\nmov cl, 10\ndiv cl");
+        assert_eq!(machine.register(Register::AX), 3 << 8 | 100);
+
+        // rounds toward zero, the remainder takes the sign of the dividend
+        let machine = run("mov eax, -7\nmov edx, -1\nmov ecx, 2\nidiv ecx");
+        assert_eq!(machine.register(Register::RAX), (-3i32) as u32 as u64);
+        assert_eq!(machine.register(Register::RDX), (-1i32) as u32 as u64);
+    }
+
+    #[test]
+    fn divide_errors_go_through_vector_0() {
+        let mut machine = load("mov eax, 10\nxor ecx, ecx\ndiv ecx");
+        let fault = Fault::Exception { exception: Exception::DivideError, address: CODE + 7 };
+        assert_eq!(machine.run(), StopReason::Fault(fault));
+        assert_eq!(machine.instruction_counter, CODE + 7);
+        assert_eq!(machine.register(Register::RAX), 10);
+
+        // the quotient does not fit
+        let mut machine = load("mov edx, 1\nmov eax, 0\nmov ecx, 1\ndiv ecx");
+        assert!(matches!(machine.run(), StopReason::Fault(Fault::Exception { exception: Exception::DivideError, .. })));
+        let mut machine = load("mov rax, 0x8000000000000000\nmov rdx, -1\nmov rcx, -1\nidiv rcx");
+        assert!(matches!(machine.run(), StopReason::Fault(Fault::Exception { exception: Exception::DivideError, .. })));
+
+        fn skip_div(machine: &mut X86Machine) {
+            machine.set_register(Register::RAX, 0xDE);
+            machine.instruction_counter += 2;
+        }
+        let mut machine = load("xor ecx, ecx\ndiv ecx");
+        machine
+            .interrupts
+            .set(0, SystemFunction::IntrinsicFunction(Intrinsic::from_ptr(skip_div)));
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.register(Register::RAX), 0xDE);
+    }
+
+    #[test]
+    fn mulx_leaves_flags() {
+        let machine = run("mov eax, -1\nadd eax, 1\nmov rdx, -1\nmov rcx, 4\nmulx rbx, rsi, rcx");
+        assert_eq!(machine.register(Register::RBX), 3);
+        assert_eq!(machine.register(Register::RSI), 0xFFFFFFFFFFFFFFFC);
+        assert!(machine.flags.is_set(RFlags::Carry) && machine.flags.is_set(RFlags::Zero));
+
+        // the same register twice gets the high half
+        let machine = run("mov edx, 0x80000000\nmov ecx, 4\nmulx eax, eax, ecx");
+        assert_eq!(machine.register(Register::RAX), 2);
+    }
+
+    #[test]
+    fn sign_extensions() {
+        // CBW and CWD leave the rest of RAX and RDX, the 32-bit forms clear it
+        let machine = run("mov rax, -1\nmov al, 0x80\ncbw\nmov rdx, -1\nmov ax, 5\ncwd");
+        assert_eq!(machine.register(Register::RAX), 0xFFFFFFFFFFFF0005);
+        assert_eq!(machine.register(Register::RDX), 0xFFFFFFFFFFFF0000);
+        let machine = run("mov rax, -1\nmov eax, 0x8000\ncwde\nmov rbx, rax\ncdq");
+        assert_eq!(machine.register(Register::RBX), 0xFFFF8000);
+        assert_eq!(machine.register(Register::RDX), 0xFFFFFFFF);
+
+        let machine = run("mov eax, -7\ncdqe\ncqo\nmov rcx, 2\nidiv rcx");
+        assert_eq!(machine.register(Register::RAX), (-3i64) as u64);
+        assert_eq!(machine.register(Register::RDX), u64::MAX);
+    }
+}
+
+#[cfg(test)]
+mod branch {
+    use lib_opcode::prelude::Mnemonic;
+    use lib_x86::prelude::*;
+
+    use crate::fixture::{run_with, BUDGET};
+
+    /// With the stack in the middle of memory, for tests that look at what is above it
+    fn run(source: &str) -> X86Machine {
+        run_with(source, BUDGET, |machine| machine.set_register(Register::RSP, 0x8000))
+    }
+
+    #[test]
+    fn conditional_jumps_and_setcc() {
+        let machine = run("mov ecx, 10\nxor eax, eax\nagain: add eax, ecx\ndec ecx\njnz again");
+        assert_eq!(machine.register(Register::RAX), 55);
+
+        // -1 is less than 1 signed, but not unsigned
+        let machine = run("mov eax, -1\ncmp eax, 1\nsetl bl\nsetb cl\nsetg dl\nseta dh");
+        assert_eq!(machine.register(Register::BL), 1);
+        assert_eq!(machine.register(Register::CL), 0);
+        assert_eq!(machine.register(Register::DL), 0);
+        assert_eq!(machine.register(Register::DH), 1);
+
+        let machine = run("mov eax, 5\nsub eax, 5\njne skip\nmov ebx, 1\nskip: jmp done\nmov ebx, 2\ndone:");
+        assert_eq!(machine.register(Register::RBX), 1);
+    }
+
+    #[test]
+    fn call_and_ret() {
+        let machine = run(
+            "call five
+             mov ebx, eax
+             lea rdx, [rel six]
+             call rdx
+             add ebx, eax
+             call [rel pointer]
+             add ebx, eax
+             jmp done
+             five: mov eax, 5
+             ret
+             six: mov eax, 6
+             ret
+             seven: mov eax, 7
+             ret
+             pointer: dq seven
+             done:",
+        );
+        assert_eq!(machine.register(Register::RBX), 18);
+        assert_eq!(machine.register(Register::RSP), 0x8000);
+
+        // ret imm16 releases the callee's arguments
+        let machine = run("sub rsp, 16\ncall callee\njmp done\ncallee: ret 16\ndone:");
+        assert_eq!(machine.register(Register::RSP), 0x8000);
+
+        // far calls push a CS slot as well
+        let machine = run("callf tword [rel target]\njmp done\nfar: mov eax, 1\ndb 0x48, 0xCB\ntarget: dq far\ndw 0x33\ndone:");
+        assert_eq!(machine.register(Register::RAX), 1);
+        assert_eq!(machine.register(Register::RSP), 0x8000);
+
+        // a 66 prefix makes the return address 16 bits
+        let machine = run("lea rax, [rel back]\nmov rbx, 0xABCD0000\nor rbx, rax\npush rbx\ndb 0x66, 0xC3\nback:");
+        assert_eq!(machine.register(Register::RSP), 0x8000 - 6);
+        let machine = run("lea rax, [rel there]\ndb 0x66, 0xFF, 0xD0\nthere: pop bx");
+        assert_eq!(machine.register(Register::RBX), machine.register(Register::RAX));
+        assert_eq!(machine.register(Register::RSP), 0x8000);
+    }
+
+    #[test]
+    fn loops() {
+        let machine = run("mov ecx, 5\nxor eax, eax\nagain: inc eax\nloop again");
+        assert_eq!(machine.register(Register::RAX), 5);
+        assert_eq!(machine.register(Register::RCX), 0);
+
+        // loopne stops once the comparison is equal
+        let machine = run("mov ecx, 5\nxor eax, eax\nagain: inc eax\ncmp eax, 3\nloopne again");
+        assert_eq!(machine.register(Register::RAX), 3);
+        assert_eq!(machine.register(Register::RCX), 2);
+
+        let machine = run("mov rcx, 0x100000000\njecxz zero\nmov eax, 1\nzero: jrcxz done\nmov ebx, 1\ndone:");
+        assert_eq!(machine.register(Register::RAX), 0);
+        assert_eq!(machine.register(Register::RBX), 1);
+    }
+
+    #[test]
+    fn cmovcc() {
+        let machine = run("mov rax, -1\nmov ecx, 5\ncmp ecx, 5\ncmove rbx, rcx\ncmovne eax, ecx");
+        assert_eq!(machine.register(Register::RBX), 5);
+        // not moved, but a 32-bit destination is still zero extended
+        assert_eq!(machine.register(Register::RAX), 0xFFFFFFFF);
+    }
+
+    #[test]
+    fn condition_evaluator() {
+        assert_eq!(Condition::from_mnemonic(Mnemonic::Jg), Some(Condition::Greater));
+        assert_eq!(Condition::from_mnemonic(Mnemonic::Cmovae), Some(Condition::AboveOrEqual));
+        assert_eq!(Condition::from_mnemonic(Mnemonic::Add), None);
+        assert_eq!(Condition::from_code(0x84 & 0xF), Condition::Equal);
+
+        let flags = RFlags::Sign as u64;
+        assert!(Condition::Less.holds(&flags));
+        assert!(!Condition::GreaterOrEqual.holds(&flags));
+        let flags = RFlags::Sign | RFlags::Overflow;
+        assert!(Condition::Greater.holds(&flags));
+        assert!(Condition::LessOrEqual.holds(&(RFlags::Zero as u64)));
+    }
+}
+
+#[cfg(test)]
+mod string {
+    use lib_x86::prelude::*;
+    use crate::fixture::{load, run, CODE};
+
+    #[test]
+    fn rep_movsb_and_stosb() {
+        let mut machine = load("mov esi, 0x2000\nmov edi, 0x3000\nmov ecx, 5\nrep movsb\nmov al, 0xAA\nmov ecx, 3\nrep stosb");
+        machine.memory.write(0x2000, b"hello").unwrap();
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.memory.read(0x3000, 9).unwrap(), b"hello\xAA\xAA\xAA\x00");
+        assert_eq!(machine.register(Register::RSI), 0x2005);
+        assert_eq!(machine.register(Register::RDI), 0x3008);
+        assert_eq!(machine.register(Register::RCX), 0);
+
+        // copying forwards onto itself repeats the first bytes, as it would one at a time
+        let mut machine = load("mov esi, 0x2000\nmov edi, 0x2002\nmov ecx, 6\nrep movsb");
+        machine.memory.write(0x2000, b"ab").unwrap();
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.memory.read(0x2000, 8).unwrap(), b"abababab");
+
+        // wider elements, and a zero count does nothing
+        let machine = run("mov rax, 0x1122334455667788\nmov edi, 0x3000\nmov ecx, 2\nrep stosq\nrep stosq\nmov esi, 0x3008\nlodsd");
+        assert_eq!(machine.register(Register::RDI), 0x3010);
+        assert_eq!(machine.register(Register::RAX), 0x55667788);
+        assert_eq!(machine.register(Register::RSI), 0x300C);
+    }
+
+    #[test]
+    fn direction_flag() {
+        let mut machine = load("std\nmov esi, 0x2003\nmov edi, 0x3003\nmov ecx, 4\nrep movsb\ncld");
+        machine.memory.write(0x2000, b"abcd").unwrap();
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.memory.read(0x3000, 4).unwrap(), b"abcd");
+        assert_eq!(machine.register(Register::RSI), 0x1FFF);
+        assert_eq!(machine.register(Register::RDI), 0x2FFF);
+        assert!(!machine.flags.is_set(RFlags::Direction));
+
+        let machine = run("std\nmov edi, 0x3008\nmov eax, 7\nstosw");
+        assert_eq!(machine.register(Register::RDI), 0x3006);
+        assert_eq!(machine.memory.read(0x3008, 2).unwrap(), [7, 0]);
+    }
+
+    #[test]
+    fn scas_and_cmps_stop_on_the_condition() {
+        // strlen
+        let mut machine = load("mov edi, 0x2000\nxor eax, eax\nmov rcx, -1\nrepne scasb\nnot rcx\ndec rcx");
+        machine.memory.write(0x2000, b"string\0").unwrap();
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.register(Register::RCX), 6);
+        assert_eq!(machine.register(Register::RDI), 0x2007);
+
+        let mut machine = load("mov esi, 0x2000\nmov edi, 0x3000\nmov ecx, 8\nrepe cmpsb");
+        machine.memory.write(0x2000, b"abcdef").unwrap();
+        machine.memory.write(0x3000, b"abcxef").unwrap();
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.register(Register::RCX), 4);
+        assert_eq!(machine.register(Register::RSI), 0x2004);
+        assert!(!machine.flags.is_set(RFlags::Zero));
+        // 'd' - 'x' borrows
+        assert!(machine.flags.is_set(RFlags::Carry));
+    }
+
+    #[test]
+    fn faults_restart_from_the_current_element() {
+        // the copy runs off the end of memory after two qwords
+        let mut machine = load("mov esi, 0x2000\nmov edi, 0xFFF0\nmov ecx, 4\nrep movsq");
+        let start = CODE + 15;
+        let fault = Fault::Exception { exception: Exception::PageFault(0x10000), address: start };
+        assert_eq!(machine.run(), StopReason::Fault(fault));
+        assert_eq!(machine.instruction_counter, start);
+        assert_eq!(machine.register(Register::RCX), 2);
+        assert_eq!(machine.register(Register::RSI), 0x2010);
+        assert_eq!(machine.register(Register::RDI), 0x10000);
+
+        // point the rest somewhere valid and carry on
+        machine.memory.write(0x2010, &[1; 16]).unwrap();
+        machine.set_register(Register::RDI, 0x4000);
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.register(Register::RCX), 0);
+        assert_eq!(machine.memory.read(0x4000, 16).unwrap(), [1; 16]);
+    }
+
+    #[test]
+    fn address_size_override() {
+        // a32 rep stosb: EDI and ECX, with the upper halves cleared
+        let machine = run("mov rdi, 0x100003000\nmov rcx, 0x100000002\nmov al, 9\ndb 0x67, 0xF3, 0xAA");
+        assert_eq!(machine.register(Register::RDI), 0x3002);
+        assert_eq!(machine.register(Register::RCX), 0);
+        assert_eq!(machine.memory.read(0x3000, 3).unwrap(), [9, 9, 0]);
+    }
+}
+
+#[cfg(test)]
+mod bits {
+    use lib_x86::prelude::*;
+    use crate::fixture::run;
+
+    #[test]
+    fn bit_test_forms() {
+        let machine = run("mov eax, 0b1010\nmov ecx, 3\nbt eax, ecx\nsetc bl\nbtr eax, 1\nbts eax, 36\nbtc ax, cx");
+        assert_eq!(machine.register(Register::BL), 1);
+        // bit 36 of a dword is bit 4
+        assert_eq!(machine.register(Register::RAX), 0b10000);
+        // BTC saw bit 3 set
+        assert!(machine.flags.is_set(RFlags::Carry));
+    }
+
+    #[test]
+    fn bit_test_memory_offsets() {
+        // a register offset reaches past the operand, in either direction
+        let machine = run("mov rcx, 100\nbts qword [0x2000], rcx\nmov rcx, -1\nbts qword [0x2000], rcx\nmov ecx, 7\nbts word [0x2000], 23");
+        assert_eq!(machine.memory.read(0x2008, 8).unwrap(), (1u64 << 36).to_le_bytes());
+        assert_eq!(machine.memory.read(0x1FF8, 8).unwrap(), (1u64 << 63).to_le_bytes());
+        // an immediate offset wraps within the word
+        assert_eq!(machine.memory.read(0x2000, 2).unwrap(), [0x80, 0]);
+        assert!(!machine.flags.is_set(RFlags::Carry));
+
+        let machine = run("mov dword [0x2004], 0x10\nmov ecx, 36\nbtr dword [0x2000], ecx");
+        assert!(machine.flags.is_set(RFlags::Carry));
+        assert_eq!(machine.memory.read(0x2004, 4).unwrap(), [0; 4]);
+    }
+
+    #[test]
+    fn scans_and_counts() {
+        let machine = run("mov ecx, 0x50\nbsf eax, ecx\nbsr ebx, ecx\npopcnt edx, ecx\nlzcnt esi, ecx\ntzcnt di, cx");
+        assert_eq!(machine.register(Register::RAX), 4);
+        assert_eq!(machine.register(Register::RBX), 6);
+        assert_eq!(machine.register(Register::RDX), 2);
+        assert_eq!(machine.register(Register::RSI), 25);
+        assert_eq!(machine.register(Register::RDI), 4);
+
+        // a zero source leaves BSF's destination alone but gives LZCNT / TZCNT the width
+        let machine = run("mov rax, -1\nxor ecx, ecx\nbsf rax, rcx\nsetz bl\ntzcnt rdx, rcx\nsetc bh\nlzcnt si, cx");
+        assert_eq!(machine.register(Register::RAX), u64::MAX);
+        assert_eq!(machine.register(Register::BX), 0x0101);
+        assert_eq!(machine.register(Register::RDX), 64);
+        assert_eq!(machine.register(Register::RSI), 16);
+
+        let machine = run("xor ecx, ecx\npopcnt rax, rcx");
+        assert!(machine.flags.is_set(RFlags::Zero));
+    }
+
+    #[test]
+    fn bmi1() {
+        let machine = run("mov eax, 0xF0\nmov ecx, 0xFF\nandn edx, eax, ecx\nmov ebx, 0x0804\nbextr esi, ecx, ebx");
+        assert_eq!(machine.register(Register::RDX), 0x0F);
+        assert_eq!(machine.register(Register::RSI), 0x0F);
+
+        let machine = run("mov ecx, 0b101000\nblsi eax, ecx\nblsmsk ebx, ecx\nblsr edx, ecx");
+        assert_eq!(machine.register(Register::RAX), 0b1000);
+        assert_eq!(machine.register(Register::RBX), 0b1111);
+        assert_eq!(machine.register(Register::RDX), 0b100000);
+        assert!(!machine.flags.is_set(RFlags::Carry));
+
+        // BLSI sets CF for a non-zero source, BLSR for a zero one
+        let machine = run("xor ecx, ecx\nblsr eax, ecx");
+        assert!(machine.flags.is_set(RFlags::Carry) && machine.flags.is_set(RFlags::Zero));
+        let machine = run("mov rcx, -8\nblsi rax, rcx");
+        assert!(machine.flags.is_set(RFlags::Carry));
+        assert_eq!(machine.register(Register::RAX), 8);
+    }
+
+    #[test]
+    fn bmi2() {
+        let machine = run("mov rax, -1\nmov ecx, 12\nbzhi rdx, rax, rcx\nmov ecx, 64\nbzhi rsi, rax, rcx");
+        assert_eq!(machine.register(Register::RDX), 0xFFF);
+        assert_eq!(machine.register(Register::RSI), u64::MAX);
+        assert!(machine.flags.is_set(RFlags::Carry));
+
+        let machine = run("mov eax, 0b101\nmov ecx, 0xF0F0\npdep edx, eax, ecx\nmov eax, 0xA0A0\npext esi, eax, ecx");
+        assert_eq!(machine.register(Register::RDX), 0b0101_0000);
+        assert_eq!(machine.register(Register::RSI), 0b1010_1010);
+    }
+
+    #[test]
+    fn adx_carry_chains() {
+        // two chains, one through CF and one through OF, interleaved
+        let machine = run(
+            "mov rax, -1\nmov rbx, -1\nxor ecx, ecx\nmov edx, 1\n\
+             adcx rax, rdx\nadox rbx, rdx\nadcx rcx, rcx\nmov esi, 0\nadox rsi, rsi",
+        );
+        assert_eq!(machine.register(Register::RAX), 0);
+        assert_eq!(machine.register(Register::RBX), 0);
+        assert_eq!(machine.register(Register::RCX), 1);
+        assert_eq!(machine.register(Register::RSI), 1);
+        assert!(!machine.flags.is_set(RFlags::Carry) && !machine.flags.is_set(RFlags::Overflow));
+    }
+
+    #[test]
+    fn byte_swaps() {
+        let machine = run("mov eax, 0x12345678\nbswap eax\nmov rbx, 0x0102030405060708\nbswap rbx\nmov r8, rbx\nbswap r8d");
+        assert_eq!(machine.register(Register::RAX), 0x78563412);
+        assert_eq!(machine.register(Register::RBX), 0x0807060504030201);
+        // the 32-bit form clears the upper half as any other 32-bit write
+        assert_eq!(machine.register(Register::R8), 0x01020304);
+    }
+}
+
+#[cfg(test)]
+mod atomic {
+    use lib_x86::prelude::*;
+    use crate::fixture::{halt, load, load_with, run, CODE};
+    use std::thread;
+
+    /// Enough for the loops below to go round a few thousand times on each machine
+    const BUDGET: u64 = 1_000_000;
+
+    /// Runs `source` on `cpus` machines sharing memory, each on a thread of its own, and gives
+    /// back one of them once they have all halted
+    fn run_on_cpus(source: &str, cpus: usize) -> X86Machine {
+        let first = load_with(source, BUDGET, |_| {});
+        let mut machines: Vec<X86Machine> = (1..cpus)
+            .map(|_| {
+                let mut machine = X86Machine::builder()
+                    .shared_memory(&first.memory)
+                    .instruction_budget(BUDGET)
+                    .build_with_defaults();
+                machine.set_instruction_counter(CODE);
+                machine
+            })
+            .collect();
+        machines.push(first);
+        thread::scope(|scope| {
+            for machine in &mut machines {
+                scope.spawn(|| halt(machine));
+            }
+        });
+        machines.pop().unwrap()
+    }
+
+    fn dword(machine: &X86Machine, address: usize) -> u32 {
+        u32::from_le_bytes(machine.memory.read(address, 4).unwrap().try_into().unwrap())
+    }
+
+    fn invalid_opcode_at(address: u64) -> StopReason {
+        StopReason::Fault(Fault::Exception { exception: Exception::InvalidOpcode, address })
+    }
+
+    #[test]
+    fn xchg_and_xadd() {
+        let machine = run("mov dword [0x2000], 5\nmov eax, 7\nxchg [0x2000], eax\nmov ecx, 3\nlock xadd [0x2000], ecx");
+        assert_eq!(machine.register(Register::RAX), 5);
+        assert_eq!(machine.register(Register::RCX), 7);
+        assert_eq!(machine.memory.read(0x2000, 4).unwrap(), 10u32.to_le_bytes());
+
+        // the same register twice ends up with the sum
+        let machine = run("mov eax, 0x40\nxadd eax, eax\nmov cl, 0xFF\nmov dl, 1\nxadd cl, dl");
+        assert_eq!(machine.register(Register::RAX), 0x80);
+        assert_eq!(machine.register(Register::CL), 0);
+        assert_eq!(machine.register(Register::DL), 0xFF);
+        assert!(machine.flags.is_set(RFlags::Carry) && machine.flags.is_set(RFlags::Zero));
+    }
+
+    #[test]
+    fn cmpxchg() {
+        let machine = run("mov qword [0x2000], 1\nmov eax, 1\nmov ecx, 9\nlock cmpxchg [0x2000], rcx\nsetz bl");
+        assert_eq!(machine.memory.read(0x2000, 8).unwrap(), 9u64.to_le_bytes());
+        assert_eq!(machine.register(Register::BL), 1);
+
+        // a mismatch loads the accumulator instead
+        let machine = run("mov qword [0x2000], 4\nmov eax, 1\nmov ecx, 9\nlock cmpxchg [0x2000], rcx\nsetz bl");
+        assert_eq!(machine.memory.read(0x2000, 8).unwrap(), 4u64.to_le_bytes());
+        assert_eq!(machine.register(Register::RAX), 4);
+        assert_eq!(machine.register(Register::BL), 0);
+        // flags as for cmp rax, [mem]
+        assert!(machine.flags.is_set(RFlags::Carry));
+    }
+
+    #[test]
+    fn cmpxchg8b_and_16b() {
+        let mut machine = load(
+            "mov rsi, 0x2000\nmov eax, 1\nmov edx, 2\nmov ebx, 3\nmov ecx, 4\nlock cmpxchg8b [rsi]\nsetz r8b\n\
+             mov rax, 5\nxor edx, edx\nlock cmpxchg16b [rsi + 0x10]\nsetz r9b",
+        );
+        machine.memory.write(0x2000, &[1, 0, 0, 0, 2, 0, 0, 0]).unwrap();
+        machine.memory.write(0x2010, &[7; 16]).unwrap();
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.memory.read(0x2000, 8).unwrap(), [3, 0, 0, 0, 4, 0, 0, 0]);
+        assert_eq!(machine.register(Register::Gpr8(8)), 1);
+        // the 16 byte compare failed, RDX:RAX now holds memory
+        assert_eq!(machine.register(Register::Gpr8(9)), 0);
+        assert_eq!(machine.register(Register::RAX), 0x0707070707070707);
+        assert_eq!(machine.register(Register::RDX), 0x0707070707070707);
+        assert_eq!(machine.memory.read(0x2010, 16).unwrap(), [7; 16]);
+
+        // CMPXCHG16B needs a 16 byte aligned operand
+        let mut machine = load("mov rsi, 0x2008\ncmpxchg16b [rsi]");
+        let fault = Fault::Exception { exception: Exception::GeneralProtection, address: CODE + 7 };
+        assert_eq!(machine.run(), StopReason::Fault(fault));
+    }
+
+    #[test]
+    fn lock_needs_a_memory_destination() {
+        // lock add eax, ecx
+        let mut machine = load("db 0xF0, 0x01, 0xC8");
+        assert_eq!(machine.run(), invalid_opcode_at(CODE));
+        // lock bt [rax], ecx, which does not write
+        let mut machine = load("mov eax, 0x2000\ndb 0xF0, 0x0F, 0xA3, 0x08");
+        assert_eq!(machine.run(), invalid_opcode_at(CODE + 5));
+        // lock mov [rax], ecx
+        let mut machine = load("mov eax, 0x2000\ndb 0xF0, 0x89, 0x08");
+        assert_eq!(machine.run(), invalid_opcode_at(CODE + 5));
+
+        let machine = run("mov edi, 0x2000\nlock inc dword [rdi]\nlock add qword [rdi], 2\nlock not byte [rdi]\nlock bts dword [rdi], 4");
+        assert_eq!(machine.memory.read(0x2000, 4).unwrap(), [0xFC, 0, 0, 0]);
+    }
+
+    #[test]
+    fn spin_lock() {
+        // take a free lock, bump a counter under it a few times, then release it
+        let machine = run(
+            "mov edi, 0x2000\nmov esi, 1\nmov ebx, 5\n\
+             acquire: xor eax, eax\nlock cmpxchg [rdi], esi\njnz acquire\n\
+             lock inc dword [rdi + 4]\ndec ebx\njnz acquire_again\njmp done\n\
+             acquire_again: mov dword [rdi], 0\njmp acquire\n\
+             done: mov eax, 0\nxchg [rdi], eax",
+        );
+        assert_eq!(machine.memory.read(0x2000, 8).unwrap(), [0, 0, 0, 0, 5, 0, 0, 0]);
+        assert_eq!(machine.register(Register::RAX), 1);
+    }
+
+    #[test]
+    fn locked_instructions_are_atomic_across_cpus() {
+        let machine = run_on_cpus(
+            "mov ecx, 5000
+             mov ebx, 2
+             again: lock inc dword [0x2000]
+             mov eax, ebx
+             lock xadd [0x2004], eax
+             lock sub word [0x2008], 1
+             dec ecx
+             jnz again",
+            4,
+        );
+        assert_eq!(dword(&machine, 0x2000), 20_000);
+        assert_eq!(dword(&machine, 0x2004), 40_000);
+        // the words either side of the one counting down are left alone
+        assert_eq!(dword(&machine, 0x2008), (-20_000i16 as u16) as u32);
+    }
+
+    #[test]
+    fn spin_lock_across_cpus() {
+        // a plain read-modify-write under the lock, which a plain store releases
+        let machine = run_on_cpus(
+            "mov edi, 0x2000
+             mov esi, 1
+             mov ecx, 2000
+             acquire: xor eax, eax
+             lock cmpxchg [rdi], esi
+             jnz acquire
+             mov eax, [rdi + 4]
+             inc eax
+             mov [rdi + 4], eax
+             mov dword [rdi], 0
+             dec ecx
+             jnz acquire",
+            4,
+        );
+        assert_eq!(dword(&machine, 0x2004), 8000);
+        assert_eq!(dword(&machine, 0x2000), 0);
+
+        // CMPXCHG16B retried until it lands, bumping both halves
+        let machine = run_on_cpus(
+            "mov ecx, 2000
+             again: mov rax, [0x2010]
+             mov rdx, [0x2018]
+             lea rbx, [rax + 1]
+             lea r8, [rdx + 3]
+             xchg rcx, r8
+             lock cmpxchg16b [0x2010]
+             mov rcx, r8
+             jnz again
+             dec ecx
+             jnz again",
+            4,
+        );
+        assert_eq!(machine.memory.read(0x2010, 16).unwrap(), [8000u64.to_le_bytes(), 24_000u64.to_le_bytes()].concat());
+    }
+
+    #[test]
+    fn byte_strings_share_quadwords_across_cpus() {
+        // each machine takes a lane, then fills and copies its half of the same two quadwords
+        let machine = run_on_cpus(
+            "mov rax, 0x0807060504030201
+             mov [0x3000], rax
+             mov edx, 1
+             lock xadd [0x2800], edx
+             lea eax, [rdx + 1]
+             mov r8d, 2000
+             again: lea rdi, [rdx * 4 + 0x2000]
+             mov ecx, 4
+             rep stosb
+             lea rsi, [rdx * 4 + 0x3000]
+             lea rdi, [rdx * 4 + 0x2008]
+             mov ecx, 4
+             rep movsb
+             dec r8d
+             jnz again",
+            2,
+        );
+        assert_eq!(machine.memory.read(0x2000, 16).unwrap(), [1, 1, 1, 1, 2, 2, 2, 2, 1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+             }
+
+             #[cfg(test)]
+mod cpuid {
+    use lib_opcode::prelude::assemble;
+    use lib_x86::functions::{Intrinsic, SystemFunction};
+    use lib_x86::prelude::*;
+    use crate::fixture::load;
+
+    const CODE: u64 = 0x1000;
+    const DATA: u64 = 0x2000;
+
+    fn cpuid(profile: Option<CpuProfile>, leaf: u32, subleaf: u32) -> [u64; 4] {
+        let source = format!("org {CODE}\nmov rax, -1\nmov rbx, -1\nmov rdx, -1\nmov eax, {leaf}\nmov rcx, {subleaf}\ncpuid\nhlt");
+        let code = assemble(&source).unwrap();
+        let mut builder = X86Machine::builder().memory(ByteUnits::KibiBytes(64)).instruction_budget(1000);
+        if let Some(profile) = profile {
+            builder = builder.cpu_profile(profile);
+        }
+        let mut machine = builder.build_with_defaults();
+        machine.memory.write(CODE as usize, &code).unwrap();
+        machine.set_instruction_counter(CODE);
+        assert_eq!(machine.run(), StopReason::Halted);
+        [Register::RAX, Register::RBX, Register::RCX, Register::RDX].map(|register| machine.register(register))
+    }
+
+    fn bytes(registers: &[u64]) -> Vec<u8> {
+        registers.iter().flat_map(|&register| (register as u32).to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn vendor_and_signature() {
+        let [max, ebx, ecx, edx] = cpuid(None, 0, 0);
+        assert_eq!(max, 0xD);
+        assert_eq!(bytes(&[ebx, edx, ecx]), b"GenuineIntel");
+
+        // family 6, model 0x55 is split across the model and extended model fields
+        let [eax, ebx, ..] = cpuid(Some(CpuProfile::skylake_server()), 1, 0);
+        assert_eq!(eax, 0x50654);
+        assert_eq!(ebx >> 16 & 0xFF, 40);
+        let mut profile = CpuProfile::emulator();
+        profile.family = 0x17;
+        profile.model = 0x31;
+        assert_eq!(cpuid(Some(profile), 1, 0)[0], 0x830F13);
+    }
+
+    #[test]
+    fn feature_bits_follow_the_profile() {
+        let [_, _, ecx, edx] = cpuid(None, 1, 0);
+        assert_ne!(ecx & 1 << 23, 0, "popcnt");
+        assert_ne!(ecx & 1 << 20, 0, "sse4.2");
+        assert_ne!(edx & 1, 0, "x87");
+        assert_ne!(edx & 1 << 23, 0, "mmx");
+        assert_eq!(ecx & 1 << 30, 0, "rdrand is not implemented");
+        let [_, ebx, ..] = cpuid(None, 7, 0);
+        assert_ne!(ebx & 1 << 8, 0, "bmi2");
+        // leaf 7 only has subleaf 0
+        assert_eq!(cpuid(None, 7, 1), [0; 4]);
+
+        let profile = CpuProfile::emulator().without_feature(Feature::Bmi2).with_feature(Feature::Rdrand);
+        assert_eq!(cpuid(Some(profile.clone()), 7, 0)[1] & 1 << 8, 0);
+        // leaf 1 ignores ECX
+        assert_ne!(cpuid(Some(profile), 1, 5)[2] & 1 << 30, 0);
+
+        let [_, _, ecx, edx] = cpuid(None, 0x8000_0001, 0);
+        assert_ne!(edx & 1 << 29, 0, "long mode");
+        assert_ne!(ecx & 1 << 5, 0, "lzcnt");
+        assert_ne!(ecx & 1, 0, "lahf / sahf");
+    }
+
+    #[test]
+    fn brand_string() {
+        let brand: Vec<u8> = (0x8000_0002..=0x8000_0004).flat_map(|leaf| bytes(&cpuid(Some(CpuProfile::skylake()), leaf, 0))).collect();
+        let expected = b"Intel(R) Core(TM) i7-6700K CPU @ 4.00GHz";
+        assert_eq!(&brand[..expected.len()], expected);
+        assert!(brand[expected.len()..].iter().all(|&byte| byte == 0));
+        assert_eq!(cpuid(None, 0x8000_0000, 0)[0], 0x8000_0008);
+    }
+
+    #[test]
+    fn cache_leaves() {
+        // L1 data: 8 ways of 64 sets of 64 bytes
+        let [eax, ebx, ecx, _] = cpuid(None, 4, 0);
+        assert_eq!(eax & 0x1F, 1);
+        assert_eq!(eax >> 5 & 7, 1);
+        assert_eq!((ebx >> 22) + 1, 8);
+        assert_eq!((ebx & 0xFFF) + 1, 64);
+        assert_eq!(ecx + 1, 64);
+
+        let [eax, ..] = cpuid(None, 4, 3);
+        assert_eq!(eax & 0x1F, 3);
+        assert_eq!(eax >> 5 & 7, 3);
+        // no more caches
+        assert_eq!(cpuid(None, 4, 4), [0; 4]);
+
+        // 256 KiB, 4 ways, 64 byte lines
+        assert_eq!(cpuid(None, 0x8000_0006, 0)[2], 256 << 16 | 4 << 12 | 64);
+    }
+
+    #[test]
+    fn leaves_past_the_highest_repeat_it() {
+        assert_eq!(cpuid(None, 0x20, 0), cpuid(None, 0xD, 0));
+        assert_eq!(cpuid(None, 0x8000_0100, 0), cpuid(None, 0xD, 0));
+        // all four registers are written as 32-bit registers
+        assert!(cpuid(None, 0, 0).iter().all(|&register| register >> 32 == 0));
+    }
+
+    /// An instruction or two from each extension, which has to run in full for the extension
+    /// to be advertised
+    fn exercise(feature: Feature) -> &'static str {
+        match feature {
+            Feature::Fpu => "fld1\nfld1\nfaddp st1, st0\nfstp qword [rsi]",
+            Feature::Cx8 => "cmpxchg8b [rsi]",
+            Feature::Cmov => "cmovz eax, ebx",
+            Feature::Mmx => "paddb mm0, mm1\nemms",
+            Feature::Fxsr => "fxsave64 [rsi]\nfxrstor64 [rsi]",
+            Feature::Sse => "movlps xmm0, [rsi]\nmovlhps xmm0, xmm0\nrcpps xmm1, xmm0\nrsqrtss xmm1, xmm0",
+            Feature::Sse2 => "movhpd xmm0, [rsi]\naddpd xmm0, xmm0\npaddq xmm0, xmm0",
+            Feature::Sse3 => "haddps xmm0, xmm1\nmovddup xmm0, xmm1\nlddqu xmm0, [rsi]\nfisttp dword [rsi]",
+            Feature::Pclmulqdq => "pclmulqdq xmm0, xmm1, 0",
+            Feature::Ssse3 => "pshufb xmm0, xmm1\npalignr xmm0, xmm1, 3",
+            Feature::Cx16 => "cmpxchg16b [rsi]",
+            Feature::Sse41 => "ptest xmm0, xmm1\nroundps xmm0, xmm1, 0\npmulld xmm0, xmm1\npextrb eax, xmm0, 1",
+            Feature::Sse42 => "pcmpgtq xmm0, xmm1\npcmpistri xmm0, xmm1, 0\ncrc32 eax, bl",
+            Feature::Popcnt => "popcnt rax, rbx",
+            Feature::Aes => "aesenc xmm0, xmm1\naeskeygenassist xmm0, xmm1, 1",
+            Feature::Fma => "vfmadd231ps ymm0, ymm1, ymm2",
+            Feature::Xsave => "mov eax, 7\nxor edx, edx\nxsave64 [rsi]\nxrstor64 [rsi]",
+            Feature::Osxsave => "xor ecx, ecx\nxgetbv",
+            Feature::Xsaveopt => "mov eax, 7\nxor edx, edx\nxsaveopt64 [rsi]",
+            Feature::Xsavec => "mov eax, 7\nxor edx, edx\nxsavec64 [rsi]",
+            Feature::Avx => "vaddps ymm0, ymm1, ymm2\nvperm2f128 ymm0, ymm1, ymm2, 1\nvzeroupper",
+            Feature::F16c => "vcvtph2ps ymm0, xmm1",
+            Feature::Avx2 => "vpaddd ymm0, ymm1, ymm2\nvpermq ymm0, ymm1, 0x1B",
+            Feature::Avx512f => "vaddps zmm0, zmm1, zmm2\nkmovw k1, eax\nvpternlogd zmm0 {k1}, zmm1, zmm2, 0x96",
+            Feature::Avx512dq => "vpmullq zmm0, zmm1, zmm2\nvrangeps zmm0, zmm1, zmm2, 0",
+            Feature::Avx512cd => "vplzcntd zmm0, zmm1\nvpconflictq zmm0, zmm1",
+            Feature::Avx512bw => "vpaddb zmm0, zmm1, zmm2\nkmovq k1, rax",
+            Feature::Avx512vl => "vpabsq ymm0, ymm1\nvaddps xmm16, xmm17, xmm18",
+            Feature::Avx512vbmi => "vpermb zmm0, zmm1, zmm2\nvpmultishiftqb zmm0, zmm1, zmm2",
+            Feature::Vaes => "vaesenc ymm0, ymm1, ymm2",
+            Feature::Vpclmulqdq => "vpclmulqdq ymm0, ymm1, ymm2, 0",
+            Feature::Bmi1 => "andn rax, rbx, rcx\ntzcnt rax, rbx",
+            Feature::Bmi2 => "pdep rax, rbx, rcx\nshlx rax, rbx, rcx",
+            Feature::Erms => "mov rdi, rsi\nmov ecx, 64\nrep stosb",
+            Feature::Mpx => "bndmk bnd0, [rsi + 0xFF]\nbndcl bnd0, rsi",
+            Feature::Adx => "adcx rax, rbx\nadox rax, rbx",
+            Feature::Sha => "sha1msg1 xmm0, xmm1\nsha256msg2 xmm0, xmm1",
+            Feature::LahfLm => "lahf\nsahf",
+            Feature::Abm => "lzcnt rax, rbx",
+            Feature::Syscall => "xor eax, eax\nsyscall",
+            Feature::LongMode => "mov r8, rsi\nadd r8, 8",
+            _ => panic!("{feature:?} is advertised without anything to run"),
+        }
+    }
+
+    #[test]
+    fn every_advertised_feature_executes() {
+        fn return_from_syscall(_: &mut X86Machine) {}
+        for &feature in IMPLEMENTED_FEATURES {
+            let mut machine = load(&format!("mov rsi, {DATA}\n{}", exercise(feature)));
+            machine
+                .syscalls
+                .set(0, SystemFunction::IntrinsicFunction(Intrinsic::from_ptr(return_from_syscall)));
+            assert_eq!(machine.run(), StopReason::Halted, "{feature:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod sse {
+    use lib_x86::prelude::*;
+    use crate::fixture::{halt, load, CODE};
+
+    const DATA: u64 = 0x2000;
+
+    fn singles(lanes: [f32; 4]) -> u128 {
+        lanes.iter().rev().fold(0, |value, lane| value << 32 | lane.to_bits() as u128)
+    }
+
+    fn doubles(lanes: [f64; 2]) -> u128 {
+        (lanes[1].to_bits() as u128) << 64 | lanes[0].to_bits() as u128
+    }
+
+    fn fault(exception: Exception, address: u64) -> StopReason {
+        StopReason::Fault(Fault::Exception { exception, address })
+    }
+
+    #[test]
+    fn moves() {
+        let mut machine = load(&format!(
+            "mov eax, {DATA}\nmovaps xmm1, [rax]\nmovups [rax + 0x13], xmm1\nmovss xmm2, [rax + 4]\nmovss xmm3, xmm1\nmovsd [rax + 0x40], xmm1"
+        ));
+        machine.memory.write(DATA as usize, &(1..=16).collect::<Vec<u8>>()).unwrap();
+        machine.set_xmm(2, u128::MAX);
+        machine.set_xmm(3, u128::MAX);
+        halt(&mut machine);
+        let value = u128::from_le_bytes(core::array::from_fn(|i| i as u8 + 1));
+        assert_eq!(machine.xmm(1), value);
+        assert_eq!(machine.memory.read(DATA as usize + 0x13, 16).unwrap(), (1..=16).collect::<Vec<u8>>());
+        // a load clears the rest of the register, a register to register move keeps it
+        assert_eq!(machine.xmm(2), 0x08070605);
+        assert_eq!(machine.xmm(3), u128::MAX << 32 | 0x04030201);
+        assert_eq!(machine.memory.read(DATA as usize + 0x40, 8).unwrap(), [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        // MOVAPS needs a 16 byte aligned operand, MOVUPS does not
+        let mut machine = load(&format!("mov eax, {}\nmovups xmm0, [rax]\nmovaps xmm0, [rax]", DATA + 8));
+        assert_eq!(machine.run(), fault(Exception::GeneralProtection, CODE + 8));
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut machine = load("addps xmm0, xmm1\naddss xmm2, xmm1\ndivpd xmm3, xmm4\nsqrtsd xmm5, xmm4\nminps xmm6, xmm7\nmaxps xmm7, xmm6");
+        machine.set_xmm(0, singles([1.0, 2.0, 3.0, 4.0]));
+        machine.set_xmm(1, singles([0.5, 0.25, -3.0, 1e30]));
+        machine.set_xmm(2, singles([1.0, 2.0, 3.0, 4.0]));
+        machine.set_xmm(3, doubles([1.0, -9.0]));
+        machine.set_xmm(4, doubles([4.0, 3.0]));
+        machine.set_xmm(5, doubles([7.0, 7.0]));
+        machine.set_xmm(6, singles([-0.0, 1.0, f32::NAN, 5.0]));
+        machine.set_xmm(7, singles([0.0, 2.0, 3.0, f32::NAN]));
+        halt(&mut machine);
+        assert_eq!(machine.xmm(0), singles([1.5, 2.25, 0.0, 1e30]));
+        // scalar forms leave the upper lanes of the destination alone
+        assert_eq!(machine.xmm(2), singles([1.5, 2.0, 3.0, 4.0]));
+        assert_eq!(machine.xmm(3), doubles([0.25, -3.0]));
+        assert_eq!(machine.xmm(5), doubles([2.0, 7.0]));
+        // the second operand when they are equal or either is a NaN
+        assert_eq!(machine.xmm(6), singles([0.0, 1.0, 3.0, f32::NAN]));
+        assert_eq!(machine.xmm(7), singles([0.0, 2.0, 3.0, f32::NAN]));
+        // 1e30 + 4 and the NaNs are inexact and invalid
+        assert_eq!(machine.mxcsr(), 0x1F80 | 0x21);
+    }
+
+    #[test]
+    fn rounding_control() {
+        let mut machine = load(&format!(
+            "mov edi, {DATA}\nmovaps xmm2, xmm0\ndivss xmm2, xmm1\n\
+             mov dword [rdi], 0x3F80\nldmxcsr [rdi]\nmovaps xmm3, xmm0\ndivss xmm3, xmm1\ncvtsd2si ecx, xmm4\ncvttsd2si edx, xmm4\n\
+             mov dword [rdi], 0x5F80\nldmxcsr [rdi]\nmovaps xmm5, xmm0\ndivss xmm5, xmm1\ncvtsd2si esi, xmm4\nstmxcsr [rdi + 4]"
+        ));
+        machine.set_xmm(0, singles([1.0; 4]));
+        machine.set_xmm(1, singles([3.0; 4]));
+        machine.set_xmm(4, doubles([-2.5, 0.0]));
+        halt(&mut machine);
+        let third = 1.0f32 / 3.0;
+        // to nearest rounds 1/3 up, so rounding down gives the float before
+        assert_eq!(machine.xmm(2) as u32, third.to_bits());
+        assert_eq!(machine.xmm(3) as u32, third.to_bits() - 1);
+        assert_eq!(machine.xmm(5) as u32, third.to_bits());
+        // -2.5 rounded down, truncated and rounded up
+        assert_eq!(machine.register(Register::ECX), -3i32 as u32 as u64);
+        assert_eq!(machine.register(Register::EDX), -2i32 as u32 as u64);
+        assert_eq!(machine.register(Register::ESI), -2i32 as u32 as u64);
+        assert_eq!(machine.memory.read(DATA as usize + 4, 4).unwrap(), (0x5F80u32 | 0x20).to_le_bytes());
+    }
+
+    #[test]
+    fn exceptions() {
+        let mut machine = load("divsd xmm0, xmm1\ncvttsd2si eax, xmm1\ncvtsd2si rcx, xmm2\ncmpps xmm3, xmm3, 0");
+        machine.set_xmm(0, doubles([1.0, 1.0]));
+        machine.set_xmm(2, doubles([1e19, 0.0]));
+        machine.set_xmm(3, singles([f32::NAN; 4]));
+        halt(&mut machine);
+        assert_eq!(machine.xmm(0), doubles([f64::INFINITY, 1.0]));
+        // out of range is the integer indefinite
+        assert_eq!(machine.register(Register::RAX), 0);
+        assert_eq!(machine.register(Register::RCX), 1 << 63);
+        // a quiet NaN does not make CMPEQ invalid
+        assert_eq!(machine.xmm(3), 0);
+        assert_eq!(machine.mxcsr(), 0x1F80 | 0x05);
+
+        // unmasked, the exception is #XM and the destination is not written
+        let mut machine = load("sqrtps xmm0, xmm1");
+        machine.set_mxcsr(0x1F80 & !0x80);
+        machine.set_xmm(1, singles([-1.0, 4.0, 9.0, 16.0]));
+        assert_eq!(machine.run(), fault(Exception::SimdFloatingPoint, CODE));
+        assert_eq!(machine.xmm(0), 0);
+        assert_eq!(machine.mxcsr(), 0x1F00 | 0x01);
+
+        // LDMXCSR with a reserved bit set
+        let mut machine = load(&format!("mov dword [{DATA}], 0x11F80\nldmxcsr [{DATA}]"));
+        assert_eq!(machine.run(), fault(Exception::GeneralProtection, CODE + 11));
+    }
+
+    #[test]
+    fn denormals() {
+        let smallest = f32::from_bits(1);
+        let mut machine = load("movaps xmm2, xmm0\nmulss xmm2, xmm1\nmov dword [0x2000], 0x9FC0\nldmxcsr [0x2000]\nmovaps xmm3, xmm0\nmulss xmm3, xmm1\nmulss xmm4, xmm1");
+        machine.set_xmm(0, singles([f32::MIN_POSITIVE; 4]));
+        machine.set_xmm(1, singles([0.5; 4]));
+        machine.set_xmm(4, singles([smallest; 4]));
+        halt(&mut machine);
+        // a tiny but exact result is not an underflow when it is masked
+        assert_eq!(machine.xmm(2) as u32, (f32::MIN_POSITIVE / 2.0).to_bits());
+        // FTZ flushes it, raising underflow and precision, and DAZ reads the denormal as zero
+        assert_eq!(machine.xmm(3) as u32, 0);
+        assert_eq!(machine.xmm(4) as u32, 0);
+        assert_eq!(machine.mxcsr(), 0x9FC0 | 0x30);
+
+        // without DAZ a denormal operand is flagged
+        let mut machine = load("addss xmm0, xmm1");
+        machine.set_xmm(0, singles([smallest; 4]));
+        halt(&mut machine);
+        assert_eq!(machine.xmm(0) as u32, 1);
+        assert_eq!(machine.mxcsr(), 0x1F80 | 0x02);
+    }
+
+    #[test]
+    fn comparisons() {
+        let mut machine = load(
+            "cmpps xmm0, xmm1, 1\ncmppd xmm2, xmm3, 3\n\
+             comiss xmm4, xmm5\nsetb al\nsetz bl\nsetp cl\nucomisd xmm6, xmm7\nsetp dl\nstmxcsr [0x2000]",
+        );
+        machine.set_xmm(0, singles([1.0, 2.0, 3.0, f32::NAN]));
+        machine.set_xmm(1, singles([2.0, 2.0, 1.0, 0.0]));
+        machine.set_xmm(2, doubles([1.0, f64::NAN]));
+        machine.set_xmm(3, doubles([1.0, 1.0]));
+        machine.set_xmm(4, singles([-1.0, 0.0, 0.0, 0.0]));
+        machine.set_xmm(5, singles([1.0, 0.0, 0.0, 0.0]));
+        machine.set_xmm(6, doubles([f64::NAN, 0.0]));
+        halt(&mut machine);
+        assert_eq!(machine.xmm(0), 0xFFFF_FFFF);
+        assert_eq!(machine.xmm(2), u128::MAX << 64);
+        assert_eq!(machine.register(Register::AL), 1);
+        assert_eq!(machine.register(Register::BL), 0);
+        assert_eq!(machine.register(Register::CL), 0);
+        // unordered sets ZF, PF and CF
+        assert_eq!(machine.register(Register::DL), 1);
+        assert!(machine.flags.is_set(RFlags::Zero) && machine.flags.is_set(RFlags::Carry));
+        // CMPLT and its NaN is invalid, UCOMISD of a quiet NaN is not
+        assert_eq!(machine.memory.read(0x2000, 4).unwrap(), (0x1F80u32 | 0x01).to_le_bytes());
+    }
+
+    #[test]
+    fn conversions() {
+        let mut machine = load(
+            "mov rax, -3\ncvtsi2sd xmm0, rax\ncvtsd2ss xmm1, xmm0\ncvtps2pd xmm2, xmm3\n\
+             cvtdq2ps xmm4, xmm5\ncvttpd2dq xmm6, xmm7\ncvtss2sd xmm8, xmm3",
+        );
+        machine.set_xmm(1, u128::MAX);
+        machine.set_xmm(3, singles([1.5, -0.25, 9.0, 9.0]));
+        machine.set_xmm(5, 7 << 96 | (-1i32 as u32 as u128) << 64 | 16_777_217 << 32 | 2);
+        machine.set_xmm(6, u128::MAX);
+        machine.set_xmm(7, doubles([-7.9, 1e10]));
+        halt(&mut machine);
+        assert_eq!(machine.xmm(0) as u64, (-3.0f64).to_bits());
+        assert_eq!(machine.xmm(1), u128::MAX << 32 | (-3.0f32).to_bits() as u128);
+        assert_eq!(machine.xmm(2), doubles([1.5, -0.25]));
+        assert_eq!(machine.xmm(4), singles([2.0, 16_777_216.0, -1.0, 7.0]));
+        // the upper half is cleared, and 1e10 does not fit
+        assert_eq!(machine.xmm(6), 0x8000_0000 << 32 | -7i32 as u32 as u128);
+        assert_eq!(machine.xmm(8) as u64, 1.5f64.to_bits());
+        assert_eq!(machine.mxcsr(), 0x1F80 | 0x21);
+    }
+
+    #[test]
+    fn horizontal_and_duplicating_moves() {
+        let mut machine = load(
+            "haddps xmm0, xmm1\nhsubpd xmm2, xmm3\naddsubps xmm4, xmm1\n\
+             movshdup xmm5, xmm1\nmovsldup xmm6, xmm1\nmovddup xmm7, xmm3",
+        );
+        machine.set_xmm(0, singles([1.0, 2.0, 3.0, 4.0]));
+        machine.set_xmm(1, singles([10.0, 20.0, 30.0, 40.0]));
+        machine.set_xmm(2, doubles([5.0, 1.5]));
+        machine.set_xmm(3, doubles([-1.0, 8.0]));
+        machine.set_xmm(4, singles([1.0, 2.0, 3.0, 4.0]));
+        halt(&mut machine);
+        // pairs of the destination in the low half, of the source in the high half
+        assert_eq!(machine.xmm(0), singles([3.0, 7.0, 30.0, 70.0]));
+        assert_eq!(machine.xmm(2), doubles([3.5, -9.0]));
+        // the even lanes subtract and the odd ones add
+        assert_eq!(machine.xmm(4), singles([-9.0, 22.0, -27.0, 44.0]));
+        assert_eq!(machine.xmm(5), singles([20.0, 20.0, 40.0, 40.0]));
+        assert_eq!(machine.xmm(6), singles([10.0, 10.0, 30.0, 30.0]));
+        assert_eq!(machine.xmm(7), doubles([-1.0, -1.0]));
+    }
+
+    #[test]
+    fn half_moves_and_approximate_reciprocals() {
+        let mut machine = load(&format!(
+            "mov eax, {DATA}\nmovlps xmm0, [rax]\nmovhps xmm1, [rax + 8]\nmovhlps xmm2, xmm3\nmovlhps xmm4, xmm3\n\
+             movhpd [rax + 0x10], xmm3\nvmovlps xmm5, xmm3, [rax]\nrcpps xmm6, xmm7\nrsqrtss xmm8, xmm7"
+        ));
+        machine.memory.write(DATA as usize, &(1..=16).collect::<Vec<u8>>()).unwrap();
+        let (low, high) = (0x0807060504030201u128, 0x100F0E0D0C0B0A09u128);
+        let halves = 0x3333333333333333u128 << 64 | 0x4444444444444444;
+        for n in [0, 1, 2, 4] {
+            machine.set_xmm(n, u128::MAX);
+        }
+        machine.set_xmm(3, halves);
+        machine.set_ymm(5, [u128::MAX; 2]);
+        // a denormal, and a value with a denormal reciprocal
+        machine.set_xmm(7, singles([4.0, 0.5, 1e-40, 1e38]));
+        machine.set_xmm(8, singles([0.0, 5.0, 6.0, 7.0]));
+        halt(&mut machine);
+        // the other half of the destination is kept
+        assert_eq!(machine.xmm(0), u128::MAX << 64 | low);
+        assert_eq!(machine.xmm(1), high << 64 | u64::MAX as u128);
+        assert_eq!(machine.xmm(2), u128::MAX << 64 | halves >> 64);
+        assert_eq!(machine.xmm(4), halves << 64 | u64::MAX as u128);
+        assert_eq!(machine.memory.read(DATA as usize + 0x10, 8).unwrap(), [0x33; 8]);
+        assert_eq!(machine.ymm(5), [halves >> 64 << 64 | low, 0]);
+        // denormals are taken as zero and flushed to zero whatever MXCSR says, without flags
+        assert_eq!(machine.xmm(6), singles([0.25, 2.0, f32::INFINITY, 0.0]));
+        assert_eq!(machine.xmm(8), singles([0.5, 5.0, 6.0, 7.0]));
+        assert_eq!(machine.mxcsr(), 0x1F80);
+    }
+
+    #[test]
+    fn rounding_and_dot_products() {
+        let mut machine = load(
+            "roundsd xmm3, xmm4, 0xB\nstmxcsr [0x2000]\nroundps xmm2, xmm1, 9\nroundps xmm0, xmm1, 0\n\
+             dpps xmm5, xmm1, 0x71\ndppd xmm6, xmm4, 0x32",
+        );
+        machine.set_xmm(1, singles([2.5, -1.5, 0.75, 3.0]));
+        machine.set_xmm(3, doubles([0.0, 42.0]));
+        machine.set_xmm(4, doubles([-2.7, 3.0]));
+        machine.set_xmm(5, singles([1.0, 2.0, 3.0, 4.0]));
+        machine.set_xmm(6, doubles([2.0, 0.5]));
+        halt(&mut machine);
+        // toward zero with the upper lane kept, down, and to nearest even
+        assert_eq!(machine.xmm(3), doubles([-2.0, 42.0]));
+        assert_eq!(machine.xmm(2), singles([2.0, -2.0, 0.0, 3.0]));
+        assert_eq!(machine.xmm(0), singles([2.0, -2.0, 1.0, 3.0]));
+        // the products of lanes 0 to 2 into lane 0, and of both lanes into lane 1
+        assert_eq!(machine.xmm(5), singles([1.75, 0.0, 0.0, 0.0]));
+        assert_eq!(machine.xmm(6), doubles([0.0, 2.0 * -2.7 + 0.5 * 3.0]));
+        // bit 3 of the immediate keeps the inexact rounds from raising precision
+        assert_eq!(machine.memory.read(0x2000, 4).unwrap(), 0x1F80u32.to_le_bytes());
+        assert_eq!(machine.mxcsr(), 0x1F80 | 0x20);
+    }
+
+    #[test]
+    fn single_inserts_and_extracts() {
+        let mut machine = load(&format!(
+            "insertps xmm0, xmm1, 0x94\ninsertps xmm2, dword [{DATA}], 0x30\nextractps eax, xmm1, 3"
+        ));
+        machine.set_xmm(0, singles([1.0, 2.0, 3.0, 4.0]));
+        machine.set_xmm(1, singles([10.0, 20.0, 30.0, 40.0]));
+        machine.set_xmm(2, singles([1.0, 2.0, 3.0, 4.0]));
+        machine.set_register(Register::RAX, u64::MAX);
+        machine.memory.write(DATA as usize, &5.0f32.to_le_bytes()).unwrap();
+        halt(&mut machine);
+        // lane 2 of the source into lane 1, then lane 2 cleared
+        assert_eq!(machine.xmm(0), singles([1.0, 30.0, 0.0, 4.0]));
+        assert_eq!(machine.xmm(2), singles([1.0, 2.0, 3.0, 5.0]));
+        assert_eq!(machine.register(Register::RAX), 40.0f32.to_bits() as u64);
+    }
+}
+
+#[cfg(test)]
+mod packed {
+    use lib_x86::prelude::*;
+    use crate::fixture::{halt, load};
+
+    const DATA: u64 = 0x2000;
+
+    fn bytes(lanes: [u8; 16]) -> u128 {
+        u128::from_le_bytes(lanes)
+    }
+
+    fn words(lanes: [u16; 8]) -> u128 {
+        lanes.iter().rev().fold(0, |value, &lane| value << 16 | lane as u128)
+    }
+
+    fn dwords(lanes: [u32; 4]) -> u128 {
+        lanes.iter().rev().fold(0, |value, &lane| value << 32 | lane as u128)
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut machine = load(
+            "paddb xmm0, xmm1\npaddusb xmm2, xmm1\npaddsw xmm3, xmm4\npsubusw xmm5, xmm4\n\
+             pmullw xmm6, xmm7\npmulhw xmm8, xmm7\npmuludq xmm9, xmm10\npmaddwd xmm11, xmm7\npsadbw xmm12, xmm1",
+        );
+        let ramp = bytes(core::array::from_fn(|i| i as u8 * 16));
+        machine.set_xmm(0, ramp);
+        machine.set_xmm(1, bytes([0x80; 16]));
+        machine.set_xmm(2, ramp);
+        machine.set_xmm(3, words([0x7FFF, 0x8000, 1, 2, 3, 4, 5, 6]));
+        machine.set_xmm(4, words([1, 0xFFFF, 1, 1, 1, 1, 1, 1]));
+        machine.set_xmm(5, words([0, 5, 0, 0, 0, 0, 0, 0]));
+        machine.set_xmm(6, words([300, 2, 0, 0, 0, 0, 0, 0xFFFF]));
+        machine.set_xmm(7, words([300, 0xFFFD, 0, 0, 0, 0, 0, 0xFFFF]));
+        machine.set_xmm(8, words([300, 2, 0, 0, 0, 0, 0, 0xFFFF]));
+        machine.set_xmm(9, dwords([0xFFFF_FFFF, 7, 3, 7]));
+        machine.set_xmm(10, dwords([0xFFFF_FFFF, 9, 5, 9]));
+        machine.set_xmm(11, words([2, 3, 0, 0, 0, 0, 0, 0]));
+        machine.set_xmm(12, ramp);
+        halt(&mut machine);
+        assert_eq!(machine.xmm(0), bytes(core::array::from_fn(|i| (i as u8 * 16).wrapping_add(0x80))));
+        assert_eq!(machine.xmm(2), bytes(core::array::from_fn(|i| (i as u8 * 16).saturating_add(0x80))));
+        // 0x7FFF + 1 and 0x8000 - 1 saturate at both ends
+        assert_eq!(machine.xmm(3), words([0x7FFF, 0x8000, 2, 3, 4, 5, 6, 7]));
+        assert_eq!(machine.xmm(5), words([0, 0, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(machine.xmm(6), words([(300u32 * 300) as u16, 0xFFFA, 0, 0, 0, 0, 0, 1]));
+        assert_eq!(machine.xmm(8), words([(300u32 * 300 >> 16) as u16, 0xFFFF, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(machine.xmm(9), (0xFFFF_FFFFu128 * 0xFFFF_FFFF) | 15 << 64);
+        assert_eq!(machine.xmm(11), dwords([(2 * 300 - 3 * 3) as u32, 0, 0, 0]));
+        let half: u128 = (0..8).map(|i: u128| (i * 16).abs_diff(0x80)).sum();
+        let high: u128 = (8..16).map(|i: u128| (i * 16).abs_diff(0x80)).sum();
+        assert_eq!(machine.xmm(12), high << 64 | half);
+    }
+
+    #[test]
+    fn multiple_sums_of_absolute_differences() {
+        let mut machine = load("mpsadbw xmm0, xmm1, 5\nmpsadbw xmm2, xmm1, 0");
+        let ramp = bytes(core::array::from_fn(|i| i as u8));
+        machine.set_xmm(0, ramp);
+        machine.set_xmm(1, bytes(core::array::from_fn(|i| i as u8 * 2)));
+        machine.set_xmm(2, ramp);
+        halt(&mut machine);
+        // bytes 4 to 7 of the source against the runs starting at bytes 4 to 11
+        assert_eq!(machine.xmm(0), words([22, 18, 14, 10, 6, 4, 4, 6]));
+        assert_eq!(machine.xmm(2), words([6, 4, 4, 6, 10, 14, 18, 22]));
+    }
+
+    #[test]
+    fn comparisons_and_masks() {
+        let mut machine = load(
+            "pcmpeqb xmm0, xmm1\npmovmskb eax, xmm0\npcmpgtd xmm2, xmm3\npminuw xmm4, xmm5\npmaxsw xmm6, xmm5\n\
+             ptest xmm7, xmm8\nsetz bl\nsetc cl",
+        );
+        machine.set_xmm(0, bytes(*b"hello, world\0abc"));
+        machine.set_xmm(1, 0);
+        machine.set_xmm(2, dwords([1, 0xFFFF_FFFF, 5, 0]));
+        machine.set_xmm(3, dwords([0, 0, 5, 0xFFFF_FFFF]));
+        machine.set_xmm(4, words([1, 0x8000, 3, 4, 5, 6, 7, 8]));
+        machine.set_xmm(5, words([2, 0x7FFF, 0, 4, 5, 6, 7, 8]));
+        machine.set_xmm(6, words([1, 0x8000, 3, 4, 5, 6, 7, 8]));
+        machine.set_xmm(7, 0xF0);
+        machine.set_xmm(8, 0x0F);
+        halt(&mut machine);
+        // the NUL terminator
+        assert_eq!(machine.register(Register::EAX), 1 << 12);
+        assert_eq!(machine.xmm(2), dwords([0xFFFF_FFFF, 0, 0, 0xFFFF_FFFF]));
+        assert_eq!(machine.xmm(4), words([1, 0x7FFF, 0, 4, 5, 6, 7, 8]));
+        assert_eq!(machine.xmm(6), words([2, 0x7FFF, 3, 4, 5, 6, 7, 8]));
+        assert_eq!(machine.register(Register::BL), 1);
+        assert_eq!(machine.register(Register::CL), 0);
+    }
+
+    #[test]
+    fn strlen() {
+        // the shape of the SSE2 strlen in glibc, on an aligned string
+        let mut machine = load(&format!(
+            "mov rdi, {DATA}\nmov rax, rdi\npxor xmm0, xmm0\n\
+             again:\nmovdqa xmm1, [rax]\npcmpeqb xmm1, xmm0\npmovmskb edx, xmm1\nadd rax, 16\ntest edx, edx\njz again\n\
+             sub rax, rdi\nbsf edx, edx\nlea rax, [rax + rdx - 16]"
+        ));
+        machine.memory.write(DATA as usize, b"the quick brown fox jumps over the lazy dog\0").unwrap();
+        halt(&mut machine);
+        assert_eq!(machine.register(Register::RAX), 43);
+    }
+
+    #[test]
+    fn shuffles_and_unpacks() {
+        let mut machine = load(
+            "pshufb xmm0, xmm1\npshufd xmm2, xmm3, 0x1B\npalignr xmm4, xmm5, 3\npunpcklbw xmm6, xmm7\n\
+             pshufhw xmm8, xmm3, 0x00\npacksswb xmm9, xmm10\npslldq xmm11, 2\npsrad xmm12, 4\npsrlw xmm13, xmm14",
+        );
+        let ramp = bytes(core::array::from_fn(|i| i as u8));
+        machine.set_xmm(0, ramp);
+        machine.set_xmm(1, bytes([15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0x80]));
+        machine.set_xmm(3, dwords([0, 1, 2, 3]));
+        machine.set_xmm(4, bytes([0xAA; 16]));
+        machine.set_xmm(5, ramp);
+        machine.set_xmm(6, ramp);
+        machine.set_xmm(7, bytes([0xFF; 16]));
+        machine.set_xmm(9, words([1, 300, 0xFFFF, 0x8000, 0, 0, 0, 0]));
+        machine.set_xmm(10, words([0x7F, 0x80, 0, 0, 0, 0, 0, 2]));
+        machine.set_xmm(11, ramp);
+        machine.set_xmm(12, dwords([0x8000_0000, 0x40, 0, 0]));
+        machine.set_xmm(13, words([0xFFFF; 8]));
+        machine.set_xmm(14, 3);
+        halt(&mut machine);
+        assert_eq!(machine.xmm(0), bytes([15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0]));
+        assert_eq!(machine.xmm(2), dwords([3, 2, 1, 0]));
+        assert_eq!(machine.xmm(4), ramp >> 24 | bytes([0xAA; 16]) << 104);
+        assert_eq!(machine.xmm(6), bytes([0, 0xFF, 1, 0xFF, 2, 0xFF, 3, 0xFF, 4, 0xFF, 5, 0xFF, 6, 0xFF, 7, 0xFF]));
+        // the low words are copied, the high ones all take word 4
+        assert_eq!(machine.xmm(8), words([0, 0, 1, 0, 2, 2, 2, 2]));
+        assert_eq!(machine.xmm(9), bytes([1, 0x7F, 0xFF, 0x80, 0, 0, 0, 0, 0x7F, 0x7F, 0, 0, 0, 0, 0, 2]));
+        assert_eq!(machine.xmm(11), ramp << 16);
+        assert_eq!(machine.xmm(12), dwords([0xF800_0000, 4, 0, 0]));
+        assert_eq!(machine.xmm(13), words([0x1FFF; 8]));
+    }
+
+    #[test]
+    fn extend_insert_extract() {
+        let mut machine = load(&format!(
+            "mov eax, {DATA}\npmovzxbw xmm0, [rax]\npmovsxbd xmm1, xmm2\nmov ecx, 0x1234\npinsrw xmm3, ecx, 5\n\
+             pextrb edx, xmm2, 1\npextrq rsi, xmm3, 1\nmovd xmm4, ecx\nmovq rdi, xmm2\n\
+             movdqa xmm0, xmm5\npblendvb xmm5, xmm6, xmm0"
+        ));
+        machine.memory.write(DATA as usize, &[1, 0x80, 3, 4, 5, 6, 7, 0xFF]).unwrap();
+        machine.set_xmm(2, bytes([0xFE, 2, 0x80, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
+        machine.set_xmm(4, u128::MAX);
+        machine.set_xmm(5, bytes([0x80, 0, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF]));
+        machine.set_xmm(6, bytes([7; 16]));
+        halt(&mut machine);
+        // XMM0 was overwritten by the blend mask, so check the extension in XMM1 and the rest
+        assert_eq!(machine.xmm(1), dwords([-2i32 as u32, 2, -128i32 as u32, 4]));
+        assert_eq!(machine.xmm(3), words([0, 0, 0, 0, 0, 0x1234, 0, 0]));
+        assert_eq!(machine.register(Register::EDX), 2);
+        assert_eq!(machine.register(Register::RSI), 0x1234 << 16);
+        assert_eq!(machine.xmm(4), 0x1234);
+        assert_eq!(machine.register(Register::RDI), 0x0480_02FE);
+        assert_eq!(machine.xmm(5), bytes([7, 0, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 7]));
+    }
+
+    #[test]
+    fn string_compares() {
+        let mut machine = load(
+            "pcmpistri xmm0, xmm1, 0x00\nmov ebx, ecx\nsetc r9b\n\
+             pcmpistri xmm2, xmm1, 0x0C\nmov esi, ecx\n\
+             mov eax, 2\nmov edx, 16\npcmpestrm xmm3, xmm1, 0x44",
+        );
+        let mut text = [0u8; 16];
+        text[..11].copy_from_slice(b"hello world");
+        machine.set_xmm(0, bytes(*b" ,.\0\0\0\0\0\0\0\0\0\0\0\0\0"));
+        machine.set_xmm(1, bytes(text));
+        machine.set_xmm(2, bytes(*b"wor\0\0\0\0\0\0\0\0\0\0\0\0\0"));
+        machine.set_xmm(3, bytes(*b"az\0\0\0\0\0\0\0\0\0\0\0\0\0\0"));
+        halt(&mut machine);
+        // the first of the set, a substring and the lower case range as a byte mask
+        assert_eq!(machine.register(Register::EBX), 5);
+        assert_eq!(machine.register(Register::Gpr8(9)), 1);
+        assert_eq!(machine.register(Register::ESI), 6);
+        let letters: [u8; 16] = core::array::from_fn(|i| if text[i].is_ascii_lowercase() { 0xFF } else { 0 });
+        assert_eq!(machine.xmm(0), bytes(letters));
+        // the source ended early, the destination did too
+        assert!(machine.flags.is_set(RFlags::Sign));
+        assert!(!machine.flags.is_set(RFlags::Zero));
+    }
+
+    #[test]
+    fn crc32() {
+        let mut machine = load("mov eax, 0xFFFFFFFF\ncrc32 eax, byte [0x2000]\ncrc32 eax, word [0x2001]\ncrc32 rax, qword [0x2003]\nnot eax");
+        machine.memory.write(DATA as usize, b"123456789abc").unwrap();
+        halt(&mut machine);
+        // the CRC-32C check value of "123456789" is 0xE3069283, over 11 bytes here
+        let expected = b"123456789ab".iter().fold(!0u32, |mut crc, &byte| {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { crc >> 1 ^ 0x82F6_3B78 } else { crc >> 1 };
+            }
+            crc
+        });
+        assert_eq!(machine.register(Register::RAX), !expected as u64);
+    }
+}
+
+#[cfg(test)]
+mod avx {
+    use crate::fixture::{halt, load};
+
+    const DATA: u64 = 0x2000;
+
+    fn dwords(lanes: [u32; 8]) -> [u128; 2] {
+        let half = |lanes: &[u32]| lanes.iter().rev().fold(0, |value, &lane| value << 32 | lane as u128);
+        [half(&lanes[..4]), half(&lanes[4..])]
+    }
+
+    fn qwords(lanes: [u64; 4]) -> [u128; 2] {
+        [lanes[0] as u128 | (lanes[1] as u128) << 64, lanes[2] as u128 | (lanes[3] as u128) << 64]
+    }
+
+    fn singles(lanes: [f32; 8]) -> [u128; 2] {
+        dwords(lanes.map(f32::to_bits))
+    }
+
+    #[test]
+    fn legacy_forms_keep_the_upper_half() {
+        let ones = singles([1.0; 8]);
+        let twos = singles([2.0; 8]);
+        let mut machine = load("addps xmm0, xmm1\nvaddps xmm2, xmm2, xmm1");
+        (0..3).for_each(|n| machine.set_ymm(n, ones));
+        halt(&mut machine);
+        // SSE leaves bits 255:128 alone, the VEX form clears them
+        assert_eq!(machine.ymm(0), [twos[0], ones[1]]);
+        assert_eq!(machine.ymm(2), [twos[0], 0]);
+
+        let mut machine = load("vzeroupper\nvmovaps ymm1, ymm0\nvzeroall");
+        machine.set_ymm(0, ones);
+        machine.set_ymm(15, ones);
+        let mut upper_cleared = load("vzeroupper");
+        upper_cleared.set_ymm(15, ones);
+        halt(&mut upper_cleared);
+        assert_eq!(upper_cleared.ymm(15), [ones[0], 0]);
+        halt(&mut machine);
+        assert_eq!(machine.ymm(0), [0, 0]);
+        assert_eq!(machine.ymm(15), [0, 0]);
+    }
+
+    #[test]
+    fn full_width_arithmetic_and_in_lane_shuffles() {
+        let mut machine = load(
+            "vaddps ymm0, ymm1, ymm2\nvpaddd ymm3, ymm4, ymm5\nvpshufd ymm6, ymm4, 0x1B\nvpunpckldq ymm7, ymm4, ymm5\n\
+             vpsllvd ymm8, ymm4, ymm9\nvpsravd ymm10, ymm11, ymm9",
+        );
+        machine.set_ymm(1, singles([1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]));
+        machine.set_ymm(2, singles([0.5; 8]));
+        machine.set_ymm(4, dwords([0, 1, 2, 3, 4, 5, 6, 7]));
+        machine.set_ymm(5, dwords([10, 20, 30, 40, 50, 60, 70, 80]));
+        machine.set_ymm(9, dwords([1, 2, 3, 31, 32, 33, 0, 4]));
+        machine.set_ymm(11, dwords([0x8000_0000; 8]));
+        halt(&mut machine);
+        assert_eq!(machine.ymm(0), singles([1.5, 2.5, 3.5, 4.5, 5.5, 6.5, 7.5, 8.5]));
+        assert_eq!(machine.ymm(3), dwords([10, 21, 32, 43, 54, 65, 76, 87]));
+        // shuffles and unpacks stay within each 128-bit half
+        assert_eq!(machine.ymm(6), dwords([3, 2, 1, 0, 7, 6, 5, 4]));
+        assert_eq!(machine.ymm(7), dwords([0, 10, 1, 20, 4, 50, 5, 60]));
+        // per lane counts, past the width giving zero or the sign
+        assert_eq!(machine.ymm(8), dwords([0, 4, 16, 0x8000_0000, 0, 0, 6, 112]));
+        let sign_filled = [0xC000_0000, 0xE000_0000, 0xF000_0000, !0, !0, !0, 0x8000_0000, 0xF800_0000];
+        assert_eq!(machine.ymm(10), dwords(sign_filled));
+    }
+
+    #[test]
+    fn permutes_and_broadcasts() {
+        let mut machine = load(
+            "vpermq ymm0, ymm4, 0x1B\nvpermd ymm1, ymm5, ymm4\nvperm2i128 ymm2, ymm4, ymm6, 0x83\n\
+             vpbroadcastd ymm3, xmm6\nvbroadcastsd ymm7, qword [0x2000]\nvinserti128 ymm8, ymm4, xmm6, 1\n\
+             vextracti128 xmm9, ymm4, 1\nvpermilps ymm10, ymm4, 0x4E",
+        );
+        machine.set_ymm(4, dwords([0, 1, 2, 3, 4, 5, 6, 7]));
+        machine.set_ymm(5, dwords([7, 6, 5, 4, 3, 2, 9, 0]));
+        machine.set_ymm(6, dwords([10, 11, 12, 13, 14, 15, 16, 17]));
+        machine.memory.write(DATA as usize, &0x1122_3344_5566_7788u64.to_le_bytes()).unwrap();
+        halt(&mut machine);
+        assert_eq!(machine.ymm(0), dwords([6, 7, 4, 5, 2, 3, 0, 1]));
+        // indices wrap at eight lanes
+        assert_eq!(machine.ymm(1), dwords([7, 6, 5, 4, 3, 2, 1, 0]));
+        // the low half from the high half of the second source, the high half zeroed
+        assert_eq!(machine.ymm(2), dwords([14, 15, 16, 17, 0, 0, 0, 0]));
+        assert_eq!(machine.ymm(3), dwords([10; 8]));
+        assert_eq!(machine.ymm(7), qwords([0x1122_3344_5566_7788; 4]));
+        assert_eq!(machine.ymm(8), dwords([0, 1, 2, 3, 10, 11, 12, 13]));
+        assert_eq!(machine.ymm(9), dwords([4, 5, 6, 7, 0, 0, 0, 0]));
+        assert_eq!(machine.ymm(10), dwords([2, 3, 0, 1, 6, 7, 4, 5]));
+    }
+
+    #[test]
+    fn gathers_and_masked_moves() {
+        let mut machine = load(
+            "mov rsi, 0x2000\nvpgatherdd ymm0, [rsi + ymm1 * 4], ymm2\n\
+             vpmaskmovd ymm3, ymm4, [rsi]\nvpmaskmovd [rsi + 0x40], ymm4, ymm1",
+        );
+        let table: Vec<u8> = (0..16u32).flat_map(|i| (i * 100).to_le_bytes()).collect();
+        machine.memory.write(DATA as usize, &table).unwrap();
+        machine.set_ymm(0, dwords([0xAAAA; 8]));
+        machine.set_ymm(1, dwords([3, 0, 15, 2, 1, 1, 8, 0xFFFF_FFFF]));
+        // the last lane is masked off, so its index below the table is never used
+        machine.set_ymm(2, dwords([!0, !0, !0, !0, !0, !0, !0, 0]));
+        machine.set_ymm(4, dwords([!0, 0, !0, 0, 0, 0, 0, !0]));
+        halt(&mut machine);
+        assert_eq!(machine.ymm(0), dwords([300, 0, 1500, 200, 100, 100, 800, 0xAAAA]));
+        assert_eq!(machine.ymm(2), [0, 0]);
+        assert_eq!(machine.ymm(3), dwords([0, 0, 200, 0, 0, 0, 0, 700]));
+        let expected: Vec<u8> = [3u32, 0, 15, 0, 0, 0, 0, 0xFFFF_FFFF].iter().flat_map(|v| v.to_le_bytes()).collect();
+        assert_eq!(machine.memory.read(DATA as usize + 0x40, 32).unwrap(), &expected[..]);
+    }
+
+    #[test]
+    fn sse3_and_sse41_forms_run_per_block() {
+        let mut machine = load(
+            "vhaddps ymm0, ymm1, ymm2\nvroundps ymm3, ymm1, 1\nvdpps ymm4, ymm1, ymm2, 0xF1\nvmovshdup ymm5, ymm1\n\
+             vinsertps xmm6, xmm1, xmm2, 0xC8\nvmpsadbw ymm7, ymm8, ymm9, 0x28",
+        );
+        let ramp = u128::from_le_bytes(core::array::from_fn(|i| i as u8));
+        let evens = u128::from_le_bytes(core::array::from_fn(|i| i as u8 * 2));
+        machine.set_ymm(1, singles([1.5, 2.0, 3.0, 4.0, 5.0, 6.5, 7.0, 8.0]));
+        machine.set_ymm(2, singles([1.0, 1.0, 2.0, 2.0, 3.0, 3.0, 4.0, 4.0]));
+        machine.set_ymm(6, singles([9.0; 8]));
+        machine.set_ymm(8, [ramp, ramp]);
+        machine.set_ymm(9, [evens, evens]);
+        halt(&mut machine);
+        assert_eq!(machine.ymm(0), singles([3.5, 7.0, 2.0, 4.0, 11.5, 15.0, 6.0, 8.0]));
+        assert_eq!(machine.ymm(3), singles([1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]));
+        assert_eq!(machine.ymm(4), singles([17.5, 0.0, 0.0, 0.0, 94.5, 0.0, 0.0, 0.0]));
+        assert_eq!(machine.ymm(5), singles([2.0, 2.0, 4.0, 4.0, 6.5, 6.5, 8.0, 8.0]));
+        assert_eq!(machine.ymm(6), singles([2.0, 2.0, 3.0, 0.0, 0.0, 0.0, 0.0, 0.0]));
+        // the high block takes bits 5:3 of the immediate, the doubleword at 4 against bytes 4 on
+        let words = |low: u32, high: u32| low | high << 16;
+        let sums = [words(6, 4), words(4, 6), words(10, 14), words(18, 22), words(22, 18), words(14, 10), words(6, 4), words(4, 6)];
+        assert_eq!(machine.ymm(7), dwords(sums));
+    }
+
+    #[test]
+    fn half_precision_conversions() {
+        let mut machine = load("vcvtps2ph xmm0, ymm1, 0\nvcvtph2ps ymm2, xmm0\nvcvtps2ph xmm3, ymm1, 3");
+        let halves = |lanes: [u16; 8]| lanes.iter().rev().fold(0, |value, &lane| value << 16 | lane as u128);
+        machine.set_ymm(1, singles([1.0, -2.0, 0.5, 65504.0, 1e6, 1.0 / 3.0, 6e-8, 0.0]));
+        halt(&mut machine);
+        // 1e6 overflows to infinity, or to the largest half toward zero, and 6e-8 is denormal
+        assert_eq!(machine.ymm(0), [halves([0x3C00, 0xC000, 0x3800, 0x7BFF, 0x7C00, 0x3555, 0x0001, 0]), 0]);
+        assert_eq!(machine.ymm(3), [halves([0x3C00, 0xC000, 0x3800, 0x7BFF, 0x7BFF, 0x3555, 0x0001, 0]), 0]);
+        let widened = singles([1.0, -2.0, 0.5, 65504.0, f32::INFINITY, 0.333_251_95, 2f32.powi(-24), 0.0]);
+        assert_eq!(machine.ymm(2), widened);
+        // overflow, underflow and precision narrowing, and the denormal half widened
+        assert_eq!(machine.mxcsr(), 0x1F80 | 0x3A);
+    }
+
+    #[test]
+    fn fused_multiply_add_rounds_once() {
+        let mut machine = load("vfmadd231sd xmm0, xmm1, xmm2\nvmulsd xmm3, xmm1, xmm2\nvaddsd xmm3, xmm3, xmm4\nvfmsub213ps ymm5, ymm6, ymm7");
+        // (1 + 2^-30)^2 - (1 + 2^-29) is 2^-60, which a rounded product loses
+        let x = 1.0 + 2f64.powi(-30);
+        let c = -(1.0 + 2f64.powi(-29));
+        machine.set_xmm(0, c.to_bits() as u128);
+        machine.set_xmm(1, x.to_bits() as u128);
+        machine.set_xmm(2, x.to_bits() as u128);
+        machine.set_xmm(4, c.to_bits() as u128);
+        machine.set_ymm(5, singles([2.0; 8]));
+        machine.set_ymm(6, singles([3.0; 8]));
+        machine.set_ymm(7, singles([1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]));
+        halt(&mut machine);
+        assert_eq!(f64::from_bits(machine.xmm(0) as u64), 2f64.powi(-60));
+        assert_eq!(f64::from_bits(machine.xmm(3) as u64), 0.0);
+        assert_eq!(machine.ymm(5), singles([5.0, 4.0, 3.0, 2.0, 1.0, 0.0, -1.0, -2.0]));
+    }
+}
+
+#[cfg(test)]
+mod avx512 {
+    use lib_opcode::prelude::Register;
+    use crate::fixture::{halt, load};
+
+    const DATA: u64 = 0x2000;
+    /// The last byte of the 64 KiB the machines have
+    const END: u64 = 0x1_0000;
+
+    fn dwords(lanes: [u32; 16]) -> [u128; 4] {
+        let mut blocks = [0; 4];
+        for (i, &lane) in lanes.iter().enumerate() {
+            blocks[i / 4] |= (lane as u128) << (i % 4 * 32);
+        }
+        blocks
+    }
+
+    fn lanes(blocks: [u128; 4]) -> [u32; 16] {
+        std::array::from_fn(|i| (blocks[i / 4] >> (i % 4 * 32)) as u32)
+    }
+
+    #[test]
+    fn registers_alias_and_masking_merges_or_zeroes() {
+        let mut machine = load(
+            "mov eax, 0x5555\nkmovw k1, eax\nvpaddd zmm0 {k1}, zmm1, zmm2\nvpaddd zmm3 {k1}{z}, zmm1, zmm2\n\
+             vpxor xmm4, xmm4, xmm4\npxor xmm5, xmm5",
+        );
+        machine.set_zmm(0, dwords([100; 16]));
+        machine.set_zmm(1, dwords(std::array::from_fn(|i| i as u32)));
+        machine.set_zmm(2, dwords([1; 16]));
+        machine.set_zmm(4, [!0; 4]);
+        machine.set_zmm(5, [!0; 4]);
+        halt(&mut machine);
+        assert_eq!(machine.opmask(1), 0x5555);
+        assert_eq!(lanes(machine.zmm(0)), std::array::from_fn(|i| if i % 2 == 0 { i as u32 + 1 } else { 100 }));
+        assert_eq!(lanes(machine.zmm(3)), std::array::from_fn(|i| if i % 2 == 0 { i as u32 + 1 } else { 0 }));
+        assert_eq!(machine.xmm(0), machine.zmm(0)[0]);
+        assert_eq!(machine.ymm(3), [machine.zmm(3)[0], machine.zmm(3)[1]]);
+        // VEX clears up to bit 511, legacy SSE keeps everything past bit 127
+        assert_eq!(machine.zmm(4), [0; 4]);
+        assert_eq!(machine.zmm(5), [0, !0, !0, !0]);
+    }
+
+    #[test]
+    fn embedded_broadcast_and_rounding() {
+        let mut machine = load(
+            "mov rax, 0x2000\nvaddps zmm0, zmm1, dword [rax]{1to16}\n\
+             vaddps zmm2, zmm1, zmm3, {rz-sae}\nvaddps zmm4, zmm1, zmm3, {ru-sae}",
+        );
+        machine.memory.write(DATA as usize, &2f32.to_le_bytes()).unwrap();
+        machine.set_zmm(1, dwords([1f32.to_bits(); 16]));
+        machine.set_zmm(3, dwords([2f32.powi(-30).to_bits(); 16]));
+        halt(&mut machine);
+        assert_eq!(lanes(machine.zmm(0)), [3f32.to_bits(); 16]);
+        assert_eq!(lanes(machine.zmm(2)), [1f32.to_bits(); 16]);
+        assert_eq!(lanes(machine.zmm(4)), [1f32.to_bits() + 1; 16]);
+        // the inexact results of the rounding forms are not recorded
+        assert_eq!(machine.mxcsr() & 0x3F, 0);
+    }
+
+    #[test]
+    fn compares_into_opmasks() {
+        let mut machine = load(
+            "vpcmpgtd k1, zmm0, zmm1\nvpcmpd k2 {k1}, zmm0, zmm2, 0\nvptestnmd k3, zmm1, zmm1\n\
+             vpmovm2d zmm3, k2\nkmovw eax, k1\nkxnorw k4, k4, k4\nkortestw k4, k4\nsetc bl\nkortestw k3, k3\nsetz cl",
+        );
+        machine.set_zmm(0, dwords(std::array::from_fn(|i| i as u32)));
+        machine.set_zmm(1, dwords([7; 16]));
+        machine.set_zmm(2, dwords(std::array::from_fn(|i| if i < 12 { i as u32 } else { 0 })));
+        halt(&mut machine);
+        assert_eq!(machine.register(Register::RAX), 0xFF00);
+        // equal lanes are 0 to 11, of which only 8 to 11 are greater than 7
+        assert_eq!(machine.opmask(2), 0x0F00);
+        assert_eq!(machine.opmask(3), 0);
+        assert_eq!(lanes(machine.zmm(3)), std::array::from_fn(|i| if (8..12).contains(&i) { !0 } else { 0 }));
+        assert_eq!(machine.opmask(4), 0xFFFF);
+        assert_eq!(machine.register(Register::RBX) & 0xFF, 1);
+        assert_eq!(machine.register(Register::RCX) & 0xFF, 1);
+    }
+
+    #[test]
+    fn masked_accesses_skip_unselected_elements() {
+        // two dwords before the end of memory, the other fourteen would fault
+        let mut machine = load(&format!(
+            "mov eax, 3\nkmovw k1, eax\nmov rsi, {}\nvmovdqu32 [rsi] {{k1}}, zmm0\nvmovdqu32 zmm1 {{k1}}{{z}}, [rsi]",
+            END - 8
+        ));
+        machine.set_zmm(0, dwords(std::array::from_fn(|i| i as u32 + 1)));
+        halt(&mut machine);
+        assert_eq!(machine.memory.read((END - 8) as usize, 8).unwrap(), &[1, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(lanes(machine.zmm(1)), std::array::from_fn(|i| if i < 2 { i as u32 + 1 } else { 0 }));
+    }
+
+    #[test]
+    fn compress_expand_and_permutes() {
+        let mut machine = load(
+            "mov eax, 0xA00A\nkmovw k1, eax\nvpcompressd zmm1 {k1}{z}, zmm0\nvpexpandd zmm2 {k1}{z}, zmm0\n\
+             vpermt2d zmm3, zmm4, zmm5\nvpternlogd zmm6, zmm0, zmm5, 0xCA\n\
+             valignd zmm7, zmm0, zmm5, 3\nvshufi32x4 zmm8, zmm0, zmm5, 0x1B\nvpmovusdb xmm9, zmm5",
+        );
+        let source: [u32; 16] = std::array::from_fn(|i| i as u32);
+        let other: [u32; 16] = std::array::from_fn(|i| 100 + i as u32 * 20);
+        machine.set_zmm(0, dwords(source));
+        machine.set_zmm(3, dwords(source.map(|i| i + 50)));
+        machine.set_zmm(4, dwords(std::array::from_fn(|i| 31 - i as u32 * 2)));
+        machine.set_zmm(5, dwords(other));
+        machine.set_zmm(6, dwords([0xFFFF_0000; 16]));
+        halt(&mut machine);
+        assert_eq!(lanes(machine.zmm(1)), [1, 3, 13, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(lanes(machine.zmm(2)), [0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 3]);
+        // indices 16 to 31 pick from the second table
+        let expected: [u32; 16] = std::array::from_fn(|i| match 31 - i * 2 {
+            index if index >= 16 => other[index - 16],
+            index => index as u32 + 50,
+        });
+        assert_eq!(lanes(machine.zmm(3)), expected);
+        assert_eq!(lanes(machine.zmm(6)), std::array::from_fn(|i| source[i] & 0xFFFF_0000 | other[i] & 0xFFFF));
+        assert_eq!(lanes(machine.zmm(7)), std::array::from_fn(|i| if i < 13 { other[i + 3] } else { source[i - 13] }));
+        let (a, b) = (machine.zmm(0), machine.zmm(5));
+        assert_eq!(machine.zmm(8), [a[3], a[2], b[1], b[0]]);
+        let saturated: Vec<u8> = other.iter().map(|&value| value.min(255) as u8).collect();
+        assert_eq!(machine.xmm(9).to_le_bytes(), &saturated[..]);
+    }
+
+    #[test]
+    fn multishift_selects_unaligned_bytes() {
+        let mut machine = load(
+            "vpmultishiftqb zmm0, zmm1, zmm2\nmov eax, 0xF0F0\nkmovq k1, rax\nmov rax, 0x2000\n\
+             vpmultishiftqb zmm3 {k1}{z}, zmm1, qword [rax]{1to8}",
+        );
+        let controls: [u8; 64] = std::array::from_fn(|i| (i * 7 + i / 8) as u8);
+        let data: [u64; 8] = std::array::from_fn(|i| 0x0123_4567_89AB_CDEF_u64.rotate_left(i as u32 * 5) ^ i as u64);
+        let bytes = |bytes: [u8; 64]| std::array::from_fn(|i| u128::from_le_bytes(bytes[i * 16..][..16].try_into().unwrap()));
+        machine.set_zmm(1, bytes(controls));
+        machine.set_zmm(2, bytes(std::array::from_fn(|i| data[i / 8].to_le_bytes()[i % 8])));
+        machine.memory.write(DATA as usize, &data[5].to_le_bytes()).unwrap();
+        halt(&mut machine);
+        // the control bits past 5 are ignored and the field wraps round the quadword
+        let pick = |data: u64, i: usize| data.rotate_right(controls[i] as u32 & 63) as u8;
+        assert_eq!(machine.zmm(0), bytes(std::array::from_fn(|i| pick(data[i / 8], i))));
+        let masked = std::array::from_fn(|i| if 0xF0F0_u64 >> i & 1 != 0 { pick(data[5], i) } else { 0 });
+        assert_eq!(machine.zmm(3), bytes(masked));
+    }
+
+    #[test]
+    fn opmask_broadcasts_and_double_block_sums() {
+        let mut machine = load(
+            "mov eax, 0x1A5\nkmovw k1, eax\nvpbroadcastmw2d zmm0, k1\nvpbroadcastmb2q ymm1, k1\n\
+             vdbpsadbw zmm2, zmm3, zmm4, 0x1B",
+        );
+        machine.set_zmm(1, [!0; 4]);
+        // each quadword of the first source is four 10s then four 20s, the second source's
+        // bytes count up from 0 in every block and the immediate reverses its dwords
+        machine.set_zmm(3, [u128::from_le_bytes(std::array::from_fn(|i| if i % 8 < 4 { 10 } else { 20 })); 4]);
+        machine.set_zmm(4, [u128::from_le_bytes(std::array::from_fn(|i| i as u8)); 4]);
+        halt(&mut machine);
+        assert_eq!(lanes(machine.zmm(0)), [0x1A5; 16]);
+        assert_eq!(machine.zmm(1), [0xA5 | 0xA5 << 64, 0xA5 | 0xA5 << 64, 0, 0]);
+        let sums = [14u16, 14, 34, 38, 18, 22, 66, 70];
+        let block = sums.iter().rev().fold(0, |block, &sum| block << 16 | sum as u128);
+        assert_eq!(machine.zmm(2), [block; 4]);
+    }
+
+    #[test]
+    fn unsigned_and_quadword_conversions() {
+        let mut machine = load(
+            "vcvtudq2ps zmm0, zmm1\nvcvttps2udq zmm2, zmm3\nvcvtqq2pd zmm4, zmm5\nvcvtps2uqq zmm6, ymm7\n\
+             mov rax, -1\nvcvtusi2sd xmm8, xmm8, rax\nvcvttsd2usi rbx, xmm9\nvcvtss2usi ecx, xmm10",
+        );
+        let qwords = |lanes: [u64; 8]| std::array::from_fn(|i| lanes[i * 2] as u128 | (lanes[i * 2 + 1] as u128) << 64);
+        machine.set_zmm(1, dwords([u32::MAX, 1 << 31, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 7]));
+        machine.set_zmm(3, dwords([-0.5f32, 3e9, -1.0, 4.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0].map(f32::to_bits)));
+        machine.set_zmm(5, qwords([-3i64 as u64, (1 << 53) + 1, 0, 0, 0, 0, 0, 1]));
+        machine.set_zmm(7, dwords([2.5f32, 3.5, 2f32.powi(63), 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0].map(f32::to_bits)));
+        machine.set_xmm(9, (2f64.powi(63) + 2048.0).to_bits() as u128);
+        machine.set_xmm(10, (-1f32).to_bits() as u128);
+        halt(&mut machine);
+        let singles = [2f32.powi(32), 2f32.powi(31), 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 7.0];
+        assert_eq!(lanes(machine.zmm(0)), singles.map(f32::to_bits));
+        // -0.5 truncates to zero, -1.0 is out of range and gives all ones
+        assert_eq!(lanes(machine.zmm(2)), [0, 3_000_000_000, u32::MAX, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        let doubles = [-3.0, 2f64.powi(53), 0.0, 0.0, 0.0, 0.0, 0.0, 1.0];
+        assert_eq!(machine.zmm(4), qwords(doubles.map(f64::to_bits)));
+        assert_eq!(machine.zmm(6), qwords([2, 4, 1 << 63, 0, 0, 0, 0, 1]));
+        assert_eq!(machine.xmm(8) as u64, 2f64.powi(64).to_bits());
+        assert_eq!(machine.register(Register::RBX), (1 << 63) + 2048);
+        assert_eq!(machine.register(Register::RCX), u32::MAX as u64);
+        assert_eq!(machine.mxcsr(), 0x1F80 | 0x21);
+    }
+
+    #[test]
+    fn exponents_mantissas_reciprocals_and_fixups() {
+        let mut machine = load(
+            "vscalefps zmm0, zmm1, zmm2\nvgetexpps zmm3, zmm1\nvgetmantps zmm4, zmm1, 0x01\nvgetmantps zmm5, zmm1, 0x07\n\
+             vrndscaleps zmm6, zmm1, 0x20\nvrcp14ps zmm7, zmm1\nvrsqrt14ps zmm8, zmm9\nvfixupimmps zmm10, zmm11, zmm12, 0x02\n\
+             vscalefsd xmm13, xmm14, xmm15",
+        );
+        // the four lanes repeated across the register
+        let singles = |lanes: [f32; 4]| dwords(std::array::from_fn(|i| lanes[i % 4].to_bits()));
+        let x = [3.0, -10.0, 0.375, 1.3];
+        machine.set_zmm(1, singles(x));
+        machine.set_zmm(2, singles([2.5, -1.5, 0.0, f32::NEG_INFINITY]));
+        machine.set_zmm(9, singles([4.0, 0.25, 2.0, -1.0]));
+        machine.set_zmm(10, singles([7.0; 4]));
+        machine.set_zmm(11, singles([0.0, 1.0, f32::NEG_INFINITY, -5.0]));
+        // zero gives +1, +1 gives pi/2, -inf gives +inf and negative values keep the destination
+        machine.set_zmm(12, dwords([0x5DA00; 16]));
+        machine.set_xmm(14, 5f64.to_bits() as u128 | (99f64.to_bits() as u128) << 64);
+        machine.set_xmm(15, (-1f64).to_bits() as u128);
+        halt(&mut machine);
+        assert_eq!(machine.zmm(0), singles([12.0, -2.5, 0.375, 0.0]));
+        assert_eq!(machine.zmm(3), singles([1.0, 3.0, -2.0, 0.0]));
+        // [1/2, 2) takes off an even power of two, [3/4, 3/2) with the sign cleared
+        assert_eq!(machine.zmm(4), singles([0.75, -0.625, 1.5, 1.3]));
+        assert_eq!(machine.zmm(5), singles([0.75, 1.25, 0.75, 1.3]));
+        // to quarters, 0.375 is a tie that goes to the even 0.5
+        assert_eq!(machine.zmm(6), singles([3.0, -10.0, 0.5, 1.25]));
+        assert_eq!(machine.zmm(7), singles(x.map(|x| 1.0 / x)));
+        assert_eq!(machine.zmm(8), singles([0.5, 2.0, 1.0 / 2f32.sqrt(), f32::from_bits(0xFFC0_0000)]));
+        assert_eq!(machine.zmm(10), singles([1.0, std::f32::consts::FRAC_PI_2, f32::INFINITY, 7.0]));
+        assert_eq!(machine.xmm(13), 2.5f64.to_bits() as u128 | (99f64.to_bits() as u128) << 64);
+        // invalid from the fixup of a zero, precision from rounding 0.375 and 1.3; the
+        // reciprocals raise nothing
+        assert_eq!(machine.mxcsr(), 0x1F80 | 0x21);
+    }
+
+    #[test]
+    fn ranges_reductions_and_classes() {
+        let mut machine = load(
+            "vrangeps zmm0, zmm1, zmm2, 0x02\nvrangeps zmm3, zmm1, zmm2, 0x05\nvreduceps zmm4, zmm7, 0x10\n\
+             vfpclassps k1, zmm5, 0x22\nvfpclasssd k2, xmm6, 0x40",
+        );
+        let singles = |lanes: [f32; 4]| dwords(std::array::from_fn(|i| lanes[i % 4].to_bits()));
+        machine.set_zmm(1, singles([-3.0, 0.0, 2.75, -0.0]));
+        machine.set_zmm(2, singles([2.0, -0.0, -5.0, 0.0]));
+        machine.set_zmm(5, singles([0.0, -0.0, f32::from_bits(1), 1.0]));
+        machine.set_xmm(6, (-2f64).to_bits() as u128);
+        machine.set_zmm(7, singles([1.3, f32::NEG_INFINITY, 2.75, 0.1]));
+        halt(&mut machine);
+        // the smaller magnitude, the first source on a tie
+        assert_eq!(machine.zmm(0), singles([2.0, 0.0, 2.75, -0.0]));
+        // the maximum, -0 below +0, with the sign of the first source
+        assert_eq!(machine.zmm(3), singles([-2.0, 0.0, 2.75, -0.0]));
+        // what rounding to halves takes off, +0 for an infinity
+        assert_eq!(machine.zmm(4), singles([1.3 - 1.5, 0.0, -0.25, 0.1]));
+        // +0 or denormal
+        assert_eq!(machine.opmask(1), 0x5555);
+        assert_eq!(machine.opmask(2), 1);
+        assert_eq!(machine.mxcsr(), 0x1F80 | 0x20);
+    }
+}
+
+#[cfg(test)]
+mod x87 {
+    use lib_x86::prelude::*;
+    use crate::fixture::{halt, load};
+
+    const DATA: u64 = 0x2000;
+
+    const ONE: u128 = 0x3FFF_8000_0000_0000_0000;
+    const INDEFINITE: u128 = 0xFFFF_C000_0000_0000_0000;
+
+    fn data(machine: &X86Machine, offset: u64, size: usize) -> Vec<u8> {
+        machine.memory.read((DATA + offset) as usize, size).unwrap()
+    }
+
+    #[test]
+    fn loads_and_stores_in_every_format() {
+        let mut machine = load(
+            "fld dword [0x2000]\nfld qword [0x2008]\nfild word [0x2010]\nfbld tword [0x2020]\n\
+             fstp tword [0x2040]\nfistp dword [0x2050]\nfst dword [0x2060]\nfstp qword [0x2068]\n\
+             fld1\nfchs\nfbstp tword [0x2070]\nfstp dword [0x2080]",
+        );
+        machine.memory.write(DATA as usize, &1.5f32.to_le_bytes()).unwrap();
+        machine.memory.write(DATA as usize + 8, &(1.0f64 / 3.0).to_le_bytes()).unwrap();
+        machine.memory.write(DATA as usize + 0x10, &(-7i16).to_le_bytes()).unwrap();
+        machine.memory.write(DATA as usize + 0x20, &[0x21, 0x43, 0x65, 0, 0, 0, 0, 0, 0, 0x80]).unwrap();
+        halt(&mut machine);
+        // -654321 as an extended value, stored exactly
+        assert_eq!(data(&machine, 0x40, 10), &(0xC012_9FBF_1000_0000_0000u128).to_le_bytes()[..10]);
+        assert_eq!(data(&machine, 0x50, 4), &(-7i32).to_le_bytes());
+        assert_eq!(data(&machine, 0x60, 4), &((1.0f64 / 3.0) as f32).to_le_bytes());
+        assert_eq!(data(&machine, 0x68, 8), &(1.0f64 / 3.0).to_le_bytes());
+        assert_eq!(data(&machine, 0x70, 10), &[1, 0, 0, 0, 0, 0, 0, 0, 0, 0x80]);
+        assert_eq!(data(&machine, 0x80, 4), &1.5f32.to_le_bytes());
+        // every register popped again
+        assert_eq!(machine.fpu_tag_word(), 0xFFFF);
+        assert_eq!(machine.fpu_status_word() & 0x3800, 0);
+    }
+
+    #[test]
+    fn arithmetic_rounds_to_the_precision_control() {
+        let mut machine = load(
+            "fld1\nfild dword [0x2000]\nfdivp st1, st0\nfnstsw word [0x2020]\nfstp tword [0x2010]\n\
+             fldcw word [0x2004]\nfld1\nfild dword [0x2000]\nfdivp st1, st0\nfstp tword [0x2030]",
+        );
+        machine.memory.write(DATA as usize, &3i32.to_le_bytes()).unwrap();
+        // single precision, everything masked
+        machine.memory.write(DATA as usize + 4, &0x007Fu16.to_le_bytes()).unwrap();
+        halt(&mut machine);
+        assert_eq!(data(&machine, 0x10, 10), &(0x3FFD_AAAA_AAAA_AAAA_AAABu128).to_le_bytes()[..10]);
+        // precision, rounded up into C1
+        assert_eq!(u16::from_le_bytes(data(&machine, 0x20, 2).try_into().unwrap()) & 0x0220, 0x0220);
+        assert_eq!(data(&machine, 0x30, 10), &(0x3FFD_AAAA_AB00_0000_0000u128).to_le_bytes()[..10]);
+    }
+
+    #[test]
+    fn comparisons_set_condition_codes_and_flags() {
+        let mut machine = load(
+            "fld1\nfldz\nfcom st1\nfnstsw ax\nmov bx, ax\nfcomi st0, st1\nsetb cl\nfxam\nfnstsw ax\n\
+             fxch st1\nfucomip st0, st1\nsetnbe dl\nfstp st0\nfxam\nfnstsw word [0x2000]",
+        );
+        halt(&mut machine);
+        let (c0, c2, c3) = (1 << 8, 1 << 10, 1 << 14);
+        // 0 < 1
+        assert_eq!(machine.register(Register::BX) as u16 & (c3 | c2 | c0), c0);
+        assert_eq!(machine.register(Register::CL), 1);
+        // a zero
+        assert_eq!(machine.register(Register::AX) as u16 & (c3 | c2 | c0), c3);
+        // 1 > 0
+        assert_eq!(machine.register(Register::DL), 1);
+        // empty
+        assert_eq!(u16::from_le_bytes(data(&machine, 0, 2).try_into().unwrap()) & (c3 | c2 | c0), c3 | c0);
+    }
+
+    #[test]
+    fn transcendentals_and_constants_match_hardware() {
+        let mut machine = load(
+            "fldpi\nfld st0\nfstp tword [0x2000]\nfsin\nfstp tword [0x2010]\n\
+             fld1\nfild dword [0x2080]\nfyl2x\nfstp tword [0x2020]\n\
+             fld1\nfld1\nfpatan\nfstp tword [0x2030]\n\
+             fld1\nfchs\nf2xm1\nfstp tword [0x2040]\n\
+             fld1\nfld1\nfaddp st1, st0\nfsqrt\nfstp tword [0x2050]",
+        );
+        machine.memory.write(DATA as usize + 0x80, &8i32.to_le_bytes()).unwrap();
+        halt(&mut machine);
+        assert_eq!(data(&machine, 0, 10), &(0x4000_C90F_DAA2_2168_C235u128).to_le_bytes()[..10]);
+        // reduced by the 66 bit pi the x87 uses, so -2^-64 rather than the true -5.0166e-20
+        assert_eq!(data(&machine, 0x10, 10), &(0xBFBF_8000_0000_0000_0000u128).to_le_bytes()[..10]);
+        assert_eq!(data(&machine, 0x20, 10), &(0x4000_C000_0000_0000_0000u128).to_le_bytes()[..10]);
+        assert_eq!(data(&machine, 0x30, 10), &(0x3FFE_C90F_DAA2_2168_C235u128).to_le_bytes()[..10]);
+        assert_eq!(data(&machine, 0x40, 10), &(0xBFFE_8000_0000_0000_0000u128).to_le_bytes()[..10]);
+        assert_eq!(data(&machine, 0x50, 10), &(0x3FFF_B504_F333_F9DE_6484u128).to_le_bytes()[..10]);
+    }
+
+    #[test]
+    fn exceptions_and_the_environment() {
+        // nine pushes overflow the stack, masked
+        let mut machine = load(&"fld1\n".repeat(9));
+        halt(&mut machine);
+        assert_eq!(machine.st(0), INDEFINITE);
+        assert_eq!(machine.st(1), ONE);
+        assert_eq!(machine.fpu_status_word() & 0x0241, 0x0241);
+
+        // FNSAVE / FRSTOR round trip, with FNINIT in between
+        let mut machine = load("fld1\nfldz\nfnsave [0x2000]\nfld1\nfrstor [0x2000]\nfnstenv [0x2100]");
+        halt(&mut machine);
+        assert_eq!((machine.st(0), machine.st(1)), (0, ONE));
+        assert_eq!(machine.fpu_tag_word(), 0x1FFF);
+        assert_eq!(data(&machine, 0x1C, 10), &[0; 10]);
+        assert_eq!(data(&machine, 0x26, 10), &ONE.to_le_bytes()[..10]);
+        assert_eq!(data(&machine, 0x108, 2), &0x1FFFu16.to_le_bytes());
+        // FNSTENV masks everything afterwards
+        assert_eq!(machine.fpu_control_word() & 0x3F, 0x3F);
+
+        // an unmasked divide by zero leaves the destination and faults on the next FPU instruction
+        let mut machine = load("fldcw word [0x2000]\nfld1\nfldz\nfdivp st1, st0\nfld1");
+        machine.memory.write(DATA as usize, &0x037Bu16.to_le_bytes()).unwrap();
+        let reason = machine.run();
+        assert!(matches!(
+            reason,
+            StopReason::Fault(Fault::Exception { exception: Exception::FloatingPoint, .. })
+        ));
+        assert_eq!(machine.fpu_status_word() & 0x8084, 0x8084);
+        assert_eq!((machine.st(0), machine.st(1)), (0, ONE));
+    }
+}
+
+#[cfg(test)]
+mod mmx {
+    use lib_x86::prelude::*;
+    use crate::fixture::{halt, load};
+
+    const DATA: u64 = 0x2000;
+
+    const ONE: u128 = 0x3FFF_8000_0000_0000_0000;
+    const INDEFINITE: u128 = 0xFFFF_C000_0000_0000_0000;
+
+    fn words(lanes: [u16; 4]) -> u64 {
+        lanes.iter().rev().fold(0, |value, &lane| value << 16 | lane as u64)
+    }
+
+    #[test]
+    fn registers_alias_the_x87_stack() {
+        let mut machine = load("fld1\nfld1\nmovq rax, mm7\nmov rbx, 0x1234\nmovq mm1, rbx");
+        halt(&mut machine);
+        // the significand of the first value pushed, in physical register 7
+        assert_eq!(machine.register(Register::RAX), 0x8000_0000_0000_0000);
+        // TOP back to 0 and every register valid
+        assert_eq!(machine.fpu_status_word() & 0x3800, 0);
+        assert_eq!(machine.fpu_tag_word(), 0);
+        // an MMX write sets the exponent and sign
+        assert_eq!(machine.st(1), 0xFFFF_0000_0000_0000_1234);
+        assert_eq!(machine.st(7), ONE);
+        assert_eq!(machine.mm(1), 0x1234);
+
+        let mut machine = load("movq mm0, rbx\nemms");
+        halt(&mut machine);
+        assert_eq!(machine.fpu_tag_word(), 0xFFFF);
+    }
+
+    #[test]
+    fn lanewise_arithmetic_on_the_low_half() {
+        let mut machine = load(
+            "movq mm0, [0x2000]\nmovq mm1, mm0\npaddusb mm1, [0x2008]\nmovq mm2, mm0\npsubsw mm2, [0x2008]\n\
+             movq mm3, mm0\npmullw mm3, mm0\nmovq mm4, mm0\npmaddwd mm4, mm0\nmovq mm5, mm0\npsadbw mm5, [0x2008]\n\
+             movq mm6, mm0\npcmpgtw mm6, [0x2008]\npxor mm7, mm7\npandn mm7, mm0",
+        );
+        machine.memory.write(DATA as usize, &words([0x00FF, 0x8000, 3, 0xFFFF]).to_le_bytes()).unwrap();
+        machine.memory.write(DATA as usize + 8, &words([0x0102, 1, 0x7FFF, 2]).to_le_bytes()).unwrap();
+        halt(&mut machine);
+        assert_eq!(machine.mm(1), words([0x01FF, 0x8001, 0x7FFF, 0xFFFF]));
+        assert_eq!(machine.mm(2), words([0xFFFD, 0x8000, 0x8004, 0xFFFD]));
+        assert_eq!(machine.mm(3), words([0xFE01, 0, 9, 1]));
+        assert_eq!(machine.mm(4), 0x0000_000A_4000_FE01);
+        assert_eq!(machine.mm(5), 0xFD + 1 + 1 + 0x80 + 0xFC + 0x7F + 0xFD + 0xFF);
+        assert_eq!(machine.mm(6), words([0, 0, 0, 0]));
+        assert_eq!(machine.mm(7), machine.mm(0));
+    }
+
+    #[test]
+    fn shuffles_packs_and_unpacks_stay_within_64_bits() {
+        let mut machine = load(
+            "movq mm0, [0x2000]\nmovq mm1, [0x2008]\nmovq mm2, mm0\npacksswb mm2, mm1\nmovq mm3, mm0\npunpckhwd mm3, mm1\n\
+             movq mm4, mm0\nphaddw mm4, mm1\nmovq mm5, mm0\npalignr mm5, mm1, 6\nmovq mm6, mm0\npshufb mm6, [0x2010]\n\
+             pshufw mm7, mm0, 0x1B\npmovmskb eax, mm1\npextrw ebx, mm0, 5\npinsrw mm1, ebx, 6",
+        );
+        machine.memory.write(DATA as usize, &words([1, 0x0200, 0xFF80, 0x7F]).to_le_bytes()).unwrap();
+        machine.memory.write(DATA as usize + 8, &words([0x8001, 5, 0xFFFF, 0x8081]).to_le_bytes()).unwrap();
+        machine.memory.write(DATA as usize + 0x10, &[7, 0x0F, 0x80, 0, 1, 9, 2, 3]).unwrap();
+        halt(&mut machine);
+        assert_eq!(machine.mm(2), u64::from_le_bytes([1, 0x7F, 0x80, 0x7F, 0x80, 5, 0xFF, 0x80]));
+        assert_eq!(machine.mm(3), words([0xFF80, 0xFFFF, 0x7F, 0x8081]));
+        assert_eq!(machine.mm(4), words([0x0201, 0xFFFF, 0x8006, 0x8080]));
+        // mm0:mm1 shifted right by 6 bytes
+        assert_eq!(machine.mm(5), words([0x8081, 1, 0x0200, 0xFF80]));
+        // indices modulo 8, and zero for a set top bit
+        assert_eq!(machine.mm(6), u64::from_le_bytes([0, 0, 0, 1, 0, 0, 0, 2]));
+        assert_eq!(machine.mm(7), words([0x7F, 0xFF80, 0x0200, 1]));
+        assert_eq!(machine.register(Register::EAX), 0b1111_0010);
+        assert_eq!(machine.register(Register::EBX), 0x0200);
+        assert_eq!(machine.mm(1), words([0x8001, 5, 0x0200, 0x8081]));
+    }
+
+    #[test]
+    fn shifts_moves_and_masked_stores() {
+        let mut machine = load(
+            "movq mm0, [0x2000]\nmovq mm1, mm0\npsllw mm1, 4\nmovq mm2, mm0\npsrad mm2, [0x2030]\nmovq mm3, mm0\n\
+             psrlq mm3, 68\nmovq2dq xmm0, mm0\nmovdq2q mm4, xmm0\nmovntq [0x2010], mm1\nmov rdi, 0x2020\n\
+             movq mm6, [0x2008]\nmaskmovq mm0, mm6\nmovd mm5, [0x2000]\nmovd ecx, mm0",
+        );
+        machine.memory.write(DATA as usize, &0x8123_4567_F00D_CAFEu64.to_le_bytes()).unwrap();
+        machine.memory.write(DATA as usize + 8, &0x8000_0080_0000_0004u64.to_le_bytes()).unwrap();
+        machine.memory.write(DATA as usize + 0x20, &[0xAA; 8]).unwrap();
+        machine.memory.write(DATA as usize + 0x30, &4u64.to_le_bytes()).unwrap();
+        halt(&mut machine);
+        assert_eq!(machine.mm(1), 0x1230_5670_00D0_AFE0);
+        assert_eq!(machine.mm(2), 0xF812_3456_FF00_DCAF);
+        assert_eq!(machine.mm(3), 0);
+        assert_eq!(machine.xmm(0), 0x8123_4567_F00D_CAFE);
+        assert_eq!(machine.mm(4), 0x8123_4567_F00D_CAFE);
+        assert_eq!(machine.memory.read(0x2010, 8).unwrap(), 0x1230_5670_00D0_AFE0u64.to_le_bytes());
+        assert_eq!(machine.memory.read(0x2020, 8).unwrap(), [0xAA, 0xAA, 0xAA, 0xAA, 0x67, 0xAA, 0xAA, 0x81]);
+        assert_eq!(machine.mm(5), 0xF00D_CAFE);
+        assert_eq!(machine.register(Register::ECX), 0xF00D_CAFE);
+    }
+
+    #[test]
+    fn transitions_between_x87_and_mmx() {
+        // without EMMS the stack is full of valid registers, and a push overflows it
+        let mut machine = load("pxor mm0, mm0\nfld1");
+        halt(&mut machine);
+        assert_eq!(machine.st(0), INDEFINITE);
+        assert_eq!(machine.fpu_status_word() & 0x0241, 0x0241);
+
+        let mut machine = load("pxor mm0, mm0\nemms\nfld1");
+        halt(&mut machine);
+        assert_eq!(machine.st(0), ONE);
+        assert_eq!(machine.fpu_tag_word(), 0x3FFF);
+
+        // a pending x87 exception is raised by the next MMX instruction
+        let mut machine = load("fldcw word [0x2000]\nfld1\nfldz\nfdivp st1, st0\npaddb mm0, mm1");
+        machine.memory.write(DATA as usize, &0x037Bu16.to_le_bytes()).unwrap();
+        let reason = machine.run();
+        assert!(matches!(
+            reason,
+            StopReason::Fault(Fault::Exception { exception: Exception::FloatingPoint, .. })
+        ));
+
+        // CVTPI2PS keeps the high half of the XMM register and makes the transition too
+        let mut machine = load("movq mm0, [0x2000]\nemms\ncvtpi2ps xmm1, mm0\ncvttps2pi mm2, xmm1");
+        machine.memory.write(DATA as usize, &[3i32.to_le_bytes(), (-5i32).to_le_bytes()].concat()).unwrap();
+        machine.set_xmm(1, u128::MAX);
+        halt(&mut machine);
+        assert_eq!(machine.xmm(1) >> 64, u64::MAX as u128);
+        assert_eq!(machine.xmm(1) as u64, ((-5.0f32).to_bits() as u64) << 32 | 3.0f32.to_bits() as u64);
+        assert_eq!(machine.mm(2), (-5i32 as u32 as u64) << 32 | 3);
+        assert_eq!(machine.fpu_tag_word(), 0);
+    }
+}
+
+#[cfg(test)]
+mod xsave {
+    use lib_x86::prelude::*;
+    use crate::fixture::{halt, load};
+
+    const DATA: u64 = 0x2000;
+
+    const ONE: u128 = 0x3FFF_8000_0000_0000_0000;
+    const PI: u128 = 0x4000_C90F_DAA2_2168_C235;
+
+    fn data(machine: &X86Machine, offset: u64, size: usize) -> Vec<u8> {
+        machine.memory.read((DATA + offset) as usize, size).unwrap()
+    }
+
+    fn qword(machine: &X86Machine, offset: u64) -> u64 {
+        u64::from_le_bytes(data(machine, offset, 8).try_into().unwrap())
+    }
+
+    fn faults_with(machine: &mut X86Machine, expected: Exception) {
+        assert!(matches!(
+            machine.run(),
+            StopReason::Fault(Fault::Exception { exception, .. }) if exception == expected
+        ));
+    }
+
+    /// Some state in every component: x87, MXCSR, XMM3, the upper halves of YMM1 and ZMM2, k1
+    /// and ZMM20
+    fn fill(machine: &mut X86Machine) {
+        machine.set_xmm(3, 0x3333);
+        machine.set_ymm(1, [0x1111, 0x1A1A]);
+        machine.set_zmm(2, [2, 0x22, 0x222, 0x2222]);
+        machine.set_zmm(20, [20, 0x20, 0x200, 0x2000]);
+        machine.set_opmask(1, 0xF0F0);
+        machine.set_mxcsr(0x1F80 | 0x6000);
+    }
+
+    #[test]
+    fn fxsave_layout_and_fxrstor() {
+        let mut machine = load("fld1\nfldpi\nfxsave64 [0x2000]\nfninit\npxor xmm3, xmm3\nfxrstor64 [0x2000]");
+        fill(&mut machine);
+        halt(&mut machine);
+        assert_eq!(data(&machine, 0, 2), &0x037Fu16.to_le_bytes());
+        // TOP = 6, with R6 and R7 in the abridged tag word
+        assert_eq!(data(&machine, 2, 2), &0x3000u16.to_le_bytes());
+        assert_eq!(data(&machine, 4, 1), &[0xC0]);
+        assert_eq!(qword(&machine, 24), 0xFFFF_0000_7F80);
+        assert_eq!(data(&machine, 32, 16), &PI.to_le_bytes());
+        assert_eq!(data(&machine, 48, 16), &ONE.to_le_bytes());
+        assert_eq!(data(&machine, 160 + 3 * 16, 16), &0x3333u128.to_le_bytes());
+        // restored
+        assert_eq!((machine.st(0), machine.st(1)), (PI, ONE));
+        assert_eq!(machine.fpu_tag_word(), 0x0FFF);
+        assert_eq!(machine.xmm(3), 0x3333);
+
+        let mut machine = load("fxsave [0x2008]");
+        faults_with(&mut machine, Exception::GeneralProtection);
+        // reserved MXCSR bits
+        let mut machine = load("fxrstor [0x2000]");
+        machine.memory.write(DATA as usize + 24, &0x1_0000u32.to_le_bytes()).unwrap();
+        faults_with(&mut machine, Exception::GeneralProtection);
+    }
+
+    #[test]
+    fn xsave_standard_form_round_trips_every_component() {
+        let mut machine = load(
+            "mov eax, -1\nmov edx, -1\nxsave64 [0x2000]\nvpxord zmm2, zmm2, zmm2\nvpxord zmm20, zmm20, zmm20\n\
+             vzeroall\nkxorw k1, k1, k1\nfninit\nxrstor64 [0x2000]",
+        );
+        fill(&mut machine);
+        halt(&mut machine);
+        // XSTATE_BV has every component XCR0 enables
+        assert_eq!(qword(&machine, 0x200), 0xFF);
+        assert_eq!(qword(&machine, 0x208), 0);
+        assert_eq!(data(&machine, 576 + 16, 16), &0x1A1Au128.to_le_bytes());
+        assert_eq!(qword(&machine, 1088 + 8), 0xF0F0);
+        assert_eq!(data(&machine, 1152 + 2 * 32, 32), [0x222u128.to_le_bytes(), 0x2222u128.to_le_bytes()].concat());
+        assert_eq!(data(&machine, 1664 + 4 * 64, 16), &20u128.to_le_bytes());
+        // and back
+        assert_eq!(machine.ymm(1), [0x1111, 0x1A1A]);
+        assert_eq!(machine.zmm(2), [2, 0x22, 0x222, 0x2222]);
+        assert_eq!(machine.zmm(20), [20, 0x20, 0x200, 0x2000]);
+        assert_eq!(machine.opmask(1), 0xF0F0);
+        assert_eq!(machine.mxcsr(), 0x7F80);
+        assert_eq!(machine.fpu_status_word(), 0);
+    }
+
+    #[test]
+    fn compacted_form_and_components_left_out() {
+        // x87, AVX and opmask state only
+        let mut machine = load(
+            "mov eax, 0x25\nxor edx, edx\nxsavec64 [0x2000]\nmov eax, -1\nmov edx, -1\nxrstor64 [0x2000]",
+        );
+        fill(&mut machine);
+        halt(&mut machine);
+        assert_eq!(qword(&machine, 0x200), 0x25);
+        assert_eq!(qword(&machine, 0x208), 1 << 63 | 0x25);
+        // AVX straight after the header, then the opmask registers
+        assert_eq!(data(&machine, 576 + 16, 16), &0x1A1Au128.to_le_bytes());
+        assert_eq!(qword(&machine, 576 + 256 + 8), 0xF0F0);
+        // XRSTOR puts what the area does not have in its initial state
+        assert_eq!(machine.ymm(1), [0, 0x1A1A]);
+        assert_eq!(machine.xmm(3), 0);
+        assert_eq!(machine.zmm(2), [0, 0x22, 0, 0]);
+        // MXCSR is saved with the AVX state as well
+        assert_eq!(machine.mxcsr(), 0x7F80);
+        assert_eq!(machine.opmask(1), 0xF0F0);
+
+        // a compacted component XCR0 does not enable
+        let mut machine = load("mov eax, -1\nmov edx, -1\nxrstor [0x2000]");
+        machine.memory.write(DATA as usize + 0x208, &(1u64 << 63 | 1 << 9).to_le_bytes()).unwrap();
+        faults_with(&mut machine, Exception::GeneralProtection);
+    }
+
+    #[test]
+    fn xcr0_and_cpuid_leaf_0xd() {
+        let mut machine = load("xor ecx, ecx\nxgetbv\nmov r8, rax\nmov r9, rdx\nmov eax, 0xD\nxor ecx, ecx\ncpuid");
+        halt(&mut machine);
+        assert_eq!((machine.register(Register::R8), machine.register(Register::R9)), (0xFF, 0));
+        assert_eq!(machine.register(Register::EAX), 0xFF);
+        assert_eq!(machine.register(Register::EBX), 2688);
+        assert_eq!(machine.register(Register::ECX), 2688);
+
+        // only the OS may write XCR0
+        let mut machine = load("mov eax, 3\nxor edx, edx\nxor ecx, ecx\nxsetbv");
+        faults_with(&mut machine, Exception::GeneralProtection);
+
+        // with AVX disabled the legacy region is all there is, and VEX instructions are #UD
+        let mut machine = load("mov eax, 3\nxor edx, edx\nxor ecx, ecx\nxsetbv\nmov eax, 0xD\ncpuid\naddps xmm0, xmm1\nvaddps ymm0, ymm0, ymm1");
+        machine.privilege_level = 0;
+        faults_with(&mut machine, Exception::InvalidOpcode);
+        assert_eq!(machine.xcr0(), 3);
+        assert_eq!(machine.register(Register::EBX), 576);
+
+        // AVX-512 state without AVX
+        let mut machine = load("mov eax, 0xE3\nxor edx, edx\nxor ecx, ecx\nxsetbv");
+        machine.privilege_level = 0;
+        faults_with(&mut machine, Exception::GeneralProtection);
+    }
+
+    #[test]
+    fn host_export_matches_xsave() {
+        let mut machine = load("fld1\nmov eax, -1\nmov edx, -1\nxsave64 [0x2000]");
+        fill(&mut machine);
+        halt(&mut machine);
+        let area = machine.xsave_area();
+        assert_eq!(area.len(), 2688);
+        assert_eq!(&area[..], data(&machine, 0, 2688));
+
+        let mut other = load("");
+        other.restore_xsave_area(&area).unwrap();
+        assert_eq!(other.st(0), ONE);
+        assert_eq!(other.zmm(2), machine.zmm(2));
+        assert_eq!(other.zmm(20), machine.zmm(20));
+        assert_eq!(other.opmask(1), 0xF0F0);
+        assert_eq!(other.xsave_area(), area);
+
+        // reserved header bytes
+        let mut malformed = area.clone();
+        malformed[512 + 20] = 1;
+        assert_eq!(other.restore_xsave_area(&malformed), Err(Exception::GeneralProtection));
+        assert_eq!(other.restore_xsave_area(&area[..100]), Err(Exception::GeneralProtection));
+    }
+}
+
+#[cfg(test)]
+mod mpx {
+    use lib_opcode::prelude::{assemble, decode_at, Bitness, Formatter, Syntax};
+    use lib_x86::prelude::*;
+    use crate::fixture::{halt, load_with, BUDGET};
+
+    const DATA: u64 = 0x2000;
+
+    /// BNDCFGU with MPX enabled and the bounds directory at 0x4000
+    const ENABLED: u64 = 0x4000 | 1;
+
+    fn load(source: &str) -> X86Machine {
+        load_with(source, BUDGET, |machine| machine.set_bndcfgu(ENABLED))
+    }
+
+    fn qword(machine: &X86Machine, address: u64) -> u64 {
+        machine.memory.load(address as usize, 8).unwrap()
+    }
+
+    fn bounds(lower: u64, upper: u64) -> u128 {
+        (!upper as u128) << 64 | lower as u128
+    }
+
+    fn faults_with_br(machine: &mut X86Machine) {
+        assert!(matches!(
+            machine.run(),
+            StopReason::Fault(Fault::Exception { exception: Exception::BoundRange, .. })
+        ));
+    }
+
+    #[test]
+    fn bndmk_and_the_bounds_checks() {
+        let mut machine = load(
+            "mov rax, 0x2000\nbndmk bnd0, [rax + 0xFF]\nbndcl bnd0, rax\nbndcu bnd0, [rax + 0xFF]\n\
+             mov rbx, 0x3000\nbndcn bnd1, rbx\nbndcl bnd0, [rax - 1]",
+        );
+        machine.set_bnd(1, 0x3000 << 64);
+        faults_with_br(&mut machine);
+        assert_eq!(machine.bnd(0), bounds(0x2000, 0x20FF));
+        assert_eq!(machine.bndstatus(), 1);
+
+        let mut machine = load("mov rax, 0x2000\nbndmk bnd0, [rax + 0xFF]\nbndcu bnd0, [rax + 0x100]");
+        faults_with_br(&mut machine);
+        // BNDCN takes the upper bound as it is
+        let mut machine = load("mov rbx, 0x3001\nbndcn bnd1, rbx");
+        machine.set_bnd(1, 0x3000 << 64);
+        faults_with_br(&mut machine);
+        // no base register to take the lower bound from, and RIP relative operands are #UD
+        let mut machine = load("bndmk bnd2, [0x10]\nbndmk bnd3, [rel $]");
+        assert!(matches!(
+            machine.run(),
+            StopReason::Fault(Fault::Exception { exception: Exception::InvalidOpcode, .. })
+        ));
+        assert_eq!(machine.bnd(2), bounds(0, 0x10));
+    }
+
+    #[test]
+    fn bndmov_between_registers_and_memory() {
+        let mut machine = load("bndmov bnd1, bnd0\nbndmov [0x2000], bnd0\nbndmov bnd2, [0x2010]");
+        machine.set_bnd(0, bounds(0x100, 0x1FF));
+        machine.memory.write(DATA as usize + 16, &bounds(0x300, 0x3FF).to_le_bytes()).unwrap();
+        halt(&mut machine);
+        assert_eq!(machine.bnd(1), bounds(0x100, 0x1FF));
+        assert_eq!(qword(&machine, DATA), 0x100);
+        assert_eq!(qword(&machine, DATA + 8), !0x1FF);
+        assert_eq!(machine.bnd(2), bounds(0x300, 0x3FF));
+    }
+
+    #[test]
+    fn bndstx_and_bndldx_walk_the_bounds_tables() {
+        // the pointer is stored at 0x2010 and points at 0x5000
+        let mut machine = load(
+            "mov rax, 0x2010\nmov rcx, 0x5000\nbndmk bnd0, [rcx + 0x7F]\nbndstx [rax + rcx*1], bnd0\n\
+             bndldx bnd1, [rax + rcx*1]\nmov rdx, 0x6000\nbndldx bnd2, [rax + rdx*1]",
+        );
+        // directory entry 0 points at a bounds table at 0x3000
+        machine.memory.write(0x4000, &(0x3000u64 | 1).to_le_bytes()).unwrap();
+        machine.set_bnd(2, bounds(1, 2));
+        halt(&mut machine);
+        // entry (0x2010 >> 3) of the table: lower bound, upper bound, pointer
+        let entry = 0x3000 + (0x2010 >> 3 << 5);
+        assert_eq!(qword(&machine, entry), 0x5000);
+        assert_eq!(qword(&machine, entry + 8), !0x507F);
+        assert_eq!(qword(&machine, entry + 16), 0x5000);
+        assert_eq!(machine.bnd(1), bounds(0x5000, 0x507F));
+        // the entry is for another pointer value
+        assert_eq!(machine.bnd(2), 0);
+
+        // a directory entry that is not valid
+        let mut machine = load("mov rax, 0x200010\nbndldx bnd0, [rax]");
+        faults_with_br(&mut machine);
+        assert_eq!(machine.bndstatus(), 0x4000 + 2 * 8 | 2);
+    }
+
+    #[test]
+    fn legacy_branches_reset_the_bounds_unless_bnd_prefixed() {
+        let mut machine = load("bnd jmp a\na:\nbnd call f\nbndmov [0x2000], bnd0\njmp b\nf:\nbnd ret\nb:");
+        machine.set_bnd(0, bounds(0x100, 0x1FF));
+        halt(&mut machine);
+        assert_eq!(qword(&machine, DATA), 0x100);
+        assert_eq!(machine.bnd(0), 0);
+
+        // BNDPRESERVE
+        let mut machine = load("call f\njmp b\nf:\nret\nb:");
+        machine.set_bndcfgu(ENABLED | 2);
+        machine.set_bnd(0, bounds(0x100, 0x1FF));
+        halt(&mut machine);
+        assert_eq!(machine.bnd(0), bounds(0x100, 0x1FF));
+
+        assert_eq!(assemble("bnd jmp a\na:").unwrap(), [0xF2, 0xEB, 0x00]);
+        let op = decode_at(Bitness::Bits64, &[0xF2, 0xEB, 0x00], 0x1000).unwrap();
+        assert_eq!(Formatter::new(Syntax::Nasm).format(&op), "bnd jmp 0x1003");
+        let op = decode_at(Bitness::Bits64, &[0xF3, 0x0F, 0x1B, 0x40, 0x10], 0x1000).unwrap();
+        assert_eq!(Formatter::new(Syntax::Nasm).format(&op), "bndmk bnd0, [rax+0x10]");
+    }
+
+    #[test]
+    fn disabled_mpx_is_a_nop_and_xsave_has_the_state() {
+        let mut machine = load("mov rax, 0x2000\nbndmk bnd0, [rax + 1]\nbndcu bnd1, [rax + 2]\njmp a\na:");
+        machine.set_bndcfgu(0);
+        machine.set_bnd(1, bounds(0, 1));
+        halt(&mut machine);
+        assert_eq!((machine.bnd(0), machine.bnd(1)), (0, bounds(0, 1)));
+
+        let mut machine = load("mov eax, 0x18\nxor edx, edx\nxsave64 [0x2000]\nbndmov bnd3, bnd0\nmov eax, -1\nmov edx, -1\nxrstor64 [0x2000]");
+        machine.set_bnd(3, bounds(0x300, 0x3FF));
+        machine.set_bndstatus(1);
+        halt(&mut machine);
+        assert_eq!(qword(&machine, DATA + 0x200), 0x18);
+        assert_eq!(qword(&machine, DATA + 960 + 48), 0x300);
+        assert_eq!(qword(&machine, DATA + 1024), ENABLED);
+        assert_eq!(qword(&machine, DATA + 1032), 1);
+        assert_eq!(machine.bnd(3), bounds(0x300, 0x3FF));
+
+        // the two MPX components go together
+        let mut machine = load("mov eax, 0xEF\nxor edx, edx\nxor ecx, ecx\nxsetbv");
+        machine.privilege_level = 0;
+        assert!(matches!(
+            machine.run(),
+            StopReason::Fault(Fault::Exception { exception: Exception::GeneralProtection, .. })
+        ));
+    }
+}
+
+#[cfg(test)]
+mod crypto {
+    use lib_x86::prelude::*;
+    use crate::fixture::{halt, load_with};
+
+    const DATA: u64 = 0x2000;
+
+    /// FIPS 197 appendix C.1, as the bytes load into an XMM register
+    const KEY: u128 = 0x0F0E_0D0C_0B0A_0908_0706_0504_0302_0100;
+    const LAST_ROUND_KEY: u128 = 0xC530_2B4D_8BA7_07F3_174A_94E3_7F1D_1113;
+    const PLAINTEXT: u128 = 0xFFEE_DDCC_BBAA_9988_7766_5544_3322_1100;
+    const CIPHERTEXT: u128 = 0x5AC5_B470_80B7_CDD8_3004_7B6A_D8E0_C469;
+
+    const SHA256_K: [u32; 64] = [
+        0x428A_2F98, 0x7137_4491, 0xB5C0_FBCF, 0xE9B5_DBA5, 0x3956_C25B, 0x59F1_11F1, 0x923F_82A4, 0xAB1C_5ED5,
+        0xD807_AA98, 0x1283_5B01, 0x2431_85BE, 0x550C_7DC3, 0x72BE_5D74, 0x80DE_B1FE, 0x9BDC_06A7, 0xC19B_F174,
+        0xE49B_69C1, 0xEFBE_4786, 0x0FC1_9DC6, 0x240C_A1CC, 0x2DE9_2C6F, 0x4A74_84AA, 0x5CB0_A9DC, 0x76F9_88DA,
+        0x983E_5152, 0xA831_C66D, 0xB003_27C8, 0xBF59_7FC7, 0xC6E0_0BF3, 0xD5A7_9147, 0x06CA_6351, 0x1429_2967,
+        0x27B7_0A85, 0x2E1B_2138, 0x4D2C_6DFC, 0x5338_0D13, 0x650A_7354, 0x766A_0ABB, 0x81C2_C92E, 0x9272_2C85,
+        0xA2BF_E8A1, 0xA81A_664B, 0xC24B_8B70, 0xC76C_51A3, 0xD192_E819, 0xD699_0624, 0xF40E_3585, 0x106A_A070,
+        0x19A4_C116, 0x1E37_6C08, 0x2748_774C, 0x34B0_BCB5, 0x391C_0CB3, 0x4ED8_AA4A, 0x5B9C_CA4F, 0x682E_6FF3,
+        0x748F_82EE, 0x78A5_636F, 0x84C8_7814, 0x8CC7_0208, 0x90BE_FFFA, 0xA450_6CEB, 0xBEF9_A3F7, 0xC671_78F2,
+    ];
+
+    /// The message words of the single padded block of "abc"
+    fn abc() -> [u32; 16] {
+        let mut words = [0; 16];
+        words[0] = 0x6162_6380;
+        words[15] = 24;
+        words
+    }
+
+    /// The hashes run a few thousand instructions
+    fn load(source: &str) -> X86Machine {
+        load_with(source, 10_000, |_| {})
+    }
+
+    fn write(machine: &mut X86Machine, address: u64, bytes: &[u8]) {
+        machine.memory.write(address as usize, bytes).unwrap();
+    }
+
+    fn dwords(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    fn from_dwords(high_first: [u32; 4]) -> u128 {
+        high_first.iter().fold(0, |value, &dword| value << 32 | dword as u128)
+    }
+
+    /// AES-128 key expansion of the key at DATA, round key i to DATA + 0x100 + 16i
+    fn expand_key() -> String {
+        let mut source = String::from("movdqa xmm1, [0x2000]\nmovdqa [0x2100], xmm1\n");
+        for (i, constant) in [1, 2, 4, 8, 0x10, 0x20, 0x40, 0x80, 0x1B, 0x36].into_iter().enumerate() {
+            source += &format!(
+                "aeskeygenassist xmm2, xmm1, {constant}\npshufd xmm2, xmm2, 0xFF\nmovdqa xmm3, xmm1\n\
+                 pslldq xmm3, 4\npxor xmm1, xmm3\npslldq xmm3, 4\npxor xmm1, xmm3\npslldq xmm3, 4\n\
+                 pxor xmm1, xmm3\npxor xmm1, xmm2\nmovdqa [{}], xmm1\n",
+                0x2110 + 16 * i
+            );
+        }
+        source
+    }
+
+    #[test]
+    fn aes_128_encrypts_and_decrypts_the_fips_197_example() {
+        let mut source = expand_key();
+        source += "pxor xmm0, [0x2100]\n";
+        for i in 1..10 {
+            source += &format!("aesenc xmm0, [{}]\n", 0x2100 + 16 * i);
+        }
+        source += "aesenclast xmm0, [0x21A0]\nmovdqa xmm4, xmm0\npxor xmm0, [0x21A0]\n";
+        // the equivalent inverse cipher, with InvMixColumns applied to the middle round keys
+        for i in (1..10).rev() {
+            source += &format!("aesimc xmm3, [{}]\naesdec xmm0, xmm3\n", 0x2100 + 16 * i);
+        }
+        source += "aesdeclast xmm0, [0x2100]";
+        let mut machine = load(&source);
+        write(&mut machine, DATA, &KEY.to_le_bytes());
+        machine.set_xmm(0, PLAINTEXT);
+        halt(&mut machine);
+        assert_eq!(machine.memory.read(DATA as usize + 0x1A0, 16).unwrap(), LAST_ROUND_KEY.to_le_bytes());
+        assert_eq!(machine.xmm(4), CIPHERTEXT);
+        assert_eq!(machine.xmm(0), PLAINTEXT);
+    }
+
+    #[test]
+    fn vaes_runs_a_round_on_each_block() {
+        let mut machine = load(
+            "vaesenc ymm0, ymm1, ymm2\nvaesdeclast xmm6, xmm1, xmm2\nmovdqa xmm3, xmm1\naesenc xmm3, xmm2\n\
+             vextracti128 xmm4, ymm1, 1\nvextracti128 xmm5, ymm2, 1\naesenc xmm4, xmm5\n\
+             movdqa xmm7, xmm1\naesdeclast xmm7, xmm2",
+        );
+        machine.set_ymm(1, [PLAINTEXT, CIPHERTEXT]);
+        machine.set_ymm(2, [KEY, LAST_ROUND_KEY]);
+        halt(&mut machine);
+        assert_eq!(machine.ymm(0), [machine.xmm(3), machine.xmm(4)]);
+        assert_ne!(machine.xmm(3), machine.xmm(4));
+        // VEX.128 clears the upper half
+        assert_eq!(machine.ymm(6), [machine.xmm(7), 0]);
+    }
+
+    #[test]
+    fn pclmulqdq_picks_quadwords_with_imm8() {
+        let mut machine = load(
+            "movdqa xmm0, xmm1\npclmulqdq xmm0, xmm2, 0x00\nmovdqa xmm3, xmm1\npclmulqdq xmm3, xmm2, 0x01\n\
+             movdqa xmm4, xmm1\npclmulqdq xmm4, xmm2, 0x11\nvpclmulqdq xmm5, xmm1, xmm2, 0x10\n\
+             vpclmulqdq ymm6, ymm1, ymm2, 0x00",
+        );
+        machine.set_ymm(1, [3 << 64 | u64::MAX as u128, 0b110]);
+        machine.set_ymm(2, [1 << 127 | u64::MAX as u128, 0b11]);
+        halt(&mut machine);
+        // squaring spreads the bits out
+        assert_eq!(machine.xmm(0), 0x5555_5555_5555_5555_5555_5555_5555_5555);
+        assert_eq!(machine.xmm(3), 0x1_0000_0000_0000_0001);
+        assert_eq!(machine.xmm(4), 0x1_8000_0000_0000_0000);
+        assert_eq!(machine.xmm(5), (u64::MAX as u128) << 63);
+        assert_eq!(machine.ymm(6), [0x5555_5555_5555_5555_5555_5555_5555_5555, 0b1010]);
+    }
+
+    #[test]
+    fn sha1_digests_abc() {
+        // message words in groups of four, the first in bits 127:96
+        let mut source = String::from("movdqa xmm5, xmm1\nmovdqa xmm6, xmm2\n");
+        for g in 4..20 {
+            source += &format!(
+                "movdqa xmm3, [{}]\nsha1msg1 xmm3, [{}]\npxor xmm3, [{}]\nsha1msg2 xmm3, [{}]\nmovdqa [{}], xmm3\n",
+                0x2000 + 16 * (g - 4),
+                0x2000 + 16 * (g - 3),
+                0x2000 + 16 * (g - 2),
+                0x2000 + 16 * (g - 1),
+                0x2000 + 16 * g
+            );
+        }
+        for g in 0..20 {
+            source += &format!("movdqa xmm0, [{}]\n", 0x2000 + 16 * g);
+            source += match g {
+                0 => "paddd xmm0, xmm2\n",
+                _ => "sha1nexte xmm3, xmm0\nmovdqa xmm0, xmm3\n",
+            };
+            source += &format!("movdqa xmm3, xmm1\nsha1rnds4 xmm1, xmm0, {}\n", g / 5);
+        }
+        source += "sha1nexte xmm3, xmm6\npaddd xmm1, xmm5";
+        let mut machine = load(&source);
+        for (g, words) in abc().chunks(4).enumerate() {
+            let group = from_dwords(words.try_into().unwrap());
+            write(&mut machine, DATA + 16 * g as u64, &group.to_le_bytes());
+        }
+        machine.set_xmm(1, from_dwords([0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476]));
+        machine.set_xmm(2, from_dwords([0xC3D2_E1F0, 0, 0, 0]));
+        halt(&mut machine);
+        assert_eq!(machine.xmm(1), from_dwords([0xA999_3E36, 0x4706_816A, 0xBA3E_2571, 0x7850_C26C]));
+        assert_eq!(machine.xmm(3) >> 96, 0x9CD0_D89D);
+    }
+
+    #[test]
+    fn sha256_digests_abc() {
+        // W[0..64] at DATA, the round constants after them
+        let mut source = String::from("movdqa xmm5, xmm1\nmovdqa xmm6, xmm2\n");
+        for t in (16..64).step_by(4) {
+            source += &format!(
+                "movdqa xmm3, [{}]\nsha256msg1 xmm3, [{}]\nmovdqu xmm4, [{}]\npaddd xmm3, xmm4\n\
+                 sha256msg2 xmm3, [{}]\nmovdqa [{}], xmm3\n",
+                0x2000 + 4 * (t - 16),
+                0x2000 + 4 * (t - 12),
+                0x2000 + 4 * (t - 7),
+                0x2000 + 4 * (t - 4),
+                0x2000 + 4 * t
+            );
+        }
+        for t in (0..64).step_by(4) {
+            source += &format!(
+                "movdqa xmm0, [{}]\npaddd xmm0, [{}]\nsha256rnds2 xmm2, xmm1, xmm0\npshufd xmm0, xmm0, 0x0E\n\
+                 sha256rnds2 xmm1, xmm2, xmm0\n",
+                0x2000 + 4 * t,
+                0x2100 + 4 * t
+            );
+        }
+        source += "paddd xmm1, xmm5\npaddd xmm2, xmm6";
+        let mut machine = load(&source);
+        write(&mut machine, DATA, &dwords(&abc()));
+        write(&mut machine, DATA + 0x100, &dwords(&SHA256_K));
+        // A, B, E, F and C, D, G, H
+        machine.set_xmm(1, from_dwords([0x6A09_E667, 0xBB67_AE85, 0x510E_527F, 0x9B05_688C]));
+        machine.set_xmm(2, from_dwords([0x3C6E_F372, 0xA54F_F53A, 0x1F83_D9AB, 0x5BE0_CD19]));
+        halt(&mut machine);
+        assert_eq!(machine.xmm(1), from_dwords([0xBA78_16BF, 0x8F01_CFEA, 0xB003_61A3, 0x9617_7A9C]));
+        assert_eq!(machine.xmm(2), from_dwords([0x4141_40DE, 0x5DAE_2223, 0xB410_FF61, 0xF200_15AD]));
+    }
+}